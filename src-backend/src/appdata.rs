@@ -0,0 +1,161 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::models::{Settings, StartupReport};
+use crate::paths::{atomic_write_json, io_error};
+
+/// Temp files older than this were left behind by an interrupted write.
+const STALE_TEMP_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+/// Files kept next to a `.jsonl` store in the datasets directory.
+pub const SIDECAR_EXTENSIONS: [&str; 4] = ["derived", "exports", "hashes", "idx"];
+
+/// Where the startup check looks.
+pub struct AppDataDirs<'a> {
+  pub settings: &'a Path,
+  pub datasets: &'a Path,
+  /// Directories searched for stale `.tmp` files, and the `.tmp` directories
+  /// of interrupted distillations.
+  pub temp_dirs: &'a [&'a Path],
+}
+
+pub fn read_settings_file(path: &Path) -> Result<Option<Settings>, String> {
+  if !path.exists() {
+    return Ok(None);
+  }
+  let content = fs::read_to_string(path).map_err(|e| io_error(path, &e))?;
+  let settings = serde_json::from_str(&content).map_err(|e| e.to_string())?;
+  Ok(Some(settings))
+}
+
+pub fn write_settings_file(path: &Path, settings: &Settings) -> Result<(), String> {
+  atomic_write_json(path, settings)
+}
+
+fn unix_now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|duration| duration.as_secs())
+    .unwrap_or_default()
+}
+
+fn display(path: &Path) -> String {
+  path.display().to_string()
+}
+
+/// Moves an unreadable settings file aside as `settings.json.corrupt-<unix time>`.
+fn check_settings(path: &Path, report: &mut StartupReport) {
+  let error = match read_settings_file(path) {
+    Ok(_) => return,
+    Err(error) => error,
+  };
+  let mut backup = path.as_os_str().to_os_string();
+  backup.push(format!(".corrupt-{}", unix_now()));
+  let backup = PathBuf::from(backup);
+  match fs::rename(path, &backup) {
+    Ok(()) => {
+      report.settings_error = Some(error);
+      report.settings_backup = Some(display(&backup));
+    }
+    Err(e) => report.errors.push(format!("Unreadable settings: {}", io_error(path, &e))),
+  }
+}
+
+fn remove_stale_temp_files(dir: &Path, report: &mut StartupReport) {
+  let Ok(entries) = fs::read_dir(dir) else {
+    return;
+  };
+  let now = SystemTime::now();
+  for entry in entries.flatten() {
+    let path = entry.path();
+    if path.extension().is_none_or(|extension| extension != "tmp") {
+      continue;
+    }
+    let stale = entry
+      .metadata()
+      .and_then(|metadata| metadata.modified())
+      .ok()
+      .and_then(|modified| now.duration_since(modified).ok())
+      .is_some_and(|age| age >= STALE_TEMP_AGE);
+    if !stale {
+      continue;
+    }
+    let removed = if path.is_dir() {
+      fs::remove_dir_all(&path)
+    } else {
+      fs::remove_file(&path)
+    };
+    match removed {
+      Ok(()) => report.removed_temp_files.push(display(&path)),
+      Err(e) => report.errors.push(io_error(&path, &e)),
+    }
+  }
+}
+
+/// Removes sidecars whose store file is gone.
+fn remove_orphan_sidecars(datasets: &Path, report: &mut StartupReport) {
+  let Ok(entries) = fs::read_dir(datasets) else {
+    return;
+  };
+  for entry in entries.flatten() {
+    let path = entry.path();
+    let is_sidecar = path
+      .extension()
+      .and_then(|extension| extension.to_str())
+      .is_some_and(|extension| SIDECAR_EXTENSIONS.contains(&extension));
+    if !is_sidecar || path.with_extension("jsonl").exists() {
+      continue;
+    }
+    match fs::remove_file(&path) {
+      Ok(()) => report.removed_sidecars.push(display(&path)),
+      Err(e) => report.errors.push(io_error(&path, &e)),
+    }
+  }
+}
+
+/// Repairs what an interrupted run can leave behind: an unreadable settings
+/// file, temp files from writes that never finished and sidecars of deleted
+/// stores. Nothing here fails startup; problems are listed in the report.
+pub fn check_app_data(dirs: &AppDataDirs) -> StartupReport {
+  let mut report = StartupReport::default();
+  check_settings(dirs.settings, &mut report);
+  for dir in dirs.temp_dirs {
+    remove_stale_temp_files(dir, &mut report);
+  }
+  remove_orphan_sidecars(dirs.datasets, &mut report);
+  report
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs::File;
+
+  use super::*;
+  use crate::test_support::TempDir;
+
+  fn make_spill_dir(path: &Path, age: Duration) {
+    fs::create_dir_all(path).unwrap();
+    fs::write(path.join("0.bin"), [0u8; 20]).unwrap();
+    let modified = SystemTime::now() - age;
+    File::open(path).unwrap().set_modified(modified).unwrap();
+  }
+
+  #[test]
+  fn stale_distill_spill_directories_are_removed() {
+    let dir = TempDir::new();
+    let datasets = dir.join("datasets");
+    let stale = datasets.join("distill-stale.tmp");
+    let running = datasets.join("distill-running.tmp");
+    make_spill_dir(&stale, STALE_TEMP_AGE * 2);
+    make_spill_dir(&running, Duration::ZERO);
+
+    let report = check_app_data(&AppDataDirs {
+      settings: &dir.join("settings.json"),
+      datasets: &datasets,
+      temp_dirs: &[&datasets],
+    });
+    assert_eq!(report.removed_temp_files, vec![display(&stale)]);
+    assert!(!stale.exists());
+    assert!(running.join("0.bin").exists());
+  }
+}
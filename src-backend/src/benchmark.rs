@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::hint::black_box;
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use serde_json::Value;
+
+use crate::filters::RecordPredicates;
+use crate::io::store_lines;
+use crate::models::{BenchmarkReport, CategoryRules, FieldMap, FilterConfig, PredicateCost};
+use crate::records::{extract_text_value, shingled_simhash};
+use crate::state::DatasetStore;
+use crate::timing::StageTimer;
+
+/// Records parsed and filtered by the benchmark.
+pub const BENCHMARK_SAMPLE: usize = 100_000;
+/// Upper bound on the bytes read by the sequential read test.
+const READ_LIMIT: u64 = 512 * 1024 * 1024;
+const READ_CHUNK: usize = 1024 * 1024;
+
+fn canceled() -> String {
+  "Benchmark canceled".to_string()
+}
+
+/// Measures store read throughput, then parses the first `BENCHMARK_SAMPLE`
+/// records and times text extraction, simhash and every configured predicate
+/// on each of them. Predicates are evaluated without short-circuiting so each
+/// cost covers the whole sample. Lines over the store's import cap are
+/// counted and skipped unread, as filtering does.
+pub fn run_benchmark(
+  store: &DatasetStore,
+  filters: &FilterConfig,
+  field_map: &FieldMap,
+  category_rules: &CategoryRules,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(&str, usize, usize),
+) -> Result<BenchmarkReport, String> {
+  let mut timer = StageTimer::new();
+
+  let mut file = File::open(&store.store_path).map_err(|e| e.to_string())?;
+  let mut buffer = vec![0u8; READ_CHUNK];
+  let mut read_bytes = 0u64;
+  let start = Instant::now();
+  while read_bytes < READ_LIMIT {
+    if cancel.load(Ordering::SeqCst) {
+      return Err(canceled());
+    }
+    let read = file.read(&mut buffer).map_err(|e| e.to_string())?;
+    if read == 0 {
+      break;
+    }
+    read_bytes += read as u64;
+  }
+  let read_elapsed = start.elapsed();
+  timer.add("read", read_elapsed);
+  let read_secs = read_elapsed.as_secs_f64();
+  let read_mb_per_sec =
+    (read_secs > 0.0).then(|| read_bytes as f64 / (1024.0 * 1024.0) / read_secs);
+  on_progress("read", 1, 1);
+
+  let predicates = RecordPredicates::new(filters, field_map, category_rules)?;
+  let mut rejected: HashMap<&'static str, usize> = HashMap::new();
+  let mut sample_count = 0usize;
+  let mut oversized_count = 0usize;
+  for line in store_lines(store)? {
+    if sample_count >= BENCHMARK_SAMPLE {
+      break;
+    }
+    if cancel.load(Ordering::SeqCst) {
+      return Err(canceled());
+    }
+    let line = line?;
+    let Some(bytes) = line.bytes() else {
+      oversized_count += 1;
+      continue;
+    };
+    if bytes.trim_ascii().is_empty() {
+      continue;
+    }
+    let record: Value = timer
+      .time("parse", || serde_json::from_slice(bytes))
+      .map_err(|e| e.to_string())?;
+    timer.count("parse", 1);
+
+    let text = timer.time("text", || predicates.text(&record));
+    timer.count("text", 1);
+    for predicate in predicates.active() {
+      let reason = predicate.reason();
+      if timer.time(reason, || predicates.rejects(*predicate, &record, &text)) {
+        *rejected.entry(reason).or_insert(0) += 1;
+      }
+      timer.count(reason, 1);
+    }
+
+    let instruction_text = extract_text_value(&record, &field_map.instruction).unwrap_or_default();
+    let output_text = extract_text_value(&record, &field_map.output).unwrap_or_default();
+    timer.time("simhash", || {
+      black_box((
+        shingled_simhash(&instruction_text, filters.shingle_size),
+        shingled_simhash(&output_text, filters.shingle_size),
+      ))
+    });
+    timer.count("simhash", 1);
+
+    sample_count += 1;
+    if sample_count.is_multiple_of(1000) {
+      on_progress("sample", sample_count, BENCHMARK_SAMPLE.min(store.record_count));
+    }
+  }
+
+  let mut timings = timer.finish();
+  let predicate_names = predicates
+    .active()
+    .iter()
+    .map(|predicate| predicate.reason())
+    .collect::<Vec<_>>();
+  let predicate_costs = timings
+    .iter()
+    .filter(|timing| predicate_names.contains(&timing.stage.as_str()))
+    .map(|timing| PredicateCost {
+      predicate: timing.stage.clone(),
+      millis: timing.millis,
+      records_per_sec: timing.records_per_sec,
+      rejected_count: rejected.get(timing.stage.as_str()).copied().unwrap_or(0),
+    })
+    .collect();
+  timings.retain(|timing| !predicate_names.contains(&timing.stage.as_str()));
+
+  Ok(BenchmarkReport {
+    record_count: store.record_count,
+    sample_count,
+    oversized_count,
+    read_bytes,
+    read_mb_per_sec,
+    timings,
+    predicates: predicate_costs,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+  use crate::models::{ImportOptions, DEFAULT_MAX_RECORD_BYTES};
+  use crate::test_support::{jsonl_store_with, text_field_map, TempDir};
+
+  #[test]
+  fn lines_over_the_import_cap_are_counted_without_being_parsed() {
+    let dir = TempDir::new();
+    let huge = "x".repeat(DEFAULT_MAX_RECORD_BYTES + 1024);
+    let records = [
+      json!({ "instruction": "first", "output": "one" }),
+      json!({ "instruction": huge, "output": "two" }),
+      json!({ "instruction": "third", "output": "three" }),
+    ];
+    let options = ImportOptions {
+      max_record_bytes: 2 * DEFAULT_MAX_RECORD_BYTES,
+      ..ImportOptions::default()
+    };
+    let mut store = jsonl_store_with(&dir, &records, &options);
+    let benchmark = |store: &DatasetStore| {
+      let filters = FilterConfig::default();
+      let rules = CategoryRules::default();
+      let cancel = AtomicBool::new(false);
+      run_benchmark(store, &filters, &text_field_map(), &rules, &cancel, |_, _, _| {}).unwrap()
+    };
+    let report = benchmark(&store);
+    assert_eq!((report.sample_count, report.oversized_count), (3, 0));
+
+    store.max_record_bytes = DEFAULT_MAX_RECORD_BYTES;
+    let report = benchmark(&store);
+    assert_eq!((report.sample_count, report.oversized_count), (2, 1));
+    let parse = report.timings.iter().find(|timing| timing.stage == "parse").unwrap();
+    assert!(parse.records_per_sec.is_some());
+  }
+}
@@ -0,0 +1,351 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use aho_corasick::AhoCorasick;
+use regex::RegexSetBuilder;
+use serde_json::Value;
+
+use crate::io::store_lines;
+use crate::models::{CategoryCount, CategoryRules, CodeLanguageReport, FieldMap};
+use crate::records::{extract_text_value, get_length_text, record_code_language};
+use crate::state::{DatasetStore, IdSet};
+
+/// The value of a category field, or `None` when it is missing, null or blank.
+pub fn record_category(record: &Value, field: &str) -> Option<String> {
+  extract_text_value(record, &Some(field.to_string()))
+    .map(|value| value.trim().to_string())
+    .filter(|value| !value.is_empty())
+}
+
+/// Compiled `CategoryRules`: one Aho-Corasick automaton over the keywords of
+/// all rules plus one regex set over their patterns.
+#[derive(Debug, Clone)]
+pub struct CategoryMatcher {
+  names: Vec<String>,
+  fallback: Option<String>,
+  scope: String,
+  case_sensitive: bool,
+  keywords: Option<AhoCorasick>,
+  /// Rule index of each keyword, by automaton pattern id.
+  keyword_rules: Vec<usize>,
+  patterns: Option<regex::RegexSet>,
+  /// Rule index of each regex, by set index.
+  pattern_rules: Vec<usize>,
+}
+
+impl CategoryMatcher {
+  pub fn new(rules: &CategoryRules) -> Result<Self, String> {
+    let mut keywords = Vec::new();
+    let mut keyword_rules = Vec::new();
+    let mut patterns = Vec::new();
+    let mut pattern_rules = Vec::new();
+    for (index, rule) in rules.rules.iter().enumerate() {
+      if rule.name.trim().is_empty() {
+        return Err(format!("Category rule {} has no name", index + 1));
+      }
+      for keyword in rule.keywords.iter().filter(|keyword| !keyword.is_empty()) {
+        keywords.push(if rules.case_sensitive {
+          keyword.clone()
+        } else {
+          keyword.to_lowercase()
+        });
+        keyword_rules.push(index);
+      }
+      for pattern in rule.patterns.iter().filter(|pattern| !pattern.is_empty()) {
+        patterns.push(pattern.clone());
+        pattern_rules.push(index);
+      }
+    }
+
+    let keywords = if keywords.is_empty() {
+      None
+    } else {
+      Some(AhoCorasick::new(&keywords).map_err(|e| e.to_string())?)
+    };
+    let patterns = if patterns.is_empty() {
+      None
+    } else {
+      Some(
+        RegexSetBuilder::new(&patterns)
+          .case_insensitive(!rules.case_sensitive)
+          .build()
+          .map_err(|e| format!("Invalid category pattern: {e}"))?,
+      )
+    };
+    Ok(Self {
+      names: rules.rules.iter().map(|rule| rule.name.trim().to_string()).collect(),
+      fallback: rules
+        .fallback
+        .as_ref()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty()),
+      scope: rules.scope.clone(),
+      case_sensitive: rules.case_sensitive,
+      keywords,
+      keyword_rules,
+      patterns,
+      pattern_rules,
+    })
+  }
+
+  /// The first rule, in rule order, with a keyword or pattern in `text`.
+  fn matching_rule(&self, text: &str) -> Option<usize> {
+    let mut best: Option<usize> = None;
+    if let Some(keywords) = &self.keywords {
+      let lowered;
+      let haystack = if self.case_sensitive {
+        text
+      } else {
+        lowered = text.to_lowercase();
+        &lowered
+      };
+      for found in keywords.find_overlapping_iter(haystack) {
+        let rule = self.keyword_rules[found.pattern().as_usize()];
+        best = Some(best.map_or(rule, |current| current.min(rule)));
+        if rule == 0 {
+          return best;
+        }
+      }
+    }
+    if let Some(patterns) = &self.patterns {
+      let rule = patterns
+        .matches(text)
+        .into_iter()
+        .map(|index| self.pattern_rules[index])
+        .min();
+      best = match (best, rule) {
+        (Some(current), Some(rule)) => Some(current.min(rule)),
+        (current, rule) => current.or(rule),
+      };
+    }
+    best
+  }
+
+  pub fn categorize(&self, record: &Value, field_map: &FieldMap) -> Option<String> {
+    let text = get_length_text(record, field_map, &self.scope);
+    match self.matching_rule(&text) {
+      Some(rule) => Some(self.names[rule].clone()),
+      None => self.fallback.clone(),
+    }
+  }
+}
+
+/// Where record categories come from: a mapped field, inferred rules, the
+/// language of the code, or nowhere.
+#[derive(Debug, Clone)]
+pub enum CategorySource {
+  Field(String),
+  Rules(Box<CategoryMatcher>),
+  CodeLanguage,
+  None,
+}
+
+impl CategorySource {
+  /// A real field takes precedence; rules apply only when no field is given.
+  pub fn new(field: Option<&str>, rules: &CategoryRules) -> Result<Self, String> {
+    match field.map(str::trim).filter(|field| !field.is_empty()) {
+      Some(field) => Ok(CategorySource::Field(field.to_string())),
+      None if rules.code_language => Ok(CategorySource::CodeLanguage),
+      None if !rules.is_empty() => Ok(CategorySource::Rules(Box::new(CategoryMatcher::new(
+        rules,
+      )?))),
+      None => Ok(CategorySource::None),
+    }
+  }
+
+  pub fn is_none(&self) -> bool {
+    matches!(self, CategorySource::None)
+  }
+
+  pub fn category(&self, record: &Value, field_map: &FieldMap) -> Option<String> {
+    match self {
+      CategorySource::Field(field) => record_category(record, field),
+      CategorySource::Rules(matcher) => matcher.categorize(record, field_map),
+      CategorySource::CodeLanguage => {
+        Some(record_code_language(record, field_map).language.to_string())
+      }
+      CategorySource::None => None,
+    }
+  }
+}
+
+/// Programming-language mix of the records in `ids`, with how each language
+/// was recognized.
+pub fn code_language_stats(
+  store: &DatasetStore,
+  ids: &IdSet,
+  field_map: &FieldMap,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<CodeLanguageReport, String> {
+  let mut counts: HashMap<&'static str, usize> = HashMap::new();
+  let mut sources: BTreeMap<String, usize> = BTreeMap::new();
+  let mut scanned_count = 0usize;
+  for line in store_lines(store)? {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Code language scan canceled".to_string());
+    }
+    let line = line?;
+    let idx = line.id;
+    if !ids.contains(idx) {
+      continue;
+    }
+    let Some(record) = line.record()? else {
+      continue;
+    };
+    let found = record_code_language(&record, field_map);
+    *counts.entry(found.language).or_insert(0) += 1;
+    *sources.entry(found.source.to_string()).or_insert(0) += 1;
+    scanned_count += 1;
+    if scanned_count.is_multiple_of(1000) {
+      on_progress(scanned_count, ids.len());
+    }
+  }
+  let mut languages = counts
+    .into_iter()
+    .map(|(name, count)| CategoryCount {
+      name: name.to_string(),
+      count,
+    })
+    .collect::<Vec<_>>();
+  languages.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+  Ok(CodeLanguageReport {
+    scanned_count,
+    languages,
+    sources,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+  use crate::filters::collect_categories;
+  use crate::models::{CategoryRule, UNCATEGORIZED_LABEL};
+  use crate::test_support::{jsonl_store, text_field_map, TempDir};
+
+  fn rule(name: &str, keywords: &[&str], patterns: &[&str]) -> CategoryRule {
+    CategoryRule {
+      name: name.to_string(),
+      keywords: keywords.iter().map(|keyword| keyword.to_string()).collect(),
+      patterns: patterns.iter().map(|pattern| pattern.to_string()).collect(),
+    }
+  }
+
+  fn math_and_coding(fallback: Option<&str>) -> CategoryRules {
+    CategoryRules {
+      rules: vec![
+        rule("math", &["equation", "integral", "solve"], &[]),
+        rule("coding", &["function", "python", "compile"], &[]),
+      ],
+      fallback: fallback.map(str::to_string),
+      ..CategoryRules::default()
+    }
+  }
+
+  fn categorize(rules: &CategoryRules, instruction: &str, output: &str) -> Option<String> {
+    let record = json!({ "instruction": instruction, "output": output });
+    CategoryMatcher::new(rules).unwrap().categorize(&record, &text_field_map())
+  }
+
+  #[test]
+  fn the_first_rule_in_order_wins_wherever_it_matches() {
+    let rules = math_and_coding(None);
+    let mixed = "Write a Python function to SOLVE the equation";
+    assert_eq!(categorize(&rules, mixed, "").as_deref(), Some("math"));
+    assert_eq!(categorize(&rules, "Why won't this compile?", "").as_deref(), Some("coding"));
+    assert_eq!(categorize(&rules, "Name a river", "").as_deref(), None);
+
+    let patterns_first = CategoryRules {
+      rules: vec![
+        rule("complexity", &[], &[r"\bO\(n( log n)?\)"]),
+        rule("coding", &["python"], &[]),
+      ],
+      ..CategoryRules::default()
+    };
+    let text = "python sort in o(n log n)";
+    assert_eq!(categorize(&patterns_first, text, "").as_deref(), Some("complexity"));
+    assert_eq!(categorize(&patterns_first, "python please", "").as_deref(), Some("coding"));
+  }
+
+  #[test]
+  fn unmatched_records_take_the_fallback() {
+    let rules = math_and_coding(Some("  general "));
+    assert_eq!(categorize(&rules, "Name a river", "").as_deref(), Some("general"));
+    assert_eq!(categorize(&rules, "Integral of x", "").as_deref(), Some("math"));
+    let blank = math_and_coding(Some(" "));
+    assert_eq!(categorize(&blank, "Name a river", ""), None);
+  }
+
+  #[test]
+  fn rules_honor_case_and_scope() {
+    let sensitive = CategoryRules {
+      case_sensitive: true,
+      ..math_and_coding(None)
+    };
+    assert_eq!(categorize(&sensitive, "PYTHON", ""), None);
+    assert_eq!(categorize(&sensitive, "python", "").as_deref(), Some("coding"));
+
+    let instruction_only = CategoryRules {
+      scope: "instruction".to_string(),
+      ..math_and_coding(None)
+    };
+    assert_eq!(categorize(&instruction_only, "Name a river", "solve it"), None);
+    let combined = categorize(&math_and_coding(None), "Name a river", "solve it");
+    assert_eq!(combined.as_deref(), Some("math"));
+  }
+
+  #[test]
+  fn a_mapped_field_takes_precedence_over_rules() {
+    let rules = math_and_coding(Some("general"));
+    let record = json!({ "instruction": "solve x", "topic": "algebra" });
+    let field_map = text_field_map();
+    let source = CategorySource::new(Some(" topic "), &rules).unwrap();
+    assert_eq!(source.category(&record, &field_map).as_deref(), Some("algebra"));
+    let source = CategorySource::new(Some(""), &rules).unwrap();
+    assert_eq!(source.category(&record, &field_map).as_deref(), Some("math"));
+    let source = CategorySource::new(None, &CategoryRules::default()).unwrap();
+    assert!(source.is_none());
+
+    let unnamed = CategoryRules {
+      rules: vec![rule(" ", &["x"], &[])],
+      ..CategoryRules::default()
+    };
+    assert_eq!(CategoryMatcher::new(&unnamed).unwrap_err(), "Category rule 1 has no name");
+    let invalid = CategoryRules {
+      rules: vec![rule("broken", &[], &["("])],
+      ..CategoryRules::default()
+    };
+    assert!(CategoryMatcher::new(&invalid).unwrap_err().starts_with("Invalid category pattern"));
+  }
+
+  #[test]
+  fn collected_categories_are_the_inferred_ones() {
+    let dir = TempDir::new();
+    let records = ["solve 2x = 4", "python function", "integral of x", "name a river"]
+      .map(|instruction| json!({ "instruction": instruction }));
+    let store = jsonl_store(&dir, &records);
+    let field_map = text_field_map();
+    let counts = |rules: &CategoryRules| {
+      let source = CategorySource::new(None, rules).unwrap();
+      collect_categories(&store, &source, &field_map, 100, &AtomicBool::new(false), |_, _| {})
+        .unwrap()
+        .categories
+        .into_iter()
+        .map(|category| (category.name, category.count))
+        .collect::<Vec<_>>()
+    };
+    let named = |pairs: &[(&str, usize)]| {
+      pairs.iter().map(|(name, count)| (name.to_string(), *count)).collect::<Vec<_>>()
+    };
+    assert_eq!(
+      counts(&math_and_coding(None)),
+      named(&[("math", 2), ("coding", 1), (UNCATEGORIZED_LABEL, 1)])
+    );
+    assert_eq!(
+      counts(&math_and_coding(Some("general"))),
+      named(&[("math", 2), ("coding", 1), ("general", 1)])
+    );
+  }
+}
@@ -0,0 +1,296 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+use crate::filters::{Deduper, RecordPredicates};
+use crate::io::store_lines;
+use crate::models::{CategoryRules, ClusterProgress, FieldMap, FilterConfig};
+use crate::records::extract_text_value;
+use crate::state::{DatasetStore, IdSet, InnerState};
+
+/// Groups every record that passes the filter predicates with the earlier
+/// record it duplicates under the config's dedupe settings. Only groups of two
+/// or more are returned, each in ascending id order, ordered by first id.
+pub fn find_duplicate_clusters(
+  store: &DatasetStore,
+  filters: &FilterConfig,
+  field_map: &FieldMap,
+  category_rules: &CategoryRules,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<Vec<Vec<usize>>, String> {
+  let predicates = RecordPredicates::new(filters, field_map, category_rules)?;
+  let mut deduper = Deduper::new(filters);
+  let mut root_of: HashMap<usize, usize> = HashMap::new();
+  let mut clusters: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+
+  for line in store_lines(store)? {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Cluster search canceled".to_string());
+    }
+    let line = line?;
+    let idx = line.id;
+    let Some(record) = line.record()? else {
+      continue;
+    };
+    if idx.is_multiple_of(1000) {
+      on_progress(idx, store.record_count);
+    }
+    if predicates.rejection(&record).is_some() {
+      continue;
+    }
+    let instruction_text = extract_text_value(&record, &field_map.instruction).unwrap_or_default();
+    let output_text = extract_text_value(&record, &field_map.output).unwrap_or_default();
+    // Clusters are the same whichever member is kept, so none is replaced.
+    let Some((_, original, _)) = deduper.check(idx, &instruction_text, &output_text, 0.0) else {
+      continue;
+    };
+    // In `either` mode the matched record may itself be a duplicate.
+    let root = root_of.get(&original).copied().unwrap_or(original);
+    root_of.insert(idx, root);
+    clusters.entry(root).or_insert_with(|| vec![root]).push(idx);
+  }
+  Ok(clusters.into_values().collect())
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ReviewCluster {
+  pub record_ids: Vec<usize>,
+  /// Content hash of each member, so decisions carry over to a later run even
+  /// when the records moved to other ids or another store.
+  pub record_hashes: Vec<u64>,
+  /// Members to keep once reviewed; the rest are dropped.
+  pub keep_ids: Option<Vec<usize>>,
+}
+
+impl ReviewCluster {
+  /// Member ids with their hashes; empty for a review saved before hashes
+  /// were kept, which cannot be matched.
+  fn hashed_members(&self) -> Vec<(usize, u64)> {
+    if self.record_hashes.len() != self.record_ids.len() {
+      return Vec::new();
+    }
+    self
+      .record_ids
+      .iter()
+      .copied()
+      .zip(self.record_hashes.iter().copied())
+      .collect()
+  }
+}
+
+/// Duplicate clusters under review, identified by their index.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ClusterReview {
+  pub clusters: Vec<ReviewCluster>,
+  /// Serialized filter config the clusters were computed with.
+  pub filters_key: String,
+  pub stale: bool,
+}
+
+pub fn filters_key(filters: &FilterConfig) -> String {
+  serde_json::to_string(filters).unwrap_or_default()
+}
+
+impl ClusterReview {
+  /// `hashes` are the content hashes of the store the clusters were found in.
+  pub fn new(clusters: Vec<Vec<usize>>, filters: &FilterConfig, hashes: &[u64]) -> Self {
+    Self {
+      clusters: clusters
+        .into_iter()
+        .map(|record_ids| ReviewCluster {
+          record_hashes: record_ids
+            .iter()
+            .map_while(|id| hashes.get(*id).copied())
+            .collect(),
+          record_ids,
+          keep_ids: None,
+        })
+        .collect(),
+      filters_key: filters_key(filters),
+      stale: false,
+    }
+  }
+
+  pub fn progress(&self) -> ClusterProgress {
+    let reviewed_count = self
+      .clusters
+      .iter()
+      .filter(|cluster| cluster.keep_ids.is_some())
+      .count();
+    ClusterProgress {
+      cluster_count: self.clusters.len(),
+      reviewed_count,
+      remaining_count: self.clusters.len() - reviewed_count,
+      stale: self.stale,
+    }
+  }
+
+  pub fn next_unreviewed(&self) -> Option<usize> {
+    self
+      .clusters
+      .iter()
+      .position(|cluster| cluster.keep_ids.is_none())
+  }
+
+  /// Carries decisions over from `previous` by content hash: a cluster whose
+  /// members were all decided before gets the same keep/drop split. Returns
+  /// how many clusters were decided this way.
+  pub fn restore_from(&mut self, previous: &ClusterReview) -> usize {
+    let mut kept = HashSet::new();
+    let mut dropped = HashSet::new();
+    for cluster in &previous.clusters {
+      let Some(keep_ids) = &cluster.keep_ids else {
+        continue;
+      };
+      for (id, hash) in cluster.hashed_members() {
+        if keep_ids.contains(&id) {
+          kept.insert(hash);
+        } else {
+          dropped.insert(hash);
+        }
+      }
+    }
+
+    let mut restored = 0usize;
+    for cluster in &mut self.clusters {
+      let members = cluster.hashed_members();
+      if members.is_empty()
+        || !members
+          .iter()
+          .all(|(_, hash)| kept.contains(hash) || dropped.contains(hash))
+      {
+        continue;
+      }
+      cluster.keep_ids = Some(
+        members
+          .into_iter()
+          .filter(|(_, hash)| kept.contains(hash))
+          .map(|(id, _)| id)
+          .collect(),
+      );
+      restored += 1;
+    }
+    restored
+  }
+}
+
+impl InnerState {
+  /// Records the decision for a cluster and applies it to the derived ids.
+  pub fn resolve_cluster(&mut self, cluster_id: usize, keep_ids: &[usize]) -> Result<(), String> {
+    let review = self
+      .cluster_review
+      .as_mut()
+      .ok_or_else(|| "No duplicate clusters computed".to_string())?;
+    if review.stale {
+      return Err("Filters changed since clusters were computed".to_string());
+    }
+    let cluster = review
+      .clusters
+      .get_mut(cluster_id)
+      .ok_or_else(|| format!("Unknown cluster {cluster_id}"))?;
+    if let Some(id) = keep_ids.iter().find(|id| !cluster.record_ids.contains(id)) {
+      return Err(format!("Record {id} is not in cluster {cluster_id}"));
+    }
+    cluster.keep_ids = Some(keep_ids.to_vec());
+    let cluster = cluster.clone();
+    self.apply_cluster_decision(&cluster);
+    Ok(())
+  }
+
+  /// Re-applies every decision, e.g. after filters reset the derived ids.
+  pub fn apply_cluster_decisions(&mut self) {
+    let Some(review) = self.cluster_review.as_ref().filter(|review| !review.stale) else {
+      return;
+    };
+    let decided = review
+      .clusters
+      .iter()
+      .filter(|cluster| cluster.keep_ids.is_some())
+      .cloned()
+      .collect::<Vec<_>>();
+    for cluster in &decided {
+      self.apply_cluster_decision(cluster);
+    }
+  }
+
+  fn apply_cluster_decision(&mut self, cluster: &ReviewCluster) {
+    let Some(keep_ids) = &cluster.keep_ids else {
+      return;
+    };
+    let record_count = self
+      .dataset
+      .as_ref()
+      .map(|store| store.record_count)
+      .unwrap_or_default();
+    let filtered_ids = self
+      .filtered_ids
+      .get_or_insert_with(|| IdSet::full(record_count));
+    for id in &cluster.record_ids {
+      let keep = keep_ids.contains(id);
+      if keep {
+        filtered_ids.insert(*id);
+        self.manual_exclude.remove(id);
+        self.manual_include.insert(*id);
+      } else {
+        filtered_ids.remove(*id);
+        self.manual_include.remove(id);
+        self.manual_exclude.insert(*id);
+      }
+      if let (Some(selected_ids), Some(removed_ids)) =
+        (self.selected_ids.as_mut(), self.removed_ids.as_mut())
+      {
+        if keep {
+          selected_ids.insert(*id);
+          removed_ids.remove(*id);
+        } else {
+          selected_ids.remove(*id);
+          removed_ids.insert(*id);
+        }
+      }
+    }
+  }
+
+  /// Marks the clusters stale when `filters` differ from the ones they were computed with.
+  pub fn check_cluster_filters(&mut self, filters: &FilterConfig) {
+    if let Some(review) = self.cluster_review.as_mut() {
+      if review.filters_key != filters_key(filters) {
+        review.stale = true;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn decisions_carry_over_to_records_at_new_ids() {
+    let filters = FilterConfig::default();
+    let mut previous =
+      ClusterReview::new(vec![vec![0, 1], vec![2, 3]], &filters, &[10, 11, 12, 13]);
+    previous.clusters[0].keep_ids = Some(vec![1]);
+    // Another store holding the same records shuffled, plus an undecided pair.
+    let hashes = [13, 99, 11, 12, 10, 98];
+    let clusters = vec![vec![1, 5], vec![2, 4], vec![0, 3]];
+    let mut review = ClusterReview::new(clusters, &filters, &hashes);
+    assert_eq!(review.restore_from(&previous), 1);
+    assert_eq!(review.clusters[1].keep_ids, Some(vec![2]));
+    assert_eq!(review.clusters[0].keep_ids, None);
+    assert_eq!(review.clusters[2].keep_ids, None);
+  }
+
+  #[test]
+  fn reviews_saved_without_hashes_restore_nothing() {
+    let filters = FilterConfig::default();
+    let mut previous = ClusterReview::new(vec![vec![0, 1]], &filters, &[]);
+    previous.clusters[0].keep_ids = Some(vec![0]);
+    let mut review = ClusterReview::new(vec![vec![0, 1]], &filters, &[10, 11]);
+    assert_eq!(review.restore_from(&previous), 0);
+    assert_eq!(review.next_unreviewed(), Some(0));
+  }
+}
@@ -0,0 +1,375 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rand::rngs::StdRng;
+use rand::seq::index;
+use rand::SeedableRng;
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::categories::CategorySource;
+use crate::filters::{normalize_for_dedupe, SimhashIndex};
+use crate::io::store_lines;
+use crate::metrics::numeric_value;
+use crate::models::{
+  CategoryRules,
+  CategoryShare,
+  DatasetComparison,
+  FieldMap,
+  NumericSummary,
+  OverlapEstimate,
+  UNCATEGORIZED_LABEL,
+};
+use crate::records::{extract_text_value, get_length_text, simhash, text_length_in};
+use crate::render::markdown_cell;
+use crate::state::DatasetStore;
+
+/// Records of A whose instructions are looked up in B by default.
+pub const DEFAULT_OVERLAP_SAMPLE: usize = 2_000;
+/// Fixed so repeated comparisons of the same datasets agree.
+const OVERLAP_SEED: u64 = 0;
+
+#[derive(Debug, Clone)]
+pub struct CompareSpec {
+  pub sample_size: usize,
+  /// Text the length distributions measure: `instruction`, `output` or `combined`.
+  pub length_scope: String,
+  /// `chars` or `tokens`.
+  pub length_unit: String,
+}
+
+#[derive(Default)]
+struct Profile {
+  lengths: Vec<f64>,
+  scores: Vec<f64>,
+  categories: HashMap<String, usize>,
+}
+
+/// Instructions of the sampled records of A, by sample slot.
+#[derive(Default)]
+struct OverlapSample {
+  exact: HashMap<u64, Vec<usize>>,
+  fuzzy: SimhashIndex,
+  exact_found: Vec<bool>,
+  fuzzy_found: Vec<bool>,
+}
+
+impl OverlapSample {
+  fn insert(&mut self, instruction: &str) {
+    let slot = self.exact_found.len();
+    let key = xxh3_64(normalize_for_dedupe(instruction).as_bytes());
+    self.exact.entry(key).or_default().push(slot);
+    self.fuzzy.insert(simhash(instruction), 0, slot);
+    self.exact_found.push(false);
+    self.fuzzy_found.push(false);
+  }
+
+  fn check(&mut self, instruction: &str) {
+    let key = xxh3_64(normalize_for_dedupe(instruction).as_bytes());
+    if let Some(slots) = self.exact.get(&key) {
+      for slot in slots {
+        self.exact_found[*slot] = true;
+        self.fuzzy_found[*slot] = true;
+      }
+    }
+    if let Some((slot, _)) = self.fuzzy.find(simhash(instruction), |_| Some(0)) {
+      self.fuzzy_found[slot] = true;
+    }
+  }
+
+  fn estimate(&self) -> OverlapEstimate {
+    let sample_size = self.exact_found.len();
+    let exact_count = self.exact_found.iter().filter(|found| **found).count();
+    let fuzzy_count = self.fuzzy_found.iter().filter(|found| **found).count();
+    let rate = |count: usize| {
+      if sample_size == 0 {
+        0.0
+      } else {
+        count as f64 / sample_size as f64
+      }
+    };
+    OverlapEstimate {
+      sample_size,
+      exact_count,
+      fuzzy_count,
+      exact_rate: rate(exact_count),
+      fuzzy_rate: rate(fuzzy_count),
+    }
+  }
+}
+
+/// Streams `store`, profiling every record and handing its non-empty
+/// instruction to `on_instruction`.
+fn scan(
+  store: &DatasetStore,
+  spec: &CompareSpec,
+  field_map: &FieldMap,
+  categories: &CategorySource,
+  cancel: &AtomicBool,
+  on_progress: &mut impl FnMut(usize),
+  mut on_instruction: impl FnMut(usize, &str),
+) -> Result<Profile, String> {
+  let mut profile = Profile::default();
+  for line in store_lines(store)? {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Comparison canceled".to_string());
+    }
+    let line = line?;
+    let idx = line.id;
+    let Some(record) = line.record()? else {
+      continue;
+    };
+    if idx.is_multiple_of(1000) {
+      on_progress(idx);
+    }
+    let length_text = get_length_text(&record, field_map, &spec.length_scope);
+    profile.lengths.push(text_length_in(&length_text, &spec.length_unit) as f64);
+    if let Some(score) = numeric_value(&record, &field_map.score) {
+      profile.scores.push(score);
+    }
+    if !categories.is_none() {
+      let name = categories
+        .category(&record, field_map)
+        .unwrap_or_else(|| UNCATEGORIZED_LABEL.to_string());
+      *profile.categories.entry(name).or_insert(0) += 1;
+    }
+    let instruction = extract_text_value(&record, &field_map.instruction).unwrap_or_default();
+    if !instruction.trim().is_empty() {
+      on_instruction(idx, &instruction);
+    }
+  }
+  Ok(profile)
+}
+
+/// Nearest-rank summary of `values`; all zero when empty.
+fn summarize(mut values: Vec<f64>) -> NumericSummary {
+  if values.is_empty() {
+    return NumericSummary::default();
+  }
+  values.sort_by(f64::total_cmp);
+  let quantile = |q: f64| values[((values.len() - 1) as f64 * q).round() as usize];
+  NumericSummary {
+    count: values.len(),
+    min: values[0],
+    max: values[values.len() - 1],
+    mean: values.iter().sum::<f64>() / values.len() as f64,
+    p10: quantile(0.1),
+    p50: quantile(0.5),
+    p90: quantile(0.9),
+  }
+}
+
+fn category_shares(
+  a: &Profile,
+  b: &Profile,
+  count_a: usize,
+  count_b: usize,
+) -> Vec<CategoryShare> {
+  let share = |count: usize, total: usize| {
+    if total == 0 {
+      0.0
+    } else {
+      count as f64 / total as f64
+    }
+  };
+  let names = a
+    .categories
+    .keys()
+    .chain(b.categories.keys())
+    .collect::<BTreeSet<_>>();
+  let mut shares = names
+    .into_iter()
+    .map(|name| {
+      let count_a_in = a.categories.get(name).copied().unwrap_or(0);
+      let count_b_in = b.categories.get(name).copied().unwrap_or(0);
+      CategoryShare {
+        name: name.clone(),
+        count_a: count_a_in,
+        count_b: count_b_in,
+        share_a: share(count_a_in, count_a),
+        share_b: share(count_b_in, count_b),
+      }
+    })
+    .collect::<Vec<_>>();
+  shares.sort_by_key(|item| std::cmp::Reverse(item.count_a + item.count_b));
+  shares
+}
+
+/// Compares two stores in one pass over each: fields, length, score and
+/// category distributions, plus an instruction overlap estimate from a seeded
+/// sample of A looked up against every record of B. Progress is reported per
+/// phase, `dataset_a` then `dataset_b`.
+pub fn compare_datasets(
+  a: &DatasetStore,
+  b: &DatasetStore,
+  spec: &CompareSpec,
+  field_map: &FieldMap,
+  category_rules: &CategoryRules,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(&str, usize, usize),
+) -> Result<DatasetComparison, String> {
+  let categories = CategorySource::new(field_map.category.as_deref(), category_rules)?;
+  let sample_size = spec.sample_size.min(a.record_count);
+  let mut rng = StdRng::seed_from_u64(OVERLAP_SEED);
+  let sampled = index::sample(&mut rng, a.record_count, sample_size)
+    .into_iter()
+    .collect::<BTreeSet<_>>();
+
+  let mut overlap = OverlapSample::default();
+  let profile_a = scan(
+    a,
+    spec,
+    field_map,
+    &categories,
+    cancel,
+    &mut |current| on_progress("dataset_a", current, a.record_count),
+    |idx, instruction| {
+      if sampled.contains(&idx) {
+        overlap.insert(instruction);
+      }
+    },
+  )?;
+  let profile_b = scan(
+    b,
+    spec,
+    field_map,
+    &categories,
+    cancel,
+    &mut |current| on_progress("dataset_b", current, b.record_count),
+    |_, instruction| overlap.check(instruction),
+  )?;
+
+  let fields_b = b.fields.iter().collect::<BTreeSet<_>>();
+  let fields_a = a.fields.iter().collect::<BTreeSet<_>>();
+  Ok(DatasetComparison {
+    dataset_a: a.summary(),
+    dataset_b: b.summary(),
+    shared_fields: fields_a.intersection(&fields_b).map(|name| name.to_string()).collect(),
+    only_a_fields: fields_a.difference(&fields_b).map(|name| name.to_string()).collect(),
+    only_b_fields: fields_b.difference(&fields_a).map(|name| name.to_string()).collect(),
+    categories: category_shares(&profile_a, &profile_b, a.record_count, b.record_count),
+    length_a: summarize(profile_a.lengths),
+    length_b: summarize(profile_b.lengths),
+    score_a: (!profile_a.scores.is_empty()).then(|| summarize(profile_a.scores)),
+    score_b: (!profile_b.scores.is_empty()).then(|| summarize(profile_b.scores)),
+    overlap: overlap.estimate(),
+    markdown_path: None,
+  })
+}
+
+fn summary_row(
+  out: &mut String,
+  label: &str,
+  a: Option<&NumericSummary>,
+  b: Option<&NumericSummary>,
+) {
+  let values = |summary: Option<&NumericSummary>| {
+    summary.map(|s| [s.min, s.p10, s.p50, s.mean, s.p90, s.max])
+  };
+  let (a, b) = (values(a), values(b));
+  let cell = |values: Option<[f64; 6]>, index: usize| {
+    values.map_or("-".to_string(), |values| format!("{:.1}", values[index]))
+  };
+  for (index, name) in ["min", "p10", "median", "mean", "p90", "max"].iter().enumerate() {
+    let _ = writeln!(out, "| {label} {name} | {} | {} |", cell(a, index), cell(b, index));
+  }
+}
+
+/// The comparison as a markdown document.
+pub fn comparison_markdown(report: &DatasetComparison) -> String {
+  let (a, b) = (&report.dataset_a, &report.dataset_b);
+  let mut out = String::new();
+  let _ = writeln!(out, "# Dataset comparison\n");
+  let _ = writeln!(out, "- A: {} ({})", a.source_path, a.id);
+  let _ = writeln!(out, "- B: {} ({})\n", b.source_path, b.id);
+
+  let _ = writeln!(out, "## Overview\n");
+  let _ = writeln!(out, "| | A | B |\n|---|---|---|");
+  let _ = writeln!(out, "| records | {} | {} |", a.record_count, b.record_count);
+  let _ = writeln!(out, "| size (bytes) | {} | {} |", a.size_bytes, b.size_bytes);
+  summary_row(&mut out, "length", Some(&report.length_a), Some(&report.length_b));
+  if report.score_a.is_some() || report.score_b.is_some() {
+    summary_row(&mut out, "score", report.score_a.as_ref(), report.score_b.as_ref());
+  }
+
+  let list = |fields: &[String]| {
+    if fields.is_empty() {
+      "none".to_string()
+    } else {
+      fields.join(", ")
+    }
+  };
+  let _ = writeln!(out, "\n## Fields\n");
+  let _ = writeln!(out, "- shared: {}", list(&report.shared_fields));
+  let _ = writeln!(out, "- only in A: {}", list(&report.only_a_fields));
+  let _ = writeln!(out, "- only in B: {}", list(&report.only_b_fields));
+
+  let overlap = &report.overlap;
+  let _ = writeln!(out, "\n## Instruction overlap\n");
+  let _ = writeln!(
+    out,
+    "Of {} sampled instructions from A, {} ({:.1}%) appear in B exactly and {} ({:.1}%) \
+     exactly or as near duplicates.",
+    overlap.sample_size,
+    overlap.exact_count,
+    overlap.exact_rate * 100.0,
+    overlap.fuzzy_count,
+    overlap.fuzzy_rate * 100.0
+  );
+
+  if !report.categories.is_empty() {
+    let _ = writeln!(out, "\n## Categories\n");
+    let _ = writeln!(out, "| category | A | A % | B | B % |\n|---|---|---|---|---|");
+    for item in &report.categories {
+      let _ = writeln!(
+        out,
+        "| {} | {} | {:.1} | {} | {:.1} |",
+        markdown_cell(&item.name, usize::MAX),
+        item.count_a,
+        item.share_a * 100.0,
+        item.count_b,
+        item.share_b * 100.0
+      );
+    }
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+  use crate::test_support::{assert_phases_in_order, jsonl_store, text_field_map, TempDir};
+
+  #[test]
+  fn comparison_reports_each_dataset_in_turn() {
+    let (dir_a, dir_b) = (TempDir::new(), TempDir::new());
+    let records = |count: usize| {
+      (0..count)
+        .map(|id| json!({ "instruction": format!("q{id}"), "output": "a" }))
+        .collect::<Vec<_>>()
+    };
+    let a = jsonl_store(&dir_a, &records(2500));
+    let b = jsonl_store(&dir_b, &records(1500));
+    let spec = CompareSpec {
+      sample_size: 100,
+      length_scope: "combined".to_string(),
+      length_unit: "chars".to_string(),
+    };
+    let mut events = Vec::new();
+    let comparison = compare_datasets(
+      &a,
+      &b,
+      &spec,
+      &text_field_map(),
+      &CategoryRules::default(),
+      &AtomicBool::new(false),
+      |phase, current, total| events.push((phase.to_string(), current, total)),
+    )
+    .unwrap();
+    assert_phases_in_order(&events, &["dataset_a", "dataset_b"]);
+    assert!(events.contains(&("dataset_b".to_string(), 1000, 1500)));
+    assert_eq!(comparison.dataset_b.record_count, 1500);
+  }
+}
@@ -0,0 +1,255 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::io::{
+  create_csv_writer,
+  csv_row,
+  export_columns,
+  export_json_text,
+  json_array_end,
+  json_separator,
+  new_export_summary,
+  prepare_export_record,
+  read_import_source,
+  validate_csv_options,
+  validate_export_fields,
+  validate_export_template,
+  validate_injected_fields,
+  write_staged,
+  ExportSpec,
+  ExportWriter,
+  NestedColumns,
+};
+use crate::models::{ConvertSummary, ExportSummary, ImportOptions};
+use crate::timing::StageTimer;
+use crate::transform::TruncateSpec;
+
+/// Converts the source at `input` straight into an export at `spec.path`,
+/// read with the import options and written with the export options, in
+/// source order and without a store. Besides `json` and `csv` it writes
+/// `jsonl`. CSV output reads the source twice, first for its columns, so
+/// `on_progress` gets the phase, `columns` or `write`, and a record count.
+pub fn convert_file(
+  input: &Path,
+  import: &ImportOptions,
+  spec: &ExportSpec,
+  cancel: &AtomicBool,
+  on_progress: impl FnMut(&str, usize),
+) -> Result<ConvertSummary, String> {
+  if !matches!(spec.format.as_str(), "json" | "jsonl" | "csv") {
+    return Err(format!("Unsupported export format: {}", spec.format));
+  }
+  if !spec.options.order_by.is_empty() || spec.options.order != "original" {
+    return Err("A converted file keeps the source order".to_string());
+  }
+  if spec.options.flatten_nested && spec.format != "csv" {
+    return Err("Only CSV exports flatten nested fields".to_string());
+  }
+  if spec.options.pretty && spec.format != "json" {
+    return Err("Only JSON array exports can be pretty-printed".to_string());
+  }
+  if spec.options.verify {
+    return Err("Only exports of a loaded dataset can be verified".to_string());
+  }
+  if spec.path.exists() && fs::canonicalize(input).ok() == fs::canonicalize(&spec.path).ok() {
+    return Err("A file cannot be converted onto itself".to_string());
+  }
+  validate_injected_fields(&spec.options)?;
+  validate_export_fields(&spec.options)?;
+  validate_export_template(spec)?;
+  if spec.format == "csv" {
+    validate_csv_options(&spec.options.csv)?;
+  }
+  let truncate = spec
+    .options
+    .truncate
+    .as_ref()
+    .map(TruncateSpec::from_options)
+    .transpose()?;
+  write_staged(spec, |staged| {
+    let result = write_conversion(input, import, staged, truncate.as_ref(), cancel, on_progress);
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Conversion canceled".to_string());
+    }
+    result
+  })
+}
+
+fn write_conversion(
+  input: &Path,
+  import: &ImportOptions,
+  spec: &ExportSpec,
+  truncate: Option<&TruncateSpec>,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(&str, usize),
+) -> Result<ConvertSummary, String> {
+  let mut summary = new_export_summary(spec);
+  let mut timer = StageTimer::new();
+  let source = if spec.format == "csv" {
+    // Sorted like the fields of an imported store, so converting matches
+    // importing and exporting.
+    let mut fields = HashSet::new();
+    let mut nested = NestedColumns::default();
+    let mut count = 0usize;
+    timer.time("columns", || {
+      read_import_source(input, import, cancel, |record, _, _| {
+        if let Some(map) = record.as_object() {
+          for key in map.keys() {
+            if !fields.contains(key) {
+              fields.insert(key.clone());
+            }
+          }
+        }
+        if spec.options.flatten_nested {
+          let mut scratch = ExportSummary::default();
+          if let Some(record) = prepare_export_record(record, spec, truncate, &mut scratch) {
+            nested.add(&record, spec.options.flatten_arrays);
+          }
+        }
+        count += 1;
+        if count.is_multiple_of(500) {
+          on_progress("columns", count);
+        }
+        Ok(())
+      })
+    })?;
+    timer.count("columns", count);
+    let mut fields = fields.into_iter().collect::<Vec<_>>();
+    fields.sort();
+    let mut columns = export_columns(&fields, spec);
+    if spec.options.flatten_nested {
+      columns = nested.spread(&columns);
+    }
+    let mut writer = create_csv_writer(spec, &columns)?;
+    let source = timer.time("write", || {
+      read_import_source(input, import, cancel, |record, _, _| {
+        let Some(record) = prepare_export_record(record, spec, truncate, &mut summary) else {
+          return Ok(());
+        };
+        writer
+          .write_record(csv_row(&record, &columns, &spec.options))
+          .map_err(|e| e.to_string())?;
+        summary.exported_count += 1;
+        if summary.exported_count.is_multiple_of(500) {
+          on_progress("write", summary.exported_count);
+        }
+        Ok(())
+      })
+    })?;
+    timer.time("write", || {
+      let file = writer.into_inner().map_err(|e| e.to_string())?;
+      file.finish().map_err(|e| e.to_string())
+    })?;
+    source
+  } else {
+    let array = spec.format == "json";
+    let mut file = ExportWriter::create(&spec.path, spec.compression)?;
+    if array {
+      file.write_all(b"[").map_err(|e| e.to_string())?;
+    }
+    let source = timer.time("write", || {
+      read_import_source(input, import, cancel, |record, line, _| {
+        let rewritten;
+        let pretty = spec.options.pretty;
+        let line = if spec.options.rewrites_records() || pretty {
+          let Some(record) = prepare_export_record(record, spec, truncate, &mut summary) else {
+            return Ok(());
+          };
+          rewritten = export_json_text(&record, pretty)?;
+          rewritten.as_bytes()
+        } else {
+          line
+        };
+        if array {
+          let separator = json_separator(summary.exported_count == 0, pretty);
+          file.write_all(separator).map_err(|e| e.to_string())?;
+        }
+        file.write_all(line).map_err(|e| e.to_string())?;
+        if !array {
+          file.write_all(b"\n").map_err(|e| e.to_string())?;
+        }
+        summary.exported_count += 1;
+        if summary.exported_count.is_multiple_of(500) {
+          on_progress("write", summary.exported_count);
+        }
+        Ok(())
+      })
+    })?;
+    if array {
+      let end = json_array_end(summary.exported_count == 0, spec.options.pretty);
+      file.write_all(end).map_err(|e| e.to_string())?;
+    }
+    timer.time("write", || file.finish()).map_err(|e| e.to_string())?;
+    source
+  };
+  timer.count("write", summary.exported_count);
+  summary.timings = timer.finish();
+  Ok(ConvertSummary {
+    source_format: source.format,
+    import_report: source.report,
+    export: summary,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+  use crate::io::{ingest_dataset, ExportCompression};
+  use crate::models::ExportOptions;
+  use crate::test_support::{stored_records, text_field_map, TempDir};
+
+  #[test]
+  fn conversions_keep_every_record_across_format_pairs() {
+    let dir = TempDir::new();
+    let expected = vec![
+      json!({ "instruction": "q1", "output": "a, b" }),
+      json!({ "instruction": "q2", "output": "line\nbreak \"quoted\"" }),
+    ];
+    let jsonl = expected.iter().map(|record| format!("{record}\n")).collect::<String>();
+    let sources = [
+      ("source.jsonl", jsonl),
+      ("source.json", serde_json::to_string_pretty(&expected).unwrap()),
+      ("source.csv", "instruction,output\nq1,\"a, b\"\nq2,\"line\nbreak \"\"quoted\"\"\"\n".into()),
+    ];
+    let targets = [
+      ("jsonl", ExportCompression::None),
+      ("json", ExportCompression::None),
+      ("csv", ExportCompression::None),
+      ("jsonl", ExportCompression::Gzip),
+    ];
+    for (source, contents) in sources {
+      let input = dir.join(source);
+      fs::write(&input, contents).unwrap();
+      for (format, compression) in targets {
+        let gzip = compression == ExportCompression::Gzip;
+        let name = format!("{source}-to.{format}{}", if gzip { ".gz" } else { "" });
+        let spec = ExportSpec {
+          path: dir.join(&name),
+          format: format.to_string(),
+          compression,
+          options: ExportOptions::default(),
+          field_map: text_field_map(),
+        };
+        let cancel = AtomicBool::new(false);
+        let summary =
+          convert_file(&input, &ImportOptions::default(), &spec, &cancel, |_, _| {}).unwrap();
+        assert_eq!(summary.export.exported_count, 2, "{name}");
+        let converted = ingest_dataset(
+          &spec.path,
+          &dir.join(&format!("{name}-store")),
+          &ImportOptions::default(),
+          &cancel,
+          |_, _| {},
+        )
+        .unwrap()
+        .0;
+        assert_eq!(stored_records(&converted), expected, "{name}");
+      }
+    }
+  }
+}
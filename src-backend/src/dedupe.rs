@@ -0,0 +1,151 @@
+use std::collections::{HashMap, HashSet};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::records::shingle_hashes;
+
+/// Hash functions in a MinHash signature.
+pub const MINHASH_PERMUTATIONS: usize = 128;
+/// Bands a signature is split into for LSH. Records sharing any band are
+/// compared; 16 bands of 8 rows make pairs above about 0.7 similarity
+/// likely candidates.
+const LSH_BANDS: usize = 16;
+const LSH_ROWS: usize = MINHASH_PERMUTATIONS / LSH_BANDS;
+/// Fixed so signatures agree between runs.
+const PERMUTATION_SEED: u64 = 0x6d69_6e68_6173_6801;
+/// Estimated Jaccard similarity at which two texts count as near-duplicates.
+pub const DEFAULT_MINHASH_THRESHOLD: f64 = 0.8;
+
+pub type MinhashSignature = [u32; MINHASH_PERMUTATIONS];
+
+/// The permutations signatures are computed with: `a * hash + b` for an odd
+/// `a`, keeping the high 32 bits.
+pub struct MinHasher {
+  permutations: Vec<(u64, u64)>,
+}
+
+impl Default for MinHasher {
+  fn default() -> Self {
+    let mut rng = StdRng::seed_from_u64(PERMUTATION_SEED);
+    let permutations = (0..MINHASH_PERMUTATIONS)
+      .map(|_| (rng.gen::<u64>() | 1, rng.gen::<u64>()))
+      .collect();
+    Self { permutations }
+  }
+}
+
+impl MinHasher {
+  /// Signature of the word shingles of `text`, or `None` when it has none,
+  /// as punctuation or emoji alone, which would otherwise all share one
+  /// signature and match each other.
+  pub fn signature(&self, text: &str, shingle_size: usize) -> Option<MinhashSignature> {
+    let hashes = shingle_hashes(text, shingle_size);
+    if hashes.is_empty() {
+      return None;
+    }
+    let mut signature = [u32::MAX; MINHASH_PERMUTATIONS];
+    for hash in hashes {
+      for (slot, (a, b)) in signature.iter_mut().zip(&self.permutations) {
+        let permuted = (a.wrapping_mul(hash).wrapping_add(*b) >> 32) as u32;
+        *slot = (*slot).min(permuted);
+      }
+    }
+    Some(signature)
+  }
+}
+
+/// Share of positions two signatures agree on, which estimates the Jaccard
+/// similarity of their shingle sets.
+pub fn estimated_jaccard(a: &MinhashSignature, b: &MinhashSignature) -> f64 {
+  let equal = a.iter().zip(b).filter(|(a, b)| a == b).count();
+  equal as f64 / MINHASH_PERMUTATIONS as f64
+}
+
+fn band_keys(signature: &MinhashSignature) -> [u64; LSH_BANDS] {
+  let mut keys = [0u64; LSH_BANDS];
+  for (key, rows) in keys.iter_mut().zip(signature.chunks_exact(LSH_ROWS)) {
+    let mut bytes = [0u8; LSH_ROWS * 4];
+    for (chunk, row) in bytes.chunks_exact_mut(4).zip(rows) {
+      chunk.copy_from_slice(&row.to_le_bytes());
+    }
+    *key = xxh3_64(&bytes);
+  }
+  keys
+}
+
+/// MinHash signatures bucketed by LSH band, each with an optional secondary
+/// signature so joint matches can be checked on the same candidates, and the
+/// id of the record it came from. Only signatures are kept, never texts.
+#[derive(Default)]
+pub struct MinhashIndex {
+  bands: Vec<HashMap<u64, Vec<usize>>>,
+  entries: Vec<(MinhashSignature, Option<Box<MinhashSignature>>, usize)>,
+}
+
+impl MinhashIndex {
+  /// The id of the indexed record most similar to `key`, and that similarity,
+  /// when it reaches `threshold`. With a `secondary` signature both must
+  /// reach it and the lower similarity is reported.
+  pub fn find(
+    &self,
+    key: &MinhashSignature,
+    secondary: Option<&MinhashSignature>,
+    threshold: f64,
+  ) -> Option<(usize, f64)> {
+    let mut compared = HashSet::new();
+    let mut best: Option<(usize, f64)> = None;
+    for (band, band_key) in self.bands.iter().zip(band_keys(key)) {
+      let Some(slots) = band.get(&band_key) else {
+        continue;
+      };
+      for slot in slots {
+        if !compared.insert(*slot) {
+          continue;
+        }
+        let (candidate, candidate_secondary, id) = &self.entries[*slot];
+        let mut similarity = estimated_jaccard(candidate, key);
+        if let (Some(candidate_secondary), Some(secondary)) = (candidate_secondary, secondary) {
+          similarity = similarity.min(estimated_jaccard(candidate_secondary, secondary));
+        }
+        if similarity >= threshold && best.is_none_or(|(_, found)| similarity > found) {
+          best = Some((*id, similarity));
+        }
+      }
+    }
+    best
+  }
+
+  pub fn insert(
+    &mut self,
+    key: MinhashSignature,
+    secondary: Option<MinhashSignature>,
+    id: usize,
+  ) {
+    if self.bands.is_empty() {
+      self.bands.resize_with(LSH_BANDS, HashMap::new);
+    }
+    let slot = self.entries.len();
+    for (band, band_key) in self.bands.iter_mut().zip(band_keys(&key)) {
+      band.entry(band_key).or_default().push(slot);
+    }
+    self.entries.push((key, secondary.map(Box::new), id));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn texts_without_words_have_no_signature() {
+    let minhasher = MinHasher::default();
+    for text in ["", "???", "...", "🎉🎉🎉", "ok?"] {
+      assert_eq!(minhasher.signature(text, 3), None, "{text:?}");
+    }
+    let signature = minhasher.signature("Explain the borrow checker", 3).unwrap();
+    let again = minhasher.signature("explain the BORROW checker!", 3).unwrap();
+    assert_eq!(estimated_jaccard(&signature, &again), 1.0);
+  }
+}
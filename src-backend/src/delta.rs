@@ -0,0 +1,352 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::distill::DEFAULT_RANDOM_SEED;
+use crate::io::ExportSpec;
+use crate::models::{DeltaManifest, DistillConfig, ExportManifest, ExportSummary, FilterConfig};
+use crate::paths::{atomic_write_json, io_error, write_atomic_with};
+use crate::state::DatasetStore;
+
+const EXPORTS_MAGIC: &[u8; 8] = b"DLEXP01\n";
+
+/// The last successful export of one view of a dataset.
+#[derive(Debug, Clone)]
+pub struct LastExport {
+  pub view: String,
+  pub path: String,
+  pub format: String,
+  pub exported_at: u64,
+  /// SHA-256 of the exported file, hex.
+  pub sha256: String,
+  /// Sorted content hashes of the records downstream has after this export.
+  pub hashes: Vec<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportEntry {
+  view: String,
+  path: String,
+  format: String,
+  exported_at: u64,
+  sha256: String,
+  hash_count: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportsHeader {
+  dataset_id: String,
+  entries: Vec<ExportEntry>,
+}
+
+/// Sidecar holding the last export of each view: a JSON header, then the
+/// content hashes of every entry as little-endian u64s, in entry order.
+pub fn exports_path(store: &DatasetStore) -> PathBuf {
+  store.store_path.with_extension("exports")
+}
+
+fn unix_now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|elapsed| elapsed.as_secs())
+    .unwrap_or_default()
+}
+
+fn read_exports(store: &DatasetStore) -> Result<Vec<LastExport>, String> {
+  let path = exports_path(store);
+  if !path.exists() {
+    return Ok(Vec::new());
+  }
+  let mut reader = BufReader::new(File::open(&path).map_err(|e| io_error(&path, &e))?);
+  let mut magic = [0u8; 8];
+  reader.read_exact(&mut magic).map_err(|e| e.to_string())?;
+  if &magic != EXPORTS_MAGIC {
+    return Err("Unrecognized export history file".to_string());
+  }
+  let mut header_len = [0u8; 8];
+  reader
+    .read_exact(&mut header_len)
+    .map_err(|e| e.to_string())?;
+  let mut header_bytes = vec![0u8; u64::from_le_bytes(header_len) as usize];
+  reader
+    .read_exact(&mut header_bytes)
+    .map_err(|e| e.to_string())?;
+  let header: ExportsHeader = serde_json::from_slice(&header_bytes).map_err(|e| e.to_string())?;
+  if header.dataset_id != store.id {
+    return Ok(Vec::new());
+  }
+  let mut exports = Vec::with_capacity(header.entries.len());
+  let mut buffer = [0u8; 8];
+  for entry in header.entries {
+    let mut hashes = Vec::with_capacity(entry.hash_count);
+    for _ in 0..entry.hash_count {
+      reader.read_exact(&mut buffer).map_err(|e| e.to_string())?;
+      hashes.push(u64::from_le_bytes(buffer));
+    }
+    exports.push(LastExport {
+      view: entry.view,
+      path: entry.path,
+      format: entry.format,
+      exported_at: entry.exported_at,
+      sha256: entry.sha256,
+      hashes,
+    });
+  }
+  Ok(exports)
+}
+
+/// The last successful export of `view`, if one was recorded.
+pub fn load_last_export(store: &DatasetStore, view: &str) -> Result<Option<LastExport>, String> {
+  Ok(read_exports(store)?.into_iter().find(|export| export.view == view))
+}
+
+/// Stores `export` as the last export of its view, replacing the previous one.
+fn save_last_export(store: &DatasetStore, export: LastExport) -> Result<(), String> {
+  // An unreadable history is replaced rather than blocking the export.
+  let mut exports = read_exports(store).unwrap_or_default();
+  exports.retain(|existing| existing.view != export.view);
+  exports.push(export);
+  let header = ExportsHeader {
+    dataset_id: store.id.clone(),
+    entries: exports
+      .iter()
+      .map(|export| ExportEntry {
+        view: export.view.clone(),
+        path: export.path.clone(),
+        format: export.format.clone(),
+        exported_at: export.exported_at,
+        sha256: export.sha256.clone(),
+        hash_count: export.hashes.len(),
+      })
+      .collect(),
+  };
+  let header_bytes = serde_json::to_vec(&header).map_err(|e| e.to_string())?;
+  write_atomic_with(&exports_path(store), |writer| {
+    writer.write_all(EXPORTS_MAGIC).map_err(|e| e.to_string())?;
+    writer
+      .write_all(&(header_bytes.len() as u64).to_le_bytes())
+      .map_err(|e| e.to_string())?;
+    writer.write_all(&header_bytes).map_err(|e| e.to_string())?;
+    for export in &exports {
+      for hash in &export.hashes {
+        writer
+          .write_all(&hash.to_le_bytes())
+          .map_err(|e| e.to_string())?;
+      }
+    }
+    Ok(())
+  })
+}
+
+/// SHA-256 of the file at `path`, hex.
+pub fn file_sha256(path: &Path) -> Result<String, String> {
+  let mut file = File::open(path).map_err(|e| io_error(path, &e))?;
+  let mut hasher = Sha256::new();
+  let mut buffer = vec![0u8; 1024 * 1024];
+  loop {
+    let read = file.read(&mut buffer).map_err(|e| io_error(path, &e))?;
+    if read == 0 {
+      break;
+    }
+    hasher.update(&buffer[..read]);
+  }
+  Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Records the file at `path` as the last export of `view`, downstream now
+/// holding the records with content `hashes`.
+pub fn record_export(
+  store: &DatasetStore,
+  view: &str,
+  path: &Path,
+  format: &str,
+  hashes: &[u64],
+) -> Result<LastExport, String> {
+  let mut hashes = hashes.to_vec();
+  hashes.sort_unstable();
+  hashes.dedup();
+  let export = LastExport {
+    view: view.to_string(),
+    path: path.display().to_string(),
+    format: format.to_string(),
+    exported_at: unix_now(),
+    sha256: file_sha256(path)?,
+    hashes,
+  };
+  save_last_export(store, export.clone())?;
+  Ok(export)
+}
+
+/// What changed since `previous`: positions in `current` of records whose
+/// content was not exported before, and hashes exported before that are no
+/// longer present.
+pub fn plan_delta(current: &[u64], previous: &[u64]) -> (Vec<usize>, Vec<u64>) {
+  let before = previous.iter().copied().collect::<HashSet<_>>();
+  let now = current.iter().copied().collect::<HashSet<_>>();
+  let added = current
+    .iter()
+    .enumerate()
+    .filter(|(_, hash)| !before.contains(hash))
+    .map(|(position, _)| position)
+    .collect();
+  let removed = previous
+    .iter()
+    .copied()
+    .filter(|hash| !now.contains(hash))
+    .collect();
+  (added, removed)
+}
+
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+  let mut name = path.as_os_str().to_os_string();
+  name.push(suffix);
+  PathBuf::from(name)
+}
+
+/// `<path>.removals.txt` and `<path>.manifest.json` next to a delta export.
+pub fn delta_sibling_paths(path: &Path) -> (PathBuf, PathBuf) {
+  (sibling_path(path, ".removals.txt"), export_manifest_path(path))
+}
+
+/// `<path>.manifest.json`, where the manifest of an export goes.
+pub fn export_manifest_path(path: &Path) -> PathBuf {
+  sibling_path(path, ".manifest.json")
+}
+
+/// Writes one removed content hash (hex) per line.
+pub fn write_removals(path: &Path, removed: &[u64]) -> Result<(), String> {
+  write_atomic_with(path, |writer| {
+    for hash in removed {
+      writeln!(writer, "{hash:016x}").map_err(|e| e.to_string())?;
+    }
+    Ok(())
+  })
+}
+
+pub fn write_delta_manifest(path: &Path, manifest: &DeltaManifest) -> Result<(), String> {
+  atomic_write_json(path, manifest)
+}
+
+pub fn write_export_manifest(path: &Path, manifest: &ExportManifest) -> Result<(), String> {
+  atomic_write_json(path, manifest)
+}
+
+/// State an export manifest records, taken when the export starts.
+#[derive(Debug, Clone)]
+pub struct ManifestContext {
+  /// Where the manifest goes, `export_manifest_path` of the export.
+  pub path: PathBuf,
+  pub view: String,
+  pub view_record_count: usize,
+  pub filters: FilterConfig,
+  pub distill_config: DistillConfig,
+  pub app_version: String,
+}
+
+/// Writes the manifest of the export at `spec.path`, reusing the time and
+/// hash of `recorded` when the export was recorded. Returns where it was
+/// written.
+pub fn write_manifest(
+  store: &DatasetStore,
+  spec: &ExportSpec,
+  summary: &ExportSummary,
+  recorded: Option<&LastExport>,
+  context: ManifestContext,
+) -> Result<PathBuf, String> {
+  let (created_at, sha256) = match recorded {
+    Some(recorded) => (recorded.exported_at, recorded.sha256.clone()),
+    None => (unix_now(), file_sha256(&spec.path)?),
+  };
+  let manifest = ExportManifest {
+    dataset_id: store.id.clone(),
+    source_path: store.source_path.to_string_lossy().to_string(),
+    view: context.view,
+    format: spec.format.clone(),
+    created_at,
+    app_version: context.app_version,
+    file: spec.path.display().to_string(),
+    sha256,
+    dataset_record_count: store.record_count,
+    view_record_count: context.view_record_count,
+    exported_count: summary.exported_count,
+    skipped_count: summary.skipped_count,
+    field_map: spec.field_map.clone(),
+    filters: context.filters,
+    random_seed: context.distill_config.random_seed.unwrap_or(DEFAULT_RANDOM_SEED),
+    distill_config: context.distill_config,
+    order_by: summary.order_by.clone(),
+    shuffle_seed: summary.shuffle_seed,
+  };
+  write_export_manifest(&context.path, &manifest)?;
+  Ok(context.path)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use serde_json::json;
+
+  use super::*;
+  use crate::io::ExportCompression;
+  use crate::models::ExportOptions;
+  use crate::test_support::{jsonl_store, text_field_map, TempDir};
+
+  #[test]
+  fn manifests_describe_the_export_and_reuse_its_recorded_hash() {
+    let dir = TempDir::new();
+    let store = jsonl_store(&dir, &[json!({ "instruction": "q", "output": "a" })]);
+    let spec = ExportSpec {
+      path: dir.join("out.jsonl"),
+      format: "jsonl".to_string(),
+      compression: ExportCompression::None,
+      options: ExportOptions::default(),
+      field_map: text_field_map(),
+    };
+    fs::write(&spec.path, "{\"instruction\":\"q\",\"output\":\"a\"}\n").unwrap();
+    let summary = ExportSummary {
+      exported_count: 1,
+      shuffle_seed: Some(7),
+      ..ExportSummary::default()
+    };
+    let context = ManifestContext {
+      path: export_manifest_path(&spec.path),
+      view: "filtered".to_string(),
+      view_record_count: 1,
+      filters: FilterConfig::default(),
+      distill_config: DistillConfig::default(),
+      app_version: "1.2.3".to_string(),
+    };
+    let read = |path: &Path| -> ExportManifest {
+      serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+    };
+
+    let path = write_manifest(&store, &spec, &summary, None, context.clone()).unwrap();
+    assert_eq!(path, dir.join("out.jsonl.manifest.json"));
+    let manifest = read(&path);
+    assert_eq!(manifest.sha256, file_sha256(&spec.path).unwrap());
+    assert_eq!((manifest.view.as_str(), manifest.app_version.as_str()), ("filtered", "1.2.3"));
+    assert_eq!((manifest.dataset_record_count, manifest.exported_count), (1, 1));
+    assert_eq!(manifest.random_seed, DEFAULT_RANDOM_SEED);
+    assert_eq!(manifest.shuffle_seed, Some(7));
+
+    let recorded = LastExport {
+      view: "filtered".to_string(),
+      path: spec.path.display().to_string(),
+      format: "jsonl".to_string(),
+      exported_at: 42,
+      sha256: "recorded".to_string(),
+      hashes: Vec::new(),
+    };
+    let path = write_manifest(&store, &spec, &summary, Some(&recorded), context).unwrap();
+    let manifest = read(&path);
+    assert_eq!((manifest.created_at, manifest.sha256.as_str()), (42, "recorded"));
+  }
+}
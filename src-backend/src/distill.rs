@@ -1,50 +1,156 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::BufReader;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
 use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
 use rand::SeedableRng;
 use serde_json::Value;
 
-use crate::models::{DistillConfig, DistillSummary, FieldMap};
-use crate::records::{extract_text_value, simhash};
-use crate::state::DatasetStore;
+use crate::categories::CategorySource;
+use crate::io::{read_line_bounded, BoundedLine};
+use crate::models::{
+  CategoryRules,
+  ComponentRange,
+  DistillConfig,
+  DistillSummary,
+  FieldMap,
+  ManualChange,
+  PrefixCount,
+  ScoreBreakdown,
+  UNCATEGORIZED_LABEL,
+};
+use crate::records::{extract_text_value, leading_words, shingled_simhash};
+use crate::scoring::{component_values, score_components, weighted_score, ComponentTable};
+use crate::spill::MetaSpill;
+use crate::state::{DatasetStore, IdSet};
+use crate::timing::StageTimer;
+use crate::warnings::{distill_warnings, Uniformity};
+
+/// Prefixes listed in the summary of a `prefix_diversity` selection.
+const TOP_PREFIX_LIMIT: usize = 20;
+/// Seed of the selection when the config sets none.
+pub const DEFAULT_RANDOM_SEED: u64 = 42;
 
 #[derive(Debug, Clone)]
 pub struct RecordMeta {
   pub id: usize,
-  pub category: Option<String>,
+  /// Index into the category names the meta was built with.
+  pub category: Option<u32>,
+  /// Index into the instruction prefixes, for `prefix_diversity` only.
+  pub prefix: Option<u32>,
   pub score: f64,
   pub signature: u64,
 }
 
+/// Names seen while building metas, such as categories, stored once each so a
+/// meta only carries an index.
+#[derive(Debug, Default)]
+pub struct InternedNames {
+  ids: HashMap<String, u32>,
+  names: Vec<String>,
+}
+
+impl InternedNames {
+  pub fn intern(&mut self, name: String) -> u32 {
+    if let Some(id) = self.ids.get(&name) {
+      return *id;
+    }
+    let id = self.names.len() as u32;
+    self.names.push(name.clone());
+    self.ids.insert(name, id);
+    id
+  }
+
+  pub fn name(&self, id: u32) -> &str {
+    &self.names[id as usize]
+  }
+}
+
+/// Data collected alongside the metas: interned names and, for normalized
+/// score components, the raw component values.
+#[derive(Debug, Default)]
+pub struct MetaTables {
+  pub categories: InternedNames,
+  pub prefixes: InternedNames,
+  pub components: ComponentTable,
+}
+
+impl MetaTables {
+  pub fn new(config: &DistillConfig) -> Self {
+    Self {
+      components: ComponentTable::new(config.score_components.len()),
+      ..Self::default()
+    }
+  }
+
+  fn keeps_components(config: &DistillConfig) -> bool {
+    config.normalize_components && !config.score_components.is_empty()
+  }
+}
+
+/// The mapped score, or the weight when no score field is mapped.
+fn mapped_score(record: &Value, field_map: &FieldMap) -> f64 {
+  let score_field = if field_map.score.is_some() {
+    &field_map.score
+  } else {
+    &field_map.weight
+  };
+  extract_text_value(record, score_field)
+    .and_then(|value| value.parse::<f64>().ok())
+    .unwrap_or(0.0)
+}
+
 pub fn build_record_meta(
   record: &Value,
   id: usize,
   field_map: &FieldMap,
-  strategy: &str,
+  categories: &CategorySource,
+  tables: &mut MetaTables,
+  config: &DistillConfig,
 ) -> RecordMeta {
-  let category = extract_text_value(record, &field_map.category);
-  let score = extract_text_value(record, &field_map.score)
-    .and_then(|value| value.parse::<f64>().ok())
-    .unwrap_or(0.0);
-  let signature = if strategy == "diversity" {
-    let text = extract_text_value(record, &field_map.instruction).unwrap_or_default();
-    simhash(&text)
+  // A record labeled with the uncategorized name joins the uncategorized bucket.
+  let category = categories
+    .category(record, field_map)
+    .filter(|name| name != UNCATEGORIZED_LABEL)
+    .map(|name| tables.categories.intern(name));
+  let score = if config.score_components.is_empty() {
+    mapped_score(record, field_map)
   } else {
-    0u64
+    let values = component_values(record, &config.score_components);
+    if MetaTables::keeps_components(config) {
+      tables.components.push(&values);
+    }
+    weighted_score(&values, config, &[])
+  };
+  let (signature, prefix) = match config.strategy.as_str() {
+    "diversity" => {
+      let text = extract_text_value(record, &field_map.instruction).unwrap_or_default();
+      (shingled_simhash(&text, config.shingle_size), None)
+    }
+    "prefix_diversity" => {
+      let text = extract_text_value(record, &field_map.instruction).unwrap_or_default();
+      let prefix = leading_words(&text, config.prefix_tokens);
+      (0u64, Some(tables.prefixes.intern(prefix)))
+    }
+    _ => (0u64, None),
   };
   RecordMeta {
     id,
     category,
+    prefix,
     score,
     signature,
   }
 }
 
-fn diversity_select(metas: &[RecordMeta], target: usize, rng: &mut StdRng) -> Vec<usize> {
+fn diversity_select<'a>(
+  metas: impl Iterator<Item = &'a RecordMeta>,
+  target: usize,
+  rng: &mut StdRng,
+) -> Vec<usize> {
   let mut buckets: HashMap<u16, Vec<&RecordMeta>> = HashMap::new();
   for meta in metas {
     let bucket = (meta.signature >> 52) as u16;
@@ -79,131 +185,1083 @@ fn diversity_select(metas: &[RecordMeta], target: usize, rng: &mut StdRng) -> Ve
   selected
 }
 
-fn apply_strategy(metas: &[RecordMeta], target: usize, config: &DistillConfig) -> Vec<usize> {
-  let seed = config.random_seed.unwrap_or(42);
+/// Spreads the target evenly over instruction prefixes; what a small prefix
+/// cannot fill goes to the larger ones. Within a prefix, higher scores win and
+/// ties are broken by the seeded shuffle.
+fn prefix_select<'a>(
+  metas: impl Iterator<Item = &'a RecordMeta>,
+  target: usize,
+  rng: &mut StdRng,
+) -> Vec<usize> {
+  let mut buckets: HashMap<Option<u32>, Vec<&RecordMeta>> = HashMap::new();
+  for meta in metas {
+    buckets.entry(meta.prefix).or_default().push(meta);
+  }
+  let mut buckets = buckets.into_iter().collect::<Vec<_>>();
+  buckets.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(&b.0)));
+  for (_, list) in &mut buckets {
+    list.shuffle(rng);
+    list.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+  }
+
+  let weights = vec![1.0; buckets.len()];
+  let rooms = buckets.iter().map(|(_, list)| list.len()).collect::<Vec<_>>();
+  let allocations = distribute(target, &weights, &rooms);
+  buckets
+    .iter()
+    .zip(allocations)
+    .flat_map(|((_, list), alloc)| list.iter().take(alloc).map(|meta| meta.id))
+    .collect()
+}
+
+/// Picks `target` of `metas` by the configured strategy, as sorted ids.
+fn apply_strategy<'a>(
+  metas: impl Iterator<Item = &'a RecordMeta>,
+  target: usize,
+  config: &DistillConfig,
+) -> Vec<usize> {
+  let seed = config.random_seed.unwrap_or(DEFAULT_RANDOM_SEED);
   let mut rng = StdRng::seed_from_u64(seed);
   let mut selected = match config.strategy.as_str() {
     "importance" => {
-      let mut sorted = metas.to_vec();
+      let mut sorted = metas.collect::<Vec<_>>();
       sorted.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
       sorted.iter().take(target).map(|meta| meta.id).collect()
     }
     "random" => {
-      let mut ids = metas.iter().map(|meta| meta.id).collect::<Vec<_>>();
+      let mut ids = metas.map(|meta| meta.id).collect::<Vec<_>>();
       ids.shuffle(&mut rng);
       ids.truncate(target);
       ids
     }
+    "prefix_diversity" => prefix_select(metas, target, &mut rng),
     _ => diversity_select(metas, target, &mut rng),
   };
   selected.sort_unstable();
   selected
 }
 
-pub fn select_records(metas: &[RecordMeta], config: &DistillConfig) -> Vec<usize> {
-  let total = metas.len();
-  if total == 0 {
-    return Vec::new();
+/// Splits `amount` over buckets in proportion to `weights` without giving any
+/// bucket more than its `room`; what a full bucket cannot take goes to the
+/// others. Fractions are rounded by largest remainder.
+fn distribute(amount: usize, weights: &[f64], rooms: &[usize]) -> Vec<usize> {
+  let mut given = vec![0usize; weights.len()];
+  let mut left = amount;
+  while left > 0 {
+    let open = (0..weights.len())
+      .filter(|index| weights[*index] > 0.0 && given[*index] < rooms[*index])
+      .collect::<Vec<_>>();
+    if open.is_empty() {
+      break;
+    }
+    let open_weight = open.iter().map(|index| weights[*index]).sum::<f64>();
+    let mut fractions = Vec::new();
+    let mut handed = 0usize;
+    for index in &open {
+      let share = left as f64 * weights[*index] / open_weight;
+      let room = rooms[*index] - given[*index];
+      let whole = (share.floor() as usize).min(room);
+      given[*index] += whole;
+      handed += whole;
+      if whole < room {
+        fractions.push((share - share.floor(), *index));
+      }
+    }
+    left -= handed;
+    fractions.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    for (_, index) in fractions {
+      if left == 0 {
+        break;
+      }
+      given[index] += 1;
+      left -= 1;
+    }
   }
-  let target = if let Some(count) = config.target_count {
+  given
+}
+
+/// Per-category allocation for balanced selection: the minimum comes first,
+/// clamped to the category size and the cap, then the rest of the target is
+/// split by weight up to the caps. Returns the allocations and a message for
+/// each constraint that could not be met.
+fn allocate_categories(
+  buckets: &[(String, usize, f32)],
+  target: usize,
+  config: &DistillConfig,
+) -> (Vec<usize>, Vec<String>) {
+  let mut warnings = Vec::new();
+  let weights = buckets
+    .iter()
+    .map(|(_, _, weight)| *weight as f64)
+    .collect::<Vec<_>>();
+  let cap = config
+    .max_per_category_fraction
+    .map(|fraction| (fraction as f64 * target as f64).floor() as usize);
+  let rooms = buckets
+    .iter()
+    .map(|(_, count, _)| cap.map_or(*count, |cap| cap.min(*count)))
+    .collect::<Vec<_>>();
+
+  let mut floors = vec![0usize; buckets.len()];
+  if let Some(min) = config.min_per_category {
+    for (index, (name, count, weight)) in buckets.iter().enumerate() {
+      if *weight <= 0.0 {
+        continue;
+      }
+      floors[index] = min.min(rooms[index]);
+      if *count < min {
+        warnings.push(format!("{name}: only {count} records, below the minimum of {min}"));
+      } else if rooms[index] < min {
+        warnings.push(format!(
+          "{name}: minimum of {min} exceeds the cap of {}",
+          rooms[index]
+        ));
+      }
+    }
+  }
+  let floor_total = floors.iter().sum::<usize>();
+  if floor_total > target {
+    warnings.push(format!(
+      "Minimums need {floor_total} records but the target is {target}"
+    ));
+    let floor_weights = floors.iter().map(|floor| *floor as f64).collect::<Vec<_>>();
+    floors = distribute(target, &floor_weights, &floors);
+  }
+
+  let left = target - floors.iter().sum::<usize>();
+  let extra_rooms = rooms
+    .iter()
+    .zip(&floors)
+    .map(|(room, floor)| room.saturating_sub(*floor))
+    .collect::<Vec<_>>();
+  let extra = distribute(left, &weights, &extra_rooms);
+  let allocations = floors
+    .iter()
+    .zip(&extra)
+    .map(|(floor, extra)| floor + extra)
+    .collect::<Vec<_>>();
+  let unallocated = target - allocations.iter().sum::<usize>();
+  if unallocated > 0 && cap.is_some() {
+    warnings.push(format!(
+      "Category caps leave {unallocated} of the {target} target records unselected"
+    ));
+  }
+  (allocations, warnings)
+}
+
+pub fn validate_distill_config(config: &DistillConfig) -> Result<(), String> {
+  if config.strategy == "prefix_diversity" && config.prefix_tokens == 0 {
+    return Err("Prefix diversity needs at least one prefix token".to_string());
+  }
+  if config.shingle_size == 0 {
+    return Err("Shingle size must be at least 1".to_string());
+  }
+  if let Some(fraction) = config.max_per_category_fraction {
+    if !(fraction > 0.0 && fraction <= 1.0) {
+      return Err("Category cap must be a fraction in (0, 1]".to_string());
+    }
+  }
+  Ok(())
+}
+
+/// Records to select out of `total`.
+fn target_size(total: usize, config: &DistillConfig) -> usize {
+  if let Some(count) = config.target_count {
     count as usize
   } else if let Some(percent) = config.target_percent {
     ((percent / 100.0) * total as f32).round() as usize
   } else {
     ((0.1 * total as f32).round()) as usize
   }
-  .clamp(1, total);
+  .clamp(1, total)
+}
+
+/// Name, size and weight of each category for balanced selection, largest first.
+fn category_buckets<'a>(
+  counts: impl Iterator<Item = (&'a str, usize)>,
+  config: &DistillConfig,
+) -> Vec<(String, usize, f32)> {
+  // Excluded buckets get a zero weight, so they receive nothing.
+  let uncategorized_weight = config.uncategorized_weight.max(0.0);
+  let mut buckets = counts
+    .map(|(name, count)| {
+      let weight = if name == UNCATEGORIZED_LABEL {
+        count as f32 * uncategorized_weight
+      } else {
+        count as f32
+      };
+      (name.to_string(), count, weight)
+    })
+    .collect::<Vec<_>>();
+  buckets.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+  buckets
+}
+
+fn category_name(category: Option<u32>, names: &InternedNames) -> &str {
+  category.map_or(UNCATEGORIZED_LABEL, |id| names.name(id))
+}
+
+/// Selected ids, plus a message for each per-category constraint that could not
+/// be met. Constraints only apply with category balance.
+pub fn select_records(
+  metas: &[RecordMeta],
+  names: &InternedNames,
+  config: &DistillConfig,
+) -> (Vec<usize>, Vec<String>) {
+  let total = metas.len();
+  if total == 0 {
+    return (Vec::new(), Vec::new());
+  }
+  let target = target_size(total, config);
 
-  if config.preserve_category_balance {
-    let mut by_category: HashMap<String, Vec<RecordMeta>> = HashMap::new();
-    for meta in metas {
-      let key = meta
-        .category
-        .clone()
-        .unwrap_or_else(|| "uncategorized".to_string());
-      by_category.entry(key).or_default().push(meta.clone());
+  if !config.preserve_category_balance {
+    let mut warnings = Vec::new();
+    if config.min_per_category.is_some() || config.max_per_category_fraction.is_some() {
+      warnings.push("Per-category limits only apply with category balance".to_string());
     }
+    return (apply_strategy(metas.iter(), target, config), warnings);
+  }
 
-    let mut allocations: Vec<(String, usize, usize)> = by_category
-      .iter()
-      .map(|(name, items)| {
-        let count = items.len();
-        let alloc = ((count as f32 / total as f32) * target as f32).round() as usize;
-        (name.clone(), count, alloc)
-      })
-      .collect();
-
-    let mut allocated = allocations.iter().map(|item| item.2).sum::<usize>();
-    allocations.sort_by(|a, b| b.1.cmp(&a.1));
-    let mut idx = 0;
-    while allocated < target {
-      allocations[idx].2 += 1;
-      allocated += 1;
-      idx = (idx + 1) % allocations.len();
-    }
-
-    let mut selected = Vec::new();
-    for (name, _, alloc) in allocations {
-      if let Some(bucket) = by_category.get(&name) {
-        let bucket_selected = apply_strategy(bucket, alloc.min(bucket.len()), config);
-        selected.extend(bucket_selected);
-      }
+  // Positions in `metas` by category name.
+  let mut by_category: HashMap<&str, Vec<usize>> = HashMap::new();
+  for (index, meta) in metas.iter().enumerate() {
+    by_category
+      .entry(category_name(meta.category, names))
+      .or_default()
+      .push(index);
+  }
+
+  let buckets = category_buckets(
+    by_category.iter().map(|(name, items)| (*name, items.len())),
+    config,
+  );
+  let (allocations, warnings) = allocate_categories(&buckets, target, config);
+  let mut selected = Vec::new();
+  for ((name, _, _), alloc) in buckets.iter().zip(allocations) {
+    if alloc > 0 {
+      let members = by_category[name.as_str()].iter().map(|index| &metas[*index]);
+      selected.extend(apply_strategy(members, alloc, config));
     }
-    selected.sort_unstable();
-    selected.truncate(target);
-    selected
-  } else {
-    apply_strategy(metas, target, config)
   }
+  selected.sort_unstable();
+  (selected, warnings)
 }
 
-pub fn preview_distillation(
-  store: &DatasetStore,
-  base_ids: Option<&[usize]>,
+/// Recomputes the scores with each component rescaled over all metas, and
+/// returns the ranges used. Nothing changes without normalized components.
+fn normalize_scores(
+  metas: &mut [RecordMeta],
+  components: &ComponentTable,
   config: &DistillConfig,
+) -> Vec<ComponentRange> {
+  if !MetaTables::keeps_components(config) {
+    return Vec::new();
+  }
+  let ranges = components.ranges(&config.score_components);
+  for (index, meta) in metas.iter_mut().enumerate() {
+    meta.score = weighted_score(&components.row(index), config, &ranges);
+  }
+  ranges
+}
+
+/// The score a record gets in distillation, component by component. `ranges`
+/// are those of the last preview and only apply with normalized components.
+pub fn explain_record_score(
+  record: &Value,
+  id: usize,
   field_map: &FieldMap,
-  cancel: &AtomicBool,
-  mut on_progress: impl FnMut(usize, usize),
-) -> Result<(Vec<usize>, Vec<usize>, DistillSummary), String> {
-  let base_ids: Vec<usize> = if let Some(list) = base_ids {
-    list.to_vec()
-  } else {
-    (0..store.record_count).collect()
-  };
-  let base_set: HashSet<usize> = base_ids.iter().cloned().collect();
+  config: &DistillConfig,
+  ranges: &[ComponentRange],
+) -> ScoreBreakdown {
+  if config.score_components.is_empty() {
+    return ScoreBreakdown {
+      id,
+      score: mapped_score(record, field_map),
+      normalized: false,
+      components: Vec::new(),
+    };
+  }
+  let values = component_values(record, &config.score_components);
+  let normalized = config.normalize_components
+    && ranges
+      .iter()
+      .map(|range| &range.field)
+      .eq(config.score_components.iter().map(|component| &component.field));
+  let ranges = if normalized { ranges } else { &[] };
+  let components = score_components(&values, config, ranges);
+  ScoreBreakdown {
+    id,
+    score: components.iter().map(|component| component.contribution).sum(),
+    normalized,
+    components,
+  }
+}
 
-  let file = File::open(&store.store_path).map_err(|e| e.to_string())?;
-  let reader = BufReader::new(file);
-  let mut metas = Vec::new();
-  for (idx, line) in reader.lines().enumerate() {
+/// Applies manual changes to a preview in place, each costing one bitmap
+/// insert and remove. Ids are checked against `record_count` first so a bad
+/// batch changes nothing. An id in neither set was outside the distilled
+/// base: including it adds it to the selection, excluding it is ignored.
+/// Returns how many ids were newly included from outside the base.
+pub fn apply_manual_changes(
+  selected_ids: &mut IdSet,
+  removed_ids: &mut IdSet,
+  changes: &[ManualChange],
+  record_count: usize,
+) -> Result<usize, String> {
+  if let Some(change) = changes.iter().find(|change| change.id >= record_count) {
+    return Err(format!("Record {} is out of range", change.id));
+  }
+  let mut added = 0usize;
+  for change in changes {
+    let was_removed = removed_ids.remove(change.id);
+    if change.include {
+      if selected_ids.insert(change.id) && !was_removed {
+        added += 1;
+      }
+    } else if selected_ids.remove(change.id) || was_removed {
+      removed_ids.insert(change.id);
+    }
+  }
+  Ok(added)
+}
+
+/// The most common instruction prefixes with how many of each were selected.
+fn top_prefixes(
+  metas: &[RecordMeta],
+  prefixes: &InternedNames,
+  selected: &IdSet,
+) -> Vec<PrefixCount> {
+  let mut counts: HashMap<u32, (usize, usize)> = HashMap::new();
+  for meta in metas {
+    if let Some(prefix) = meta.prefix {
+      let entry = counts.entry(prefix).or_default();
+      entry.0 += 1;
+      if selected.contains(meta.id) {
+        entry.1 += 1;
+      }
+    }
+  }
+  let mut list = counts
+    .into_iter()
+    .map(|(prefix, (count, selected_count))| PrefixCount {
+      prefix: prefixes.name(prefix).to_string(),
+      count,
+      selected_count,
+    })
+    .collect::<Vec<_>>();
+  list.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.prefix.cmp(&b.prefix)));
+  list.truncate(TOP_PREFIX_LIMIT);
+  list
+}
+
+/// What a meta scan reads: the base records of a store.
+struct MetaScan<'a> {
+  store: &'a DatasetStore,
+  base_set: &'a IdSet,
+  field_map: &'a FieldMap,
+  categories: &'a CategorySource,
+  config: &'a DistillConfig,
+}
+
+/// Builds the meta of every base record in id order and hands it to `sink`.
+/// Returns the number of metas.
+fn scan_metas(
+  scan: &MetaScan,
+  tables: &mut MetaTables,
+  cancel: &AtomicBool,
+  mut sink: impl FnMut(RecordMeta) -> Result<(), String>,
+  on_progress: &mut impl FnMut(&str, usize, usize),
+) -> Result<usize, String> {
+  let file = File::open(&scan.store.store_path).map_err(|e| e.to_string())?;
+  let mut reader = BufReader::new(file);
+  let mut count = 0usize;
+  let mut idx = 0usize;
+  while let Some(line) = read_line_bounded(&mut reader, scan.store.max_record_bytes)? {
     if cancel.load(Ordering::SeqCst) {
       return Err("Distillation canceled".to_string());
     }
-    if !base_set.contains(&idx) {
+    let id = idx;
+    idx += 1;
+    if !scan.base_set.contains(id) {
       continue;
     }
-    let line = line.map_err(|e| e.to_string())?;
-    let record: Value = serde_json::from_str(&line).map_err(|e| e.to_string())?;
-    metas.push(build_record_meta(&record, idx, field_map, &config.strategy));
-    if metas.len() % 1000 == 0 {
-      on_progress(metas.len(), base_set.len());
+    let meta = match line {
+      BoundedLine::Line(bytes) => {
+        let record: Value = serde_json::from_slice(&bytes).map_err(|e| e.to_string())?;
+        build_record_meta(&record, id, scan.field_map, scan.categories, tables, scan.config)
+      }
+      BoundedLine::Oversized(_) => {
+        if MetaTables::keeps_components(scan.config) {
+          tables.components.push_missing();
+        }
+        RecordMeta {
+          id,
+          category: None,
+          prefix: None,
+          score: 0.0,
+          signature: 0,
+        }
+      }
+    };
+    sink(meta)?;
+    count += 1;
+    if count.is_multiple_of(1000) {
+      on_progress("meta", count, scan.base_set.len());
     }
   }
+  Ok(count)
+}
 
-  let mut selected = select_records(&metas, config);
-  selected.sort_unstable();
-  let selected_set: HashSet<usize> = selected.iter().cloned().collect();
-  let mut removed = base_ids
+/// Whether selection keeps metas on disk instead of in memory: balanced
+/// importance or random selection over more than `external_meta_threshold`
+/// records, without normalized score components.
+fn uses_external_selection(config: &DistillConfig, meta_count: usize) -> bool {
+  config.preserve_category_balance
+    && matches!(config.strategy.as_str(), "importance" | "random")
+    && !MetaTables::keeps_components(config)
+    && meta_count > config.external_meta_threshold
+}
+
+struct ExternalSelection {
+  ids: Vec<usize>,
+  warnings: Vec<String>,
+  /// The category of every selected record, when the base had several.
+  single_category: Option<String>,
+}
+
+/// Balanced selection with the metas spilled to disk by category. Each
+/// category is selected from on its own, exactly as in memory, so only one
+/// spill file of metas is loaded at a time.
+fn select_external(
+  scan: &MetaScan,
+  tables: &mut MetaTables,
+  cancel: &AtomicBool,
+  timer: &mut StageTimer,
+  on_progress: &mut impl FnMut(&str, usize, usize),
+) -> Result<ExternalSelection, String> {
+  let meta_start = Instant::now();
+  let mut spill = MetaSpill::new(&scan.store.store_path)?;
+  let mut counts: HashMap<Option<u32>, usize> = HashMap::new();
+  let total = scan_metas(
+    scan,
+    tables,
+    cancel,
+    |meta| {
+      *counts.entry(meta.category).or_default() += 1;
+      spill.push(&meta)
+    },
+    on_progress,
+  )?;
+  spill.finish()?;
+  timer.add("meta", meta_start.elapsed());
+  timer.count("meta", total);
+  on_progress("select", 0, total);
+  if total == 0 {
+    return Ok(ExternalSelection {
+      ids: Vec::new(),
+      warnings: Vec::new(),
+      single_category: None,
+    });
+  }
+
+  let select_start = Instant::now();
+  let names = &tables.categories;
+  let buckets = category_buckets(
+    counts
+      .iter()
+      .map(|(category, count)| (category_name(*category, names), *count)),
+    scan.config,
+  );
+  let target = target_size(total, scan.config);
+  let (allocations, warnings) = allocate_categories(&buckets, target, scan.config);
+  let allocations = buckets
     .iter()
-    .filter(|id| !selected_set.contains(id))
-    .cloned()
-    .collect::<Vec<_>>();
-  removed.sort_unstable();
+    .zip(allocations)
+    .map(|((name, _, _), alloc)| (name.as_str(), alloc))
+    .collect::<HashMap<_, _>>();
+  let mut selected = Vec::new();
+  let mut selected_categories = Uniformity::default();
+  spill.for_each_partition(|partition| {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Distillation canceled".to_string());
+    }
+    for (category, metas) in partition {
+      let alloc = allocations[category_name(category, names)];
+      if alloc > 0 {
+        let picked = apply_strategy(metas.iter(), alloc, scan.config);
+        if !picked.is_empty() {
+          selected_categories.add(category);
+        }
+        selected.extend(picked);
+      }
+    }
+    Ok(())
+  })?;
+  selected.sort_unstable();
+  timer.add("select", select_start.elapsed());
+  let single_category = (counts.len() > 1)
+    .then(|| selected_categories.single().map(|category| category_name(*category, names)))
+    .flatten()
+    .map(str::to_string);
+  Ok(ExternalSelection {
+    ids: selected,
+    warnings,
+    single_category,
+  })
+}
+
+pub fn preview_distillation(
+  store: &DatasetStore,
+  base_ids: Option<&IdSet>,
+  config: &DistillConfig,
+  field_map: &FieldMap,
+  category_rules: &CategoryRules,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(&str, usize, usize),
+) -> Result<(IdSet, IdSet, DistillSummary), String> {
+  validate_distill_config(config)?;
+  let categories = CategorySource::new(field_map.category.as_deref(), category_rules)?;
+  let base_set = match base_ids {
+    Some(set) => set.clone(),
+    None => IdSet::full(store.record_count),
+  };
+  let scan = MetaScan {
+    store,
+    base_set: &base_set,
+    field_map,
+    categories: &categories,
+    config,
+  };
+
+  let mut timer = StageTimer::new();
+  let mut tables = MetaTables::new(config);
+  let external_selection = uses_external_selection(config, base_set.len());
+  let (selected, constraint_warnings, top_prefixes, score_ranges, single_category) =
+    if external_selection {
+      let selection = select_external(&scan, &mut tables, cancel, &mut timer, &mut on_progress)?;
+      let selected: IdSet = selection.ids.into_iter().collect();
+      (
+        selected,
+        selection.warnings,
+        Vec::new(),
+        Vec::new(),
+        selection.single_category,
+      )
+    } else {
+      let meta_start = Instant::now();
+      let mut metas = Vec::new();
+      scan_metas(
+        &scan,
+        &mut tables,
+        cancel,
+        |meta| {
+          metas.push(meta);
+          Ok(())
+        },
+        &mut on_progress,
+      )?;
+      let score_ranges = normalize_scores(&mut metas, &tables.components, config);
+      timer.add("meta", meta_start.elapsed());
+      timer.count("meta", metas.len());
+      on_progress("select", 0, metas.len());
+
+      let (selected, warnings) =
+        timer.time("select", || select_records(&metas, &tables.categories, config));
+      let selected: IdSet = selected.into_iter().collect();
+      let top_prefixes = top_prefixes(&metas, &tables.prefixes, &selected);
+      let mut base_categories = Uniformity::default();
+      let mut selected_categories = Uniformity::default();
+      for meta in &metas {
+        base_categories.add(meta.category);
+        if selected.contains(meta.id) {
+          selected_categories.add(meta.category);
+        }
+      }
+      let single_category = base_categories
+        .is_mixed()
+        .then(|| {
+          let names = &tables.categories;
+          selected_categories.single().map(|category| category_name(*category, names))
+        })
+        .flatten()
+        .map(str::to_string);
+      (selected, warnings, top_prefixes, score_ranges, single_category)
+    };
+  let removed = base_set.difference(&selected);
 
-  let summary = DistillSummary {
-    total_count: base_ids.len(),
+  let mut summary = DistillSummary {
+    total_count: base_set.len(),
     selected_count: selected.len(),
     removed_count: removed.len(),
+    timings: timer.finish(),
+    sample_view: None,
+    constraint_warnings,
+    top_prefixes,
+    score_ranges,
+    external_selection,
+    warnings: Vec::new(),
   };
+  summary.warnings = distill_warnings(&summary, config, single_category.as_deref());
   Ok((selected, removed, summary))
 }
+
+/// Metas of the base records with the scores the strategy orders them by,
+/// normalized as in `preview_distillation`, in id order. `None` when the
+/// selection follows no order (`random`) or runs outside memory for its size.
+pub fn distill_ranking(
+  store: &DatasetStore,
+  base_set: &IdSet,
+  config: &DistillConfig,
+  field_map: &FieldMap,
+  category_rules: &CategoryRules,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(&str, usize, usize),
+) -> Result<Option<Vec<RecordMeta>>, String> {
+  if config.strategy == "random" || uses_external_selection(config, base_set.len()) {
+    return Ok(None);
+  }
+  let categories = CategorySource::new(field_map.category.as_deref(), category_rules)?;
+  let scan = MetaScan {
+    store,
+    base_set,
+    field_map,
+    categories: &categories,
+    config,
+  };
+  let mut tables = MetaTables::new(config);
+  let mut metas = Vec::new();
+  scan_metas(
+    &scan,
+    &mut tables,
+    cancel,
+    |meta| {
+      metas.push(meta);
+      Ok(())
+    },
+    &mut on_progress,
+  )?;
+  normalize_scores(&mut metas, &tables.components, config);
+  Ok(Some(metas))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use serde_json::json;
+
+  use super::*;
+  use crate::models::ScoreComponent;
+  use crate::test_support::{
+    assert_phases_in_order, assert_stages_timed, jsonl_store, text_field_map, TempDir,
+  };
+
+  fn distilled_ids(store: &DatasetStore, config: &DistillConfig) -> Vec<usize> {
+    let field_map = FieldMap {
+      category: Some("topic".to_string()),
+      score: Some("score".to_string()),
+      ..text_field_map()
+    };
+    let (selected, _, _) = preview_distillation(
+      store,
+      None,
+      config,
+      &field_map,
+      &CategoryRules::default(),
+      &AtomicBool::new(false),
+      |_, _, _| {},
+    )
+    .unwrap();
+    selected.iter().collect()
+  }
+
+  #[test]
+  fn external_selection_matches_the_in_memory_selection() {
+    let dir = TempDir::new();
+    let topics = ["math", "code", "chat", "law"];
+    let records = (0..300)
+      .map(|id| {
+        json!({
+          "instruction": format!("question {id}"),
+          "output": "answer",
+          "topic": topics[id % 7 % 4],
+          "score": (id * 37 % 101) as f64,
+        })
+      })
+      .collect::<Vec<_>>();
+    let store = jsonl_store(&dir, &records);
+    for strategy in ["importance", "random"] {
+      let in_memory = DistillConfig {
+        target_count: Some(60),
+        target_percent: None,
+        strategy: strategy.to_string(),
+        random_seed: Some(3),
+        preserve_category_balance: true,
+        min_per_category: Some(5),
+        external_meta_threshold: usize::MAX,
+        ..DistillConfig::default()
+      };
+      let external = DistillConfig {
+        external_meta_threshold: 0,
+        ..in_memory.clone()
+      };
+      let expected = distilled_ids(&store, &in_memory);
+      assert_eq!(expected.len(), 60);
+      assert_eq!(distilled_ids(&store, &external), expected, "{strategy}");
+    }
+    let leftovers = fs::read_dir(dir.join("store"))
+      .unwrap()
+      .flatten()
+      .filter(|entry| entry.file_name().to_string_lossy().starts_with("distill-"))
+      .count();
+    assert_eq!(leftovers, 0);
+  }
+
+  fn change(id: usize, include: bool) -> ManualChange {
+    ManualChange { id, include }
+  }
+
+  #[test]
+  fn a_manual_change_moves_only_its_own_id() {
+    let mut selected = IdSet::from_iter(0..5000);
+    let mut removed = IdSet::from_iter(5000..10_000);
+    let added =
+      apply_manual_changes(&mut selected, &mut removed, &[change(42, false)], 10_000).unwrap();
+    assert_eq!(added, 0);
+    assert_eq!(selected.len(), 4999);
+    assert_eq!(removed.len(), 5001);
+    assert!(!selected.contains(42) && removed.contains(42));
+
+    let added =
+      apply_manual_changes(&mut selected, &mut removed, &[change(42, true)], 10_000).unwrap();
+    assert_eq!(added, 0);
+    assert_eq!(selected, IdSet::from_iter(0..5000));
+    assert_eq!(removed, IdSet::from_iter(5000..10_000));
+  }
+
+  #[test]
+  fn a_manual_change_to_a_huge_selection_does_not_rebuild_it() {
+    let count = 5_000_000;
+    let mut selected = IdSet::from_iter(0..count);
+    let mut removed = IdSet::new();
+    let before = selected.memory_size();
+    assert!(before * 50 < count * std::mem::size_of::<usize>());
+
+    apply_manual_changes(&mut selected, &mut removed, &[change(42, false)], count).unwrap();
+    assert_eq!(selected.memory_size(), before);
+    assert_eq!(removed.memory_size(), IdSet::from_iter([42]).memory_size());
+    apply_manual_changes(&mut selected, &mut removed, &[change(42, true)], count).unwrap();
+    assert_eq!(selected.memory_size(), before);
+    assert!(removed.is_empty());
+  }
+
+  #[test]
+  fn a_manual_change_to_an_id_outside_the_base() {
+    let mut selected = IdSet::from_iter([1, 2]);
+    let mut removed = IdSet::from_iter([3]);
+    let added =
+      apply_manual_changes(&mut selected, &mut removed, &[change(7, false)], 10).unwrap();
+    assert_eq!(added, 0);
+    assert_eq!(removed, IdSet::from_iter([3]));
+
+    let added = apply_manual_changes(&mut selected, &mut removed, &[change(7, true)], 10).unwrap();
+    assert_eq!(added, 1);
+    assert_eq!(selected, IdSet::from_iter([1, 2, 7]));
+
+    let batch = [change(2, false), change(10, true)];
+    assert!(apply_manual_changes(&mut selected, &mut removed, &batch, 10).is_err());
+    assert_eq!(selected, IdSet::from_iter([1, 2, 7]));
+  }
+
+  fn buckets(sizes: &[(&str, usize)]) -> Vec<(String, usize, f32)> {
+    sizes
+      .iter()
+      .map(|(name, count)| (name.to_string(), *count, *count as f32))
+      .collect()
+  }
+
+  fn limits(min: Option<usize>, fraction: Option<f32>) -> DistillConfig {
+    DistillConfig {
+      preserve_category_balance: true,
+      min_per_category: min,
+      max_per_category_fraction: fraction,
+      ..DistillConfig::default()
+    }
+  }
+
+  #[test]
+  fn a_minimum_above_a_category_size_takes_the_whole_category() {
+    let (allocations, warnings) =
+      allocate_categories(&buckets(&[("a", 20), ("b", 2)]), 10, &limits(Some(4), None));
+    assert_eq!(allocations, vec![8, 2]);
+    assert_eq!(warnings, vec!["b: only 2 records, below the minimum of 4"]);
+  }
+
+  #[test]
+  fn minimums_above_the_target_are_scaled_down_to_it() {
+    let sizes = [("a", 10), ("b", 10), ("c", 10), ("d", 10)];
+    let (allocations, warnings) = allocate_categories(&buckets(&sizes), 6, &limits(Some(3), None));
+    assert_eq!(allocations.iter().sum::<usize>(), 6);
+    assert!(allocations.iter().all(|count| (1..=2).contains(count)));
+    assert_eq!(warnings, vec!["Minimums need 12 records but the target is 6"]);
+  }
+
+  #[test]
+  fn a_minimum_above_the_cap_is_held_to_the_cap() {
+    let (allocations, warnings) = allocate_categories(
+      &buckets(&[("a", 10), ("b", 10)]),
+      8,
+      &limits(Some(3), Some(0.25)),
+    );
+    assert_eq!(allocations, vec![2, 2]);
+    assert_eq!(
+      warnings,
+      vec![
+        "a: minimum of 3 exceeds the cap of 2",
+        "b: minimum of 3 exceeds the cap of 2",
+        "Category caps leave 4 of the 8 target records unselected",
+      ]
+    );
+  }
+
+  #[test]
+  fn caps_that_cannot_reach_the_target_select_fewer_records() {
+    let (allocations, warnings) =
+      allocate_categories(&buckets(&[("a", 30), ("b", 1)]), 10, &limits(None, Some(0.5)));
+    assert_eq!(allocations, vec![5, 1]);
+    assert_eq!(warnings, vec!["Category caps leave 4 of the 10 target records unselected"]);
+  }
+
+  #[test]
+  fn infeasible_limits_are_reported_in_the_summary() {
+    let dir = TempDir::new();
+    let records = (0..12)
+      .map(|id| {
+        let topic = if id % 3 == 0 { "math" } else { "chat" };
+        json!({ "instruction": format!("q{id}"), "output": "a", "topic": topic })
+      })
+      .collect::<Vec<_>>();
+    let store = jsonl_store(&dir, &records);
+    let config = DistillConfig {
+      target_count: Some(6),
+      target_percent: None,
+      ..limits(Some(5), Some(0.5))
+    };
+    let field_map = FieldMap {
+      category: Some("topic".to_string()),
+      ..text_field_map()
+    };
+    let (selected, _, summary) = preview_distillation(
+      &store,
+      None,
+      &config,
+      &field_map,
+      &CategoryRules::default(),
+      &AtomicBool::new(false),
+      |_, _, _| {},
+    )
+    .unwrap();
+    assert_eq!(selected.len(), 6);
+    assert_eq!(
+      summary.constraint_warnings,
+      vec![
+        "chat: minimum of 5 exceeds the cap of 3",
+        "math: only 4 records, below the minimum of 5",
+      ]
+    );
+  }
+
+  fn weighted(components: &[(&str, f64)], normalize_components: bool) -> DistillConfig {
+    DistillConfig {
+      score_components: components
+        .iter()
+        .map(|(field, weight)| ScoreComponent {
+          field: field.to_string(),
+          weight: *weight,
+        })
+        .collect(),
+      missing_component_value: 1.0,
+      normalize_components,
+      ..DistillConfig::default()
+    }
+  }
+
+  #[test]
+  fn missing_components_score_the_configured_value() {
+    let config = weighted(&[("helpfulness", 0.5), ("correctness", 2.0), ("style", 1.0)], false);
+    let record = json!({ "helpfulness": 4, "style": "n/a" });
+    let breakdown = explain_record_score(&record, 7, &text_field_map(), &config, &[]);
+    assert_eq!((breakdown.id, breakdown.score, breakdown.normalized), (7, 5.0, false));
+    let parts = breakdown
+      .components
+      .iter()
+      .map(|component| (component.raw, component.value, component.contribution))
+      .collect::<Vec<_>>();
+    assert_eq!(parts, vec![(Some(4.0), 4.0, 2.0), (None, 1.0, 2.0), (None, 1.0, 1.0)]);
+  }
+
+  #[test]
+  fn an_all_equal_component_adds_nothing_once_normalized() {
+    let dir = TempDir::new();
+    let records = (1..=3)
+      .map(|helpfulness| {
+        let instruction = format!("q{helpfulness}");
+        json!({ "instruction": instruction, "helpfulness": helpfulness, "correctness": 5 })
+      })
+      .collect::<Vec<_>>();
+    let store = jsonl_store(&dir, &records);
+    let config = DistillConfig {
+      strategy: "importance".to_string(),
+      target_count: Some(1),
+      target_percent: None,
+      ..weighted(&[("helpfulness", 1.0), ("correctness", 3.0)], true)
+    };
+    let (selected, _, summary) = preview_distillation(
+      &store,
+      None,
+      &config,
+      &text_field_map(),
+      &CategoryRules::default(),
+      &AtomicBool::new(false),
+      |_, _, _| {},
+    )
+    .unwrap();
+    assert_eq!(selected.iter().collect::<Vec<_>>(), vec![2]);
+    let range = |field: &str, min, max| ComponentRange {
+      field: field.to_string(),
+      min: Some(min),
+      max: Some(max),
+    };
+    let found = summary
+      .score_ranges
+      .iter()
+      .map(|range| (range.field.as_str(), range.min, range.max))
+      .collect::<Vec<_>>();
+    let expected = [("helpfulness", Some(1.0), Some(3.0)), ("correctness", Some(5.0), Some(5.0))];
+    assert_eq!(found, expected);
+
+    let field_map = text_field_map();
+    let ranges = &summary.score_ranges;
+    let breakdown = explain_record_score(&records[1], 1, &field_map, &config, ranges);
+    assert!(breakdown.normalized);
+    assert_eq!(breakdown.score, 0.5);
+    assert_eq!(breakdown.components[1].value, 0.0);
+
+    let stale = [range("style", 0.0, 1.0), range("correctness", 5.0, 5.0)];
+    let breakdown = explain_record_score(&records[1], 1, &field_map, &config, &stale);
+    assert!(!breakdown.normalized);
+    assert_eq!(breakdown.score, 17.0);
+  }
+
+  #[test]
+  fn interned_names_are_stored_once() {
+    let mut names = InternedNames::default();
+    let ids = ["math", "code", "math", "Math"].map(|name| names.intern(name.to_string()));
+    assert_eq!(ids, [0, 1, 0, 2]);
+    assert_eq!([names.name(0), names.name(1), names.name(2)], ["math", "code", "Math"]);
+    assert_eq!(names.names.len(), 3);
+  }
+
+  #[test]
+  fn metas_carry_category_indices_and_stay_small() {
+    let field_map = FieldMap {
+      category: Some("topic".to_string()),
+      ..text_field_map()
+    };
+    let categories = CategorySource::new(Some("topic"), &CategoryRules::default()).unwrap();
+    let config = DistillConfig::default();
+    let mut tables = MetaTables::new(&config);
+    let topics = [json!("math"), json!("code"), json!("math"), json!(UNCATEGORIZED_LABEL)];
+    let mut metas = topics
+      .iter()
+      .enumerate()
+      .map(|(id, topic)| {
+        let record = json!({ "instruction": format!("q{id}"), "topic": topic });
+        build_record_meta(&record, id, &field_map, &categories, &mut tables, &config)
+      })
+      .collect::<Vec<_>>();
+    let record = json!({ "instruction": "q4" });
+    metas.push(build_record_meta(&record, 4, &field_map, &categories, &mut tables, &config));
+    let found = metas.iter().map(|meta| meta.category).collect::<Vec<_>>();
+    assert_eq!(found, vec![Some(0), Some(1), Some(0), None, None]);
+    assert_eq!(category_name(Some(1), &tables.categories), "code");
+    assert_eq!(category_name(None, &tables.categories), UNCATEGORIZED_LABEL);
+    // An id, two interned indices, a score and a signature: no owned strings.
+    assert!(std::mem::size_of::<RecordMeta>() <= 40);
+  }
+
+  #[test]
+  fn balanced_selection_picks_within_each_category_by_index() {
+    let mut names = InternedNames::default();
+    let (math, code) = (names.intern("math".to_string()), names.intern("code".to_string()));
+    let metas = [(math, 1.0), (code, 9.0), (math, 5.0), (code, 2.0), (math, 3.0), (code, 7.0)]
+      .into_iter()
+      .enumerate()
+      .map(|(id, (category, score))| RecordMeta {
+        id,
+        category: Some(category),
+        prefix: None,
+        score,
+        signature: 0,
+      })
+      .collect::<Vec<_>>();
+    let config = DistillConfig {
+      strategy: "importance".to_string(),
+      target_count: Some(4),
+      target_percent: None,
+      preserve_category_balance: true,
+      ..DistillConfig::default()
+    };
+    let (selected, warnings) = select_records(&metas, &names, &config);
+    assert_eq!(selected, vec![1, 2, 4, 5]);
+    assert!(warnings.is_empty());
+  }
+
+  #[test]
+  fn distillation_reports_meta_then_select() {
+    let dir = TempDir::new();
+    let records = (0..2500)
+      .map(|id| json!({ "instruction": format!("q{id}"), "score": id % 7 }))
+      .collect::<Vec<_>>();
+    let store = jsonl_store(&dir, &records);
+    let mut events = Vec::new();
+    preview_distillation(
+      &store,
+      None,
+      &DistillConfig::default(),
+      &text_field_map(),
+      &CategoryRules::default(),
+      &AtomicBool::new(false),
+      |phase, current, total| events.push((phase.to_string(), current, total)),
+    )
+    .unwrap();
+    assert_phases_in_order(&events, &["meta", "select"]);
+    assert_eq!(events[0], ("meta".to_string(), 1000, 2500));
+  }
+
+  #[test]
+  fn distill_summaries_time_meta_and_select() {
+    let dir = TempDir::new();
+    let records = (0..2500)
+      .map(|id| json!({ "instruction": format!("q{id}"), "score": id % 7 }))
+      .collect::<Vec<_>>();
+    let store = jsonl_store(&dir, &records);
+    let config = DistillConfig {
+      target_count: Some(100),
+      target_percent: None,
+      ..DistillConfig::default()
+    };
+    let (selected, _, summary) = preview_distillation(
+      &store,
+      None,
+      &config,
+      &text_field_map(),
+      &CategoryRules::default(),
+      &AtomicBool::new(false),
+      |_, _, _| {},
+    )
+    .unwrap();
+    assert_eq!(selected.len(), 100);
+    assert_stages_timed(&summary.timings, &["meta", "select"], &["meta"]);
+  }
+}
@@ -0,0 +1,286 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::categories::CategorySource;
+use crate::distill::preview_distillation;
+use crate::filters::apply_filters_inner;
+use crate::io::store_lines;
+use crate::models::{
+  CategoryEstimate,
+  CategoryRules,
+  DistillConfig,
+  FieldMap,
+  PipelineEstimate,
+  PipelineSpec,
+  StageEstimate,
+  UNCATEGORIZED_LABEL,
+};
+use crate::records::{count_tokens, extract_text_value};
+use crate::sample::sample_count_ids;
+use crate::state::{DatasetStore, IdSet};
+
+/// Records a pipeline is estimated from by default.
+pub const DEFAULT_ESTIMATE_SAMPLE: usize = 50_000;
+/// Fixed so repeated estimates of the same pipeline agree.
+const ESTIMATE_SEED: u64 = 0;
+/// Two-sided 95% normal quantile for the retained-count bands.
+const CONFIDENCE_Z: f64 = 1.96;
+/// Categories with fewer sample records than this get a caveat.
+const RARE_CATEGORY_SAMPLE: usize = 30;
+
+fn scaled(count: f64) -> usize {
+  count.max(0.0).round() as usize
+}
+
+/// Estimate and 95% band of `population * rate`, where `rate` was observed on
+/// `sample` of `population` records without replacement.
+fn rate_band(rate: f64, sample: usize, population: f64) -> (usize, usize, usize) {
+  if sample == 0 {
+    return (0, 0, 0);
+  }
+  let correction = if population > 1.0 {
+    ((population - sample as f64) / (population - 1.0)).max(0.0)
+  } else {
+    0.0
+  };
+  let error = CONFIDENCE_Z * (rate * (1.0 - rate) / sample as f64 * correction).sqrt();
+  (
+    scaled(rate * population),
+    scaled((rate - error).max(0.0) * population),
+    scaled((rate + error).min(1.0) * population),
+  )
+}
+
+/// The distillation config with absolute counts scaled to a sample fraction.
+fn scale_distill_config(config: &DistillConfig, fraction: f64) -> DistillConfig {
+  let scale = |count: f64| (count * fraction).round().max(1.0);
+  let mut scaled_config = config.clone();
+  scaled_config.target_count = config.target_count.map(|count| scale(count as f64) as u32);
+  scaled_config.min_per_category = config
+    .min_per_category
+    .map(|count| scale(count as f64) as usize);
+  scaled_config
+}
+
+/// Tokens and category counts of the records in `ids`.
+fn profile_output(
+  store: &DatasetStore,
+  ids: &IdSet,
+  field_map: &FieldMap,
+  categories: &CategorySource,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<(usize, HashMap<String, usize>), String> {
+  let mut tokens = 0usize;
+  let mut counts: HashMap<String, usize> = HashMap::new();
+  let mut scanned = 0usize;
+  for line in store_lines(store)? {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Estimate canceled".to_string());
+    }
+    let line = line?;
+    let idx = line.id;
+    if !ids.contains(idx) {
+      continue;
+    }
+    let Some(record) = line.record()? else {
+      continue;
+    };
+    scanned += 1;
+    if scanned.is_multiple_of(1000) {
+      on_progress(scanned, ids.len());
+    }
+    for field in [&field_map.instruction, &field_map.output] {
+      tokens += count_tokens(&extract_text_value(&record, field).unwrap_or_default());
+    }
+    if !categories.is_none() {
+      let name = categories
+        .category(&record, field_map)
+        .unwrap_or_else(|| UNCATEGORIZED_LABEL.to_string());
+      *counts.entry(name).or_insert(0) += 1;
+    }
+  }
+  Ok((tokens, counts))
+}
+
+/// Runs `spec` on a seeded sample of `sample_size` records and extrapolates
+/// each stage to the full dataset. Predicate rejections and the distillation
+/// rate scale linearly, with a 95% band from the sample size.
+///
+/// Duplicates do not: a pair is only seen when both copies are sampled, so
+/// the observed rate `d` is a lower bound. Assuming duplicates come in pairs,
+/// the full-dataset rate is `d / p` for sample fraction `p`, capped at one
+/// half, the most pairs allow, and never below `d`. The dedupe estimate uses
+/// that corrected rate, and its `high` bound the observed one.
+pub fn estimate_pipeline(
+  store: &DatasetStore,
+  spec: &PipelineSpec,
+  field_map: &FieldMap,
+  category_rules: &CategoryRules,
+  sample_size: usize,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(&str, usize, usize),
+) -> Result<PipelineEstimate, String> {
+  let total_count = store.record_count;
+  let sample = sample_count_ids(total_count, sample_size, ESTIMATE_SEED);
+  let sample_count = sample.len();
+  if sample_count == 0 {
+    return Err("No records to sample".to_string());
+  }
+  let fraction = sample_count as f64 / total_count as f64;
+  let scale = 1.0 / fraction;
+
+  let (filtered_ids, filter_summary) = apply_filters_inner(
+    store,
+    Some(&sample),
+    &spec.filters,
+    field_map,
+    category_rules,
+    cancel,
+    |current, total| on_progress("filter", current, total),
+  )?;
+  let predicate_rejected = filter_summary
+    .rejected
+    .values()
+    .sum::<usize>()
+    .saturating_sub(filter_summary.duplicates_removed);
+  let passed = sample_count - predicate_rejected;
+  let (passed_estimate, passed_low, passed_high) =
+    rate_band(passed as f64 / sample_count as f64, sample_count, total_count as f64);
+  let mut stages = vec![StageEstimate {
+    stage: "filter".to_string(),
+    sample_input: sample_count,
+    sample_output: passed,
+    estimated_input: total_count,
+    estimated_output: passed_estimate,
+    low: passed_low,
+    high: passed_high,
+  }];
+
+  let observed_duplicate_rate = if passed == 0 {
+    0.0
+  } else {
+    filter_summary.duplicates_removed as f64 / passed as f64
+  };
+  let corrected_duplicate_rate = (observed_duplicate_rate / fraction)
+    .min(0.5)
+    .max(observed_duplicate_rate);
+  let retained = |passed: usize, rate: f64| scaled(passed as f64 * (1.0 - rate));
+  let mut output = StageEstimate {
+    stage: "dedupe".to_string(),
+    sample_input: passed,
+    sample_output: filter_summary.filtered_count,
+    estimated_input: passed_estimate,
+    estimated_output: retained(passed_estimate, corrected_duplicate_rate),
+    low: retained(passed_low, corrected_duplicate_rate),
+    high: retained(passed_high, observed_duplicate_rate),
+  };
+
+  let mut caveats = vec![format!(
+    "Estimated from a seeded sample of {sample_count} of {total_count} records ({:.2}%).",
+    fraction * 100.0
+  )];
+  if filter_summary.duplicates_removed > 0 && fraction < 1.0 {
+    caveats.push(format!(
+      "Duplicates are undercounted in a sample: {:.2}% were seen, corrected to {:.2}% \
+       assuming duplicates come in pairs. Larger duplicate groups make the correction high.",
+      observed_duplicate_rate * 100.0,
+      corrected_duplicate_rate * 100.0
+    ));
+  }
+
+  let mut final_ids = filtered_ids;
+  if let Some(config) = &spec.distill {
+    let sample_config = scale_distill_config(config, fraction);
+    let (selected_ids, _, summary) = preview_distillation(
+      store,
+      Some(&final_ids),
+      &sample_config,
+      field_map,
+      category_rules,
+      cancel,
+      |phase, current, total| on_progress(phase, current, total),
+    )?;
+    let rate = if final_ids.is_empty() {
+      0.0
+    } else {
+      summary.selected_count as f64 / final_ids.len() as f64
+    };
+    let pick = |available: usize| match config.target_count {
+      Some(target) => available.min(target as usize),
+      None => scaled(available as f64 * rate),
+    };
+    let distill = StageEstimate {
+      stage: "distill".to_string(),
+      sample_input: final_ids.len(),
+      sample_output: summary.selected_count,
+      estimated_input: output.estimated_output,
+      estimated_output: pick(output.estimated_output),
+      low: pick(output.low),
+      high: pick(output.high),
+    };
+    if config.target_count.is_some() || config.min_per_category.is_some() {
+      caveats.push(
+        "The target count and per-category minimum were scaled to the sample; the \
+         selection mix may differ at full size."
+          .to_string(),
+      );
+    }
+    stages.push(output);
+    output = distill;
+    final_ids = selected_ids;
+  }
+
+  let categories_source = CategorySource::new(field_map.category.as_deref(), category_rules)?;
+  let (tokens, category_counts) = profile_output(
+    store,
+    &final_ids,
+    field_map,
+    &categories_source,
+    cancel,
+    |current, total| on_progress("profile", current, total),
+  )?;
+  let output_scale = if final_ids.is_empty() {
+    0.0
+  } else {
+    output.estimated_output as f64 / final_ids.len() as f64
+  };
+  let mut categories = category_counts
+    .into_iter()
+    .map(|(name, count)| CategoryEstimate {
+      name,
+      sample_count: count,
+      share: count as f64 / final_ids.len() as f64,
+      estimated_count: scaled(count as f64 * output_scale),
+    })
+    .collect::<Vec<_>>();
+  categories.sort_by(|a, b| b.sample_count.cmp(&a.sample_count).then_with(|| a.name.cmp(&b.name)));
+  let rare = categories
+    .iter()
+    .filter(|category| category.sample_count < RARE_CATEGORY_SAMPLE)
+    .count();
+  if rare > 0 {
+    caveats.push(format!(
+      "{rare} categories have fewer than {RARE_CATEGORY_SAMPLE} sampled records; their \
+       estimates are unreliable."
+    ));
+  }
+  stages.push(output);
+
+  Ok(PipelineEstimate {
+    total_count,
+    sample_count,
+    stages,
+    estimated_rejected: filter_summary
+      .rejected
+      .into_iter()
+      .filter(|(reason, _)| reason != "duplicate")
+      .map(|(reason, count)| (reason, scaled(count as f64 * scale)))
+      .collect::<BTreeMap<_, _>>(),
+    observed_duplicate_rate,
+    corrected_duplicate_rate,
+    categories,
+    estimated_tokens: scaled(tokens as f64 * output_scale),
+    caveats,
+  })
+}
@@ -0,0 +1,372 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde_json::Value;
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::io::store_lines;
+use crate::models::{FieldMatrix, FieldStats, FillRateChange, SchemaDiff};
+use crate::records::{byte_prefix, text_length, value_to_string};
+use crate::render::markdown_cell;
+use crate::sample::sample_subset;
+use crate::state::{DatasetStore, IdSet};
+
+/// Example values kept per field.
+const FIELD_EXAMPLES: usize = 3;
+/// Bytes kept per example value, cut on a character boundary.
+const EXAMPLE_MAX_BYTES: usize = 80;
+/// Smallest value hashes kept per field for the distinct-count estimate.
+const DISTINCT_SKETCH_SIZE: usize = 1024;
+/// Fixed so the same view and sample size always profile the same records.
+const FIELD_SAMPLE_SEED: u64 = 0;
+/// Records of each dataset profiled for a schema diff by default.
+pub const DEFAULT_SCHEMA_SAMPLE: usize = 10_000;
+/// Change in fill rate, as a share of records, that a schema diff reports.
+pub const SCHEMA_FILL_RATE_THRESHOLD: f64 = 0.2;
+
+/// K-minimum-values sketch: the smallest hashes seen estimate how many
+/// distinct values there are, and count them exactly below the sketch size.
+#[derive(Default)]
+struct DistinctSketch {
+  smallest: BTreeSet<u64>,
+}
+
+impl DistinctSketch {
+  fn insert(&mut self, hash: u64) {
+    if self.smallest.len() < DISTINCT_SKETCH_SIZE {
+      self.smallest.insert(hash);
+    } else if self.smallest.last().is_some_and(|largest| hash < *largest)
+      && self.smallest.insert(hash)
+    {
+      self.smallest.pop_last();
+    }
+  }
+
+  /// Distinct count and whether it is exact.
+  fn estimate(&self) -> (usize, bool) {
+    match self.smallest.last() {
+      Some(largest) if self.smallest.len() == DISTINCT_SKETCH_SIZE => {
+        let fraction = (*largest as f64 + 1.0) / (u64::MAX as f64 + 1.0);
+        (((DISTINCT_SKETCH_SIZE - 1) as f64 / fraction).round() as usize, false)
+      }
+      _ => (self.smallest.len(), true),
+    }
+  }
+}
+
+#[derive(Default)]
+struct FieldProfile {
+  present_count: usize,
+  non_empty_count: usize,
+  types: BTreeMap<String, usize>,
+  distinct: DistinctSketch,
+  examples: Vec<String>,
+  string_count: usize,
+  string_chars: usize,
+}
+
+fn type_name(value: &Value) -> &'static str {
+  match value {
+    Value::Null => "null",
+    Value::Bool(_) => "boolean",
+    Value::Number(_) => "number",
+    Value::String(_) => "string",
+    Value::Array(_) => "array",
+    Value::Object(_) => "object",
+  }
+}
+
+fn is_empty_value(value: &Value) -> bool {
+  match value {
+    Value::Null => true,
+    Value::String(text) => text.trim().is_empty(),
+    Value::Array(items) => items.is_empty(),
+    Value::Object(map) => map.is_empty(),
+    _ => false,
+  }
+}
+
+fn example_text(text: &str) -> String {
+  let text = text.trim();
+  let head = byte_prefix(text, EXAMPLE_MAX_BYTES);
+  if head.len() < text.len() {
+    format!("{head}...")
+  } else {
+    head.to_string()
+  }
+}
+
+impl FieldProfile {
+  fn add(&mut self, value: &Value) {
+    self.present_count += 1;
+    *self.types.entry(type_name(value).to_string()).or_insert(0) += 1;
+    if is_empty_value(value) {
+      return;
+    }
+    self.non_empty_count += 1;
+    let text = value_to_string(value);
+    self.distinct.insert(xxh3_64(text.as_bytes()));
+    if let Value::String(text) = value {
+      self.string_count += 1;
+      self.string_chars += text_length(text);
+    }
+    if self.examples.len() < FIELD_EXAMPLES {
+      let example = example_text(&text);
+      if !self.examples.contains(&example) {
+        self.examples.push(example);
+      }
+    }
+  }
+
+  fn finish(self, name: String, scanned_count: usize) -> FieldStats {
+    let (distinct_estimate, distinct_exact) = self.distinct.estimate();
+    FieldStats {
+      name,
+      present_count: self.present_count,
+      non_empty_count: self.non_empty_count,
+      fill_rate: if scanned_count == 0 {
+        0.0
+      } else {
+        self.non_empty_count as f64 / scanned_count as f64
+      },
+      types: self.types,
+      distinct_estimate,
+      distinct_exact,
+      examples: self.examples,
+      mean_length: (self.string_count > 0)
+        .then(|| self.string_chars as f64 / self.string_count as f64),
+    }
+  }
+}
+
+/// Profiles every top-level field of the records in `ids` in one pass: JSON
+/// types, fill rate, distinct values, examples and mean string length.
+/// Fields are listed in store order, then in order of first appearance.
+pub fn field_matrix(
+  store: &DatasetStore,
+  ids: &IdSet,
+  sample_size: Option<usize>,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<FieldMatrix, String> {
+  let sampled = sample_subset(ids, sample_size, FIELD_SAMPLE_SEED);
+  let total = sampled.len();
+  let mut names = store.fields.clone();
+  let mut profiles: BTreeMap<String, FieldProfile> = BTreeMap::new();
+  let mut scanned_count = 0usize;
+  for line in store_lines(store)? {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Field scan canceled".to_string());
+    }
+    let line = line?;
+    let idx = line.id;
+    if !sampled.contains(idx) {
+      continue;
+    }
+    let Some(record) = line.record()? else {
+      continue;
+    };
+    scanned_count += 1;
+    if scanned_count.is_multiple_of(1000) {
+      on_progress(scanned_count, total);
+    }
+    for (name, value) in record.as_object().into_iter().flatten() {
+      if !profiles.contains_key(name) && !names.contains(name) {
+        names.push(name.clone());
+      }
+      profiles.entry(name.clone()).or_default().add(value);
+    }
+  }
+
+  let fields = names
+    .into_iter()
+    .map(|name| {
+      let profile = profiles.remove(&name).unwrap_or_default();
+      profile.finish(name, scanned_count)
+    })
+    .collect();
+  Ok(FieldMatrix {
+    view_count: ids.len(),
+    scanned_count,
+    fields,
+  })
+}
+
+/// Compares the fields of `incoming` with those of `base`: which fields only
+/// one of them has, from their full field lists, and which shared fields'
+/// fill rates differ by more than `threshold` over a sample of each.
+pub fn schema_diff(
+  base: &DatasetStore,
+  incoming: &DatasetStore,
+  sample_size: usize,
+  threshold: f64,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(&'static str, usize, usize),
+) -> Result<SchemaDiff, String> {
+  let sample_size = Some(sample_size.max(1));
+  let base_matrix = field_matrix(
+    base,
+    &IdSet::full(base.record_count),
+    sample_size,
+    cancel,
+    |current, total| on_progress("base", current, total),
+  )?;
+  let incoming_matrix = field_matrix(
+    incoming,
+    &IdSet::full(incoming.record_count),
+    sample_size,
+    cancel,
+    |current, total| on_progress("incoming", current, total),
+  )?;
+  let only_in = |left: &DatasetStore, right: &DatasetStore| {
+    left
+      .fields
+      .iter()
+      .filter(|field| !right.fields.contains(field))
+      .cloned()
+      .collect::<Vec<_>>()
+  };
+  let fill_rate_changes = base_matrix
+    .fields
+    .iter()
+    .filter_map(|base_stats| {
+      let incoming_stats = incoming_matrix
+        .fields
+        .iter()
+        .find(|stats| stats.name == base_stats.name)?;
+      ((base_stats.fill_rate - incoming_stats.fill_rate).abs() > threshold).then(|| {
+        FillRateChange {
+          field: base_stats.name.clone(),
+          base_fill_rate: base_stats.fill_rate,
+          incoming_fill_rate: incoming_stats.fill_rate,
+        }
+      })
+    })
+    .collect();
+  Ok(SchemaDiff {
+    only_in_base: only_in(base, incoming),
+    only_in_incoming: only_in(incoming, base),
+    fill_rate_changes,
+    base_scanned_count: base_matrix.scanned_count,
+    incoming_scanned_count: incoming_matrix.scanned_count,
+  })
+}
+
+/// One line describing a schema diff, for the event log.
+pub fn describe_schema_diff(diff: &SchemaDiff) -> String {
+  if diff.is_empty() {
+    return "no schema changes".to_string();
+  }
+  let mut parts = Vec::new();
+  if !diff.only_in_base.is_empty() {
+    parts.push(format!("missing fields {}", diff.only_in_base.join(", ")));
+  }
+  if !diff.only_in_incoming.is_empty() {
+    parts.push(format!("new fields {}", diff.only_in_incoming.join(", ")));
+  }
+  for change in &diff.fill_rate_changes {
+    parts.push(format!(
+      "{} filled {:.1}% -> {:.1}%",
+      change.field,
+      change.base_fill_rate * 100.0,
+      change.incoming_fill_rate * 100.0
+    ));
+  }
+  parts.join("; ")
+}
+
+fn type_summary(stats: &FieldStats) -> String {
+  let mut types = stats.types.iter().collect::<Vec<_>>();
+  types.sort_by_key(|(_, count)| std::cmp::Reverse(**count));
+  types
+    .into_iter()
+    .map(|(name, count)| {
+      let share = *count as f64 / stats.present_count.max(1) as f64 * 100.0;
+      format!("{name} {share:.0}%")
+    })
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+fn distinct_summary(stats: &FieldStats) -> String {
+  if stats.distinct_exact {
+    stats.distinct_estimate.to_string()
+  } else {
+    format!("~{}", stats.distinct_estimate)
+  }
+}
+
+fn length_summary(stats: &FieldStats) -> String {
+  stats
+    .mean_length
+    .map_or("-".to_string(), |length| format!("{length:.1}"))
+}
+
+/// The matrix as the "Fields" section of a dataset card.
+pub fn field_matrix_markdown(matrix: &FieldMatrix) -> String {
+  let mut out = String::new();
+  let _ = writeln!(out, "## Fields\n");
+  if matrix.scanned_count < matrix.view_count {
+    let _ = writeln!(
+      out,
+      "Profiled from a sample of {} of {} records.\n",
+      matrix.scanned_count, matrix.view_count
+    );
+  }
+  let _ = writeln!(
+    out,
+    "| field | types | fill rate | distinct | mean length | examples |\n\
+     |---|---|---|---|---|---|"
+  );
+  for stats in &matrix.fields {
+    let examples = stats
+      .examples
+      .iter()
+      .map(|example| markdown_cell(example, usize::MAX))
+      .collect::<Vec<_>>()
+      .join("<br>");
+    let _ = writeln!(
+      out,
+      "| {} | {} | {:.1}% | {} | {} | {} |",
+      markdown_cell(&stats.name, usize::MAX),
+      type_summary(stats),
+      stats.fill_rate * 100.0,
+      distinct_summary(stats),
+      length_summary(stats),
+      examples
+    );
+  }
+  out
+}
+
+/// The matrix as CSV, one row per field; examples are joined by ` | `.
+pub fn field_matrix_csv(matrix: &FieldMatrix) -> Result<String, String> {
+  let mut writer = csv::Writer::from_writer(Vec::new());
+  writer
+    .write_record([
+      "field",
+      "types",
+      "fill_rate",
+      "non_empty_count",
+      "distinct",
+      "mean_length",
+      "examples",
+    ])
+    .map_err(|e| e.to_string())?;
+  for stats in &matrix.fields {
+    writer
+      .write_record([
+        stats.name.clone(),
+        type_summary(stats),
+        format!("{:.4}", stats.fill_rate),
+        stats.non_empty_count.to_string(),
+        distinct_summary(stats),
+        length_summary(stats),
+        stats.examples.join(" | "),
+      ])
+      .map_err(|e| e.to_string())?;
+  }
+  let bytes = writer.into_inner().map_err(|e| e.to_string())?;
+  String::from_utf8(bytes).map_err(|e| e.to_string())
+}
@@ -1,219 +1,1679 @@
-use std::collections::{HashMap, HashSet};
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
 
 use serde_json::Value;
 
-use crate::models::{CategoryCount, FieldMap, FilterConfig, FilterSummary};
+use crate::categories::CategorySource;
+use crate::dedupe::{MinHasher, MinhashIndex};
+use crate::io::store_lines;
+use crate::language::languages_differ;
+use crate::models::{
+  CategoryCount,
+  CategoryList,
+  CategoryRules,
+  DrillDown,
+  FieldMap,
+  FilterConfig,
+  FilterSummary,
+  UNCATEGORIZED_LABEL,
+};
+use crate::metrics::numeric_value;
 use crate::records::{
-  extract_text_value, get_length_text, hamming_distance, simhash, text_length, value_to_string,
+  extract_text_value,
+  get_length_text,
+  hamming_distance,
+  output_instruction_ratio,
+  shingled_simhash,
+  text_length,
+  text_length_in,
 };
-use crate::state::DatasetStore;
+use crate::pii::PiiDetector;
+use crate::refusals::RefusalDetector;
+use crate::state::{DatasetStore, IdSet, InnerState};
+use crate::timing::StageTimer;
+use crate::validation::RuleSet;
+use crate::warnings::{filter_warnings, Uniformity};
 
-pub fn apply_filters_inner(
-  store: &DatasetStore,
-  filters: &FilterConfig,
-  field_map: &FieldMap,
-  cancel: &AtomicBool,
-  mut on_progress: impl FnMut(usize, usize),
-) -> Result<(Vec<usize>, FilterSummary), String> {
-  let mut required_fields = filters.require_fields.clone();
-  if required_fields.is_empty() {
-    if let Some(name) = &field_map.instruction {
-      required_fields.push(name.clone());
+fn count_rejection(rejected: &mut BTreeMap<String, usize>, reason: &str) {
+  *rejected.entry(reason.to_string()).or_insert(0) += 1;
+}
+
+/// One configurable check, in the order `RecordPredicates` applies them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Predicate {
+  RequiredFields,
+  Length,
+  LengthRatio,
+  IncludeKeywords,
+  ExcludeKeywords,
+  Category,
+  Score,
+  Numeric,
+  Pii,
+  Refusal,
+  LanguageMismatch,
+  Validation,
+}
+
+impl Predicate {
+  /// The rejection reason reported for records failing this check.
+  pub(crate) fn reason(self) -> &'static str {
+    match self {
+      Predicate::RequiredFields => "missing_fields",
+      Predicate::Length => "length",
+      Predicate::LengthRatio => "length_ratio",
+      Predicate::IncludeKeywords => "include_keywords",
+      Predicate::ExcludeKeywords => "exclude_keywords",
+      Predicate::Category => "category",
+      Predicate::Score => "score",
+      Predicate::Numeric => "numeric",
+      Predicate::Pii => "pii",
+      Predicate::Refusal => "refusal",
+      Predicate::LanguageMismatch => "language_mismatch",
+      Predicate::Validation => "validation",
     }
-    if let Some(name) = &field_map.output {
-      required_fields.push(name.clone());
+  }
+}
+
+/// The check that rejected a record, with the numeric rule or PII kind it
+/// failed on so the rejection counts need not run it again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Rejection {
+  Check(Predicate),
+  Numeric(usize),
+  Pii(&'static str),
+}
+
+impl Rejection {
+  pub(crate) fn reason(self) -> &'static str {
+    match self {
+      Rejection::Check(predicate) => predicate.reason(),
+      Rejection::Numeric(_) => Predicate::Numeric.reason(),
+      Rejection::Pii(_) => Predicate::Pii.reason(),
     }
   }
+}
 
-  let include_keywords = if filters.keyword_case_sensitive {
-    filters.include_keywords.clone()
+/// Rejection reason of a record longer than a store scan reads.
+const OVERSIZED_REASON: &str = "oversized";
+
+/// Keywords and the text they are looked for in, as the keyword checks
+/// compare them: lowercased unless matching is case-sensitive.
+pub(crate) fn keyword_normalized(text: &str, case_sensitive: bool) -> String {
+  if case_sensitive {
+    text.to_string()
   } else {
-    filters
-      .include_keywords
+    text.to_lowercase()
+  }
+}
+
+/// The scoped text a record's length and keyword checks look at.
+pub(crate) struct PredicateText {
+  length: usize,
+  keyword_text: String,
+}
+
+/// Per-record checks other than deduplication, prepared once from the filter config.
+pub(crate) struct RecordPredicates<'a> {
+  filters: &'a FilterConfig,
+  field_map: &'a FieldMap,
+  active: Vec<Predicate>,
+  required_fields: Vec<String>,
+  include_keywords: Vec<String>,
+  exclude_keywords: Vec<String>,
+  category_source: CategorySource,
+  category_filter: HashSet<String>,
+  category_exclude: HashSet<String>,
+  include_uncategorized: bool,
+  refusal_detector: Option<RefusalDetector>,
+  pii_detector: Option<PiiDetector>,
+  validation: RuleSet,
+  numeric_fields: Vec<Option<String>>,
+}
+
+impl<'a> RecordPredicates<'a> {
+  pub(crate) fn new(
+    filters: &'a FilterConfig,
+    field_map: &'a FieldMap,
+    category_rules: &CategoryRules,
+  ) -> Result<Self, String> {
+    let mut required_fields = filters.require_fields.clone();
+    if required_fields.is_empty() {
+      if let Some(name) = &field_map.instruction {
+        required_fields.push(name.clone());
+      }
+      if let Some(name) = &field_map.output {
+        required_fields.push(name.clone());
+      }
+    }
+
+    let normalized = |keywords: &[String]| {
+      keywords
+        .iter()
+        .map(|keyword| keyword_normalized(keyword, filters.keyword_case_sensitive))
+        .collect::<Vec<_>>()
+    };
+    let include_keywords = normalized(&filters.include_keywords);
+    let exclude_keywords = normalized(&filters.exclude_keywords);
+
+    let category_field = filters.category_field.as_deref().or(field_map.category.as_deref());
+    let category_source = CategorySource::new(category_field, category_rules)?;
+    let category_filter: HashSet<String> = filters
+      .categories
       .iter()
-      .map(|k| k.to_lowercase())
-      .collect()
-  };
-  let exclude_keywords = if filters.keyword_case_sensitive {
-    filters.exclude_keywords.clone()
-  } else {
-    filters
-      .exclude_keywords
+      .map(|cat| cat.to_lowercase())
+      .collect();
+    let category_exclude: HashSet<String> = filters
+      .exclude_categories
       .iter()
-      .map(|k| k.to_lowercase())
-      .collect()
-  };
+      .map(|cat| cat.to_lowercase())
+      .collect();
+    let include_uncategorized =
+      filters.include_uncategorized || category_filter.contains(UNCATEGORIZED_LABEL);
+
+    let refusal_detector = if filters.drop_refusals {
+      Some(RefusalDetector::from_filters(filters))
+    } else {
+      None
+    };
+
+    let pii_detector = if filters.pii.is_active() {
+      Some(PiiDetector::new(&filters.pii)?)
+    } else {
+      None
+    };
 
-  let category_field = filters
-    .category_field
-    .clone()
-    .or_else(|| field_map.category.clone());
-  let category_filter: HashSet<String> = filters
-    .categories
-    .iter()
-    .map(|cat| cat.to_lowercase())
+    let validation = RuleSet::new(&filters.validation_rules)?;
+    if filters.shingle_size == 0 {
+      return Err("Shingle size must be at least 1".to_string());
+    }
+    if filters.dedupe_fuzzy {
+      if !matches!(filters.dedupe_method.as_str(), "simhash" | "minhash") {
+        return Err(format!("Unknown dedupe method: {}", filters.dedupe_method));
+      }
+      if !(filters.minhash_threshold > 0.0 && filters.minhash_threshold <= 1.0) {
+        return Err("MinHash threshold must be in (0, 1]".to_string());
+      }
+      if filters.fuzzy_threshold.is_some_and(|threshold| threshold > FUZZY_THRESHOLD_LIMIT) {
+        return Err(format!("Fuzzy threshold must be at most {FUZZY_THRESHOLD_LIMIT}"));
+      }
+    }
+    if !matches!(filters.dedupe_keep.as_str(), "first" | "highest_score" | "longest_output") {
+      return Err(format!("Unknown duplicate to keep: {}", filters.dedupe_keep));
+    }
+    if filters.dedupe_keep == "highest_score" && field_map.score.is_none() {
+      return Err("Keeping the highest score needs a mapped score field".to_string());
+    }
+    if !matches!(filters.length_unit.as_str(), "chars" | "tokens") {
+      return Err(format!("Unknown length unit: {}", filters.length_unit));
+    }
+    let ratio_active = filters.min_output_instruction_ratio.is_some()
+      || filters.max_output_instruction_ratio.is_some();
+    if ratio_active {
+      if !matches!(filters.ratio_unit.as_str(), "chars" | "tokens") {
+        return Err(format!("Unknown ratio unit: {}", filters.ratio_unit));
+      }
+      if !matches!(filters.ratio_missing.as_str(), "skip" | "reject") {
+        return Err(format!("Unknown ratio policy: {}", filters.ratio_missing));
+      }
+    }
+    let score_active = filters.min_score.is_some() || filters.max_score.is_some();
+    if score_active && field_map.score.is_none() {
+      return Err("Score bounds need a mapped score field".to_string());
+    }
+    for rule in &filters.numeric_filters {
+      if rule.field.trim().is_empty() {
+        return Err("Numeric filters need a field".to_string());
+      }
+      if rule.min.zip(rule.max).is_some_and(|(min, max)| min > max) {
+        return Err(format!("Numeric filter on {} has min above max", rule.field));
+      }
+      if !matches!(rule.missing.as_str(), "keep" | "drop") {
+        return Err(format!("Unknown missing-value policy: {}", rule.missing));
+      }
+    }
+
+    let active = [
+      (Predicate::RequiredFields, !required_fields.is_empty()),
+      (
+        Predicate::Length,
+        filters.min_length.is_some() || filters.max_length.is_some(),
+      ),
+      (Predicate::LengthRatio, ratio_active),
+      (Predicate::IncludeKeywords, !include_keywords.is_empty()),
+      (Predicate::ExcludeKeywords, !exclude_keywords.is_empty()),
+      (
+        Predicate::Category,
+        !category_source.is_none()
+          && (!category_filter.is_empty() || !category_exclude.is_empty()),
+      ),
+      (Predicate::Score, score_active),
+      (Predicate::Numeric, !filters.numeric_filters.is_empty()),
+      (Predicate::Pii, pii_detector.is_some()),
+      (Predicate::Refusal, refusal_detector.is_some()),
+      (Predicate::LanguageMismatch, filters.require_same_language),
+      (Predicate::Validation, !validation.is_empty()),
+    ]
+    .into_iter()
+    .filter_map(|(predicate, active)| active.then_some(predicate))
     .collect();
 
-  let mut exact_seen: HashSet<String> = HashSet::new();
-  let mut fuzzy_buckets: HashMap<u16, Vec<u64>> = HashMap::new();
-  let mut filtered_ids = Vec::new();
-  let mut duplicates_removed = 0usize;
+    Ok(Self {
+      filters,
+      field_map,
+      active,
+      required_fields,
+      include_keywords,
+      exclude_keywords,
+      category_source,
+      category_filter,
+      category_exclude,
+      include_uncategorized,
+      refusal_detector,
+      pii_detector,
+      validation,
+      numeric_fields: filters
+        .numeric_filters
+        .iter()
+        .map(|rule| Some(rule.field.clone()))
+        .collect(),
+    })
+  }
+
+  /// The record's category, or `None` when no category source is set.
+  pub(crate) fn category(&self, record: &Value) -> Option<String> {
+    (!self.category_source.is_none()).then(|| {
+      self
+        .category_source
+        .category(record, self.field_map)
+        .unwrap_or_else(|| UNCATEGORIZED_LABEL.to_string())
+    })
+  }
 
-  let file = File::open(&store.store_path).map_err(|e| e.to_string())?;
-  let reader = BufReader::new(file);
+  /// The checks the filter config turns on, in application order.
+  pub(crate) fn active(&self) -> &[Predicate] {
+    &self.active
+  }
 
-  for (idx, line) in reader.lines().enumerate() {
-    if cancel.load(Ordering::SeqCst) {
-      return Err("Filter canceled".to_string());
-    }
-    let line = line.map_err(|e| e.to_string())?;
-    if line.trim().is_empty() {
-      continue;
+  /// Extracts the scoped text shared by the length and keyword checks.
+  pub(crate) fn text(&self, record: &Value) -> PredicateText {
+    let length_text = get_length_text(record, self.field_map, &self.filters.length_scope);
+    let length = text_length_in(&length_text, &self.filters.length_unit);
+    let keyword_text = if self.filters.keyword_case_sensitive {
+      length_text
+    } else {
+      keyword_normalized(&length_text, false)
+    };
+    PredicateText {
+      length,
+      keyword_text,
     }
-    let record: Value = serde_json::from_str(&line).map_err(|e| e.to_string())?;
+  }
 
-    if !required_fields.is_empty() {
-      let mut missing = false;
-      for field in &required_fields {
-        let value = record.get(field);
-        if value.is_none() || value == Some(&Value::Null) {
-          missing = true;
-          break;
+  /// Whether `predicate` rejects the record.
+  pub(crate) fn rejects(&self, predicate: Predicate, record: &Value, text: &PredicateText) -> bool {
+    let filters = self.filters;
+    match predicate {
+      Predicate::RequiredFields => self.required_fields.iter().any(|field| match record.get(field) {
+        None | Some(Value::Null) => true,
+        Some(Value::String(text)) => text.trim().is_empty(),
+        Some(_) => false,
+      }),
+      Predicate::Length => {
+        let length = text.length as u32;
+        filters.min_length.is_some_and(|min_len| length < min_len)
+          || filters.max_length.is_some_and(|max_len| length > max_len)
+      }
+      Predicate::LengthRatio => {
+        match output_instruction_ratio(record, self.field_map, &filters.ratio_unit) {
+          Some(ratio) => {
+            filters.min_output_instruction_ratio.is_some_and(|min| ratio < min)
+              || filters.max_output_instruction_ratio.is_some_and(|max| ratio > max)
+          }
+          None => filters.ratio_missing == "reject",
         }
-        if let Some(Value::String(text)) = value {
-          if text.trim().is_empty() {
-            missing = true;
-            break;
+      }
+      Predicate::IncludeKeywords => !self
+        .include_keywords
+        .iter()
+        .all(|keyword| text.keyword_text.contains(keyword)),
+      Predicate::ExcludeKeywords => self
+        .exclude_keywords
+        .iter()
+        .any(|keyword| text.keyword_text.contains(keyword)),
+      Predicate::Category => {
+        let include_all = self.category_filter.is_empty();
+        match self.category_source.category(record, self.field_map) {
+          Some(category) => {
+            let category = category.to_lowercase();
+            let included = include_all || self.category_filter.contains(&category);
+            !included || self.category_exclude.contains(&category)
           }
+          // Only the include list drops records without a category.
+          None => !include_all && !self.include_uncategorized,
         }
       }
-      if missing {
-        continue;
+      Predicate::Score => match numeric_value(record, &self.field_map.score) {
+        Some(score) => {
+          filters.min_score.is_some_and(|min| score < min)
+            || filters.max_score.is_some_and(|max| score > max)
+        }
+        None => true,
+      },
+      Predicate::Numeric => self.failed_numeric_filter(record).is_some(),
+      Predicate::Pii => self.pii_kind(record).is_some(),
+      Predicate::Refusal => self.refusal_detector.as_ref().is_some_and(|detector| {
+        let output_text = extract_text_value(record, &self.field_map.output).unwrap_or_default();
+        detector.is_refusal(&output_text)
+      }),
+      Predicate::LanguageMismatch => {
+        let field_map = self.field_map;
+        let instruction = extract_text_value(record, &field_map.instruction).unwrap_or_default();
+        let output = extract_text_value(record, &field_map.output).unwrap_or_default();
+        languages_differ(&instruction, &output, filters.language_min_confidence)
       }
+      Predicate::Validation => self.validation.violated_by(record),
     }
+  }
 
-    let length_text = get_length_text(&record, field_map, &filters.length_scope);
-    let length = text_length(&length_text) as u32;
-    if let Some(min_len) = filters.min_length {
-      if length < min_len {
-        continue;
+  /// The reason the record is rejected, if any.
+  pub(crate) fn rejection(&self, record: &Value) -> Option<&'static str> {
+    self.screen(record).err().map(Rejection::reason)
+  }
+
+  /// The rejection `predicate` gives the record, if it rejects it.
+  fn rejection_by(
+    &self,
+    predicate: Predicate,
+    record: &Value,
+    text: &PredicateText,
+  ) -> Option<Rejection> {
+    match predicate {
+      Predicate::Numeric => self.failed_numeric_filter(record).map(Rejection::Numeric),
+      Predicate::Pii => self.pii_kind(record).map(Rejection::Pii),
+      _ => self
+        .rejects(predicate, record, text)
+        .then_some(Rejection::Check(predicate)),
+    }
+  }
+
+  /// Index of the first numeric filter the record fails.
+  pub(crate) fn failed_numeric_filter(&self, record: &Value) -> Option<usize> {
+    let fields = self.numeric_fields.iter();
+    self.filters.numeric_filters.iter().zip(fields).position(|(rule, field)| {
+      match numeric_value(record, field) {
+        Some(value) => {
+          rule.min.is_some_and(|min| value < min) || rule.max.is_some_and(|max| value > max)
+        }
+        None => rule.missing == "drop",
       }
+    })
+  }
+
+  /// The first PII detector with a match in the record's instruction or output.
+  pub(crate) fn pii_kind(&self, record: &Value) -> Option<&'static str> {
+    let detector = self.pii_detector.as_ref()?;
+    detector.detect(&get_length_text(record, self.field_map, "combined"))
+  }
+
+  /// The scoped length of a record every check passes, or the rejection of
+  /// the first check that rejects it.
+  pub(crate) fn screen(&self, record: &Value) -> Result<usize, Rejection> {
+    let text = self.text(record);
+    match self
+      .active
+      .iter()
+      .find_map(|predicate| self.rejection_by(*predicate, record, &text))
+    {
+      Some(rejection) => Err(rejection),
+      None => Ok(text.length),
     }
-    if let Some(max_len) = filters.max_length {
-      if length > max_len {
-        continue;
+  }
+}
+
+/// Default maximum simhash Hamming distance for two texts to count as
+/// near-duplicates.
+const FUZZY_MAX_DISTANCE: u32 = 3;
+/// Largest configurable distance; each step adds a bucket segment.
+const FUZZY_THRESHOLD_LIMIT: u32 = 16;
+
+/// Similarity of two texts whose simhashes are `distance` bits apart.
+fn simhash_similarity(distance: u32) -> f64 {
+  1.0 - f64::from(distance) / 64.0
+}
+
+pub(crate) fn normalize_for_dedupe(text: &str) -> String {
+  text
+    .split_whitespace()
+    .collect::<Vec<_>>()
+    .join(" ")
+    .to_lowercase()
+}
+
+/// Splits `hash` into one run of adjacent bits per allowed bit of distance
+/// plus one, and at least four, each tagged with its position. Two hashes
+/// within `max_distance` bits share a run.
+fn simhash_segments(hash: u64, max_distance: u32) -> impl Iterator<Item = u64> {
+  let count = (max_distance + 1).max(4);
+  (0..count).map(move |segment| {
+    let start = segment * 64 / count;
+    let end = (segment + 1) * 64 / count;
+    let bits = (hash >> start) & ((1u64 << (end - start)) - 1);
+    (u64::from(segment) << 32) | bits
+  })
+}
+
+/// Simhashes bucketed by segments of a key hash, each carrying a secondary
+/// hash so joint matches can be checked on the same candidates, and the id
+/// of the record it came from. No match within `max_distance` is missed.
+pub(crate) struct SimhashIndex {
+  max_distance: u32,
+  buckets: HashMap<u64, Vec<(u64, u64, usize)>>,
+}
+
+impl Default for SimhashIndex {
+  fn default() -> Self {
+    Self::new(FUZZY_MAX_DISTANCE)
+  }
+}
+
+impl SimhashIndex {
+  pub(crate) fn new(max_distance: u32) -> Self {
+    Self {
+      max_distance,
+      buckets: HashMap::new(),
+    }
+  }
+
+  /// The id of an indexed record near `key` whose secondary hash `accept`
+  /// places within some distance, and the larger of the two distances.
+  pub(crate) fn find(
+    &self,
+    key: u64,
+    accept: impl Fn(u64) -> Option<u32>,
+  ) -> Option<(usize, u32)> {
+    simhash_segments(key, self.max_distance).find_map(|segment| {
+      self.buckets.get(&segment).and_then(|existing| {
+        existing.iter().find_map(|(candidate, secondary, id)| {
+          let distance = hamming_distance(*candidate, key);
+          if distance > self.max_distance {
+            return None;
+          }
+          accept(*secondary).map(|secondary| (*id, distance.max(secondary)))
+        })
+      })
+    })
+  }
+
+  pub(crate) fn insert(&mut self, key: u64, secondary: u64, id: usize) {
+    for segment in simhash_segments(key, self.max_distance) {
+      self.buckets.entry(segment).or_default().push((key, secondary, id));
+    }
+  }
+}
+
+/// Exact and simhash- or MinHash-based duplicate tracking over the records
+/// seen so far.
+#[derive(Default)]
+pub(crate) struct Deduper {
+  exact: bool,
+  fuzzy: bool,
+  mode: String,
+  shingle_size: usize,
+  max_distance: u32,
+  /// Replace a kept exact duplicate by a later copy of higher quality.
+  keep_best: bool,
+  /// Set when near-duplicates are found by MinHash rather than simhash.
+  minhasher: Option<MinHasher>,
+  minhash_threshold: f64,
+  /// Kept record and its quality per normalized text.
+  instruction_seen: HashMap<String, (usize, f64)>,
+  output_seen: HashMap<String, (usize, f64)>,
+  instruction_index: SimhashIndex,
+  output_index: SimhashIndex,
+  instruction_minhash: MinhashIndex,
+  output_minhash: MinhashIndex,
+  removed_by_method: BTreeMap<String, usize>,
+  /// Kept records replaced by a better exact copy, and their replacement.
+  replaced: HashMap<usize, usize>,
+}
+
+impl Deduper {
+  pub(crate) fn new(filters: &FilterConfig) -> Self {
+    let minhash = filters.dedupe_fuzzy && filters.dedupe_method == "minhash";
+    let max_distance = filters.fuzzy_threshold.unwrap_or(FUZZY_MAX_DISTANCE);
+    Self {
+      exact: filters.dedupe_exact,
+      fuzzy: filters.dedupe_fuzzy,
+      mode: filters.dedupe_mode.clone(),
+      shingle_size: filters.shingle_size,
+      max_distance,
+      keep_best: filters.dedupe_keep != "first",
+      minhasher: minhash.then(MinHasher::default),
+      minhash_threshold: filters.minhash_threshold,
+      instruction_index: SimhashIndex::new(max_distance),
+      output_index: SimhashIndex::new(max_distance),
+      ..Self::default()
+    }
+  }
+
+  /// Duplicates reported so far by each method: `exact`, `simhash` or `minhash`.
+  pub(crate) fn removed_by_method(&self) -> &BTreeMap<String, usize> {
+    &self.removed_by_method
+  }
+
+  /// Records the texts of record `id`, of the given `quality`, and returns
+  /// the record to drop as a duplicate, the record kept in its place and
+  /// their similarity: 1.0 for an exact match. The dropped record is `id`
+  /// unless it is an exact duplicate of better quality than the kept one.
+  pub(crate) fn check(
+    &mut self,
+    id: usize,
+    instruction_text: &str,
+    output_text: &str,
+    quality: f64,
+  ) -> Option<(usize, usize, f64)> {
+    let found = self.find_duplicate(id, instruction_text, output_text, quality)?;
+    *self.removed_by_method.entry(found.method.to_string()).or_insert(0) += 1;
+    match found.replaces {
+      Some(_) => Some((found.kept, id, found.similarity)),
+      None => Some((id, found.kept, found.similarity)),
+    }
+  }
+
+  fn find_duplicate(
+    &mut self,
+    id: usize,
+    instruction_text: &str,
+    output_text: &str,
+    quality: f64,
+  ) -> Option<Found> {
+    let found = match self.mode.as_str() {
+      "output" => self.single_duplicate(id, output_text, false, quality),
+      "both" => self.joint_duplicate(id, instruction_text, output_text, quality),
+      "either" => {
+        // Both sides are recorded even when the first already matched.
+        let instruction_dup = self.single_duplicate(id, instruction_text, true, quality);
+        let output_dup = self.single_duplicate(id, output_text, false, quality);
+        return self.either_duplicate(id, quality, instruction_dup, output_dup);
+      }
+      "combined" => {
+        if instruction_text.is_empty() && output_text.is_empty() {
+          return None;
+        }
+        let text = format!("{instruction_text}\n{output_text}");
+        self.single_duplicate(id, &text, true, quality)
       }
+      _ => self.single_duplicate(id, instruction_text, true, quality),
+    }?;
+    if let Some((instruction, key)) = &found.replaces {
+      self.replace_kept(*instruction, key.clone(), found.kept, id, quality);
     }
+    Some(found)
+  }
 
-    let keyword_text = if filters.keyword_case_sensitive {
-      length_text.clone()
+  /// Combines the two sides of `either` mode. A side finding `id` to be a
+  /// duplicate drops it; a better copy only replaces the kept record when
+  /// both sides matched that same record, since it would otherwise lose a
+  /// text only the kept record holds.
+  fn either_duplicate(
+    &mut self,
+    id: usize,
+    quality: f64,
+    instruction_dup: Option<Found>,
+    output_dup: Option<Found>,
+  ) -> Option<Found> {
+    if let (Some(instruction), Some(output)) = (&instruction_dup, &output_dup) {
+      if let (Some((_, instruction_key)), Some((_, output_key))) =
+        (&instruction.replaces, &output.replaces)
+      {
+        if instruction.kept == output.kept {
+          self.replace_kept(true, instruction_key.clone(), instruction.kept, id, quality);
+          self.replace_kept(false, output_key.clone(), output.kept, id, quality);
+          return instruction_dup;
+        }
+      }
+    }
+    let mut found = instruction_dup.or(output_dup)?;
+    found.replaces = None;
+    Some(found)
+  }
+
+  /// Makes `id` the kept record of the exact text `key` in place of `kept`.
+  /// Index entries of `kept` then stand for `id`, whose texts match them.
+  fn replace_kept(&mut self, instruction: bool, key: String, kept: usize, id: usize, quality: f64) {
+    let seen = if instruction {
+      &mut self.instruction_seen
     } else {
-      length_text.to_lowercase()
+      &mut self.output_seen
     };
-    if !include_keywords.is_empty()
-      && !include_keywords
-        .iter()
-        .all(|keyword| keyword_text.contains(keyword))
-    {
+    seen.insert(key, (id, quality));
+    self.replaced.insert(kept, id);
+  }
+
+  /// Looks `key` up among the exact texts seen so far. A record of higher
+  /// quality than the kept one is reported as replacing it when `keep_best`
+  /// is set, leaving the caller to apply the replacement.
+  fn exact_duplicate(
+    seen: &mut HashMap<String, (usize, f64)>,
+    key: String,
+    instruction: bool,
+    id: usize,
+    quality: f64,
+    keep_best: bool,
+  ) -> Option<Found> {
+    match seen.entry(key) {
+      Entry::Occupied(entry) => {
+        let (kept, kept_quality) = *entry.get();
+        let replaces =
+          (keep_best && quality > kept_quality).then(|| (instruction, entry.key().clone()));
+        Some(Found {
+          kept,
+          similarity: 1.0,
+          method: "exact",
+          replaces,
+        })
+      }
+      Entry::Vacant(entry) => {
+        entry.insert((id, quality));
+        None
+      }
+    }
+  }
+
+  fn single_duplicate(
+    &mut self,
+    id: usize,
+    text: &str,
+    instruction: bool,
+    quality: f64,
+  ) -> Option<Found> {
+    if text.is_empty() {
+      return None;
+    }
+    let (seen, index, minhash_index) = if instruction {
+      (
+        &mut self.instruction_seen,
+        &mut self.instruction_index,
+        &mut self.instruction_minhash,
+      )
+    } else {
+      (&mut self.output_seen, &mut self.output_index, &mut self.output_minhash)
+    };
+    if self.exact {
+      let key = normalize_for_dedupe(text);
+      let found = Self::exact_duplicate(seen, key, instruction, id, quality, self.keep_best);
+      if found.is_some() {
+        return found;
+      }
+    }
+    if !self.fuzzy {
+      return None;
+    }
+    if let Some(minhasher) = &self.minhasher {
+      // Texts without words are left to the exact check.
+      let signature = minhasher.signature(text, self.shingle_size)?;
+      if let Some((original, similarity)) =
+        minhash_index.find(&signature, None, self.minhash_threshold)
+      {
+        return Some(Found::near(current_id(&self.replaced, original), similarity, "minhash"));
+      }
+      minhash_index.insert(signature, None, id);
+    } else {
+      let hash = shingled_simhash(text, self.shingle_size);
+      if let Some((original, distance)) = index.find(hash, |_| Some(0)) {
+        let kept = current_id(&self.replaced, original);
+        return Some(Found::near(kept, simhash_similarity(distance), "simhash"));
+      }
+      index.insert(hash, 0, id);
+    }
+    None
+  }
+
+  fn joint_duplicate(
+    &mut self,
+    id: usize,
+    instruction_text: &str,
+    output_text: &str,
+    quality: f64,
+  ) -> Option<Found> {
+    if instruction_text.is_empty() && output_text.is_empty() {
+      return None;
+    }
+    if self.exact {
+      let key = format!(
+        "{}\u{1f}{}",
+        normalize_for_dedupe(instruction_text),
+        normalize_for_dedupe(output_text)
+      );
+      let seen = &mut self.instruction_seen;
+      let found = Self::exact_duplicate(seen, key, true, id, quality, self.keep_best);
+      if found.is_some() {
+        return found;
+      }
+    }
+    if !self.fuzzy {
+      return None;
+    }
+    if let Some(minhasher) = &self.minhasher {
+      // Records with a side without words are left to the exact check.
+      let instruction_signature = minhasher.signature(instruction_text, self.shingle_size)?;
+      let output_signature = minhasher.signature(output_text, self.shingle_size)?;
+      let found = self.instruction_minhash.find(
+        &instruction_signature,
+        Some(&output_signature),
+        self.minhash_threshold,
+      );
+      if let Some((original, similarity)) = found {
+        return Some(Found::near(current_id(&self.replaced, original), similarity, "minhash"));
+      }
+      self
+        .instruction_minhash
+        .insert(instruction_signature, Some(output_signature), id);
+    } else {
+      let instruction_hash = shingled_simhash(instruction_text, self.shingle_size);
+      let output_hash = shingled_simhash(output_text, self.shingle_size);
+      let found = self.instruction_index.find(instruction_hash, |candidate| {
+        Some(hamming_distance(candidate, output_hash)).filter(|d| *d <= self.max_distance)
+      });
+      if let Some((original, distance)) = found {
+        let kept = current_id(&self.replaced, original);
+        return Some(Found::near(kept, simhash_similarity(distance), "simhash"));
+      }
+      self.instruction_index.insert(instruction_hash, output_hash, id);
+    }
+    None
+  }
+}
+
+/// A kept record the checked record duplicates.
+struct Found {
+  kept: usize,
+  similarity: f64,
+  method: &'static str,
+  /// Set when the checked record is the better exact copy and should take
+  /// the kept record's place: the side and normalized text it replaces on.
+  replaces: Option<(bool, String)>,
+}
+
+impl Found {
+  fn near(kept: usize, similarity: f64, method: &'static str) -> Self {
+    Self {
+      kept,
+      similarity,
+      method,
+      replaces: None,
+    }
+  }
+}
+
+/// The record standing for `id` after any replacements by better copies.
+fn current_id(replaced: &HashMap<usize, usize>, mut id: usize) -> usize {
+  while let Some(next) = replaced.get(&id) {
+    id = *next;
+  }
+  id
+}
+
+/// The kept record each record dropped as a duplicate was matched to, and
+/// how similar the two were. Holds one entry per dropped record.
+#[derive(Debug, Clone, Default)]
+pub struct DuplicateMap {
+  removed: HashMap<usize, (usize, f64)>,
+}
+
+/// A kept record and the records dropped as its duplicates, in id order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateCluster {
+  pub kept: usize,
+  pub removed: Vec<usize>,
+  /// Lowest similarity between the kept record and one it replaced.
+  pub similarity: f64,
+}
+
+impl DuplicateMap {
+  /// Notes that `removed` was dropped as a duplicate of `kept`. A record
+  /// matched to one that was itself dropped joins that record's cluster.
+  pub fn insert(&mut self, removed: usize, kept: usize, similarity: f64) {
+    let kept = self.root(kept);
+    self.removed.insert(removed, (kept, similarity));
+  }
+
+  /// The kept record `id` ends up represented by. A kept record replaced by
+  /// a better duplicate hands its cluster on to the replacement.
+  fn root(&self, mut id: usize) -> usize {
+    while let Some((kept, _)) = self.removed.get(&id) {
+      id = *kept;
+    }
+    id
+  }
+
+  pub fn len(&self) -> usize {
+    self.removed.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.removed.is_empty()
+  }
+
+  /// Dropped records grouped by the record they were matched to, in kept id
+  /// order.
+  pub fn clusters(&self) -> Vec<DuplicateCluster> {
+    let mut grouped: BTreeMap<usize, DuplicateCluster> = BTreeMap::new();
+    for (removed, (kept, similarity)) in &self.removed {
+      let kept = self.root(*kept);
+      let cluster = grouped.entry(kept).or_insert_with(|| DuplicateCluster {
+        kept,
+        removed: Vec::new(),
+        similarity: 1.0,
+      });
+      cluster.removed.push(*removed);
+      cluster.similarity = cluster.similarity.min(*similarity);
+    }
+    let mut clusters = grouped.into_values().collect::<Vec<_>>();
+    for cluster in &mut clusters {
+      cluster.removed.sort_unstable();
+    }
+    clusters
+  }
+}
+
+/// Token total and category spread of the records in `kept`, rescanned once
+/// better duplicates have replaced records that were counted when kept.
+fn kept_totals(
+  store: &DatasetStore,
+  kept: &IdSet,
+  predicates: &RecordPredicates,
+  count_tokens: bool,
+  cancel: &AtomicBool,
+) -> Result<(Option<usize>, Uniformity<Option<String>>), String> {
+  let mut tokens = count_tokens.then_some(0usize);
+  let mut categories = Uniformity::default();
+  for line in store_lines(store)? {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Filter canceled".to_string());
+    }
+    let line = line?;
+    if !kept.contains(line.id) {
       continue;
     }
-    if exclude_keywords
-      .iter()
-      .any(|keyword| keyword_text.contains(keyword))
-    {
+    let Some(record) = line.record()? else {
       continue;
+    };
+    categories.add(predicates.category(&record));
+    if let Some(tokens) = &mut tokens {
+      *tokens += predicates.screen(&record).unwrap_or_default();
     }
+  }
+  Ok((tokens, categories))
+}
+
+/// Filters the store, or only the records in `base_ids` when given.
+pub fn apply_filters_inner(
+  store: &DatasetStore,
+  base_ids: Option<&IdSet>,
+  filters: &FilterConfig,
+  field_map: &FieldMap,
+  category_rules: &CategoryRules,
+  cancel: &AtomicBool,
+  on_progress: impl FnMut(usize, usize),
+) -> Result<(IdSet, FilterSummary), String> {
+  let (filtered_ids, mut summary, _) = apply_filters_tracked(
+    store,
+    base_ids,
+    filters,
+    field_map,
+    category_rules,
+    cancel,
+    on_progress,
+  )?;
+  summary.duplicate_map_kept = false;
+  Ok((filtered_ids, summary))
+}
+
+/// Like `apply_filters_inner`, also returning which record each dropped
+/// duplicate was matched to when `filters.keep_duplicate_map` is set.
+pub fn apply_filters_tracked(
+  store: &DatasetStore,
+  base_ids: Option<&IdSet>,
+  filters: &FilterConfig,
+  field_map: &FieldMap,
+  category_rules: &CategoryRules,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<(IdSet, FilterSummary, Option<DuplicateMap>), String> {
+  let mut timer = StageTimer::new();
+  let predicates = RecordPredicates::new(filters, field_map, category_rules)?;
+  let mut deduper = Deduper::new(filters);
+  let mut duplicates = filters.keep_duplicate_map.then(DuplicateMap::default);
+  let mut filtered_ids = IdSet::new();
+  let mut duplicates_removed = 0usize;
+  let mut rejected: BTreeMap<String, usize> = BTreeMap::new();
+  let mut scanned_categories = Uniformity::default();
+  let mut kept_categories = Uniformity::default();
+  let mut kept_tokens = (filters.length_unit == "tokens").then_some(0usize);
+  let mut numeric_rejected = vec![0usize; filters.numeric_filters.len()];
+  let mut pii_rejected: BTreeMap<String, usize> = BTreeMap::new();
+  let mut replaced_kept = false;
+
+  let mut lines = store_lines(store)?;
 
-    if let Some(category_field) = &category_field {
-      if !category_filter.is_empty() {
-        let category_value = record
-          .get(category_field)
-          .map(|value| value_to_string(value).to_lowercase())
-          .unwrap_or_default();
-        if !category_filter.contains(&category_value) {
+  loop {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Filter canceled".to_string());
+    }
+    let next = timer.time("scan", || -> Result<Option<(usize, Value)>, String> {
+      for line in lines.by_ref() {
+        let line = line?;
+        if base_ids.is_some_and(|ids| !ids.contains(line.id)) {
+          continue;
+        }
+        if line.oversized_size().is_some() {
+          count_rejection(&mut rejected, OVERSIZED_REASON);
           continue;
         }
+        if let Some(record) = line.record()? {
+          return Ok(Some((line.id, record)));
+        }
       }
-    }
+      Ok(None)
+    })?;
+    let Some((idx, record)) = next else {
+      break;
+    };
+    timer.count("scan", 1);
+    let category = predicates.category(&record);
+    scanned_categories.add(category.clone());
 
-    let instruction_text = extract_text_value(&record, &field_map.instruction).unwrap_or_default();
-    if filters.dedupe_exact && !instruction_text.is_empty() {
-      let normalized = instruction_text
-        .split_whitespace()
-        .collect::<Vec<_>>()
-        .join(" ")
-        .to_lowercase();
-      if !exact_seen.insert(normalized) {
-        duplicates_removed += 1;
+    timer.count("predicates", 1);
+    let length = match timer.time("predicates", || predicates.screen(&record)) {
+      Ok(length) => length,
+      Err(rejection) => {
+        match rejection {
+          Rejection::Numeric(rule) => numeric_rejected[rule] += 1,
+          Rejection::Pii(kind) => count_rejection(&mut pii_rejected, kind),
+          Rejection::Check(_) => {}
+        }
+        count_rejection(&mut rejected, rejection.reason());
         continue;
       }
-    }
+    };
 
-    if filters.dedupe_fuzzy && !instruction_text.is_empty() {
-      let hash = simhash(&instruction_text);
-      let mut duplicate = false;
-      let segments = [
-        (hash & 0xFFFF) as u16,
-        ((hash >> 16) & 0xFFFF) as u16,
-        ((hash >> 32) & 0xFFFF) as u16,
-        ((hash >> 48) & 0xFFFF) as u16,
-      ];
-      for segment in segments {
-        if let Some(existing) = fuzzy_buckets.get(&segment) {
-          if existing
-            .iter()
-            .any(|candidate| hamming_distance(*candidate, hash) <= 3)
-          {
-            duplicate = true;
-            break;
-          }
-        }
+    timer.count("dedupe", 1);
+    let duplicate = timer.time("dedupe", || {
+      let instruction_text = extract_text_value(&record, &field_map.instruction).unwrap_or_default();
+      let output_text = extract_text_value(&record, &field_map.output).unwrap_or_default();
+      let quality = match filters.dedupe_keep.as_str() {
+        "highest_score" => numeric_value(&record, &field_map.score).unwrap_or(f64::NEG_INFINITY),
+        "longest_output" => text_length(&output_text) as f64,
+        _ => 0.0,
+      };
+      deduper.check(idx, &instruction_text, &output_text, quality)
+    });
+    if let Some((removed, kept, similarity)) = duplicate {
+      if let Some(duplicates) = &mut duplicates {
+        duplicates.insert(removed, kept, similarity);
       }
-      if duplicate {
-        duplicates_removed += 1;
+      duplicates_removed += 1;
+      count_rejection(&mut rejected, "duplicate");
+      if removed == idx {
         continue;
       }
-      for segment in segments {
-        fuzzy_buckets.entry(segment).or_default().push(hash);
-      }
+      filtered_ids.remove(removed);
+      replaced_kept = true;
     }
 
-    filtered_ids.push(idx);
-    if idx % 1000 == 0 {
+    filtered_ids.insert(idx);
+    kept_categories.add(category);
+    if let Some(tokens) = &mut kept_tokens {
+      *tokens += length;
+    }
+    if idx.is_multiple_of(1000) {
       on_progress(idx, store.record_count);
     }
   }
+  if replaced_kept {
+    (kept_tokens, kept_categories) = timer.time("scan", || {
+      kept_totals(store, &filtered_ids, &predicates, kept_tokens.is_some(), cancel)
+    })?;
+  }
 
-  let summary = FilterSummary {
-    total_count: store.record_count,
+  let mut summary = FilterSummary {
+    total_count: base_ids.map_or(store.record_count, IdSet::len),
     filtered_count: filtered_ids.len(),
     duplicates_removed,
+    rejected,
+    timings: timer.finish(),
+    sample_view: None,
+    warnings: Vec::new(),
+    duplicate_map_kept: duplicates.is_some(),
+    kept_tokens,
+    duplicates_by_method: deduper.removed_by_method().clone(),
+    numeric_rejected,
+    pii_rejected,
   };
-  Ok((filtered_ids, summary))
+  // Keeping the one category asked for is no surprise.
+  let single_category = if scanned_categories.is_mixed() && filters.categories.len() != 1 {
+    kept_categories.single().cloned().flatten()
+  } else {
+    None
+  };
+  summary.warnings = filter_warnings(&summary, single_category.as_deref());
+  Ok((filtered_ids, summary, duplicates))
+}
+
+/// The settings a filter run's deduplication depended on. A later run with
+/// the same key drops the same duplicates among the records both keep.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DedupeKey {
+  exact: bool,
+  fuzzy: bool,
+  mode: String,
+  method: String,
+  minhash_threshold: f64,
+  fuzzy_threshold: Option<u32>,
+  keep: String,
+  shingle_size: usize,
+  instruction: Option<String>,
+  output: Option<String>,
+}
+
+impl DedupeKey {
+  pub fn new(filters: &FilterConfig, field_map: &FieldMap) -> Self {
+    Self {
+      exact: filters.dedupe_exact,
+      fuzzy: filters.dedupe_fuzzy,
+      mode: filters.dedupe_mode.clone(),
+      method: filters.dedupe_method.clone(),
+      minhash_threshold: filters.minhash_threshold,
+      fuzzy_threshold: filters.fuzzy_threshold,
+      keep: filters.dedupe_keep.clone(),
+      shingle_size: filters.shingle_size,
+      instruction: field_map.instruction.clone(),
+      output: field_map.output.clone(),
+    }
+  }
+}
+
+/// The stricter of two optional bounds: the larger lower bound, or the
+/// smaller upper one.
+fn stricter<T: PartialOrd + Copy>(current: Option<T>, added: Option<T>, lower: bool) -> Option<T> {
+  match (current, added) {
+    (Some(a), Some(b)) => Some(if (a < b) == lower { b } else { a }),
+    (a, b) => a.or(b),
+  }
+}
+
+fn check_range<T: PartialOrd>(min: Option<T>, max: Option<T>, label: &str) -> Result<(), String> {
+  match (min, max) {
+    (Some(min), Some(max)) if min > max => {
+      Err(format!("The {label} bucket lies outside the current filters"))
+    }
+    _ => Ok(()),
+  }
 }
 
-pub fn collect_categories(store: &DatasetStore, field: &str) -> Result<Vec<CategoryCount>, String> {
-  let file = File::open(&store.store_path).map_err(|e| e.to_string())?;
-  let reader = BufReader::new(file);
+/// `filters` narrowed by `drill`. Bounds are intersected with the current
+/// ones; a category replaces the selected list, which must allow it.
+pub fn compose_drill_down(
+  filters: &FilterConfig,
+  drill: &DrillDown,
+) -> Result<FilterConfig, String> {
+  let mut composed = filters.clone();
+  match drill {
+    DrillDown::Category { value } => {
+      let value = value.trim();
+      if value.is_empty() {
+        return Err("Category is empty".to_string());
+      }
+      let lower = value.to_lowercase();
+      let allowed = filters.categories.is_empty()
+        || filters.categories.iter().any(|name| name.to_lowercase() == lower)
+        || (value == UNCATEGORIZED_LABEL && filters.include_uncategorized);
+      let excluded = value != UNCATEGORIZED_LABEL
+        && filters.exclude_categories.iter().any(|name| name.to_lowercase() == lower);
+      if !allowed || excluded {
+        return Err(format!("Category {value} is excluded by the current filters"));
+      }
+      composed.categories = vec![value.to_string()];
+      composed.include_uncategorized = false;
+    }
+    DrillDown::Length { min, max } => {
+      composed.min_length = stricter(filters.min_length, *min, true);
+      composed.max_length = stricter(filters.max_length, *max, false);
+      check_range(composed.min_length, composed.max_length, "length")?;
+    }
+    DrillDown::Score { min, max } => {
+      composed.min_score = stricter(filters.min_score, *min, true);
+      composed.max_score = stricter(filters.max_score, *max, false);
+      check_range(composed.min_score, composed.max_score, "score")?;
+    }
+  }
+  Ok(composed)
+}
+
+/// Re-checks the predicates of `filters` on `filtered_ids`, the result of an
+/// earlier run with the same `DedupeKey`, without deduplicating again. A
+/// record whose only earlier copy `filters` now rejects stays dropped, as it
+/// was in the earlier run.
+pub fn narrow_filtered(
+  store: &DatasetStore,
+  filtered_ids: &IdSet,
+  filters: &FilterConfig,
+  field_map: &FieldMap,
+  category_rules: &CategoryRules,
+  cancel: &AtomicBool,
+  on_progress: impl FnMut(usize, usize),
+) -> Result<(IdSet, FilterSummary), String> {
+  let predicates_only = FilterConfig {
+    dedupe_exact: false,
+    dedupe_fuzzy: false,
+    ..filters.clone()
+  };
+  apply_filters_inner(
+    store,
+    Some(filtered_ids),
+    &predicates_only,
+    field_map,
+    category_rules,
+    cancel,
+    on_progress,
+  )
+}
+
+pub const DEFAULT_MAX_DISTINCT_CATEGORIES: usize = 10_000;
+
+/// Counts category values until `max_distinct` different values are seen, so
+/// a free-text field picked by mistake stops early instead of exhausting
+/// memory. Canceling also returns the counts gathered so far.
+pub fn collect_categories(
+  store: &DatasetStore,
+  source: &CategorySource,
+  field_map: &FieldMap,
+  max_distinct: usize,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<CategoryList, String> {
   let mut counts: HashMap<String, usize> = HashMap::new();
-  for line in reader.lines() {
-    let line = line.map_err(|e| e.to_string())?;
-    if line.trim().is_empty() {
+  let mut uncategorized = 0usize;
+  let mut scanned_count = 0usize;
+  let mut truncated = false;
+  for line in store_lines(store)? {
+    if cancel.load(Ordering::SeqCst) {
+      truncated = true;
+      break;
+    }
+    let line = line?;
+    let idx = line.id;
+    let Some(record) = line.record()? else {
       continue;
+    };
+    if idx.is_multiple_of(1000) {
+      on_progress(idx, store.record_count);
     }
-    let record: Value = serde_json::from_str(&line).map_err(|e| e.to_string())?;
-    if let Some(value) = record.get(field) {
-      let key = value_to_string(value);
-      *counts.entry(key).or_insert(0) += 1;
+    match source.category(&record, field_map) {
+      Some(key) => {
+        if let Some(count) = counts.get_mut(&key) {
+          *count += 1;
+        } else if counts.len() < max_distinct {
+          counts.insert(key, 1);
+        } else {
+          truncated = true;
+          break;
+        }
+      }
+      None => uncategorized += 1,
     }
+    scanned_count += 1;
   }
-  let mut list = counts
+  let distinct_count = counts.len();
+  let mut categories = counts
     .into_iter()
     .map(|(name, count)| CategoryCount { name, count })
     .collect::<Vec<_>>();
-  list.sort_by(|a, b| b.count.cmp(&a.count));
-  Ok(list)
+  categories.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+  // Listed last so it reads as a separate choice rather than a real category.
+  if uncategorized > 0 {
+    categories.push(CategoryCount {
+      name: UNCATEGORIZED_LABEL.to_string(),
+      count: uncategorized,
+    });
+  }
+  Ok(CategoryList {
+    categories,
+    truncated,
+    scanned_count,
+    distinct_count,
+  })
+}
+
+/// `collect_categories` over the active dataset, by `field` or by the
+/// category rules. The store and configs are cloned under the read lock,
+/// which is released before the scan so previews and edits are not held up
+/// by it.
+pub fn list_active_categories(
+  state: &RwLock<InnerState>,
+  field: Option<&str>,
+  max_distinct: usize,
+  cancel: &AtomicBool,
+  on_progress: impl FnMut(usize, usize),
+) -> Result<CategoryList, String> {
+  let (store, source, field_map) = {
+    let inner = state.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    let source = CategorySource::new(field, &inner.category_rules)?;
+    (store, source, inner.field_map.clone())
+  };
+  if source.is_none() {
+    return Err("No category field or category rules configured".to_string());
+  }
+  collect_categories(&store, &source, &field_map, max_distinct, cancel, on_progress)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::mpsc;
+  use std::thread;
+  use std::time::{Duration, Instant};
+
+  use serde_json::json;
+
+  use super::*;
+  use crate::io::PreviewPageSource;
+  use crate::models::{ImportOptions, NumericFilter, PiiFilter, DEFAULT_MAX_RECORD_BYTES};
+  use crate::pii::{PII_EMAIL, PII_PHONE};
+  use crate::store_index::load_store_index;
+  use crate::test_support::{assert_stages_timed, jsonl_store_with, text_field_map, TempDir};
+
+  const TEXT: &str = "explain how the borrow checker keeps references valid while a value is \
+                      moved between functions in a small rust program";
+
+  fn keep_best(mode: &str) -> FilterConfig {
+    FilterConfig {
+      dedupe_mode: mode.to_string(),
+      dedupe_keep: "highest_score".to_string(),
+      ..FilterConfig::default()
+    }
+  }
+
+  #[test]
+  fn either_mode_drops_a_better_copy_that_duplicates_another_kept_output() {
+    let mut deduper = Deduper::new(&keep_best("either"));
+    assert_eq!(deduper.check(0, "a", "x", 1.0), None);
+    assert_eq!(deduper.check(1, "b", "y", 1.0), None);
+    // The instruction would replace record 0, but the output duplicates record 1.
+    let (removed, _, _) = deduper.check(2, "a", "y", 9.0).unwrap();
+    assert_eq!(removed, 2);
+    // Record 0 is still the kept copy of its instruction.
+    assert_eq!(deduper.check(3, "a", "z", 0.0), Some((3, 0, 1.0)));
+  }
+
+  #[test]
+  fn either_mode_replaces_when_both_sides_match_the_same_record() {
+    let mut deduper = Deduper::new(&keep_best("either"));
+    assert_eq!(deduper.check(0, "a", "x", 1.0), None);
+    assert_eq!(deduper.check(1, "a", "x", 9.0), Some((0, 1, 1.0)));
+    assert_eq!(deduper.check(2, "A", "X", 5.0), Some((2, 1, 1.0)));
+  }
+
+  #[test]
+  fn near_duplicates_match_the_record_that_replaced_the_first_copy() {
+    let filters = FilterConfig {
+      dedupe_fuzzy: true,
+      fuzzy_threshold: Some(16),
+      ..keep_best("instruction")
+    };
+    let mut deduper = Deduper::new(&filters);
+    assert_eq!(deduper.check(0, TEXT, "", 1.0), None);
+    assert_eq!(deduper.check(1, TEXT, "", 9.0), Some((0, 1, 1.0)));
+    let near = format!("{TEXT} today");
+    let (removed, kept, _) = deduper.check(2, &near, "", 0.0).unwrap();
+    assert_eq!((removed, kept), (2, 1));
+  }
+
+  #[test]
+  fn duplicate_map_moves_clusters_to_the_replacing_record() {
+    let mut duplicates = DuplicateMap::default();
+    duplicates.insert(2, 0, 1.0);
+    duplicates.insert(0, 3, 1.0);
+    let clusters = duplicates.clusters();
+    assert_eq!(clusters.len(), 1);
+    assert_eq!(clusters[0].kept, 3);
+    assert_eq!(clusters[0].removed, vec![0, 2]);
+  }
+
+  #[test]
+  fn records_are_read_up_to_the_import_cap_of_their_store() {
+    let dir = TempDir::new();
+    let huge = "x".repeat(DEFAULT_MAX_RECORD_BYTES + 1024);
+    let records = [
+      json!({ "instruction": "first", "output": "one" }),
+      json!({ "instruction": huge, "output": "two" }),
+      json!({ "instruction": "third", "output": "three" }),
+    ];
+    let options = ImportOptions {
+      max_record_bytes: 2 * DEFAULT_MAX_RECORD_BYTES,
+      ..ImportOptions::default()
+    };
+    let mut store = jsonl_store_with(&dir, &records, &options);
+    assert_eq!(store.record_count, 3);
+    let reopened = load_store_index(&store.store_path).unwrap().unwrap();
+    assert_eq!(reopened.max_record_bytes, 2 * DEFAULT_MAX_RECORD_BYTES);
+    let filter = |store: &DatasetStore| {
+      apply_filters_inner(
+        store,
+        None,
+        &FilterConfig::default(),
+        &text_field_map(),
+        &CategoryRules::default(),
+        &AtomicBool::new(false),
+        |_, _| {},
+      )
+      .unwrap()
+    };
+    // Over the default cap but under the one the store was imported with.
+    let (ids, summary) = filter(&store);
+    assert_eq!(ids.iter().collect::<Vec<_>>(), vec![0, 1, 2]);
+    assert_eq!(summary.rejected.get(OVERSIZED_REASON), None);
+
+    // A line longer than the store's cap is counted without being read.
+    store.max_record_bytes = DEFAULT_MAX_RECORD_BYTES;
+    let (ids, summary) = filter(&store);
+    assert_eq!(ids.iter().collect::<Vec<_>>(), vec![0, 2]);
+    assert_eq!(summary.rejected.get(OVERSIZED_REASON), Some(&1));
+    let lines = store_lines(&store)
+      .unwrap()
+      .collect::<Result<Vec<_>, _>>()
+      .unwrap();
+    assert!(lines[1].oversized_size().is_some_and(|size| size > huge.len() as u64));
+    assert!(lines[1].record().unwrap().is_none());
+  }
+
+  #[test]
+  fn rejections_count_the_numeric_rule_and_pii_kind_that_failed() {
+    let dir = TempDir::new();
+    let records = [
+      json!({ "instruction": "mail a@example.com", "output": "ok", "score": 5 }),
+      json!({ "instruction": "call 415-555-0123", "output": "ok", "score": 5 }),
+      json!({ "instruction": "plain", "output": "ok", "score": 1 }),
+      json!({ "instruction": "plain", "output": "ok", "score": 5, "turns": 9 }),
+      json!({ "instruction": "kept", "output": "ok", "score": 5 }),
+    ];
+    let store = jsonl_store_with(&dir, &records, &ImportOptions::default());
+    let rule = |field: &str, min: Option<f64>, max: f64| NumericFilter {
+      field: field.to_string(),
+      min,
+      max: Some(max),
+      missing: "keep".to_string(),
+    };
+    let filters = FilterConfig {
+      numeric_filters: vec![rule("score", Some(2.0), 6.0), rule("turns", None, 3.0)],
+      pii: PiiFilter {
+        drop_emails: true,
+        drop_phone_numbers: true,
+        ..PiiFilter::default()
+      },
+      ..FilterConfig::default()
+    };
+    let (ids, summary) = apply_filters_inner(
+      &store,
+      None,
+      &filters,
+      &text_field_map(),
+      &CategoryRules::default(),
+      &AtomicBool::new(false),
+      |_, _| {},
+    )
+    .unwrap();
+    assert_eq!(ids.iter().collect::<Vec<_>>(), vec![4]);
+    assert_eq!(summary.numeric_rejected, vec![1, 1]);
+    let pii = [(PII_EMAIL, 1), (PII_PHONE, 1)].map(|(kind, count)| (kind.to_string(), count));
+    assert_eq!(summary.pii_rejected, BTreeMap::from(pii));
+  }
+
+  /// Ids of the records a deduper over `filters` keeps, in order.
+  fn kept_ids(filters: &FilterConfig, records: &[(&str, &str)]) -> Vec<usize> {
+    let mut deduper = Deduper::new(filters);
+    (0..records.len())
+      .filter(|idx| {
+        let (instruction, output) = records[*idx];
+        deduper.check(*idx, instruction, output, 0.0).is_none()
+      })
+      .collect()
+  }
+
+  #[test]
+  fn a_larger_fuzzy_threshold_collapses_more_paraphrases() {
+    let records = [
+      TEXT,
+      "explain how the borrow checker keeps references valid when a value is moved between \
+       functions in a small rust program",
+      "describe how the borrow checker keeps references valid while a value is moved between \
+       functions in a small rust program",
+      "please explain how the borrow checker keeps references valid while a value is moved \
+       between functions in a small rust program",
+      "describe how rust's borrow checker keeps references valid when a value moves between \
+       functions in a small program",
+    ]
+    .map(|instruction| (instruction, ""));
+    let fuzzy = |threshold| FilterConfig {
+      dedupe_fuzzy: true,
+      fuzzy_threshold: Some(threshold),
+      ..FilterConfig::default()
+    };
+    assert_eq!(kept_ids(&fuzzy(3), &records), vec![0, 1, 2, 3, 4]);
+    assert_eq!(kept_ids(&fuzzy(8), &records), vec![0, 4]);
+  }
+
+  #[test]
+  fn output_scope_dedupes_independently_of_the_instruction() {
+    let records = [
+      ("what is two plus two", "four"),
+      ("add two and two", "four"),
+      ("what is two plus two", "it is four"),
+    ];
+    let scoped = |mode: &str| FilterConfig {
+      dedupe_mode: mode.to_string(),
+      ..FilterConfig::default()
+    };
+    assert_eq!(kept_ids(&scoped("instruction"), &records), vec![0, 1]);
+    assert_eq!(kept_ids(&scoped("output"), &records), vec![0, 2]);
+    assert_eq!(kept_ids(&scoped("combined"), &records), vec![0, 1, 2]);
+  }
+
+  #[test]
+  fn dedupe_scope_is_read_as_the_dedupe_mode() {
+    let filters: FilterConfig = serde_json::from_value(json!({ "dedupeScope": "output" })).unwrap();
+    assert_eq!(filters.dedupe_mode, "output");
+  }
+
+  #[test]
+  fn three_word_shingles_keep_reordered_paraphrases_apart() {
+    let text = "the quick brown fox jumps over the lazy dog near the river bank while the farmer \
+                watches from his old wooden porch and the children play games in the tall green \
+                grass behind the red barn on a warm summer afternoon";
+    let reordered = "on a warm summer afternoon the children play games in the tall green grass \
+                     behind the red barn while the farmer watches from his old wooden porch and \
+                     the quick brown fox jumps over the lazy dog near the river bank";
+    let near = format!("{text} today");
+    let records = [text, reordered, &near].map(|instruction| (instruction, ""));
+    let shingled = |shingle_size| FilterConfig {
+      dedupe_fuzzy: true,
+      fuzzy_threshold: Some(8),
+      shingle_size,
+      ..FilterConfig::default()
+    };
+    assert_eq!(kept_ids(&shingled(1), &records), vec![0]);
+    assert_eq!(kept_ids(&shingled(3), &records), vec![0, 1]);
+  }
+
+  #[test]
+  fn excluded_categories_win_and_never_drop_uncategorized_records() {
+    let dir = TempDir::new();
+    let records = [
+      json!({ "instruction": "a", "output": "x", "topic": "Math" }),
+      json!({ "instruction": "b", "output": "x", "topic": "toxic" }),
+      json!({ "instruction": "c", "output": "x", "topic": "chat" }),
+      json!({ "instruction": "d", "output": "x" }),
+      json!({ "instruction": "e", "output": "x", "topic": "" }),
+    ];
+    let store = jsonl_store_with(&dir, &records, &ImportOptions::default());
+    let kept = |categories: &[&str], exclude: &[&str]| {
+      let filters = FilterConfig {
+        category_field: Some("topic".to_string()),
+        categories: categories.iter().map(|name| name.to_string()).collect(),
+        exclude_categories: exclude.iter().map(|name| name.to_string()).collect(),
+        ..FilterConfig::default()
+      };
+      let (ids, summary) = apply_filters_inner(
+        &store,
+        None,
+        &filters,
+        &text_field_map(),
+        &CategoryRules::default(),
+        &AtomicBool::new(false),
+        |_, _| {},
+      )
+      .unwrap();
+      (ids.to_vec(), summary.rejected.get("category").copied().unwrap_or(0))
+    };
+    assert_eq!(kept(&[], &["TOXIC"]), (vec![0, 2, 3, 4], 1));
+    assert_eq!(kept(&["math", "toxic"], &["toxic"]), (vec![0], 4));
+    assert_eq!(kept(&["math"], &[]), (vec![0], 4));
+  }
+
+  #[test]
+  fn each_dedupe_mode_covers_its_quadrants() {
+    let base = ("what is two plus two", "four");
+    let quadrants = [
+      ("same instruction, same output", base),
+      ("same instruction, new output", ("what is two plus two", "it is four")),
+      ("new instruction, same output", ("add two and two", "four")),
+      ("new instruction, new output", ("add two and three", "five")),
+    ];
+    let expected = [
+      ("instruction", [true, true, false, false]),
+      ("output", [true, false, true, false]),
+      ("both", [true, false, false, false]),
+      ("either", [true, true, true, false]),
+    ];
+    for (mode, duplicates) in expected {
+      let filters = FilterConfig {
+        dedupe_mode: mode.to_string(),
+        ..FilterConfig::default()
+      };
+      for ((quadrant, candidate), duplicate) in quadrants.iter().zip(duplicates) {
+        let kept = kept_ids(&filters, &[base, *candidate]);
+        assert_eq!(kept.len() == 1, duplicate, "{mode}: {quadrant}");
+      }
+    }
+  }
+
+  #[test]
+  fn joint_fuzzy_dedupe_needs_both_sides_near() {
+    let output = "the borrow checker tracks which references are alive and rejects any use of a \
+                  value after it has been moved into another function or variable";
+    let near_instruction = format!("{TEXT} today");
+    let near_output = format!("{output} too");
+    let filters = FilterConfig {
+      dedupe_fuzzy: true,
+      fuzzy_threshold: Some(16),
+      ..keep_best("both")
+    };
+    let records = [
+      (TEXT, output),
+      (near_instruction.as_str(), near_output.as_str()),
+      (near_instruction.as_str(), "a different answer about lifetimes and scopes entirely"),
+      ("how do i print a vector in rust", near_output.as_str()),
+    ];
+    assert_eq!(kept_ids(&filters, &records), vec![0, 2, 3]);
+  }
+
+  #[test]
+  fn minhash_leaves_texts_without_words_to_the_exact_check() {
+    let near = format!("{TEXT} today");
+    let records = [
+      ("???", "a"),
+      ("...", "b"),
+      ("🎉🎉", "c"),
+      ("???", "d"),
+      (TEXT, "e"),
+      (near.as_str(), "f"),
+    ];
+    for mode in ["instruction", "both"] {
+      let filters = FilterConfig {
+        dedupe_fuzzy: true,
+        dedupe_method: "minhash".to_string(),
+        dedupe_mode: mode.to_string(),
+        ..FilterConfig::default()
+      };
+      let kept = kept_ids(&filters, &records);
+      let expected = if mode == "both" { vec![0, 1, 2, 3, 4, 5] } else { vec![0, 1, 2, 4] };
+      assert_eq!(kept, expected, "{mode}");
+    }
+  }
+
+  #[test]
+  fn filter_summaries_time_scan_predicates_and_dedupe() {
+    let dir = TempDir::new();
+    let records = (0..3000)
+      .map(|id| json!({ "instruction": format!("q{}", id % 2000), "output": "a" }))
+      .collect::<Vec<_>>();
+    let store = jsonl_store_with(&dir, &records, &ImportOptions::default());
+    let (ids, summary) = apply_filters_inner(
+      &store,
+      None,
+      &FilterConfig::default(),
+      &text_field_map(),
+      &CategoryRules::default(),
+      &AtomicBool::new(false),
+      |_, _| {},
+    )
+    .unwrap();
+    assert_eq!(ids.len(), 2000);
+    let stages = ["scan", "predicates", "dedupe"];
+    assert_stages_timed(&summary.timings, &stages, &stages);
+  }
+
+  #[test]
+  fn a_slow_category_scan_does_not_hold_the_state_lock() {
+    let dir = TempDir::new();
+    let records = (0..5000)
+      .map(|id| json!({ "instruction": format!("q{id}"), "topic": format!("t{}", id % 3) }))
+      .collect::<Vec<_>>();
+    let state = RwLock::new(InnerState {
+      dataset: Some(jsonl_store_with(&dir, &records, &ImportOptions::default())),
+      field_map: text_field_map(),
+      ..InnerState::default()
+    });
+    let (started, scanning) = mpsc::channel();
+    thread::scope(|scope| {
+      let scan = scope.spawn(|| {
+        let cancel = AtomicBool::new(false);
+        list_active_categories(&state, Some("topic"), 100, &cancel, |current, _| {
+          // Every progress step stalls the scan, as a slow disk would.
+          let _ = started.send(current);
+          thread::sleep(Duration::from_millis(100));
+        })
+      });
+      scanning.recv().unwrap();
+      let start = Instant::now();
+      let page = PreviewPageSource::locate(&state.read().unwrap(), "all", 2, 20).unwrap();
+      let (items, _) = page.read(usize::MAX).unwrap();
+      state.write().unwrap().filtered_ids = Some(IdSet::from_iter([0]));
+      let waited = start.elapsed();
+      assert!(!scan.is_finished());
+      assert!(waited < Duration::from_millis(250), "waited {waited:?}");
+      assert_eq!(items.first().map(|item| item.id), Some(20));
+
+      let list = scan.join().unwrap().unwrap();
+      assert_eq!(list.scanned_count, 5000);
+      assert_eq!(list.distinct_count, 3);
+    });
+  }
 }
@@ -0,0 +1,741 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::models::PushSummary;
+
+pub const HUB_ENDPOINT: &str = "https://huggingface.co";
+
+/// Formats `ingest_dataset` can read, in order of preference.
+const SUPPORTED_EXTENSIONS: &[&str] = &["jsonl", "json", "csv"];
+
+/// Characters escaped inside a single URL path segment.
+const SEGMENT: &AsciiSet = &CONTROLS
+  .add(b' ')
+  .add(b'"')
+  .add(b'#')
+  .add(b'%')
+  .add(b'<')
+  .add(b'>')
+  .add(b'?')
+  .add(b'`')
+  .add(b'{')
+  .add(b'}');
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HubFile {
+  #[serde(rename = "type")]
+  pub kind: String,
+  pub path: String,
+  #[serde(default)]
+  pub size: Option<u64>,
+}
+
+fn encode_path(path: &str) -> String {
+  path
+    .split('/')
+    .map(|segment| utf8_percent_encode(segment, SEGMENT).to_string())
+    .collect::<Vec<_>>()
+    .join("/")
+}
+
+fn agent() -> ureq::Agent {
+  ureq::AgentBuilder::new()
+    .timeout_connect(Duration::from_secs(15))
+    .timeout_read(Duration::from_secs(60))
+    .build()
+}
+
+fn hub_get(url: &str, token: Option<&str>) -> ureq::Request {
+  hub_request("GET", url, token)
+}
+
+fn hub_request(method: &str, url: &str, token: Option<&str>) -> ureq::Request {
+  let request = agent().request(method, url);
+  match token.filter(|token| !token.is_empty()) {
+    Some(token) => request.set("Authorization", &format!("Bearer {token}")),
+    None => request,
+  }
+}
+
+/// Error of a Hub command, as the UI receives it. The kind tells a private or
+/// gated repository, which an access token may open, apart from a missing one
+/// or a failed connection.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HubError {
+  /// `unauthorized` for a private repository or a missing token, `gated`,
+  /// `notFound`, `network` when the Hub could not be reached, `status` for
+  /// other HTTP failures and `failed` for anything else.
+  pub kind: &'static str,
+  pub message: String,
+  /// HTTP status of the failed request.
+  pub status: Option<u16>,
+}
+
+impl HubError {
+  fn new(kind: &'static str, message: String) -> Self {
+    Self {
+      kind,
+      message,
+      status: None,
+    }
+  }
+
+  fn network(reason: impl fmt::Display) -> Self {
+    Self::new("network", format!("Network error reaching the Hub: {reason}"))
+  }
+}
+
+impl From<String> for HubError {
+  fn from(message: String) -> Self {
+    Self::new("failed", message)
+  }
+}
+
+impl fmt::Display for HubError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.message)
+  }
+}
+
+/// Turns an HTTP failure into an error naming the likely cause. Never includes the token.
+fn hub_error(err: ureq::Error, repo_id: &str) -> HubError {
+  let (kind, code, message) = match err {
+    ureq::Error::Status(401, _) => (
+      "unauthorized",
+      401,
+      format!(
+        "Hub repository {repo_id} was not found or is private; check the id or set an access token"
+      ),
+    ),
+    ureq::Error::Status(403, _) => (
+      "gated",
+      403,
+      format!(
+        "Hub repository {repo_id} is gated; accept its terms on the Hub and use an access token"
+      ),
+    ),
+    ureq::Error::Status(404, _) => (
+      "notFound",
+      404,
+      format!("Hub repository or file not found: {repo_id}"),
+    ),
+    ureq::Error::Status(code, response) => (
+      "status",
+      code,
+      format!("Hub request failed with status {code}: {}", response.status_text()),
+    ),
+    ureq::Error::Transport(transport) => return HubError::network(transport),
+  };
+  HubError {
+    status: Some(code),
+    ..HubError::new(kind, message)
+  }
+}
+
+fn validate_repo_id(repo_id: &str) -> Result<(), String> {
+  let parts = repo_id.split('/').collect::<Vec<_>>();
+  let valid_part = |part: &&str| {
+    !part.is_empty()
+      && *part != "."
+      && *part != ".."
+      && part
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+  };
+  if parts.is_empty() || parts.len() > 2 || !parts.iter().all(valid_part) {
+    return Err(format!("Invalid Hub repository id: {repo_id}"));
+  }
+  Ok(())
+}
+
+/// All files of a dataset repository on its main branch.
+pub fn list_repo_files(repo_id: &str, token: Option<&str>) -> Result<Vec<HubFile>, HubError> {
+  validate_repo_id(repo_id)?;
+  let mut url = Some(format!(
+    "{HUB_ENDPOINT}/api/datasets/{repo_id}/tree/main?recursive=true"
+  ));
+  let mut files = Vec::new();
+  while let Some(page_url) = url.take() {
+    let response = hub_get(&page_url, token)
+      .call()
+      .map_err(|err| hub_error(err, repo_id))?;
+    url = response.header("Link").and_then(next_page_url);
+    let entries: Vec<HubFile> = response
+      .into_json()
+      .map_err(|e| format!("Unexpected Hub response: {e}"))?;
+    files.extend(entries.into_iter().filter(|entry| entry.kind == "file"));
+  }
+  Ok(files)
+}
+
+/// The `rel="next"` target of a `Link` header, used by the Hub to paginate listings.
+fn next_page_url(link: &str) -> Option<String> {
+  link.split(',').find_map(|part| {
+    let (target, params) = part.split_once(';')?;
+    params
+      .contains("rel=\"next\"")
+      .then(|| target.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+  })
+}
+
+fn extension_rank(path: &str) -> Option<usize> {
+  let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+  SUPPORTED_EXTENSIONS
+    .iter()
+    .position(|supported| *supported == extension)
+}
+
+/// Picks the file to import: an exact path match, otherwise the preferred
+/// supported file whose path mentions the split (default `train`).
+pub fn pick_hub_file(files: &[HubFile], filename_or_split: Option<&str>) -> Result<HubFile, String> {
+  let wanted = filename_or_split.map(str::trim).filter(|value| !value.is_empty());
+  if let Some(exact) = wanted.and_then(|name| files.iter().find(|file| file.path == name)) {
+    if extension_rank(&exact.path).is_none() {
+      return Err(format!("Unsupported file type: {}", exact.path));
+    }
+    return Ok(exact.clone());
+  }
+
+  let split = wanted.unwrap_or("train").to_lowercase();
+  let mentions_split = |file: &&HubFile| file.path.to_lowercase().contains(&split);
+  let best = |candidates: Vec<&HubFile>| {
+    candidates
+      .into_iter()
+      .filter_map(|file| extension_rank(&file.path).map(|rank| (rank, file)))
+      .min_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.path.cmp(&b.1.path)))
+      .map(|(_, file)| file.clone())
+  };
+  let in_split = files.iter().filter(mentions_split).collect::<Vec<_>>();
+  if let Some(file) = best(in_split) {
+    return Ok(file);
+  }
+  if wanted.is_none() {
+    if let Some(file) = best(files.iter().collect()) {
+      return Ok(file);
+    }
+  }
+  let parquet_only = files
+    .iter()
+    .filter(|file| wanted.is_none() || mentions_split(file))
+    .any(|file| file.path.ends_with(".parquet"));
+  if parquet_only {
+    return Err("Only parquet files match; parquet import is not supported yet".to_string());
+  }
+  Err(format!("No jsonl, json or csv file matches \"{split}\""))
+}
+
+/// Downloads `file` from the repository into `dest_dir`, keeping its extension.
+/// The partial file is removed on error or cancel.
+pub fn download_hub_file(
+  repo_id: &str,
+  file: &HubFile,
+  token: Option<&str>,
+  dest_dir: &Path,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(u64, u64),
+) -> Result<PathBuf, HubError> {
+  validate_repo_id(repo_id)?;
+  fs::create_dir_all(dest_dir).map_err(|e| e.to_string())?;
+  let name = Path::new(&file.path)
+    .file_name()
+    .and_then(|name| name.to_str())
+    .unwrap_or("download");
+  let dest = dest_dir.join(format!("{}-{name}", Uuid::new_v4()));
+  let url = format!(
+    "{HUB_ENDPOINT}/datasets/{repo_id}/resolve/main/{}",
+    encode_path(&file.path)
+  );
+
+  let result = (|| -> Result<(), HubError> {
+    let response = hub_get(&url, token)
+      .call()
+      .map_err(|err| hub_error(err, repo_id))?;
+    let total = response
+      .header("Content-Length")
+      .and_then(|value| value.parse::<u64>().ok())
+      .or(file.size)
+      .unwrap_or(0);
+    let mut reader = response.into_reader();
+    let mut writer = BufWriter::new(File::create(&dest).map_err(|e| e.to_string())?);
+    let mut buffer = vec![0u8; 256 * 1024];
+    let mut downloaded = 0u64;
+    let mut last_reported = 0u64;
+    loop {
+      if cancel.load(Ordering::SeqCst) {
+        return Err(HubError::from("Download canceled".to_string()));
+      }
+      let read = reader.read(&mut buffer).map_err(HubError::network)?;
+      if read == 0 {
+        break;
+      }
+      writer.write_all(&buffer[..read]).map_err(|e| e.to_string())?;
+      downloaded += read as u64;
+      if downloaded - last_reported >= 4 * 1024 * 1024 {
+        last_reported = downloaded;
+        on_progress(downloaded, total);
+      }
+    }
+    writer.flush().map_err(|e| e.to_string())?;
+    on_progress(downloaded, total);
+    Ok(())
+  })();
+  if let Err(err) = result {
+    let _ = fs::remove_file(&dest);
+    return Err(err);
+  }
+  Ok(dest)
+}
+
+/// Files at least this large are always offered to LFS.
+const LFS_THRESHOLD: u64 = 10 * 1024 * 1024;
+const UPLOAD_ATTEMPTS: u32 = 3;
+
+#[derive(Debug, Clone)]
+pub struct PushSpec {
+  pub repo_id: String,
+  pub private: bool,
+  pub commit_message: String,
+  /// Local folder whose files are uploaded, keeping their relative paths.
+  pub folder: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct UploadProgress<'a> {
+  pub file: &'a str,
+  pub file_index: usize,
+  pub file_count: usize,
+  pub bytes_sent: u64,
+  pub bytes_total: u64,
+}
+
+struct LocalUpload {
+  path: PathBuf,
+  repo_path: String,
+  size: u64,
+  sha256: String,
+  sample: String,
+  lfs: bool,
+}
+
+fn is_transient(err: &ureq::Error) -> bool {
+  match err {
+    ureq::Error::Status(code, _) => *code == 429 || *code >= 500,
+    ureq::Error::Transport(_) => true,
+  }
+}
+
+/// Sends the request again with backoff while it fails transiently.
+fn with_retries(
+  mut send: impl FnMut() -> Result<ureq::Response, Box<ureq::Error>>,
+) -> Result<ureq::Response, Box<ureq::Error>> {
+  let mut attempt = 1;
+  loop {
+    match send() {
+      Err(err) if attempt < UPLOAD_ATTEMPTS && is_transient(&err) => {
+        std::thread::sleep(Duration::from_secs(1 << attempt));
+        attempt += 1;
+      }
+      result => return result,
+    }
+  }
+}
+
+fn collect_upload_files(folder: &Path, dir: &Path, out: &mut Vec<(PathBuf, String)>) -> Result<(), String> {
+  for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+    let entry = entry.map_err(|e| e.to_string())?;
+    let path = entry.path();
+    if entry.file_name().to_string_lossy().starts_with('.') {
+      continue;
+    }
+    if path.is_dir() {
+      collect_upload_files(folder, &path, out)?;
+    } else if let Ok(relative) = path.strip_prefix(folder) {
+      let repo_path = relative
+        .components()
+        .map(|part| part.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join("/");
+      out.push((path, repo_path));
+    }
+  }
+  Ok(())
+}
+
+fn describe_file(path: PathBuf, repo_path: String) -> Result<LocalUpload, String> {
+  let mut file = File::open(&path).map_err(|e| e.to_string())?;
+  let mut hasher = Sha256::new();
+  let mut buffer = vec![0u8; 1024 * 1024];
+  let mut sample = Vec::new();
+  let mut size = 0u64;
+  loop {
+    let read = file.read(&mut buffer).map_err(|e| e.to_string())?;
+    if read == 0 {
+      break;
+    }
+    if sample.len() < 512 {
+      let take = (512 - sample.len()).min(read);
+      sample.extend_from_slice(&buffer[..take]);
+    }
+    hasher.update(&buffer[..read]);
+    size += read as u64;
+  }
+  Ok(LocalUpload {
+    path,
+    repo_path,
+    size,
+    sha256: format!("{:x}", hasher.finalize()),
+    sample: BASE64.encode(&sample),
+    lfs: size >= LFS_THRESHOLD,
+  })
+}
+
+fn create_repo(spec: &PushSpec, token: &str) -> Result<(), HubError> {
+  let (organization, name) = spec
+    .repo_id
+    .split_once('/')
+    .ok_or_else(|| format!("Hub repository id must be namespace/name: {}", spec.repo_id))?;
+  let body = json!({
+    "type": "dataset",
+    "name": name,
+    "organization": organization,
+    "private": spec.private,
+  });
+  let url = format!("{HUB_ENDPOINT}/api/repos/create");
+  let created = with_retries(|| {
+    hub_request("POST", &url, Some(token))
+      .send_json(body.clone())
+      .map_err(Box::new)
+  });
+  match created.map_err(|err| *err) {
+    Ok(_) | Err(ureq::Error::Status(409, _)) => Ok(()),
+    Err(err) => Err(hub_error(err, &spec.repo_id)),
+  }
+}
+
+/// Asks the Hub which files must go through LFS.
+fn mark_lfs_files(repo_id: &str, files: &mut [LocalUpload], token: &str) -> Result<(), HubError> {
+  let body = json!({
+    "files": files
+      .iter()
+      .map(|file| json!({ "path": file.repo_path, "size": file.size, "sample": file.sample }))
+      .collect::<Vec<_>>(),
+  });
+  let url = format!("{HUB_ENDPOINT}/api/datasets/{repo_id}/preupload/main");
+  let response: Value = with_retries(|| {
+    hub_request("POST", &url, Some(token))
+      .send_json(body.clone())
+      .map_err(Box::new)
+  })
+  .map_err(|err| hub_error(*err, repo_id))?
+    .into_json()
+    .map_err(|e| format!("Unexpected Hub response: {e}"))?;
+  let modes = response["files"].as_array().cloned().unwrap_or_default();
+  for file in files.iter_mut() {
+    let mode = modes
+      .iter()
+      .find(|entry| entry["path"].as_str() == Some(file.repo_path.as_str()))
+      .and_then(|entry| entry["uploadMode"].as_str());
+    if let Some(mode) = mode {
+      file.lfs = mode == "lfs";
+    }
+  }
+  Ok(())
+}
+
+fn apply_headers(mut request: ureq::Request, headers: &Value) -> ureq::Request {
+  if let Some(headers) = headers.as_object() {
+    for (name, value) in headers {
+      if let Some(value) = value.as_str() {
+        request = request.set(name, value);
+      }
+    }
+  }
+  request
+}
+
+fn read_chunk(path: &Path, offset: u64, len: u64) -> Result<Vec<u8>, String> {
+  let mut file = File::open(path).map_err(|e| e.to_string())?;
+  file
+    .seek(SeekFrom::Start(offset))
+    .map_err(|e| e.to_string())?;
+  let mut chunk = Vec::with_capacity(len as usize);
+  file
+    .take(len)
+    .read_to_end(&mut chunk)
+    .map_err(|e| e.to_string())?;
+  Ok(chunk)
+}
+
+/// Uploads one file's content to LFS storage, in parts when the Hub asks for
+/// multipart transfer. Objects the Hub already has are skipped.
+fn upload_lfs(
+  repo_id: &str,
+  file: &LocalUpload,
+  token: &str,
+  cancel: &AtomicBool,
+  mut on_bytes: impl FnMut(u64),
+) -> Result<(), HubError> {
+  let batch_url = format!("{HUB_ENDPOINT}/datasets/{repo_id}.git/info/lfs/objects/batch");
+  let body = json!({
+    "operation": "upload",
+    "transfers": ["basic", "multipart"],
+    "hash_algo": "sha256",
+    "objects": [{ "oid": file.sha256, "size": file.size }],
+  });
+  let batch: Value = with_retries(|| {
+    hub_request("POST", &batch_url, Some(token))
+      .set("Accept", "application/vnd.git-lfs+json")
+      .set("Content-Type", "application/vnd.git-lfs+json")
+      .send_string(&body.to_string())
+      .map_err(Box::new)
+  })
+  .map_err(|err| hub_error(*err, repo_id))?
+  .into_json()
+  .map_err(|e| format!("Unexpected Hub response: {e}"))?;
+
+  let object = &batch["objects"][0];
+  if let Some(message) = object["error"]["message"].as_str() {
+    return Err(format!("LFS upload of {} rejected: {message}", file.repo_path).into());
+  }
+  let upload = &object["actions"]["upload"];
+  let Some(upload_url) = upload["href"].as_str() else {
+    on_bytes(file.size);
+    return Ok(());
+  };
+
+  let chunk_size = upload["header"]["chunk_size"]
+    .as_str()
+    .and_then(|value| value.parse::<u64>().ok());
+  if let Some(chunk_size) = chunk_size {
+    let mut part_urls = upload["header"]
+      .as_object()
+      .map(|header| {
+        header
+          .iter()
+          .filter_map(|(key, value)| Some((key.parse::<u64>().ok()?, value.as_str()?.to_string())))
+          .collect::<Vec<_>>()
+      })
+      .unwrap_or_default();
+    part_urls.sort_by_key(|(number, _)| *number);
+    let mut parts = Vec::with_capacity(part_urls.len());
+    for (number, part_url) in part_urls {
+      if cancel.load(Ordering::SeqCst) {
+        return Err(HubError::from("Upload canceled".to_string()));
+      }
+      let offset = (number - 1) * chunk_size;
+      let chunk = read_chunk(&file.path, offset, chunk_size)?;
+      let response = with_retries(|| agent().put(&part_url).send_bytes(&chunk).map_err(Box::new))
+        .map_err(|err| hub_error(*err, repo_id))?;
+      let etag = response.header("ETag").unwrap_or_default().to_string();
+      parts.push(json!({ "partNumber": number, "etag": etag }));
+      on_bytes((offset + chunk.len() as u64).min(file.size));
+    }
+    let complete = json!({ "oid": file.sha256, "parts": parts });
+    with_retries(|| agent().post(upload_url).send_json(complete.clone()).map_err(Box::new))
+      .map_err(|err| hub_error(*err, repo_id))?;
+  } else {
+    with_retries(|| {
+      let reader = File::open(&file.path).map_err(|e| Box::new(ureq::Error::from(e)))?;
+      apply_headers(agent().put(upload_url), &upload["header"])
+        .send(reader)
+        .map_err(Box::new)
+    })
+    .map_err(|err| hub_error(*err, repo_id))?;
+    on_bytes(file.size);
+  }
+
+  if let Some(verify_url) = object["actions"]["verify"]["href"].as_str() {
+    let verify = json!({ "oid": file.sha256, "size": file.size });
+    with_retries(|| {
+      apply_headers(
+        hub_request("POST", verify_url, Some(token)),
+        &object["actions"]["verify"]["header"],
+      )
+      .send_json(verify.clone())
+      .map_err(Box::new)
+    })
+    .map_err(|err| hub_error(*err, repo_id))?;
+  }
+  Ok(())
+}
+
+fn commit_files(
+  spec: &PushSpec,
+  files: &[LocalUpload],
+  token: &str,
+) -> Result<Option<String>, HubError> {
+  let mut body = json!({
+    "key": "header",
+    "value": { "summary": spec.commit_message, "description": "" },
+  })
+  .to_string();
+  for file in files {
+    let line = if file.lfs {
+      json!({
+        "key": "lfsFile",
+        "value": { "path": file.repo_path, "algo": "sha256", "oid": file.sha256, "size": file.size },
+      })
+    } else {
+      let content = fs::read(&file.path).map_err(|e| e.to_string())?;
+      json!({
+        "key": "file",
+        "value": { "path": file.repo_path, "encoding": "base64", "content": BASE64.encode(content) },
+      })
+    };
+    body.push('\n');
+    body.push_str(&line.to_string());
+  }
+  let url = format!("{HUB_ENDPOINT}/api/datasets/{}/commit/main", spec.repo_id);
+  let response: Value = with_retries(|| {
+    hub_request("POST", &url, Some(token))
+      .set("Content-Type", "application/x-ndjson")
+      .send_string(&body)
+      .map_err(Box::new)
+  })
+  .map_err(|err| hub_error(*err, &spec.repo_id))?
+  .into_json()
+  .map_err(|e| format!("Unexpected Hub response: {e}"))?;
+  Ok(response["commitUrl"].as_str().map(str::to_string))
+}
+
+/// Creates the dataset repository if needed, uploads large files to LFS one by
+/// one, then commits every file of `spec.folder` in a single commit, which
+/// carries the content of the small ones. Errors name the LFS files whose
+/// content was already uploaded; rerunning skips them.
+pub fn push_to_hub(
+  spec: &PushSpec,
+  token: &str,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(&UploadProgress),
+) -> Result<PushSummary, HubError> {
+  validate_repo_id(&spec.repo_id)?;
+  if token.trim().is_empty() {
+    let message = "Pushing to the Hub requires an access token".to_string();
+    return Err(HubError::new("unauthorized", message));
+  }
+  let mut found = Vec::new();
+  collect_upload_files(&spec.folder, &spec.folder, &mut found)?;
+  found.sort_by(|a, b| a.1.cmp(&b.1));
+  if found.is_empty() {
+    return Err(format!("No files to upload in {}", spec.folder.display()).into());
+  }
+  let mut files = found
+    .into_iter()
+    .map(|(path, repo_path)| describe_file(path, repo_path))
+    .collect::<Result<Vec<_>, _>>()?;
+
+  create_repo(spec, token)?;
+  mark_lfs_files(&spec.repo_id, &mut files, token)?;
+  // LFS files are sent before the commit and the others inside it, so
+  // progress goes through them in that order.
+  files.sort_by_key(|file| !file.lfs);
+  let (lfs_files, inline_files) = files.split_at(files.iter().filter(|file| file.lfs).count());
+
+  let file_count = files.len();
+  let mut progress = |file_index: usize, file: &LocalUpload, bytes_sent: u64| {
+    on_progress(&UploadProgress {
+      file: &file.repo_path,
+      file_index,
+      file_count,
+      bytes_sent,
+      bytes_total: file.size,
+    })
+  };
+  let mut uploaded: Vec<String> = Vec::new();
+  for (file_index, file) in lfs_files.iter().enumerate() {
+    if cancel.load(Ordering::SeqCst) {
+      return Err(partial_upload_error(upload_canceled(), &uploaded));
+    }
+    let mut report = |bytes_sent: u64| progress(file_index, file, bytes_sent);
+    report(0);
+    upload_lfs(&spec.repo_id, file, token, cancel, &mut report)
+      .map_err(|err| partial_upload_error(err, &uploaded))?;
+    report(file.size);
+    uploaded.push(file.repo_path.clone());
+  }
+
+  if cancel.load(Ordering::SeqCst) {
+    return Err(partial_upload_error(upload_canceled(), &uploaded));
+  }
+  let commit_url = commit_files(spec, &files, token)
+    .map_err(|err| partial_upload_error(err, &uploaded))?;
+  for (offset, file) in inline_files.iter().enumerate() {
+    progress(lfs_files.len() + offset, file, file.size);
+  }
+  Ok(PushSummary {
+    repo_id: spec.repo_id.clone(),
+    commit_url,
+    uploaded_files: files.iter().map(|file| file.repo_path.clone()).collect(),
+    lfs_file_count: uploaded.len(),
+  })
+}
+
+fn upload_canceled() -> HubError {
+  HubError::from("Upload canceled".to_string())
+}
+
+/// `err` with the LFS files already uploaded appended to its message.
+fn partial_upload_error(err: HubError, uploaded: &[String]) -> HubError {
+  let message = if uploaded.is_empty() {
+    format!("{}; nothing was committed", err.message)
+  } else {
+    format!(
+      "{}; nothing was committed, already uploaded: {}",
+      err.message,
+      uploaded.join(", ")
+    )
+  };
+  HubError { message, ..err }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+
+  fn status_error(code: u16) -> HubError {
+    let response = ureq::Response::new(code, "Status", "").unwrap();
+    hub_error(ureq::Error::Status(code, response), "org/data")
+  }
+
+  #[test]
+  fn hub_failures_reach_the_ui_with_their_kind() {
+    let kinds = [401, 403, 404, 500].map(|code| (status_error(code).kind, code));
+    assert_eq!(
+      kinds,
+      [("unauthorized", 401), ("gated", 403), ("notFound", 404), ("status", 500)]
+    );
+    let gated = status_error(403);
+    assert_eq!(
+      serde_json::to_value(&gated).unwrap(),
+      json!({ "kind": "gated", "message": gated.to_string(), "status": 403 })
+    );
+
+    let refused = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused");
+    let network = hub_error(ureq::Error::from(refused), "org/data");
+    assert_eq!((network.kind, network.status), ("network", None));
+    let failed = HubError::from("Download canceled".to_string());
+    assert_eq!((failed.kind, failed.message.as_str()), ("failed", "Download canceled"));
+  }
+
+  #[test]
+  fn a_failed_push_keeps_its_kind_and_names_the_uploaded_files() {
+    let error = partial_upload_error(status_error(403), &["data.parquet".to_string()]);
+    assert_eq!(error.kind, "gated");
+    assert!(error.message.ends_with("nothing was committed, already uploaded: data.parquet"));
+  }
+}
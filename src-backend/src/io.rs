@@ -1,15 +1,317 @@
-use std::collections::HashSet;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::fs::{self, File};
-use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
-use std::path::Path;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
 
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use serde::de::Deserializer;
-use serde_json::Value;
+use serde_json::{json, Map, Value};
 use uuid::Uuid;
 
-use crate::records::value_to_string;
-use crate::state::DatasetStore;
+use crate::filters::DuplicateCluster;
+use crate::json_records::{is_json_document, stream_json_records, JSON_LAYOUT_SNIFF_BYTES};
+use crate::markdown::{markdown_files, markdown_sections};
+use crate::models::{
+  CsvOptions,
+  ExportOptions,
+  ExportSummary,
+  FieldMap,
+  ImportOptions,
+  ImportReport,
+  MalformedLine,
+  PreviewItem,
+  DEFAULT_MAX_RECORD_BYTES,
+  MAX_READ_AHEAD_CHUNKS,
+};
+use crate::paths::{
+  create_output_file,
+  io_error,
+  replace_with_temp,
+  unique_temp_path,
+  write_atomic_with,
+};
+use crate::records::{
+  build_preview_fields,
+  content_hash,
+  extract_field_value,
+  extract_text_value,
+  flatten_record,
+  oversized_preview_fields,
+  record_weight,
+  shrink_record,
+  text_length,
+  value_to_string,
+  DEFAULT_WEIGHT,
+};
+use crate::sqlite_export::write_sqlite_export;
+use crate::state::{DatasetStore, IdSet, InnerState};
+use crate::store_index::{save_store_index, store_index_path};
+use crate::timing::StageTimer;
+use crate::transform::TruncateSpec;
+
+/// Number of oversized record sizes kept in the import report.
+const OVERSIZED_SAMPLE_LIMIT: usize = 20;
+/// Size of the raw chunks the import reader thread hands to the parser.
+const READ_CHUNK_BYTES: usize = 256 * 1024;
+/// First bytes of every gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// Bytes of a file without a known extension read to guess its format.
+const FORMAT_SNIFF_BYTES: u64 = 512;
+/// Delimiters a CSV file is sniffed for, in order of preference on a tie.
+const CSV_DELIMITERS: [u8; 4] = [b',', b'\t', b';', b'|'];
+/// Bytes of a CSV file read to find the end of its header line.
+const DELIMITER_SNIFF_BYTES: u64 = 64 * 1024;
+/// Malformed lines of a lenient import kept in its report.
+const MALFORMED_SAMPLE_LIMIT: usize = 100;
+/// Characters of a malformed line quoted in the import report.
+const MALFORMED_SNIPPET_CHARS: usize = 200;
+/// Bytes of a source without a byte order mark read to guess its encoding.
+const ENCODING_SNIFF_BYTES: u64 = 64 * 1024;
+/// Rows of a CSV file read before the types of its columns are decided.
+const TYPE_INFERENCE_ROWS: usize = 1000;
+/// Records of a verified export compared in full with the store.
+const VERIFY_SPOT_CHECKS: usize = 32;
+/// Seed picking the spot-checked records, so a rerun checks the same ones.
+const VERIFY_SEED: u64 = 7;
+/// Characters of a record quoted in a verification failure.
+const MISMATCH_QUOTE_CHARS: usize = 200;
+
+pub enum BoundedLine {
+  Line(Vec<u8>),
+  /// The line exceeded the cap; only its total length in bytes is known.
+  Oversized(u64),
+}
+
+/// Reads one `\n`-terminated line, keeping at most `max_bytes` of it in memory.
+/// Longer lines are consumed without buffering and reported as `Oversized`.
+pub fn read_line_bounded<R: BufRead>(
+  reader: &mut R,
+  max_bytes: usize,
+) -> Result<Option<BoundedLine>, String> {
+  let mut line = Vec::new();
+  let mut total = 0u64;
+  let mut oversized = false;
+  loop {
+    let available = reader.fill_buf().map_err(|e| e.to_string())?;
+    if available.is_empty() {
+      break;
+    }
+    let (chunk, done) = match available.iter().position(|byte| *byte == b'\n') {
+      Some(pos) => (&available[..pos], Some(pos + 1)),
+      None => (available, None),
+    };
+    total += chunk.len() as u64;
+    if !oversized {
+      if line.len() + chunk.len() > max_bytes {
+        oversized = true;
+        line = Vec::new();
+      } else {
+        line.extend_from_slice(chunk);
+      }
+    }
+    let consumed = done.unwrap_or(chunk.len());
+    reader.consume(consumed);
+    if done.is_some() {
+      return Ok(Some(finish_line(line, total, oversized)));
+    }
+  }
+  if total == 0 {
+    return Ok(None);
+  }
+  Ok(Some(finish_line(line, total, oversized)))
+}
+
+/// Most records a store holds; record ids are `u32` keys in an `IdSet`.
+const MAX_STORE_RECORDS: usize = u32::MAX as usize;
+
+/// Appends records to a new store file, tracking the line index as it goes.
+pub struct StoreWriter {
+  id: String,
+  path: PathBuf,
+  writer: BufWriter<File>,
+  offsets: Vec<u64>,
+  /// Content hash of every record, persisted next to the store.
+  hashes: Vec<u64>,
+  fields: HashSet<String>,
+  offset: u64,
+  max_records: usize,
+}
+
+impl StoreWriter {
+  pub fn create(store_dir: &Path) -> Result<Self, String> {
+    fs::create_dir_all(store_dir).map_err(|e| e.to_string())?;
+    let id = Uuid::new_v4().to_string();
+    let path = store_dir.join(format!("{id}.jsonl"));
+    let writer = BufWriter::new(File::create(&path).map_err(|e| e.to_string())?);
+    Ok(Self {
+      id,
+      path,
+      writer,
+      offsets: Vec::new(),
+      hashes: Vec::new(),
+      fields: HashSet::new(),
+      offset: 0,
+      max_records: MAX_STORE_RECORDS,
+    })
+  }
+
+  pub fn len(&self) -> usize {
+    self.offsets.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.offsets.is_empty()
+  }
+
+  /// Bytes written so far.
+  pub fn bytes_written(&self) -> u64 {
+    self.offset
+  }
+
+  /// Writes `line`, the already-serialized form of `record`.
+  pub fn write_serialized(&mut self, record: &Value, line: &[u8]) -> Result<(), String> {
+    if self.offsets.len() >= self.max_records {
+      return Err(format!("A dataset holds at most {} records", self.max_records));
+    }
+    if let Some(map) = record.as_object() {
+      for key in map.keys() {
+        if !self.fields.contains(key) {
+          self.fields.insert(key.clone());
+        }
+      }
+    }
+    self.offsets.push(self.offset);
+    self.hashes.push(content_hash(record));
+    self.writer.write_all(line).map_err(|e| e.to_string())?;
+    self.writer.write_all(b"\n").map_err(|e| e.to_string())?;
+    self.offset += line.len() as u64 + 1;
+    Ok(())
+  }
+
+  pub fn write_value(&mut self, record: &Value) -> Result<(), String> {
+    let line = serde_json::to_vec(record).map_err(|e| e.to_string())?;
+    self.write_serialized(record, &line)
+  }
+
+  pub fn finish(
+    mut self,
+    source_path: &Path,
+    size_bytes: u64,
+    format: &str,
+    parent_id: Option<String>,
+  ) -> Result<DatasetStore, String> {
+    self.writer.flush().map_err(|e| e.to_string())?;
+    write_content_hashes(&content_hashes_path(&self.path), &self.hashes)?;
+    let mut fields = self.fields.into_iter().collect::<Vec<_>>();
+    fields.sort();
+    Ok(DatasetStore {
+      id: self.id,
+      source_path: source_path.to_path_buf(),
+      store_path: self.path,
+      record_count: self.offsets.len(),
+      offsets: self.offsets,
+      fields,
+      size_bytes,
+      format: format.to_string(),
+      delimiter: None,
+      encoding: None,
+      parent_id,
+      truncated: false,
+      resume_offset: None,
+      max_record_bytes: DEFAULT_MAX_RECORD_BYTES,
+    })
+  }
+
+  /// Drops the partially written store file.
+  pub fn discard(self) {
+    let path = self.path.clone();
+    drop(self.writer);
+    let _ = fs::remove_file(path);
+  }
+}
+
+/// Sidecar holding one little-endian u64 content hash per record, in record order.
+/// Kept apart from the `.idx` offsets index: stores derived by transforms are
+/// written without an index, and the index is rewritten from a `DatasetStore`,
+/// which does not hold the hashes. Startup cleanup and delete find it through
+/// `SIDECAR_EXTENSIONS`.
+pub fn content_hashes_path(store_path: &Path) -> PathBuf {
+  store_path.with_extension("hashes")
+}
+
+fn write_content_hashes(path: &Path, hashes: &[u64]) -> Result<(), String> {
+  write_atomic_with(path, |writer| {
+    for hash in hashes {
+      writer
+        .write_all(&hash.to_le_bytes())
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+  })
+}
+
+/// Content hashes of every record of `store`.
+pub fn read_content_hashes(store: &DatasetStore) -> Result<Vec<u64>, String> {
+  let bytes = fs::read(content_hashes_path(&store.store_path))
+    .map_err(|e| format!("Content hashes unavailable: {e}"))?;
+  if bytes.len() != store.record_count * 8 {
+    return Err("Content hashes do not match the store".to_string());
+  }
+  Ok(
+    bytes
+      .chunks_exact(8)
+      .map(|chunk| u64::from_le_bytes(chunk.try_into().expect("8-byte chunk")))
+      .collect(),
+  )
+}
+
+/// Content hashes of the given records only, in ascending id order.
+pub fn read_content_hashes_for(store: &DatasetStore, ids: &IdSet) -> Result<Vec<u64>, String> {
+  let file = File::open(content_hashes_path(&store.store_path))
+    .map_err(|e| format!("Content hashes unavailable: {e}"))?;
+  let mut reader = BufReader::new(file);
+  let mut position = 0u64;
+  let mut hashes = Vec::with_capacity(ids.len());
+  let mut buffer = [0u8; 8];
+  for id in ids.iter() {
+    let target = id as u64 * 8;
+    reader
+      .seek_relative(target as i64 - position as i64)
+      .map_err(|e| e.to_string())?;
+    reader.read_exact(&mut buffer).map_err(|e| e.to_string())?;
+    position = target + 8;
+    hashes.push(u64::from_le_bytes(buffer));
+  }
+  Ok(hashes)
+}
+
+fn note_oversized(report: &mut ImportReport, size: u64) {
+  report.oversized_skipped += 1;
+  if report.oversized_sizes.len() < OVERSIZED_SAMPLE_LIMIT {
+    report.oversized_sizes.push(size);
+  }
+}
+
+fn finish_line(mut line: Vec<u8>, total: u64, oversized: bool) -> BoundedLine {
+  if oversized {
+    return BoundedLine::Oversized(total);
+  }
+  if line.last() == Some(&b'\r') {
+    line.pop();
+  }
+  BoundedLine::Line(line)
+}
 
 fn normalize_record(value: Value) -> Value {
   match value {
@@ -22,26 +324,155 @@ fn normalize_record(value: Value) -> Value {
   }
 }
 
-fn detect_format(path: &Path) -> Result<String, String> {
-  let ext = path
-    .extension()
-    .and_then(|s| s.to_str())
-    .unwrap_or("")
-    .to_lowercase();
-  if ext == "csv" {
-    return Ok("csv".to_string());
+/// Whether the file at `path` is gzip-compressed, going by its first bytes.
+pub fn is_gzip(path: &Path) -> Result<bool, String> {
+  let file = File::open(path).map_err(|e| io_error(path, &e))?;
+  let mut magic = Vec::with_capacity(GZIP_MAGIC.len());
+  file
+    .take(GZIP_MAGIC.len() as u64)
+    .read_to_end(&mut magic)
+    .map_err(|e| io_error(path, &e))?;
+  Ok(magic == GZIP_MAGIC)
+}
+
+/// `source`, decompressed when it is gzip. Concatenated gzip members are
+/// read as one stream.
+fn decompressed<'a>(source: impl Read + 'a, gzip: bool) -> Box<dyn Read + 'a> {
+  if gzip {
+    Box::new(MultiGzDecoder::new(source))
+  } else {
+    Box::new(source)
+  }
+}
+
+/// Text encoding of a source, and whether it starts with a byte order mark.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceEncoding {
+  pub encoding: &'static Encoding,
+  pub bom: bool,
+}
+
+impl SourceEncoding {
+  pub const UTF_8: SourceEncoding = SourceEncoding {
+    encoding: UTF_8,
+    bom: false,
+  };
+
+  /// Whether the source has to pass through `decoded` to become plain UTF-8.
+  pub fn transcodes(&self) -> bool {
+    self.bom || self.encoding != UTF_8
+  }
+
+  pub fn name(&self) -> &'static str {
+    self.encoding.name()
+  }
+}
+
+/// Encoding of `sample`, the start of a source: the one its byte order mark
+/// names, UTF-8 when the sample is valid UTF-8, otherwise a guess from its
+/// bytes. `complete` tells whether the sample is the whole source.
+fn sniff_encoding(sample: &[u8], complete: bool) -> SourceEncoding {
+  if let Some((encoding, _)) = Encoding::for_bom(sample) {
+    return SourceEncoding {
+      encoding,
+      bom: true,
+    };
+  }
+  // A sample may end partway through a character.
+  let utf8 = match std::str::from_utf8(sample) {
+    Ok(_) => true,
+    Err(err) => !complete && err.error_len().is_none(),
+  };
+  if utf8 {
+    return SourceEncoding::UTF_8;
+  }
+  let mut detector = EncodingDetector::new();
+  detector.feed(sample, complete);
+  SourceEncoding {
+    encoding: detector.guess(None, false),
+    bom: false,
+  }
+}
+
+/// Encoding of the file at `path`, decompressed if it is gzip, going by its
+/// first `ENCODING_SNIFF_BYTES`.
+pub fn detect_encoding(path: &Path) -> Result<SourceEncoding, String> {
+  let file = File::open(path).map_err(|e| io_error(path, &e))?;
+  let mut buf = Vec::new();
+  decompressed(file, is_gzip(path)?)
+    .take(ENCODING_SNIFF_BYTES)
+    .read_to_end(&mut buf)
+    .map_err(|e| io_error(path, &e))?;
+  Ok(sniff_encoding(&buf, (buf.len() as u64) < ENCODING_SNIFF_BYTES))
+}
+
+/// `source` as UTF-8 without a byte order mark, transcoded from `encoding`
+/// when it is anything else.
+fn decoded<'a>(source: impl Read + 'a, encoding: SourceEncoding) -> Box<dyn Read + 'a> {
+  if !encoding.transcodes() {
+    return Box::new(source);
+  }
+  Box::new(
+    DecodeReaderBytesBuilder::new()
+      .encoding(Some(encoding.encoding))
+      .bom_override(true)
+      .strip_bom(true)
+      .build(source),
+  )
+}
+
+/// Up to `limit` bytes from the start of the file at `path` as UTF-8,
+/// decompressed and transcoded as its records will be.
+fn source_sample(path: &Path, limit: u64) -> Result<Vec<u8>, String> {
+  let encoding = detect_encoding(path)?;
+  let file = File::open(path).map_err(|e| io_error(path, &e))?;
+  let mut buf = Vec::new();
+  decoded(decompressed(file, is_gzip(path)?), encoding)
+    .take(limit)
+    .read_to_end(&mut buf)
+    .map_err(|e| io_error(path, &e))?;
+  Ok(buf)
+}
+
+/// Extension of `path`, looking past a `.gz` suffix. Bytes rather than a
+/// string so extensions that are not valid UTF-8 still compare.
+fn source_extension(path: &Path) -> Vec<u8> {
+  let extension = |path: &Path| {
+    path
+      .extension()
+      .map(|ext| ext.as_encoded_bytes().to_vec())
+      .unwrap_or_default()
+  };
+  let ext = extension(path);
+  if ext.eq_ignore_ascii_case(b"gz") {
+    path
+      .file_stem()
+      .map(|stem| extension(Path::new(stem)))
+      .unwrap_or_default()
+  } else {
+    ext
+  }
+}
+
+/// Format of the file at `path` from its extension, looking past a `.gz`
+/// suffix, or else from its first bytes, decompressed if they are gzip.
+/// Tab-separated files are `csv`; see `csv_dialect`.
+pub fn detect_format(path: &Path) -> Result<String, String> {
+  let ext = source_extension(path);
+  for format in ["csv", "jsonl", "json"] {
+    if ext.eq_ignore_ascii_case(format.as_bytes()) {
+      return Ok(format.to_string());
+    }
   }
-  if ext == "jsonl" {
-    return Ok("jsonl".to_string());
+  if ext.eq_ignore_ascii_case(b"tsv") {
+    return Ok("csv".to_string());
   }
-  if ext == "json" {
-    return Ok("json".to_string());
+  if ext.eq_ignore_ascii_case(b"md") || ext.eq_ignore_ascii_case(b"markdown") {
+    return Ok("markdown".to_string());
   }
 
-  let mut file = File::open(path).map_err(|e| e.to_string())?;
-  let mut buf = [0u8; 512];
-  let read = file.read(&mut buf).map_err(|e| e.to_string())?;
-  let snippet = String::from_utf8_lossy(&buf[..read]);
+  let buf = source_sample(path, FORMAT_SNIFF_BYTES)?;
+  let snippet = String::from_utf8_lossy(&buf);
   if snippet.trim_start().starts_with('[') || snippet.trim_start().starts_with('{') {
     Ok("json".to_string())
   } else {
@@ -49,6 +480,152 @@ fn detect_format(path: &Path) -> Result<String, String> {
   }
 }
 
+/// How a CSV source splits into fields and records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvDialect {
+  pub delimiter: u8,
+  /// Whether the first row names the columns. Without one they are named
+  /// `column_1` onwards and the first row is data.
+  pub has_header: bool,
+  /// Read cells as numbers, booleans and nulls where their column allows;
+  /// see `infer_column`. Every cell is a string otherwise.
+  pub infer_types: bool,
+}
+
+impl Default for CsvDialect {
+  fn default() -> Self {
+    Self {
+      delimiter: b',',
+      has_header: true,
+      infer_types: false,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColumnType {
+  Number,
+  Bool,
+  Text,
+}
+
+/// Whether `cell` is a plain decimal number. Leading zeros, signs other than
+/// `-` and spelled-out values such as `inf` are rejected, so identifiers like
+/// `007` stay text.
+fn is_clean_number(cell: &str) -> bool {
+  let digits = cell.strip_prefix('-').unwrap_or(cell);
+  let leading_zero = digits.len() > 1
+    && digits.starts_with('0')
+    && !digits[1..].starts_with(['.', 'e', 'E']);
+  !leading_zero
+    && digits.starts_with(|c: char| c.is_ascii_digit())
+    && cell.parse::<f64>().is_ok_and(f64::is_finite)
+}
+
+fn is_bool(cell: &str) -> bool {
+  cell.eq_ignore_ascii_case("true") || cell.eq_ignore_ascii_case("false")
+}
+
+/// Type of a column from its cells in the inference window: a number or
+/// boolean column only when every non-empty cell is one, so no column mixes
+/// types within the window.
+fn infer_column<'a>(cells: impl Iterator<Item = &'a str>) -> ColumnType {
+  let mut kind = None;
+  for cell in cells.map(str::trim).filter(|cell| !cell.is_empty()) {
+    let cell_kind = if is_clean_number(cell) {
+      ColumnType::Number
+    } else if is_bool(cell) {
+      ColumnType::Bool
+    } else {
+      return ColumnType::Text;
+    };
+    if kind.is_some_and(|kind| kind != cell_kind) {
+      return ColumnType::Text;
+    }
+    kind = Some(cell_kind);
+  }
+  kind.unwrap_or(ColumnType::Text)
+}
+
+/// `cell` as a value of its column's type; empty cells are null. A cell past
+/// the inference window that does not fit its column stays a string.
+fn typed_cell(cell: &str, kind: ColumnType) -> Value {
+  let trimmed = cell.trim();
+  if trimmed.is_empty() {
+    return Value::Null;
+  }
+  match kind {
+    ColumnType::Number if is_clean_number(trimmed) => match trimmed.parse::<i64>() {
+      Ok(int) => Value::from(int),
+      Err(_) => trimmed
+        .parse::<f64>()
+        .map_or_else(|_| Value::String(cell.to_string()), Value::from),
+    },
+    ColumnType::Bool if is_bool(trimmed) => Value::Bool(trimmed.eq_ignore_ascii_case("true")),
+    _ => Value::String(cell.to_string()),
+  }
+}
+
+/// Dialect of the CSV file at `path`. The delimiter is a tab for `.tsv`
+/// files, otherwise whichever of `CSV_DELIMITERS` occurs most often outside
+/// quotes in the first line, so semicolon and pipe files need no setting;
+/// comma when none occurs. Unless `has_header` is given, the first row is
+/// taken for data when some column is numeric in both it and the second row,
+/// as column names rarely are numbers.
+pub fn csv_dialect(path: &Path, has_header: Option<bool>) -> Result<CsvDialect, String> {
+  let buf = source_sample(path, DELIMITER_SNIFF_BYTES)?;
+  let delimiter = if source_extension(path).eq_ignore_ascii_case(b"tsv") {
+    b'\t'
+  } else {
+    sniff_delimiter(&buf)
+  };
+  Ok(CsvDialect {
+    delimiter,
+    has_header: has_header.unwrap_or_else(|| sniff_header(&buf, delimiter)),
+    infer_types: false,
+  })
+}
+
+fn sniff_header(bytes: &[u8], delimiter: u8) -> bool {
+  let mut reader = csv::ReaderBuilder::new()
+    .delimiter(delimiter)
+    .has_headers(false)
+    .flexible(true)
+    .from_reader(bytes);
+  let mut rows = reader.records().map_while(Result::ok);
+  let (Some(first), Some(second)) = (rows.next(), rows.next()) else {
+    return true;
+  };
+  let numeric = |cell: &str| cell.trim().parse::<f64>().is_ok_and(f64::is_finite);
+  !first
+    .iter()
+    .zip(second.iter())
+    .any(|(first, second)| numeric(first) && numeric(second))
+}
+
+fn sniff_delimiter(bytes: &[u8]) -> u8 {
+  let mut counts = [0usize; CSV_DELIMITERS.len()];
+  let mut quoted = false;
+  for &byte in bytes {
+    match byte {
+      b'"' => quoted = !quoted,
+      b'\n' if !quoted => break,
+      _ if !quoted => {
+        if let Some(idx) = CSV_DELIMITERS.iter().position(|&d| d == byte) {
+          counts[idx] += 1;
+        }
+      }
+      _ => {}
+    }
+  }
+  // The first of the most frequent, so a tie keeps the comma.
+  let (best, _) = counts
+    .iter()
+    .enumerate()
+    .fold((0, 0), |best, (idx, &count)| if count > best.1 { (idx, count) } else { best });
+  CSV_DELIMITERS[best]
+}
+
 fn stream_json_array<R: Read, F: FnMut(Value) -> Result<(), String>>(
   reader: R,
   mut on_value: F,
@@ -80,177 +657,2662 @@ fn stream_json_array<R: Read, F: FnMut(Value) -> Result<(), String>>(
     .map_err(|e| e.to_string())
 }
 
-pub fn ingest_dataset(
-  path: &Path,
-  store_dir: &Path,
-  cancel: &AtomicBool,
-  mut on_progress: impl FnMut(usize, usize),
-) -> Result<DatasetStore, String> {
-  fs::create_dir_all(store_dir).map_err(|e| e.to_string())?;
-  let dataset_id = Uuid::new_v4().to_string();
-  let store_path = store_dir.join(format!("{dataset_id}.jsonl"));
-  let mut writer = BufWriter::new(File::create(&store_path).map_err(|e| e.to_string())?);
-  let mut offsets = Vec::new();
-  let mut fields = HashSet::new();
-  let mut offset = 0u64;
-  let mut count = 0usize;
-  let size_bytes = fs::metadata(path)
-    .map(|meta| meta.len())
-    .unwrap_or_default();
-  let format = detect_format(path)?;
-
-  let mut write_record = |value: Value| -> Result<(), String> {
+/// Reads `file` in chunks on its own thread so read latency overlaps with
+/// parsing. Stops at end of file, on cancel, or once the receiver is dropped.
+fn read_chunks(mut file: File, sender: SyncSender<io::Result<Vec<u8>>>, cancel: &AtomicBool) {
+  loop {
     if cancel.load(Ordering::SeqCst) {
-      return Err("Import canceled".to_string());
+      let _ = sender.send(Err(io::Error::other("Import canceled")));
+      return;
     }
-    let record = normalize_record(value);
-    if let Some(map) = record.as_object() {
-      for key in map.keys() {
-        fields.insert(key.clone());
+    let mut chunk = vec![0u8; READ_CHUNK_BYTES];
+    let read = match file.read(&mut chunk) {
+      Ok(0) => return,
+      Ok(read) => read,
+      Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+      Err(err) => {
+        let _ = sender.send(Err(err));
+        return;
       }
+    };
+    chunk.truncate(read);
+    if sender.send(Ok(chunk)).is_err() {
+      return;
     }
-    let line = serde_json::to_vec(&record).map_err(|e| e.to_string())?;
-    offsets.push(offset);
-    writer.write_all(&line).map_err(|e| e.to_string())?;
-    writer.write_all(b"\n").map_err(|e| e.to_string())?;
-    offset += line.len() as u64 + 1;
-    count += 1;
-    if count % 500 == 0 {
-      on_progress(count, 0);
+  }
+}
+
+/// The parsing side of `read_chunks`: the chunks as one byte stream.
+struct ChunkReader<'a> {
+  receiver: Receiver<io::Result<Vec<u8>>>,
+  chunk: Vec<u8>,
+  pos: usize,
+  /// File bytes handed to the parser so far, ahead of any decompression.
+  consumed: &'a Cell<u64>,
+}
+
+impl Read for ChunkReader<'_> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    while self.pos == self.chunk.len() {
+      match self.receiver.recv() {
+        Ok(chunk) => {
+          self.chunk = chunk?;
+          self.pos = 0;
+        }
+        // The reader thread finished the file.
+        Err(_) => return Ok(0),
+      }
     }
-    Ok(())
+    let count = buf.len().min(self.chunk.len() - self.pos);
+    buf[..count].copy_from_slice(&self.chunk[self.pos..self.pos + count]);
+    self.pos += count;
+    self.consumed.set(self.consumed.get() + count as u64);
+    Ok(count)
+  }
+}
+
+/// Parses every record of a source file in `format`, gzip-compressed or not
+/// and in any encoding `detect_encoding` finds, handing each to `on_value`.
+/// JSONL lines longer than `line_cap` bytes go to `on_oversized` unparsed.
+pub fn for_each_source_record(
+  path: &Path,
+  format: &str,
+  line_cap: usize,
+  on_value: impl FnMut(Value) -> Result<(), String>,
+  mut on_oversized: impl FnMut(u64),
+) -> Result<(), String> {
+  let dialect = if format == "csv" {
+    csv_dialect(path, None)?
+  } else {
+    CsvDialect::default()
   };
+  let gzip = is_gzip(path)?;
+  let encoding = detect_encoding(path)?;
+  let file = File::open(path).map_err(|e| io_error(path, &e))?;
+  let source = decoded(decompressed(file, gzip), encoding);
+  let position = Cell::new(None);
+  let layout = RecordLayout {
+    dialect,
+    line_cap,
+    records_path: None,
+  };
+  for_each_record_in(source, format, &layout, &position, on_value, |skipped| {
+    match skipped {
+      SkippedLine::Oversized(size) => {
+        on_oversized(size);
+        Ok(())
+      }
+      SkippedLine::Malformed(line) => Err(line.strict_error()),
+    }
+  })
+}
+
+/// A JSON Lines line handed to `on_skipped` instead of `on_value`.
+enum SkippedLine {
+  /// Longer than the line cap; its length in bytes.
+  Oversized(u64),
+  /// Not valid UTF-8 or JSON.
+  Malformed(MalformedLine),
+}
+
+impl MalformedLine {
+  fn new(line_number: usize, error: String, line: &[u8]) -> Self {
+    Self {
+      line_number,
+      error,
+      snippet: String::from_utf8_lossy(line)
+        .chars()
+        .take(MALFORMED_SNIPPET_CHARS)
+        .collect(),
+    }
+  }
+
+  /// The error that stops a strict import at this line.
+  fn strict_error(&self) -> String {
+    format!("Line {}: {}", self.line_number, self.error)
+  }
+}
+
+/// Counts the bytes read through it.
+struct CountingReader<R> {
+  inner: R,
+  count: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let read = self.inner.read(buf)?;
+    self.count += read as u64;
+    Ok(read)
+  }
+}
 
-  match format.as_str() {
+/// The next CSV row and the byte offset just past it.
+fn next_csv_row<R: Read>(
+  reader: &mut csv::Reader<R>,
+) -> Result<Option<(csv::StringRecord, u64)>, String> {
+  let mut row = csv::StringRecord::new();
+  if reader.read_record(&mut row).map_err(|e| e.to_string())? {
+    Ok(Some((row, reader.position().byte())))
+  } else {
+    Ok(None)
+  }
+}
+
+/// How `for_each_record_in` finds the records of a source.
+#[derive(Debug, Clone, Copy, Default)]
+struct RecordLayout<'a> {
+  dialect: CsvDialect,
+  /// JSON Lines lines longer than this many bytes are skipped unparsed.
+  line_cap: usize,
+  /// Dot path of keys to the array holding the records of a JSON document.
+  records_path: Option<&'a str>,
+}
+
+/// Parses `source` as `format`. For CSV and JSON Lines, `position` is kept at
+/// the byte offset in `source` just past the last record `on_value` accepted
+/// or skipped; it stays unset for JSON documents, which cannot be resumed.
+/// JSON Lines lines that are too long or do not parse go to `on_skipped`,
+/// which stops the read by returning an error.
+fn for_each_record_in(
+  mut source: impl Read,
+  format: &str,
+  layout: &RecordLayout,
+  position: &Cell<Option<u64>>,
+  mut on_value: impl FnMut(Value) -> Result<(), String>,
+  mut on_skipped: impl FnMut(SkippedLine) -> Result<(), String>,
+) -> Result<(), String> {
+  match format {
     "csv" => {
-      let file = File::open(path).map_err(|e| e.to_string())?;
       let mut reader = csv::ReaderBuilder::new()
+        .delimiter(layout.dialect.delimiter)
+        .has_headers(layout.dialect.has_header)
         .flexible(true)
-        .from_reader(file);
-      let headers = reader
-        .headers()
-        .map_err(|e| e.to_string())?
-        .iter()
-        .map(|s| s.to_string())
-        .collect::<Vec<_>>();
-      for result in reader.records() {
-        let record = result.map_err(|e| e.to_string())?;
+        .from_reader(source);
+      let mut headers = if layout.dialect.has_header {
+        reader
+          .headers()
+          .map_err(|e| e.to_string())?
+          .iter()
+          .map(|s| s.to_string())
+          .collect::<Vec<_>>()
+      } else {
+        Vec::new()
+      };
+      position.set(Some(reader.position().byte()));
+      let mut window = Vec::new();
+      let mut types = Vec::new();
+      if layout.dialect.infer_types {
+        while window.len() < TYPE_INFERENCE_ROWS {
+          match next_csv_row(&mut reader)? {
+            Some(row) => window.push(row),
+            None => break,
+          }
+        }
+        let columns = window.iter().map(|(row, _)| row.len()).max().unwrap_or(0);
+        types = (0..columns)
+          .map(|idx| infer_column(window.iter().filter_map(|(row, _)| row.get(idx))))
+          .collect::<Vec<_>>();
+      }
+      let mut window = window.into_iter();
+      loop {
+        let (record, end) = match window.next() {
+          Some(row) => row,
+          None => match next_csv_row(&mut reader)? {
+            Some(row) => row,
+            None => break,
+          },
+        };
+        if !layout.dialect.has_header {
+          while headers.len() < record.len() {
+            headers.push(format!("column_{}", headers.len() + 1));
+          }
+        }
         let mut map = serde_json::Map::new();
         for (idx, header) in headers.iter().enumerate() {
-          let value = record.get(idx).unwrap_or_default();
-          map.insert(header.clone(), Value::String(value.to_string()));
+          let cell = record.get(idx).unwrap_or_default();
+          let value = if layout.dialect.infer_types {
+            typed_cell(cell, types.get(idx).copied().unwrap_or(ColumnType::Text))
+          } else {
+            Value::String(cell.to_string())
+          };
+          map.insert(header.clone(), value);
         }
-        write_record(Value::Object(map))?;
+        on_value(Value::Object(map))?;
+        position.set(Some(end));
       }
     }
     "json" | "jsonl" => {
-      let mut file = File::open(path).map_err(|e| e.to_string())?;
-      let mut probe = [0u8; 128];
-      let read = file.read(&mut probe).map_err(|e| e.to_string())?;
-      let prefix = String::from_utf8_lossy(&probe[..read]);
-      file.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
-      if prefix.trim_start().starts_with('[') {
-        stream_json_array(file, |value| write_record(value))?;
+      let mut probe = Vec::new();
+      (&mut source)
+        .take(JSON_LAYOUT_SNIFF_BYTES)
+        .read_to_end(&mut probe)
+        .map_err(|e| e.to_string())?;
+      let prefix = String::from_utf8_lossy(&probe[..probe.len().min(128)]);
+      let is_array = prefix.trim_start().starts_with('[');
+      let document =
+        layout.records_path.is_some() || (format == "json" && is_json_document(&probe));
+      let source = probe.as_slice().chain(source);
+      if document {
+        stream_json_records(source, layout.records_path, &mut on_value)?;
+      } else if is_array {
+        stream_json_array(source, &mut on_value)?;
       } else {
-        let reader = BufReader::new(file);
-        for line in reader.lines() {
-          let line = line.map_err(|e| e.to_string())?;
-          if line.trim().is_empty() {
-            continue;
+        let mut reader = BufReader::new(CountingReader {
+          inner: source,
+          count: 0,
+        });
+        // Bytes consumed so far: those read from `source` less those still buffered.
+        let consumed = |reader: &BufReader<CountingReader<_>>| {
+          reader.get_ref().count - reader.buffer().len() as u64
+        };
+        position.set(Some(0));
+        let mut line_number = 0;
+        while let Some(line) = read_line_bounded(&mut reader, layout.line_cap)? {
+          line_number += 1;
+          let parsed = match line {
+            BoundedLine::Line(bytes) => {
+              let malformed = |error: String| {
+                SkippedLine::Malformed(MalformedLine::new(line_number, error, &bytes))
+              };
+              match std::str::from_utf8(&bytes) {
+                Ok(text) if text.trim().is_empty() => Ok(None),
+                Ok(text) => serde_json::from_str::<Value>(text)
+                  .map(Some)
+                  .map_err(|e| malformed(e.to_string())),
+                Err(e) => Err(malformed(e.to_string())),
+              }
+            }
+            BoundedLine::Oversized(size) => Err(SkippedLine::Oversized(size)),
+          };
+          match parsed {
+            Ok(Some(value)) => on_value(value)?,
+            Ok(None) => {}
+            Err(skipped) => on_skipped(skipped)?,
           }
-          let value: Value = serde_json::from_str(&line).map_err(|e| e.to_string())?;
-          write_record(value)?;
+          position.set(Some(consumed(&reader)));
         }
       }
     }
     _ => return Err("Unsupported format".to_string()),
   }
+  Ok(())
+}
 
-  writer.flush().map_err(|e| e.to_string())?;
-  let mut fields_list = fields.into_iter().collect::<Vec<_>>();
-  fields_list.sort();
+/// Hands every section of the Markdown at `path`, a file or a directory of
+/// them, to `on_value` as a `title`, `body` and `source_file` record; the
+/// source file is relative to the directory. Returns the bytes read and the
+/// sections skipped for a short body.
+fn for_each_markdown_record(
+  path: &Path,
+  options: &ImportOptions,
+  cancel: &AtomicBool,
+  mut on_value: impl FnMut(Value) -> Result<(), String>,
+) -> Result<(u64, usize), String> {
+  let (files, root) = if path.is_dir() {
+    (markdown_files(path)?, path)
+  } else {
+    (vec![path.to_path_buf()], path.parent().unwrap_or(Path::new("")))
+  };
+  if files.is_empty() {
+    return Err(format!("No Markdown files in {}", path.display()));
+  }
+  let level = options.heading_level.clamp(1, 6);
+  let mut size_bytes = 0u64;
+  let mut skipped = 0usize;
+  for file in &files {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Import canceled".to_string());
+    }
+    let text = fs::read_to_string(file).map_err(|e| io_error(file, &e))?;
+    size_bytes += text.len() as u64;
+    let source_file = file
+      .strip_prefix(root)
+      .unwrap_or(file)
+      .to_string_lossy()
+      .replace('\\', "/");
+    let stem = file
+      .file_stem()
+      .map(|stem| stem.to_string_lossy().to_string())
+      .unwrap_or_default();
+    for section in markdown_sections(&text, level, &stem) {
+      if text_length(&section.body) < options.min_body_length {
+        skipped += 1;
+        continue;
+      }
+      on_value(json!({
+        "title": section.title,
+        "body": section.body,
+        "source_file": source_file,
+      }))?;
+    }
+  }
+  Ok((size_bytes, skipped))
+}
 
-  Ok(DatasetStore {
-    id: dataset_id,
-    source_path: path.to_path_buf(),
-    store_path,
-    offsets,
-    fields: fields_list,
-    record_count: count,
-    size_bytes,
-    format,
-  })
+/// How far an import has read through its source file. Both are zero when
+/// unknown, as for Markdown.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SourceProgress {
+  /// File bytes parsed so far, compressed ones for gzip sources.
+  pub bytes_read: u64,
+  pub bytes_total: u64,
 }
 
-pub fn read_record_line(store: &DatasetStore, id: usize) -> Result<String, String> {
-  if id >= store.offsets.len() {
-    return Err("Record id out of range".to_string());
-  }
-  let mut file = File::open(&store.store_path).map_err(|e| e.to_string())?;
-  file
-    .seek(SeekFrom::Start(store.offsets[id]))
-    .map_err(|e| e.to_string())?;
-  let mut reader = BufReader::new(file);
-  let mut line = String::new();
-  reader.read_line(&mut line).map_err(|e| e.to_string())?;
-  Ok(line)
+/// What reading an import source found besides its records.
+pub(crate) struct SourceInfo {
+  pub(crate) format: String,
+  delimiter: Option<u8>,
+  /// The compressed size for gzip sources, as the decompressed one is unknown up front.
+  size_bytes: u64,
+  pub(crate) report: ImportReport,
+  /// Reading was canceled, so only the records before that point were handed on.
+  truncated: bool,
+  /// Byte offset of the source just past the last record handed on, when
+  /// reading can continue there: uncompressed UTF-8 CSV and JSON Lines only.
+  resume_offset: Option<u64>,
+  encoding: Option<SourceEncoding>,
 }
 
-pub fn read_record_value(store: &DatasetStore, id: usize) -> Result<Value, String> {
-  let line = read_record_line(store, id)?;
-  serde_json::from_str(&line).map_err(|e| e.to_string())
+/// The raw header row of an uncompressed CSV file, line break included, which
+/// a continued import reads ahead of the rest of the file.
+fn csv_header_bytes(path: &Path, dialect: CsvDialect) -> Result<Vec<u8>, String> {
+  let file = File::open(path).map_err(|e| io_error(path, &e))?;
+  let mut reader = csv::ReaderBuilder::new()
+    .delimiter(dialect.delimiter)
+    .has_headers(true)
+    .flexible(true)
+    .from_reader(file);
+  reader.headers().map_err(|e| e.to_string())?;
+  let header_len = reader.position().byte();
+  let mut header = Vec::with_capacity(header_len as usize);
+  File::open(path)
+    .map_err(|e| io_error(path, &e))?
+    .take(header_len)
+    .read_to_end(&mut header)
+    .map_err(|e| io_error(path, &e))?;
+  Ok(header)
 }
 
-pub fn export_dataset(
-  store: &DatasetStore,
-  ids: &[usize],
+/// Parses the source at `path` as the import options say, handing every
+/// record that fits the size limit to `on_record` with its serialized line
+/// and how far through the file reading is.
+pub(crate) fn read_import_source(
   path: &Path,
-  format: &str,
+  options: &ImportOptions,
   cancel: &AtomicBool,
-  mut on_progress: impl FnMut(usize, usize),
-) -> Result<(), String> {
-  if cancel.load(Ordering::SeqCst) {
-    return Err("Export canceled".to_string());
+  mut on_record: impl FnMut(Value, &[u8], SourceProgress) -> Result<(), String>,
+) -> Result<SourceInfo, String> {
+  let format = match options.format.as_deref() {
+    Some(format @ ("csv" | "json" | "jsonl" | "markdown")) => format.to_string(),
+    Some(other) => return Err(format!("Unsupported format: {other}")),
+    None if path.is_dir() => "markdown".to_string(),
+    None => detect_format(path)?,
+  };
+  if path.is_dir() && format != "markdown" {
+    return Err("Only Markdown can be imported from a directory".to_string());
   }
-  if format == "csv" {
-    let mut writer = csv::Writer::from_path(path).map_err(|e| e.to_string())?;
-    writer
-      .write_record(&store.fields)
-      .map_err(|e| e.to_string())?;
-    for (idx, id) in ids.iter().enumerate() {
-      let record = read_record_value(store, *id)?;
-      let mut row = Vec::with_capacity(store.fields.len());
-      for field in &store.fields {
-        let value = record
-          .get(field)
-          .map(value_to_string)
-          .unwrap_or_default();
-        row.push(value);
-      }
-      writer.write_record(&row).map_err(|e| e.to_string())?;
-      if idx % 1000 == 0 {
-        on_progress(idx, ids.len());
+  let gzip = !path.is_dir() && is_gzip(path)?;
+  if gzip && format == "markdown" {
+    return Err("Compressed Markdown files are not supported".to_string());
+  }
+  // Markdown files are read as UTF-8 one by one.
+  let encoding = if format == "markdown" {
+    None
+  } else {
+    Some(detect_encoding(path)?)
+  };
+  // Offsets into transcoded text are not file offsets. JSON arrays never
+  // record one, so only a caller's guess is refused here.
+  let resumable = !gzip
+    && !encoding.is_some_and(|encoding| encoding.transcodes())
+    && matches!(format.as_str(), "csv" | "json" | "jsonl");
+  if options.resume_offset.is_some() && !resumable {
+    return Err("Only uncompressed UTF-8 CSV and JSON Lines imports can be continued".to_string());
+  }
+  let dialect = if format == "csv" {
+    Some(CsvDialect {
+      infer_types: options.infer_types,
+      ..csv_dialect(path, options.has_header)?
+    })
+  } else {
+    None
+  };
+  let max_record_bytes = options.max_record_bytes.max(1);
+  let mut report = ImportReport {
+    generated_headers: dialect.is_some_and(|dialect| !dialect.has_header),
+    ..ImportReport::default()
+  };
+  let mut oversized_lines = Vec::new();
+  let mut malformed_count = 0;
+  let mut malformed_lines = Vec::new();
+  // Markdown sizes are only known once every file was read.
+  let bytes_total = if format == "markdown" {
+    0
+  } else {
+    fs::metadata(path)
+      .map(|meta| meta.len())
+      .map_err(|e| io_error(path, &e))?
+  };
+  // A continued import starts counting at its offset, so progress covers the whole file.
+  let bytes_read = Cell::new(options.resume_offset.unwrap_or_default());
+
+  let mut write_record = |value: Value| -> Result<(), String> {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Import canceled".to_string());
+    }
+    let mut record = normalize_record(value);
+    let mut line = serde_json::to_vec(&record).map_err(|e| e.to_string())?;
+    if line.len() > max_record_bytes && options.truncate_large_fields {
+      shrink_record(&mut record, max_record_bytes);
+      line = serde_json::to_vec(&record).map_err(|e| e.to_string())?;
+      if line.len() <= max_record_bytes {
+        report.truncated_records += 1;
       }
     }
-    writer.flush().map_err(|e| e.to_string())?;
+    if line.len() > max_record_bytes {
+      note_oversized(&mut report, line.len() as u64);
+      return Ok(());
+    }
+    let progress = SourceProgress {
+      bytes_read: bytes_read.get(),
+      bytes_total,
+    };
+    on_record(record, &line, progress)
+  };
+
+  // Truncation needs the full line to parse, so only skip-mode avoids buffering it.
+  let line_cap = if options.truncate_large_fields {
+    usize::MAX
   } else {
-    let mut file = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
-    file.write_all(b"[").map_err(|e| e.to_string())?;
-    for (idx, id) in ids.iter().enumerate() {
-      let line = read_record_line(store, *id)?;
-      let trimmed = line.trim();
-      if idx > 0 {
-        file.write_all(b",\n").map_err(|e| e.to_string())?;
+    max_record_bytes
+  };
+  let mut size_bytes = 0u64;
+  let position = Cell::new(None);
+  // Where in the file parsing starts; a continued CSV import re-reads the
+  // header row first, which the parser's positions then include.
+  let mut base = 0u64;
+  let parsed = if format == "markdown" {
+    for_each_markdown_record(path, options, cancel, &mut write_record).map(|(bytes, skipped)| {
+      size_bytes = bytes;
+      report.short_sections_skipped = skipped;
+    })
+  } else {
+    size_bytes = bytes_total;
+    let mut file = File::open(path).map_err(|e| io_error(path, &e))?;
+    let mut header = Vec::new();
+    if let Some(offset) = options.resume_offset {
+      if dialect.is_some_and(|dialect| dialect.has_header) {
+        header = csv_header_bytes(path, dialect.unwrap_or_default())?;
       }
       file
-        .write_all(trimmed.as_bytes())
-        .map_err(|e| e.to_string())?;
-      if idx % 1000 == 0 {
-        on_progress(idx, ids.len());
+        .seek(SeekFrom::Start(offset))
+        .map_err(|e| io_error(path, &e))?;
+      base = offset - (header.len() as u64).min(offset);
+    }
+    thread::scope(|scope| {
+      let read_ahead = options.read_ahead_chunks.clamp(1, MAX_READ_AHEAD_CHUNKS);
+      let (sender, receiver) = mpsc::sync_channel(read_ahead);
+      scope.spawn(move || read_chunks(file, sender, cancel));
+      let chunks = ChunkReader {
+        receiver,
+        chunk: Vec::new(),
+        pos: 0,
+        consumed: &bytes_read,
+      };
+      let source = decoded(
+        decompressed(header.as_slice().chain(chunks), gzip),
+        encoding.unwrap_or(SourceEncoding::UTF_8),
+      );
+      // Returning drops the receiver, which stops the reader thread before the scope joins it.
+      let layout = RecordLayout {
+        dialect: dialect.unwrap_or_default(),
+        line_cap,
+        records_path: options.records_path.as_deref().filter(|path| !path.is_empty()),
+      };
+      for_each_record_in(source, &format, &layout, &position, &mut write_record, |skipped| {
+        match skipped {
+          SkippedLine::Oversized(size) => oversized_lines.push(size),
+          SkippedLine::Malformed(line) if options.lenient => {
+            malformed_count += 1;
+            if malformed_lines.len() < MALFORMED_SAMPLE_LIMIT {
+              malformed_lines.push(line);
+            }
+          }
+          SkippedLine::Malformed(line) => return Err(line.strict_error()),
+        }
+        Ok(())
+      })
+    })
+  };
+  // A canceled read keeps what it handed on; other failures lose it.
+  let truncated = match parsed {
+    Ok(()) => false,
+    Err(_) if cancel.load(Ordering::SeqCst) => true,
+    Err(err) => return Err(err),
+  };
+  for size in oversized_lines {
+    note_oversized(&mut report, size);
+  }
+  report.skipped_count = malformed_count;
+  report.malformed_lines = malformed_lines;
+  Ok(SourceInfo {
+    format,
+    delimiter: dialect.map(|dialect| dialect.delimiter),
+    size_bytes,
+    report,
+    truncated,
+    resume_offset: position.get().filter(|_| resumable).map(|end| base + end),
+    encoding,
+  })
+}
+
+pub fn ingest_dataset(
+  path: &Path,
+  store_dir: &Path,
+  options: &ImportOptions,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, SourceProgress),
+) -> Result<(DatasetStore, ImportReport), String> {
+  let mut store_writer = StoreWriter::create(store_dir)?;
+  let read = read_import_source(path, options, cancel, |record, line, progress| {
+    store_writer.write_serialized(&record, line)?;
+    let count = store_writer.len();
+    if count.is_multiple_of(500) {
+      on_progress(count, progress);
+    }
+    Ok(())
+  });
+  // A failed import, or one canceled before its first record, leaves no
+  // partial store behind.
+  let source = match read {
+    Ok(source) if source.truncated && store_writer.is_empty() => {
+      store_writer.discard();
+      return Err("Import canceled".to_string());
+    }
+    Ok(source) => source,
+    Err(err) => {
+      store_writer.discard();
+      return Err(err);
+    }
+  };
+  let mut store = store_writer.finish(path, source.size_bytes, &source.format, None)?;
+  store.delimiter = source.delimiter;
+  store.encoding = source.encoding.map(|encoding| encoding.name().to_string());
+  store.truncated = source.truncated;
+  store.resume_offset = source.resume_offset.filter(|_| source.truncated);
+  store.max_record_bytes = options.max_record_bytes.max(1);
+  save_store_index(&store)?;
+  Ok((store, source.report))
+}
+
+/// Deletes the files of a store that was never opened, such as the partial
+/// result of an import that is not kept.
+pub fn discard_store(store: &DatasetStore) {
+  let _ = fs::remove_file(&store.store_path);
+  let _ = fs::remove_file(content_hashes_path(&store.store_path));
+  let _ = fs::remove_file(store_index_path(&store.store_path));
+}
+
+pub fn read_record_line(store: &DatasetStore, id: usize) -> Result<String, String> {
+  if id >= store.offsets.len() {
+    return Err("Record id out of range".to_string());
+  }
+  let mut file = File::open(&store.store_path).map_err(|e| e.to_string())?;
+  file
+    .seek(SeekFrom::Start(store.offsets[id]))
+    .map_err(|e| e.to_string())?;
+  let mut reader = BufReader::new(file);
+  let mut line = String::new();
+  reader.read_line(&mut line).map_err(|e| e.to_string())?;
+  Ok(line)
+}
+
+pub fn read_record_value(store: &DatasetStore, id: usize) -> Result<Value, String> {
+  let line = read_record_line(store, id)?;
+  serde_json::from_str(&line).map_err(|e| e.to_string())
+}
+
+/// Like `read_record_value`, but never buffers more than `max_bytes` of the record.
+pub fn read_record_value_bounded(
+  store: &DatasetStore,
+  id: usize,
+  max_bytes: usize,
+) -> Result<Result<Value, u64>, String> {
+  if id >= store.offsets.len() {
+    return Err("Record id out of range".to_string());
+  }
+  read_value_at(&store.store_path, store.offsets[id], max_bytes)
+}
+
+/// The record starting at byte `offset` of the store file at `store_path`,
+/// or its size when it is longer than `max_bytes`.
+pub fn read_value_at(
+  store_path: &Path,
+  offset: u64,
+  max_bytes: usize,
+) -> Result<Result<Value, u64>, String> {
+  let mut file = File::open(store_path).map_err(|e| e.to_string())?;
+  file
+    .seek(SeekFrom::Start(offset))
+    .map_err(|e| e.to_string())?;
+  let mut reader = BufReader::new(file);
+  match read_line_bounded(&mut reader, max_bytes)? {
+    Some(BoundedLine::Line(bytes)) => serde_json::from_slice(&bytes)
+      .map(Ok)
+      .map_err(|e| e.to_string()),
+    Some(BoundedLine::Oversized(size)) => Ok(Err(size)),
+    None => Err("Record id out of range".to_string()),
+  }
+}
+
+/// Ids and store offsets of `ids`, so their records can be read without
+/// holding on to the store.
+pub fn record_offsets(store: &DatasetStore, ids: &[usize]) -> Result<Vec<(usize, u64)>, String> {
+  ids
+    .iter()
+    .map(|&id| match store.offsets.get(id) {
+      Some(offset) => Ok((id, *offset)),
+      None => Err("Record id out of range".to_string()),
+    })
+    .collect()
+}
+
+/// Preview items of the records at `offsets`, as from `record_offsets`, in
+/// order, until their serialized fields would pass `max_bytes`; the first
+/// item is always kept. Records longer than `max_record_bytes`, as from
+/// `DatasetStore::preview_record_bytes`, are shown by size only. Returns the
+/// items and whether any were left out.
+pub fn preview_items(
+  store_path: &Path,
+  max_record_bytes: usize,
+  offsets: &[(usize, u64)],
+  field_map: &FieldMap,
+  max_bytes: usize,
+) -> Result<(Vec<PreviewItem>, bool), String> {
+  let mut items = Vec::with_capacity(offsets.len());
+  let mut bytes = 0usize;
+  for &(id, offset) in offsets {
+    let fields = match read_value_at(store_path, offset, max_record_bytes)? {
+      Ok(record) => build_preview_fields(&record, field_map),
+      Err(size) => oversized_preview_fields(size),
+    };
+    bytes += serde_json::to_vec(&fields).map_err(|e| e.to_string())?.len();
+    if bytes > max_bytes && !items.is_empty() {
+      return Ok((items, true));
+    }
+    items.push(PreviewItem { id, fields });
+  }
+  Ok((items, false))
+}
+
+/// Where the records of one preview page are, taken under the state lock so
+/// they can be read once it is released.
+#[derive(Debug, Clone)]
+pub struct PreviewPageSource {
+  store_path: PathBuf,
+  record_bytes: usize,
+  offsets: Vec<(usize, u64)>,
+  field_map: FieldMap,
+  /// Records in the whole view.
+  pub total: usize,
+}
+
+impl PreviewPageSource {
+  /// Page `page`, counted from 1, of `view` in pages of `page_size`.
+  pub fn locate(
+    inner: &InnerState,
+    view: &str,
+    page: usize,
+    page_size: usize,
+  ) -> Result<Self, String> {
+    let store = inner
+      .dataset
+      .as_ref()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    let ids = inner.view_ids(view);
+    let offsets = record_offsets(store, &ids.page(page.saturating_sub(1) * page_size, page_size))?;
+    Ok(Self {
+      store_path: store.store_path.clone(),
+      record_bytes: store.preview_record_bytes(),
+      offsets,
+      field_map: inner.field_map.clone(),
+      total: ids.len(),
+    })
+  }
+
+  /// The page's items, as from `preview_items` with a `max_bytes` budget.
+  pub fn read(&self, max_bytes: usize) -> Result<(Vec<PreviewItem>, bool), String> {
+    preview_items(
+      &self.store_path,
+      self.record_bytes,
+      &self.offsets,
+      &self.field_map,
+      max_bytes,
+    )
+  }
+}
+
+/// `read_record_value_bounded` for several records, opening the store once.
+/// Results are in the order of `ids`.
+pub fn read_record_values_bounded(
+  store: &DatasetStore,
+  ids: &[usize],
+  max_bytes: usize,
+) -> Result<Vec<Result<Value, u64>>, String> {
+  if ids.iter().any(|id| *id >= store.offsets.len()) {
+    return Err("Record id out of range".to_string());
+  }
+  let mut reader = BufReader::new(File::open(&store.store_path).map_err(|e| e.to_string())?);
+  let mut records = Vec::with_capacity(ids.len());
+  for id in ids {
+    reader
+      .seek(SeekFrom::Start(store.offsets[*id]))
+      .map_err(|e| e.to_string())?;
+    let record = match read_line_bounded(&mut reader, max_bytes)? {
+      Some(BoundedLine::Line(bytes)) => {
+        Ok(serde_json::from_slice(&bytes).map_err(|e| e.to_string())?)
+      }
+      Some(BoundedLine::Oversized(size)) => Err(size),
+      None => return Err("Record id out of range".to_string()),
+    };
+    records.push(record);
+  }
+  Ok(records)
+}
+
+/// One line of a store file, as read by `store_lines`.
+pub struct StoreLine {
+  pub id: usize,
+  line: BoundedLine,
+}
+
+impl StoreLine {
+  /// The bytes of the line, or `None` when it is longer than the import cap.
+  pub fn bytes(&self) -> Option<&[u8]> {
+    match &self.line {
+      BoundedLine::Line(bytes) => Some(bytes),
+      BoundedLine::Oversized(_) => None,
+    }
+  }
+
+  /// The record on the line, or `None` when the line is blank or longer than
+  /// the import cap.
+  pub fn record(&self) -> Result<Option<Value>, String> {
+    match self.bytes() {
+      Some(bytes) if !bytes.trim_ascii().is_empty() => serde_json::from_slice(bytes)
+        .map(Some)
+        .map_err(|e| e.to_string()),
+      _ => Ok(None),
+    }
+  }
+
+  /// The size of a line longer than the import cap.
+  pub fn oversized_size(&self) -> Option<u64> {
+    match self.line {
+      BoundedLine::Oversized(size) => Some(size),
+      BoundedLine::Line(_) => None,
+    }
+  }
+}
+
+pub struct StoreLines {
+  reader: BufReader<File>,
+  next_id: usize,
+  max_record_bytes: usize,
+}
+
+impl Iterator for StoreLines {
+  type Item = Result<StoreLine, String>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let line = match read_line_bounded(&mut self.reader, self.max_record_bytes) {
+      Ok(line) => line?,
+      Err(e) => return Some(Err(e)),
+    };
+    let id = self.next_id;
+    self.next_id += 1;
+    Some(Ok(StoreLine { id, line }))
+  }
+}
+
+/// The lines of `store` in id order. A line longer than the store's import
+/// cap is skipped over without being buffered, so a corrupt or hand-edited
+/// store cannot exhaust memory in a full scan.
+pub fn store_lines(store: &DatasetStore) -> Result<StoreLines, String> {
+  let file = File::open(&store.store_path).map_err(|e| e.to_string())?;
+  Ok(StoreLines {
+    reader: BufReader::new(file),
+    next_id: 0,
+    max_record_bytes: store.max_record_bytes,
+  })
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportSpec {
+  pub path: PathBuf,
+  /// `json` for an array, `jsonl` for one record per line, `csv`, or
+  /// `sqlite` for a database with one table.
+  pub format: String,
+  pub compression: ExportCompression,
+  pub options: ExportOptions,
+  pub field_map: FieldMap,
+}
+
+/// Compression applied to an exported file as it is written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExportCompression {
+  #[default]
+  None,
+  Gzip,
+  Zstd,
+}
+
+impl ExportCompression {
+  pub fn parse(value: &str) -> Result<Self, String> {
+    match value {
+      "none" => Ok(Self::None),
+      "gzip" => Ok(Self::Gzip),
+      "zstd" => Ok(Self::Zstd),
+      other => Err(format!("Unsupported export compression: {other}")),
+    }
+  }
+
+  /// Gzip for a `.gz` path, Zstandard for `.zst`, otherwise none.
+  pub fn for_path(path: &Path) -> Self {
+    match path.extension().and_then(|ext| ext.to_str()) {
+      Some(ext) if ext.eq_ignore_ascii_case("gz") => Self::Gzip,
+      Some(ext) if ext.eq_ignore_ascii_case("zst") => Self::Zstd,
+      _ => Self::None,
+    }
+  }
+}
+
+/// Zstandard level used for exports, the library's default.
+const ZSTD_EXPORT_LEVEL: i32 = 3;
+
+/// Buffered output file, compressed or not. `finish` must be called to write
+/// the compressed stream's trailer.
+pub(crate) enum ExportWriter {
+  Plain(BufWriter<File>),
+  Gzip(GzEncoder<BufWriter<File>>),
+  Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl ExportWriter {
+  pub(crate) fn create(path: &Path, compression: ExportCompression) -> Result<Self, String> {
+    let file = BufWriter::new(create_output_file(path)?);
+    Ok(match compression {
+      ExportCompression::None => Self::Plain(file),
+      ExportCompression::Gzip => Self::Gzip(GzEncoder::new(file, flate2::Compression::default())),
+      ExportCompression::Zstd => Self::Zstd(
+        zstd::Encoder::new(file, ZSTD_EXPORT_LEVEL).map_err(|e| io_error(path, &e))?,
+      ),
+    })
+  }
+
+  pub(crate) fn finish(self) -> io::Result<()> {
+    let mut file = match self {
+      Self::Plain(file) => file,
+      Self::Gzip(encoder) => encoder.finish()?,
+      Self::Zstd(encoder) => encoder.finish()?,
+    };
+    file.flush()
+  }
+}
+
+impl Write for ExportWriter {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    match self {
+      Self::Plain(file) => file.write(buf),
+      Self::Gzip(encoder) => encoder.write(buf),
+      Self::Zstd(encoder) => encoder.write(buf),
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    match self {
+      Self::Plain(file) => file.flush(),
+      Self::Gzip(encoder) => encoder.flush(),
+      Self::Zstd(encoder) => encoder.flush(),
+    }
+  }
+}
+
+/// Reader over a written export, undoing its compression.
+fn export_reader(path: &Path, compression: ExportCompression) -> Result<Box<dyn Read>, String> {
+  let file = BufReader::new(File::open(path).map_err(|e| io_error(path, &e))?);
+  Ok(match compression {
+    ExportCompression::None => Box::new(file),
+    ExportCompression::Gzip => Box::new(MultiGzDecoder::new(file)),
+    ExportCompression::Zstd => {
+      Box::new(zstd::Decoder::with_buffer(file).map_err(|e| io_error(path, &e))?)
+    }
+  })
+}
+
+/// The first difference a verification pass found between an export and the
+/// store. Positions count records in the file from 0.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExportMismatch {
+  /// The file could not be read back or parsed at `position`.
+  Unreadable { position: usize, reason: String },
+  Count { expected: usize, found: usize },
+  /// The CSV header row differs from the exported columns.
+  Header { expected: Vec<String>, found: Vec<String> },
+  /// A record read back has a field the export leaves out: one outside the
+  /// chosen fields, or an unmapped one when those are dropped.
+  LeakedField { position: usize, field: String },
+  /// A spot-checked record differs from exporting it again.
+  Record {
+    position: usize,
+    id: usize,
+    expected: String,
+    found: String,
+  },
+}
+
+fn quote_record(text: &str) -> String {
+  let mut quoted = text.chars().take(MISMATCH_QUOTE_CHARS).collect::<String>();
+  if quoted.len() < text.len() {
+    quoted.push_str("...");
+  }
+  quoted
+}
+
+impl fmt::Display for ExportMismatch {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ExportMismatch::Unreadable { position, reason } => {
+        write!(f, "record {position} could not be read back: {reason}")
+      }
+      ExportMismatch::Count { expected, found } => {
+        write!(f, "expected {expected} records, found {found}")
+      }
+      ExportMismatch::Header { expected, found } => write!(
+        f,
+        "CSV header is [{}], expected [{}]",
+        found.join(", "),
+        expected.join(", ")
+      ),
+      ExportMismatch::LeakedField { position, field } => {
+        write!(f, "record {position} has the field {field}, which the export leaves out")
+      }
+      ExportMismatch::Record {
+        position,
+        id,
+        expected,
+        found,
+      } => write!(
+        f,
+        "record {position} (id {id}) differs from the store: expected {}, found {}",
+        quote_record(expected),
+        quote_record(found)
+      ),
+    }
+  }
+}
+
+/// List field, role key and content key of the OpenAI and ShareGPT layouts.
+const CHAT_LAYOUTS: [(&str, &str, &str); 2] =
+  [("messages", "role", "content"), ("conversations", "from", "value")];
+/// Field a system prompt goes in for records without a chat layout.
+const SYSTEM_FIELD: &str = "system";
+
+/// Sets `name` unless the record already has it and `overwrite` is off.
+/// Returns whether the value was written.
+fn inject_field(map: &mut Map<String, Value>, name: &str, value: Value, overwrite: bool) -> bool {
+  if !overwrite && map.contains_key(name) {
+    return false;
+  }
+  map.insert(name.to_string(), value);
+  true
+}
+
+/// Gives a record the system prompt: as the first turn of its `messages` or
+/// `conversations`, or as a `system` field when it has neither. An existing
+/// system turn is only replaced with `overwrite`. Returns whether the prompt
+/// was written.
+fn inject_system_prompt(map: &mut Map<String, Value>, prompt: &str, overwrite: bool) -> bool {
+  for (list, role_key, content_key) in CHAT_LAYOUTS {
+    let Some(turns) = map.get_mut(list).and_then(Value::as_array_mut) else {
+      continue;
+    };
+    let existing = turns
+      .iter_mut()
+      .filter_map(Value::as_object_mut)
+      .find(|turn| turn.get(role_key).and_then(Value::as_str) == Some("system"));
+    return match existing {
+      Some(turn) => inject_field(turn, content_key, Value::from(prompt), overwrite),
+      None => {
+        let mut turn = Map::new();
+        turn.insert(role_key.to_string(), Value::from("system"));
+        turn.insert(content_key.to_string(), Value::from(prompt));
+        turns.insert(0, Value::Object(turn));
+        true
+      }
+    };
+  }
+  inject_field(map, SYSTEM_FIELD, Value::from(prompt), overwrite)
+}
+
+/// Checks that injected field names are present and distinct.
+pub(crate) fn validate_injected_fields(options: &ExportOptions) -> Result<(), String> {
+  let mut seen = HashSet::new();
+  for field in &options.inject_fields {
+    let name = field.name.trim();
+    if name.is_empty() {
+      return Err("Injected fields need a name".to_string());
+    }
+    if !seen.insert(name) {
+      return Err(format!("Field {name} is injected more than once"));
+    }
+  }
+  Ok(())
+}
+
+pub(crate) fn validate_export_fields(options: &ExportOptions) -> Result<(), String> {
+  let Some(fields) = &options.fields else {
+    return Ok(());
+  };
+  if fields.is_empty() {
+    return Err("Choose at least one field to export".to_string());
+  }
+  let mut seen = HashSet::new();
+  for field in fields {
+    if !seen.insert(field.as_str()) {
+      return Err(format!("Field {field} is exported more than once"));
+    }
+  }
+  Ok(())
+}
+
+/// Export template writing `{"instruction", "input", "output"}` records.
+const ALPACA_TEMPLATE: &str = "alpaca";
+/// Export template writing `{"conversations": [{"from", "value"}, ...]}` records.
+const SHAREGPT_TEMPLATE: &str = "sharegpt";
+
+/// Checks that the template is known, its fields are mapped and no other
+/// option reshapes the records.
+pub(crate) fn validate_export_template(spec: &ExportSpec) -> Result<(), String> {
+  let Some(template) = &spec.options.template else {
+    return Ok(());
+  };
+  if ![ALPACA_TEMPLATE, SHAREGPT_TEMPLATE].contains(&template.as_str()) {
+    return Err(format!("Unsupported export template: {template}"));
+  }
+  if spec.field_map.instruction.is_none() || spec.field_map.output.is_none() {
+    return Err(format!(
+      "The {template} template needs mapped instruction and output fields"
+    ));
+  }
+  if spec.options.fields.is_some() || spec.options.normalize_fields {
+    return Err("A template already chooses the exported fields".to_string());
+  }
+  if template == SHAREGPT_TEMPLATE && spec.format == "csv" {
+    return Err("The sharegpt template writes nested turns; export JSON or JSON Lines".to_string());
+  }
+  Ok(())
+}
+
+/// Text of the mapped `field`, or `None` when it is missing or blank.
+fn template_text(record: &Value, field: &Option<String>) -> Option<String> {
+  extract_text_value(record, field).filter(|text| !text.trim().is_empty())
+}
+
+/// `record` in the alpaca layout, or `None` when its mapped instruction or
+/// output is missing.
+fn alpaca_record(record: &Value, field_map: &FieldMap) -> Option<Value> {
+  let instruction = template_text(record, &field_map.instruction)?;
+  let output = template_text(record, &field_map.output)?;
+  Some(json!({ "instruction": instruction, "input": "", "output": output }))
+}
+
+/// `record` as a ShareGPT conversation: the mapped system, when present, then
+/// the instruction as the human turn and the output as the gpt turn. A record
+/// whose instruction is already a list of turns is kept as it is. `None` when
+/// the mapped instruction or output is missing.
+fn sharegpt_record(record: Value, field_map: &FieldMap) -> Option<Value> {
+  if let Some(Value::Array(_)) = extract_field_value(&record, &field_map.instruction) {
+    return Some(record);
+  }
+  let instruction = template_text(&record, &field_map.instruction)?;
+  let output = template_text(&record, &field_map.output)?;
+  let mut turns = Vec::new();
+  if let Some(system) = template_text(&record, &field_map.system) {
+    turns.push(json!({ "from": "system", "value": system }));
+  }
+  turns.push(json!({ "from": "human", "value": instruction }));
+  turns.push(json!({ "from": "gpt", "value": output }));
+  Some(json!({ "conversations": turns }))
+}
+
+/// `record` rewritten by `template`, or `None` when it lacks the template's fields.
+fn template_record(template: &str, record: Value, field_map: &FieldMap) -> Option<Value> {
+  match template {
+    ALPACA_TEMPLATE => alpaca_record(&record, field_map),
+    SHAREGPT_TEMPLATE => sharegpt_record(record, field_map),
+    _ => Some(record),
+  }
+}
+
+/// Names `normalize_fields` gives the mapped fields, with the field each is read from.
+fn mapped_field_names(field_map: &FieldMap) -> Vec<(&'static str, &Option<String>)> {
+  [
+    ("instruction", &field_map.instruction),
+    ("output", &field_map.output),
+    ("category", &field_map.category),
+    ("score", &field_map.score),
+  ]
+  .into_iter()
+  .filter(|(_, field)| field.is_some())
+  .collect()
+}
+
+/// Whether `column` is where a record's `field` is read from, as
+/// `extract_field_value` reads it.
+fn is_mapped_column(column: &str, field: &Option<String>) -> bool {
+  field
+    .as_deref()
+    .is_some_and(|field| column == field || column == field.to_lowercase())
+}
+
+/// `record` with its mapped fields renamed as `mapped_field_names` says. A
+/// mapped value replaces a field that already has the new name.
+fn rename_mapped_fields(record: Value, field_map: &FieldMap, keep_unmapped: bool) -> Value {
+  let renames = mapped_field_names(field_map);
+  let values = renames
+    .iter()
+    .map(|(name, field)| (*name, extract_field_value(&record, field)))
+    .collect::<Vec<_>>();
+  let Value::Object(mut map) = record else {
+    return record;
+  };
+  if keep_unmapped {
+    for (_, field) in &renames {
+      map.retain(|key, _| !is_mapped_column(key, field));
+    }
+  } else {
+    map.clear();
+  }
+  for (name, value) in values {
+    if let Some(value) = value {
+      map.insert(name.to_string(), value);
+    }
+  }
+  Value::Object(map)
+}
+
+/// `record` with only `fields`, missing ones as null.
+fn project_record(record: &Value, fields: &[String]) -> Value {
+  let map = fields
+    .iter()
+    .map(|field| (field.clone(), record.get(field).cloned().unwrap_or(Value::Null)))
+    .collect::<Map<_, _>>();
+  Value::Object(map)
+}
+
+/// Applies the export options to one record; `None` means the record is skipped.
+pub(crate) fn prepare_export_record(
+  mut record: Value,
+  spec: &ExportSpec,
+  truncate: Option<&TruncateSpec>,
+  summary: &mut ExportSummary,
+) -> Option<Value> {
+  if let Some(saved) = truncate.and_then(|truncate| truncate.apply(&mut record)) {
+    summary.truncated_count += 1;
+    summary.tokens_saved += saved;
+  }
+  // Weighed before projecting, which may drop the weight field.
+  let weight = if spec.options.include_weight {
+    match record_weight(&record, &spec.field_map.weight) {
+      Some(weight) => Some(weight),
+      None => {
+        summary.invalid_weight_count += 1;
+        if spec.options.invalid_weight == "skip" {
+          summary.skipped_count += 1;
+          return None;
+        }
+        Some(DEFAULT_WEIGHT)
       }
     }
-    file.write_all(b"]").map_err(|e| e.to_string())?;
-    file.flush().map_err(|e| e.to_string())?;
+  } else {
+    None
+  };
+  if let Some(template) = &spec.options.template {
+    let Some(templated) = template_record(template, record, &spec.field_map) else {
+      summary.template_skipped_count += 1;
+      summary.skipped_count += 1;
+      return None;
+    };
+    record = templated;
+  }
+  if let Some(fields) = &spec.options.fields {
+    record = project_record(&record, fields);
+  }
+  if spec.options.normalize_fields {
+    let keep_unmapped = !spec.options.drop_unmapped_fields;
+    record = rename_mapped_fields(record, &spec.field_map, keep_unmapped);
+  }
+  if let (Some(weight), Some(map)) = (weight, record.as_object_mut()) {
+    map.insert("weight".to_string(), Value::from(weight));
+  }
+  if let Some(map) = record.as_object_mut() {
+    let overwrite = spec.options.overwrite_existing;
+    for field in &spec.options.inject_fields {
+      if !inject_field(map, field.name.trim(), field.value.clone(), overwrite) {
+        summary.kept_existing_count += 1;
+      }
+    }
+    if let Some(prompt) = &spec.options.system_prompt {
+      if inject_system_prompt(map, prompt, overwrite) {
+        summary.system_prompt_count += 1;
+      } else {
+        summary.kept_existing_count += 1;
+      }
+    }
+  }
+  Some(record)
+}
+
+/// Columns of a CSV export. Added columns follow the stored ones, the
+/// fields asked for or those of the template, with mapped ones renamed when
+/// fields are normalized: weight, injected fields in the order given, then
+/// `system`, which stays empty for chat-layout records.
+pub(crate) fn export_columns(fields: &[String], spec: &ExportSpec) -> Vec<String> {
+  let template_fields = ["instruction", "input", "output"].map(String::from);
+  let stored = match spec.options.template.as_deref() {
+    Some(ALPACA_TEMPLATE) => &template_fields[..],
+    _ => spec.options.fields.as_deref().unwrap_or(fields),
+  };
+  let mut columns = Vec::new();
+  let mut add_column = |name: &str| {
+    if !columns.iter().any(|field| field == name) {
+      columns.push(name.to_string());
+    }
+  };
+  if spec.options.normalize_fields {
+    let renames = mapped_field_names(&spec.field_map);
+    if !spec.options.drop_unmapped_fields {
+      for column in stored {
+        match renames.iter().find(|(_, field)| is_mapped_column(column, field)) {
+          Some((name, _)) => add_column(name),
+          None => add_column(column),
+        }
+      }
+    }
+    for (name, _) in renames {
+      add_column(name);
+    }
+  } else {
+    for column in stored {
+      add_column(column);
+    }
+  }
+  if spec.options.include_weight {
+    add_column("weight");
+  }
+  for field in &spec.options.inject_fields {
+    add_column(field.name.trim());
+  }
+  if spec.options.system_prompt.is_some() {
+    add_column(SYSTEM_FIELD);
+  }
+  columns
+}
+
+/// Checks that CSV exports can be written and read back with `options`.
+pub(crate) fn validate_csv_options(options: &CsvOptions) -> Result<(), String> {
+  let delimiter = options.delimiter;
+  if !delimiter.is_ascii() || matches!(delimiter, b'"' | b'\n' | b'\r') {
+    return Err(format!("Unsupported CSV delimiter: {:?}", delimiter as char));
+  }
+  if !matches!(options.quote_style.as_str(), "necessary" | "always" | "non_numeric") {
+    return Err(format!("Unsupported CSV quote style: {}", options.quote_style));
   }
   Ok(())
 }
+
+/// CSV writer of an export, with its header row written unless turned off.
+pub(crate) fn create_csv_writer(
+  spec: &ExportSpec,
+  columns: &[String],
+) -> Result<csv::Writer<ExportWriter>, String> {
+  let options = &spec.options.csv;
+  let quote_style = match options.quote_style.as_str() {
+    "always" => csv::QuoteStyle::Always,
+    "non_numeric" => csv::QuoteStyle::NonNumeric,
+    _ => csv::QuoteStyle::Necessary,
+  };
+  let mut writer = csv::WriterBuilder::new()
+    .delimiter(options.delimiter)
+    .quote_style(quote_style)
+    .from_writer(ExportWriter::create(&spec.path, spec.compression)?);
+  if options.write_header {
+    writer.write_record(columns).map_err(|e| e.to_string())?;
+  }
+  Ok(writer)
+}
+
+/// Dotted columns a flattened CSV export spreads nested values over, in the
+/// order they were first found.
+#[derive(Default)]
+pub(crate) struct NestedColumns {
+  seen: HashSet<String>,
+  order: Vec<String>,
+}
+
+impl NestedColumns {
+  pub(crate) fn add(&mut self, record: &Value, arrays: bool) {
+    for (key, _) in flatten_record(record, arrays) {
+      if !self.seen.contains(&key) {
+        self.seen.insert(key.clone());
+        self.order.push(key);
+      }
+    }
+  }
+
+  /// `columns` with each one followed by the dotted columns found under it,
+  /// and left out when all its values were spread over those.
+  pub(crate) fn spread(&self, columns: &[String]) -> Vec<String> {
+    let mut spread = Vec::new();
+    let mut add_column = |name: &String| {
+      if !spread.contains(name) {
+        spread.push(name.clone());
+      }
+    };
+    for column in columns {
+      let prefix = format!("{column}.");
+      let nested = self
+        .order
+        .iter()
+        .filter(|key| key.starts_with(&prefix))
+        .collect::<Vec<_>>();
+      if nested.is_empty() || self.seen.contains(column) {
+        add_column(column);
+      }
+      for key in nested {
+        add_column(key);
+      }
+    }
+    spread
+  }
+}
+
+/// Columns of a flattened CSV export of `ids`, found by preparing every
+/// record before anything is written.
+fn scan_nested_columns(
+  store: &DatasetStore,
+  ids: &[usize],
+  spec: &ExportSpec,
+  truncate: Option<&TruncateSpec>,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<Vec<String>, String> {
+  let mut nested = NestedColumns::default();
+  for (idx, id) in ids.iter().copied().enumerate() {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Export canceled".to_string());
+    }
+    let record = read_record_value(store, id)?;
+    let prepared = prepare_export_record(record, spec, truncate, &mut ExportSummary::default());
+    if let Some(record) = prepared {
+      nested.add(&record, spec.options.flatten_arrays);
+    }
+    if idx.is_multiple_of(1000) {
+      on_progress(idx, ids.len());
+    }
+  }
+  Ok(nested.spread(&export_columns(&store.fields, spec)))
+}
+
+pub(crate) fn csv_row(record: &Value, columns: &[String], options: &ExportOptions) -> Vec<String> {
+  if options.flatten_nested {
+    let flat = flatten_record(record, options.flatten_arrays)
+      .into_iter()
+      .collect::<HashMap<_, _>>();
+    return columns
+      .iter()
+      .map(|column| flat.get(column).map(|value| value_to_string(value)).unwrap_or_default())
+      .collect();
+  }
+  columns
+    .iter()
+    .map(|field| record.get(field).map(value_to_string).unwrap_or_default())
+    .collect()
+}
+
+/// Writes the records in `ids` in the given order, returning the ids written
+/// in file order, which leaves out the records the options skipped.
+/// `on_progress` gets the phase, `write` and then `verify` when the export is
+/// verified.
+pub fn export_dataset(
+  store: &DatasetStore,
+  ids: &[usize],
+  spec: &ExportSpec,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(&str, usize, usize),
+) -> Result<(ExportSummary, Vec<usize>), String> {
+  if cancel.load(Ordering::SeqCst) {
+    return Err("Export canceled".to_string());
+  }
+  if !matches!(spec.format.as_str(), "json" | "jsonl" | "csv" | "sqlite") {
+    return Err(format!("Unsupported export format: {}", spec.format));
+  }
+  validate_injected_fields(&spec.options)?;
+  validate_export_fields(&spec.options)?;
+  validate_export_template(spec)?;
+  if spec.format == "csv" {
+    validate_csv_options(&spec.options.csv)?;
+  }
+  if spec.options.flatten_nested && spec.format != "csv" {
+    return Err("Only CSV exports flatten nested fields".to_string());
+  }
+  if spec.options.pretty && spec.format != "json" {
+    return Err("Only JSON array exports can be pretty-printed".to_string());
+  }
+  if spec.format == "sqlite" {
+    if spec.compression != ExportCompression::None {
+      return Err("SQLite exports cannot be compressed".to_string());
+    }
+    if spec.options.verify {
+      return Err("SQLite exports cannot be verified".to_string());
+    }
+  }
+  let truncate = spec
+    .options
+    .truncate
+    .as_ref()
+    .map(TruncateSpec::from_options)
+    .transpose()?;
+  let truncate = truncate.as_ref();
+  write_staged(spec, |staged| {
+    let columns = if spec.options.flatten_nested {
+      scan_nested_columns(store, ids, staged, truncate, cancel, |current, total| {
+        on_progress("columns", current, total)
+      })?
+    } else {
+      export_columns(&store.fields, spec)
+    };
+    let (mut summary, written) =
+      write_export(store, ids, staged, truncate, &columns, cancel, |current, total| {
+        on_progress("write", current, total)
+      })?;
+    if !spec.options.verify {
+      return Ok((summary, written));
+    }
+    let checked =
+      verify_export(store, staged, truncate, &columns, &written, cancel, |current, total| {
+        on_progress("verify", current, total)
+      })?;
+    match checked {
+      Ok(spot_checked) => {
+        summary.verified = true;
+        summary.spot_checked_count = spot_checked;
+        Ok((summary, written))
+      }
+      Err(mismatch) => Err(format!(
+        "Export verification failed, {} was not written: {mismatch}",
+        spec.path.display()
+      )),
+    }
+  })
+}
+
+/// Runs `write` against a temp file next to the target and renames it into
+/// place once `write` succeeds. On an error or cancel the temp file is
+/// removed, so the target is either the complete export or what it was.
+pub(crate) fn write_staged<T>(
+  spec: &ExportSpec,
+  write: impl FnOnce(&ExportSpec) -> Result<T, String>,
+) -> Result<T, String> {
+  let staged = ExportSpec {
+    path: unique_temp_path(&spec.path),
+    ..spec.clone()
+  };
+  let result = write(&staged).and_then(|value| {
+    replace_with_temp(&staged.path, &spec.path)?;
+    Ok(value)
+  });
+  if result.is_err() {
+    let _ = fs::remove_file(&staged.path);
+  }
+  result
+}
+
+/// `record` as an element of a JSON export: on one line, or with `pretty`
+/// indented to sit inside the array.
+pub(crate) fn export_json_text(record: &Value, pretty: bool) -> Result<String, String> {
+  if !pretty {
+    return serde_json::to_string(record).map_err(|e| e.to_string());
+  }
+  let text = serde_json::to_string_pretty(record).map_err(|e| e.to_string())?;
+  // Strings never hold a raw line break, so every one starts a new line.
+  Ok(text.replace('\n', "\n  "))
+}
+
+/// What goes before a record of a JSON array export: a line break after `[`
+/// or after the previous record's comma, indented when pretty-printed.
+pub(crate) fn json_separator(first: bool, pretty: bool) -> &'static [u8] {
+  match (first, pretty) {
+    (true, false) => b"",
+    (false, false) => b",\n",
+    (true, true) => b"\n  ",
+    (false, true) => b",\n  ",
+  }
+}
+
+/// The closing bracket of a JSON array export, on its own line when records
+/// were pretty-printed.
+pub(crate) fn json_array_end(empty: bool, pretty: bool) -> &'static [u8] {
+  if pretty && !empty {
+    b"\n]"
+  } else {
+    b"]"
+  }
+}
+
+pub(crate) fn new_export_summary(spec: &ExportSpec) -> ExportSummary {
+  ExportSummary {
+    injected_fields: spec
+      .options
+      .inject_fields
+      .iter()
+      .map(|field| field.name.trim().to_string())
+      .collect(),
+    ..ExportSummary::default()
+  }
+}
+
+/// Writes the export, returning the ids written in file order. `columns` are
+/// those of a CSV export.
+fn write_export(
+  store: &DatasetStore,
+  ids: &[usize],
+  spec: &ExportSpec,
+  truncate: Option<&TruncateSpec>,
+  columns: &[String],
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<(ExportSummary, Vec<usize>), String> {
+  if spec.format == "sqlite" {
+    return write_sqlite_export(store, ids, spec, truncate, cancel, on_progress);
+  }
+  let mut summary = new_export_summary(spec);
+  let mut written = Vec::new();
+  let mut timer = StageTimer::new();
+  if spec.format == "csv" {
+    let mut writer = create_csv_writer(spec, columns)?;
+    for (idx, id) in ids.iter().copied().enumerate() {
+      if cancel.load(Ordering::SeqCst) {
+        return Err("Export canceled".to_string());
+      }
+      let record = timer.time("read", || read_record_value(store, id))?;
+      timer.count("read", 1);
+      let Some(record) = prepare_export_record(record, spec, truncate, &mut summary) else {
+        continue;
+      };
+      timer.time("write", || {
+        writer
+          .write_record(csv_row(&record, columns, &spec.options))
+          .map_err(|e| e.to_string())
+      })?;
+      timer.count("write", 1);
+      summary.exported_count += 1;
+      written.push(id);
+      if idx.is_multiple_of(1000) {
+        on_progress(idx, ids.len());
+      }
+    }
+    timer.time("write", || {
+      let file = writer.into_inner().map_err(|e| e.to_string())?;
+      file.finish().map_err(|e| e.to_string())
+    })?;
+  } else {
+    // A JSON array, or for `jsonl` one record per line.
+    let array = spec.format == "json";
+    let mut file = ExportWriter::create(&spec.path, spec.compression)?;
+    if array {
+      file.write_all(b"[").map_err(|e| e.to_string())?;
+    }
+    for (idx, id) in ids.iter().copied().enumerate() {
+      if cancel.load(Ordering::SeqCst) {
+        return Err("Export canceled".to_string());
+      }
+      let line = timer.time("read", || read_record_line(store, id))?;
+      timer.count("read", 1);
+      let trimmed = line.trim();
+      let pretty = spec.options.pretty;
+      let serialized = if spec.options.rewrites_records() || pretty {
+        let record: Value = serde_json::from_str(trimmed).map_err(|e| e.to_string())?;
+        let Some(record) = prepare_export_record(record, spec, truncate, &mut summary) else {
+          continue;
+        };
+        export_json_text(&record, pretty)?
+      } else {
+        trimmed.to_string()
+      };
+      let separator = if array {
+        json_separator(summary.exported_count == 0, pretty)
+      } else {
+        b""
+      };
+      timer.time("write", || {
+        file.write_all(separator).map_err(|e| e.to_string())?;
+        file
+          .write_all(serialized.as_bytes())
+          .map_err(|e| e.to_string())?;
+        if !array {
+          file.write_all(b"\n").map_err(|e| e.to_string())?;
+        }
+        Ok::<_, String>(())
+      })?;
+      timer.count("write", 1);
+      summary.exported_count += 1;
+      written.push(id);
+      if idx.is_multiple_of(1000) {
+        on_progress(idx, ids.len());
+      }
+    }
+    if array {
+      let end = json_array_end(summary.exported_count == 0, spec.options.pretty);
+      file.write_all(end).map_err(|e| e.to_string())?;
+    }
+    timer.time("write", || file.finish()).map_err(|e| e.to_string())?;
+  }
+  summary.timings = timer.finish();
+  Ok((summary, written))
+}
+
+/// `id` as the export writes it: its JSON text, or its CSV row.
+fn expected_export(
+  store: &DatasetStore,
+  id: usize,
+  spec: &ExportSpec,
+  truncate: Option<&TruncateSpec>,
+  columns: &[String],
+) -> Result<Option<Value>, String> {
+  let record = read_record_value(store, id)?;
+  let Some(record) = prepare_export_record(record, spec, truncate, &mut ExportSummary::default())
+  else {
+    return Ok(None);
+  };
+  if spec.format == "csv" {
+    return Ok(Some(Value::from(csv_row(&record, columns, &spec.options))));
+  }
+  if spec.options.pretty {
+    return Ok(Some(record));
+  }
+  if spec.options.rewrites_records() {
+    let text = serde_json::to_string(&record).map_err(|e| e.to_string())?;
+    Ok(Some(Value::String(text)))
+  } else {
+    Ok(Some(Value::String(read_record_line(store, id)?.trim().to_string())))
+  }
+}
+
+/// Top-level fields a JSON export of `spec` may write when its options narrow
+/// them: the chosen fields, the mapped ones when unmapped fields are dropped,
+/// or the alpaca template's, and then the fields the export adds. `None` when
+/// stored fields pass through.
+fn allowed_export_fields(spec: &ExportSpec) -> Option<HashSet<String>> {
+  let options = &spec.options;
+  let mapped = || mapped_field_names(&spec.field_map).into_iter().map(|(name, _)| name);
+  let mut allowed = match (options.template.as_deref(), &options.fields) {
+    (Some(ALPACA_TEMPLATE), _) => ["instruction", "input", "output"].map(String::from).into(),
+    // A record that already holds turns passes through the sharegpt template.
+    (Some(_), _) => return None,
+    (None, Some(fields)) => fields.iter().cloned().collect::<HashSet<_>>(),
+    (None, None) if options.normalize_fields && options.drop_unmapped_fields => {
+      mapped().map(String::from).collect()
+    }
+    (None, None) => return None,
+  };
+  if options.normalize_fields {
+    allowed.extend(mapped().map(String::from));
+  }
+  if options.include_weight {
+    allowed.insert("weight".to_string());
+  }
+  allowed.extend(options.inject_fields.iter().map(|field| field.name.trim().to_string()));
+  if options.system_prompt.is_some() {
+    allowed.insert(SYSTEM_FIELD.to_string());
+  }
+  Some(allowed)
+}
+
+/// Reads a written export back: every record must parse, the count must be
+/// the one written, no JSON record may have a field the options leave out,
+/// and a seeded sample of `written` (ids in file order) must match exporting
+/// those records again byte for byte. CSV exports are held to their columns
+/// by the header check. Returns the number of records spot-checked, or the
+/// first mismatch.
+fn verify_export(
+  store: &DatasetStore,
+  spec: &ExportSpec,
+  truncate: Option<&TruncateSpec>,
+  columns: &[String],
+  written: &[usize],
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<Result<usize, ExportMismatch>, String> {
+  let mut rng = StdRng::seed_from_u64(VERIFY_SEED);
+  let sample_size = VERIFY_SPOT_CHECKS.min(written.len());
+  let checks = rand::seq::index::sample(&mut rng, written.len(), sample_size)
+    .into_iter()
+    .map(|position| (position, written[position]))
+    .collect::<HashMap<_, _>>();
+  let reader = match export_reader(&spec.path, spec.compression) {
+    Ok(reader) => reader,
+    Err(reason) => return Ok(Err(ExportMismatch::Unreadable { position: 0, reason })),
+  };
+  let allowed = allowed_export_fields(spec);
+  let leaked = |position: usize, record: &Value| {
+    let allowed = allowed.as_ref()?;
+    let field = record.as_object()?.keys().find(|key| !allowed.contains(*key))?;
+    Some(ExportMismatch::LeakedField {
+      position,
+      field: field.clone(),
+    })
+  };
+  // Compares one record read back with exporting it again.
+  let mut check = |position: usize, found: Value| -> Result<Option<ExportMismatch>, String> {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Export canceled".to_string());
+    }
+    if position.is_multiple_of(1000) {
+      on_progress(position, written.len());
+    }
+    let Some(&id) = checks.get(&position) else {
+      return Ok(None);
+    };
+    let expected = expected_export(store, id, spec, truncate, columns)?;
+    if expected.as_ref() == Some(&found) {
+      return Ok(None);
+    }
+    let text = |value: &Value| match value {
+      Value::String(text) => text.clone(),
+      other => other.to_string(),
+    };
+    Ok(Some(ExportMismatch::Record {
+      position,
+      id,
+      expected: expected.as_ref().map(text).unwrap_or_else(|| "no record".to_string()),
+      found: text(&found),
+    }))
+  };
+
+  let mut found = 0;
+  if spec.format == "csv" {
+    let write_header = spec.options.csv.write_header;
+    let mut reader = csv::ReaderBuilder::new()
+      .delimiter(spec.options.csv.delimiter)
+      .has_headers(write_header)
+      .from_reader(reader);
+    if write_header {
+      let header = match reader.headers() {
+        Ok(header) => header.iter().map(str::to_string).collect::<Vec<_>>(),
+        Err(e) => {
+          let reason = e.to_string();
+          return Ok(Err(ExportMismatch::Unreadable { position: 0, reason }));
+        }
+      };
+      if header != columns {
+        return Ok(Err(ExportMismatch::Header {
+          expected: columns.to_vec(),
+          found: header,
+        }));
+      }
+    }
+    for row in reader.records() {
+      let row = match row {
+        Ok(row) => row,
+        Err(e) => {
+          let reason = e.to_string();
+          return Ok(Err(ExportMismatch::Unreadable { position: found, reason }));
+        }
+      };
+      if let Some(mismatch) = check(found, Value::from_iter(row.iter()))? {
+        return Ok(Err(mismatch));
+      }
+      found += 1;
+    }
+  } else if spec.format == "jsonl" {
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+    loop {
+      line.clear();
+      let unreadable = |reason: String| ExportMismatch::Unreadable {
+        position: found,
+        reason,
+      };
+      match reader.read_line(&mut line) {
+        Ok(0) => break,
+        Ok(_) => {}
+        Err(e) => return Ok(Err(unreadable(e.to_string()))),
+      }
+      let Some(text) = line.strip_suffix('\n') else {
+        return Ok(Err(unreadable("the last record is not followed by a line break".to_string())));
+      };
+      let record = match serde_json::from_str::<Value>(text) {
+        Ok(record) => record,
+        Err(e) => return Ok(Err(unreadable(e.to_string()))),
+      };
+      if let Some(mismatch) = leaked(found, &record) {
+        return Ok(Err(mismatch));
+      }
+      if let Some(mismatch) = check(found, Value::from(text))? {
+        return Ok(Err(mismatch));
+      }
+      found += 1;
+    }
+  } else if spec.options.pretty {
+    // Pretty records span lines, so the array is parsed as a whole and its
+    // records compared as values.
+    let mut stopped = None;
+    let streamed = stream_json_records(reader, None, |value| {
+      let checked = match leaked(found, &value) {
+        Some(mismatch) => Ok(Some(mismatch)),
+        None => check(found, value),
+      };
+      match checked {
+        Ok(None) => {
+          found += 1;
+          Ok(())
+        }
+        Ok(Some(mismatch)) => {
+          stopped = Some(Ok(mismatch));
+          Err(String::new())
+        }
+        Err(err) => {
+          stopped = Some(Err(err));
+          Err(String::new())
+        }
+      }
+    });
+    match stopped {
+      Some(Ok(mismatch)) => return Ok(Err(mismatch)),
+      Some(Err(err)) => return Err(err),
+      None => {}
+    }
+    if let Err(reason) = streamed {
+      return Ok(Err(ExportMismatch::Unreadable { position: found, reason }));
+    }
+  } else {
+    // The writer puts each record on its own line: `[` before the first,
+    // `,` after each but the last and `]` after that.
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+    let mut closed = false;
+    loop {
+      line.clear();
+      let unreadable = |reason: &str| ExportMismatch::Unreadable {
+        position: found,
+        reason: reason.to_string(),
+      };
+      match reader.read_line(&mut line) {
+        Ok(0) => break,
+        Ok(_) => {}
+        Err(e) => return Ok(Err(unreadable(&e.to_string()))),
+      }
+      if closed {
+        return Ok(Err(unreadable("text after the closing bracket")));
+      }
+      let mut text = line.strip_suffix('\n').unwrap_or(&line);
+      if found == 0 {
+        let Some(rest) = text.strip_prefix('[') else {
+          return Ok(Err(unreadable("the file does not start with a JSON array")));
+        };
+        text = rest;
+      }
+      if let Some(rest) = text.strip_suffix(',') {
+        text = rest;
+      } else if let Some(rest) = text.strip_suffix(']') {
+        text = rest;
+        closed = true;
+      } else {
+        return Ok(Err(unreadable("record is not followed by a comma or the closing bracket")));
+      }
+      if found == 0 && closed && text.is_empty() {
+        break;
+      }
+      let record = match serde_json::from_str::<Value>(text) {
+        Ok(record) => record,
+        Err(e) => return Ok(Err(unreadable(&e.to_string()))),
+      };
+      if let Some(mismatch) = leaked(found, &record) {
+        return Ok(Err(mismatch));
+      }
+      if let Some(mismatch) = check(found, Value::from(text))? {
+        return Ok(Err(mismatch));
+      }
+      found += 1;
+    }
+    if !closed {
+      let reason = "the file ends before the closing bracket".to_string();
+      return Ok(Err(ExportMismatch::Unreadable { position: found, reason }));
+    }
+  }
+  if found != written.len() {
+    return Ok(Err(ExportMismatch::Count {
+      expected: written.len(),
+      found,
+    }));
+  }
+  on_progress(found, written.len());
+  Ok(Ok(checks.len()))
+}
+
+/// Writes one JSON line per duplicate cluster: the kept record, the records
+/// dropped as its duplicates and the lowest similarity between them, so a
+/// reviewer can spot false positives.
+pub fn export_duplicate_report(
+  store: &DatasetStore,
+  clusters: &[DuplicateCluster],
+  path: &Path,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<ExportSummary, String> {
+  let mut timer = StageTimer::new();
+  let mut file = BufWriter::new(create_output_file(path)?);
+  for (idx, cluster) in clusters.iter().enumerate() {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Export canceled".to_string());
+    }
+    let (kept, removed) = timer.time("read", || -> Result<_, String> {
+      let kept = read_record_value(store, cluster.kept)?;
+      let removed = cluster
+        .removed
+        .iter()
+        .map(|id| read_record_value(store, *id))
+        .collect::<Result<Vec<_>, _>>()?;
+      Ok((kept, removed))
+    })?;
+    timer.count("read", 1 + cluster.removed.len());
+    let line = json!({
+      "kept": kept,
+      "removed": removed,
+      "similarity": cluster.similarity,
+    });
+    timer.time("write", || {
+      serde_json::to_writer(&mut file, &line).map_err(|e| e.to_string())?;
+      file.write_all(b"\n").map_err(|e| e.to_string())
+    })?;
+    timer.count("write", 1);
+    if idx.is_multiple_of(100) {
+      on_progress(idx, clusters.len());
+    }
+  }
+  timer.time("write", || file.flush()).map_err(|e| e.to_string())?;
+  Ok(ExportSummary {
+    exported_count: clusters.len(),
+    timings: timer.finish(),
+    ..ExportSummary::default()
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::RwLock;
+  use std::thread;
+
+  use serde_json::json;
+
+  use super::*;
+  use crate::models::{InjectedField, TruncateOptions, DEFAULT_READ_AHEAD_CHUNKS};
+  use crate::state::InnerState;
+  use crate::test_support::{
+    assert_phases_in_order,
+    assert_stages_timed,
+    jsonl_store,
+    jsonl_store_with,
+    stored_records,
+    text_field_map,
+    TempDir,
+  };
+
+  #[test]
+  fn preview_pages_read_off_the_state_lock_while_it_changes() {
+    let dir = TempDir::new();
+    let records = (0..200)
+      .map(|id| json!({ "instruction": format!("question {id}"), "output": "answer" }))
+      .collect::<Vec<_>>();
+    let state = RwLock::new(InnerState {
+      dataset: Some(jsonl_store(&dir, &records)),
+      field_map: text_field_map(),
+      ..InnerState::default()
+    });
+    thread::scope(|scope| {
+      for page in 0..4 {
+        let state = &state;
+        scope.spawn(move || {
+          let ids = (page * 50..page * 50 + 50).collect::<Vec<_>>();
+          for _ in 0..25 {
+            let source = PreviewPageSource::locate(&state.read().unwrap(), "all", page + 1, 50);
+            let (items, truncated) = source.unwrap().read(usize::MAX).unwrap();
+            assert!(!truncated);
+            for (item, id) in items.iter().zip(&ids) {
+              assert_eq!(item.id, *id);
+              assert_eq!(item.fields[0].value, format!("question {id}"));
+            }
+          }
+        });
+      }
+      // Commands that write the state get the lock between page reads.
+      for id in 0..100 {
+        state.write().unwrap().filtered_ids = Some(IdSet::from_iter([id]));
+      }
+    });
+  }
+
+  #[test]
+  fn read_ahead_does_not_change_the_imported_store() {
+    let records = (0..20_000)
+      .map(|id| json!({ "instruction": format!("question {id} {}", "x".repeat(id % 200)) }))
+      .collect::<Vec<_>>();
+    let stores = [0, DEFAULT_READ_AHEAD_CHUNKS, usize::MAX].map(|read_ahead_chunks| {
+      let dir = TempDir::new();
+      let options = ImportOptions {
+        read_ahead_chunks,
+        ..ImportOptions::default()
+      };
+      let store = jsonl_store_with(&dir, &records, &options);
+      fs::read(&store.store_path).unwrap()
+    });
+    assert!(stores[0].len() > 8 * READ_CHUNK_BYTES);
+    assert!(stores.iter().all(|store| *store == stores[0]));
+  }
+
+  #[test]
+  fn preview_pages_stop_at_the_byte_cap_but_keep_one_record() {
+    let dir = TempDir::new();
+    let records = (0..10)
+      .map(|id| json!({ "instruction": format!("question {id} {}", "x".repeat(200)) }))
+      .collect::<Vec<_>>();
+    let store = jsonl_store(&dir, &records);
+    let offsets = record_offsets(&store, &(0..10).collect::<Vec<_>>()).unwrap();
+    let field_map = text_field_map();
+    let (path, record_bytes) = (&store.store_path, store.preview_record_bytes());
+
+    let (items, truncated) = preview_items(path, record_bytes, &offsets, &field_map, 1).unwrap();
+    assert_eq!((items.len(), truncated), (1, true));
+    let (items, truncated) = preview_items(path, record_bytes, &offsets, &field_map, 1000).unwrap();
+    assert!(truncated && (2..10).contains(&items.len()));
+    assert_eq!(items.last().map(|item| item.id), Some(items.len() - 1));
+    let (items, truncated) =
+      preview_items(path, record_bytes, &offsets, &field_map, usize::MAX).unwrap();
+    assert_eq!((items.len(), truncated), (10, false));
+  }
+
+  /// The `instruction` field of every record in `store`, in order.
+  fn instructions(store: &DatasetStore) -> Vec<String> {
+    store_lines(store)
+      .unwrap()
+      .map(|line| line.unwrap().record().unwrap().unwrap()["instruction"].to_string())
+      .collect()
+  }
+
+  #[test]
+  fn a_canceled_crlf_import_continues_where_it_stopped() {
+    let dir = TempDir::new();
+    let jsonl = (0..1200)
+      .map(|id| format!("{}\r\n", json!({ "instruction": format!("q{id}"), "output": "a" })))
+      .collect::<String>();
+    let csv = (0..1200).fold("instruction,output\r\n".to_string(), |mut csv, id| {
+      csv.push_str(&format!("q{id},a\r\n"));
+      csv
+    });
+    for (name, contents) in [("source.jsonl", jsonl), ("source.csv", csv)] {
+      let source = dir.join(name);
+      fs::write(&source, contents).unwrap();
+      let cancel = AtomicBool::new(false);
+      let (first, _) = ingest_dataset(
+        &source,
+        &dir.join(&format!("{name}-first")),
+        &ImportOptions::default(),
+        &cancel,
+        |count, _| cancel.store(count >= 500, Ordering::SeqCst),
+      )
+      .unwrap();
+      assert!(first.truncated, "{name}");
+      let options = ImportOptions {
+        resume_offset: first.resume_offset,
+        ..ImportOptions::default()
+      };
+      let (rest, _) = ingest_dataset(
+        &source,
+        &dir.join(&format!("{name}-rest")),
+        &options,
+        &AtomicBool::new(false),
+        |_, _| {},
+      )
+      .unwrap();
+      let mut all = instructions(&first);
+      all.extend(instructions(&rest));
+      let expected = (0..1200).map(|id| format!("\"q{id}\"")).collect::<Vec<_>>();
+      assert_eq!(all, expected, "{name}");
+    }
+  }
+
+  #[test]
+  fn a_store_refuses_records_past_its_limit() {
+    let dir = TempDir::new();
+    let mut writer = StoreWriter::create(dir.path()).unwrap();
+    writer.max_records = 2;
+    let record = json!({ "instruction": "q" });
+    writer.write_value(&record).unwrap();
+    writer.write_value(&record).unwrap();
+    let err = writer.write_value(&record).unwrap_err();
+    assert_eq!(err, "A dataset holds at most 2 records");
+    assert_eq!(writer.len(), 2);
+    writer.discard();
+  }
+
+  fn export_to(
+    store: &DatasetStore,
+    path: &Path,
+    format: &str,
+    options: ExportOptions,
+  ) -> Result<ExportSummary, String> {
+    export_mapped(store, path, format, options, text_field_map())
+  }
+
+  fn export_mapped(
+    store: &DatasetStore,
+    path: &Path,
+    format: &str,
+    options: ExportOptions,
+    field_map: FieldMap,
+  ) -> Result<ExportSummary, String> {
+    let spec = ExportSpec {
+      path: path.to_path_buf(),
+      format: format.to_string(),
+      compression: ExportCompression::None,
+      options,
+      field_map,
+    };
+    let ids = (0..store.record_count).collect::<Vec<_>>();
+    export_dataset(store, &ids, &spec, &AtomicBool::new(false), |_, _, _| {})
+      .map(|(summary, _)| summary)
+  }
+
+  #[test]
+  fn a_canceled_export_leaves_no_files_behind() {
+    let dir = TempDir::new();
+    let records = (0..6000)
+      .map(|id| json!({ "instruction": format!("q{id}"), "output": "a" }))
+      .collect::<Vec<_>>();
+    let store = jsonl_store(&dir, &records);
+    let ids = (0..store.record_count).collect::<Vec<_>>();
+    let out = dir.join("out");
+    fs::create_dir(&out).unwrap();
+    for format in ["json", "jsonl", "csv", "sqlite"] {
+      let spec = ExportSpec {
+        path: out.join(format!("export.{format}")),
+        format: format.to_string(),
+        compression: ExportCompression::None,
+        options: ExportOptions::default(),
+        field_map: text_field_map(),
+      };
+      let cancel = AtomicBool::new(false);
+      let mut progressed = 0;
+      let err = export_dataset(&store, &ids, &spec, &cancel, |_, current, _| {
+        progressed = current;
+        cancel.store(current > 0, Ordering::SeqCst);
+      })
+      .unwrap_err();
+      assert_eq!(err, "Export canceled", "{format}");
+      assert!(progressed > 0 && progressed < ids.len(), "{format}");
+      assert!(!spec.path.exists(), "{format}");
+      assert_eq!(fs::read_dir(&out).unwrap().count(), 0, "{format}");
+    }
+  }
+
+  #[test]
+  fn exports_return_only_the_ids_they_wrote_in_file_order() {
+    let dir = TempDir::new();
+    let records = [
+      json!({ "instruction": "q0", "output": "a", "w": 2 }),
+      json!({ "instruction": "q1", "output": "a", "w": "heavy" }),
+      json!({ "instruction": "q2", "output": "a", "w": 1 }),
+    ];
+    let store = jsonl_store(&dir, &records);
+    for format in ["jsonl", "json", "csv", "sqlite"] {
+      let spec = ExportSpec {
+        path: dir.join(&format!("out.{format}")),
+        format: format.to_string(),
+        compression: ExportCompression::None,
+        options: ExportOptions {
+          include_weight: true,
+          invalid_weight: "skip".to_string(),
+          ..ExportOptions::default()
+        },
+        field_map: FieldMap {
+          weight: Some("w".to_string()),
+          ..text_field_map()
+        },
+      };
+      let cancel = AtomicBool::new(false);
+      let (summary, written) =
+        export_dataset(&store, &[2, 1, 0], &spec, &cancel, |_, _, _| {}).unwrap();
+      assert_eq!(summary.skipped_count, 1, "{format}");
+      assert_eq!(written, vec![2, 0], "{format}");
+    }
+  }
+
+  #[test]
+  fn an_empty_export_is_an_empty_file_or_an_empty_array() {
+    let dir = TempDir::new();
+    let store = jsonl_store(&dir, &[json!({ "instruction": "q", "output": "a" })]);
+    let cases = [("jsonl", false, ""), ("json", false, "[]"), ("json", true, "[]")];
+    for (format, pretty, expected) in cases {
+      let spec = ExportSpec {
+        path: dir.join(&format!("empty-{pretty}.{format}")),
+        format: format.to_string(),
+        compression: ExportCompression::None,
+        options: ExportOptions {
+          pretty,
+          ..ExportOptions::default()
+        },
+        field_map: text_field_map(),
+      };
+      let (summary, _) =
+        export_dataset(&store, &[], &spec, &AtomicBool::new(false), |_, _, _| {}).unwrap();
+      assert_eq!(summary.exported_count, 0);
+      assert_eq!(fs::read_to_string(&spec.path).unwrap(), expected, "{format}");
+    }
+  }
+
+  fn ingest_file(dir: &TempDir, name: &str, contents: &str) -> DatasetStore {
+    let source = dir.join(name);
+    fs::write(&source, contents).unwrap();
+    let store_dir = dir.join(&format!("{name}-store"));
+    let cancel = AtomicBool::new(false);
+    let options = ImportOptions::default();
+    ingest_dataset(&source, &store_dir, &options, &cancel, |_, _| {}).unwrap().0
+  }
+
+  #[test]
+  fn semicolon_csvs_are_sniffed_past_quoted_commas() {
+    let dir = TempDir::new();
+    let store = ingest_file(
+      &dir,
+      "prices.csv",
+      "name;price;note\n\"Müller, K.\";3,50;\"a; b\"\nSchmidt;4,20;c\n",
+    );
+    assert_eq!(store.delimiter, Some(b';'));
+    assert_eq!(
+      stored_records(&store),
+      vec![
+        json!({ "name": "Müller, K.", "price": "3,50", "note": "a; b" }),
+        json!({ "name": "Schmidt", "price": "4,20", "note": "c" }),
+      ]
+    );
+  }
+
+  #[test]
+  fn tab_separated_files_are_read_by_extension_or_sniffed() {
+    let dir = TempDir::new();
+    let contents = "instruction\toutput\nsay hi, please\thi\n";
+    let expected = vec![json!({ "instruction": "say hi, please", "output": "hi" })];
+    for name in ["pairs.tsv", "pairs.csv"] {
+      let store = ingest_file(&dir, name, contents);
+      assert_eq!(store.delimiter, Some(b'\t'), "{name}");
+      assert_eq!(stored_records(&store), expected, "{name}");
+    }
+    assert_eq!(sniff_delimiter(b"a,b,c\n1;2;3;4;5\n"), b',');
+    assert_eq!(sniff_delimiter(b"a|b|c\n"), b'|');
+    assert_eq!(sniff_delimiter(b"single\n"), b',');
+  }
+
+  #[test]
+  fn records_under_a_wrapper_key_are_imported() {
+    let dir = TempDir::new();
+    let text = r#"{"version": 2, "items": [{"q": "a"}, {"q": "b"}]}"#;
+    let store = ingest_file(&dir, "dump.json", text);
+    assert_eq!(stored_records(&store), vec![json!({ "q": "a" }), json!({ "q": "b" })]);
+  }
+
+  #[test]
+  fn csv_exports_follow_the_delimiter_header_and_quoting_options() {
+    let dir = TempDir::new();
+    let records = [
+      json!({ "instruction": "say \"hi\"", "output": "line1\nline2", "n": 3 }),
+      json!({ "instruction": "a;b,c", "output": "plain", "n": 4.5 }),
+    ];
+    let store = jsonl_store(&dir, &records);
+    let path = dir.join("default.csv");
+    export_to(&store, &path, "csv", ExportOptions::default()).unwrap();
+    assert_eq!(
+      fs::read_to_string(&path).unwrap(),
+      "instruction,n,output\n\"say \"\"hi\"\"\",3,\"line1\nline2\"\n\"a;b,c\",4.5,plain\n"
+    );
+
+    let rows = [
+      [("instruction", false), ("n", false), ("output", false)],
+      [("say \"hi\"", false), ("3", true), ("line1\nline2", false)],
+      [("a;b,c", false), ("4.5", true), ("plain", false)],
+    ];
+    for delimiter in [b',', b';', b'\t'] {
+      for write_header in [true, false] {
+        for quote_style in ["necessary", "always", "non_numeric"] {
+          let cell = |(text, numeric): (&str, bool)| {
+            let special = text.contains([delimiter as char, '"', '\n']);
+            let quoted = match quote_style {
+              "always" => true,
+              "non_numeric" => !numeric,
+              _ => special,
+            };
+            if quoted {
+              format!("\"{}\"", text.replace('"', "\"\""))
+            } else {
+              text.to_string()
+            }
+          };
+          let expected = rows
+            .iter()
+            .skip(usize::from(!write_header))
+            .map(|row| {
+              let cells = row.iter().map(|field| cell(*field)).collect::<Vec<_>>();
+              format!("{}\n", cells.join(&(delimiter as char).to_string()))
+            })
+            .collect::<String>();
+          let options = ExportOptions {
+            csv: CsvOptions {
+              delimiter,
+              write_header,
+              quote_style: quote_style.to_string(),
+            },
+            ..ExportOptions::default()
+          };
+          let path = dir.join(&format!("{delimiter}-{write_header}-{quote_style}.csv"));
+          export_to(&store, &path, "csv", options).unwrap();
+          let case = format!("{:?} {write_header} {quote_style}", delimiter as char);
+          assert_eq!(fs::read_to_string(&path).unwrap(), expected, "{case}");
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn sharegpt_exports_single_turns_and_pass_conversations_through() {
+    let dir = TempDir::new();
+    let turns = json!([{ "from": "human", "value": "hi" }, { "from": "gpt", "value": "hello" }]);
+    let records = [
+      json!({ "instruction": "2+2?", "output": "4", "topic": "math" }),
+      json!({ "instruction": turns, "output": "ignored", "id": 7 }),
+      json!({ "instruction": "no answer", "output": " " }),
+    ];
+    let store = jsonl_store(&dir, &records);
+    let path = dir.join("chat.jsonl");
+    let options = ExportOptions {
+      template: Some(SHAREGPT_TEMPLATE.to_string()),
+      ..ExportOptions::default()
+    };
+    let summary = export_to(&store, &path, "jsonl", options).unwrap();
+    assert_eq!((summary.exported_count, summary.template_skipped_count), (2, 1));
+    let lines = fs::read_to_string(&path)
+      .unwrap()
+      .lines()
+      .map(|line| serde_json::from_str::<Value>(line).unwrap())
+      .collect::<Vec<_>>();
+    let single = json!({
+      "conversations": [{ "from": "human", "value": "2+2?" }, { "from": "gpt", "value": "4" }]
+    });
+    assert_eq!(lines, vec![single, records[1].clone()]);
+
+    let field_map = FieldMap {
+      system: Some("persona".to_string()),
+      ..text_field_map()
+    };
+    let record = json!({ "persona": "Be brief.", "instruction": "hi", "output": "hey" });
+    assert_eq!(
+      sharegpt_record(record, &field_map).unwrap()["conversations"][0],
+      json!({ "from": "system", "value": "Be brief." })
+    );
+  }
+
+  #[test]
+  fn normalized_fields_replace_existing_keys_of_the_same_name() {
+    let dir = TempDir::new();
+    let records = [json!({ "prompt": "P", "response": "R", "instruction": "stale", "extra": 1 })];
+    let store = jsonl_store(&dir, &records);
+    let field_map = FieldMap {
+      instruction: Some("prompt".to_string()),
+      output: Some("response".to_string()),
+      ..FieldMap::default()
+    };
+    let options = ExportOptions {
+      normalize_fields: true,
+      ..ExportOptions::default()
+    };
+    let path = dir.join("normalized.jsonl");
+    export_mapped(&store, &path, "jsonl", options.clone(), field_map.clone()).unwrap();
+    let record: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(record, json!({ "instruction": "P", "output": "R", "extra": 1 }));
+
+    let path = dir.join("normalized.csv");
+    export_mapped(&store, &path, "csv", options, field_map).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "extra,instruction,output\n1,P,R\n");
+  }
+
+  #[test]
+  fn truncation_leaves_outputs_under_the_budget_byte_identical() {
+    let dir = TempDir::new();
+    let records = [
+      json!({ "instruction": "a", "output": "Xin chào các bạn. Hôm nay trời đẹp quá." }),
+      json!({ "instruction": "b", "output": "今天天气很好。", "meta": { "k": 1.50 } }),
+    ];
+    let store = jsonl_store(&dir, &records);
+    let plain = dir.join("plain.jsonl");
+    export_to(&store, &plain, "jsonl", ExportOptions::default()).unwrap();
+    let truncated = dir.join("truncated.jsonl");
+    let options = ExportOptions {
+      truncate: Some(TruncateOptions {
+        field: "output".to_string(),
+        max_tokens: 100,
+        boundary: "sentence".to_string(),
+        marker: "…".to_string(),
+      }),
+      ..ExportOptions::default()
+    };
+    let summary = export_to(&store, &truncated, "jsonl", options).unwrap();
+    assert_eq!((summary.truncated_count, summary.tokens_saved), (0, 0));
+    assert_eq!(fs::read(&truncated).unwrap(), fs::read(&plain).unwrap());
+  }
+
+  fn exported_lines(path: &Path) -> Vec<Value> {
+    fs::read_to_string(path)
+      .unwrap()
+      .lines()
+      .map(|line| serde_json::from_str(line).unwrap())
+      .collect()
+  }
+
+  #[test]
+  fn the_system_prompt_fills_only_records_without_a_system_turn() {
+    let dir = TempDir::new();
+    let user = json!({ "role": "user", "content": "hi" });
+    let records = [
+      json!({ "messages": [user] }),
+      json!({ "messages": [{ "role": "system", "content": "Be kind" }, user] }),
+      json!({ "conversations": [{ "from": "human", "value": "hi" }] }),
+      json!({ "conversations": [{ "from": "system", "value": "Be kind" }] }),
+      json!({ "instruction": "q" }),
+      json!({ "instruction": "q", "system": "Be kind" }),
+    ];
+    let store = jsonl_store(&dir, &records);
+    let prompt = |overwrite_existing| ExportOptions {
+      system_prompt: Some("Be brief".to_string()),
+      overwrite_existing,
+      ..ExportOptions::default()
+    };
+
+    let path = dir.join("kept.jsonl");
+    let summary = export_to(&store, &path, "jsonl", prompt(false)).unwrap();
+    assert_eq!((summary.system_prompt_count, summary.kept_existing_count), (3, 3));
+    let system = |content: &str| json!({ "role": "system", "content": content });
+    let sharegpt = |value: &str| json!({ "from": "system", "value": value });
+    let human = json!({ "from": "human", "value": "hi" });
+    assert_eq!(
+      exported_lines(&path),
+      vec![
+        json!({ "messages": [system("Be brief"), user] }),
+        records[1].clone(),
+        json!({ "conversations": [sharegpt("Be brief"), human] }),
+        records[3].clone(),
+        json!({ "instruction": "q", "system": "Be brief" }),
+        records[5].clone(),
+      ]
+    );
+
+    let path = dir.join("replaced.jsonl");
+    let summary = export_to(&store, &path, "jsonl", prompt(true)).unwrap();
+    assert_eq!((summary.system_prompt_count, summary.kept_existing_count), (6, 0));
+    let exported = exported_lines(&path);
+    assert_eq!(exported[1], json!({ "messages": [system("Be brief"), user] }));
+    assert_eq!(exported[3], json!({ "conversations": [sharegpt("Be brief")] }));
+    assert_eq!(exported[5], json!({ "instruction": "q", "system": "Be brief" }));
+  }
+
+  #[test]
+  fn injected_csv_columns_follow_the_stored_ones_in_order() {
+    let dir = TempDir::new();
+    let records = [
+      json!({ "instruction": "q1", "output": "a", "w": 2 }),
+      json!({ "instruction": "q2", "output": "b", "w": 1, "source": "human" }),
+    ];
+    let store = jsonl_store(&dir, &records);
+    let inject = |name: &str, value: &str| InjectedField {
+      name: name.to_string(),
+      value: Value::from(value),
+    };
+    let options = ExportOptions {
+      include_weight: true,
+      inject_fields: vec![inject("source", "synthetic"), inject("lang", "en")],
+      system_prompt: Some("Be brief".to_string()),
+      ..ExportOptions::default()
+    };
+    let field_map = FieldMap {
+      weight: Some("w".to_string()),
+      ..text_field_map()
+    };
+    let path = dir.join("out.csv");
+    let summary = export_mapped(&store, &path, "csv", options, field_map).unwrap();
+    assert_eq!(summary.injected_fields, vec!["source", "lang"]);
+    assert_eq!(summary.kept_existing_count, 1);
+    assert_eq!(
+      fs::read_to_string(&path).unwrap(),
+      "instruction,output,source,w,weight,lang,system\n\
+       q1,a,synthetic,2,2.0,en,Be brief\n\
+       q2,b,human,1,1.0,en,Be brief\n"
+    );
+  }
+
+  #[test]
+  fn verified_flat_exports_report_columns_write_then_verify() {
+    let dir = TempDir::new();
+    let records = (0..3000)
+      .map(|id| json!({ "instruction": format!("q{id}"), "meta": { "turn": id % 4 } }))
+      .collect::<Vec<_>>();
+    let store = jsonl_store(&dir, &records);
+    let spec = ExportSpec {
+      path: dir.join("out.csv"),
+      format: "csv".to_string(),
+      compression: ExportCompression::None,
+      options: ExportOptions {
+        flatten_nested: true,
+        verify: true,
+        ..ExportOptions::default()
+      },
+      field_map: text_field_map(),
+    };
+    let ids = (0..store.record_count).collect::<Vec<_>>();
+    let mut events = Vec::new();
+    let (summary, _) = export_dataset(&store, &ids, &spec, &AtomicBool::new(false), |phase, n, of| {
+      events.push((phase.to_string(), n, of))
+    })
+    .unwrap();
+    assert!(summary.verified);
+    assert_phases_in_order(&events, &["columns", "write", "verify"]);
+  }
+
+  #[test]
+  fn verification_finds_a_left_out_field_in_any_record() {
+    let dir = TempDir::new();
+    let records = (0..100)
+      .map(|id| json!({ "instruction": format!("q{id}"), "secret": "key" }))
+      .collect::<Vec<_>>();
+    let store = jsonl_store(&dir, &records);
+    let spec = ExportSpec {
+      path: dir.join("out.jsonl"),
+      format: "jsonl".to_string(),
+      compression: ExportCompression::None,
+      options: ExportOptions {
+        fields: Some(vec!["instruction".to_string()]),
+        verify: true,
+        ..ExportOptions::default()
+      },
+      field_map: text_field_map(),
+    };
+    let ids = (0..store.record_count).collect::<Vec<_>>();
+    let cancel = AtomicBool::new(false);
+    let (summary, _) = export_dataset(&store, &ids, &spec, &cancel, |_, _, _| {}).unwrap();
+    assert!(summary.verified);
+
+    // Only one record leaks, so the seeded sample rarely lands on it.
+    let mut lines = exported_lines(&spec.path);
+    lines[70]["secret"] = json!("key");
+    let text = lines.iter().map(|line| format!("{line}\n")).collect::<String>();
+    fs::write(&spec.path, text).unwrap();
+    let verified = verify_export(&store, &spec, None, &[], &ids, &cancel, |_, _| {}).unwrap();
+    assert_eq!(
+      verified,
+      Err(ExportMismatch::LeakedField {
+        position: 70,
+        field: "secret".to_string(),
+      })
+    );
+  }
+
+  #[test]
+  fn formats_are_detected_from_non_ascii_and_upper_case_names() {
+    let dir = TempDir::new();
+    let folder = dir.join("Dữ liệu 📦");
+    fs::create_dir_all(&folder).unwrap();
+    for (name, format) in [("tập.JSONL", "jsonl"), ("bảng.Csv", "csv"), ("dữ.json.GZ", "json")] {
+      let path = folder.join(name);
+      fs::write(&path, "").unwrap();
+      assert_eq!(detect_format(&path).unwrap(), format, "{name}");
+    }
+    let store = ingest_file(&dir, "Dữ liệu 📦/ví dụ.jsonl", "{\"instruction\":\"xin chào\"}\n");
+    assert_eq!(stored_records(&store), vec![json!({ "instruction": "xin chào" })]);
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn extensions_after_non_utf8_names_are_still_recognized() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = TempDir::new();
+    let path = dir.path().join(OsStr::from_bytes(b"d\xffta.JSONL"));
+    fs::write(&path, "").unwrap();
+    assert_eq!(detect_format(&path).unwrap(), "jsonl");
+  }
+
+  #[test]
+  fn export_summaries_time_read_and_write() {
+    let dir = TempDir::new();
+    let records = (0..3000)
+      .map(|id| json!({ "instruction": format!("q{id}"), "output": "a" }))
+      .collect::<Vec<_>>();
+    let store = jsonl_store(&dir, &records);
+    for format in ["jsonl", "json", "sqlite"] {
+      let path = dir.join(&format!("out.{format}"));
+      let summary = export_to(&store, &path, format, ExportOptions::default()).unwrap();
+      assert_eq!(summary.exported_count, 3000);
+      assert_stages_timed(&summary.timings, &["read", "write"], &["read", "write"]);
+    }
+  }
+}
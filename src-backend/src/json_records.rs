@@ -0,0 +1,247 @@
+use std::fmt;
+use std::io::Read;
+
+use serde::de::{self as de, Deserializer};
+use serde_json::{Map, Value};
+
+/// Bytes of a JSON source read to tell a single document from JSON Lines.
+pub(crate) const JSON_LAYOUT_SNIFF_BYTES: u64 = 64 * 1024;
+/// Keys whose array of objects holds the records of a wrapped JSON document.
+const RECORD_WRAPPER_KEYS: [&str; 5] = ["data", "rows", "items", "examples", "train"];
+
+/// Whether a JSON source starting with `sample` is one document rather than
+/// JSON Lines: its first line is not a complete value, as in pretty-printed
+/// files and lines too long for the sample, or it is the only line.
+pub(crate) fn is_json_document(sample: &[u8]) -> bool {
+  let complete = (sample.len() as u64) < JSON_LAYOUT_SNIFF_BYTES;
+  let Some(end) = sample.iter().position(|byte| *byte == b'\n') else {
+    return true;
+  };
+  let only_line = complete && sample[end..].iter().all(u8::is_ascii_whitespace);
+  only_line || serde_json::from_slice::<de::IgnoredAny>(&sample[..end]).is_err()
+}
+
+/// What a node of a JSON document turned out to hold.
+enum JsonNode {
+  /// An array of records, already handed on.
+  Records,
+  Value(Value),
+}
+
+/// Walks a JSON document down to its array of records: along `path` when one
+/// is given, otherwise through wrapper keys. Other nodes are read as values,
+/// or skipped while following a path.
+struct RecordsSeed<'a, F> {
+  /// Keys still to follow; `None` looks under `RECORD_WRAPPER_KEYS`.
+  path: Option<&'a [String]>,
+  /// Dot path of this node, for messages.
+  at: String,
+  on_value: &'a mut F,
+  /// Dot path of the array already handed on, if any.
+  streamed: &'a mut Option<String>,
+}
+
+impl<'a, F> RecordsSeed<'a, F>
+where
+  F: FnMut(Value) -> Result<(), String>,
+{
+  fn child(&mut self, key: &str, path: Option<&'a [String]>) -> RecordsSeed<'_, F> {
+    RecordsSeed {
+      path,
+      at: if self.at.is_empty() {
+        key.to_string()
+      } else {
+        format!("{}.{key}", self.at)
+      },
+      on_value: &mut *self.on_value,
+      streamed: &mut *self.streamed,
+    }
+  }
+
+  fn emit<E: de::Error>(&mut self, value: Value) -> Result<(), E> {
+    (self.on_value)(value).map_err(E::custom)
+  }
+}
+
+impl<'de, F> de::DeserializeSeed<'de> for RecordsSeed<'_, F>
+where
+  F: FnMut(Value) -> Result<(), String>,
+{
+  type Value = JsonNode;
+
+  fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<JsonNode, D::Error> {
+    deserializer.deserialize_any(self)
+  }
+}
+
+impl<'de, F> de::Visitor<'de> for RecordsSeed<'_, F>
+where
+  F: FnMut(Value) -> Result<(), String>,
+{
+  type Value = JsonNode;
+
+  fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+    formatter.write_str("a JSON value")
+  }
+
+  fn visit_bool<E: de::Error>(self, value: bool) -> Result<JsonNode, E> {
+    Ok(JsonNode::Value(Value::Bool(value)))
+  }
+
+  fn visit_i64<E: de::Error>(self, value: i64) -> Result<JsonNode, E> {
+    Ok(JsonNode::Value(Value::from(value)))
+  }
+
+  fn visit_u64<E: de::Error>(self, value: u64) -> Result<JsonNode, E> {
+    Ok(JsonNode::Value(Value::from(value)))
+  }
+
+  fn visit_f64<E: de::Error>(self, value: f64) -> Result<JsonNode, E> {
+    Ok(JsonNode::Value(Value::from(value)))
+  }
+
+  fn visit_str<E: de::Error>(self, value: &str) -> Result<JsonNode, E> {
+    Ok(JsonNode::Value(Value::String(value.to_string())))
+  }
+
+  fn visit_unit<E: de::Error>(self) -> Result<JsonNode, E> {
+    Ok(JsonNode::Value(Value::Null))
+  }
+
+  fn visit_seq<A: de::SeqAccess<'de>>(mut self, mut seq: A) -> Result<JsonNode, A::Error> {
+    match self.path {
+      // A top-level array is the records, whatever its elements.
+      Some([]) => {}
+      None if self.at.is_empty() => {}
+      Some(_) => return Err(de::Error::custom(format!("{} is an array, not an object", self.at))),
+      None => {
+        // Only an array of objects holds records; any other stays a value.
+        let first = seq.next_element::<Value>()?;
+        if !matches!(first, Some(Value::Object(_))) {
+          let mut items = first.into_iter().collect::<Vec<_>>();
+          while let Some(item) = seq.next_element::<Value>()? {
+            items.push(item);
+          }
+          return Ok(JsonNode::Value(Value::Array(items)));
+        }
+        if let Some(previous) = self.streamed.as_ref() {
+          return Err(de::Error::custom(format!(
+            "Both {previous} and {} hold records; choose one with a records path",
+            self.at
+          )));
+        }
+        *self.streamed = Some(self.at.clone());
+        self.emit(first.unwrap_or_default())?;
+      }
+    }
+    *self.streamed = Some(self.at.clone());
+    while let Some(value) = seq.next_element::<Value>()? {
+      self.emit(value)?;
+    }
+    Ok(JsonNode::Records)
+  }
+
+  fn visit_map<A: de::MapAccess<'de>>(mut self, mut map: A) -> Result<JsonNode, A::Error> {
+    if matches!(self.path, Some([])) {
+      return Err(de::Error::custom(format!("{} is not an array", self.at)));
+    }
+    let mut entries = Map::new();
+    while let Some(key) = map.next_key::<String>()? {
+      let path = match self.path {
+        Some([next, rest @ ..]) if *next == key => Some(Some(rest)),
+        Some(_) => None,
+        None if RECORD_WRAPPER_KEYS.contains(&key.as_str()) => Some(None),
+        None => None,
+      };
+      match path {
+        Some(path) => {
+          if let JsonNode::Value(value) = map.next_value_seed(self.child(&key, path))? {
+            entries.insert(key, value);
+          }
+        }
+        None if self.path.is_some() => {
+          map.next_value::<de::IgnoredAny>()?;
+        }
+        None => {
+          entries.insert(key, map.next_value::<Value>()?);
+        }
+      }
+    }
+    Ok(JsonNode::Value(Value::Object(entries)))
+  }
+}
+
+/// Parses a single JSON document, handing on the records of the array under
+/// `records_path`, a dot path of keys, or without one the array of objects
+/// under a wrapper key such as `data`, also when nested as in
+/// `{"data": {"rows": [...]}}`. A top-level array is the records itself; a
+/// document without an array of records is one record.
+pub(crate) fn stream_json_records<R: Read>(
+  reader: R,
+  records_path: Option<&str>,
+  mut on_value: impl FnMut(Value) -> Result<(), String>,
+) -> Result<(), String> {
+  let path = records_path.map(|path| path.split('.').map(str::to_string).collect::<Vec<_>>());
+  let mut streamed = None;
+  let mut de = serde_json::Deserializer::from_reader(reader);
+  let seed = RecordsSeed {
+    path: path.as_deref(),
+    at: String::new(),
+    on_value: &mut on_value,
+    streamed: &mut streamed,
+  };
+  let node = de::DeserializeSeed::deserialize(seed, &mut de).map_err(|e| e.to_string())?;
+  de.end().map_err(|e| e.to_string())?;
+  match node {
+    JsonNode::Records => Ok(()),
+    JsonNode::Value(_) if streamed.is_some() => Ok(()),
+    JsonNode::Value(_) if path.is_some() => Err(format!(
+      "No array of records at {}",
+      records_path.unwrap_or_default()
+    )),
+    JsonNode::Value(value) => on_value(value),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+
+  fn json_records(text: &str, records_path: Option<&str>) -> Result<Vec<Value>, String> {
+    let mut records = Vec::new();
+    stream_json_records(text.as_bytes(), records_path, |record| {
+      records.push(record);
+      Ok(())
+    })?;
+    Ok(records)
+  }
+
+  #[test]
+  fn records_two_wrappers_deep_are_streamed() {
+    let text = r#"{"data": {"rows": [{"q": 1}, {"q": 2}], "page": 1}, "status": "ok"}"#;
+    assert_eq!(json_records(text, None).unwrap(), vec![json!({ "q": 1 }), json!({ "q": 2 })]);
+    assert_eq!(
+      json_records(text, Some("data.rows")).unwrap(),
+      vec![json!({ "q": 1 }), json!({ "q": 2 })]
+    );
+    let err = json_records(text, Some("data.page")).unwrap_err();
+    assert_eq!(err, "No array of records at data.page");
+  }
+
+  #[test]
+  fn wrapper_metadata_keys_are_not_records() {
+    let text = r#"{
+      "version": 2,
+      "tags": ["train", "en"],
+      "items": [{"q": "a"}, {"q": "b"}],
+      "source": {"name": "dump"}
+    }"#;
+    assert_eq!(json_records(text, None).unwrap(), vec![json!({ "q": "a" }), json!({ "q": "b" })]);
+    // Without a wrapper, the document is the one record.
+    let single = r#"{"version": 2, "tags": ["train"]}"#;
+    let record = json!({ "version": 2, "tags": ["train"] });
+    assert_eq!(json_records(single, None).unwrap(), vec![record]);
+  }
+}
@@ -0,0 +1,389 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::io::store_lines;
+use crate::models::{CategoryCount, FieldMap, FilterConfig, LanguageStats, ScriptShare};
+use crate::records::extract_text_value;
+use crate::sample::sample_subset;
+use crate::state::{DatasetStore, IdSet};
+
+/// Texts with fewer letters than this, code excluded, are not classified.
+const MIN_LANGUAGE_LETTERS: usize = 20;
+/// Share of Vietnamese-only letters among Latin ones that marks Vietnamese.
+const VIETNAMESE_LETTER_SHARE: f64 = 0.05;
+/// Share of kana among Han and kana letters that marks Japanese over Chinese.
+const KANA_SHARE: f64 = 0.1;
+/// Share of code symbols in a line that marks it as code.
+const CODE_SYMBOL_SHARE: f64 = 0.1;
+const CODE_SYMBOLS: &str = "{}[]();=<>$#\\|&*:_";
+/// Records sampled for language stats by default.
+pub const DEFAULT_LANGUAGE_SAMPLE: usize = 10_000;
+/// Fixed so the same view always yields the same language stats.
+const LANGUAGE_SAMPLE_SEED: u64 = 0;
+/// Language reported for texts too short to classify.
+pub const UNKNOWN_LANGUAGE: &str = "unknown";
+
+/// Language of a text, as far as its letters tell. Scripts shared by many
+/// languages are reported as the script: `latin` covers English, French and
+/// the rest, except Vietnamese, which its letters give away.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LanguageGuess {
+  pub language: &'static str,
+  /// Share of the text's letters in that language's script.
+  pub confidence: f64,
+}
+
+fn looks_like_code(line: &str) -> bool {
+  let trimmed = line.trim();
+  if trimmed.is_empty() {
+    return false;
+  }
+  if trimmed.ends_with([';', '{', '}']) {
+    return true;
+  }
+  let visible = trimmed.chars().filter(|c| !c.is_whitespace()).count();
+  let symbols = trimmed.chars().filter(|c| CODE_SYMBOLS.contains(*c)).count();
+  symbols as f64 / visible as f64 >= CODE_SYMBOL_SHARE
+}
+
+/// The prose of `text`: fenced blocks, inline code spans and lines that look
+/// like code are dropped, so a code answer is not read as English.
+fn prose(text: &str) -> String {
+  let mut out = String::with_capacity(text.len());
+  let mut in_fence = false;
+  for line in text.lines() {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+      in_fence = !in_fence;
+      continue;
+    }
+    if in_fence || looks_like_code(line) {
+      continue;
+    }
+    for (index, part) in line.split('`').enumerate() {
+      if index % 2 == 0 {
+        out.push_str(part);
+      }
+    }
+    out.push('\n');
+  }
+  out
+}
+
+fn is_vietnamese_letter(c: char) -> bool {
+  matches!(c, 'đ' | 'Đ' | 'ơ' | 'Ơ' | 'ư' | 'Ư' | 'ă' | 'Ă')
+    || ('\u{1EA0}'..='\u{1EF9}').contains(&c)
+}
+
+#[derive(Default)]
+struct ScriptCounts {
+  latin: usize,
+  vietnamese: usize,
+  han: usize,
+  kana: usize,
+  hangul: usize,
+  cyrillic: usize,
+  greek: usize,
+  arabic: usize,
+  hebrew: usize,
+  devanagari: usize,
+  thai: usize,
+  other: usize,
+}
+
+impl ScriptCounts {
+  fn add(&mut self, c: char) {
+    match c as u32 {
+      0x41..=0x5A | 0x61..=0x7A | 0xC0..=0x24F | 0x1E00..=0x1EFF => {
+        self.latin += 1;
+        if is_vietnamese_letter(c) {
+          self.vietnamese += 1;
+        }
+      }
+      0x3040..=0x30FF => self.kana += 1,
+      0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF => self.han += 1,
+      0xAC00..=0xD7AF | 0x1100..=0x11FF => self.hangul += 1,
+      0x400..=0x4FF => self.cyrillic += 1,
+      0x370..=0x3FF => self.greek += 1,
+      0x600..=0x6FF | 0x750..=0x77F => self.arabic += 1,
+      0x590..=0x5FF => self.hebrew += 1,
+      0x900..=0x97F => self.devanagari += 1,
+      0xE00..=0xE7F => self.thai += 1,
+      _ => self.other += 1,
+    }
+  }
+
+  /// Letters per script, with Han and kana counted together as `cjk`.
+  fn scripts(&self) -> [(&'static str, usize); 10] {
+    [
+      ("latin", self.latin),
+      ("cjk", self.han + self.kana),
+      ("hangul", self.hangul),
+      ("cyrillic", self.cyrillic),
+      ("greek", self.greek),
+      ("arabic", self.arabic),
+      ("hebrew", self.hebrew),
+      ("devanagari", self.devanagari),
+      ("thai", self.thai),
+      ("other", self.other),
+    ]
+  }
+
+  fn guess(&self) -> Option<LanguageGuess> {
+    let total = self.latin
+      + self.han
+      + self.kana
+      + self.hangul
+      + self.cyrillic
+      + self.greek
+      + self.arabic
+      + self.hebrew
+      + self.devanagari
+      + self.thai
+      + self.other;
+    if total < MIN_LANGUAGE_LETTERS {
+      return None;
+    }
+    let cjk = self.han + self.kana;
+    let japanese = cjk > 0 && self.kana as f64 / cjk as f64 >= KANA_SHARE;
+    let latin = if self.vietnamese as f64 >= self.latin as f64 * VIETNAMESE_LETTER_SHARE {
+      "vi"
+    } else {
+      "latin"
+    };
+    let candidates = [
+      (latin, self.latin),
+      (if japanese { "ja" } else { "zh" }, cjk),
+      ("ko", self.hangul),
+      ("cyrillic", self.cyrillic),
+      ("greek", self.greek),
+      ("arabic", self.arabic),
+      ("hebrew", self.hebrew),
+      ("devanagari", self.devanagari),
+      ("thai", self.thai),
+    ];
+    let (language, count) = candidates
+      .into_iter()
+      .max_by_key(|(_, count)| *count)
+      .filter(|(_, count)| *count > 0)?;
+    Some(LanguageGuess {
+      language,
+      confidence: count as f64 / total as f64,
+    })
+  }
+}
+
+/// Guesses the language of `text` from the scripts of its letters, ignoring
+/// code. `None` when there are too few letters to tell.
+pub fn detect_language(text: &str) -> Option<LanguageGuess> {
+  let mut counts = ScriptCounts::default();
+  for c in prose(text).chars().filter(|c| c.is_alphabetic()) {
+    counts.add(c);
+  }
+  counts.guess()
+}
+
+/// Whether the instruction and output are confidently in different
+/// languages. Pairs where either guess is missing or below
+/// `min_confidence` pass.
+pub fn languages_differ(instruction: &str, output: &str, min_confidence: f64) -> bool {
+  let confident =
+    |text: &str| detect_language(text).filter(|guess| guess.confidence >= min_confidence);
+  match (confident(instruction), confident(output)) {
+    (Some(instruction), Some(output)) => instruction.language != output.language,
+    _ => false,
+  }
+}
+
+/// The records of `ids` language stats are computed from.
+pub fn language_sample(ids: &IdSet, sample_size: usize) -> IdSet {
+  sample_subset(ids, Some(sample_size.max(1)), LANGUAGE_SAMPLE_SEED)
+}
+
+/// Detected instruction languages of the records in `sample`, and the
+/// script mix of all their letters, code included.
+pub fn language_stats(
+  store: &DatasetStore,
+  sample: &IdSet,
+  view_count: usize,
+  field_map: &FieldMap,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<LanguageStats, String> {
+  let mut languages: HashMap<&'static str, usize> = HashMap::new();
+  let mut letters = ScriptCounts::default();
+  let mut scanned_count = 0usize;
+  for line in store_lines(store)? {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Language scan canceled".to_string());
+    }
+    let line = line?;
+    let idx = line.id;
+    if !sample.contains(idx) {
+      continue;
+    }
+    let Some(record) = line.record()? else {
+      continue;
+    };
+    let instruction = extract_text_value(&record, &field_map.instruction).unwrap_or_default();
+    let language = detect_language(&instruction).map_or(UNKNOWN_LANGUAGE, |guess| guess.language);
+    *languages.entry(language).or_insert(0) += 1;
+    for c in instruction.chars().filter(|c| c.is_alphabetic()) {
+      letters.add(c);
+    }
+    scanned_count += 1;
+    if scanned_count.is_multiple_of(1000) {
+      on_progress(scanned_count, sample.len());
+    }
+  }
+
+  let mut languages = languages
+    .into_iter()
+    .map(|(name, count)| CategoryCount {
+      name: name.to_string(),
+      count,
+    })
+    .collect::<Vec<_>>();
+  languages.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+  let scripts = letters.scripts();
+  let letter_total = scripts.iter().map(|(_, count)| count).sum::<usize>();
+  let mut scripts = scripts
+    .into_iter()
+    .filter(|(_, count)| *count > 0)
+    .map(|(script, letter_count)| ScriptShare {
+      script: script.to_string(),
+      letter_count,
+      share: letter_count as f64 / letter_total as f64,
+    })
+    .collect::<Vec<_>>();
+  scripts.sort_by_key(|script| std::cmp::Reverse(script.letter_count));
+  Ok(LanguageStats {
+    view_count,
+    scanned_count,
+    languages,
+    scripts,
+    cached: false,
+  })
+}
+
+/// Records in `base_ids` (all records when `None`) whose instruction and
+/// output languages differ, for tagging rather than dropping them.
+pub fn find_language_mismatches(
+  store: &DatasetStore,
+  base_ids: Option<&IdSet>,
+  filters: &FilterConfig,
+  field_map: &FieldMap,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<(IdSet, usize), String> {
+  let total = base_ids
+    .map(|set| set.len())
+    .unwrap_or(store.record_count);
+  let mut matches = IdSet::new();
+  let mut scanned = 0usize;
+  for line in store_lines(store)? {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Language scan canceled".to_string());
+    }
+    let line = line?;
+    let idx = line.id;
+    if base_ids.is_some_and(|set| !set.contains(idx)) {
+      continue;
+    }
+    let Some(record) = line.record()? else {
+      continue;
+    };
+    let instruction = extract_text_value(&record, &field_map.instruction).unwrap_or_default();
+    let output = extract_text_value(&record, &field_map.output).unwrap_or_default();
+    if languages_differ(&instruction, &output, filters.language_min_confidence) {
+      matches.insert(idx);
+    }
+    scanned += 1;
+    if scanned.is_multiple_of(1000) {
+      on_progress(scanned, total);
+    }
+  }
+  Ok((matches, scanned))
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+  use crate::filters::apply_filters_inner;
+  use crate::models::CategoryRules;
+  use crate::test_support::{jsonl_store, text_field_map, TempDir};
+
+  const ENGLISH: &str = "How do I reverse a list in place without allocating a new one?";
+  const CHINESE: &str =
+    "你可以使用列表的反转方法在原地反转列表，这样不会分配新的内存空间，也不会复制任何元素。";
+  const VIETNAMESE: &str = "Bạn có thể dùng phương thức đảo ngược để đảo danh sách tại chỗ.";
+  /// A code answer whose identifiers and comments are English.
+  const CODE_ANSWER: &str = "```python\n# reverse the list in place\nitems.reverse()\n```\n\
+                             items.sort(key=lambda item: item.name)\n`items[::-1]`";
+
+  #[test]
+  fn scripts_give_the_language_away() {
+    let language = |text| detect_language(text).map(|guess| guess.language);
+    assert_eq!(language(ENGLISH), Some("latin"));
+    assert_eq!(language(CHINESE), Some("zh"));
+    assert_eq!(language(VIETNAMESE), Some("vi"));
+    assert_eq!(language("Short one"), None);
+    assert_eq!(detect_language(CHINESE).unwrap().confidence, 1.0);
+  }
+
+  #[test]
+  fn only_confident_pairs_in_different_languages_differ() {
+    assert!(languages_differ(ENGLISH, CHINESE, 0.8));
+    assert!(languages_differ(CHINESE, ENGLISH, 0.8));
+    assert!(languages_differ(VIETNAMESE, ENGLISH, 0.8));
+    assert!(!languages_differ(ENGLISH, ENGLISH, 0.8));
+    assert!(!languages_differ(ENGLISH, "Use reverse().", 0.8));
+    let mixed = format!("{ENGLISH} {CHINESE}");
+    assert!(!languages_differ(ENGLISH, &mixed, 0.8));
+  }
+
+  #[test]
+  fn code_answers_are_not_read_as_prose() {
+    assert_eq!(detect_language(CODE_ANSWER), None);
+    assert!(!languages_differ(CHINESE, CODE_ANSWER, 0.8));
+  }
+
+  #[test]
+  fn mismatches_are_rejected_or_found_for_tagging() {
+    let dir = TempDir::new();
+    let records = [
+      (ENGLISH, "Call reverse() on the list; it swaps the items where they are."),
+      (ENGLISH, CHINESE),
+      (CHINESE, CHINESE),
+      ("请写一段代码，在不分配新内存的情况下原地反转一个列表。", CODE_ANSWER),
+      (VIETNAMESE, ENGLISH),
+    ]
+    .map(|(instruction, output)| json!({ "instruction": instruction, "output": output }));
+    let store = jsonl_store(&dir, &records);
+    let filters = FilterConfig {
+      require_same_language: true,
+      ..FilterConfig::default()
+    };
+    let cancel = AtomicBool::new(false);
+    let field_map = text_field_map();
+    let (kept, summary) = apply_filters_inner(
+      &store,
+      None,
+      &filters,
+      &field_map,
+      &CategoryRules::default(),
+      &cancel,
+      |_, _| {},
+    )
+    .unwrap();
+    assert_eq!(kept.iter().collect::<Vec<_>>(), vec![0, 2, 3]);
+    assert_eq!(summary.rejected.get("language_mismatch"), Some(&2));
+
+    let (found, scanned) =
+      find_language_mismatches(&store, None, &filters, &field_map, &cancel, |_, _| {}).unwrap();
+    assert_eq!(found.iter().collect::<Vec<_>>(), vec![1, 4]);
+    assert_eq!(scanned, 5);
+  }
+}
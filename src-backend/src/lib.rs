@@ -1,6 +1,49 @@
+pub mod appdata;
+pub mod benchmark;
+pub mod categories;
+pub mod clusters;
+pub mod compare;
+pub mod convert;
+pub mod dedupe;
+pub mod delta;
 pub mod distill;
+pub mod estimate;
+pub mod field_matrix;
 pub mod filters;
+pub mod hub;
+pub mod idset;
 pub mod io;
+pub mod json_records;
+pub mod language;
+pub mod markdown;
+pub mod metrics;
 pub mod models;
+pub mod ordering;
+pub mod paths;
+pub mod pattern;
+pub mod pii;
 pub mod records;
+pub mod refusals;
+pub mod remote;
+pub mod render;
+pub mod report;
+pub mod review_queue;
+pub mod sample;
+pub mod session;
+pub mod score_command;
+pub mod scoring;
+pub mod sidecar;
+pub mod spill;
+pub mod sqlite_export;
+pub mod stable;
 pub mod state;
+pub mod stats;
+pub mod store_index;
+pub mod templates;
+#[cfg(test)]
+mod test_support;
+pub mod timing;
+pub mod transform;
+pub mod validation;
+pub mod views;
+pub mod warnings;
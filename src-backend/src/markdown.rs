@@ -0,0 +1,123 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::paths::io_error;
+
+/// File extensions a directory import picks up.
+pub const MARKDOWN_EXTENSIONS: [&str; 3] = ["md", "markdown", "txt"];
+
+/// One section of a Markdown document: a heading and the text under it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarkdownSection {
+  pub title: String,
+  pub body: String,
+}
+
+/// Level and text of an ATX heading (`## Title`), if `line` is one.
+fn heading(line: &str) -> Option<(usize, &str)> {
+  let indent = line.len() - line.trim_start_matches(' ').len();
+  if indent > 3 {
+    return None;
+  }
+  let line = &line[indent..];
+  let level = line.len() - line.trim_start_matches('#').len();
+  if !(1..=6).contains(&level) {
+    return None;
+  }
+  let rest = &line[level..];
+  if !rest.is_empty() && !rest.starts_with([' ', '\t']) {
+    return None;
+  }
+  // A closing run of `#` is not part of the text.
+  let text = rest.trim();
+  let text = match text.trim_end_matches('#') {
+    stripped if stripped.is_empty() || stripped.ends_with([' ', '\t']) => stripped.trim_end(),
+    _ => text,
+  };
+  Some((level, text))
+}
+
+/// Marker character and length of a code fence opening, if `line` is one.
+fn fence(line: &str) -> Option<(char, usize)> {
+  let trimmed = line.trim_start_matches(' ');
+  if line.len() - trimmed.len() > 3 {
+    return None;
+  }
+  let marker = trimmed.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+  let length = trimmed.len() - trimmed.trim_start_matches(marker).len();
+  (length >= 3).then_some((marker, length))
+}
+
+fn push_section(sections: &mut Vec<MarkdownSection>, title: String, lines: &[&str]) {
+  let body = lines.join("\n").trim_matches('\n').trim_end().to_string();
+  if !body.trim().is_empty() {
+    sections.push(MarkdownSection { title, body });
+  }
+}
+
+/// Splits `text` at every heading of `level` or higher; deeper headings stay
+/// in the body. Lines inside code fences are never headings. Text before the
+/// first heading gets `preamble_title`, and sections without a body are left
+/// out. Setext headings (underlined with `===` or `---`) are not recognized.
+pub fn markdown_sections(text: &str, level: usize, preamble_title: &str) -> Vec<MarkdownSection> {
+  let mut sections = Vec::new();
+  let mut title = preamble_title.to_string();
+  let mut lines: Vec<&str> = Vec::new();
+  let mut open_fence: Option<(char, usize)> = None;
+  for line in text.lines() {
+    match (open_fence, fence(line)) {
+      (Some((marker, length)), Some((close, close_length)))
+        if close == marker
+          && close_length >= length
+          && line.trim().trim_start_matches(marker).is_empty() =>
+      {
+        open_fence = None;
+      }
+      (None, Some(opening)) => open_fence = Some(opening),
+      _ => {}
+    }
+    if open_fence.is_none() {
+      if let Some((_, text)) = heading(line).filter(|(found, _)| *found <= level) {
+        push_section(&mut sections, std::mem::take(&mut title), &lines);
+        title = text.to_string();
+        lines.clear();
+        continue;
+      }
+    }
+    lines.push(line);
+  }
+  push_section(&mut sections, title, &lines);
+  sections
+}
+
+fn is_markdown_file(path: &Path) -> bool {
+  path.extension().is_some_and(|ext| {
+    MARKDOWN_EXTENSIONS
+      .iter()
+      .any(|known| ext.as_encoded_bytes().eq_ignore_ascii_case(known.as_bytes()))
+  })
+}
+
+/// Markdown and text files under `dir`, recursively, in path order. Hidden
+/// files and directories are skipped.
+pub fn markdown_files(dir: &Path) -> Result<Vec<PathBuf>, String> {
+  let mut files = Vec::new();
+  let mut pending = vec![dir.to_path_buf()];
+  while let Some(current) = pending.pop() {
+    for entry in fs::read_dir(&current).map_err(|e| io_error(&current, &e))? {
+      let entry = entry.map_err(|e| io_error(&current, &e))?;
+      let path = entry.path();
+      if entry.file_name().as_encoded_bytes().starts_with(b".") {
+        continue;
+      }
+      let file_type = entry.file_type().map_err(|e| io_error(&path, &e))?;
+      if file_type.is_dir() {
+        pending.push(path);
+      } else if file_type.is_file() && is_markdown_file(&path) {
+        files.push(path);
+      }
+    }
+  }
+  files.sort();
+  Ok(files)
+}
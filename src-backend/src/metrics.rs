@@ -0,0 +1,201 @@
+use std::cmp::{Ordering as CmpOrdering, Reverse};
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde_json::Value;
+
+use crate::io::store_lines;
+use crate::models::FieldMap;
+use crate::records::{
+  count_tokens,
+  extract_field_value,
+  get_length_text,
+  output_instruction_ratio,
+  text_length,
+};
+use crate::state::{DatasetStore, IdSet};
+
+/// A per-record number used for ranking: `score`, `length:<scope>`,
+/// `tokens:<scope>`, `ratio:<unit>` for output over instruction length in
+/// `chars` or `tokens`, or `field:<name>` for any numeric field.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordMetric {
+  Score,
+  Length(String),
+  Tokens(String),
+  Ratio(String),
+  Field(String),
+}
+
+impl RecordMetric {
+  pub fn parse(spec: &str) -> Result<Self, String> {
+    if spec == "score" {
+      return Ok(RecordMetric::Score);
+    }
+    match spec.split_once(':') {
+      Some(("length", scope)) => Ok(RecordMetric::Length(scope.to_string())),
+      Some(("tokens", scope)) => Ok(RecordMetric::Tokens(scope.to_string())),
+      Some(("ratio", unit)) if unit == "chars" || unit == "tokens" => {
+        Ok(RecordMetric::Ratio(unit.to_string()))
+      }
+      Some(("field", name)) if !name.is_empty() => Ok(RecordMetric::Field(name.to_string())),
+      _ => Err(format!("Unknown metric: {spec}")),
+    }
+  }
+
+  pub fn value(&self, record: &Value, field_map: &FieldMap) -> Option<f64> {
+    match self {
+      RecordMetric::Score => numeric_value(record, &field_map.score),
+      RecordMetric::Length(scope) => {
+        Some(text_length(&get_length_text(record, field_map, scope)) as f64)
+      }
+      RecordMetric::Tokens(scope) => {
+        Some(count_tokens(&get_length_text(record, field_map, scope)) as f64)
+      }
+      RecordMetric::Ratio(unit) => output_instruction_ratio(record, field_map, unit),
+      RecordMetric::Field(name) => numeric_value(record, &Some(name.clone())),
+    }
+  }
+}
+
+/// Reads a JSON number or a numeric string.
+pub fn numeric_value(record: &Value, field: &Option<String>) -> Option<f64> {
+  match extract_field_value(record, field)? {
+    Value::Number(number) => number.as_f64(),
+    Value::String(text) => text.trim().parse::<f64>().ok(),
+    Value::Bool(flag) => Some(if flag { 1.0 } else { 0.0 }),
+    _ => None,
+  }
+  .filter(|value| value.is_finite())
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Ranked {
+  /// Metric value, negated for bottom-k so that larger is always better.
+  key: f64,
+  id: usize,
+}
+
+impl PartialEq for Ranked {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == CmpOrdering::Equal
+  }
+}
+
+impl Eq for Ranked {}
+
+impl PartialOrd for Ranked {
+  fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for Ranked {
+  /// Greater means better ranked; ties prefer the lower id.
+  fn cmp(&self, other: &Self) -> CmpOrdering {
+    self
+      .key
+      .total_cmp(&other.key)
+      .then_with(|| other.id.cmp(&self.id))
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExtremesSpec {
+  pub metric: RecordMetric,
+  /// Largest values first when true, smallest first otherwise.
+  pub descending: bool,
+  pub k: usize,
+}
+
+/// Top or bottom K records of `base_ids` by the spec's metric in one streaming
+/// pass, best first. Records without a value for the metric are skipped.
+pub fn collect_extremes(
+  store: &DatasetStore,
+  base_ids: &IdSet,
+  spec: &ExtremesSpec,
+  field_map: &FieldMap,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<(Vec<(usize, f64)>, usize), String> {
+  let ExtremesSpec {
+    metric,
+    descending,
+    k,
+  } = spec;
+  // No more than every base record can be ranked, whatever `k` asks for.
+  let (descending, k) = (*descending, (*k).min(base_ids.len()));
+  let mut heap: BinaryHeap<Reverse<Ranked>> = BinaryHeap::with_capacity(k + 1);
+  let mut scanned = 0usize;
+  for line in store_lines(store)? {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Extremes scan canceled".to_string());
+    }
+    let line = line?;
+    let idx = line.id;
+    if !base_ids.contains(idx) {
+      continue;
+    }
+    let Some(record) = line.record()? else {
+      continue;
+    };
+    scanned += 1;
+    if scanned.is_multiple_of(1000) {
+      on_progress(scanned, base_ids.len());
+    }
+    let Some(value) = metric.value(&record, field_map) else {
+      continue;
+    };
+    if k == 0 {
+      continue;
+    }
+    let key = if descending { value } else { -value };
+    heap.push(Reverse(Ranked { key, id: idx }));
+    if heap.len() > k {
+      heap.pop();
+    }
+  }
+
+  let mut ranked = heap.into_iter().map(|Reverse(item)| item).collect::<Vec<_>>();
+  ranked.sort_by(|a, b| b.cmp(a));
+  let items = ranked
+    .into_iter()
+    .map(|item| {
+      let value = if descending { item.key } else { -item.key };
+      (item.id, value)
+    })
+    .collect();
+  Ok((items, scanned))
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+  use crate::test_support::{jsonl_store, text_field_map, TempDir};
+
+  #[test]
+  fn extremes_with_a_huge_k_rank_every_record() {
+    let dir = TempDir::new();
+    let records = [3.0, 1.0, 2.0]
+      .map(|value| json!({ "instruction": "question", "output": "answer", "value": value }));
+    let store = jsonl_store(&dir, &records);
+    let spec = ExtremesSpec {
+      metric: RecordMetric::Field("value".to_string()),
+      descending: false,
+      k: usize::MAX,
+    };
+    let (items, scanned) = collect_extremes(
+      &store,
+      &IdSet::full(store.record_count),
+      &spec,
+      &text_field_map(),
+      &AtomicBool::new(false),
+      |_, _| {},
+    )
+    .unwrap();
+    assert_eq!(scanned, 3);
+    assert_eq!(items, vec![(1, 1.0), (2, 2.0), (0, 3.0)]);
+  }
+}
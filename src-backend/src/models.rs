@@ -1,17 +1,94 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+use crate::dedupe::DEFAULT_MINHASH_THRESHOLD;
+use crate::records::TRUNCATED_FIELD_MARKER;
+
+/// Fields each role is read from. A name may be a dotted path into nested
+/// values, such as `meta.category` or `messages.0.content`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
 pub struct FieldMap {
   pub instruction: Option<String>,
   pub output: Option<String>,
   pub code: Option<String>,
   pub category: Option<String>,
   pub score: Option<String>,
+  /// Per-example training weight; also ranks `importance` when no score is mapped.
+  pub weight: Option<String>,
+  /// System prompt of the record, written as the system turn of chat templates.
+  pub system: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+impl FieldMap {
+  /// Names of all mapped fields.
+  pub fn mapped_fields(&self) -> Vec<&str> {
+    [
+      &self.instruction,
+      &self.output,
+      &self.code,
+      &self.category,
+      &self.score,
+      &self.weight,
+      &self.system,
+    ]
+    .into_iter()
+    .filter_map(|field| field.as_deref())
+    .collect()
+  }
+}
+
+/// Keyword and regex rules that infer a category from record text when no
+/// category field is mapped. Rules are tried in order; the first that matches wins.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CategoryRules {
+  pub rules: Vec<CategoryRule>,
+  /// Category for records no rule matches; `None` leaves them uncategorized.
+  pub fallback: Option<String>,
+  /// Text the rules look at: `instruction`, `output` or `combined`.
+  pub scope: String,
+  pub case_sensitive: bool,
+  /// Categorizes records by the programming language of their code instead
+  /// of by the rules.
+  pub code_language: bool,
+}
+
+impl Default for CategoryRules {
+  fn default() -> Self {
+    Self {
+      rules: Vec::new(),
+      fallback: None,
+      scope: "combined".to_string(),
+      case_sensitive: false,
+      code_language: false,
+    }
+  }
+}
+
+impl CategoryRules {
+  pub fn is_empty(&self) -> bool {
+    self.rules.is_empty() && self.fallback.is_none() && !self.code_language
+  }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CategoryRule {
+  pub name: String,
+  /// Plain substrings; any one of them matches.
+  pub keywords: Vec<String>,
+  /// Regular expressions; any one of them matches.
+  pub patterns: Vec<String>,
+}
+
+/// Category name reported for records whose category field is missing or empty.
+pub const UNCATEGORIZED_LABEL: &str = "(uncategorized)";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
 pub struct FilterConfig {
   pub require_fields: Vec<String>,
   pub min_length: Option<u32>,
@@ -20,10 +97,71 @@ pub struct FilterConfig {
   pub exclude_keywords: Vec<String>,
   pub category_field: Option<String>,
   pub categories: Vec<String>,
+  /// Keep records with no category when filtering by category. Selecting
+  /// `UNCATEGORIZED_LABEL` in `categories` has the same effect.
+  pub include_uncategorized: bool,
+  /// Categories to drop, matched like `categories` and applied after it, so
+  /// a category in both is dropped. Records without a category are kept.
+  pub exclude_categories: Vec<String>,
   pub dedupe_exact: bool,
   pub dedupe_fuzzy: bool,
+  /// Which texts must match for a duplicate: `instruction`, `output`,
+  /// `both` (instruction and output together), `either`, or `combined`
+  /// (the instruction and output joined as one text, as `length_scope` joins
+  /// them). Also read from `dedupeScope`, where `instruction`, `output` and
+  /// `combined` pick the text near-duplicates are found in.
+  #[serde(alias = "dedupeScope")]
+  pub dedupe_mode: String,
   pub length_scope: String,
+  /// Unit of `min_length` and `max_length`: `chars`, or `tokens` as
+  /// `count_tokens` estimates them.
+  pub length_unit: String,
   pub keyword_case_sensitive: bool,
+  pub drop_refusals: bool,
+  /// Phrase list of `drop_refusals` and the refusal tag. A new dataset takes
+  /// it from `Settings::refusal_phrases` when that is set.
+  pub use_builtin_refusal_phrases: bool,
+  pub refusal_phrases: Vec<String>,
+  /// Records breaking any of these rules are excluded.
+  pub validation_rules: Vec<ValidationRule>,
+  /// Bounds on the output length divided by the instruction length.
+  pub min_output_instruction_ratio: Option<f64>,
+  pub max_output_instruction_ratio: Option<f64>,
+  /// Length unit of the ratio: `chars` or `tokens`.
+  pub ratio_unit: String,
+  /// Records with an empty instruction or output pass the ratio check with
+  /// `skip` and fail it with `reject`.
+  pub ratio_missing: String,
+  /// Rejects records whose instruction and output are in different languages.
+  pub require_same_language: bool,
+  /// Language guesses less certain than this never cause a rejection.
+  pub language_min_confidence: f64,
+  /// Bounds on the mapped score field; records without a numeric score fail
+  /// when either is set.
+  pub min_score: Option<f64>,
+  pub max_score: Option<f64>,
+  /// Bounds on arbitrary numeric fields; records must satisfy all of them.
+  pub numeric_filters: Vec<NumericFilter>,
+  /// Kinds of personal data that exclude a record when found in its
+  /// instruction or output.
+  pub pii: PiiFilter,
+  /// Remember which kept record each dropped duplicate matched, for a
+  /// duplicate report export.
+  pub keep_duplicate_map: bool,
+  /// Words per shingle of the near-duplicate hashes; 1 hashes single words,
+  /// 3 keeps reworded texts with a shared vocabulary apart.
+  pub shingle_size: usize,
+  /// Which of a set of exact duplicates is kept: `first` in file order,
+  /// `highest_score` by the mapped score field or `longest_output`. Ties go
+  /// to the earlier record; near-duplicates always keep the first.
+  pub dedupe_keep: String,
+  /// Largest simhash Hamming distance counted as a near-duplicate; `None`
+  /// uses 3. Larger values catch more paraphrases and run slower.
+  pub fuzzy_threshold: Option<u32>,
+  /// How near-duplicates are found: `simhash`, or `minhash` for long texts.
+  pub dedupe_method: String,
+  /// Estimated Jaccard similarity at which `minhash` counts a near-duplicate.
+  pub minhash_threshold: f64,
 }
 
 impl Default for FilterConfig {
@@ -36,14 +174,172 @@ impl Default for FilterConfig {
       exclude_keywords: Vec::new(),
       category_field: None,
       categories: Vec::new(),
+      include_uncategorized: false,
+      exclude_categories: Vec::new(),
       dedupe_exact: true,
       dedupe_fuzzy: false,
+      dedupe_mode: "instruction".to_string(),
       length_scope: "instruction".to_string(),
+      length_unit: "chars".to_string(),
       keyword_case_sensitive: false,
+      drop_refusals: false,
+      use_builtin_refusal_phrases: true,
+      refusal_phrases: Vec::new(),
+      validation_rules: Vec::new(),
+      min_output_instruction_ratio: None,
+      max_output_instruction_ratio: None,
+      ratio_unit: "chars".to_string(),
+      ratio_missing: "skip".to_string(),
+      require_same_language: false,
+      language_min_confidence: 0.8,
+      min_score: None,
+      max_score: None,
+      numeric_filters: Vec::new(),
+      pii: PiiFilter::default(),
+      keep_duplicate_map: false,
+      shingle_size: 1,
+      dedupe_keep: "first".to_string(),
+      fuzzy_threshold: None,
+      dedupe_method: "simhash".to_string(),
+      minhash_threshold: DEFAULT_MINHASH_THRESHOLD,
+    }
+  }
+}
+
+/// A saved view: the ids of a base view narrowed or combined step by step.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ViewDefinition {
+  /// Any view name, including `view:<name>` for another saved view.
+  pub base: String,
+  /// Applied in order to the base view's ids.
+  #[serde(default)]
+  pub refinements: Vec<ViewRefinement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", rename_all_fields = "camelCase")]
+pub enum ViewRefinement {
+  /// Keeps the records that pass the filter config, deduplication included.
+  Filter { config: Box<FilterConfig> },
+  /// Keeps the records carrying the tag.
+  Tag { tag: String },
+  /// Combines with another view's ids: `intersect`, `union` or `subtract`.
+  Combine { op: String, view: String },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NamedViewInfo {
+  pub name: String,
+  pub definition: ViewDefinition,
+  /// Record count, when the view resolves without running any of its filters.
+  pub count: Option<usize>,
+}
+
+/// A record-level invariant. Field names refer to raw record fields.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", rename_all_fields = "camelCase")]
+pub enum ValidationRule {
+  /// Broken when the text of `field` starts with the text of `prefix_field`.
+  StartsWithField { field: String, prefix_field: String },
+  /// Broken when any of `fields`, or any field when empty, contains `literal`.
+  ContainsLiteral {
+    #[serde(default)]
+    fields: Vec<String>,
+    literal: String,
+    #[serde(default)]
+    case_sensitive: bool,
+  },
+  /// Broken when `field` does not match `pattern`.
+  RegexMust { field: String, pattern: String },
+  /// Broken when `field` matches `pattern`.
+  RegexMustNot { field: String, pattern: String },
+  /// Broken when `field` and `other` hold the same text.
+  FieldEquals { field: String, other: String },
+}
+
+impl ValidationRule {
+  /// Short human-readable form of the rule.
+  pub fn label(&self) -> String {
+    match self {
+      ValidationRule::StartsWithField {
+        field,
+        prefix_field,
+      } => format!("{field} must not start with {prefix_field}"),
+      ValidationRule::ContainsLiteral { fields, literal, .. } if fields.is_empty() => {
+        format!("no field may contain \"{literal}\"")
+      }
+      ValidationRule::ContainsLiteral { fields, literal, .. } => {
+        format!("{} must not contain \"{literal}\"", fields.join(", "))
+      }
+      ValidationRule::RegexMust { field, pattern } => format!("{field} must match /{pattern}/"),
+      ValidationRule::RegexMustNot { field, pattern } => {
+        format!("{field} must not match /{pattern}/")
+      }
+      ValidationRule::FieldEquals { field, other } => format!("{field} must differ from {other}"),
     }
   }
 }
 
+/// Inclusive bounds on a numeric field, which may be a dotted path. Numeric
+/// strings count as numbers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NumericFilter {
+  pub field: String,
+  #[serde(default)]
+  pub min: Option<f64>,
+  #[serde(default)]
+  pub max: Option<f64>,
+  /// Records where the field is missing or not a number are kept with `keep`
+  /// and dropped with `drop`.
+  #[serde(default = "default_numeric_missing")]
+  pub missing: String,
+}
+
+fn default_numeric_missing() -> String {
+  "drop".to_string()
+}
+
+/// Which personal data detectors exclude records.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PiiFilter {
+  pub drop_emails: bool,
+  pub drop_phone_numbers: bool,
+  pub drop_urls: bool,
+  pub drop_ip_addresses: bool,
+}
+
+impl PiiFilter {
+  pub fn is_active(&self) -> bool {
+    self.drop_emails || self.drop_phone_numbers || self.drop_urls || self.drop_ip_addresses
+  }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleViolations {
+  pub rule: ValidationRule,
+  pub label: String,
+  /// A fix to consider instead of excluding the records.
+  pub suggestion: String,
+  pub count: usize,
+  /// The first violating ids.
+  pub sample_ids: Vec<usize>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationReport {
+  pub scanned_count: usize,
+  /// Records breaking at least one rule.
+  pub violating_count: usize,
+  /// One entry per rule, in the order given.
+  pub rules: Vec<RuleViolations>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DistillConfig {
@@ -52,6 +348,59 @@ pub struct DistillConfig {
   pub strategy: String,
   pub random_seed: Option<u64>,
   pub preserve_category_balance: bool,
+  /// Scales the uncategorized bucket's share in balanced selection; 0 leaves it out.
+  #[serde(default = "default_uncategorized_weight")]
+  pub uncategorized_weight: f32,
+  /// Balanced selection takes at least this many records from every category
+  /// that has them, before the proportional split.
+  #[serde(default)]
+  pub min_per_category: Option<usize>,
+  /// Balanced selection takes at most this share of the target from any category.
+  #[serde(default)]
+  pub max_per_category_fraction: Option<f32>,
+  /// Leading instruction words that form a bucket for `prefix_diversity`.
+  #[serde(default = "default_prefix_tokens")]
+  pub prefix_tokens: usize,
+  /// When set, the score is the weighted sum of these fields instead of the
+  /// mapped score or weight field.
+  #[serde(default)]
+  pub score_components: Vec<ScoreComponent>,
+  /// Used in place of a component a record does not have.
+  #[serde(default)]
+  pub missing_component_value: f64,
+  /// Rescales each component to [0, 1] over the records being distilled.
+  #[serde(default)]
+  pub normalize_components: bool,
+  /// Balanced importance and random selection over more records than this
+  /// keeps the metas in temp files instead of memory.
+  #[serde(default = "default_external_meta_threshold")]
+  pub external_meta_threshold: usize,
+  /// Words per shingle of the instruction simhash `diversity` buckets by.
+  #[serde(default = "default_shingle_size")]
+  pub shingle_size: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScoreComponent {
+  pub field: String,
+  pub weight: f64,
+}
+
+fn default_uncategorized_weight() -> f32 {
+  1.0
+}
+
+fn default_prefix_tokens() -> usize {
+  2
+}
+
+fn default_external_meta_threshold() -> usize {
+  20_000_000
+}
+
+fn default_shingle_size() -> usize {
+  1
 }
 
 impl Default for DistillConfig {
@@ -62,19 +411,533 @@ impl Default for DistillConfig {
       strategy: "diversity".to_string(),
       random_seed: None,
       preserve_category_balance: false,
+      uncategorized_weight: default_uncategorized_weight(),
+      min_per_category: None,
+      max_per_category_fraction: None,
+      prefix_tokens: default_prefix_tokens(),
+      score_components: Vec::new(),
+      missing_component_value: 0.0,
+      normalize_components: false,
+      external_meta_threshold: default_external_meta_threshold(),
+      shingle_size: default_shingle_size(),
     }
   }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+pub const DEFAULT_MAX_RECORD_BYTES: usize = 16 * 1024 * 1024;
+
+/// Import cap of a store whose index or session predates it being recorded.
+pub fn default_max_record_bytes() -> usize {
+  DEFAULT_MAX_RECORD_BYTES
+}
+pub const DEFAULT_READ_AHEAD_CHUNKS: usize = 8;
+/// Read-ahead beyond this, 16 MB of chunks, only costs memory.
+pub const MAX_READ_AHEAD_CHUNKS: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ImportOptions {
+  pub max_record_bytes: usize,
+  pub truncate_large_fields: bool,
+  /// Chunks the reader thread may read ahead of parsing; raise it for slow
+  /// sources such as network drives. Capped at `MAX_READ_AHEAD_CHUNKS`.
+  pub read_ahead_chunks: usize,
+  /// `csv`, `json`, `jsonl` or `markdown`; detected from the path when unset.
+  /// Directories are always read as Markdown.
+  pub format: Option<String>,
+  /// Markdown headings of this level or higher start a new record.
+  pub heading_level: usize,
+  /// Markdown sections with a shorter body, in characters, are skipped.
+  pub min_body_length: usize,
+  /// Whether a CSV file's first row names its columns; guessed when unset.
+  pub has_header: Option<bool>,
+  /// Import CSV cells as numbers, booleans and nulls where their whole
+  /// column parses as such, instead of as strings.
+  pub infer_types: bool,
+  /// Start reading an uncompressed CSV or JSON Lines file at this byte
+  /// offset, as recorded by a canceled import, instead of at the beginning.
+  pub resume_offset: Option<u64>,
+  /// Skip JSON Lines lines that do not parse, listing them in the import
+  /// report, instead of stopping at the first.
+  pub lenient: bool,
+  /// Dot path of the array of records in a JSON document, such as
+  /// `data.rows`; looked for under wrapper keys like `data` when unset.
+  pub records_path: Option<String>,
+}
+
+impl Default for ImportOptions {
+  fn default() -> Self {
+    Self {
+      max_record_bytes: DEFAULT_MAX_RECORD_BYTES,
+      truncate_large_fields: false,
+      read_ahead_chunks: DEFAULT_READ_AHEAD_CHUNKS,
+      format: None,
+      heading_level: 2,
+      min_body_length: 0,
+      has_header: None,
+      infer_types: false,
+      resume_offset: None,
+      lenient: false,
+      records_path: None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ImportReport {
+  pub oversized_skipped: usize,
+  /// Approximate byte sizes of the first skipped records.
+  pub oversized_sizes: Vec<u64>,
+  pub truncated_records: usize,
+  /// Markdown sections dropped for a body below the minimum length.
+  pub short_sections_skipped: usize,
+  /// The CSV file had no header row, so its columns were named `column_1` onwards.
+  pub generated_headers: bool,
+  /// JSON Lines lines a lenient import skipped for not parsing.
+  pub skipped_count: usize,
+  /// The first of those lines, in file order.
+  pub malformed_lines: Vec<MalformedLine>,
+}
+
+/// A JSON Lines line that is not valid UTF-8 or JSON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MalformedLine {
+  /// 1-based, counted from where reading started.
+  pub line_number: usize,
+  pub error: String,
+  /// The start of the line.
+  pub snippet: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DatasetSummary {
   pub id: String,
   pub source_path: String,
   pub format: String,
+  /// Field delimiter of a CSV source, detected on import.
+  #[serde(default)]
+  pub delimiter: Option<String>,
+  /// Text encoding the source was read in, detected on import.
+  #[serde(default)]
+  pub encoding: Option<String>,
   pub record_count: usize,
   pub fields: Vec<String>,
   pub size_bytes: u64,
+  #[serde(default)]
+  pub import_report: ImportReport,
+  #[serde(default)]
+  pub parent_id: Option<String>,
+  /// Annotations carried over from an earlier import of the same source.
+  #[serde(default)]
+  pub remap_report: Option<RemapReport>,
+  /// The import was canceled and only the records read until then were kept.
+  #[serde(default)]
+  pub truncated: bool,
+  /// Source byte offset a continued import starts from, when it can continue.
+  #[serde(default)]
+  pub resume_offset: Option<u64>,
+}
+
+/// A store in the datasets directory, as its index sidecar describes it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredDataset {
+  pub id: String,
+  pub source_path: String,
+  pub format: String,
+  pub record_count: usize,
+  /// Size of the source, compressed for gzip sources.
+  pub size_bytes: u64,
+  /// Size of the store on disk.
+  pub store_bytes: u64,
+  /// Unix seconds when the store was imported.
+  pub imported_at: u64,
+  pub truncated: bool,
+  /// Open this session, and whether it is the active dataset.
+  pub open: bool,
+  pub active: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemapReport {
+  pub restored_tags: Vec<String>,
+  pub matched_count: usize,
+  /// Annotated records no longer present in the source.
+  pub missing_count: usize,
+  /// Annotated contents now shared by several records; all of them inherit the annotation.
+  pub collision_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ExportOptions {
+  /// Emit the mapped weight as a numeric `weight` field, 1.0 when missing.
+  pub include_weight: bool,
+  /// `default` writes 1.0 for weights that are not positive numbers, `skip` drops the record.
+  pub invalid_weight: String,
+  /// Replace the target file if it already exists.
+  pub overwrite: bool,
+  /// Allow writing inside the app's internal datasets directory.
+  pub allow_internal: bool,
+  /// Sort keys applied before writing, most significant first; empty keeps id order.
+  pub order_by: Vec<OrderKey>,
+  /// `original` writes in id order or by `order_by`, `shuffled` in a random
+  /// order from `shuffle_seed`, `score_desc` and `score_asc` by the mapped score.
+  pub order: String,
+  /// Seed of a `shuffled` order, the default one when unset.
+  pub shuffle_seed: Option<u64>,
+  /// Static fields added to every exported record.
+  pub inject_fields: Vec<InjectedField>,
+  /// System turn added to records without one: prepended to `messages`
+  /// (OpenAI) or `conversations` (ShareGPT), or set as a `system` field.
+  pub system_prompt: Option<String>,
+  /// Let injected fields and the system prompt replace existing values.
+  pub overwrite_existing: bool,
+  /// Cut an over-long text field to a token budget instead of dropping the record.
+  pub truncate: Option<TruncateOptions>,
+  /// Read the file back before moving it into place, and fail the export if it
+  /// does not match the store.
+  pub verify: bool,
+  /// Keep only these stored fields, in this order for CSV columns; missing
+  /// ones are written as null. Added fields such as the weight are kept.
+  pub fields: Option<Vec<String>>,
+  /// Write the mapped instruction, output, category and score fields under
+  /// those names, replacing any field already called so.
+  pub normalize_fields: bool,
+  /// With `normalize_fields`, leave out every field that is not mapped.
+  pub drop_unmapped_fields: bool,
+  /// Rewrite each record into a fixed training layout from the mapped fields:
+  /// `alpaca` gives `instruction`, an empty `input` and `output`; `sharegpt`
+  /// gives `conversations` with the mapped system, human and gpt turns.
+  /// Records missing the mapped instruction or output are skipped.
+  pub template: Option<String>,
+  /// How CSV exports are delimited, quoted and headed.
+  pub csv: CsvOptions,
+  /// Write `<path>.manifest.json` recording the dataset, configuration and
+  /// file hash behind the export.
+  pub write_manifest: bool,
+  /// Spread nested objects of CSV exports over dotted columns such as
+  /// `meta.lang` instead of writing them as JSON.
+  pub flatten_nested: bool,
+  /// With `flatten_nested`, give array items columns too (`tags.0`, `tags.1`)
+  /// instead of writing arrays as JSON.
+  pub flatten_arrays: bool,
+  /// Indent the records of a JSON array export by two spaces per level.
+  pub pretty: bool,
+}
+
+impl ExportOptions {
+  /// Whether exported records differ from the stored ones.
+  pub fn rewrites_records(&self) -> bool {
+    self.include_weight
+      || !self.inject_fields.is_empty()
+      || self.system_prompt.is_some()
+      || self.truncate.is_some()
+      || self.fields.is_some()
+      || self.normalize_fields
+      || self.template.is_some()
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TruncateOptions {
+  pub field: String,
+  pub max_tokens: usize,
+  /// `token` or `sentence`.
+  #[serde(default = "default_truncate_boundary")]
+  pub boundary: String,
+  /// Appended to truncated texts.
+  #[serde(default = "default_truncate_marker")]
+  pub marker: String,
+}
+
+fn default_truncate_boundary() -> String {
+  "sentence".to_string()
+}
+
+fn default_truncate_marker() -> String {
+  TRUNCATED_FIELD_MARKER.to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InjectedField {
+  pub name: String,
+  pub value: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderKey {
+  /// A metric (`score`, `length:<scope>`, `tokens:<scope>`, `field:<name>`),
+  /// `category` for the mapped category field, or any field name.
+  pub field_or_metric: String,
+  /// `asc` or `desc`.
+  pub direction: String,
+}
+
+impl Default for ExportOptions {
+  fn default() -> Self {
+    Self {
+      include_weight: false,
+      invalid_weight: "default".to_string(),
+      overwrite: false,
+      allow_internal: false,
+      order_by: Vec::new(),
+      order: "original".to_string(),
+      shuffle_seed: None,
+      inject_fields: Vec::new(),
+      system_prompt: None,
+      overwrite_existing: false,
+      truncate: None,
+      verify: false,
+      fields: None,
+      normalize_fields: false,
+      drop_unmapped_fields: false,
+      template: None,
+      csv: CsvOptions::default(),
+      write_manifest: false,
+      flatten_nested: false,
+      flatten_arrays: false,
+      pretty: false,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CsvOptions {
+  /// Byte written between fields.
+  pub delimiter: u8,
+  /// Write the column names as the first row.
+  pub write_header: bool,
+  /// `necessary` quotes fields holding the delimiter, a quote or a line
+  /// break; `always` quotes every field and `non_numeric` all but numbers.
+  pub quote_style: String,
+}
+
+impl Default for CsvOptions {
+  fn default() -> Self {
+    Self {
+      delimiter: b',',
+      write_header: true,
+      quote_style: "necessary".to_string(),
+    }
+  }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportSummary {
+  pub exported_count: usize,
+  pub invalid_weight_count: usize,
+  pub skipped_count: usize,
+  #[serde(default)]
+  pub timings: Vec<StageTiming>,
+  /// Set when the exported ids came from a sample rather than the full dataset.
+  #[serde(default)]
+  pub sample_view: Option<String>,
+  /// Ordering the records were written in; empty means id order.
+  #[serde(default)]
+  pub order_by: Vec<OrderKey>,
+  /// Seed the records were shuffled with, when written in a shuffled order.
+  #[serde(default)]
+  pub shuffle_seed: Option<u64>,
+  /// Names of the static fields injected into the exported records.
+  #[serde(default)]
+  pub injected_fields: Vec<String>,
+  /// Records given the configured system prompt.
+  #[serde(default)]
+  pub system_prompt_count: usize,
+  /// Injected values not written because the record already had one.
+  #[serde(default)]
+  pub kept_existing_count: usize,
+  /// Records whose text was cut by the truncate option.
+  #[serde(default)]
+  pub truncated_count: usize,
+  #[serde(default)]
+  pub tokens_saved: usize,
+  /// Whether the file was read back and matched the store.
+  #[serde(default)]
+  pub verified: bool,
+  /// Records the verification compared in full with the store.
+  #[serde(default)]
+  pub spot_checked_count: usize,
+  /// Records skipped because the template's fields were missing; also
+  /// counted in `skipped_count`.
+  #[serde(default)]
+  pub template_skipped_count: usize,
+  /// Where the manifest was written, when one was asked for.
+  #[serde(default)]
+  pub manifest_path: Option<String>,
+}
+
+/// Written next to a delta export so downstream can check it continues the
+/// export it was built against.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeltaManifest {
+  pub dataset_id: String,
+  pub view: String,
+  pub format: String,
+  pub created_at: u64,
+  pub file: String,
+  pub sha256: String,
+  pub added_count: usize,
+  /// Hex content hashes of dropped records, one per line; unset for a full export.
+  pub removals_file: Option<String>,
+  pub removals_sha256: Option<String>,
+  pub removed_count: usize,
+  /// The export this one follows; unset when it is a full export.
+  pub previous_file: Option<String>,
+  pub previous_sha256: Option<String>,
+  pub previous_exported_at: Option<u64>,
+  /// Records downstream holds once this export is applied.
+  pub total_count: usize,
+}
+
+/// Written next to an export with `write_manifest`, so the file can be traced
+/// back to the dataset and the settings that produced it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportManifest {
+  pub dataset_id: String,
+  pub source_path: String,
+  pub view: String,
+  pub format: String,
+  pub created_at: u64,
+  pub app_version: String,
+  pub file: String,
+  pub sha256: String,
+  /// Records in the dataset, in the exported view and written to the file.
+  pub dataset_record_count: usize,
+  pub view_record_count: usize,
+  pub exported_count: usize,
+  pub skipped_count: usize,
+  pub field_map: FieldMap,
+  pub filters: FilterConfig,
+  pub distill_config: DistillConfig,
+  /// Seed distillation ran with, the default one when none was configured.
+  pub random_seed: u64,
+  /// Sort keys of the export; empty when written in id or shuffled order.
+  pub order_by: Vec<OrderKey>,
+  pub shuffle_seed: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeltaExportSummary {
+  pub export: ExportSummary,
+  /// Set when no earlier export was recorded and every selected record was written.
+  pub full_export: bool,
+  pub added_count: usize,
+  pub removed_count: usize,
+  pub removals_path: Option<String>,
+  pub manifest_path: String,
+  pub manifest: DeltaManifest,
+}
+
+/// A file converted to another format without being loaded.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConvertSummary {
+  /// Format the source was read as.
+  pub source_format: String,
+  pub import_report: ImportReport,
+  pub export: ExportSummary,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PushSummary {
+  pub repo_id: String,
+  pub commit_url: Option<String>,
+  pub uploaded_files: Vec<String>,
+  pub lfs_file_count: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MaterializeSummary {
+  pub dataset: DatasetSummary,
+  pub input_count: usize,
+  pub output_count: usize,
+  /// Input records the operation actually changed.
+  pub affected_count: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandScoreSummary {
+  pub scored_count: usize,
+  pub batch_count: usize,
+  pub min_score: Option<f64>,
+  pub max_score: Option<f64>,
+  pub mean_score: Option<f64>,
+  /// Field the scores were written to, when they were materialized.
+  pub field: Option<String>,
+  /// The derived store holding the scores, when they were materialized.
+  pub dataset: Option<DatasetSummary>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PruneSummary {
+  pub dataset: DatasetSummary,
+  pub input_count: usize,
+  /// Fields of the parent store that are not in the pruned one.
+  pub dropped_fields: Vec<String>,
+  pub size_before: u64,
+  pub size_after: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunkSizeBin {
+  /// Inclusive size range of the bin, in chunk units.
+  pub min: usize,
+  pub max: usize,
+  pub count: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunkSummary {
+  pub dataset: DatasetSummary,
+  pub input_count: usize,
+  pub output_count: usize,
+  /// Input records split into more than one chunk.
+  pub chunked_count: usize,
+  /// Input records without a text value in the field, copied unchanged.
+  pub skipped_count: usize,
+  pub histogram: Vec<ChunkSizeBin>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TruncateSummary {
+  pub dataset: DatasetSummary,
+  pub input_count: usize,
+  pub truncated_count: usize,
+  /// Estimated tokens cut from the truncated texts.
+  pub tokens_saved: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JoinSummary {
+  pub dataset: DatasetSummary,
+  pub input_count: usize,
+  pub right_row_count: usize,
+  pub matched_count: usize,
+  pub missed_count: usize,
+  /// Right-side keys seen more than once; the last row was kept.
+  pub duplicate_right_keys: usize,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -83,6 +946,57 @@ pub struct FilterSummary {
   pub total_count: usize,
   pub filtered_count: usize,
   pub duplicates_removed: usize,
+  pub rejected: BTreeMap<String, usize>,
+  #[serde(default)]
+  pub timings: Vec<StageTiming>,
+  /// Sample view the result was computed on; `None` means the full dataset.
+  #[serde(default)]
+  pub sample_view: Option<String>,
+  /// Signs the result is probably a mistake, such as nothing retained.
+  #[serde(default)]
+  pub warnings: Vec<String>,
+  /// Whether the run kept its duplicate map, so a duplicate report can be
+  /// exported.
+  #[serde(default)]
+  pub duplicate_map_kept: bool,
+  /// Estimated tokens in the length scope of the kept records, when lengths
+  /// are counted in tokens.
+  #[serde(default)]
+  pub kept_tokens: Option<usize>,
+  /// Duplicates removed by each method: `exact`, `simhash` or `minhash`.
+  #[serde(default)]
+  pub duplicates_by_method: BTreeMap<String, usize>,
+  /// Records rejected by each of `FilterConfig::numeric_filters`, in order;
+  /// a record is counted against the first rule it fails.
+  #[serde(default)]
+  pub numeric_rejected: Vec<usize>,
+  /// Records rejected by each PII detector: `email`, `phone`, `url` or
+  /// `ip_address`, counted against the first one that matched.
+  #[serde(default)]
+  pub pii_rejected: BTreeMap<String, usize>,
+}
+
+/// A constraint added to the current filters by clicking a stats bucket.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "dimension", rename_all = "snake_case")]
+pub enum DrillDown {
+  /// Keep one category; `UNCATEGORIZED_LABEL` keeps records without one.
+  Category { value: String },
+  /// Keep scoped lengths within the bucket's bounds.
+  Length { min: Option<u32>, max: Option<u32> },
+  /// Keep scores within the bucket's bounds.
+  Score { min: Option<f64>, max: Option<f64> },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DrillDownResult {
+  /// The current filters with the drill-down constraint added.
+  pub filters: FilterConfig,
+  pub summary: FilterSummary,
+  /// Whether the previous filter result was narrowed instead of filtering,
+  /// and deduplicating, the base view again.
+  pub dedupe_reused: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -91,6 +1005,285 @@ pub struct DistillSummary {
   pub total_count: usize,
   pub selected_count: usize,
   pub removed_count: usize,
+  #[serde(default)]
+  pub timings: Vec<StageTiming>,
+  /// Sample view the selection was made within, if any.
+  #[serde(default)]
+  pub sample_view: Option<String>,
+  /// Per-category constraints that could not be met, one message each.
+  #[serde(default)]
+  pub constraint_warnings: Vec<String>,
+  /// Most common instruction prefixes, for `prefix_diversity` only.
+  #[serde(default)]
+  pub top_prefixes: Vec<PrefixCount>,
+  /// Ranges the score components were normalized with, if they were.
+  #[serde(default)]
+  pub score_ranges: Vec<ComponentRange>,
+  /// Whether the metas were kept in temp files rather than memory.
+  #[serde(default)]
+  pub external_selection: bool,
+  /// Signs the selection is probably a mistake, such as nothing selected.
+  #[serde(default)]
+  pub warnings: Vec<String>,
+}
+
+/// Present values of a score component; both are `None` when it never occurs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ComponentRange {
+  pub field: String,
+  pub min: Option<f64>,
+  pub max: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ComponentScore {
+  pub field: String,
+  pub weight: f64,
+  /// The record's value, if it has one.
+  pub raw: Option<f64>,
+  /// Normalized value, or the missing-component value.
+  pub value: f64,
+  pub contribution: f64,
+}
+
+/// How a record's distillation score was computed.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScoreBreakdown {
+  pub id: usize,
+  pub score: f64,
+  /// Whether the components were normalized with the ranges of the last preview.
+  pub normalized: bool,
+  pub components: Vec<ComponentScore>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrefixCount {
+  pub prefix: String,
+  pub count: usize,
+  pub selected_count: usize,
+}
+
+/// A seeded random subset of the dataset for quick iteration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SampleSpec {
+  pub percent: f32,
+  pub seed: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SampleSummary {
+  /// View name to pass as `view` or `base_view`, e.g. `sample:1%-seed42`.
+  pub view: String,
+  pub percent: f32,
+  pub seed: u64,
+  pub sample_count: usize,
+  pub total_count: usize,
+}
+
+/// Both stages of re-running the active configs on the full dataset.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PromoteSummary {
+  /// Sample view the configs were tuned on, if any.
+  pub promoted_from: Option<String>,
+  pub filter: FilterSummary,
+  /// Only present when a distillation preview existed to promote.
+  pub distill: Option<DistillSummary>,
+}
+
+/// Configs of a filter-then-distill run, as `promote_to_full` applies them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PipelineSpec {
+  pub filters: FilterConfig,
+  pub distill: Option<DistillConfig>,
+}
+
+/// Records into and out of one pipeline stage, on the sample and estimated
+/// for the full dataset. `low` and `high` bound the full-dataset output.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StageEstimate {
+  pub stage: String,
+  pub sample_input: usize,
+  pub sample_output: usize,
+  pub estimated_input: usize,
+  pub estimated_output: usize,
+  pub low: usize,
+  pub high: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CategoryEstimate {
+  pub name: String,
+  pub sample_count: usize,
+  pub share: f64,
+  pub estimated_count: usize,
+}
+
+/// Extrapolation of a pipeline run from a seeded sample. Every count other
+/// than the sample counts is an estimate.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PipelineEstimate {
+  pub total_count: usize,
+  pub sample_count: usize,
+  /// Stages in run order: `filter`, `dedupe`, then `distill` when configured.
+  pub stages: Vec<StageEstimate>,
+  /// Sample rejections by predicate reason, scaled to the full dataset.
+  pub estimated_rejected: BTreeMap<String, usize>,
+  /// Share of duplicates seen among records passing the predicates; a lower
+  /// bound on the full-dataset rate.
+  pub observed_duplicate_rate: f64,
+  /// The observed rate corrected for duplicate pairs split by sampling.
+  pub corrected_duplicate_rate: f64,
+  /// Categories of the final output, largest first.
+  pub categories: Vec<CategoryEstimate>,
+  /// Instruction and output tokens of the final output.
+  pub estimated_tokens: usize,
+  pub caveats: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterProgress {
+  pub cluster_count: usize,
+  pub reviewed_count: usize,
+  pub remaining_count: usize,
+  /// Clusters computed under a filter config that has since changed.
+  pub stale: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterSummary {
+  /// Records that duplicate an earlier record, across all clusters.
+  pub duplicate_count: usize,
+  /// Clusters decided automatically from earlier decisions on the same records.
+  pub restored_count: usize,
+  pub progress: ClusterProgress,
+}
+
+/// A duplicate cluster up for review, with full records.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterView {
+  pub cluster_id: usize,
+  pub members: Vec<ClusterMember>,
+  pub progress: ClusterProgress,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterMember {
+  pub id: usize,
+  /// The full record, or null when it is too large to preview.
+  pub record: Value,
+  pub fields: Vec<PreviewField>,
+  /// Whether the record is currently kept in the filtered set.
+  pub kept: bool,
+}
+
+/// Wall time spent in one phase of an operation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StageTiming {
+  pub stage: String,
+  pub millis: u64,
+  /// Throughput for phases that process records one by one.
+  pub records_per_sec: Option<f64>,
+}
+
+/// Diagnostics from `run_benchmark`: raw store read speed plus per-stage and
+/// per-predicate costs of filtering a sample of the dataset.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BenchmarkReport {
+  pub record_count: usize,
+  pub sample_count: usize,
+  /// Lines over the store's import cap, skipped without being read.
+  pub oversized_count: usize,
+  pub read_bytes: u64,
+  pub read_mb_per_sec: Option<f64>,
+  /// Sequential read, JSON parse, text extraction and simhash stages.
+  pub timings: Vec<StageTiming>,
+  pub predicates: Vec<PredicateCost>,
+}
+
+/// Cost of one configured filter predicate, evaluated on every sampled record.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PredicateCost {
+  pub predicate: String,
+  pub millis: u64,
+  pub records_per_sec: Option<f64>,
+  pub rejected_count: usize,
+}
+
+/// Structural comparison of two open datasets from `compare_datasets`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DatasetComparison {
+  pub dataset_a: DatasetSummary,
+  pub dataset_b: DatasetSummary,
+  pub shared_fields: Vec<String>,
+  pub only_a_fields: Vec<String>,
+  pub only_b_fields: Vec<String>,
+  /// Text length over the filter's length scope, in its length unit.
+  pub length_a: NumericSummary,
+  pub length_b: NumericSummary,
+  /// `None` when no score field is mapped or no record has a score.
+  pub score_a: Option<NumericSummary>,
+  pub score_b: Option<NumericSummary>,
+  /// Empty when neither a category field nor category rules are configured.
+  pub categories: Vec<CategoryShare>,
+  pub overlap: OverlapEstimate,
+  /// Where the markdown report was written, if requested.
+  pub markdown_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NumericSummary {
+  pub count: usize,
+  pub min: f64,
+  pub max: f64,
+  pub mean: f64,
+  pub p10: f64,
+  pub p50: f64,
+  pub p90: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CategoryShare {
+  pub name: String,
+  pub count_a: usize,
+  pub count_b: usize,
+  /// Fraction of each dataset's records in the category.
+  pub share_a: f64,
+  pub share_b: f64,
+}
+
+/// Instructions of a random sample of A looked up in all of B.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OverlapEstimate {
+  /// Sampled records of A with a non-empty instruction.
+  pub sample_size: usize,
+  /// Sampled instructions found in B after whitespace and case normalization.
+  pub exact_count: usize,
+  /// Sampled instructions with an exact or near-duplicate match in B.
+  pub fuzzy_count: usize,
+  pub exact_rate: f64,
+  pub fuzzy_rate: f64,
 }
 
 #[derive(Debug, Serialize)]
@@ -99,7 +1292,113 @@ pub struct PreviewPage {
   pub items: Vec<PreviewItem>,
   pub total_count: usize,
   pub page: usize,
+  /// Page size used, after clamping to the configured maximum.
   pub page_size: usize,
+  /// Whether the requested page size was above the maximum.
+  pub page_size_clamped: bool,
+  /// Whether items were left out to keep the page under its byte cap.
+  pub truncated_page: bool,
+}
+
+pub const DEFAULT_PREVIEW_MAX_PAGE_SIZE: usize = 500;
+pub const DEFAULT_PREVIEW_MAX_PAGE_BYTES: usize = 2 * 1024 * 1024;
+
+/// Bounds on one preview page, so a single request cannot stall the app.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PreviewLimits {
+  pub max_page_size: usize,
+  /// Serialized bytes of the preview fields on one page.
+  pub max_page_bytes: usize,
+}
+
+impl Default for PreviewLimits {
+  fn default() -> Self {
+    Self {
+      max_page_size: DEFAULT_PREVIEW_MAX_PAGE_SIZE,
+      max_page_bytes: DEFAULT_PREVIEW_MAX_PAGE_BYTES,
+    }
+  }
+}
+
+impl PreviewLimits {
+  /// `page_size` within `max_page_size`, and whether it had to be lowered.
+  pub fn clamp_page_size(&self, page_size: usize) -> (usize, bool) {
+    let max_page_size = self.max_page_size.max(1);
+    (page_size.min(max_page_size), page_size > max_page_size)
+  }
+}
+
+/// Phrases that mark an output as a refusal, set once for every new dataset.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RefusalPhrases {
+  /// Whether the English and Vietnamese phrases shipped with DataLab are used.
+  pub use_builtin: bool,
+  /// Phrases added to, or without `use_builtin` replacing, the shipped ones.
+  pub phrases: Vec<String>,
+}
+
+impl Default for RefusalPhrases {
+  fn default() -> Self {
+    Self {
+      use_builtin: true,
+      phrases: Vec::new(),
+    }
+  }
+}
+
+/// How much each signal counts toward a record's place in the review queue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ReviewWeights {
+  /// Nearness to the distillation cut, on either side of it.
+  pub boundary: f64,
+  /// Size of the duplicate cluster a record was kept over.
+  pub duplicates: f64,
+  pub refusal: f64,
+}
+
+impl Default for ReviewWeights {
+  fn default() -> Self {
+    Self {
+      boundary: 1.0,
+      duplicates: 0.6,
+      refusal: 0.8,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReviewReason {
+  /// `boundary`, `duplicates` or `refusal`.
+  pub kind: String,
+  /// Strength of the signal in [0, 1], before weighting.
+  pub signal: f64,
+  pub detail: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReviewQueueItem {
+  pub id: usize,
+  pub priority: f64,
+  /// Whether the distillation currently keeps the record.
+  pub selected: bool,
+  pub reasons: Vec<ReviewReason>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReviewQueue {
+  pub items: Vec<ReviewQueueItem>,
+  /// Whether the strategy ranks records, so nearness to the cut is known.
+  pub boundary_ranked: bool,
+  /// Whether the last filter run kept its duplicate map.
+  pub duplicates_known: bool,
+  /// Whether refusal phrases are configured.
+  pub refusals_checked: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -117,6 +1416,41 @@ pub struct PreviewField {
   pub kind: String,
 }
 
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtremeItem {
+  pub id: usize,
+  pub value: f64,
+  pub fields: Vec<PreviewField>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderedItem {
+  pub id: usize,
+  /// Key values, one per order key; null when the record has none.
+  pub values: Vec<Value>,
+  pub fields: Vec<PreviewField>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderPreview {
+  pub order_by: Vec<OrderKey>,
+  pub scanned_count: usize,
+  /// The first records in order.
+  pub items: Vec<OrderedItem>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtremesResult {
+  pub metric: String,
+  pub direction: String,
+  pub scanned_count: usize,
+  pub items: Vec<ExtremeItem>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ManualChange {
@@ -124,13 +1458,297 @@ pub struct ManualChange {
   pub include: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CategoryCount {
   pub name: String,
   pub count: usize,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CategoryList {
+  pub categories: Vec<CategoryCount>,
+  /// The scan stopped early, at the distinct-value cap or when canceled.
+  pub truncated: bool,
+  pub scanned_count: usize,
+  /// Distinct values seen; a lower bound when `truncated`.
+  pub distinct_count: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeLanguageReport {
+  pub scanned_count: usize,
+  /// Records per programming language, most common first.
+  pub languages: Vec<CategoryCount>,
+  /// Records per way the language was recognized: `metadata`, `path`,
+  /// `fence`, `shebang`, `keywords` or `none`.
+  pub sources: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScriptShare {
+  pub script: String,
+  pub letter_count: usize,
+  /// Share of all letters in the sample.
+  pub share: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LanguageStats {
+  pub view_count: usize,
+  /// Records examined; fewer than `view_count` when sampled.
+  pub scanned_count: usize,
+  /// Records per detected instruction language, most common first;
+  /// `unknown` counts instructions too short to tell.
+  pub languages: Vec<CategoryCount>,
+  /// Letters of the sampled instructions by script, most common first.
+  pub scripts: Vec<ScriptShare>,
+  /// Whether the stats were reused from an earlier request.
+  #[serde(default)]
+  pub cached: bool,
+}
+
+/// How often a keyword or regex matched a sample of a view.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PatternTest {
+  pub view_count: usize,
+  pub scanned_count: usize,
+  pub matched_count: usize,
+  /// Matched share of the scanned records, 0 when none were scanned.
+  pub match_rate: f64,
+  pub matches: Vec<PatternExample>,
+  pub non_matches: Vec<PatternExample>,
+}
+
+/// A snippet of a record's scoped text split around the first match; a
+/// record without a match has its opening text in `before` only.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PatternExample {
+  pub id: usize,
+  pub before: String,
+  pub matched: String,
+  pub after: String,
+}
+
+/// Personal data found in a sample of a view, for reviewing the detectors
+/// before filtering with them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PiiScan {
+  pub view_count: usize,
+  pub scanned_count: usize,
+  /// Records with at least one match.
+  pub matched_count: usize,
+  /// Records with a match, by detector.
+  pub counts: BTreeMap<String, usize>,
+  pub matches: Vec<PiiMatch>,
+}
+
+/// A match split like a `PatternExample`, with the match and any other
+/// personal data in its context masked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PiiMatch {
+  pub id: usize,
+  /// `email`, `phone`, `url` or `ip_address`.
+  pub kind: String,
+  pub before: String,
+  pub matched: String,
+  pub after: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TagSummary {
+  pub tag: String,
+  pub scanned_count: usize,
+  pub tagged_count: usize,
+}
+
+/// Profile of one top-level record field.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldStats {
+  pub name: String,
+  /// Records that have the field at all, empty or not.
+  pub present_count: usize,
+  pub non_empty_count: usize,
+  /// Non-empty values over scanned records.
+  pub fill_rate: f64,
+  /// Records by JSON type of the value.
+  pub types: BTreeMap<String, usize>,
+  pub distinct_estimate: usize,
+  /// The distinct count is exact rather than estimated.
+  pub distinct_exact: bool,
+  pub examples: Vec<String>,
+  /// Mean character length of non-empty string values.
+  pub mean_length: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldMatrix {
+  pub view_count: usize,
+  /// Records profiled; fewer than `view_count` when sampled.
+  pub scanned_count: usize,
+  pub fields: Vec<FieldStats>,
+}
+
+/// Share of scanned records in which a top-level field is present and not null.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldCoverage {
+  pub name: String,
+  pub non_null_count: usize,
+  /// Non-null values over scanned records, in percent.
+  pub coverage_percent: f64,
+}
+
+/// Mean, median and 95th percentile of a length.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LengthStats {
+  pub mean: f64,
+  pub median: f64,
+  pub p95: f64,
+}
+
+/// Lengths of a mapped text field, over the records where it is not empty.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextStats {
+  pub field: String,
+  pub count: usize,
+  pub chars: LengthStats,
+  pub words: LengthStats,
+}
+
+/// Overview of a dataset or view from `dataset_stats`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DatasetStats {
+  pub view_count: usize,
+  pub scanned_count: usize,
+  /// Fields in store order, then in order of first appearance.
+  pub fields: Vec<FieldCoverage>,
+  /// `None` when the field is not mapped.
+  pub instruction: Option<TextStats>,
+  pub output: Option<TextStats>,
+  /// Distinct categories, `None` when neither a category field nor category
+  /// rules are configured.
+  pub category_count: Option<usize>,
+  /// Share of records whose normalized instruction and output repeat an
+  /// earlier record's, estimated from hashes.
+  pub duplicate_rate: f64,
+  /// Estimated instruction and output tokens of all scanned records.
+  pub estimated_tokens: usize,
+}
+
+/// A field whose fill rate differs between two datasets.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FillRateChange {
+  pub field: String,
+  pub base_fill_rate: f64,
+  pub incoming_fill_rate: f64,
+}
+
+/// How the fields of records about to be appended differ from the dataset
+/// they are appended to.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SchemaDiff {
+  pub only_in_base: Vec<String>,
+  pub only_in_incoming: Vec<String>,
+  /// Shared fields whose sampled fill rates differ beyond the threshold.
+  pub fill_rate_changes: Vec<FillRateChange>,
+  pub base_scanned_count: usize,
+  pub incoming_scanned_count: usize,
+}
+
+impl SchemaDiff {
+  pub fn is_empty(&self) -> bool {
+    self.only_in_base.is_empty()
+      && self.only_in_incoming.is_empty()
+      && self.fill_rate_changes.is_empty()
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppendSummary {
+  pub schema_diff: SchemaDiff,
+  /// False when the schema changed and the changes were not accepted.
+  pub appended: bool,
+  /// The dataset whose records were, or would be, appended.
+  pub incoming: DatasetSummary,
+  /// The combined dataset, now active, when the records were appended.
+  pub dataset: Option<DatasetSummary>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReviewExportSummary {
+  pub path: String,
+  pub view_count: usize,
+  pub exported_count: usize,
+  /// Set when the view held more records than the bundle limit.
+  pub truncated: bool,
+}
+
+/// Records whose instructions share one masked template.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateCluster {
+  pub masked: String,
+  pub count: usize,
+  /// The first few member ids, in id order.
+  pub sample_ids: Vec<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateReport {
+  /// Largest clusters first, ties by masked text.
+  pub clusters: Vec<TemplateCluster>,
+  /// More clusters reached the minimum size than are listed.
+  pub truncated: bool,
+  pub scanned_count: usize,
+  /// Distinct templates among scanned records with an instruction.
+  pub template_count: usize,
+  /// Records in clusters of at least the minimum size, listed or not.
+  pub clustered_count: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateCapSummary {
+  pub scanned_count: usize,
+  pub template_count: usize,
+  /// Templates that had more records than the cap.
+  pub capped_templates: usize,
+  pub removed_count: usize,
+  pub filtered_count: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DerivedStateInfo {
+  pub dataset_id: String,
+  /// Unix timestamp (seconds) of the snapshot.
+  pub saved_at: u64,
+  pub filtered_count: Option<usize>,
+  pub selected_count: Option<usize>,
+  pub removed_count: Option<usize>,
+  pub tags: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Settings {
@@ -139,6 +1757,86 @@ pub struct Settings {
   pub field_map: FieldMap,
   pub filters: FilterConfig,
   pub distill: DistillConfig,
+  /// Hugging Face access token for private or gated repositories. Never logged.
+  #[serde(default)]
+  pub hub_token: Option<String>,
+  #[serde(default)]
+  pub preview_limits: Option<PreviewLimits>,
+  #[serde(default)]
+  pub review_weights: Option<ReviewWeights>,
+  /// Overrides the refusal phrases of `filters` for datasets opened from now on.
+  #[serde(default)]
+  pub refusal_phrases: Option<RefusalPhrases>,
+  /// Reopen the last dataset at its view, page and sort on startup.
+  #[serde(default)]
+  pub restore_last_session: Option<bool>,
+  /// View shown when a dataset opens, or when a restored view is gone.
+  #[serde(default)]
+  pub default_view: Option<String>,
+  #[serde(default)]
+  pub default_page_size: Option<usize>,
+}
+
+/// Field map and configs a dataset was last used with, kept in its sidecar.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DatasetConfig {
+  pub field_map: FieldMap,
+  #[serde(default)]
+  pub category_rules: CategoryRules,
+  pub filters: FilterConfig,
+  pub distill_config: DistillConfig,
+}
+
+impl DatasetConfig {
+  /// Defaults for a dataset opened for the first time.
+  pub fn from_settings(settings: &Settings) -> Self {
+    let mut filters = settings.filters.clone();
+    if let Some(refusals) = &settings.refusal_phrases {
+      filters.use_builtin_refusal_phrases = refusals.use_builtin;
+      filters.refusal_phrases = refusals.phrases.clone();
+    }
+    Self {
+      field_map: settings.field_map.clone(),
+      category_rules: CategoryRules::default(),
+      filters,
+      distill_config: settings.distill.clone(),
+    }
+  }
+}
+
+/// The dataset and preview reopened at startup.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RestoredSession {
+  pub dataset: DatasetSummary,
+  pub view: String,
+  pub page: usize,
+  pub page_size: usize,
+  pub order_by: Vec<OrderKey>,
+}
+
+/// What the startup check of the app data directory found and repaired.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StartupReport {
+  /// Why the settings file could not be read, when it was moved aside.
+  pub settings_error: Option<String>,
+  pub settings_backup: Option<String>,
+  pub removed_temp_files: Vec<String>,
+  /// Sidecars whose store file no longer exists.
+  pub removed_sidecars: Vec<String>,
+  /// Problems the check could not repair.
+  pub errors: Vec<String>,
+}
+
+impl StartupReport {
+  pub fn is_clean(&self) -> bool {
+    self.settings_backup.is_none()
+      && self.removed_temp_files.is_empty()
+      && self.removed_sidecars.is_empty()
+      && self.errors.is_empty()
+  }
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -148,4 +1846,15 @@ pub struct ProgressPayload {
   pub current: usize,
   pub total: usize,
   pub message: Option<String>,
+  /// Phase of a multi-phase operation; `current` and `total` restart with each phase.
+  pub phase: Option<String>,
+  /// Zero-based position of `phase` among `phase_count` phases.
+  pub phase_index: Option<usize>,
+  pub phase_count: Option<usize>,
+  /// Units per second (records, or bytes for downloads) since the previous
+  /// event of the same stage and phase.
+  pub rate: Option<f64>,
+  /// Source bytes read so far by an import, whose record total is unknown.
+  pub bytes_current: Option<u64>,
+  pub bytes_total: Option<u64>,
 }
@@ -0,0 +1,237 @@
+use std::cmp::Ordering as CmpOrdering;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde_json::Value;
+
+use crate::distill::DEFAULT_RANDOM_SEED;
+use crate::io::store_lines;
+use crate::metrics::RecordMetric;
+use crate::models::{ExportOptions, FieldMap, OrderKey};
+use crate::records::{extract_field_value, value_to_string};
+use crate::state::{DatasetStore, IdSet};
+
+pub const MAX_ORDER_KEYS: usize = 3;
+
+/// A sort key: a record metric, the mapped category, or a raw field.
+#[derive(Debug, Clone, PartialEq)]
+enum OrderSource {
+  Metric(RecordMetric),
+  Category,
+  Field(String),
+}
+
+impl OrderSource {
+  fn parse(spec: &str) -> Self {
+    if spec == "category" {
+      return OrderSource::Category;
+    }
+    RecordMetric::parse(spec)
+      .map(OrderSource::Metric)
+      .unwrap_or_else(|_| OrderSource::Field(spec.to_string()))
+  }
+
+  fn value(&self, record: &Value, field_map: &FieldMap) -> Option<SortValue> {
+    let raw = match self {
+      OrderSource::Metric(metric) => return metric.value(record, field_map).map(SortValue::Number),
+      OrderSource::Category => extract_field_value(record, &field_map.category),
+      OrderSource::Field(name) => extract_field_value(record, &Some(name.clone())),
+    };
+    match raw? {
+      Value::Null => None,
+      Value::Number(number) => number.as_f64().map(SortValue::Number),
+      Value::Bool(flag) => Some(SortValue::Number(if flag { 1.0 } else { 0.0 })),
+      Value::String(text) => {
+        let trimmed = text.trim();
+        if trimmed.is_empty() {
+          return None;
+        }
+        match trimmed.parse::<f64>() {
+          Ok(number) if number.is_finite() => Some(SortValue::Number(number)),
+          _ => Some(SortValue::Text(text)),
+        }
+      }
+      other => Some(SortValue::Text(value_to_string(&other))),
+    }
+  }
+}
+
+/// One key value of a record. Numbers, including numeric strings, compare
+/// numerically and sort before text.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SortValue {
+  Number(f64),
+  Text(String),
+}
+
+impl SortValue {
+  fn compare(&self, other: &Self) -> CmpOrdering {
+    match (self, other) {
+      (SortValue::Number(a), SortValue::Number(b)) => a.total_cmp(b),
+      (SortValue::Text(a), SortValue::Text(b)) => a.cmp(b),
+      (SortValue::Number(_), SortValue::Text(_)) => CmpOrdering::Less,
+      (SortValue::Text(_), SortValue::Number(_)) => CmpOrdering::Greater,
+    }
+  }
+
+  pub fn to_json(&self) -> Value {
+    match self {
+      SortValue::Number(number) => Value::from(*number),
+      SortValue::Text(text) => Value::String(text.clone()),
+    }
+  }
+}
+
+/// A record id with its key values, one per order key.
+pub type SortRow = (usize, Vec<Option<SortValue>>);
+
+pub fn validate_order(order_by: &[OrderKey]) -> Result<(), String> {
+  if order_by.len() > MAX_ORDER_KEYS {
+    return Err(format!("At most {MAX_ORDER_KEYS} order keys are supported"));
+  }
+  for key in order_by {
+    if key.field_or_metric.trim().is_empty() {
+      return Err("Order key needs a field or metric".to_string());
+    }
+    if key.direction != "asc" && key.direction != "desc" {
+      return Err(format!("Unknown direction: {}", key.direction));
+    }
+  }
+  Ok(())
+}
+
+/// Checks the order of an export and returns the sort keys it needs read:
+/// the mapped score for `score_desc` and `score_asc`, `order_by` for
+/// `original` and none for `shuffled`.
+pub fn export_order_keys(
+  options: &ExportOptions,
+  field_map: &FieldMap,
+) -> Result<Vec<OrderKey>, String> {
+  validate_order(&options.order_by)?;
+  let direction = match options.order.as_str() {
+    "original" => return Ok(options.order_by.clone()),
+    "shuffled" => None,
+    "score_desc" => Some("desc"),
+    "score_asc" => Some("asc"),
+    other => return Err(format!("Unknown export order: {other}")),
+  };
+  if !options.order_by.is_empty() {
+    return Err(format!("Export order {} cannot be combined with sort keys", options.order));
+  }
+  let Some(direction) = direction else {
+    return Ok(Vec::new());
+  };
+  if field_map.score.is_none() {
+    return Err("Ordering by score needs a mapped score field".to_string());
+  }
+  Ok(vec![OrderKey {
+    field_or_metric: "score".to_string(),
+    direction: direction.to_string(),
+  }])
+}
+
+/// Shuffles `ids` from `seed`, or the default seed, so the same seed always
+/// gives the same order. Returns the seed used.
+pub fn shuffle_ids(ids: &mut [usize], seed: Option<u64>) -> u64 {
+  let seed = seed.unwrap_or(DEFAULT_RANDOM_SEED);
+  ids.shuffle(&mut StdRng::seed_from_u64(seed));
+  seed
+}
+
+/// Reads the key values of every record in `ids` in one pass, in id order.
+pub fn collect_sort_keys(
+  store: &DatasetStore,
+  ids: &IdSet,
+  order_by: &[OrderKey],
+  field_map: &FieldMap,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<Vec<SortRow>, String> {
+  let sources = order_by
+    .iter()
+    .map(|key| OrderSource::parse(&key.field_or_metric))
+    .collect::<Vec<_>>();
+  let mut rows = Vec::with_capacity(ids.len());
+  for line in store_lines(store)? {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Ordering canceled".to_string());
+    }
+    let line = line?;
+    let idx = line.id;
+    if !ids.contains(idx) {
+      continue;
+    }
+    let values = match line.record()? {
+      Some(record) => sources
+        .iter()
+        .map(|source| source.value(&record, field_map))
+        .collect(),
+      None => vec![None; sources.len()],
+    };
+    rows.push((idx, values));
+    if rows.len().is_multiple_of(1000) {
+      on_progress(rows.len(), ids.len());
+    }
+  }
+  Ok(rows)
+}
+
+/// Sorts rows by the order keys. Missing values go last in either direction
+/// and ties keep their current order.
+pub fn sort_rows(rows: &mut [SortRow], order_by: &[OrderKey]) {
+  rows.sort_by(|(_, a), (_, b)| {
+    for (index, key) in order_by.iter().enumerate() {
+      let ordering = match (&a[index], &b[index]) {
+        (Some(a), Some(b)) if key.direction == "desc" => b.compare(a),
+        (Some(a), Some(b)) => a.compare(b),
+        (Some(_), None) => CmpOrdering::Less,
+        (None, Some(_)) => CmpOrdering::Greater,
+        (None, None) => CmpOrdering::Equal,
+      };
+      if ordering != CmpOrdering::Equal {
+        return ordering;
+      }
+    }
+    CmpOrdering::Equal
+  });
+}
+
+/// Key values from the latest ordering scan, so exporting with the ordering
+/// that was just previewed does not scan the store again.
+#[derive(Debug, Clone)]
+pub struct OrderCache {
+  pub store_path: PathBuf,
+  pub order_by: Vec<OrderKey>,
+  pub field_map: FieldMap,
+  /// Sorted by id.
+  pub rows: Vec<SortRow>,
+}
+
+impl OrderCache {
+  /// The cached rows for `ids`, if the cache was built for the same store,
+  /// ordering and field map and covers every id.
+  pub fn rows_for(
+    &self,
+    store: &DatasetStore,
+    ids: &IdSet,
+    order_by: &[OrderKey],
+    field_map: &FieldMap,
+  ) -> Option<Vec<SortRow>> {
+    if self.store_path != store.store_path
+      || self.order_by != order_by
+      || self.field_map != *field_map
+    {
+      return None;
+    }
+    ids
+      .iter()
+      .map(|id| {
+        let index = self.rows.binary_search_by_key(&id, |(row_id, _)| *row_id).ok()?;
+        Some(self.rows[index].clone())
+      })
+      .collect()
+  }
+}
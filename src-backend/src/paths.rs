@@ -0,0 +1,487 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{is_separator, Component, Path, PathBuf, Prefix};
+
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Longest path Windows accepts without the verbatim `\\?\` prefix.
+const WINDOWS_MAX_PATH: usize = 259;
+
+/// Locations an output path must not resolve to.
+#[derive(Debug, Clone, Default)]
+pub struct OutputGuard {
+  /// Files that are never written, such as open stores and settings.
+  pub protected_files: Vec<PathBuf>,
+  /// Directories that are only written when `allow_internal` is set.
+  pub internal_dirs: Vec<PathBuf>,
+  pub allow_internal: bool,
+  pub overwrite: bool,
+}
+
+/// Why an output path was refused.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathConflict {
+  /// The path resolves to a file the app itself reads or writes.
+  ProtectedFile(PathBuf),
+  /// The path resolves into an app-managed directory.
+  InternalDir { path: PathBuf, dir: PathBuf },
+  /// The file exists and overwriting was not requested.
+  Exists(PathBuf),
+  Invalid { path: PathBuf, reason: String },
+}
+
+impl fmt::Display for PathConflict {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      PathConflict::ProtectedFile(path) => write!(
+        f,
+        "Refusing to write {}: it is a file DataLab is using",
+        path.display()
+      ),
+      PathConflict::InternalDir { path, dir } => write!(
+        f,
+        "Refusing to write {}: it is inside the internal directory {}",
+        path.display(),
+        dir.display()
+      ),
+      PathConflict::Exists(path) => write!(
+        f,
+        "{} already exists; pass overwrite to replace it",
+        path.display()
+      ),
+      PathConflict::Invalid { path, reason } => {
+        write!(f, "Invalid output path {}: {reason}", path.display())
+      }
+    }
+  }
+}
+
+/// Error of a command that writes output files, as the UI receives it. A
+/// refused path keeps the kind of conflict, so an existing file can be told
+/// apart from a protected one; other failures only carry the message.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutputError {
+  /// `protectedFile`, `internalDir`, `exists` or `invalidPath` for a refused
+  /// path, `failed` for anything else.
+  pub kind: &'static str,
+  pub message: String,
+  /// The refused path, resolved.
+  pub path: Option<PathBuf>,
+  /// The internal directory the path resolves into.
+  pub dir: Option<PathBuf>,
+}
+
+impl From<PathConflict> for OutputError {
+  fn from(conflict: PathConflict) -> Self {
+    let message = conflict.to_string();
+    let (kind, path, dir) = match conflict {
+      PathConflict::ProtectedFile(path) => ("protectedFile", path, None),
+      PathConflict::InternalDir { path, dir } => ("internalDir", path, Some(dir)),
+      PathConflict::Exists(path) => ("exists", path, None),
+      PathConflict::Invalid { path, .. } => ("invalidPath", path, None),
+    };
+    Self {
+      kind,
+      message,
+      path: Some(path),
+      dir,
+    }
+  }
+}
+
+impl From<String> for OutputError {
+  fn from(message: String) -> Self {
+    Self {
+      kind: "failed",
+      message,
+      path: None,
+      dir: None,
+    }
+  }
+}
+
+impl fmt::Display for OutputError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.message)
+  }
+}
+
+/// Drops the verbatim prefix Windows adds to canonical paths (`\\?\C:\x` becomes
+/// `C:\x`, `\\?\UNC\server\share` becomes `\\server\share`) so the same file
+/// always has the same path. Paths too long for the plain form are kept as is.
+pub fn normalize_path(path: &Path) -> PathBuf {
+  let mut components = path.components();
+  let Some(Component::Prefix(prefix)) = components.next() else {
+    return path.to_path_buf();
+  };
+  let base = match prefix.kind() {
+    Prefix::VerbatimDisk(disk) => OsString::from(format!("{}:\\", disk as char)),
+    Prefix::VerbatimUNC(server, share) => {
+      let mut base = OsString::from("\\\\");
+      base.push(server);
+      base.push("\\");
+      base.push(share);
+      base
+    }
+    _ => return path.to_path_buf(),
+  };
+  let normalized = PathBuf::from(base).join(components.as_path());
+  if normalized.as_os_str().len() > WINDOWS_MAX_PATH {
+    return path.to_path_buf();
+  }
+  normalized
+}
+
+/// An IO error on `path`, telling a missing file apart from a permission problem.
+pub fn io_error(path: &Path, err: &io::Error) -> String {
+  match err.kind() {
+    io::ErrorKind::NotFound => format!("{} does not exist", path.display()),
+    io::ErrorKind::PermissionDenied => format!("Permission denied: {}", path.display()),
+    _ => format!("{}: {err}", path.display()),
+  }
+}
+
+/// Creates `path` for writing, along with any missing parent directories.
+pub fn create_output_file(path: &Path) -> Result<File, String> {
+  if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+    fs::create_dir_all(parent).map_err(|e| io_error(parent, &e))?;
+  }
+  File::create(path).map_err(|e| io_error(path, &e))
+}
+
+/// Flushes a rename in `dir` to disk. Directories can only be synced on Unix,
+/// and some filesystems refuse even there, so failures are ignored.
+fn sync_dir(dir: &Path) {
+  #[cfg(unix)]
+  if let Ok(dir) = File::open(dir) {
+    let _ = dir.sync_all();
+  }
+  #[cfg(not(unix))]
+  let _ = dir;
+}
+
+/// A temp file next to `path` named `{name}.tmp-{uuid}`, distinct from any
+/// other write to the same path.
+pub fn unique_temp_path(path: &Path) -> PathBuf {
+  let mut tmp_name = path.as_os_str().to_os_string();
+  tmp_name.push(format!(".tmp-{}", Uuid::new_v4()));
+  PathBuf::from(tmp_name)
+}
+
+/// Renames the finished `tmp_path` over `path` and syncs the directory.
+pub fn replace_with_temp(tmp_path: &Path, path: &Path) -> Result<(), String> {
+  fs::rename(tmp_path, path).map_err(|e| io_error(path, &e))?;
+  if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+    sync_dir(parent);
+  }
+  Ok(())
+}
+
+/// Writes `path` through `write` into a temp file next to it, syncs the temp
+/// file, renames it over `path` and syncs the directory. If `write` or any
+/// step fails, the temp file is removed and the previous file left intact, so
+/// a crash or full disk never leaves a truncated file behind. Readers only
+/// open `path` itself, so a temp file left by a crash is ignored.
+pub fn write_atomic_with(
+  path: &Path,
+  write: impl FnOnce(&mut BufWriter<File>) -> Result<(), String>,
+) -> Result<(), String> {
+  let mut tmp_name = path.as_os_str().to_os_string();
+  tmp_name.push(".tmp");
+  let tmp_path = PathBuf::from(tmp_name);
+  let written = (|| {
+    let file = File::create(&tmp_path).map_err(|e| io_error(&tmp_path, &e))?;
+    let mut writer = BufWriter::new(file);
+    write(&mut writer)?;
+    let file = writer
+      .into_inner()
+      .map_err(|e| io_error(&tmp_path, e.error()))?;
+    file.sync_all().map_err(|e| io_error(&tmp_path, &e))
+  })();
+  if let Err(err) = written {
+    let _ = fs::remove_file(&tmp_path);
+    return Err(err);
+  }
+  replace_with_temp(&tmp_path, path)
+}
+
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), String> {
+  write_atomic_with(path, |writer| {
+    writer.write_all(contents).map_err(|e| io_error(path, &e))
+  })
+}
+
+/// `value` as pretty JSON, written with `write_atomic_with`.
+pub fn atomic_write_json(path: &Path, value: &impl Serialize) -> Result<(), String> {
+  write_atomic_with(path, |writer| {
+    serde_json::to_writer_pretty(writer, value).map_err(|e| e.to_string())
+  })
+}
+
+/// Resolves symlinks and `..` in the existing part of `path`. Missing
+/// directories are allowed and kept as named, so they can be created on write.
+pub fn canonical_target(path: &Path) -> Result<PathBuf, PathConflict> {
+  let invalid = |reason: &str| PathConflict::Invalid {
+    path: path.to_path_buf(),
+    reason: reason.to_string(),
+  };
+  let ends_with_separator = path
+    .as_os_str()
+    .as_encoded_bytes()
+    .last()
+    .is_some_and(|byte| is_separator(*byte as char));
+  if ends_with_separator {
+    return Err(invalid("it ends with a path separator, so it has no file name"));
+  }
+  if path.symlink_metadata().is_ok() {
+    return fs::canonicalize(path)
+      .map(|target| normalize_path(&target))
+      .map_err(|_| invalid("it is a broken symlink"));
+  }
+
+  let mut missing = Vec::new();
+  let mut ancestor = path;
+  loop {
+    let Some(name) = ancestor.file_name() else {
+      return Err(invalid(if missing.is_empty() {
+        "it has no file name"
+      } else {
+        "it goes up from a directory that does not exist"
+      }));
+    };
+    missing.push(name);
+    ancestor = match ancestor.parent() {
+      Some(parent) if !parent.as_os_str().is_empty() => parent,
+      _ => Path::new("."),
+    };
+    if ancestor.symlink_metadata().is_ok() {
+      break;
+    }
+  }
+  let mut target = fs::canonicalize(ancestor).map_err(|e| invalid(&io_error(ancestor, &e)))?;
+  if !target.is_dir() {
+    return Err(invalid(&format!("{} is not a directory", ancestor.display())));
+  }
+  for name in missing.into_iter().rev() {
+    target.push(name);
+  }
+  Ok(normalize_path(&target))
+}
+
+/// The canonical form of `path`, or `path` itself when it does not exist.
+fn canonical_or_self(path: &Path) -> PathBuf {
+  fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Checks `path` against the guard and returns its canonical form.
+pub fn check_output_path(path: &Path, guard: &OutputGuard) -> Result<PathBuf, PathConflict> {
+  let target = canonical_target(path)?;
+  if guard
+    .protected_files
+    .iter()
+    .any(|file| canonical_or_self(file) == target)
+  {
+    return Err(PathConflict::ProtectedFile(target));
+  }
+  if !guard.allow_internal {
+    for dir in &guard.internal_dirs {
+      let dir = canonical_or_self(dir);
+      if target.starts_with(&dir) {
+        return Err(PathConflict::InternalDir { path: target, dir });
+      }
+    }
+  }
+  if target.is_dir() {
+    return Err(PathConflict::Invalid {
+      path: target,
+      reason: "it is a directory".to_string(),
+    });
+  }
+  if target.exists() && !guard.overwrite {
+    return Err(PathConflict::Exists(target));
+  }
+  Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+  use crate::test_support::TempDir;
+
+  #[test]
+  fn a_failed_write_leaves_the_original_intact() {
+    let dir = TempDir::new();
+    let path = dir.join("settings.json");
+    fs::write(&path, "original").unwrap();
+    let err = write_atomic_with(&path, |writer| {
+      writer.write_all(&[b'x'; 64 * 1024]).unwrap();
+      Err("disk full".to_string())
+    })
+    .unwrap_err();
+    assert_eq!(err, "disk full");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "original");
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+    write_atomic(&path, b"replaced").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "replaced");
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+  }
+
+  #[test]
+  fn missing_non_ascii_directories_are_created_on_write() {
+    let dir = TempDir::new();
+    let path = dir.path().join("Dữ liệu").join("📦 xuất").join("tập.jsonl");
+    let target = canonical_target(&path).unwrap();
+    assert!(target.ends_with("Dữ liệu/📦 xuất/tập.jsonl"));
+    assert_eq!(check_output_path(&path, &OutputGuard::default()), Ok(target.clone()));
+
+    create_output_file(&target).unwrap().write_all(b"{}\n").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "{}\n");
+    let conflict = check_output_path(&path, &OutputGuard::default());
+    assert_eq!(conflict, Err(PathConflict::Exists(target)));
+  }
+
+  #[test]
+  fn paths_without_a_file_name_are_invalid() {
+    let dir = TempDir::new();
+    let reason = |path: PathBuf| match canonical_target(&path) {
+      Err(PathConflict::Invalid { reason, .. }) => reason,
+      other => panic!("{other:?}"),
+    };
+    let trailing = format!("{}{}", dir.join("out").display(), std::path::MAIN_SEPARATOR);
+    assert_eq!(
+      reason(PathBuf::from(trailing)),
+      "it ends with a path separator, so it has no file name"
+    );
+    assert_eq!(
+      reason(dir.join("missing").join("..").join("out.jsonl")),
+      "it goes up from a directory that does not exist"
+    );
+    fs::write(dir.join("file"), "").unwrap();
+    let under_file = dir.join("file").join("out.jsonl");
+    assert!(reason(under_file).ends_with("is not a directory"));
+    let err = check_output_path(dir.path(), &OutputGuard::default()).unwrap_err();
+    assert!(matches!(err, PathConflict::Invalid { reason, .. } if reason == "it is a directory"));
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn symlinks_are_followed_before_missing_directories_are_added() {
+    use std::os::unix::fs::symlink;
+
+    let dir = TempDir::new();
+    let real = dir.join("thư mục");
+    fs::create_dir(&real).unwrap();
+    symlink(&real, dir.join("link")).unwrap();
+    let through_link = dir.join("link").join("mới").join("out.jsonl");
+    let canonical = fs::canonicalize(&real).unwrap().join("mới").join("out.jsonl");
+    assert_eq!(canonical_target(&through_link), Ok(canonical));
+
+    let dangling = dir.join("dangling.jsonl");
+    symlink(dir.join("gone.jsonl"), &dangling).unwrap();
+    let broken = PathConflict::Invalid {
+      path: dangling.clone(),
+      reason: "it is a broken symlink".to_string(),
+    };
+    assert_eq!(canonical_target(&dangling), Err(broken));
+  }
+
+  #[test]
+  fn io_errors_tell_missing_files_from_permission_problems() {
+    let path = Path::new("/data/Tiếng Việt.jsonl");
+    let error = |kind| io_error(path, &io::Error::from(kind));
+    assert_eq!(error(io::ErrorKind::NotFound), "/data/Tiếng Việt.jsonl does not exist");
+    assert_eq!(
+      error(io::ErrorKind::PermissionDenied),
+      "Permission denied: /data/Tiếng Việt.jsonl"
+    );
+    assert!(error(io::ErrorKind::InvalidData).starts_with("/data/Tiếng Việt.jsonl: "));
+  }
+
+  #[test]
+  fn plain_paths_are_not_rewritten() {
+    let path = Path::new("exports/Dữ liệu/out.jsonl");
+    assert_eq!(normalize_path(path), path);
+  }
+
+  #[cfg(windows)]
+  #[test]
+  fn verbatim_prefixes_are_dropped() {
+    let normalize = |path: &str| normalize_path(Path::new(path));
+    assert_eq!(normalize(r"\\?\C:\Dữ liệu\out.jsonl"), Path::new(r"C:\Dữ liệu\out.jsonl"));
+    assert_eq!(
+      normalize(r"\\?\UNC\server\share\out.jsonl"),
+      Path::new(r"\\server\share\out.jsonl")
+    );
+    let long = format!(r"\\?\C:\{}\out.jsonl", "a".repeat(300));
+    assert_eq!(normalize(&long), Path::new(&long));
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn symlinks_into_the_datasets_dir_are_refused() {
+    use std::os::unix::fs::symlink;
+
+    let dir = TempDir::new();
+    let datasets = dir.join("datasets");
+    fs::create_dir(&datasets).unwrap();
+    let store = datasets.join("store.jsonl");
+    fs::write(&store, "{}\n").unwrap();
+    symlink(&datasets, dir.join("exports")).unwrap();
+    symlink(&store, dir.join("alias.jsonl")).unwrap();
+    let mut guard = OutputGuard {
+      protected_files: vec![store.clone()],
+      internal_dirs: vec![datasets.clone()],
+      ..OutputGuard::default()
+    };
+    let datasets = fs::canonicalize(&datasets).unwrap();
+    let store = fs::canonicalize(&store).unwrap();
+
+    let inside = dir.join("exports").join("new").join("out.jsonl");
+    let conflict = PathConflict::InternalDir {
+      path: datasets.join("new").join("out.jsonl"),
+      dir: datasets.clone(),
+    };
+    assert_eq!(check_output_path(&inside, &guard), Err(conflict));
+    let protected = PathConflict::ProtectedFile(store.clone());
+    assert_eq!(check_output_path(&dir.join("alias.jsonl"), &guard), Err(protected.clone()));
+
+    guard.allow_internal = true;
+    guard.overwrite = true;
+    let allowed = check_output_path(&inside, &guard);
+    assert_eq!(allowed, Ok(datasets.join("new").join("out.jsonl")));
+    assert_eq!(check_output_path(&dir.join("alias.jsonl"), &guard), Err(protected));
+  }
+
+  #[test]
+  fn conflicts_reach_the_ui_with_their_kind() {
+    let conflict = PathConflict::InternalDir {
+      path: PathBuf::from("/app/datasets/out.jsonl"),
+      dir: PathBuf::from("/app/datasets"),
+    };
+    let error = OutputError::from(conflict.clone());
+    assert_eq!(error.to_string(), conflict.to_string());
+    assert_eq!(
+      serde_json::to_value(&error).unwrap(),
+      json!({
+        "kind": "internalDir",
+        "message": conflict.to_string(),
+        "path": "/app/datasets/out.jsonl",
+        "dir": "/app/datasets",
+      })
+    );
+    let exists = OutputError::from(PathConflict::Exists(PathBuf::from("out.jsonl")));
+    assert_eq!((exists.kind, exists.dir), ("exists", None));
+    let failed = OutputError::from("No dataset loaded".to_string());
+    assert_eq!(
+      serde_json::to_value(&failed).unwrap(),
+      json!({ "kind": "failed", "message": "No dataset loaded", "path": null, "dir": null })
+    );
+  }
+}
@@ -0,0 +1,176 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use regex::{Regex, RegexBuilder};
+
+use crate::filters::keyword_normalized;
+use crate::io::read_record_values_bounded;
+use crate::models::{FieldMap, PatternExample, PatternTest};
+use crate::records::get_length_text;
+use crate::sample::sample_subset;
+use crate::state::{DatasetStore, IdSet};
+
+pub const DEFAULT_PATTERN_SAMPLE: usize = 2_000;
+/// Fixed so the same view always yields the same prediction.
+const PATTERN_SAMPLE_SEED: u64 = 0;
+/// Matching and non-matching examples returned, each.
+const PATTERN_EXAMPLE_LIMIT: usize = 10;
+/// Characters of context kept on each side of a match.
+const SNIPPET_CONTEXT_CHARS: usize = 80;
+/// Records read between cancel checks.
+const PATTERN_READ_BATCH: usize = 256;
+
+/// What `test_pattern` looks for and where.
+#[derive(Debug, Clone)]
+pub struct PatternSpec {
+  pub pattern: String,
+  /// `instruction`, `output` or `combined`, as the filters' length scope.
+  pub scope: String,
+  pub sample_size: usize,
+  pub case_sensitive: bool,
+  pub regex: bool,
+}
+
+/// A keyword matched like the include and exclude keyword filters, or a regex.
+enum PatternMatcher {
+  Keyword { keyword: String, case_sensitive: bool },
+  Regex(Regex),
+}
+
+impl PatternMatcher {
+  fn new(pattern: &str, case_sensitive: bool, regex: bool) -> Result<Self, String> {
+    if pattern.is_empty() {
+      return Err("Pattern is empty".to_string());
+    }
+    if regex {
+      let regex = RegexBuilder::new(pattern)
+        .case_insensitive(!case_sensitive)
+        .build()
+        .map_err(|e| format!("Invalid pattern: {e}"))?;
+      return Ok(PatternMatcher::Regex(regex));
+    }
+    Ok(PatternMatcher::Keyword {
+      keyword: keyword_normalized(pattern, case_sensitive),
+      case_sensitive,
+    })
+  }
+
+  /// The first match in `text`, split into the text before, the match and the
+  /// text after. A keyword found in lowercased text is cut from `text` when
+  /// lowercasing kept its byte positions, otherwise from the lowercased text.
+  fn split_first(&self, text: &str) -> Option<(String, String, String)> {
+    let (haystack, start, end) = match self {
+      PatternMatcher::Regex(regex) => {
+        let found = regex.find(text)?;
+        (text.to_string(), found.start(), found.end())
+      }
+      PatternMatcher::Keyword {
+        keyword,
+        case_sensitive,
+      } => {
+        let normalized = keyword_normalized(text, *case_sensitive);
+        let start = normalized.find(keyword.as_str())?;
+        let end = start + keyword.len();
+        let same_positions = normalized.len() == text.len()
+          && text.is_char_boundary(start)
+          && text.is_char_boundary(end);
+        if same_positions {
+          (text.to_string(), start, end)
+        } else {
+          (normalized, start, end)
+        }
+      }
+    };
+    Some((
+      haystack[..start].to_string(),
+      haystack[start..end].to_string(),
+      haystack[end..].to_string(),
+    ))
+  }
+}
+
+pub(crate) fn last_chars(text: &str, count: usize) -> String {
+  let total = text.chars().count();
+  if total <= count {
+    return text.to_string();
+  }
+  let kept = text.chars().skip(total - count).collect::<String>();
+  format!("…{kept}")
+}
+
+pub(crate) fn first_chars(text: &str, count: usize) -> String {
+  let mut chars = text.chars();
+  let kept = chars.by_ref().take(count).collect::<String>();
+  if chars.next().is_some() {
+    format!("{kept}…")
+  } else {
+    kept
+  }
+}
+
+/// Looks for the pattern in the scoped text of a seeded sample of `ids`, the
+/// way the keyword filters read it, and reports the match rate with examples.
+pub fn test_pattern(
+  store: &DatasetStore,
+  ids: &IdSet,
+  spec: &PatternSpec,
+  field_map: &FieldMap,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<PatternTest, String> {
+  let scope = spec.scope.as_str();
+  if !matches!(scope, "instruction" | "output" | "combined") {
+    return Err(format!("Unknown pattern scope: {scope}"));
+  }
+  let matcher = PatternMatcher::new(&spec.pattern, spec.case_sensitive, spec.regex)?;
+  let sample = sample_subset(ids, Some(spec.sample_size.max(1)), PATTERN_SAMPLE_SEED)
+    .iter()
+    .collect::<Vec<_>>();
+  let mut result = PatternTest {
+    view_count: ids.len(),
+    scanned_count: 0,
+    matched_count: 0,
+    match_rate: 0.0,
+    matches: Vec::new(),
+    non_matches: Vec::new(),
+  };
+  for batch in sample.chunks(PATTERN_READ_BATCH) {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Pattern test canceled".to_string());
+    }
+    let records = read_record_values_bounded(store, batch, store.max_record_bytes)?;
+    for (id, record) in batch.iter().zip(records) {
+      let Ok(record) = record else {
+        continue;
+      };
+      let text = get_length_text(&record, field_map, scope);
+      result.scanned_count += 1;
+      match matcher.split_first(&text) {
+        Some((before, matched, after)) => {
+          result.matched_count += 1;
+          if result.matches.len() < PATTERN_EXAMPLE_LIMIT {
+            result.matches.push(PatternExample {
+              id: *id,
+              before: last_chars(&before, SNIPPET_CONTEXT_CHARS),
+              matched,
+              after: first_chars(&after, SNIPPET_CONTEXT_CHARS),
+            });
+          }
+        }
+        None if result.non_matches.len() < PATTERN_EXAMPLE_LIMIT => {
+          result.non_matches.push(PatternExample {
+            id: *id,
+            before: first_chars(&text, SNIPPET_CONTEXT_CHARS * 2),
+            matched: String::new(),
+            after: String::new(),
+          });
+        }
+        None => {}
+      }
+    }
+    on_progress(result.scanned_count, sample.len());
+  }
+  if result.scanned_count > 0 {
+    result.match_rate = result.matched_count as f64 / result.scanned_count as f64;
+  }
+  Ok(result)
+}
@@ -0,0 +1,278 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use regex::Regex;
+
+use crate::io::read_record_values_bounded;
+use crate::models::{FieldMap, PiiFilter, PiiMatch, PiiScan};
+use crate::pattern::{first_chars, last_chars};
+use crate::records::get_length_text;
+use crate::sample::sample_subset;
+use crate::state::{DatasetStore, IdSet};
+
+pub const PII_EMAIL: &str = "email";
+pub const PII_PHONE: &str = "phone";
+pub const PII_URL: &str = "url";
+pub const PII_IP_ADDRESS: &str = "ip_address";
+
+pub const DEFAULT_PII_SAMPLE: usize = 2_000;
+/// Fixed so the same view always yields the same scan.
+const PII_SAMPLE_SEED: u64 = 0;
+/// Masked matches returned by a scan.
+const PII_EXAMPLE_LIMIT: usize = 20;
+/// Characters of context kept on each side of a match.
+const SNIPPET_CONTEXT_CHARS: usize = 60;
+/// Records read between cancel checks.
+const PII_READ_BATCH: usize = 256;
+
+const EMAIL_PATTERN: &str = r"(?i)\b[a-z0-9._%+-]+@[a-z0-9-]+(?:\.[a-z0-9-]+)*\.[a-z]{2,}\b";
+/// International numbers, or groups of 2-4 digits split by spaces, dots or
+/// dashes; `is_phone_number` checks the digit count and surroundings.
+const PHONE_PATTERN: &str =
+  r"(?:\+\d{1,3}[\s.-]?)?(?:\(\d{1,4}\)[\s.-]?)?\d{2,4}(?:[\s.-]\d{2,4}){1,4}|\+\d{9,15}";
+const URL_PATTERN: &str = r#"(?i)\b(?:https?://|www\.)[^\s<>"'()\[\]{}]+"#;
+/// Dotted IPv4 addresses, or IPv6 addresses written out in full.
+const IP_PATTERN: &str = concat!(
+  r"(?i)\b(?:(?:25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)\.){3}(?:25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)\b",
+  r"|\b(?:[0-9a-f]{1,4}:){7}[0-9a-f]{1,4}\b",
+);
+const DOTTED_QUAD_PATTERN: &str = r"^\d{1,3}(?:\.\d{1,3}){3}$";
+/// Year-first or year-last dates, which a following time would otherwise
+/// pad out to a phone number's digit count.
+const DATE_PATTERN: &str = r"^(?:\d{4}[-./]\d{1,2}[-./]\d{1,2}|\d{1,2}[-./]\d{1,2}[-./]\d{4})\b";
+
+struct Detector {
+  kind: &'static str,
+  regex: Regex,
+}
+
+/// Regex detectors for the kinds of personal data a `PiiFilter` drops, in
+/// the order emails, phone numbers, URLs, IP addresses.
+pub struct PiiDetector {
+  detectors: Vec<Detector>,
+  dotted_quad: Regex,
+  date: Regex,
+}
+
+impl PiiDetector {
+  pub fn new(filter: &PiiFilter) -> Result<Self, String> {
+    let enabled = [
+      (PII_EMAIL, EMAIL_PATTERN, filter.drop_emails),
+      (PII_PHONE, PHONE_PATTERN, filter.drop_phone_numbers),
+      (PII_URL, URL_PATTERN, filter.drop_urls),
+      (PII_IP_ADDRESS, IP_PATTERN, filter.drop_ip_addresses),
+    ];
+    let detectors = enabled
+      .into_iter()
+      .filter(|(_, _, enabled)| *enabled)
+      .map(|(kind, pattern, _)| {
+        let regex = Regex::new(pattern).map_err(|e| format!("Invalid {kind} pattern: {e}"))?;
+        Ok(Detector { kind, regex })
+      })
+      .collect::<Result<Vec<_>, String>>()?;
+    let dotted_quad = Regex::new(DOTTED_QUAD_PATTERN).map_err(|e| e.to_string())?;
+    let date = Regex::new(DATE_PATTERN).map_err(|e| e.to_string())?;
+    Ok(Self {
+      detectors,
+      dotted_quad,
+      date,
+    })
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.detectors.is_empty()
+  }
+
+  /// The first detector with a match in `text`.
+  pub fn detect(&self, text: &str) -> Option<&'static str> {
+    self
+      .detectors
+      .iter()
+      .find(|detector| self.find(detector, text).is_some())
+      .map(|detector| detector.kind)
+  }
+
+  /// Every detector with a match in `text`, with its first match.
+  pub fn matches(&self, text: &str) -> Vec<(&'static str, Range<usize>)> {
+    self
+      .detectors
+      .iter()
+      .filter_map(|detector| Some((detector.kind, self.find(detector, text)?)))
+      .collect()
+  }
+
+  /// `text` with every match masked, keeping the first character of each
+  /// and any punctuation so the shape stays readable.
+  pub fn mask(&self, text: &str) -> String {
+    let mut masked = text.to_string();
+    for detector in &self.detectors {
+      let mut result = String::with_capacity(masked.len());
+      let mut last = 0;
+      for found in detector.regex.find_iter(&masked) {
+        if !self.accepts(detector, &masked, found.range()) {
+          continue;
+        }
+        result.push_str(&masked[last..found.start()]);
+        result.push_str(&mask_match(found.as_str()));
+        last = found.end();
+      }
+      result.push_str(&masked[last..]);
+      masked = result;
+    }
+    masked
+  }
+
+  fn find(&self, detector: &Detector, text: &str) -> Option<Range<usize>> {
+    detector
+      .regex
+      .find_iter(text)
+      .map(|found| found.range())
+      .find(|range| self.accepts(detector, text, range.clone()))
+  }
+
+  fn accepts(&self, detector: &Detector, text: &str, range: Range<usize>) -> bool {
+    detector.kind != PII_PHONE || self.is_phone_number(text, range)
+  }
+
+  /// Whether a phone pattern match holds 9 to 15 digits, is not part of a
+  /// longer number or word, and is not a dotted IPv4 address or a date.
+  fn is_phone_number(&self, text: &str, range: Range<usize>) -> bool {
+    let candidate = &text[range.clone()];
+    let digits = candidate.chars().filter(char::is_ascii_digit).count();
+    let joined = |c: char| c.is_alphanumeric() || c == '.' || c == '-';
+    (9..=15).contains(&digits)
+      && !text[..range.start].chars().next_back().is_some_and(joined)
+      && !text[range.end..].chars().next().is_some_and(char::is_alphanumeric)
+      && !self.dotted_quad.is_match(candidate)
+      && !self.date.is_match(candidate)
+  }
+}
+
+fn mask_match(text: &str) -> String {
+  text
+    .chars()
+    .enumerate()
+    .map(|(idx, c)| if idx > 0 && c.is_alphanumeric() { '*' } else { c })
+    .collect()
+}
+
+/// Runs the detectors `filter` enables, or all of them when it enables none,
+/// on the instruction and output of a seeded sample of `ids`, and returns
+/// match counts with masked examples.
+pub fn scan_pii(
+  store: &DatasetStore,
+  ids: &IdSet,
+  filter: &PiiFilter,
+  field_map: &FieldMap,
+  sample_size: usize,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<PiiScan, String> {
+  let detector = if filter.is_active() {
+    PiiDetector::new(filter)?
+  } else {
+    PiiDetector::new(&PiiFilter {
+      drop_emails: true,
+      drop_phone_numbers: true,
+      drop_urls: true,
+      drop_ip_addresses: true,
+    })?
+  };
+  let sample = sample_subset(ids, Some(sample_size.max(1)), PII_SAMPLE_SEED)
+    .iter()
+    .collect::<Vec<_>>();
+  let mut result = PiiScan {
+    view_count: ids.len(),
+    scanned_count: 0,
+    matched_count: 0,
+    counts: BTreeMap::new(),
+    matches: Vec::new(),
+  };
+  for batch in sample.chunks(PII_READ_BATCH) {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("PII scan canceled".to_string());
+    }
+    let records = read_record_values_bounded(store, batch, store.max_record_bytes)?;
+    for (id, record) in batch.iter().zip(records) {
+      let Ok(record) = record else {
+        continue;
+      };
+      let text = get_length_text(&record, field_map, "combined");
+      result.scanned_count += 1;
+      let found = detector.matches(&text);
+      for (kind, _) in &found {
+        *result.counts.entry(kind.to_string()).or_insert(0) += 1;
+      }
+      let Some((kind, range)) = found.into_iter().min_by_key(|(_, range)| range.start) else {
+        continue;
+      };
+      result.matched_count += 1;
+      if result.matches.len() < PII_EXAMPLE_LIMIT {
+        result.matches.push(PiiMatch {
+          id: *id,
+          kind: kind.to_string(),
+          before: last_chars(&detector.mask(&text[..range.start]), SNIPPET_CONTEXT_CHARS),
+          matched: mask_match(&text[range.clone()]),
+          after: first_chars(&detector.mask(&text[range.end..]), SNIPPET_CONTEXT_CHARS),
+        });
+      }
+    }
+    on_progress(result.scanned_count, sample.len());
+  }
+  Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn all_kinds() -> PiiDetector {
+    PiiDetector::new(&PiiFilter {
+      drop_emails: true,
+      drop_phone_numbers: true,
+      drop_urls: true,
+      drop_ip_addresses: true,
+    })
+    .unwrap()
+  }
+
+  #[test]
+  fn dates_and_times_are_not_phone_numbers() {
+    let detector = all_kinds();
+    for text in [
+      "Logged at 2024-01-15 10:30:00 UTC",
+      "Due 15.01.2024 10:30",
+      "Released 2024/01/15 at 09:45",
+    ] {
+      assert_eq!(detector.detect(text), None, "{text}");
+      assert_eq!(detector.mask(text), text);
+    }
+  }
+
+  #[test]
+  fn phone_numbers_are_detected() {
+    let detector = all_kinds();
+    for text in ["Call +1 415 555 0123 today", "call 415-555-0123", "(028) 3822 1234"] {
+      assert_eq!(detector.detect(text), Some(PII_PHONE), "{text}");
+    }
+    assert_eq!(detector.detect("order 1234567890123456789"), None);
+  }
+
+  #[test]
+  fn ipv4_addresses_are_not_phone_numbers() {
+    let detector = all_kinds();
+    let found = detector.matches("ping 192.168.1.10 now");
+    assert_eq!(found, vec![(PII_IP_ADDRESS, 5..17)]);
+  }
+
+  #[test]
+  fn masking_keeps_the_first_character_and_punctuation() {
+    let detector = all_kinds();
+    assert_eq!(
+      detector.mask("mail jane.doe@example.com or call 415-555-0123"),
+      "mail j***.***@*******.*** or call 4**-***-****"
+    );
+    assert_eq!(detector.mask("host 10.0.0.1"), "host 1*.*.*.*");
+  }
+}
@@ -1,8 +1,16 @@
+use std::ops::Range;
+
 use serde_json::Value;
-use xxhash_rust::xxh3::xxh3_64;
+use xxhash_rust::xxh3::{xxh3_64, Xxh3};
 
 use crate::models::{FieldMap, PreviewField};
 
+/// Only the head of very long texts is hashed so a single huge field cannot stall a scan.
+pub const SIMHASH_MAX_BYTES: usize = 64 * 1024;
+pub const TRUNCATED_FIELD_MARKER: &str = "...[truncated]";
+/// Records larger than this are not parsed for preview.
+pub const PREVIEW_MAX_RECORD_BYTES: usize = 4 * 1024 * 1024;
+
 pub fn value_to_string(value: &Value) -> String {
   match value {
     Value::String(text) => text.clone(),
@@ -11,27 +19,204 @@ pub fn value_to_string(value: &Value) -> String {
   }
 }
 
+/// Leaves of `record` keyed by their dotted path, `meta.lang` for
+/// `{"meta": {"lang": ...}}`. Arrays are leaves unless `arrays` is set, when
+/// their items are keyed by index (`tags.0`). Empty objects and arrays are
+/// leaves.
+pub fn flatten_record(record: &Value, arrays: bool) -> Vec<(String, &Value)> {
+  fn flatten_into<'a>(
+    path: String,
+    value: &'a Value,
+    arrays: bool,
+    out: &mut Vec<(String, &'a Value)>,
+  ) {
+    match value {
+      Value::Object(map) if !map.is_empty() => {
+        for (key, item) in map {
+          flatten_into(format!("{path}.{key}"), item, arrays, out);
+        }
+      }
+      Value::Array(items) if arrays && !items.is_empty() => {
+        for (idx, item) in items.iter().enumerate() {
+          flatten_into(format!("{path}.{idx}"), item, arrays, out);
+        }
+      }
+      _ => out.push((path, value)),
+    }
+  }
+  let mut out = Vec::new();
+  for (key, value) in record.as_object().into_iter().flatten() {
+    flatten_into(key.clone(), value, arrays, &mut out);
+  }
+  out
+}
+
+/// Combining diacritics and variation selectors, which belong to the
+/// character before them.
+fn is_combining_mark(ch: char) -> bool {
+  matches!(
+    ch,
+    '\u{0300}'..='\u{036F}'
+      | '\u{1AB0}'..='\u{1AFF}'
+      | '\u{1DC0}'..='\u{1DFF}'
+      | '\u{20D0}'..='\u{20FF}'
+      | '\u{FE00}'..='\u{FE0F}'
+      | '\u{FE20}'..='\u{FE2F}'
+  )
+}
+
+/// The first `limit` characters of `text`, followed by `...` when anything
+/// was cut. Combining marks after the last character are kept with it.
 pub fn truncate_text(text: &str, limit: usize) -> String {
-  if text.len() <= limit {
+  let Some((cut, _)) = text.char_indices().nth(limit) else {
+    return text.to_string();
+  };
+  let end = text[cut..]
+    .char_indices()
+    .find(|(_, ch)| !is_combining_mark(*ch))
+    .map_or(text.len(), |(offset, _)| cut + offset);
+  if end == text.len() {
     return text.to_string();
   }
-  let mut out = text[..limit].to_string();
-  out.push_str("...");
-  out
+  format!("{}...", &text[..end])
+}
+
+/// Longest prefix of `text` that fits in `max_bytes` without splitting a character.
+pub fn byte_prefix(text: &str, max_bytes: usize) -> &str {
+  if text.len() <= max_bytes {
+    return text;
+  }
+  let mut end = max_bytes;
+  while !text.is_char_boundary(end) {
+    end -= 1;
+  }
+  &text[..end]
+}
+
+/// Shrinks the largest top-level fields of an object record until its JSON
+/// encoding fits in `max_bytes`. Returns whether anything was cut.
+pub fn shrink_record(record: &mut Value, max_bytes: usize) -> bool {
+  let Some(map) = record.as_object_mut() else {
+    return false;
+  };
+  let mut total = serde_json::to_vec(map).map(|bytes| bytes.len()).unwrap_or(0);
+  if total <= max_bytes {
+    return false;
+  }
+  let mut sizes = map
+    .iter()
+    .map(|(key, value)| {
+      let size = serde_json::to_vec(value).map(|bytes| bytes.len()).unwrap_or(0);
+      (key.clone(), size)
+    })
+    .collect::<Vec<_>>();
+  sizes.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+
+  for (key, size) in sizes {
+    if total <= max_bytes {
+      break;
+    }
+    let excess = total - max_bytes;
+    let Some(value) = map.get_mut(&key) else {
+      continue;
+    };
+    let replacement = match value {
+      Value::String(text) if size > excess + TRUNCATED_FIELD_MARKER.len() => {
+        let keep = text.len().saturating_sub(excess + TRUNCATED_FIELD_MARKER.len());
+        format!("{}{}", byte_prefix(text, keep), TRUNCATED_FIELD_MARKER)
+      }
+      _ => TRUNCATED_FIELD_MARKER.to_string(),
+    };
+    let new_size = serde_json::to_vec(&replacement)
+      .map(|bytes| bytes.len())
+      .unwrap_or(0);
+    *value = Value::String(replacement);
+    total = (total + new_size).saturating_sub(size);
+  }
+  true
+}
+
+/// Segments of a dotted field path such as `meta.category` or
+/// `messages.0.content`. `\.` is a literal dot and `\\` a backslash.
+pub fn field_path_segments(path: &str) -> Vec<String> {
+  let mut segments = Vec::new();
+  let mut segment = String::new();
+  let mut chars = path.chars();
+  while let Some(ch) = chars.next() {
+    match ch {
+      '\\' => match chars.next() {
+        Some(escaped @ ('.' | '\\')) => segment.push(escaped),
+        Some(other) => {
+          segment.push('\\');
+          segment.push(other);
+        }
+        None => segment.push('\\'),
+      },
+      '.' => segments.push(std::mem::take(&mut segment)),
+      _ => segment.push(ch),
+    }
+  }
+  segments.push(segment);
+  segments
+}
+
+/// The key `name` of an object, or else its lowercase form.
+fn lookup_key<'a>(value: &'a Value, name: &str) -> Option<&'a Value> {
+  value.get(name).or_else(|| value.get(name.to_lowercase()))
+}
+
+/// One step down a field path: an object key, or an index into an array.
+fn path_step<'a>(value: &'a Value, segment: &str) -> Option<&'a Value> {
+  match value {
+    Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+    _ => value.get(segment),
+  }
+}
+
+/// The value at `path` in `record`. A top-level field named exactly `path`
+/// wins; otherwise dots lead into nested objects and arrays. Only the last
+/// segment falls back to its lowercase form.
+pub fn resolve_field<'a>(record: &'a Value, path: &str) -> Option<&'a Value> {
+  if let Some(value) = lookup_key(record, path) {
+    return Some(value);
+  }
+  if !path.contains(['.', '\\']) {
+    return None;
+  }
+  let segments = field_path_segments(path);
+  let (last, parents) = segments.split_last()?;
+  let parent = parents
+    .iter()
+    .try_fold(record, |value, segment| path_step(value, segment))?;
+  match parent {
+    Value::Array(_) => path_step(parent, last),
+    _ => lookup_key(parent, last),
+  }
 }
 
 pub fn extract_field_value(record: &Value, field: &Option<String>) -> Option<Value> {
-  let field_name = field.as_ref()?;
-  record
-    .get(field_name)
-    .cloned()
-    .or_else(|| record.get(&field_name.to_lowercase()).cloned())
+  resolve_field(record, field.as_ref()?).cloned()
 }
 
 pub fn extract_text_value(record: &Value, field: &Option<String>) -> Option<String> {
   extract_field_value(record, field).map(|value| value_to_string(&value))
 }
 
+/// Weight used when a record has no value in the weight field.
+pub const DEFAULT_WEIGHT: f64 = 1.0;
+
+/// The record's weight, `DEFAULT_WEIGHT` when absent or null, or `None` when
+/// the value is not a positive finite number.
+pub fn record_weight(record: &Value, field: &Option<String>) -> Option<f64> {
+  let weight = match extract_field_value(record, field) {
+    None | Some(Value::Null) => return Some(DEFAULT_WEIGHT),
+    Some(Value::Number(number)) => number.as_f64(),
+    Some(Value::String(text)) => text.trim().parse::<f64>().ok(),
+    Some(_) => None,
+  }?;
+  (weight.is_finite() && weight > 0.0).then_some(weight)
+}
+
 pub fn build_preview_fields(record: &Value, field_map: &FieldMap) -> Vec<PreviewField> {
   let mut fields = Vec::new();
   let mut used = Vec::new();
@@ -96,10 +281,27 @@ pub fn build_preview_fields(record: &Value, field_map: &FieldMap) -> Vec<Preview
   fields
 }
 
+pub fn oversized_preview_fields(size: u64) -> Vec<PreviewField> {
+  vec![PreviewField {
+    name: "record".to_string(),
+    value: format!("Record too large to preview ({size} bytes)"),
+    kind: "meta".to_string(),
+  }]
+}
+
 pub fn text_length(value: &str) -> usize {
   value.chars().count()
 }
 
+/// Length of `text` in `unit`: estimated tokens for `tokens`, characters otherwise.
+pub fn text_length_in(text: &str, unit: &str) -> usize {
+  if unit == "tokens" {
+    count_tokens(text)
+  } else {
+    text_length(text)
+  }
+}
+
 pub fn get_length_text(record: &Value, field_map: &FieldMap, scope: &str) -> String {
   match scope {
     "output" => extract_text_value(record, &field_map.output).unwrap_or_default(),
@@ -112,6 +314,265 @@ pub fn get_length_text(record: &Value, field_map: &FieldMap, scope: &str) -> Str
   }
 }
 
+/// Output length over instruction length in `chars` or `tokens`, or `None`
+/// when either text is empty.
+pub fn output_instruction_ratio(record: &Value, field_map: &FieldMap, unit: &str) -> Option<f64> {
+  let measure = |field: &Option<String>| {
+    let text = extract_text_value(record, field).unwrap_or_default();
+    let length = if unit == "tokens" {
+      count_tokens(&text)
+    } else {
+      text_length(text.trim())
+    };
+    Some(length).filter(|length| *length > 0)
+  };
+  let instruction = measure(&field_map.instruction)?;
+  let output = measure(&field_map.output)?;
+  Some(output as f64 / instruction as f64)
+}
+
+fn is_cjk(c: char) -> bool {
+  matches!(
+    c as u32,
+    0x3040..=0x30FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xAC00..=0xD7AF | 0xF900..=0xFAFF
+  )
+}
+
+/// Rough LLM token estimate: about four characters per token for words in
+/// alphabetic scripts, one token per CJK character and per punctuation mark.
+pub fn count_tokens(text: &str) -> usize {
+  let mut tokens = 0usize;
+  let mut word_chars = 0usize;
+  let flush = |word_chars: &mut usize, tokens: &mut usize| {
+    if *word_chars > 0 {
+      *tokens += word_chars.div_ceil(4);
+      *word_chars = 0;
+    }
+  };
+  for c in text.chars() {
+    if is_cjk(c) {
+      flush(&mut word_chars, &mut tokens);
+      tokens += 1;
+    } else if c.is_alphanumeric() {
+      word_chars += 1;
+    } else {
+      flush(&mut word_chars, &mut tokens);
+      if !c.is_whitespace() {
+        tokens += 1;
+      }
+    }
+  }
+  flush(&mut word_chars, &mut tokens);
+  tokens
+}
+
+/// Byte ranges of the tokens `count_tokens` estimates: four-character slices of
+/// words, single CJK characters and single punctuation marks.
+pub fn token_spans(text: &str) -> Vec<Range<usize>> {
+  let mut spans = Vec::new();
+  let mut word: Option<(usize, usize)> = None;
+  for (offset, c) in text.char_indices() {
+    if !is_cjk(c) && c.is_alphanumeric() {
+      let (start, chars) = word.get_or_insert((offset, 0));
+      *chars += 1;
+      if *chars == 4 {
+        spans.push(*start..offset + c.len_utf8());
+        word = None;
+      }
+      continue;
+    }
+    if let Some((start, _)) = word.take() {
+      spans.push(start..offset);
+    }
+    if !c.is_whitespace() {
+      spans.push(offset..offset + c.len_utf8());
+    }
+  }
+  if let Some((start, _)) = word {
+    spans.push(start..text.len());
+  }
+  spans
+}
+
+/// Byte offsets just past each sentence of `text`: after `.`, `!`, `?` or `…`
+/// followed by whitespace or the end, after CJK full stops, and at line breaks.
+pub fn sentence_ends(text: &str) -> Vec<usize> {
+  let mut ends = Vec::new();
+  let mut chars = text.char_indices().peekable();
+  while let Some((offset, c)) = chars.next() {
+    let end = offset + c.len_utf8();
+    let next_is_space = chars.peek().is_none_or(|(_, next)| next.is_whitespace());
+    let is_end = match c {
+      '.' | '!' | '?' | '…' => next_is_space,
+      '。' | '！' | '？' | '\n' => true,
+      _ => false,
+    };
+    if is_end {
+      ends.push(end);
+    }
+  }
+  ends
+}
+
+/// Where `truncate_tokens` may cut a text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TruncateBoundary {
+  Token,
+  /// The last sentence end within the limit, or the token limit when the
+  /// first sentence is already longer.
+  Sentence,
+}
+
+impl TruncateBoundary {
+  pub fn parse(boundary: &str) -> Result<Self, String> {
+    match boundary {
+      "token" => Ok(TruncateBoundary::Token),
+      "sentence" => Ok(TruncateBoundary::Sentence),
+      _ => Err(format!("Unknown truncation boundary: {boundary}")),
+    }
+  }
+}
+
+/// `text` cut to its first `max_tokens` tokens as `count_tokens` estimates
+/// them, with `marker` appended, and the number of tokens dropped. `None`
+/// when the text is within the limit.
+pub fn truncate_tokens(
+  text: &str,
+  max_tokens: usize,
+  boundary: TruncateBoundary,
+  marker: &str,
+) -> Option<(String, usize)> {
+  let spans = token_spans(text);
+  if spans.len() <= max_tokens {
+    return None;
+  }
+  let limit = max_tokens.checked_sub(1).map_or(0, |last| spans[last].end);
+  let cut = match boundary {
+    TruncateBoundary::Token => limit,
+    TruncateBoundary::Sentence => sentence_ends(&text[..limit])
+      .into_iter()
+      .rev()
+      .find(|end| !text[..*end].trim().is_empty())
+      .unwrap_or(limit),
+  };
+  let kept = spans.iter().take_while(|span| span.end <= cut).count();
+  let truncated = format!("{}{marker}", text[..cut].trim_end());
+  Some((truncated, spans.len() - kept))
+}
+
+/// The first `count` words of `text`, lowercased and without surrounding
+/// punctuation, joined by single spaces.
+pub fn leading_words(text: &str, count: usize) -> String {
+  text
+    .split_whitespace()
+    .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()))
+    .filter(|word| !word.is_empty())
+    .take(count)
+    .map(|word| word.to_lowercase())
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+pub const TEMPLATE_NUMBER: &str = "{N}";
+pub const TEMPLATE_QUOTE: &str = "{Q}";
+pub const TEMPLATE_ENTITY: &str = "{E}";
+
+fn closing_quote(open: char) -> Option<char> {
+  match open {
+    '"' => Some('"'),
+    '\'' => Some('\''),
+    '`' => Some('`'),
+    '“' => Some('”'),
+    '‘' => Some('’'),
+    '«' => Some('»'),
+    _ => None,
+  }
+}
+
+/// Byte offset of the quote closing a span that starts right before `text`.
+/// A straight single quote followed by a letter is an apostrophe, not a close.
+fn find_closing_quote(text: &str, close: char) -> Option<usize> {
+  text.match_indices(close).map(|(offset, _)| offset).find(|offset| {
+    close != '\''
+      || !text[offset + 1..]
+        .chars()
+        .next()
+        .is_some_and(char::is_alphanumeric)
+  })
+}
+
+/// Replaces quoted spans with `{Q}`. A straight single quote right after a
+/// letter is an apostrophe and never opens a span.
+fn mask_quotes(text: &str) -> String {
+  let mut out = String::with_capacity(text.len());
+  let mut rest = text;
+  let mut previous: Option<char> = None;
+  while let Some(c) = rest.chars().next() {
+    let after = &rest[c.len_utf8()..];
+    let close = closing_quote(c)
+      .filter(|_| c != '\'' || !previous.is_some_and(char::is_alphanumeric));
+    if let Some(close) = close {
+      if let Some(end) = find_closing_quote(after, close) {
+        out.push_str(TEMPLATE_QUOTE);
+        rest = &after[end + close.len_utf8()..];
+        previous = Some(close);
+        continue;
+      }
+    }
+    out.push(c);
+    previous = Some(c);
+    rest = after;
+  }
+  out
+}
+
+/// Heuristic template of an instruction: quoted spans become `{Q}`, words
+/// containing digits `{N}`, and runs of capitalized words that do not start a
+/// sentence or line `{E}`. Words are joined by single spaces, so instances of
+/// one template share the same mask. Only the head of very long texts is read.
+pub fn mask_template(text: &str) -> String {
+  let quoted = mask_quotes(byte_prefix(text, SIMHASH_MAX_BYTES));
+  let not_alphanumeric = |c: char| !c.is_alphanumeric();
+  let mut words: Vec<String> = Vec::new();
+  for line in quoted.lines() {
+    let mut sentence_start = true;
+    let mut entity_open = false;
+    for word in line.split_whitespace() {
+      let trimmed = word.trim_start_matches(not_alphanumeric);
+      let prefix = &word[..word.len() - trimmed.len()];
+      let core = trimmed.trim_end_matches(not_alphanumeric);
+      let suffix = &trimmed[core.len()..];
+      let mask = if core.is_empty() || word.contains(TEMPLATE_QUOTE) {
+        None
+      } else if core.chars().any(char::is_numeric) {
+        Some(TEMPLATE_NUMBER)
+      } else if !sentence_start
+        && core.split(['\'', '’']).next() != Some("I")
+        && core.chars().next().is_some_and(char::is_uppercase)
+      {
+        Some(TEMPLATE_ENTITY)
+      } else {
+        None
+      };
+      match mask {
+        Some(TEMPLATE_ENTITY) if entity_open && prefix.is_empty() => {
+          if let Some(last) = words.last_mut() {
+            last.push_str(suffix);
+          }
+        }
+        Some(mask) => words.push(format!("{prefix}{mask}{suffix}")),
+        None => words.push(word.to_string()),
+      }
+      entity_open = mask == Some(TEMPLATE_ENTITY) && suffix.is_empty();
+      let ends_sentence = word.ends_with(['.', '!', '?', ':']);
+      if !core.is_empty() || ends_sentence {
+        sentence_start = ends_sentence;
+      }
+    }
+  }
+  words.join(" ")
+}
+
 pub fn tokenize(text: &str) -> Vec<String> {
   text
     .split(|c: char| !c.is_alphanumeric())
@@ -120,21 +581,80 @@ pub fn tokenize(text: &str) -> Vec<String> {
     .collect()
 }
 
+fn feed_canonical(hasher: &mut Xxh3, value: &Value) {
+  match value {
+    Value::Null => hasher.update(b"n"),
+    Value::Bool(flag) => hasher.update(if *flag { b"t" } else { b"f" }),
+    Value::Number(number) => {
+      hasher.update(b"#");
+      hasher.update(number.to_string().as_bytes());
+    }
+    Value::String(text) => {
+      hasher.update(b"s");
+      hasher.update(&(text.len() as u64).to_le_bytes());
+      hasher.update(text.as_bytes());
+    }
+    Value::Array(items) => {
+      hasher.update(b"[");
+      hasher.update(&(items.len() as u64).to_le_bytes());
+      for item in items {
+        feed_canonical(hasher, item);
+      }
+    }
+    Value::Object(map) => {
+      let mut keys = map.keys().collect::<Vec<_>>();
+      keys.sort();
+      hasher.update(b"{");
+      hasher.update(&(keys.len() as u64).to_le_bytes());
+      for key in keys {
+        hasher.update(&(key.len() as u64).to_le_bytes());
+        hasher.update(key.as_bytes());
+        feed_canonical(hasher, &map[key]);
+      }
+    }
+  }
+}
+
+/// Hash of the record's content, independent of key order and formatting, so
+/// the same record gets the same value across imports.
+pub fn content_hash(record: &Value) -> u64 {
+  let mut hasher = Xxh3::new();
+  feed_canonical(&mut hasher, record);
+  hasher.digest()
+}
+
 pub fn simhash(text: &str) -> u64 {
+  shingled_simhash(text, 1)
+}
+
+/// Hashes of the runs of `shingle_size` consecutive words in the start of
+/// `text`. A text with fewer words is one shingle; a size of 1 gives one hash
+/// per word.
+pub fn shingle_hashes(text: &str, shingle_size: usize) -> Vec<u64> {
+  let tokens = tokenize(byte_prefix(text, SIMHASH_MAX_BYTES));
+  let size = shingle_size.clamp(1, tokens.len().max(1));
+  tokens
+    .windows(size)
+    .map(|shingle| xxh3_64(shingle.join("\u{1f}").as_bytes()))
+    .collect()
+}
+
+/// Simhash over word shingles, so texts sharing words in a different order
+/// drift apart as `shingle_size` grows.
+pub fn shingled_simhash(text: &str, shingle_size: usize) -> u64 {
   let mut weights = [0i32; 64];
-  for token in tokenize(text) {
-    let hash = xxh3_64(token.as_bytes());
-    for idx in 0..64 {
+  for hash in shingle_hashes(text, shingle_size) {
+    for (idx, weight) in weights.iter_mut().enumerate() {
       if (hash >> idx) & 1 == 1 {
-        weights[idx] += 1;
+        *weight += 1;
       } else {
-        weights[idx] -= 1;
+        *weight -= 1;
       }
     }
   }
   let mut out = 0u64;
-  for idx in 0..64 {
-    if weights[idx] > 0 {
+  for (idx, weight) in weights.iter().enumerate() {
+    if *weight > 0 {
       out |= 1u64 << idx;
     }
   }
@@ -144,3 +664,499 @@ pub fn simhash(text: &str) -> u64 {
 pub fn hamming_distance(a: u64, b: u64) -> u32 {
   (a ^ b).count_ones()
 }
+
+/// Category given to code no heuristic recognizes.
+pub const UNKNOWN_CODE_LANGUAGE: &str = "unknown";
+/// Keyword score a language needs before code is attributed to it.
+const MIN_CODE_LANGUAGE_SCORE: u32 = 3;
+/// Record fields that may name the language of the code.
+const CODE_LANGUAGE_FIELDS: &[&str] = &["language", "lang", "programming_language"];
+/// Record fields that may hold the path of the file the code came from.
+const CODE_PATH_FIELDS: &[&str] = &["path", "file_path", "filepath", "filename", "file_name"];
+
+/// Language of a piece of code and what gave it away: `metadata`, `path`,
+/// `fence`, `shebang`, `keywords`, or `none` for unknown code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeLanguage {
+  pub language: &'static str,
+  pub source: &'static str,
+}
+
+/// The language a name, file extension or fence tag stands for.
+fn code_language_alias(name: &str) -> Option<&'static str> {
+  match name.trim().to_ascii_lowercase().as_str() {
+    "python" | "python3" | "py" | "pyw" => Some("python"),
+    "javascript" | "js" | "jsx" | "mjs" | "cjs" | "node" | "nodejs" => Some("javascript"),
+    "typescript" | "ts" | "tsx" | "mts" | "cts" => Some("typescript"),
+    "rust" | "rs" => Some("rust"),
+    "java" => Some("java"),
+    "c" | "h" => Some("c"),
+    "cpp" | "c++" | "cc" | "cxx" | "hpp" | "hh" | "hxx" => Some("cpp"),
+    "sql" | "mysql" | "postgresql" | "postgres" | "sqlite" | "plpgsql" => Some("sql"),
+    "shell" | "sh" | "bash" | "zsh" | "ksh" => Some("shell"),
+    _ => None,
+  }
+}
+
+fn path_language(path: &str) -> Option<&'static str> {
+  let name = path.rsplit(['/', '\\']).next()?;
+  let (_, extension) = name.rsplit_once('.')?;
+  code_language_alias(extension)
+}
+
+fn fence_language(text: &str) -> Option<&'static str> {
+  text
+    .lines()
+    .map(str::trim_start)
+    .filter_map(|line| line.strip_prefix("```"))
+    .find_map(|info| code_language_alias(info.split_whitespace().next()?))
+}
+
+fn shebang_language(text: &str) -> Option<&'static str> {
+  let line = text.trim_start().lines().next()?.strip_prefix("#!")?;
+  if line.contains("python") {
+    Some("python")
+  } else if line.contains("node") {
+    Some("javascript")
+  } else if line.ends_with("sh") || line.contains("sh ") {
+    Some("shell")
+  } else {
+    None
+  }
+}
+
+#[derive(Default)]
+struct CodeScores {
+  python: u32,
+  javascript: u32,
+  typescript: u32,
+  rust: u32,
+  java: u32,
+  c: u32,
+  cpp: u32,
+  sql: u32,
+  shell: u32,
+}
+
+impl CodeScores {
+  fn add_line(&mut self, line: &str) {
+    let t = line.trim();
+    if t.is_empty() {
+      return;
+    }
+    let lower = t.to_ascii_lowercase();
+    let starts = |prefixes: &[&str]| prefixes.iter().any(|prefix| t.starts_with(prefix));
+    let contains = |needles: &[&str]| needles.iter().any(|needle| t.contains(needle));
+
+    if starts(&["def ", "class "]) && t.ends_with(':') {
+      self.python += 3;
+    }
+    if (starts(&["from "]) && t.contains(" import ")) || starts(&["if __name__"]) {
+      self.python += 3;
+    }
+    if starts(&["elif ", "for ", "if ", "while ", "with ", "try:", "except"]) && t.ends_with(':') {
+      self.python += 2;
+    }
+    if starts(&["import "]) && !t.ends_with(';') && !t.contains(" from ") {
+      self.python += 1;
+    }
+    if contains(&["self.", "print("]) && !t.ends_with(';') {
+      self.python += 1;
+    }
+
+    if contains(&["console.log(", "module.exports", "export default"]) {
+      self.javascript += 3;
+    }
+    if contains(&["function ", "require(", "document.", "window.", " === ", " !== "]) {
+      self.javascript += 2;
+    }
+    if starts(&["import "]) && t.contains(" from ") && contains(&["'", "\""]) {
+      self.javascript += 2;
+    }
+    if starts(&["const ", "let ", "var "]) {
+      self.javascript += if t.contains("=>") { 2 } else { 1 };
+    }
+    if contains(&[": string", ": number", ": boolean", ": any", "as const", "readonly "]) {
+      self.typescript += 3;
+    }
+    if starts(&["interface ", "export interface "])
+      || (starts(&["type ", "export type "]) && t.contains(" = "))
+    {
+      self.typescript += 3;
+    }
+
+    if starts(&["fn ", "pub fn ", "async fn ", "pub(crate) fn ", "impl ", "impl<"]) {
+      self.rust += 3;
+    }
+    if contains(&["let mut ", "println!(", "format!(", "vec![", ".unwrap()"])
+      || (starts(&["use "]) && t.contains("::") && t.ends_with(';'))
+    {
+      self.rust += 3;
+    }
+    if starts(&["#["]) || contains(&["&mut ", "&self", "Option<", "Result<"]) {
+      self.rust += 2;
+    }
+
+    if contains(&["public class ", "public static void main", "System.out.print"])
+      || starts(&["import java", "@Override"])
+      || (starts(&["package "]) && t.ends_with(';'))
+    {
+      self.java += 3;
+    }
+    if contains(&["String[]", "ArrayList<", "HashMap<"]) {
+      self.java += 2;
+    }
+    if starts(&["public ", "private ", "protected "]) && t.ends_with(['{', ';']) {
+      self.java += 1;
+    }
+
+    if starts(&["#include <"]) {
+      if t.ends_with(".h>") {
+        self.c += 2;
+      } else {
+        self.cpp += 3;
+      }
+    }
+    if contains(&["printf(", "malloc(", "sizeof(", "int main("]) || starts(&["#define "]) {
+      self.c += 2;
+    }
+    if contains(&["std::", "cout <<", "cin >>"])
+      || starts(&["template<", "template <", "using namespace "])
+    {
+      self.cpp += 3;
+    }
+
+    let lower_starts = |prefixes: &[&str]| prefixes.iter().any(|prefix| lower.starts_with(prefix));
+    if lower_starts(&[
+      "insert into ",
+      "create table ",
+      "create index ",
+      "alter table ",
+      "drop table ",
+      "delete from ",
+    ]) || (lower_starts(&["update "]) && lower.contains(" set "))
+    {
+      self.sql += 3;
+    }
+    if lower_starts(&["select ", "group by ", "order by ", "inner join ", "left join "]) {
+      self.sql += 2;
+    }
+    if lower_starts(&["from ", "where "]) {
+      self.sql += 1;
+    }
+
+    if starts(&["if [", "while ["]) || (starts(&["for "]) && t.contains("; do")) {
+      self.shell += 3;
+    }
+    if matches!(t, "fi" | "done" | "esac" | "then" | "do")
+      || starts(&["echo ", "$ ", "sudo ", "apt-get ", "cd ", "mkdir ", "chmod ", "pip install "])
+      || starts(&["npm install", "git ", "curl ", "wget "])
+    {
+      self.shell += 2;
+    }
+    if contains(&["$(", "${"]) {
+      self.shell += 1;
+    }
+  }
+
+  /// Best language by score; JavaScript and C win over their supersets
+  /// unless TypeScript or C++ features showed up.
+  fn best(&self) -> Option<&'static str> {
+    let javascript = if self.typescript >= MIN_CODE_LANGUAGE_SCORE {
+      "typescript"
+    } else {
+      "javascript"
+    };
+    let c = if self.cpp >= MIN_CODE_LANGUAGE_SCORE {
+      "cpp"
+    } else {
+      "c"
+    };
+    let candidates = [
+      ("python", self.python),
+      (javascript, self.javascript + self.typescript),
+      ("rust", self.rust),
+      ("java", self.java),
+      (c, self.c + self.cpp),
+      ("sql", self.sql),
+      ("shell", self.shell),
+    ];
+    let mut best: Option<(&'static str, u32)> = None;
+    for (language, score) in candidates {
+      if score >= MIN_CODE_LANGUAGE_SCORE && best.is_none_or(|(_, top)| score > top) {
+        best = Some((language, score));
+      }
+    }
+    best.map(|(language, _)| language)
+  }
+}
+
+/// Guesses the programming language of `code` from a fence tag, a shebang
+/// or, failing those, keyword scores. Deterministic; unknown when no
+/// language scores high enough.
+pub fn detect_code_language(code: &str) -> CodeLanguage {
+  let code = byte_prefix(code, SIMHASH_MAX_BYTES);
+  let found = |language, source| CodeLanguage { language, source };
+  if let Some(language) = fence_language(code) {
+    return found(language, "fence");
+  }
+  if let Some(language) = shebang_language(code) {
+    return found(language, "shebang");
+  }
+  let mut scores = CodeScores::default();
+  for line in code.lines() {
+    scores.add_line(line);
+  }
+  match scores.best() {
+    Some(language) => found(language, "keywords"),
+    None => found(UNKNOWN_CODE_LANGUAGE, "none"),
+  }
+}
+
+/// Language of a record's code: a language or file path field names it when
+/// present, otherwise the mapped code field, or the output when no code field
+/// is mapped, is classified.
+pub fn record_code_language(record: &Value, field_map: &FieldMap) -> CodeLanguage {
+  let field_text = |name: &str| extract_text_value(record, &Some(name.to_string()));
+  for name in CODE_LANGUAGE_FIELDS {
+    if let Some(language) = field_text(name).as_deref().and_then(code_language_alias) {
+      return CodeLanguage { language, source: "metadata" };
+    }
+  }
+  for name in CODE_PATH_FIELDS {
+    if let Some(language) = field_text(name).as_deref().and_then(path_language) {
+      return CodeLanguage { language, source: "path" };
+    }
+  }
+  let field = field_map.code.as_ref().or(field_map.output.as_ref()).cloned();
+  detect_code_language(&extract_text_value(record, &field).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+
+  #[test]
+  fn truncation_counts_vietnamese_letters_as_one_character() {
+    assert_eq!(truncate_text("Tiếng Việt rất đẹp", 5), "Tiếng...");
+    assert_eq!(truncate_text("Tiếng Việt rất đẹp", 10), "Tiếng Việt...");
+  }
+
+  #[test]
+  fn truncation_never_splits_an_emoji() {
+    assert_eq!(truncate_text("ab😀cd", 3), "ab😀...");
+    assert_eq!(truncate_text("❤\u{FE0F} love", 1), "❤\u{FE0F}...");
+  }
+
+  #[test]
+  fn combining_marks_at_the_boundary_stay_with_their_letter() {
+    let decomposed = "Vie\u{0302}\u{0323}t Nam";
+    assert_eq!(truncate_text(decomposed, 3), "Vie\u{0302}\u{0323}...");
+    assert_eq!(truncate_text("cafe\u{0301}", 4), "cafe\u{0301}");
+  }
+
+  #[test]
+  fn text_within_the_limit_gets_no_ellipsis() {
+    assert_eq!(truncate_text("Xin chào", 8), "Xin chào");
+    assert_eq!(truncate_text("Xin chào", 100), "Xin chào");
+    assert_eq!(truncate_text("", 0), "");
+    assert_eq!(truncate_text("a", 0), "...");
+  }
+
+  #[test]
+  fn quoted_spans_are_masked_but_apostrophes_are_not() {
+    assert_eq!(mask_template("Translate \"good morning\" please"), "Translate {Q} please");
+    assert_eq!(mask_template("Fix “the bug” and 'this'"), "Fix {Q} and {Q}");
+    assert_eq!(mask_template("don't stop, it's fine"), "don't stop, it's fine");
+  }
+
+  #[test]
+  fn words_with_digits_are_masked_as_numbers() {
+    assert_eq!(mask_template("What is 12 plus 7.5?"), "What is {N} plus {N}?");
+    assert_eq!(mask_template("Compare v2 and (3rd)"), "Compare {N} and ({N})");
+  }
+
+  #[test]
+  fn capitalized_runs_are_masked_unless_they_start_a_sentence() {
+    assert_eq!(
+      mask_template("Write a poem about New York City. Then stop"),
+      "Write a poem about {E}. Then stop"
+    );
+    assert_eq!(mask_template("Tell me what I'm missing"), "Tell me what I'm missing");
+    assert_eq!(mask_template("Summarize:\nParis is big"), "Summarize: Paris is big");
+  }
+
+  #[test]
+  fn instances_of_one_template_share_a_mask() {
+    assert_eq!(
+      mask_template("Email   Alice Smith about order 1042"),
+      mask_template("Email Bob about order 77")
+    );
+  }
+
+  fn field(record: &Value, path: &str) -> Option<Value> {
+    extract_field_value(record, &Some(path.to_string()))
+  }
+
+  #[test]
+  fn dotted_paths_lead_into_nested_objects() {
+    let record = json!({ "meta": { "category": "math", "lang": "vi" }, "meta.raw": true });
+    assert_eq!(field(&record, "meta.category"), Some(json!("math")));
+    // Only the last segment falls back to lowercase.
+    assert_eq!(field(&record, "meta.LANG"), Some(json!("vi")));
+    assert_eq!(field(&record, "META.category"), None);
+    assert_eq!(field(&record, "meta.raw"), Some(json!(true)));
+  }
+
+  #[test]
+  fn missing_intermediate_objects_resolve_to_nothing() {
+    let record = json!({ "meta": { "source": null, "tags": "a" } });
+    assert_eq!(field(&record, "info.category"), None);
+    assert_eq!(field(&record, "meta.source.name"), None);
+    assert_eq!(field(&record, "meta.tags.first"), None);
+    assert_eq!(field(&record, "meta.category.name"), None);
+  }
+
+  #[test]
+  fn numeric_segments_index_into_arrays() {
+    let record = json!({
+      "messages": [
+        { "role": "user", "content": "hi" },
+        { "role": "assistant", "content": "hello" }
+      ]
+    });
+    assert_eq!(field(&record, "messages.1.content"), Some(json!("hello")));
+    assert_eq!(field(&record, "messages.0"), Some(json!({ "role": "user", "content": "hi" })));
+    assert_eq!(field(&record, "messages.2.content"), None);
+    assert_eq!(field(&record, "messages.first.content"), None);
+  }
+
+  #[test]
+  fn escaped_dots_are_part_of_a_key() {
+    let record = json!({ "meta": { "v1.2": "old", "v1": { "2": "nested" } } });
+    assert_eq!(field(&record, "meta.v1\\.2"), Some(json!("old")));
+    assert_eq!(field(&record, "meta.v1.2"), Some(json!("nested")));
+    assert_eq!(field_path_segments("a\\\\b.c"), vec!["a\\b", "c"]);
+    assert_eq!(field_path_segments("x\\.y.z"), vec!["x.y", "z"]);
+  }
+
+  #[test]
+  fn token_truncation_cuts_multi_byte_text_on_character_boundaries() {
+    let vietnamese = "Xin chào các bạn. Hôm nay trời đẹp quá. Chúng ta đi chơi nhé!";
+    let sentence = TruncateBoundary::Sentence;
+    assert_eq!(
+      truncate_tokens(vietnamese, 8, sentence, "…"),
+      Some(("Xin chào các bạn.…".to_string(), 13))
+    );
+    assert_eq!(
+      truncate_tokens(vietnamese, 8, TruncateBoundary::Token, " [cut]"),
+      Some(("Xin chào các bạn. Hôm nay trời [cut]".to_string(), 10))
+    );
+    let chinese = "今天天气很好。我们去公园吧。然后回家。";
+    assert_eq!(
+      truncate_tokens(chinese, 8, sentence, "…"),
+      Some(("今天天气很好。…".to_string(), 12))
+    );
+    // A first sentence over the budget is cut at the token limit instead.
+    assert_eq!(
+      truncate_tokens(chinese, 3, sentence, "…"),
+      Some(("今天天…".to_string(), 16))
+    );
+    assert_eq!(
+      truncate_tokens("Đây là câu một. 😀 emoji 😀 here", 8, TruncateBoundary::Token, ""),
+      Some(("Đây là câu một. 😀 emoji".to_string(), 2))
+    );
+  }
+
+  #[test]
+  fn text_within_the_token_budget_is_not_truncated() {
+    let text = "Xin chào các bạn. 今天天气很好。";
+    let tokens = count_tokens(text);
+    assert_eq!(token_spans(text).len(), tokens);
+    for boundary in [TruncateBoundary::Token, TruncateBoundary::Sentence] {
+      assert_eq!(truncate_tokens(text, tokens, boundary, "…"), None);
+    }
+  }
+
+  #[test]
+  fn code_languages_are_recognized_by_keywords() {
+    let samples = [
+      ("python", "import os\n\ndef main():\n    for name in os.listdir('.'):\n        print(name)"),
+      ("javascript", "const add = (a, b) => a + b;\nconsole.log(add(1, 2));\n"),
+      ("typescript", "interface User {\n  name: string;\n}\nconst user: User = { name: 'x' };\n"),
+      ("rust", "use std::fs;\n\nfn main() {\n  let mut total = 0;\n  println!(\"{total}\");\n}"),
+      (
+        "java",
+        "public class Main {\n  public static void main(String[] args) {\n    \
+         System.out.println(\"hi\");\n  }\n}\n",
+      ),
+      ("c", "#include <stdio.h>\n\nint main(void) {\n  printf(\"hi\\n\");\n  return 0;\n}\n"),
+      ("cpp", "#include <iostream>\n\nint main() {\n  std::cout << \"hi\";\n}\n"),
+      ("sql", "SELECT name, COUNT(*)\nFROM users\nWHERE active = 1\nGROUP BY name;\n"),
+      ("shell", "if [ -d build ]; then\n  echo \"clean\"\n  rm -rf build\nfi\n"),
+    ];
+    for (language, code) in samples {
+      let found = detect_code_language(code);
+      assert_eq!(found, CodeLanguage { language, source: "keywords" }, "{code}");
+      assert_eq!(detect_code_language(code), found);
+    }
+  }
+
+  #[test]
+  fn prose_and_empty_code_are_unknown() {
+    let unknown = CodeLanguage {
+      language: UNKNOWN_CODE_LANGUAGE,
+      source: "none",
+    };
+    assert_eq!(detect_code_language(""), unknown);
+    assert_eq!(detect_code_language("The capital of France is Paris."), unknown);
+    assert_eq!(detect_code_language("x = 1"), unknown);
+  }
+
+  #[test]
+  fn fences_and_shebangs_override_keywords() {
+    let fenced = "Here you go:\n```ts\nconsole.log('hi');\n```";
+    let by_fence = CodeLanguage {
+      language: "typescript",
+      source: "fence",
+    };
+    assert_eq!(detect_code_language(fenced), by_fence);
+    let script = "#!/usr/bin/env python3\nconsole.log('not js')\n";
+    let by_shebang = CodeLanguage {
+      language: "python",
+      source: "shebang",
+    };
+    assert_eq!(detect_code_language(script), by_shebang);
+    let bash = "#!/bin/bash\nset -e\n";
+    assert_eq!(detect_code_language(bash).language, "shell");
+  }
+
+  #[test]
+  fn record_metadata_names_the_language_before_the_code() {
+    let field_map = FieldMap {
+      code: Some("code".to_string()),
+      output: Some("output".to_string()),
+      ..FieldMap::default()
+    };
+    let code = "def main():\n    print('hi')\n";
+    let tagged = json!({ "lang": "Rust", "path": "src/app.js", "code": code });
+    assert_eq!(
+      record_code_language(&tagged, &field_map),
+      CodeLanguage { language: "rust", source: "metadata" }
+    );
+    let pathed = json!({ "path": "src\\app.cpp", "code": code });
+    assert_eq!(
+      record_code_language(&pathed, &field_map),
+      CodeLanguage { language: "cpp", source: "path" }
+    );
+    let bare = json!({ "lang": "cobol", "code": code, "output": "SELECT 1;" });
+    assert_eq!(record_code_language(&bare, &field_map).language, "python");
+    let output_only = FieldMap {
+      code: None,
+      ..field_map
+    };
+    assert_eq!(record_code_language(&bare, &output_only).language, UNKNOWN_CODE_LANGUAGE);
+  }
+}
@@ -0,0 +1,197 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::io::store_lines;
+use crate::models::{FieldMap, FilterConfig};
+use crate::records::extract_text_value;
+use crate::state::{DatasetStore, IdSet};
+
+/// How many leading characters of the output are inspected for boilerplate.
+pub const REFUSAL_HEAD_CHARS: usize = 200;
+
+pub const DEFAULT_REFUSAL_PHRASES: &[&str] = &[
+  // English
+  "i'm sorry, but i can't",
+  "i'm sorry, but i cannot",
+  "i am sorry, but i cannot",
+  "i'm sorry, i can't",
+  "i apologize, but i can't",
+  "i apologize, but i cannot",
+  "i can't help with",
+  "i cannot help with",
+  "i can't assist with",
+  "i cannot assist with",
+  "i'm unable to",
+  "i am unable to",
+  "i cannot comply",
+  "i can't comply",
+  "i must decline",
+  "i won't be able to help",
+  "as an ai language model",
+  "as an ai model",
+  "as a language model",
+  "it is not appropriate for me to",
+  // Vietnamese
+  "xin lỗi, nhưng tôi không thể",
+  "xin lỗi, tôi không thể",
+  "tôi xin lỗi, nhưng",
+  "tôi rất tiếc, nhưng tôi không thể",
+  "tôi không thể giúp",
+  "tôi không thể hỗ trợ",
+  "tôi không được phép",
+  "là một mô hình ngôn ngữ ai",
+  "với tư cách là một mô hình ngôn ngữ",
+];
+
+fn normalize(text: &str) -> String {
+  text.replace(['\u{2019}', '\u{2018}'], "'").to_lowercase()
+}
+
+#[derive(Debug, Clone)]
+pub struct RefusalDetector {
+  phrases: Vec<String>,
+}
+
+impl RefusalDetector {
+  pub fn new(use_builtin: bool, extra: &[String]) -> Self {
+    let mut phrases = Vec::new();
+    if use_builtin {
+      phrases.extend(DEFAULT_REFUSAL_PHRASES.iter().map(|phrase| normalize(phrase)));
+    }
+    phrases.extend(
+      extra
+        .iter()
+        .map(|phrase| normalize(phrase.trim()))
+        .filter(|phrase| !phrase.is_empty()),
+    );
+    Self { phrases }
+  }
+
+  pub fn from_filters(filters: &FilterConfig) -> Self {
+    Self::new(filters.use_builtin_refusal_phrases, &filters.refusal_phrases)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.phrases.is_empty()
+  }
+
+  pub fn is_refusal(&self, text: &str) -> bool {
+    let head = text
+      .trim_start()
+      .chars()
+      .take(REFUSAL_HEAD_CHARS)
+      .collect::<String>();
+    let head = normalize(&head);
+    self.phrases.iter().any(|phrase| head.contains(phrase))
+  }
+}
+
+pub fn find_refusals(
+  store: &DatasetStore,
+  base_ids: Option<&IdSet>,
+  detector: &RefusalDetector,
+  field_map: &FieldMap,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<(IdSet, usize), String> {
+  let total = base_ids
+    .map(|set| set.len())
+    .unwrap_or(store.record_count);
+  let mut matches = IdSet::new();
+  let mut scanned = 0usize;
+  for line in store_lines(store)? {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Refusal scan canceled".to_string());
+    }
+    let line = line?;
+    let idx = line.id;
+    if let Some(set) = base_ids {
+      if !set.contains(idx) {
+        continue;
+      }
+    }
+    let Some(record) = line.record()? else {
+      continue;
+    };
+    let output_text = extract_text_value(&record, &field_map.output).unwrap_or_default();
+    if detector.is_refusal(&output_text) {
+      matches.insert(idx);
+    }
+    scanned += 1;
+    if scanned.is_multiple_of(1000) {
+      on_progress(scanned, total);
+    }
+  }
+  Ok((matches, scanned))
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+  use crate::models::{DatasetConfig, DistillConfig, Settings};
+
+  #[test]
+  fn refusals_are_matched_regardless_of_case_and_apostrophe() {
+    let detector = RefusalDetector::new(true, &[]);
+    assert!(detector.is_refusal("I'M SORRY, BUT I CAN'T help with that."));
+    assert!(detector.is_refusal("  I\u{2019}m sorry, but I can\u{2019}t share that."));
+    assert!(!detector.is_refusal("Sorry for the wait: here is the answer."));
+  }
+
+  #[test]
+  fn only_the_first_characters_of_the_output_are_checked() {
+    let detector = RefusalDetector::new(true, &[]);
+    // Two-byte letters, so the window is counted in characters, not bytes.
+    let lead = |chars: usize| "é".repeat(chars);
+    let phrase = "As an AI language model";
+    let inside = REFUSAL_HEAD_CHARS - phrase.len();
+    assert!(detector.is_refusal(&format!("{}{phrase}", lead(inside))));
+    assert!(!detector.is_refusal(&format!("{}{phrase}", lead(inside + 1))));
+  }
+
+  #[test]
+  fn vietnamese_refusals_are_matched() {
+    let detector = RefusalDetector::new(true, &[]);
+    assert!(detector.is_refusal("XIN LỖI, NHƯNG TÔI KHÔNG THỂ trả lời câu hỏi này."));
+    assert!(detector.is_refusal("Tôi rất tiếc, nhưng tôi không thể giúp bạn việc đó."));
+    assert!(!detector.is_refusal("Thủ đô của Việt Nam là Hà Nội."));
+  }
+
+  #[test]
+  fn user_phrases_extend_or_replace_the_shipped_ones() {
+    let extra = vec!["  Not My Job ".to_string(), " ".to_string()];
+    let both = RefusalDetector::new(true, &extra);
+    assert!(both.is_refusal("not my job, sorry"));
+    assert!(both.is_refusal("I must decline."));
+    let only = RefusalDetector::new(false, &extra);
+    assert!(only.is_refusal("NOT MY JOB"));
+    assert!(!only.is_refusal("I must decline."));
+    assert!(RefusalDetector::new(false, &[" ".to_string()]).is_empty());
+  }
+
+  #[test]
+  fn new_datasets_take_the_refusal_phrases_of_the_settings() {
+    let settings = |refusals: serde_json::Value| {
+      serde_json::from_value::<Settings>(json!({
+        "fieldMap": {},
+        "filters": { "refusalPhrases": ["from filters"] },
+        "distill": DistillConfig::default(),
+        "refusalPhrases": refusals,
+      }))
+      .unwrap()
+    };
+    let config = DatasetConfig::from_settings(&settings(json!(null)));
+    assert!(config.filters.use_builtin_refusal_phrases);
+    assert_eq!(config.filters.refusal_phrases, vec!["from filters"]);
+
+    let config = DatasetConfig::from_settings(&settings(json!({
+      "useBuiltin": false,
+      "phrases": ["from settings"],
+    })));
+    let detector = RefusalDetector::from_filters(&config.filters);
+    assert!(detector.is_refusal("From settings: no."));
+    assert!(!detector.is_refusal("From filters: no."));
+    assert!(!detector.is_refusal("I must decline."));
+  }
+}
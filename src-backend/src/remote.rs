@@ -0,0 +1,136 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use uuid::Uuid;
+
+/// Extensions `ingest_dataset` recognizes, kept on the downloaded file so the
+/// format is detected the same way as for a local one.
+const KNOWN_EXTENSIONS: &[&str] = &["jsonl", "json", "csv", "tsv", "gz"];
+
+fn agent() -> ureq::Agent {
+  ureq::AgentBuilder::new()
+    .timeout_connect(Duration::from_secs(15))
+    .timeout_read(Duration::from_secs(60))
+    .redirects(10)
+    .build()
+}
+
+/// Turns an HTTP failure into a message that names the status code.
+fn download_error(err: ureq::Error, url: &str) -> String {
+  match err {
+    ureq::Error::Status(code, response) => format!(
+      "Download of {url} failed with HTTP status {code} {}",
+      response.status_text()
+    ),
+    ureq::Error::Transport(transport) => format!("Network error downloading {url}: {transport}"),
+  }
+}
+
+pub fn validate_url(url: &str) -> Result<(), String> {
+  let lower = url.to_ascii_lowercase();
+  if !(lower.starts_with("http://") || lower.starts_with("https://")) {
+    return Err(format!("Only http and https URLs can be imported: {url}"));
+  }
+  Ok(())
+}
+
+/// A file name for the download: the last path segment of `url` when it has a
+/// known extension, otherwise one guessed from the content type.
+fn download_name(url: &str, content_type: &str) -> String {
+  let path = url.split(['?', '#']).next().unwrap_or_default();
+  let segment = path
+    .rsplit('/')
+    .next()
+    .unwrap_or_default()
+    .chars()
+    .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    .collect::<String>();
+  let known = Path::new(&segment)
+    .extension()
+    .and_then(|ext| ext.to_str())
+    .is_some_and(|ext| KNOWN_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+  if known {
+    return segment;
+  }
+  let content_type = content_type.to_ascii_lowercase();
+  let extension = if content_type.contains("ndjson") || content_type.contains("jsonl") {
+    ".jsonl"
+  } else if content_type.contains("json") {
+    ".json"
+  } else if content_type.contains("tab-separated") {
+    ".tsv"
+  } else if content_type.contains("csv") {
+    ".csv"
+  } else if content_type.contains("gzip") {
+    ".gz"
+  } else {
+    ""
+  };
+  format!("download{extension}")
+}
+
+/// Downloads `url` into `dest_dir`, following redirects and decoding a gzip
+/// content encoding. Reports bytes downloaded against `Content-Length`, or
+/// against 0 when the length is unknown or the body is encoded. Cancel stops
+/// the transfer; the partial file is removed on error or cancel.
+pub fn download_url(
+  url: &str,
+  dest_dir: &Path,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(u64, u64),
+) -> Result<PathBuf, String> {
+  validate_url(url)?;
+  fs::create_dir_all(dest_dir).map_err(|e| e.to_string())?;
+  let response = agent()
+    .get(url)
+    .call()
+    .map_err(|err| download_error(err, url))?;
+  let name = download_name(response.get_url(), response.content_type());
+  let dest = dest_dir.join(format!("{}-{name}", Uuid::new_v4()));
+  // The decoder drops `Content-Length` for encoded bodies, whose decoded size is unknown.
+  let total = response
+    .header("Content-Length")
+    .and_then(|value| value.parse::<u64>().ok())
+    .unwrap_or(0);
+
+  let result = (|| -> Result<(), String> {
+    // Dropping the reader on return closes the connection.
+    let mut reader = response.into_reader();
+    let mut writer = BufWriter::new(File::create(&dest).map_err(|e| e.to_string())?);
+    let mut buffer = vec![0u8; 256 * 1024];
+    let mut downloaded = 0u64;
+    let mut last_reported = 0u64;
+    on_progress(0, total);
+    loop {
+      if cancel.load(Ordering::SeqCst) {
+        return Err("Download canceled".to_string());
+      }
+      let read = reader
+        .read(&mut buffer)
+        .map_err(|e| format!("Network error downloading {url}: {e}"))?;
+      if read == 0 {
+        break;
+      }
+      writer.write_all(&buffer[..read]).map_err(|e| e.to_string())?;
+      downloaded += read as u64;
+      if downloaded - last_reported >= 1024 * 1024 {
+        last_reported = downloaded;
+        on_progress(downloaded, total);
+      }
+    }
+    writer.flush().map_err(|e| e.to_string())?;
+    if total > 0 && downloaded < total {
+      return Err(format!("Download of {url} ended after {downloaded} of {total} bytes"));
+    }
+    on_progress(downloaded, total);
+    Ok(())
+  })();
+  if let Err(err) = result {
+    let _ = fs::remove_file(&dest);
+    return Err(err);
+  }
+  Ok(dest)
+}
@@ -0,0 +1,157 @@
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::io::read_value_at;
+use crate::models::FieldMap;
+use crate::records::{extract_text_value, value_to_string};
+
+/// Largest rendering `render_records` produces, in bytes.
+pub const RENDER_MAX_BYTES: usize = 1024 * 1024;
+/// Characters kept per markdown table cell.
+const MARKDOWN_CELL_CHARS: usize = 200;
+
+/// One markdown table cell: pipes escaped, line breaks as `<br>`, and cut to
+/// `max_chars` characters.
+pub fn markdown_cell(text: &str, max_chars: usize) -> String {
+  let mut cell = String::new();
+  let mut chars = text.trim().chars();
+  for c in chars.by_ref().take(max_chars) {
+    match c {
+      '|' => cell.push_str("\\|"),
+      '\n' => cell.push_str("<br>"),
+      '\r' => {}
+      _ => cell.push(c),
+    }
+  }
+  if chars.next().is_some() {
+    cell.push('…');
+  }
+  cell
+}
+
+/// Mapped fields, or the fields of the records in order of first appearance
+/// when nothing is mapped.
+pub(crate) fn table_columns(records: &[(usize, Value)], field_map: &FieldMap) -> Vec<String> {
+  let mapped = field_map
+    .mapped_fields()
+    .into_iter()
+    .map(str::to_string)
+    .collect::<Vec<_>>();
+  if !mapped.is_empty() {
+    return mapped;
+  }
+  let mut columns: Vec<String> = Vec::new();
+  for (_, record) in records {
+    for name in record.as_object().into_iter().flat_map(|map| map.keys()) {
+      if !columns.contains(name) {
+        columns.push(name.clone());
+      }
+    }
+  }
+  columns
+}
+
+fn markdown_table(records: &[(usize, Value)], field_map: &FieldMap) -> String {
+  let columns = table_columns(records, field_map);
+  let mut lines = Vec::with_capacity(records.len() + 2);
+  let header = columns
+    .iter()
+    .map(|name| markdown_cell(name, MARKDOWN_CELL_CHARS))
+    .collect::<Vec<_>>();
+  lines.push(format!("| id | {} |", header.join(" | ")));
+  lines.push(format!("|---|{}", "---|".repeat(columns.len())));
+  for (id, record) in records {
+    let cells = columns
+      .iter()
+      .map(|name| {
+        let value = extract_text_value(record, &Some(name.clone())).unwrap_or_default();
+        markdown_cell(&value, MARKDOWN_CELL_CHARS)
+      })
+      .collect::<Vec<_>>();
+    lines.push(format!("| {id} | {} |", cells.join(" | ")));
+  }
+  lines.join("\n")
+}
+
+fn too_large_to_copy(bytes: usize) -> String {
+  format!(
+    "{} KB is too large to copy (limit {} KB); select fewer rows",
+    bytes.div_ceil(1024),
+    RENDER_MAX_BYTES / 1024
+  )
+}
+
+/// The records at `offsets`, as from `record_offsets`, in order. Stops with an
+/// error once their combined size passes `RENDER_MAX_BYTES`, so a selection
+/// too large to render is never held in memory.
+pub fn read_render_records(
+  store_path: &Path,
+  offsets: &[(usize, u64)],
+) -> Result<Vec<(usize, Value)>, String> {
+  let mut records = Vec::with_capacity(offsets.len());
+  let mut total = 0usize;
+  for &(id, offset) in offsets {
+    let budget = RENDER_MAX_BYTES - total;
+    let record = read_value_at(store_path, offset, budget)?
+      .map_err(|size| too_large_to_copy(total + size as usize))?;
+    total += record.to_string().len();
+    if total > RENDER_MAX_BYTES {
+      return Err(too_large_to_copy(total));
+    }
+    records.push((id, record));
+  }
+  Ok(records)
+}
+
+/// Renders records as a pretty JSON array (`json`), one compact record per line
+/// (`jsonl`) or a markdown table of the mapped fields (`markdown`). Fails when
+/// the result exceeds `RENDER_MAX_BYTES`.
+pub fn render_records(
+  records: &[(usize, Value)],
+  format: &str,
+  field_map: &FieldMap,
+) -> Result<String, String> {
+  let text = match format {
+    "json" => {
+      let values = records.iter().map(|(_, record)| record).collect::<Vec<_>>();
+      serde_json::to_string_pretty(&values).map_err(|e| e.to_string())?
+    }
+    "jsonl" => records
+      .iter()
+      .map(|(_, record)| value_to_string(record))
+      .collect::<Vec<_>>()
+      .join("\n"),
+    "markdown" => markdown_table(records, field_map),
+    other => return Err(format!("Unknown format: {other}")),
+  };
+  if text.len() > RENDER_MAX_BYTES {
+    return Err(too_large_to_copy(text.len()));
+  }
+  Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+  use crate::io::record_offsets;
+  use crate::test_support::{jsonl_store, TempDir};
+
+  #[test]
+  fn render_reads_stop_once_the_selection_passes_the_cap() {
+    let dir = TempDir::new();
+    let text = "x".repeat(RENDER_MAX_BYTES / 3);
+    let records = (0..4).map(|_| json!({ "text": text })).collect::<Vec<_>>();
+    let store = jsonl_store(&dir, &records);
+
+    let offsets = record_offsets(&store, &[0, 1]).unwrap();
+    let read = read_render_records(&store.store_path, &offsets).unwrap();
+    assert_eq!(read.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![0, 1]);
+
+    let offsets = record_offsets(&store, &[0, 1, 2, 3]).unwrap();
+    let err = read_render_records(&store.store_path, &offsets).unwrap_err();
+    assert!(err.contains("too large to copy"), "{err}");
+  }
+}
@@ -0,0 +1,269 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde_json::Value;
+
+use crate::categories::CategorySource;
+use crate::io::read_record_values_bounded;
+use crate::models::{CategoryRules, FieldMap, UNCATEGORIZED_LABEL};
+use crate::records::{extract_text_value, text_length};
+use crate::render::table_columns;
+use crate::state::{DatasetStore, IdSet};
+
+/// Records a review bundle holds by default.
+pub const DEFAULT_REVIEW_LIMIT: usize = 2_000;
+/// Most records a review bundle holds; larger views are cut to this.
+pub const MAX_REVIEW_LIMIT: usize = 5_000;
+/// Records larger than this are listed without their text.
+const REVIEW_RECORD_MAX_BYTES: usize = 1024 * 1024;
+/// Records read from the store at a time.
+const REVIEW_READ_CHUNK: usize = 500;
+/// Characters of a cell shown before it has to be expanded.
+const REVIEW_PREVIEW_CHARS: usize = 160;
+
+const REVIEW_STYLE: &str = "\
+body{font-family:system-ui,sans-serif;margin:24px;color:#1f2328;background:#fff}\
+h1{font-size:20px;margin:0 0 12px}\
+dl.manifest{display:grid;grid-template-columns:max-content 1fr;gap:4px 16px;margin:0 0 16px}\
+dl.manifest dt{font-weight:600}dl.manifest dd{margin:0;word-break:break-all}\
+.notice{background:#fff8c5;border:1px solid #d4a72c;padding:8px 12px;border-radius:6px}\
+.controls{display:flex;gap:12px;align-items:center;margin:16px 0 8px}\
+#search{flex:1;max-width:480px;padding:6px 8px;font-size:14px}\
+.chips{display:flex;flex-wrap:wrap;gap:6px;margin:0 0 12px}\
+.chip{display:inline-block;padding:2px 8px;border-radius:12px;background:#ddf4ff;\
+border:1px solid #54aeff;font-size:12px;white-space:nowrap}\
+button.chip{cursor:pointer}button.chip.active{background:#0969da;color:#fff}\
+table{border-collapse:collapse;width:100%;font-size:13px}\
+th,td{border:1px solid #d0d7de;padding:6px 8px;text-align:left;vertical-align:top}\
+th{background:#f6f8fa;position:sticky;top:0}\
+td{white-space:pre-wrap;word-break:break-word;max-width:640px}\
+details summary{cursor:pointer}details pre{white-space:pre-wrap;margin:6px 0 0;font:inherit}\
+.oversized{color:#9a6700;font-style:italic}";
+
+const REVIEW_SCRIPT: &str = "\
+(function(){\
+var search=document.getElementById('search');\
+var count=document.getElementById('count');\
+var rows=Array.prototype.slice.call(document.querySelectorAll('tbody tr'));\
+var chips=Array.prototype.slice.call(document.querySelectorAll('button.chip'));\
+var category=null;\
+function apply(){\
+var query=search.value.trim().toLowerCase();var shown=0;\
+rows.forEach(function(row){\
+var match=(!query||row.textContent.toLowerCase().indexOf(query)>=0)&&\
+(category===null||row.getAttribute('data-category')===category);\
+row.style.display=match?'':'none';if(match){shown++;}});\
+count.textContent=shown+' of '+rows.length+' records';}\
+chips.forEach(function(chip){chip.addEventListener('click',function(){\
+var value=chip.getAttribute('data-category');\
+category=category===value?null:value;\
+chips.forEach(function(other){\
+other.classList.toggle('active',other.getAttribute('data-category')===category);});\
+apply();});});\
+search.addEventListener('input',apply);apply();})();";
+
+/// What a review bundle shows: the view it covers, summary lines for its
+/// header and how records are mapped and categorized.
+#[derive(Debug, Clone)]
+pub struct ReviewSpec {
+  pub view: String,
+  pub limit: usize,
+  /// Label and value pairs listed at the top of the page.
+  pub manifest: Vec<(String, String)>,
+  pub field_map: FieldMap,
+  pub category_rules: CategoryRules,
+}
+
+/// Escapes text for HTML element content and quoted attribute values.
+pub fn html_escape(text: &str) -> String {
+  let mut out = String::with_capacity(text.len());
+  for c in text.chars() {
+    match c {
+      '&' => out.push_str("&amp;"),
+      '<' => out.push_str("&lt;"),
+      '>' => out.push_str("&gt;"),
+      '"' => out.push_str("&quot;"),
+      '\'' => out.push_str("&#39;"),
+      _ => out.push(c),
+    }
+  }
+  out
+}
+
+/// One table cell; text longer than the preview is folded into a
+/// `<details>` element holding the full text.
+fn text_cell(text: &str) -> String {
+  let text = text.trim();
+  if text_length(text) <= REVIEW_PREVIEW_CHARS {
+    return format!("<td>{}</td>", html_escape(text));
+  }
+  let preview = text.chars().take(REVIEW_PREVIEW_CHARS).collect::<String>();
+  format!(
+    "<td><details><summary>{}…</summary><pre>{}</pre></details></td>",
+    html_escape(&preview),
+    html_escape(text)
+  )
+}
+
+/// Renders up to `spec.limit` records of `ids`, in id order, as one
+/// self-contained HTML page: the manifest, a notice when the view was cut, a
+/// search box, category chips and a table of the mapped fields. Nothing is
+/// loaded from the network. Returns the page and the records it holds.
+pub fn review_html(
+  store: &DatasetStore,
+  ids: &IdSet,
+  spec: &ReviewSpec,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<(String, usize), String> {
+  let limit = spec.limit.clamp(1, MAX_REVIEW_LIMIT);
+  let page_ids = ids.iter().take(limit).collect::<Vec<_>>();
+  let categories = CategorySource::new(spec.field_map.category.as_deref(), &spec.category_rules)?;
+
+  let mut records: Vec<(usize, Result<Value, u64>)> = Vec::with_capacity(page_ids.len());
+  for chunk in page_ids.chunks(REVIEW_READ_CHUNK) {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Review export canceled".to_string());
+    }
+    let values = read_record_values_bounded(store, chunk, REVIEW_RECORD_MAX_BYTES)?;
+    records.extend(chunk.iter().copied().zip(values));
+    on_progress(records.len(), page_ids.len());
+  }
+
+  let readable = records
+    .iter()
+    .filter_map(|(id, record)| Some((*id, record.as_ref().ok()?.clone())))
+    .collect::<Vec<_>>();
+  let columns = table_columns(&readable, &spec.field_map)
+    .into_iter()
+    .filter(|name| Some(name) != spec.field_map.category.as_ref())
+    .collect::<Vec<_>>();
+  let show_categories = !categories.is_none();
+  let mut category_counts: BTreeMap<String, usize> = BTreeMap::new();
+
+  let mut body = String::new();
+  for (id, record) in &records {
+    match record {
+      Ok(record) => {
+        let category = show_categories.then(|| {
+          categories
+            .category(record, &spec.field_map)
+            .unwrap_or_else(|| UNCATEGORIZED_LABEL.to_string())
+        });
+        let _ = write!(body, "<tr");
+        if let Some(category) = &category {
+          *category_counts.entry(category.clone()).or_insert(0) += 1;
+          let _ = write!(body, " data-category=\"{}\"", html_escape(category));
+        }
+        let _ = write!(body, "><td>{id}</td>");
+        if let Some(category) = &category {
+          let _ = write!(body, "<td><span class=\"chip\">{}</span></td>", html_escape(category));
+        }
+        for name in &columns {
+          let value = extract_text_value(record, &Some(name.clone())).unwrap_or_default();
+          body.push_str(&text_cell(&value));
+        }
+        body.push_str("</tr>\n");
+      }
+      Err(size) => {
+        let span = columns.len() + usize::from(show_categories);
+        let _ = writeln!(
+          body,
+          "<tr><td>{id}</td><td class=\"oversized\" colspan=\"{span}\">\
+           Record too large to show ({size} bytes)</td></tr>"
+        );
+      }
+    }
+  }
+
+  let title = format!("Review: {}", spec.view);
+  let mut page = String::new();
+  let _ = write!(
+    page,
+    "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+     <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+     <title>{}</title>\n<style>{REVIEW_STYLE}</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+    html_escape(&title),
+    html_escape(&title)
+  );
+  page.push_str("<dl class=\"manifest\">\n");
+  for (label, value) in &spec.manifest {
+    let _ = writeln!(page, "<dt>{}</dt><dd>{}</dd>", html_escape(label), html_escape(value));
+  }
+  page.push_str("</dl>\n");
+  if records.len() < ids.len() {
+    let _ = writeln!(
+      page,
+      "<p class=\"notice\">This bundle holds the first {} of {} records in the view, \
+       in id order.</p>",
+      records.len(),
+      ids.len()
+    );
+  }
+  page.push_str(
+    "<div class=\"controls\"><input id=\"search\" type=\"search\" \
+     placeholder=\"Search records\"><span id=\"count\"></span></div>\n",
+  );
+  if !category_counts.is_empty() {
+    page.push_str("<div class=\"chips\">");
+    for (name, count) in &category_counts {
+      let name = html_escape(name);
+      let _ = write!(
+        page,
+        "<button type=\"button\" class=\"chip\" data-category=\"{name}\">{name} ({count})</button>"
+      );
+    }
+    page.push_str("</div>\n");
+  }
+  page.push_str("<table>\n<thead><tr><th>id</th>");
+  if show_categories {
+    page.push_str("<th>category</th>");
+  }
+  for name in &columns {
+    let _ = write!(page, "<th>{}</th>", html_escape(name));
+  }
+  page.push_str("</tr></thead>\n<tbody>\n");
+  page.push_str(&body);
+  let _ = write!(
+    page,
+    "</tbody>\n</table>\n<script>{REVIEW_SCRIPT}</script>\n</body>\n</html>\n"
+  );
+  Ok((page, records.len()))
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+  use crate::test_support::{jsonl_store, text_field_map, TempDir};
+
+  #[test]
+  fn record_text_cannot_inject_markup() {
+    let dir = TempDir::new();
+    let script = "<script>alert('x')</script>";
+    let records = [
+      json!({ "instruction": script, "output": "ok", "topic": "\"><b>" }),
+      json!({ "instruction": "long", "output": format!("{}{script}", "a".repeat(200)) }),
+    ];
+    let store = jsonl_store(&dir, &records);
+    let spec = ReviewSpec {
+      view: script.to_string(),
+      limit: DEFAULT_REVIEW_LIMIT,
+      manifest: vec![("Source".to_string(), script.to_string())],
+      field_map: FieldMap {
+        category: Some("topic".to_string()),
+        ..text_field_map()
+      },
+      category_rules: CategoryRules::default(),
+    };
+    let (page, count) =
+      review_html(&store, &IdSet::full(2), &spec, &AtomicBool::new(false), |_, _| {}).unwrap();
+    assert_eq!(count, 2);
+    assert_eq!(page.matches("<script>").count(), 1);
+    assert!(page.contains("&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;"));
+    assert!(page.contains("data-category=\"&quot;&gt;&lt;b&gt;\""));
+    assert!(!page.contains("<b>"));
+  }
+}
@@ -0,0 +1,127 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::distill::RecordMeta;
+use crate::filters::DuplicateMap;
+use crate::models::{ReviewQueueItem, ReviewReason, ReviewWeights};
+use crate::state::IdSet;
+
+/// What is known about the current distillation, for ranking records for
+/// manual review.
+pub struct ReviewSignals<'a> {
+  pub selected: &'a IdSet,
+  pub removed: &'a IdSet,
+  /// The strategy's ranking of the base records, when it has one.
+  pub ranking: Option<&'a [RecordMeta]>,
+  /// Whether selection was balanced per category, so each category has its own cut.
+  pub balanced: bool,
+  /// Whether selection spread over instruction prefixes, each with its own cut.
+  pub by_prefix: bool,
+  pub duplicates: Option<&'a DuplicateMap>,
+  pub refusals: Option<&'a IdSet>,
+  /// Records already included or excluded by hand, left out of the queue.
+  pub decided: &'a HashSet<usize>,
+}
+
+/// Nearness of each record to the cut of its group, in [0, 1]: the lowest
+/// scored selected records and the highest scored removed ones come first,
+/// fading out over `window` ranks on each side. Groups whose records all
+/// score the same carry no ordering and give no signal.
+fn boundary_signals(
+  signals: &ReviewSignals,
+  ranking: &[RecordMeta],
+  window: usize,
+) -> HashMap<usize, f64> {
+  let mut groups: BTreeMap<(Option<u32>, Option<u32>), Vec<&RecordMeta>> = BTreeMap::new();
+  for meta in ranking {
+    let category = meta.category.filter(|_| signals.balanced);
+    let prefix = meta.prefix.filter(|_| signals.by_prefix);
+    groups.entry((category, prefix)).or_default().push(meta);
+  }
+  let mut nearness = HashMap::new();
+  for metas in groups.values() {
+    let (mut kept, mut dropped): (Vec<&RecordMeta>, Vec<&RecordMeta>) = metas
+      .iter()
+      .copied()
+      .filter(|meta| signals.selected.contains(meta.id) || signals.removed.contains(meta.id))
+      .partition(|meta| signals.selected.contains(meta.id));
+    let (low, high) = kept
+      .iter()
+      .chain(&dropped)
+      .fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), meta| {
+        (low.min(meta.score), high.max(meta.score))
+      });
+    if kept.is_empty() || dropped.is_empty() || high <= low {
+      continue;
+    }
+    kept.sort_by(|a, b| a.score.total_cmp(&b.score).then(a.id.cmp(&b.id)));
+    dropped.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.id.cmp(&b.id)));
+    for side in [&kept, &dropped] {
+      for (rank, meta) in side.iter().take(window).enumerate() {
+        nearness.insert(meta.id, 1.0 - rank as f64 / window as f64);
+      }
+    }
+  }
+  nearness
+}
+
+/// The `limit` undecided records most worth a manual look, highest priority
+/// first. A record's priority is the weighted sum of its signals, and every
+/// signal it has is listed as a reason.
+pub fn review_queue(
+  signals: &ReviewSignals,
+  weights: &ReviewWeights,
+  limit: usize,
+) -> Vec<ReviewQueueItem> {
+  let mut reasons: BTreeMap<usize, Vec<(f64, ReviewReason)>> = BTreeMap::new();
+  let mut add = |id: usize, weight: f64, kind: &str, signal: f64, detail: String| {
+    if weight > 0.0 && signal > 0.0 && !signals.decided.contains(&id) {
+      let reason = ReviewReason {
+        kind: kind.to_string(),
+        signal,
+        detail,
+      };
+      reasons.entry(id).or_default().push((weight * signal, reason));
+    }
+  };
+
+  if let Some(ranking) = signals.ranking {
+    for (id, nearness) in boundary_signals(signals, ranking, limit.max(1)) {
+      let detail = if signals.selected.contains(id) {
+        "Among the lowest ranked records the distillation keeps"
+      } else {
+        "Among the highest ranked records the distillation drops"
+      };
+      add(id, weights.boundary, "boundary", nearness, detail.to_string());
+    }
+  }
+  if let Some(duplicates) = signals.duplicates {
+    let clusters = duplicates.clusters();
+    let largest = clusters.iter().map(|cluster| cluster.removed.len()).max().unwrap_or(0);
+    for cluster in clusters {
+      let size = cluster.removed.len();
+      let detail = format!(
+        "Kept over a cluster of duplicates ({size} dropped, least similar at {:.0}%)",
+        cluster.similarity * 100.0
+      );
+      add(cluster.kept, weights.duplicates, "duplicates", size as f64 / largest as f64, detail);
+    }
+  }
+  if let Some(refusals) = signals.refusals {
+    for id in refusals.iter() {
+      add(id, weights.refusal, "refusal", 1.0, "Output reads as a refusal".to_string());
+    }
+  }
+
+  let mut items = reasons
+    .into_iter()
+    .map(|(id, reasons)| ReviewQueueItem {
+      id,
+      priority: reasons.iter().map(|(contribution, _)| contribution).sum(),
+      selected: signals.selected.contains(id),
+      reasons: reasons.into_iter().map(|(_, reason)| reason).collect(),
+    })
+    .collect::<Vec<_>>();
+  items.sort_by(|a, b| b.priority.total_cmp(&a.priority).then(a.id.cmp(&b.id)));
+  items.truncate(limit);
+  items
+}
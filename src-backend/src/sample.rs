@@ -0,0 +1,55 @@
+use rand::rngs::StdRng;
+use rand::seq::index;
+use rand::SeedableRng;
+
+use crate::models::SampleSpec;
+use crate::state::IdSet;
+
+pub const SAMPLE_VIEW_PREFIX: &str = "sample:";
+
+pub fn validate_sample(spec: &SampleSpec) -> Result<(), String> {
+  if !(spec.percent > 0.0 && spec.percent <= 100.0) {
+    return Err(format!(
+      "Sample percent must be in (0, 100], got {}",
+      spec.percent
+    ));
+  }
+  Ok(())
+}
+
+/// View name of a sample, e.g. `sample:1%-seed42`.
+pub fn sample_view_name(spec: &SampleSpec) -> String {
+  format!("{SAMPLE_VIEW_PREFIX}{}%-seed{}", spec.percent, spec.seed)
+}
+
+/// Exactly `percent` of `0..record_count` (rounded, at least one id), chosen
+/// uniformly with `seed` so the same spec always yields the same sample.
+pub fn sample_ids(record_count: usize, spec: &SampleSpec) -> IdSet {
+  if record_count == 0 {
+    return IdSet::new();
+  }
+  let amount = ((record_count as f64 * spec.percent.clamp(0.0, 100.0) as f64 / 100.0).round()
+    as usize)
+    .clamp(1, record_count);
+  sample_count_ids(record_count, amount, spec.seed)
+}
+
+/// `amount` ids of `0..record_count` (all of them if fewer), chosen uniformly with `seed`.
+pub fn sample_count_ids(record_count: usize, amount: usize, seed: u64) -> IdSet {
+  let mut rng = StdRng::seed_from_u64(seed);
+  index::sample(&mut rng, record_count, amount.min(record_count))
+    .into_iter()
+    .collect()
+}
+
+/// Up to `amount` ids of `ids`, chosen uniformly with `seed`; all of them
+/// when `amount` is `None` or not smaller.
+pub fn sample_subset(ids: &IdSet, amount: Option<usize>, seed: u64) -> IdSet {
+  match amount {
+    Some(amount) if amount < ids.len() => sample_count_ids(ids.len(), amount, seed)
+      .iter()
+      .filter_map(|position| ids.page(position, 1).first().copied())
+      .collect(),
+    _ => ids.clone(),
+  }
+}
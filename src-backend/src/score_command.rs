@@ -0,0 +1,402 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use crate::io::store_lines;
+use crate::state::{DatasetStore, IdSet};
+use crate::transform::materialize;
+
+/// Records sent to the command per batch by default.
+pub const DEFAULT_SCORE_BATCH_SIZE: usize = 64;
+/// Seconds the command gets to score one batch by default.
+pub const DEFAULT_SCORE_TIMEOUT_SECS: u64 = 300;
+/// Lines of the command's stderr kept for error messages.
+const STDERR_TAIL_LINES: usize = 20;
+/// How long to wait for the last stderr lines after the command exits.
+const STDERR_GRACE: Duration = Duration::from_millis(500);
+/// How often the output wait checks for cancellation and the deadline.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Characters of a malformed output line quoted in the error.
+const QUOTED_LINE_CHARS: usize = 200;
+
+/// A user-provided scoring command, run through the platform shell. It reads
+/// `{"id": ..., "record": {...}}` lines on stdin and answers each with an
+/// `{"id": ..., "score": ...}` line on stdout, in any order within a batch.
+#[derive(Debug, Clone)]
+pub struct ScoreCommandSpec {
+  pub command: String,
+  pub batch_size: usize,
+  /// Longest wait for one batch's scores.
+  pub timeout: Duration,
+}
+
+/// Why a scoring command failed. Failures of the command itself carry the
+/// last lines it wrote to stderr.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScoreCommandError {
+  Spawn(String),
+  /// A batch was not fully scored within the timeout.
+  Timeout { secs: u64, stderr: Vec<String> },
+  /// The command exited unsuccessfully.
+  Exit { status: String, stderr: Vec<String> },
+  /// The command exited successfully before scoring every record sent.
+  Incomplete { missing: usize, stderr: Vec<String> },
+  /// An output line was not a score for a pending record.
+  Malformed {
+    line: usize,
+    text: String,
+    reason: String,
+    stderr: Vec<String>,
+  },
+  Canceled,
+  /// Reading the store failed.
+  Store(String),
+}
+
+fn write_stderr(f: &mut fmt::Formatter<'_>, stderr: &[String]) -> fmt::Result {
+  if stderr.is_empty() {
+    return Ok(());
+  }
+  write!(f, "\nstderr:")?;
+  for line in stderr {
+    write!(f, "\n{line}")?;
+  }
+  Ok(())
+}
+
+impl fmt::Display for ScoreCommandError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ScoreCommandError::Spawn(reason) => {
+        write!(f, "Could not start the scoring command: {reason}")
+      }
+      ScoreCommandError::Timeout { secs, stderr } => {
+        write!(f, "Scoring command did not score a batch within {secs}s")?;
+        write_stderr(f, stderr)
+      }
+      ScoreCommandError::Exit { status, stderr } => {
+        write!(f, "Scoring command failed ({status})")?;
+        write_stderr(f, stderr)
+      }
+      ScoreCommandError::Incomplete { missing, stderr } => {
+        write!(f, "Scoring command exited with {missing} records unscored")?;
+        write_stderr(f, stderr)
+      }
+      ScoreCommandError::Malformed {
+        line,
+        text,
+        reason,
+        stderr,
+      } => {
+        write!(f, "Scoring command output line {line} is invalid: {reason}: {text}")?;
+        write_stderr(f, stderr)
+      }
+      ScoreCommandError::Canceled => write!(f, "Scoring canceled"),
+      ScoreCommandError::Store(reason) => write!(f, "{reason}"),
+    }
+  }
+}
+
+/// The running command: stdin fed by a writer thread, stdout lines read into
+/// a channel and the tail of stderr kept, so no pipe can fill up and stall it.
+struct ScoreProcess {
+  child: Child,
+  batches: Option<mpsc::Sender<Vec<u8>>>,
+  lines: Receiver<String>,
+  stderr: Arc<Mutex<VecDeque<String>>>,
+  stderr_reader: JoinHandle<()>,
+  lines_read: usize,
+}
+
+fn shell_command(command: &str) -> Command {
+  if cfg!(windows) {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+  } else {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+  }
+}
+
+fn feed_stdin(mut stdin: ChildStdin, batches: Receiver<Vec<u8>>) {
+  for batch in batches {
+    // A command that stopped reading shows up as an exit or a timeout.
+    if stdin.write_all(&batch).and_then(|_| stdin.flush()).is_err() {
+      return;
+    }
+  }
+}
+
+impl ScoreProcess {
+  fn spawn(command: &str) -> Result<Self, ScoreCommandError> {
+    let mut child = shell_command(command)
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .spawn()
+      .map_err(|e| ScoreCommandError::Spawn(e.to_string()))?;
+    let (Some(stdin), Some(stdout), Some(stderr_pipe)) =
+      (child.stdin.take(), child.stdout.take(), child.stderr.take())
+    else {
+      let _ = child.kill();
+      let _ = child.wait();
+      return Err(ScoreCommandError::Spawn("missing pipes".to_string()));
+    };
+
+    let (batch_tx, batch_rx) = mpsc::channel::<Vec<u8>>();
+    thread::spawn(move || feed_stdin(stdin, batch_rx));
+    let (line_tx, lines) = mpsc::channel();
+    thread::spawn(move || {
+      for line in BufReader::new(stdout).lines() {
+        let Ok(line) = line else {
+          return;
+        };
+        if line_tx.send(line).is_err() {
+          return;
+        }
+      }
+    });
+    let stderr = Arc::new(Mutex::new(VecDeque::with_capacity(STDERR_TAIL_LINES)));
+    let tail = stderr.clone();
+    let stderr_reader = thread::spawn(move || {
+      for line in BufReader::new(stderr_pipe).lines() {
+        let Ok(line) = line else {
+          return;
+        };
+        if let Ok(mut tail) = tail.lock() {
+          if tail.len() == STDERR_TAIL_LINES {
+            tail.pop_front();
+          }
+          tail.push_back(line);
+        }
+      }
+    });
+    Ok(Self {
+      child,
+      batches: Some(batch_tx),
+      lines,
+      stderr,
+      stderr_reader,
+      lines_read: 0,
+    })
+  }
+
+  /// The last stderr lines, after giving the reader a moment to catch up
+  /// with a command that has exited.
+  fn stderr_tail(&self) -> Vec<String> {
+    let waited = Instant::now();
+    while !self.stderr_reader.is_finished() && waited.elapsed() < STDERR_GRACE {
+      thread::sleep(Duration::from_millis(10));
+    }
+    self
+      .stderr
+      .lock()
+      .map(|tail| tail.iter().cloned().collect())
+      .unwrap_or_default()
+  }
+
+  fn kill(&mut self) {
+    let _ = self.child.kill();
+    let _ = self.child.wait();
+  }
+
+  /// The error for a command whose stdout closed early.
+  fn exit_error(&mut self, missing: usize) -> ScoreCommandError {
+    match self.child.wait() {
+      Ok(status) if status.success() => ScoreCommandError::Incomplete {
+        missing,
+        stderr: self.stderr_tail(),
+      },
+      Ok(status) => ScoreCommandError::Exit {
+        status: status.to_string(),
+        stderr: self.stderr_tail(),
+      },
+      Err(e) => ScoreCommandError::Exit {
+        status: e.to_string(),
+        stderr: self.stderr_tail(),
+      },
+    }
+  }
+
+  fn malformed(&self, text: &str, reason: String) -> ScoreCommandError {
+    ScoreCommandError::Malformed {
+      line: self.lines_read,
+      text: text.chars().take(QUOTED_LINE_CHARS).collect(),
+      reason,
+      stderr: self.stderr_tail(),
+    }
+  }
+
+  /// Sends one batch and collects a score for each of its records.
+  fn score_batch(
+    &mut self,
+    batch: &[(usize, String)],
+    spec: &ScoreCommandSpec,
+    cancel: &AtomicBool,
+    scores: &mut HashMap<usize, f64>,
+  ) -> Result<(), ScoreCommandError> {
+    let mut input = Vec::new();
+    for (id, line) in batch {
+      input.extend_from_slice(format!("{{\"id\":{id},\"record\":{line}}}\n").as_bytes());
+    }
+    if let Some(batches) = &self.batches {
+      // A closed feeder means the command is gone; its exit is reported below.
+      let _ = batches.send(input);
+    }
+    let mut pending = batch.iter().map(|(id, _)| *id).collect::<HashSet<_>>();
+    let deadline = Instant::now() + spec.timeout;
+    while !pending.is_empty() {
+      if cancel.load(Ordering::SeqCst) {
+        return Err(ScoreCommandError::Canceled);
+      }
+      let line = match self.lines.recv_timeout(POLL_INTERVAL) {
+        Ok(line) => line,
+        Err(RecvTimeoutError::Timeout) if Instant::now() < deadline => continue,
+        Err(RecvTimeoutError::Timeout) => {
+          return Err(ScoreCommandError::Timeout {
+            secs: spec.timeout.as_secs(),
+            stderr: self.stderr_tail(),
+          })
+        }
+        Err(RecvTimeoutError::Disconnected) => return Err(self.exit_error(pending.len())),
+      };
+      self.lines_read += 1;
+      if line.trim().is_empty() {
+        continue;
+      }
+      let (id, score) = parse_score_line(&line).map_err(|reason| self.malformed(&line, reason))?;
+      if !pending.remove(&id) {
+        let reason = if scores.contains_key(&id) {
+          format!("record {id} was already scored")
+        } else {
+          format!("record {id} was not sent in this batch")
+        };
+        return Err(self.malformed(&line, reason));
+      }
+      scores.insert(id, score);
+    }
+    Ok(())
+  }
+
+  /// Closes stdin and waits for a clean exit.
+  fn finish(&mut self, spec: &ScoreCommandSpec) -> Result<(), ScoreCommandError> {
+    self.batches = None;
+    let deadline = Instant::now() + spec.timeout;
+    loop {
+      match self.child.try_wait() {
+        Ok(Some(status)) if status.success() => return Ok(()),
+        Ok(Some(status)) => {
+          return Err(ScoreCommandError::Exit {
+            status: status.to_string(),
+            stderr: self.stderr_tail(),
+          })
+        }
+        Ok(None) if Instant::now() < deadline => thread::sleep(POLL_INTERVAL),
+        Ok(None) => {
+          return Err(ScoreCommandError::Timeout {
+            secs: spec.timeout.as_secs(),
+            stderr: self.stderr_tail(),
+          })
+        }
+        Err(e) => {
+          return Err(ScoreCommandError::Exit {
+            status: e.to_string(),
+            stderr: self.stderr_tail(),
+          })
+        }
+      }
+    }
+  }
+}
+
+/// The id and finite score of an `{"id": ..., "score": ...}` line.
+fn parse_score_line(line: &str) -> Result<(usize, f64), String> {
+  let value: Value = serde_json::from_str(line).map_err(|e| format!("not JSON ({e})"))?;
+  let id = value
+    .get("id")
+    .and_then(Value::as_u64)
+    .ok_or_else(|| "missing a numeric id".to_string())?;
+  let score = value
+    .get("score")
+    .and_then(Value::as_f64)
+    .filter(|score| score.is_finite())
+    .ok_or_else(|| "missing a finite numeric score".to_string())?;
+  Ok((id as usize, score))
+}
+
+/// Scores the records of `ids` with the command, one batch at a time.
+/// `on_progress` is called after every batch. Canceling or any failure kills
+/// the command.
+pub fn score_with_command(
+  store: &DatasetStore,
+  ids: &IdSet,
+  spec: &ScoreCommandSpec,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<HashMap<usize, f64>, ScoreCommandError> {
+  let batch_size = spec.batch_size.max(1);
+  let mut process = ScoreProcess::spawn(&spec.command)?;
+  let mut scores = HashMap::with_capacity(ids.len());
+  let result = (|| {
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut lines = store_lines(store).map_err(ScoreCommandError::Store)?;
+    loop {
+      let next = lines.next();
+      if let Some(line) = &next {
+        let line = line.as_ref().map_err(|e| ScoreCommandError::Store(e.clone()))?;
+        // Records over the import cap are left unscored rather than buffered.
+        let text = line.bytes().filter(|_| ids.contains(line.id)).map(<[u8]>::trim_ascii);
+        if let Some(text) = text.filter(|text| !text.is_empty()) {
+          batch.push((line.id, String::from_utf8_lossy(text).into_owned()));
+        }
+      }
+      if batch.len() == batch_size || (next.is_none() && !batch.is_empty()) {
+        process.score_batch(&batch, spec, cancel, &mut scores)?;
+        batch.clear();
+        on_progress(scores.len(), ids.len());
+      }
+      if next.is_none() {
+        break;
+      }
+    }
+    process.finish(spec)
+  })();
+  if let Err(err) = result {
+    process.kill();
+    return Err(err);
+  }
+  Ok(scores)
+}
+
+/// A derived store with each scored record's score written to `field`;
+/// unscored records are copied unchanged. Returns the store and how many
+/// records got a score.
+pub fn attach_scores(
+  parent: &DatasetStore,
+  store_dir: &Path,
+  scores: &HashMap<usize, f64>,
+  field: &str,
+  cancel: &AtomicBool,
+  on_progress: impl FnMut(usize, usize),
+) -> Result<(DatasetStore, usize), String> {
+  let mut attached = 0usize;
+  let store = materialize(parent, store_dir, cancel, on_progress, |idx, mut record| {
+    if let (Some(score), Some(map)) = (scores.get(&idx), record.as_object_mut()) {
+      map.insert(field.to_string(), Value::from(*score));
+      attached += 1;
+    }
+    Ok(vec![record])
+  })?;
+  Ok((store, attached))
+}
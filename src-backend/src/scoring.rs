@@ -0,0 +1,120 @@
+use serde_json::Value;
+
+use crate::metrics::numeric_value;
+use crate::models::{ComponentRange, ComponentScore, DistillConfig, ScoreComponent};
+
+/// The value of each component in `record`, `None` where it is missing or not numeric.
+pub fn component_values(record: &Value, components: &[ScoreComponent]) -> Vec<Option<f64>> {
+  components
+    .iter()
+    .map(|component| numeric_value(record, &Some(component.field.clone())))
+    .collect()
+}
+
+/// Component values of every meta, one row each, kept for the normalization
+/// pass. Missing values are stored as NaN.
+#[derive(Debug, Default)]
+pub struct ComponentTable {
+  width: usize,
+  values: Vec<f64>,
+}
+
+impl ComponentTable {
+  pub fn new(width: usize) -> Self {
+    Self {
+      width,
+      values: Vec::new(),
+    }
+  }
+
+  pub fn push(&mut self, row: &[Option<f64>]) {
+    self
+      .values
+      .extend(row.iter().map(|value| value.unwrap_or(f64::NAN)));
+  }
+
+  pub fn push_missing(&mut self) {
+    self.values.extend(std::iter::repeat_n(f64::NAN, self.width));
+  }
+
+  pub fn row(&self, index: usize) -> Vec<Option<f64>> {
+    self.values[index * self.width..(index + 1) * self.width]
+      .iter()
+      .map(|value| Some(*value).filter(|value| !value.is_nan()))
+      .collect()
+  }
+
+  /// Smallest and largest present value of each component.
+  pub fn ranges(&self, components: &[ScoreComponent]) -> Vec<ComponentRange> {
+    components
+      .iter()
+      .enumerate()
+      .map(|(column, component)| {
+        let (min, max) = self
+          .values
+          .iter()
+          .skip(column)
+          .step_by(self.width.max(1))
+          .filter(|value| !value.is_nan())
+          .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| {
+            (min.min(*value), max.max(*value))
+          });
+        ComponentRange {
+          field: component.field.clone(),
+          min: min.is_finite().then_some(min),
+          max: max.is_finite().then_some(max),
+        }
+      })
+      .collect()
+  }
+}
+
+/// Rescales to [0, 1]. A component with a single value everywhere carries no
+/// signal and maps to 0.
+fn normalize(value: f64, range: &ComponentRange) -> f64 {
+  match (range.min, range.max) {
+    (Some(min), Some(max)) if max > min => (value - min) / (max - min),
+    _ => 0.0,
+  }
+}
+
+/// How each component adds to the weighted score. `ranges` is empty when the
+/// values are not normalized; a missing component takes
+/// `missing_component_value`, which is not normalized.
+pub fn score_components(
+  values: &[Option<f64>],
+  config: &DistillConfig,
+  ranges: &[ComponentRange],
+) -> Vec<ComponentScore> {
+  config
+    .score_components
+    .iter()
+    .zip(values)
+    .enumerate()
+    .map(|(index, (component, raw))| {
+      let value = match (raw, ranges.get(index)) {
+        (Some(raw), Some(range)) => normalize(*raw, range),
+        (Some(raw), None) => *raw,
+        (None, _) => config.missing_component_value,
+      };
+      ComponentScore {
+        field: component.field.clone(),
+        weight: component.weight,
+        raw: *raw,
+        value,
+        contribution: value * component.weight,
+      }
+    })
+    .collect()
+}
+
+pub fn weighted_score(
+  values: &[Option<f64>],
+  config: &DistillConfig,
+  ranges: &[ComponentRange],
+) -> f64 {
+  score_components(values, config, ranges)
+    .iter()
+    .map(|component| component.contribution)
+    .sum()
+}
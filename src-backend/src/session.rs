@@ -0,0 +1,128 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::{default_max_record_bytes, OrderKey};
+use crate::paths::{atomic_write_json, io_error};
+use crate::sample::SAMPLE_VIEW_PREFIX;
+use crate::state::{DatasetStore, InnerState};
+use crate::store_index::{load_store_index, scan_store_offsets};
+use crate::views::NAMED_VIEW_PREFIX;
+
+/// Records per preview page when the settings name none.
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+
+/// The dataset and preview the app showed last, kept so startup can return
+/// to them. The store's line index is rebuilt from its file on restore.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LastSession {
+  pub store_path: PathBuf,
+  pub source_path: PathBuf,
+  pub format: String,
+  #[serde(default)]
+  pub delimiter: Option<u8>,
+  #[serde(default)]
+  pub encoding: Option<String>,
+  #[serde(default)]
+  pub parent_id: Option<String>,
+  pub fields: Vec<String>,
+  pub size_bytes: u64,
+  #[serde(default)]
+  pub truncated: bool,
+  #[serde(default)]
+  pub resume_offset: Option<u64>,
+  #[serde(default = "default_max_record_bytes")]
+  pub max_record_bytes: usize,
+  pub view: String,
+  pub page: usize,
+  pub page_size: usize,
+  #[serde(default)]
+  pub order_by: Vec<OrderKey>,
+}
+
+impl LastSession {
+  /// A session on `store`, showing the first page of all records.
+  pub fn new(store: &DatasetStore) -> Self {
+    Self {
+      store_path: store.store_path.clone(),
+      source_path: store.source_path.clone(),
+      format: store.format.clone(),
+      delimiter: store.delimiter,
+      encoding: store.encoding.clone(),
+      parent_id: store.parent_id.clone(),
+      fields: store.fields.clone(),
+      size_bytes: store.size_bytes,
+      truncated: store.truncated,
+      resume_offset: store.resume_offset,
+      max_record_bytes: store.max_record_bytes,
+      view: "all".to_string(),
+      page: 1,
+      page_size: DEFAULT_PAGE_SIZE,
+      order_by: Vec::new(),
+    }
+  }
+}
+
+pub fn read_last_session(path: &Path) -> Result<Option<LastSession>, String> {
+  if !path.exists() {
+    return Ok(None);
+  }
+  let content = fs::read_to_string(path).map_err(|e| io_error(path, &e))?;
+  let session = serde_json::from_str(&content).map_err(|e| e.to_string())?;
+  Ok(Some(session))
+}
+
+pub fn write_last_session(path: &Path, session: &LastSession) -> Result<(), String> {
+  atomic_write_json(path, session)
+}
+
+/// Opens the store of `session` again, from its index sidecar when it has
+/// one and otherwise by rebuilding its line index. The id is the store's file
+/// name, so its sidecars are found as before.
+pub fn reopen_store(session: &LastSession) -> Result<DatasetStore, String> {
+  let path = &session.store_path;
+  if let Some(store) = load_store_index(path)? {
+    return Ok(store);
+  }
+  let id = path
+    .file_stem()
+    .and_then(|stem| stem.to_str())
+    .ok_or_else(|| format!("Not a dataset store: {}", path.display()))?
+    .to_string();
+  let offsets = scan_store_offsets(path)?;
+  Ok(DatasetStore {
+    id,
+    source_path: session.source_path.clone(),
+    store_path: path.clone(),
+    record_count: offsets.len(),
+    offsets,
+    fields: session.fields.clone(),
+    size_bytes: session.size_bytes,
+    format: session.format.clone(),
+    delimiter: session.delimiter,
+    encoding: session.encoding.clone(),
+    parent_id: session.parent_id.clone(),
+    truncated: session.truncated,
+    resume_offset: session.resume_offset,
+    max_record_bytes: session.max_record_bytes,
+  })
+}
+
+/// Whether `view` still names records after a restore: derived views only
+/// once they were computed, samples, tags and saved views only while they exist.
+pub fn restorable_view(inner: &InnerState, view: &str) -> bool {
+  match view {
+    "all" | "filtered" => true,
+    "selected" => inner.selected_ids.is_some(),
+    "removed" => inner.removed_ids.is_some(),
+    other if other.starts_with(SAMPLE_VIEW_PREFIX) => inner.samples.contains_key(other),
+    other if other.starts_with(NAMED_VIEW_PREFIX) => other
+      .strip_prefix(NAMED_VIEW_PREFIX)
+      .is_some_and(|name| inner.views.contains_key(name)),
+    other => other
+      .strip_prefix("tag:")
+      .is_some_and(|tag| inner.tags.contains_key(tag)),
+  }
+}
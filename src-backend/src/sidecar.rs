@@ -0,0 +1,431 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::{
+  CategoryRules,
+  DatasetConfig,
+  DerivedStateInfo,
+  DistillConfig,
+  FieldMap,
+  FilterConfig,
+  SampleSpec,
+  ViewDefinition,
+};
+use crate::clusters::ClusterReview;
+use crate::paths::write_atomic_with;
+use crate::sample::sample_ids;
+use crate::state::{DatasetStore, IdSet, InnerState, SampleView};
+use crate::views::SavedView;
+
+const DERIVED_MAGIC: &[u8; 8] = b"DLDRV01\n";
+
+/// Derived ids and configs of a dataset, persisted next to its store.
+#[derive(Debug, Clone)]
+pub struct DerivedState {
+  pub dataset_id: String,
+  pub record_count: usize,
+  pub saved_at: u64,
+  pub field_map: FieldMap,
+  pub category_rules: CategoryRules,
+  pub filters: FilterConfig,
+  pub distill_config: DistillConfig,
+  pub filtered_ids: Option<IdSet>,
+  pub selected_ids: Option<IdSet>,
+  pub removed_ids: Option<IdSet>,
+  pub manual_include: IdSet,
+  pub manual_exclude: IdSet,
+  pub tags: BTreeMap<String, IdSet>,
+  /// Sample views by name; their ids are regenerated from the spec on restore.
+  pub samples: BTreeMap<String, SampleSpec>,
+  pub filtered_sample: Option<String>,
+  pub selected_sample: Option<String>,
+  pub cluster_review: Option<ClusterReview>,
+  /// Saved view definitions; their ids are recomputed on demand.
+  pub views: BTreeMap<String, ViewDefinition>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DerivedHeader {
+  dataset_id: String,
+  record_count: usize,
+  saved_at: u64,
+  field_map: FieldMap,
+  #[serde(default)]
+  category_rules: CategoryRules,
+  filters: FilterConfig,
+  distill_config: DistillConfig,
+  /// Names of the id sets that follow the header, in order.
+  sets: Vec<String>,
+  #[serde(default)]
+  samples: BTreeMap<String, SampleSpec>,
+  #[serde(default)]
+  filtered_sample: Option<String>,
+  #[serde(default)]
+  selected_sample: Option<String>,
+  #[serde(default)]
+  cluster_review: Option<ClusterReview>,
+  #[serde(default)]
+  views: BTreeMap<String, ViewDefinition>,
+}
+
+pub fn derived_state_path(store: &DatasetStore) -> PathBuf {
+  store.store_path.with_extension("derived")
+}
+
+fn unix_now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|elapsed| elapsed.as_secs())
+    .unwrap_or_default()
+}
+
+impl DerivedState {
+  /// Snapshot of the active dataset's derived state, if a dataset is loaded.
+  pub fn capture(inner: &InnerState) -> Option<Self> {
+    let store = inner.dataset.as_ref()?;
+    Some(Self {
+      dataset_id: store.id.clone(),
+      record_count: store.record_count,
+      saved_at: unix_now(),
+      field_map: inner.field_map.clone(),
+      category_rules: inner.category_rules.clone(),
+      filters: inner.filters.clone(),
+      distill_config: inner.distill_config.clone(),
+      filtered_ids: inner.filtered_ids.clone(),
+      selected_ids: inner.selected_ids.clone(),
+      removed_ids: inner.removed_ids.clone(),
+      manual_include: inner.manual_include.iter().cloned().collect(),
+      manual_exclude: inner.manual_exclude.iter().cloned().collect(),
+      tags: inner.tags.clone(),
+      samples: inner
+        .samples
+        .iter()
+        .map(|(name, sample)| (name.clone(), sample.spec.clone()))
+        .collect(),
+      filtered_sample: inner.filtered_sample.clone(),
+      selected_sample: inner.selected_sample.clone(),
+      cluster_review: inner.cluster_review.clone(),
+      views: inner
+        .views
+        .iter()
+        .map(|(name, saved)| (name.clone(), saved.definition.clone()))
+        .collect(),
+    })
+  }
+
+  pub fn info(&self) -> DerivedStateInfo {
+    DerivedStateInfo {
+      dataset_id: self.dataset_id.clone(),
+      saved_at: self.saved_at,
+      filtered_count: self.filtered_ids.as_ref().map(IdSet::len),
+      selected_count: self.selected_ids.as_ref().map(IdSet::len),
+      removed_count: self.removed_ids.as_ref().map(IdSet::len),
+      tags: self.tags.keys().cloned().collect(),
+    }
+  }
+
+  pub fn apply_to(self, inner: &mut InnerState) {
+    inner.field_map = self.field_map;
+    inner.category_rules = self.category_rules;
+    inner.filters = self.filters;
+    inner.distill_config = self.distill_config;
+    inner.distill_score_ranges.clear();
+    inner.filtered_ids = self.filtered_ids;
+    inner.selected_ids = self.selected_ids;
+    inner.removed_ids = self.removed_ids;
+    inner.manual_include = self.manual_include.iter().collect::<HashSet<_>>();
+    inner.manual_exclude = self.manual_exclude.iter().collect::<HashSet<_>>();
+    inner.tags = self.tags;
+    inner.samples = self
+      .samples
+      .into_iter()
+      .map(|(name, spec)| {
+        let ids = sample_ids(self.record_count, &spec);
+        (name, SampleView { spec, ids })
+      })
+      .collect();
+    inner.filtered_sample = self.filtered_sample;
+    inner.filter_run = None;
+    inner.selected_sample = self.selected_sample;
+    inner.cluster_review = self.cluster_review;
+    inner.views = self
+      .views
+      .into_iter()
+      .map(|(name, definition)| (name, SavedView::new(definition)))
+      .collect();
+  }
+}
+
+/// Writes the snapshot to the sidecar atomically.
+pub fn save_derived_state(store: &DatasetStore, state: &DerivedState) -> Result<(), String> {
+  let path = derived_state_path(store);
+
+  let mut sets: Vec<(String, &IdSet)> = Vec::new();
+  let optional = [
+    ("filtered", &state.filtered_ids),
+    ("selected", &state.selected_ids),
+    ("removed", &state.removed_ids),
+  ];
+  for (name, ids) in optional {
+    if let Some(ids) = ids {
+      sets.push((name.to_string(), ids));
+    }
+  }
+  sets.push(("manual_include".to_string(), &state.manual_include));
+  sets.push(("manual_exclude".to_string(), &state.manual_exclude));
+  for (tag, ids) in &state.tags {
+    sets.push((format!("tag:{tag}"), ids));
+  }
+
+  let header = DerivedHeader {
+    dataset_id: state.dataset_id.clone(),
+    record_count: state.record_count,
+    saved_at: state.saved_at,
+    field_map: state.field_map.clone(),
+    category_rules: state.category_rules.clone(),
+    filters: state.filters.clone(),
+    distill_config: state.distill_config.clone(),
+    sets: sets.iter().map(|(name, _)| name.clone()).collect(),
+    samples: state.samples.clone(),
+    filtered_sample: state.filtered_sample.clone(),
+    selected_sample: state.selected_sample.clone(),
+    cluster_review: state.cluster_review.clone(),
+    views: state.views.clone(),
+  };
+  let header_bytes = serde_json::to_vec(&header).map_err(|e| e.to_string())?;
+
+  write_atomic_with(&path, |writer| {
+    writer.write_all(DERIVED_MAGIC).map_err(|e| e.to_string())?;
+    writer
+      .write_all(&(header_bytes.len() as u64).to_le_bytes())
+      .map_err(|e| e.to_string())?;
+    writer.write_all(&header_bytes).map_err(|e| e.to_string())?;
+    for (_, ids) in &sets {
+      ids.write_to(&mut *writer)?;
+    }
+    Ok(())
+  })
+}
+
+/// Reads the sidecar header of `store`, leaving the reader at the first id
+/// set. `None` when there is no sidecar or it belongs to a different store.
+fn read_derived_header(
+  store: &DatasetStore,
+) -> Result<Option<(DerivedHeader, BufReader<File>)>, String> {
+  let path = derived_state_path(store);
+  if !path.exists() {
+    return Ok(None);
+  }
+  let mut reader = BufReader::new(File::open(&path).map_err(|e| e.to_string())?);
+  let mut magic = [0u8; 8];
+  reader.read_exact(&mut magic).map_err(|e| e.to_string())?;
+  if &magic != DERIVED_MAGIC {
+    return Err("Unrecognized derived state file".to_string());
+  }
+  let mut header_len = [0u8; 8];
+  reader
+    .read_exact(&mut header_len)
+    .map_err(|e| e.to_string())?;
+  let header_len = u64::from_le_bytes(header_len);
+  // A truncated or corrupt file must not size the allocation.
+  let file_len = reader.get_ref().metadata().map_err(|e| e.to_string())?.len();
+  if header_len > file_len.saturating_sub(DERIVED_MAGIC.len() as u64 + 8) {
+    return Err("Derived state file is truncated or corrupt".to_string());
+  }
+  let mut header_bytes = vec![0u8; header_len as usize];
+  reader
+    .read_exact(&mut header_bytes)
+    .map_err(|e| e.to_string())?;
+  let header: DerivedHeader = serde_json::from_slice(&header_bytes).map_err(|e| e.to_string())?;
+  if header.dataset_id != store.id || header.record_count != store.record_count {
+    return Ok(None);
+  }
+  Ok(Some((header, reader)))
+}
+
+/// The field map and configs saved with `store`, without its id sets.
+pub fn load_dataset_config(store: &DatasetStore) -> Result<Option<DatasetConfig>, String> {
+  Ok(read_derived_header(store)?.map(|(header, _)| DatasetConfig {
+    field_map: header.field_map,
+    category_rules: header.category_rules,
+    filters: header.filters,
+    distill_config: header.distill_config,
+  }))
+}
+
+/// Loads the sidecar for `store`, ignoring snapshots that belong to a different store.
+pub fn load_derived_state(store: &DatasetStore) -> Result<Option<DerivedState>, String> {
+  let Some((header, mut reader)) = read_derived_header(store)? else {
+    return Ok(None);
+  };
+  let mut state = DerivedState {
+    dataset_id: header.dataset_id,
+    record_count: header.record_count,
+    saved_at: header.saved_at,
+    field_map: header.field_map,
+    category_rules: header.category_rules,
+    filters: header.filters,
+    distill_config: header.distill_config,
+    filtered_ids: None,
+    selected_ids: None,
+    removed_ids: None,
+    manual_include: IdSet::new(),
+    manual_exclude: IdSet::new(),
+    tags: BTreeMap::new(),
+    samples: header.samples,
+    filtered_sample: header.filtered_sample,
+    selected_sample: header.selected_sample,
+    cluster_review: header.cluster_review,
+    views: header.views,
+  };
+  for name in header.sets {
+    let ids = IdSet::read_from(&mut reader)?;
+    match name.as_str() {
+      "filtered" => state.filtered_ids = Some(ids),
+      "selected" => state.selected_ids = Some(ids),
+      "removed" => state.removed_ids = Some(ids),
+      "manual_include" => state.manual_include = ids,
+      "manual_exclude" => state.manual_exclude = ids,
+      other => {
+        if let Some(tag) = other.strip_prefix("tag:") {
+          state.tags.insert(tag.to_string(), ids);
+        }
+      }
+    }
+  }
+  Ok(Some(state))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use serde_json::json;
+
+  use super::*;
+  use crate::test_support::{jsonl_store, text_field_map, TempDir};
+
+  /// Saves the active dataset and switches to `store`, restoring its saved
+  /// configs or falling back to `defaults`, as the app does on activation.
+  fn switch_to(inner: &mut InnerState, store: &DatasetStore, defaults: &DatasetConfig) {
+    if let Some(active) = inner.dataset.clone() {
+      save_derived_state(&active, &DerivedState::capture(inner).unwrap()).unwrap();
+    }
+    let config = load_dataset_config(store).unwrap().unwrap_or_else(|| defaults.clone());
+    inner.activate_dataset(store.clone());
+    inner.apply_config(config);
+  }
+
+  #[test]
+  fn switching_datasets_restores_each_field_map() {
+    let (dir_a, dir_b) = (TempDir::new(), TempDir::new());
+    let chat = jsonl_store(&dir_a, &[json!({"instruction": "a", "output": "b"})]);
+    let qa = jsonl_store(&dir_b, &[json!({"question": "a", "answer": "b"})]);
+    let qa_map = FieldMap {
+      instruction: Some("question".to_string()),
+      output: Some("answer".to_string()),
+      ..FieldMap::default()
+    };
+    let defaults = DatasetConfig {
+      field_map: text_field_map(),
+      ..DatasetConfig::default()
+    };
+    let mut inner = InnerState::default();
+
+    switch_to(&mut inner, &chat, &defaults);
+    assert_eq!(inner.field_map, text_field_map());
+    switch_to(&mut inner, &qa, &defaults);
+    assert_eq!(inner.field_map, text_field_map());
+    inner.field_map = qa_map.clone();
+
+    for _ in 0..2 {
+      switch_to(&mut inner, &chat, &defaults);
+      assert_eq!(inner.field_map, text_field_map());
+      switch_to(&mut inner, &qa, &defaults);
+      assert_eq!(inner.field_map, qa_map);
+    }
+  }
+
+  #[test]
+  fn a_sidecar_of_another_store_is_ignored() {
+    let (dir_a, dir_b) = (TempDir::new(), TempDir::new());
+    let first = jsonl_store(&dir_a, &[json!({"instruction": "a"})]);
+    let mut second = jsonl_store(&dir_b, &[json!({"instruction": "a"})]);
+    let mut inner = InnerState::default();
+    inner.activate_dataset(first.clone());
+    save_derived_state(&first, &DerivedState::capture(&inner).unwrap()).unwrap();
+    assert!(load_dataset_config(&first).unwrap().is_some());
+
+    second.store_path = first.store_path.clone();
+    assert!(load_dataset_config(&second).unwrap().is_none());
+  }
+
+  #[test]
+  fn derived_state_survives_a_save_and_load() {
+    let dir = TempDir::new();
+    let records = (0..10).map(|id| json!({ "instruction": format!("q{id}") })).collect::<Vec<_>>();
+    let store = jsonl_store(&dir, &records);
+    let mut inner = InnerState::default();
+    inner.activate_dataset(store.clone());
+    inner.field_map = text_field_map();
+    inner.filtered_ids = Some(IdSet::from_iter([1, 3, 5, 7]));
+    inner.selected_ids = Some(IdSet::from_iter([3, 5]));
+    inner.removed_ids = Some(IdSet::from_iter([1, 7]));
+    inner.manual_include.insert(9);
+    inner.manual_exclude.insert(5);
+    inner.tags.insert("review".to_string(), IdSet::from_iter([0, 2]));
+    let saved = DerivedState::capture(&inner).unwrap();
+    save_derived_state(&store, &saved).unwrap();
+
+    let loaded = load_derived_state(&store).unwrap().unwrap();
+    let ids = |set: &Option<IdSet>| set.as_ref().map(|ids| ids.iter().collect::<Vec<_>>());
+    assert_eq!(loaded.saved_at, saved.saved_at);
+    assert_eq!(loaded.field_map, text_field_map());
+    assert_eq!(ids(&loaded.filtered_ids), Some(vec![1, 3, 5, 7]));
+    assert_eq!(ids(&loaded.selected_ids), Some(vec![3, 5]));
+    assert_eq!(ids(&loaded.removed_ids), Some(vec![1, 7]));
+    assert_eq!(loaded.manual_include.iter().collect::<Vec<_>>(), vec![9]);
+    assert_eq!(loaded.manual_exclude.iter().collect::<Vec<_>>(), vec![5]);
+    assert_eq!(loaded.tags["review"].iter().collect::<Vec<_>>(), vec![0, 2]);
+
+    let mut restored = InnerState::default();
+    restored.activate_dataset(store);
+    loaded.apply_to(&mut restored);
+    assert_eq!(restored.filtered_ids, inner.filtered_ids);
+    assert_eq!(restored.manual_exclude, inner.manual_exclude);
+  }
+
+  #[test]
+  fn a_corrupt_sidecar_is_refused_without_reading_its_claimed_length() {
+    let dir = TempDir::new();
+    let store = jsonl_store(&dir, &[json!({ "instruction": "a" })]);
+    let mut inner = InnerState::default();
+    inner.activate_dataset(store.clone());
+    inner.filtered_ids = Some(IdSet::from_iter([0]));
+    save_derived_state(&store, &DerivedState::capture(&inner).unwrap()).unwrap();
+    let path = derived_state_path(&store);
+    let saved = fs::read(&path).unwrap();
+
+    // A header length far past the end of the file.
+    let mut huge = DERIVED_MAGIC.to_vec();
+    huge.extend_from_slice(&(u64::MAX / 2).to_le_bytes());
+    huge.extend_from_slice(b"{}");
+    fs::write(&path, &huge).unwrap();
+    let err = load_derived_state(&store).unwrap_err();
+    assert_eq!(err, "Derived state file is truncated or corrupt");
+
+    // Cut off inside the header, as a crash mid-write without the atomic rename would.
+    fs::write(&path, &saved[..saved.len() / 2]).unwrap();
+    assert!(load_derived_state(&store).is_err());
+    assert!(load_dataset_config(&store).is_err());
+
+    fs::write(&path, b"not a sidecar").unwrap();
+    assert!(load_derived_state(&store).is_err());
+  }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use uuid::Uuid;
+
+use crate::distill::RecordMeta;
+use crate::paths::io_error;
+
+/// Most spill files written at once; categories beyond this share files.
+const MAX_SPILL_FILES: usize = 256;
+/// Category, id and score of one meta.
+const ROW_BYTES: usize = 4 + 8 + 8;
+const UNCATEGORIZED_KEY: u32 = u32::MAX;
+
+/// Metas written to temp files, one file per category while there are few
+/// enough of them, so each category can be selected from without holding
+/// every meta in memory. Each run gets its own `distill-<uuid>.tmp` directory
+/// next to the store, so runs on the same store never share files; the
+/// directory is removed on drop.
+pub struct MetaSpill {
+  dir: PathBuf,
+  paths: Vec<PathBuf>,
+  writers: Vec<Option<BufWriter<File>>>,
+}
+
+/// Metas of one spill file by category, in id order.
+pub type SpillPartition = HashMap<Option<u32>, Vec<RecordMeta>>;
+
+impl MetaSpill {
+  pub fn new(store_path: &Path) -> Result<Self, String> {
+    let parent = store_path.parent().unwrap_or(Path::new("."));
+    let dir = parent.join(format!("distill-{}.tmp", Uuid::new_v4()));
+    fs::create_dir_all(&dir).map_err(|e| io_error(&dir, &e))?;
+    Ok(Self {
+      paths: (0..MAX_SPILL_FILES)
+        .map(|index| dir.join(format!("{index}.bin")))
+        .collect(),
+      writers: (0..MAX_SPILL_FILES).map(|_| None).collect(),
+      dir,
+    })
+  }
+
+  pub fn push(&mut self, meta: &RecordMeta) -> Result<(), String> {
+    let key = meta.category.unwrap_or(UNCATEGORIZED_KEY);
+    let slot = key as usize % MAX_SPILL_FILES;
+    let writer = match &mut self.writers[slot] {
+      Some(writer) => writer,
+      empty => {
+        let path = &self.paths[slot];
+        let file = File::create(path).map_err(|e| io_error(path, &e))?;
+        empty.insert(BufWriter::new(file))
+      }
+    };
+    let mut row = [0u8; ROW_BYTES];
+    row[..4].copy_from_slice(&key.to_le_bytes());
+    row[4..12].copy_from_slice(&(meta.id as u64).to_le_bytes());
+    row[12..].copy_from_slice(&meta.score.to_le_bytes());
+    writer.write_all(&row).map_err(|e| e.to_string())
+  }
+
+  /// Flushes every file; call once all metas are pushed.
+  pub fn finish(&mut self) -> Result<(), String> {
+    for writer in self.writers.iter_mut().flatten() {
+      writer.flush().map_err(|e| e.to_string())?;
+    }
+    Ok(())
+  }
+
+  fn read_partition(path: &Path) -> Result<SpillPartition, String> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| io_error(path, &e))?);
+    let mut partition = SpillPartition::new();
+    let mut row = [0u8; ROW_BYTES];
+    loop {
+      match reader.read_exact(&mut row) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+        Err(e) => return Err(io_error(path, &e)),
+      }
+      let key = u32::from_le_bytes(row[..4].try_into().unwrap_or_default());
+      let category = (key != UNCATEGORIZED_KEY).then_some(key);
+      partition.entry(category).or_default().push(RecordMeta {
+        id: u64::from_le_bytes(row[4..12].try_into().unwrap_or_default()) as usize,
+        category,
+        prefix: None,
+        score: f64::from_le_bytes(row[12..].try_into().unwrap_or_default()),
+        signature: 0,
+      });
+    }
+    Ok(partition)
+  }
+
+  /// Reads the files back one at a time, in file order.
+  pub fn for_each_partition(
+    &self,
+    mut visit: impl FnMut(SpillPartition) -> Result<(), String>,
+  ) -> Result<(), String> {
+    for (path, writer) in self.paths.iter().zip(&self.writers) {
+      if writer.is_some() {
+        visit(Self::read_partition(path)?)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+impl Drop for MetaSpill {
+  fn drop(&mut self) {
+    self.writers.clear();
+    let _ = fs::remove_dir_all(&self.dir);
+  }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rusqlite::types::Value as SqlValue;
+use rusqlite::Connection;
+use serde_json::Value;
+
+use crate::io::{
+  export_columns,
+  new_export_summary,
+  prepare_export_record,
+  read_record_value,
+  ExportSpec,
+};
+use crate::models::ExportSummary;
+use crate::paths::create_output_file;
+use crate::state::DatasetStore;
+use crate::timing::StageTimer;
+use crate::transform::TruncateSpec;
+
+/// Table a SQLite export writes.
+const SQLITE_TABLE: &str = "records";
+/// Rows a SQLite export inserts per transaction.
+const SQLITE_BATCH_SIZE: usize = 5000;
+
+fn quote_sql_identifier(name: &str) -> String {
+  format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Column of a SQLite export holding the record id: `id`, or `_id`, `__id`
+/// and so on when a field already has the name.
+fn sqlite_id_column(columns: &[String]) -> String {
+  let mut name = "id".to_string();
+  while columns.iter().any(|column| column.eq_ignore_ascii_case(&name)) {
+    name.insert(0, '_');
+  }
+  name
+}
+
+/// A record value as SQLite text: strings as they are, other values as JSON
+/// and null or missing ones as NULL.
+fn sqlite_value(value: Option<&Value>) -> SqlValue {
+  match value {
+    None | Some(Value::Null) => SqlValue::Null,
+    Some(Value::String(text)) => SqlValue::Text(text.clone()),
+    Some(other) => SqlValue::Text(other.to_string()),
+  }
+}
+
+/// Writes the records in `ids` as rows of a single `records` table with a
+/// TEXT column per exported field and an integer column of record ids, a
+/// transaction per batch. Returns the ids written, in order.
+pub(crate) fn write_sqlite_export(
+  store: &DatasetStore,
+  ids: &[usize],
+  spec: &ExportSpec,
+  truncate: Option<&TruncateSpec>,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<(ExportSummary, Vec<usize>), String> {
+  let columns = export_columns(&store.fields, spec);
+  // SQLite column names ignore ASCII case.
+  let mut seen = HashSet::new();
+  for column in &columns {
+    if !seen.insert(column.to_ascii_lowercase()) {
+      return Err(format!("Field {column} clashes with another SQLite column of the same name"));
+    }
+  }
+  let id_column = sqlite_id_column(&columns);
+  let sqlite_error = |e: rusqlite::Error| format!("{}: {e}", spec.path.display());
+  create_output_file(&spec.path)?;
+  let mut connection = Connection::open(&spec.path).map_err(sqlite_error)?;
+  let names = std::iter::once(&id_column)
+    .chain(&columns)
+    .map(|column| quote_sql_identifier(column))
+    .collect::<Vec<_>>();
+  let definitions = std::iter::once(format!("{} INTEGER PRIMARY KEY", names[0]))
+    .chain(names[1..].iter().map(|name| format!("{name} TEXT")))
+    .collect::<Vec<_>>();
+  connection
+    .execute(&format!("CREATE TABLE {SQLITE_TABLE} ({})", definitions.join(", ")), [])
+    .map_err(sqlite_error)?;
+  let insert = format!(
+    "INSERT INTO {SQLITE_TABLE} ({}) VALUES ({})",
+    names.join(", "),
+    vec!["?"; names.len()].join(", ")
+  );
+
+  let mut summary = new_export_summary(spec);
+  let mut written = Vec::new();
+  let mut timer = StageTimer::new();
+  let mut done = 0;
+  for batch in ids.chunks(SQLITE_BATCH_SIZE) {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Export canceled".to_string());
+    }
+    let transaction = connection.transaction().map_err(sqlite_error)?;
+    {
+      let mut statement = transaction.prepare_cached(&insert).map_err(sqlite_error)?;
+      for &id in batch {
+        let record = timer.time("read", || read_record_value(store, id))?;
+        timer.count("read", 1);
+        let Some(record) = prepare_export_record(record, spec, truncate, &mut summary) else {
+          continue;
+        };
+        let row = std::iter::once(SqlValue::Integer(id as i64))
+          .chain(columns.iter().map(|column| sqlite_value(record.get(column))));
+        timer.time("write", || statement.execute(rusqlite::params_from_iter(row)))
+          .map_err(sqlite_error)?;
+        timer.count("write", 1);
+        summary.exported_count += 1;
+        written.push(id);
+      }
+    }
+    timer.time("write", || transaction.commit()).map_err(sqlite_error)?;
+    done += batch.len();
+    on_progress(done, ids.len());
+  }
+  connection.close().map_err(|(_, e)| sqlite_error(e))?;
+  summary.timings = timer.finish();
+  Ok((summary, written))
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+  use crate::io::{export_dataset, ExportCompression};
+  use crate::models::ExportOptions;
+  use crate::test_support::{jsonl_store, text_field_map, TempDir};
+
+  #[test]
+  fn sqlite_exports_hold_every_record_as_a_row() {
+    let dir = TempDir::new();
+    let records = (0..12_000)
+      .map(|id| {
+        json!({ "instruction": format!("q{id}"), "output": "a", "meta": { "turn": id % 3 } })
+      })
+      .collect::<Vec<_>>();
+    let store = jsonl_store(&dir, &records);
+    let path = dir.join("out.sqlite");
+    let spec = ExportSpec {
+      path: path.clone(),
+      format: "sqlite".to_string(),
+      compression: ExportCompression::None,
+      options: ExportOptions::default(),
+      field_map: text_field_map(),
+    };
+    let ids = (0..store.record_count).collect::<Vec<_>>();
+    let cancel = AtomicBool::new(false);
+    let (summary, _) = export_dataset(&store, &ids, &spec, &cancel, |_, _, _| {}).unwrap();
+    assert_eq!(summary.exported_count, 12_000);
+
+    let connection = Connection::open(&path).unwrap();
+    let count: i64 = connection
+      .query_row("SELECT COUNT(*) FROM records", [], |row| row.get(0))
+      .unwrap();
+    assert_eq!(count, 12_000);
+    let (instruction, meta): (String, String) = connection
+      .query_row(
+        "SELECT instruction, meta FROM records WHERE id = 7777",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+      )
+      .unwrap();
+    assert_eq!(instruction, "q7777");
+    assert_eq!(meta, r#"{"turn":1}"#);
+  }
+}
@@ -0,0 +1,184 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::io::{read_content_hashes, read_content_hashes_for};
+use crate::models::RemapReport;
+use crate::paths::atomic_write_json;
+use crate::sidecar::DerivedState;
+use crate::state::{DatasetStore, IdSet, InnerState};
+
+/// Tags and manual overrides of a source file, keyed by record content hash
+/// instead of position so they survive re-imports of an updated source.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct StableAnnotations {
+  pub source_path: String,
+  /// Id of the store derived from the source they belong to; `None` for the
+  /// import itself, whose annotations are the ones a re-import restores.
+  pub derived_store: Option<String>,
+  pub tags: BTreeMap<String, Vec<u64>>,
+  pub manual_include: Vec<u64>,
+  pub manual_exclude: Vec<u64>,
+}
+
+impl StableAnnotations {
+  pub fn is_empty(&self) -> bool {
+    self.tags.values().all(Vec::is_empty)
+      && self.manual_include.is_empty()
+      && self.manual_exclude.is_empty()
+  }
+}
+
+/// Annotation file for an imported source, named after a hash of its path,
+/// or for a store derived from it, of its path and the store id.
+pub fn annotations_path(dir: &Path, source_path: &Path, derived_store: Option<&str>) -> PathBuf {
+  let mut key = source_path.to_string_lossy().into_owned();
+  if let Some(store_id) = derived_store {
+    key.push('#');
+    key.push_str(store_id);
+  }
+  dir.join(format!("{:016x}.json", xxh3_64(key.as_bytes())))
+}
+
+/// Content hashes of the tagged and manually overridden records of a snapshot.
+pub fn capture_annotations(
+  store: &DatasetStore,
+  state: &DerivedState,
+) -> Result<StableAnnotations, String> {
+  let mut tags = BTreeMap::new();
+  for (tag, ids) in &state.tags {
+    tags.insert(tag.clone(), read_content_hashes_for(store, ids)?);
+  }
+  Ok(StableAnnotations {
+    source_path: store.source_path.to_string_lossy().to_string(),
+    derived_store: store.parent_id.as_ref().map(|_| store.id.clone()),
+    tags,
+    manual_include: read_content_hashes_for(store, &state.manual_include)?,
+    manual_exclude: read_content_hashes_for(store, &state.manual_exclude)?,
+  })
+}
+
+pub fn save_annotations(dir: &Path, annotations: &StableAnnotations) -> Result<(), String> {
+  fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+  let path = annotations_path(
+    dir,
+    Path::new(&annotations.source_path),
+    annotations.derived_store.as_deref(),
+  );
+  atomic_write_json(&path, annotations)
+}
+
+/// Annotations saved for the import of `source_path`.
+pub fn load_annotations(dir: &Path, source_path: &Path) -> Result<Option<StableAnnotations>, String> {
+  let path = annotations_path(dir, source_path, None);
+  if !path.exists() {
+    return Ok(None);
+  }
+  let content = fs::read(&path).map_err(|e| e.to_string())?;
+  let annotations: StableAnnotations =
+    serde_json::from_slice(&content).map_err(|e| e.to_string())?;
+  Ok(Some(annotations))
+}
+
+/// Positional ids of the records with the given content hashes. Hashes shared
+/// by several records resolve to all of them and count as collisions; hashes
+/// with no record count as missing.
+pub fn resolve_stable_ids(
+  store_hashes: &[u64],
+  hashes: &[u64],
+) -> (IdSet, RemapReport) {
+  let wanted = hashes.iter().copied().collect::<HashSet<_>>();
+  let mut found: HashMap<u64, usize> = HashMap::new();
+  let mut ids = IdSet::new();
+  for (id, hash) in store_hashes.iter().enumerate() {
+    if wanted.contains(hash) {
+      ids.insert(id);
+      *found.entry(*hash).or_insert(0) += 1;
+    }
+  }
+  let report = RemapReport {
+    matched_count: found.len(),
+    missing_count: wanted.len() - found.len(),
+    collision_count: found.values().filter(|count| **count > 1).count(),
+    restored_tags: Vec::new(),
+  };
+  (ids, report)
+}
+
+/// Saved annotations mapped onto the positional ids of a store.
+#[derive(Debug, Default)]
+pub struct RemappedAnnotations {
+  pub tags: BTreeMap<String, IdSet>,
+  pub manual_include: IdSet,
+  pub manual_exclude: IdSet,
+}
+
+impl RemappedAnnotations {
+  pub fn apply_to(self, inner: &mut InnerState) {
+    inner.tags.extend(self.tags);
+    inner.manual_include = self.manual_include.iter().collect();
+    inner.manual_exclude = self.manual_exclude.iter().collect();
+  }
+}
+
+pub fn remap_annotations(
+  store: &DatasetStore,
+  annotations: &StableAnnotations,
+) -> Result<(RemappedAnnotations, RemapReport), String> {
+  let store_hashes = read_content_hashes(store)?;
+  let mut remapped = RemappedAnnotations::default();
+  let mut total = RemapReport::default();
+  let mut merge = |report: RemapReport| {
+    total.matched_count += report.matched_count;
+    total.missing_count += report.missing_count;
+    total.collision_count += report.collision_count;
+  };
+  for (tag, hashes) in &annotations.tags {
+    let (ids, report) = resolve_stable_ids(&store_hashes, hashes);
+    merge(report);
+    if !ids.is_empty() {
+      remapped.tags.insert(tag.clone(), ids);
+    }
+  }
+  let (include, report) = resolve_stable_ids(&store_hashes, &annotations.manual_include);
+  merge(report);
+  let (exclude, report) = resolve_stable_ids(&store_hashes, &annotations.manual_exclude);
+  merge(report);
+  remapped.manual_include = include;
+  remapped.manual_exclude = exclude;
+  total.restored_tags = remapped.tags.keys().cloned().collect();
+  Ok((remapped, total))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_support::TempDir;
+
+  fn annotations(derived_store: Option<&str>, tag: &str) -> StableAnnotations {
+    StableAnnotations {
+      source_path: "/data/train.jsonl".to_string(),
+      derived_store: derived_store.map(str::to_string),
+      tags: BTreeMap::from([(tag.to_string(), vec![1, 2])]),
+      ..StableAnnotations::default()
+    }
+  }
+
+  #[test]
+  fn derived_store_annotations_do_not_replace_the_import_annotations() {
+    let dir = TempDir::new();
+    save_annotations(dir.path(), &annotations(None, "imported")).unwrap();
+    save_annotations(dir.path(), &annotations(Some("chunked"), "derived")).unwrap();
+    save_annotations(dir.path(), &annotations(Some("filtered"), "other")).unwrap();
+    let loaded = load_annotations(dir.path(), Path::new("/data/train.jsonl"))
+      .unwrap()
+      .unwrap();
+    assert_eq!(loaded.derived_store, None);
+    assert_eq!(loaded.tags.keys().collect::<Vec<_>>(), vec!["imported"]);
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+  }
+}
@@ -1,9 +1,38 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::PathBuf;
-use std::sync::atomic::AtomicBool;
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::{Arc, Mutex, RwLock};
 
-use crate::models::{DistillConfig, FieldMap, FilterConfig};
+use crate::clusters::ClusterReview;
+use crate::filters::{DedupeKey, DuplicateMap};
+pub use crate::idset::IdSet;
+use crate::models::{
+  CategoryRules,
+  ComponentRange,
+  DatasetConfig,
+  DatasetSummary,
+  DistillConfig,
+  FieldMap,
+  FieldMatrix,
+  FilterConfig,
+  ImportReport,
+  LanguageStats,
+  PreviewLimits,
+  RestoredSession,
+  SampleSpec,
+  StartupReport,
+};
+use crate::ordering::OrderCache;
+use crate::records::PREVIEW_MAX_RECORD_BYTES;
+use crate::sample::SAMPLE_VIEW_PREFIX;
+use crate::timing::RateMeter;
+use crate::views::{
+  named_view_sample_origin,
+  resolve_view,
+  SavedView,
+  ViewResolution,
+  NAMED_VIEW_PREFIX,
+};
 
 #[derive(Debug, Clone)]
 pub struct DatasetStore {
@@ -15,25 +44,259 @@ pub struct DatasetStore {
   pub record_count: usize,
   pub size_bytes: u64,
   pub format: String,
+  /// Field delimiter of a CSV source.
+  pub delimiter: Option<u8>,
+  /// Text encoding the source was read in.
+  pub encoding: Option<String>,
+  /// Store this one was materialized from, if any.
+  pub parent_id: Option<String>,
+  /// The import was canceled, so only the records read until then are here.
+  pub truncated: bool,
+  /// Byte offset of the source the import can continue from, when truncated.
+  pub resume_offset: Option<u64>,
+  /// Longest record line the import accepted; store scans skip longer lines.
+  pub max_record_bytes: usize,
+}
+
+impl DatasetStore {
+  /// Longest record read for a preview: the preview cap, or the import cap
+  /// when that is lower.
+  pub fn preview_record_bytes(&self) -> usize {
+    self.max_record_bytes.min(PREVIEW_MAX_RECORD_BYTES)
+  }
+
+  pub fn summary(&self) -> DatasetSummary {
+    DatasetSummary {
+      id: self.id.clone(),
+      source_path: self.source_path.to_string_lossy().to_string(),
+      format: self.format.clone(),
+      delimiter: self.delimiter.map(|delimiter| char::from(delimiter).to_string()),
+      encoding: self.encoding.clone(),
+      record_count: self.record_count,
+      fields: self.fields.clone(),
+      size_bytes: self.size_bytes,
+      import_report: ImportReport::default(),
+      parent_id: self.parent_id.clone(),
+      remap_report: None,
+      truncated: self.truncated,
+      resume_offset: self.resume_offset,
+    }
+  }
 }
 
 #[derive(Debug, Default)]
 pub struct InnerState {
   pub dataset: Option<DatasetStore>,
+  /// Every store imported or materialized this session, keyed by id.
+  pub datasets: BTreeMap<String, DatasetStore>,
   pub field_map: FieldMap,
+  /// Infers categories when no category field is mapped.
+  pub category_rules: CategoryRules,
   pub filters: FilterConfig,
   pub distill_config: DistillConfig,
-  pub filtered_ids: Option<Vec<usize>>,
-  pub selected_ids: Option<Vec<usize>>,
-  pub removed_ids: Option<Vec<usize>>,
+  /// Score component ranges of the last distillation, for explaining scores.
+  pub distill_score_ranges: Vec<ComponentRange>,
+  pub filtered_ids: Option<IdSet>,
+  pub selected_ids: Option<IdSet>,
+  pub removed_ids: Option<IdSet>,
   pub manual_include: HashSet<usize>,
   pub manual_exclude: HashSet<usize>,
+  pub tags: BTreeMap<String, IdSet>,
+  /// Sample views keyed by view name (`sample:...`).
+  pub samples: BTreeMap<String, SampleView>,
+  /// Sample view the filtered ids were computed on, if any.
+  pub filtered_sample: Option<String>,
+  /// How the filtered ids were last computed, when a filter run did it.
+  pub filter_run: Option<FilterRun>,
+  /// Sample view the selected and removed ids were computed on, if any.
+  pub selected_sample: Option<String>,
+  pub cluster_review: Option<ClusterReview>,
+  /// Saved views keyed by name, without the `view:` prefix.
+  pub views: BTreeMap<String, SavedView>,
+  /// Last field matrix computed, kept for export.
+  pub field_matrix: Option<FieldMatrix>,
+  pub language_stats: Option<LanguageStatsCache>,
+  pub command_scores: Option<CommandScores>,
+}
+
+/// The view a filter run started from and the dedupe settings it used, so a
+/// drill-down can narrow its result instead of deduplicating again.
+#[derive(Debug, Clone)]
+pub struct FilterRun {
+  pub base_view: Option<String>,
+  pub dedupe: DedupeKey,
+  /// Which record each dropped duplicate matched, when the run kept it.
+  pub duplicates: Option<DuplicateMap>,
+}
+
+/// Language stats of the active dataset and the sample they were computed
+/// from, reused while the sample and instruction field stay the same.
+#[derive(Debug, Clone)]
+pub struct LanguageStatsCache {
+  pub instruction_field: Option<String>,
+  pub sample: IdSet,
+  pub stats: LanguageStats,
+}
+
+/// Scores a scoring command gave records of the active dataset, by id.
+#[derive(Debug, Clone)]
+pub struct CommandScores {
+  pub command: String,
+  pub scores: HashMap<usize, f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SampleView {
+  pub spec: SampleSpec,
+  pub ids: IdSet,
+}
+
+/// Ids behind a named view, borrowed from the state where possible.
+#[derive(Debug, Clone)]
+pub enum ViewIds<'a> {
+  All(usize),
+  Set(&'a IdSet),
+  /// Computed on request, as for saved views.
+  Owned(IdSet),
+  Empty,
+}
+
+fn borrowed(ids: Option<&IdSet>) -> ViewIds<'_> {
+  ids.map(ViewIds::Set).unwrap_or(ViewIds::Empty)
+}
+
+impl ViewIds<'_> {
+  pub fn len(&self) -> usize {
+    match self {
+      ViewIds::All(count) => *count,
+      ViewIds::Set(set) => set.len(),
+      ViewIds::Owned(set) => set.len(),
+      ViewIds::Empty => 0,
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  pub fn page(&self, offset: usize, limit: usize) -> Vec<usize> {
+    match self {
+      ViewIds::All(count) => (offset.min(*count)..offset.saturating_add(limit).min(*count)).collect(),
+      ViewIds::Set(set) => set.page(offset, limit),
+      ViewIds::Owned(set) => set.page(offset, limit),
+      ViewIds::Empty => Vec::new(),
+    }
+  }
+
+  pub fn to_set(&self) -> IdSet {
+    match self {
+      ViewIds::All(count) => IdSet::full(*count),
+      ViewIds::Set(set) => (*set).clone(),
+      ViewIds::Owned(set) => set.clone(),
+      ViewIds::Empty => IdSet::new(),
+    }
+  }
+}
+
+impl InnerState {
+  /// Makes `store` the active dataset and drops all state derived from the previous one.
+  pub fn activate_dataset(&mut self, store: DatasetStore) {
+    self.datasets.insert(store.id.clone(), store.clone());
+    self.dataset = Some(store);
+    self.filtered_ids = None;
+    self.selected_ids = None;
+    self.removed_ids = None;
+    self.distill_score_ranges.clear();
+    self.manual_include.clear();
+    self.manual_exclude.clear();
+    self.tags.clear();
+    self.samples.clear();
+    self.filtered_sample = None;
+    self.filter_run = None;
+    self.selected_sample = None;
+    self.cluster_review = None;
+    self.views.clear();
+    self.field_matrix = None;
+    self.language_stats = None;
+    self.command_scores = None;
+  }
+
+  /// Field map and configs of the session, as saved with the dataset.
+  pub fn config(&self) -> DatasetConfig {
+    DatasetConfig {
+      field_map: self.field_map.clone(),
+      category_rules: self.category_rules.clone(),
+      filters: self.filters.clone(),
+      distill_config: self.distill_config.clone(),
+    }
+  }
+
+  pub fn apply_config(&mut self, config: DatasetConfig) {
+    self.field_map = config.field_map;
+    self.category_rules = config.category_rules;
+    self.filters = config.filters;
+    self.distill_config = config.distill_config;
+  }
+
+  /// The sample view whose records `view` is limited to, if any.
+  pub fn sample_origin(&self, view: &str) -> Option<String> {
+    match view {
+      "filtered" => self.filtered_sample.clone(),
+      "selected" | "removed" => self.selected_sample.clone(),
+      other if other.starts_with(SAMPLE_VIEW_PREFIX) => Some(other.to_string()),
+      other if other.starts_with(NAMED_VIEW_PREFIX) => named_view_sample_origin(self, other),
+      _ => None,
+    }
+  }
+
+  /// Ids of `view`. A saved view whose filters have not run yet has none; run
+  /// them first with `views::resolve_view`.
+  pub fn view_ids(&self, view: &str) -> ViewIds<'_> {
+    let record_count = self
+      .dataset
+      .as_ref()
+      .map(|store| store.record_count)
+      .unwrap_or_default();
+    match view {
+      "filtered" => self
+        .filtered_ids
+        .as_ref()
+        .map(ViewIds::Set)
+        .unwrap_or(ViewIds::All(record_count)),
+      "selected" => borrowed(self.selected_ids.as_ref()),
+      "removed" => borrowed(self.removed_ids.as_ref()),
+      other if other.starts_with(SAMPLE_VIEW_PREFIX) => {
+        borrowed(self.samples.get(other).map(|sample| &sample.ids))
+      }
+      other if other.starts_with(NAMED_VIEW_PREFIX) => match resolve_view(self, other) {
+        Ok(ViewResolution::Ready(ids)) => ViewIds::Owned(ids),
+        _ => ViewIds::Empty,
+      },
+      other => match other.strip_prefix("tag:") {
+        Some(tag) => borrowed(self.tags.get(tag)),
+        None => ViewIds::All(record_count),
+      },
+    }
+  }
 }
 
 #[derive(Debug)]
 pub struct AppState {
   pub inner: RwLock<InnerState>,
   pub cancel: Arc<AtomicBool>,
+  /// Bumped on every autosave request; only the latest request writes.
+  pub autosave_generation: AtomicU64,
+  /// Serializes sidecar writes without touching `inner`.
+  pub autosave_lock: Mutex<()>,
+  /// Rate of the most recent progress events.
+  pub progress_rate: Mutex<RateMeter>,
+  pub order_cache: Mutex<Option<OrderCache>>,
+  pub startup_report: Mutex<StartupReport>,
+  /// Set at startup when the last session was reopened.
+  pub restored_session: Mutex<Option<RestoredSession>>,
+  /// Preview limits of the saved settings, kept here when they are loaded or
+  /// saved so preview pages never read the settings file.
+  preview_limits: Mutex<PreviewLimits>,
 }
 
 impl Default for AppState {
@@ -41,6 +304,66 @@ impl Default for AppState {
     Self {
       inner: RwLock::new(InnerState::default()),
       cancel: Arc::new(AtomicBool::new(false)),
+      autosave_generation: AtomicU64::new(0),
+      autosave_lock: Mutex::new(()),
+      progress_rate: Mutex::new(RateMeter::default()),
+      order_cache: Mutex::new(None),
+      startup_report: Mutex::new(StartupReport::default()),
+      restored_session: Mutex::new(None),
+      preview_limits: Mutex::new(PreviewLimits::default()),
     }
   }
 }
+
+impl AppState {
+  pub fn preview_limits(&self) -> PreviewLimits {
+    self
+      .preview_limits
+      .lock()
+      .map(|limits| limits.clone())
+      .unwrap_or_default()
+  }
+
+  /// Replaces the cached preview limits; `None` restores the defaults.
+  pub fn set_preview_limits(&self, limits: Option<PreviewLimits>) {
+    if let Ok(mut cached) = self.preview_limits.lock() {
+      *cached = limits.unwrap_or_default();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::models::DEFAULT_PREVIEW_MAX_PAGE_SIZE;
+
+  #[test]
+  fn page_sizes_are_clamped_to_the_cached_limits() {
+    let state = AppState::default();
+    assert_eq!(state.preview_limits().clamp_page_size(50), (50, false));
+    state.set_preview_limits(Some(PreviewLimits {
+      max_page_size: 20,
+      ..PreviewLimits::default()
+    }));
+    assert_eq!(state.preview_limits().clamp_page_size(20), (20, false));
+    assert_eq!(state.preview_limits().clamp_page_size(50), (20, true));
+    state.set_preview_limits(Some(PreviewLimits {
+      max_page_size: 0,
+      ..PreviewLimits::default()
+    }));
+    assert_eq!(state.preview_limits().clamp_page_size(5), (1, true));
+    state.set_preview_limits(None);
+    assert_eq!(state.preview_limits().max_page_size, DEFAULT_PREVIEW_MAX_PAGE_SIZE);
+  }
+
+  #[test]
+  fn preview_limits_are_read_while_a_command_writes_the_state() {
+    let state = AppState::default();
+    let _writer = state.inner.write().unwrap();
+    state.set_preview_limits(Some(PreviewLimits {
+      max_page_bytes: 1024,
+      ..PreviewLimits::default()
+    }));
+    assert_eq!(state.preview_limits().max_page_bytes, 1024);
+  }
+}
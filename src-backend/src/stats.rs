@@ -0,0 +1,156 @@
+use std::collections::{BTreeMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::categories::CategorySource;
+use crate::filters::normalize_for_dedupe;
+use crate::io::store_lines;
+use crate::models::{
+  CategoryRules,
+  DatasetStats,
+  FieldCoverage,
+  FieldMap,
+  LengthStats,
+  TextStats,
+};
+use crate::records::{count_tokens, extract_text_value, text_length};
+use crate::state::{DatasetStore, IdSet};
+
+/// Character and word lengths of one mapped field's non-empty texts.
+#[derive(Default)]
+struct TextLengths {
+  chars: Vec<u32>,
+  words: Vec<u32>,
+}
+
+impl TextLengths {
+  fn add(&mut self, text: &str) {
+    if text.trim().is_empty() {
+      return;
+    }
+    let clamp = |length: usize| u32::try_from(length).unwrap_or(u32::MAX);
+    self.chars.push(clamp(text_length(text)));
+    self.words.push(clamp(text.split_whitespace().count()));
+  }
+
+  fn finish(self, field: String) -> TextStats {
+    TextStats {
+      field,
+      count: self.chars.len(),
+      chars: length_stats(self.chars),
+      words: length_stats(self.words),
+    }
+  }
+}
+
+fn length_stats(mut lengths: Vec<u32>) -> LengthStats {
+  if lengths.is_empty() {
+    return LengthStats::default();
+  }
+  lengths.sort_unstable();
+  let quantile = |q: f64| f64::from(lengths[((lengths.len() - 1) as f64 * q).round() as usize]);
+  LengthStats {
+    mean: lengths.iter().map(|length| f64::from(*length)).sum::<f64>() / lengths.len() as f64,
+    median: quantile(0.5),
+    p95: quantile(0.95),
+  }
+}
+
+/// Scans the records in `ids` once for field coverage, the lengths of the
+/// mapped instruction and output, category cardinality, a duplicate rate and
+/// an estimated token total. Only lengths and hashes are kept per record.
+pub fn dataset_stats(
+  store: &DatasetStore,
+  ids: &IdSet,
+  field_map: &FieldMap,
+  category_rules: &CategoryRules,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<DatasetStats, String> {
+  let categories = CategorySource::new(field_map.category.as_deref(), category_rules)?;
+  let mut names = store.fields.clone();
+  let mut non_null: BTreeMap<String, usize> = BTreeMap::new();
+  let mut instruction = TextLengths::default();
+  let mut output = TextLengths::default();
+  let mut category_hashes = HashSet::new();
+  let mut text_hashes = HashSet::new();
+  let mut duplicate_count = 0usize;
+  let mut estimated_tokens = 0usize;
+  let mut scanned_count = 0usize;
+
+  for line in store_lines(store)? {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Stats scan canceled".to_string());
+    }
+    let line = line?;
+    let idx = line.id;
+    if !ids.contains(idx) {
+      continue;
+    }
+    let Some(record) = line.record()? else {
+      continue;
+    };
+    scanned_count += 1;
+    if scanned_count.is_multiple_of(1000) {
+      on_progress(scanned_count, ids.len());
+    }
+
+    for (name, value) in record.as_object().into_iter().flatten() {
+      if !non_null.contains_key(name) && !names.contains(name) {
+        names.push(name.clone());
+      }
+      let count = non_null.entry(name.clone()).or_insert(0);
+      if !value.is_null() {
+        *count += 1;
+      }
+    }
+
+    let instruction_text = extract_text_value(&record, &field_map.instruction).unwrap_or_default();
+    let output_text = extract_text_value(&record, &field_map.output).unwrap_or_default();
+    instruction.add(&instruction_text);
+    output.add(&output_text);
+    estimated_tokens += count_tokens(&instruction_text) + count_tokens(&output_text);
+    let key = format!(
+      "{}\u{1f}{}",
+      normalize_for_dedupe(&instruction_text),
+      normalize_for_dedupe(&output_text)
+    );
+    if key != "\u{1f}" && !text_hashes.insert(xxh3_64(key.as_bytes())) {
+      duplicate_count += 1;
+    }
+    if let Some(category) = categories.category(&record, field_map) {
+      category_hashes.insert(xxh3_64(category.as_bytes()));
+    }
+  }
+
+  let fields = names
+    .into_iter()
+    .map(|name| {
+      let non_null_count = non_null.get(&name).copied().unwrap_or(0);
+      FieldCoverage {
+        name,
+        non_null_count,
+        coverage_percent: if scanned_count == 0 {
+          0.0
+        } else {
+          non_null_count as f64 * 100.0 / scanned_count as f64
+        },
+      }
+    })
+    .collect();
+  Ok(DatasetStats {
+    view_count: ids.len(),
+    scanned_count,
+    fields,
+    instruction: field_map.instruction.clone().map(|field| instruction.finish(field)),
+    output: field_map.output.clone().map(|field| output.finish(field)),
+    category_count: (!categories.is_none()).then_some(category_hashes.len()),
+    duplicate_rate: if scanned_count == 0 {
+      0.0
+    } else {
+      duplicate_count as f64 / scanned_count as f64
+    },
+    estimated_tokens,
+  })
+}
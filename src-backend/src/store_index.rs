@@ -0,0 +1,272 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::appdata::SIDECAR_EXTENSIONS;
+use crate::io::content_hashes_path;
+use crate::models::{default_max_record_bytes, StoredDataset};
+use crate::paths::{io_error, write_atomic_with};
+use crate::state::DatasetStore;
+
+const INDEX_MAGIC: &[u8; 8] = b"DLIDX01\n";
+
+/// What the index sidecar of a store records about it besides its line offsets.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoreIndexHeader {
+  pub dataset_id: String,
+  pub source_path: PathBuf,
+  pub format: String,
+  #[serde(default)]
+  pub delimiter: Option<u8>,
+  #[serde(default)]
+  pub encoding: Option<String>,
+  #[serde(default)]
+  pub parent_id: Option<String>,
+  pub fields: Vec<String>,
+  pub record_count: usize,
+  pub size_bytes: u64,
+  #[serde(default)]
+  pub truncated: bool,
+  #[serde(default)]
+  pub resume_offset: Option<u64>,
+  #[serde(default = "default_max_record_bytes")]
+  pub max_record_bytes: usize,
+  /// Unix seconds when the store was written.
+  pub imported_at: u64,
+  /// Length of the store file, which is where the line after the last would start.
+  pub store_bytes: u64,
+}
+
+fn unix_now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|elapsed| elapsed.as_secs())
+    .unwrap_or_default()
+}
+
+/// Sidecar holding the line offsets and metadata of a store.
+pub fn store_index_path(store_path: &Path) -> PathBuf {
+  store_path.with_extension("idx")
+}
+
+/// Writes the index sidecar of `store`, stamped as imported now.
+pub fn save_store_index(store: &DatasetStore) -> Result<(), String> {
+  write_store_index(store, unix_now())
+}
+
+fn write_store_index(store: &DatasetStore, imported_at: u64) -> Result<(), String> {
+  let store_bytes = fs::metadata(&store.store_path)
+    .map_err(|e| io_error(&store.store_path, &e))?
+    .len();
+  let header = StoreIndexHeader {
+    dataset_id: store.id.clone(),
+    source_path: store.source_path.clone(),
+    format: store.format.clone(),
+    delimiter: store.delimiter,
+    encoding: store.encoding.clone(),
+    parent_id: store.parent_id.clone(),
+    fields: store.fields.clone(),
+    record_count: store.record_count,
+    size_bytes: store.size_bytes,
+    truncated: store.truncated,
+    resume_offset: store.resume_offset,
+    max_record_bytes: store.max_record_bytes,
+    imported_at,
+    store_bytes,
+  };
+  let header_bytes = serde_json::to_vec(&header).map_err(|e| e.to_string())?;
+  write_atomic_with(&store_index_path(&store.store_path), |writer| {
+    writer.write_all(INDEX_MAGIC).map_err(|e| e.to_string())?;
+    writer
+      .write_all(&(header_bytes.len() as u64).to_le_bytes())
+      .map_err(|e| e.to_string())?;
+    writer.write_all(&header_bytes).map_err(|e| e.to_string())?;
+    for offset in &store.offsets {
+      writer
+        .write_all(&offset.to_le_bytes())
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+  })
+}
+
+/// Reads the header of the index sidecar at `path`, leaving the reader at the
+/// first offset.
+pub fn read_store_index_header(
+  path: &Path,
+) -> Result<(StoreIndexHeader, BufReader<File>), String> {
+  let mut reader = BufReader::new(File::open(path).map_err(|e| io_error(path, &e))?);
+  let mut magic = [0u8; 8];
+  reader.read_exact(&mut magic).map_err(|e| e.to_string())?;
+  if &magic != INDEX_MAGIC {
+    return Err(format!("Unrecognized store index: {}", path.display()));
+  }
+  let mut header_len = [0u8; 8];
+  reader
+    .read_exact(&mut header_len)
+    .map_err(|e| e.to_string())?;
+  let mut header_bytes = vec![0u8; u64::from_le_bytes(header_len) as usize];
+  reader
+    .read_exact(&mut header_bytes)
+    .map_err(|e| e.to_string())?;
+  let header = serde_json::from_slice(&header_bytes).map_err(|e| e.to_string())?;
+  Ok((header, reader))
+}
+
+/// Line start offsets of the store at `path`, found by reading it through. A
+/// store whose line count disagrees with its content hashes is refused.
+pub fn scan_store_offsets(path: &Path) -> Result<Vec<u64>, String> {
+  let file = File::open(path).map_err(|e| io_error(path, &e))?;
+  let mut reader = BufReader::with_capacity(1024 * 1024, file);
+  let mut offsets = Vec::new();
+  let mut offset = 0u64;
+  let mut line = Vec::new();
+  loop {
+    line.clear();
+    let read = reader
+      .read_until(b'\n', &mut line)
+      .map_err(|e| io_error(path, &e))?;
+    if read == 0 {
+      break;
+    }
+    offsets.push(offset);
+    offset += read as u64;
+  }
+  let hashes_path = content_hashes_path(path);
+  let hashes_len = fs::metadata(&hashes_path)
+    .map_err(|e| io_error(&hashes_path, &e))?
+    .len();
+  if hashes_len != offsets.len() as u64 * 8 {
+    return Err(format!(
+      "{} has {} records but content hashes for {}",
+      path.display(),
+      offsets.len(),
+      hashes_len / 8
+    ));
+  }
+  Ok(offsets)
+}
+
+/// Opens the store at `store_path` from its index sidecar, without reading
+/// its records. When the store's length no longer matches the index, the
+/// offsets are rebuilt from the store and the index rewritten. `None` when
+/// the store has no index.
+pub fn load_store_index(store_path: &Path) -> Result<Option<DatasetStore>, String> {
+  let path = store_index_path(store_path);
+  if !path.exists() {
+    return Ok(None);
+  }
+  let (header, mut reader) = read_store_index_header(&path)?;
+  let store_bytes = fs::metadata(store_path)
+    .map_err(|e| io_error(store_path, &e))?
+    .len();
+  let rebuild = store_bytes != header.store_bytes;
+  let offsets = if rebuild {
+    scan_store_offsets(store_path)?
+  } else {
+    let mut offsets = Vec::with_capacity(header.record_count);
+    let mut offset = [0u8; 8];
+    for _ in 0..header.record_count {
+      reader.read_exact(&mut offset).map_err(|e| io_error(&path, &e))?;
+      offsets.push(u64::from_le_bytes(offset));
+    }
+    offsets
+  };
+  let imported_at = header.imported_at;
+  let store = DatasetStore {
+    id: header.dataset_id,
+    source_path: header.source_path,
+    store_path: store_path.to_path_buf(),
+    record_count: offsets.len(),
+    offsets,
+    fields: header.fields,
+    size_bytes: header.size_bytes,
+    format: header.format,
+    delimiter: header.delimiter,
+    encoding: header.encoding,
+    parent_id: header.parent_id,
+    truncated: header.truncated,
+    resume_offset: header.resume_offset,
+    max_record_bytes: header.max_record_bytes,
+  };
+  if rebuild {
+    write_store_index(&store, imported_at)?;
+  }
+  Ok(Some(store))
+}
+
+/// Path of the store `dataset_id` in `store_dir`, which must exist.
+fn stored_path(store_dir: &Path, dataset_id: &str) -> Result<PathBuf, String> {
+  if Uuid::parse_str(dataset_id).is_err() {
+    return Err(format!("Not a dataset id: {dataset_id}"));
+  }
+  let store_path = store_dir.join(format!("{dataset_id}.jsonl"));
+  if !store_path.is_file() {
+    return Err(format!("Dataset {dataset_id} does not exist"));
+  }
+  Ok(store_path)
+}
+
+/// Every indexed store in `store_dir`, most recently imported first. Stores
+/// without a readable index are left out, as they cannot be reopened.
+pub fn list_stored_datasets(store_dir: &Path) -> Result<Vec<StoredDataset>, String> {
+  let entries = match fs::read_dir(store_dir) {
+    Ok(entries) => entries,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+    Err(e) => return Err(io_error(store_dir, &e)),
+  };
+  let mut datasets = Vec::new();
+  for entry in entries.flatten() {
+    let path = entry.path();
+    if path.extension().is_none_or(|extension| extension != "idx") {
+      continue;
+    }
+    let Ok(store_bytes) = fs::metadata(path.with_extension("jsonl")).map(|meta| meta.len()) else {
+      continue;
+    };
+    let Ok((header, _)) = read_store_index_header(&path) else {
+      continue;
+    };
+    datasets.push(StoredDataset {
+      id: header.dataset_id,
+      source_path: header.source_path.to_string_lossy().to_string(),
+      format: header.format,
+      record_count: header.record_count,
+      size_bytes: header.size_bytes,
+      store_bytes,
+      imported_at: header.imported_at,
+      truncated: header.truncated,
+      open: false,
+      active: false,
+    });
+  }
+  datasets.sort_by(|a, b| b.imported_at.cmp(&a.imported_at).then_with(|| a.id.cmp(&b.id)));
+  Ok(datasets)
+}
+
+/// Deletes the store `dataset_id` of `store_dir` with all its sidecars.
+pub fn delete_stored_dataset(store_dir: &Path, dataset_id: &str) -> Result<(), String> {
+  let store_path = stored_path(store_dir, dataset_id)?;
+  fs::remove_file(&store_path).map_err(|e| io_error(&store_path, &e))?;
+  for extension in SIDECAR_EXTENSIONS {
+    let path = store_path.with_extension(extension);
+    match fs::remove_file(&path) {
+      Ok(()) => {}
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+      Err(e) => return Err(io_error(&path, &e)),
+    }
+  }
+  Ok(())
+}
+
+/// Opens the dataset `dataset_id` of `store_dir` from its index sidecar.
+pub fn reopen_indexed_store(store_dir: &Path, dataset_id: &str) -> Result<DatasetStore, String> {
+  let store_path = stored_path(store_dir, dataset_id)?;
+  load_store_index(&store_path)?
+    .ok_or_else(|| format!("Dataset {dataset_id} has no index; import its source again"))
+}
@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::io::store_lines;
+use crate::models::{FieldMap, TemplateCapSummary, TemplateCluster, TemplateReport};
+use crate::records::{extract_text_value, mask_template};
+use crate::state::{DatasetStore, IdSet};
+
+/// Member ids listed per template cluster.
+const TEMPLATE_SAMPLE_IDS: usize = 5;
+/// Most clusters a report lists.
+pub const MAX_TEMPLATE_CLUSTERS: usize = 500;
+
+/// Masks the instruction of every record in `base_ids` (all records when
+/// `None`) and calls `visit` with its id, the masked text and its hash.
+/// Records without an instruction are skipped. Returns the scanned count.
+fn scan_templates(
+  store: &DatasetStore,
+  base_ids: Option<&IdSet>,
+  field_map: &FieldMap,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+  mut visit: impl FnMut(usize, String, u64),
+) -> Result<usize, String> {
+  let total = base_ids
+    .map(|set| set.len())
+    .unwrap_or(store.record_count);
+  let mut scanned = 0usize;
+  for line in store_lines(store)? {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Template scan canceled".to_string());
+    }
+    let line = line?;
+    let idx = line.id;
+    if base_ids.is_some_and(|set| !set.contains(idx)) {
+      continue;
+    }
+    let Some(record) = line.record()? else {
+      continue;
+    };
+    scanned += 1;
+    if scanned.is_multiple_of(1000) {
+      on_progress(scanned, total);
+    }
+    let instruction = extract_text_value(&record, &field_map.instruction).unwrap_or_default();
+    let masked = mask_template(&instruction);
+    if masked.is_empty() {
+      continue;
+    }
+    let hash = xxh3_64(masked.as_bytes());
+    visit(idx, masked, hash);
+  }
+  Ok(scanned)
+}
+
+#[derive(Default)]
+struct TemplateStats {
+  count: usize,
+  sample_ids: Vec<usize>,
+  /// Kept once the template reaches the minimum cluster size.
+  masked: Option<String>,
+}
+
+/// Groups the records in `ids` by masked instruction and lists the templates
+/// shared by at least `min_cluster` records.
+pub fn detect_templates(
+  store: &DatasetStore,
+  ids: &IdSet,
+  field_map: &FieldMap,
+  min_cluster: usize,
+  cancel: &AtomicBool,
+  on_progress: impl FnMut(usize, usize),
+) -> Result<TemplateReport, String> {
+  let min_cluster = min_cluster.max(1);
+  let mut stats: HashMap<u64, TemplateStats> = HashMap::new();
+  let scanned_count = scan_templates(
+    store,
+    Some(ids),
+    field_map,
+    cancel,
+    on_progress,
+    |id, masked, hash| {
+      let entry = stats.entry(hash).or_default();
+      entry.count += 1;
+      if entry.sample_ids.len() < TEMPLATE_SAMPLE_IDS {
+        entry.sample_ids.push(id);
+      }
+      if entry.count == min_cluster {
+        entry.masked = Some(masked);
+      }
+    },
+  )?;
+
+  let template_count = stats.len();
+  let mut clusters = stats
+    .into_values()
+    .filter_map(|entry| {
+      Some(TemplateCluster {
+        masked: entry.masked?,
+        count: entry.count,
+        sample_ids: entry.sample_ids,
+      })
+    })
+    .collect::<Vec<_>>();
+  let clustered_count = clusters.iter().map(|cluster| cluster.count).sum();
+  clusters.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.masked.cmp(&b.masked)));
+  let truncated = clusters.len() > MAX_TEMPLATE_CLUSTERS;
+  clusters.truncate(MAX_TEMPLATE_CLUSTERS);
+  Ok(TemplateReport {
+    clusters,
+    truncated,
+    scanned_count,
+    template_count,
+    clustered_count,
+  })
+}
+
+/// Keeps the first `keep` records of each template, in id order, and returns
+/// the ids of the rest. Records without an instruction are always kept.
+pub fn cap_per_template(
+  store: &DatasetStore,
+  base_ids: Option<&IdSet>,
+  field_map: &FieldMap,
+  keep: usize,
+  cancel: &AtomicBool,
+  on_progress: impl FnMut(usize, usize),
+) -> Result<(IdSet, TemplateCapSummary), String> {
+  if keep == 0 {
+    return Err("Keep at least one record per template".to_string());
+  }
+  let mut counts: HashMap<u64, usize> = HashMap::new();
+  let mut removed = IdSet::new();
+  let scanned_count = scan_templates(
+    store,
+    base_ids,
+    field_map,
+    cancel,
+    on_progress,
+    |id, _, hash| {
+      let count = counts.entry(hash).or_default();
+      *count += 1;
+      if *count > keep {
+        removed.insert(id);
+      }
+    },
+  )?;
+
+  let total = base_ids
+    .map(|set| set.len())
+    .unwrap_or(store.record_count);
+  let summary = TemplateCapSummary {
+    scanned_count,
+    template_count: counts.len(),
+    capped_templates: counts.values().filter(|count| **count > keep).count(),
+    removed_count: removed.len(),
+    filtered_count: total.saturating_sub(removed.len()),
+  };
+  Ok((removed, summary))
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+  use crate::test_support::{jsonl_store, text_field_map, TempDir};
+
+  fn removed(store: &DatasetStore, keep: usize) -> Result<IdSet, String> {
+    let cancel = AtomicBool::new(false);
+    cap_per_template(store, None, &text_field_map(), keep, &cancel, |_, _| {})
+      .map(|(removed, _)| removed)
+  }
+
+  #[test]
+  fn the_cap_keeps_the_first_records_of_each_template() {
+    let dir = TempDir::new();
+    let records = ["Translate \"hi\"", "Translate \"bye\"", "Translate \"ok\"", "Sum 2 and 3"]
+      .map(|instruction| json!({ "instruction": instruction, "output": "x" }));
+    let store = jsonl_store(&dir, &records);
+    assert_eq!(removed(&store, 2).unwrap().to_vec(), vec![2]);
+    assert_eq!(removed(&store, 0).unwrap_err(), "Keep at least one record per template");
+  }
+}
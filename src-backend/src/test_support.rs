@@ -0,0 +1,111 @@
+//! Fixtures shared by the unit tests.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+
+use serde_json::Value;
+
+use crate::io::{ingest_dataset, store_lines};
+use crate::models::{FieldMap, ImportOptions, StageTiming};
+use crate::state::DatasetStore;
+
+/// A fresh directory under the system temp dir, removed on drop.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+  pub(crate) fn new() -> Self {
+    let path = std::env::temp_dir().join(format!("datalab-test-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&path).expect("create temp dir");
+    Self(path)
+  }
+
+  pub(crate) fn path(&self) -> &Path {
+    &self.0
+  }
+
+  pub(crate) fn join(&self, name: &str) -> PathBuf {
+    self.0.join(name)
+  }
+}
+
+impl Drop for TempDir {
+  fn drop(&mut self) {
+    let _ = fs::remove_dir_all(&self.0);
+  }
+}
+
+/// Imports `records` as a JSON Lines store inside `dir`.
+pub(crate) fn jsonl_store(dir: &TempDir, records: &[Value]) -> DatasetStore {
+  jsonl_store_with(dir, records, &ImportOptions::default())
+}
+
+pub(crate) fn jsonl_store_with(
+  dir: &TempDir,
+  records: &[Value],
+  options: &ImportOptions,
+) -> DatasetStore {
+  let source = dir.join("source.jsonl");
+  let lines = records.iter().map(|record| format!("{record}\n")).collect::<String>();
+  fs::write(&source, lines).expect("write source");
+  let cancel = AtomicBool::new(false);
+  let (store, _) = ingest_dataset(
+    &source,
+    &dir.join("store"),
+    options,
+    &cancel,
+    |_, _| {},
+  )
+  .expect("ingest fixture");
+  store
+}
+
+/// Every record of `store`, in order.
+pub(crate) fn stored_records(store: &DatasetStore) -> Vec<Value> {
+  store_lines(store)
+    .expect("open store")
+    .map(|line| line.expect("read line").record().expect("parse record").expect("a record"))
+    .collect()
+}
+
+/// Instruction and output mapped to fields of those names.
+pub(crate) fn text_field_map() -> FieldMap {
+  FieldMap {
+    instruction: Some("instruction".to_string()),
+    output: Some("output".to_string()),
+    ..FieldMap::default()
+  }
+}
+
+/// Checks `(phase, current, total)` progress events: phases arrive in the
+/// order given, and `current` never goes backwards or past `total` within one.
+pub(crate) fn assert_phases_in_order(events: &[(String, usize, usize)], phases: &[&str]) {
+  let mut seen: Vec<&str> = Vec::new();
+  let mut last = 0;
+  for (phase, current, total) in events {
+    if seen.last() != Some(&phase.as_str()) {
+      seen.push(phase);
+      last = 0;
+    }
+    assert!(*current >= last, "{phase} went back from {last} to {current}");
+    assert!(current <= total, "{phase} reported {current} of {total}");
+    last = *current;
+  }
+  assert_eq!(seen, phases);
+}
+
+/// Checks that `timings` names `stages` in order, took some time in total,
+/// and gives every stage in `rated` a positive records/sec rate.
+pub(crate) fn assert_stages_timed(timings: &[StageTiming], stages: &[&str], rated: &[&str]) {
+  let names = timings.iter().map(|timing| timing.stage.as_str()).collect::<Vec<_>>();
+  assert_eq!(names, stages);
+  assert!(timings.iter().map(|timing| timing.millis).sum::<u64>() > 0, "{timings:?}");
+  for timing in timings {
+    let rate = timing.records_per_sec;
+    if rated.contains(&timing.stage.as_str()) {
+      assert!(rate.is_some_and(|rate| rate.is_finite() && rate > 0.0), "{timing:?}");
+    } else {
+      assert_eq!(rate, None, "{timing:?}");
+    }
+  }
+}
@@ -0,0 +1,151 @@
+use std::time::{Duration, Instant};
+
+use crate::models::StageTiming;
+
+/// Accumulates wall time per named stage, in first-seen order.
+#[derive(Debug, Default)]
+pub struct StageTimer {
+  stages: Vec<(&'static str, Duration, usize)>,
+}
+
+impl StageTimer {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  fn entry(&mut self, stage: &'static str) -> &mut (&'static str, Duration, usize) {
+    let pos = match self.stages.iter().position(|(name, _, _)| *name == stage) {
+      Some(pos) => pos,
+      None => {
+        self.stages.push((stage, Duration::ZERO, 0));
+        self.stages.len() - 1
+      }
+    };
+    &mut self.stages[pos]
+  }
+
+  /// Runs `work` and charges its duration to `stage`.
+  pub fn time<T>(&mut self, stage: &'static str, work: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = work();
+    self.entry(stage).1 += start.elapsed();
+    result
+  }
+
+  /// Charges an externally measured duration to `stage`.
+  pub fn add(&mut self, stage: &'static str, elapsed: Duration) {
+    self.entry(stage).1 += elapsed;
+  }
+
+  /// Counts records handled by `stage`, which adds a records/sec rate to its timing.
+  pub fn count(&mut self, stage: &'static str, records: usize) {
+    self.entry(stage).2 += records;
+  }
+
+  pub fn finish(self) -> Vec<StageTiming> {
+    self
+      .stages
+      .into_iter()
+      .map(|(stage, elapsed, records)| {
+        let secs = elapsed.as_secs_f64();
+        StageTiming {
+          stage: stage.to_string(),
+          millis: elapsed.as_millis() as u64,
+          records_per_sec: (records > 0 && secs > 0.0).then(|| records as f64 / secs),
+        }
+      })
+      .collect()
+  }
+}
+
+/// One-line breakdown for the log, e.g. `scan 120ms (85000 rec/s), select 4ms`.
+pub fn format_timings(timings: &[StageTiming]) -> String {
+  timings
+    .iter()
+    .map(|timing| match timing.records_per_sec {
+      Some(rate) => format!("{} {}ms ({:.0} rec/s)", timing.stage, timing.millis, rate),
+      None => format!("{} {}ms", timing.stage, timing.millis),
+    })
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+/// Instantaneous progress rate between consecutive updates of the same key.
+#[derive(Debug, Default)]
+pub struct RateMeter {
+  last: Option<(String, Instant, usize)>,
+}
+
+impl RateMeter {
+  /// Units per second since the previous update, or `None` when `key` changed
+  /// or progress went backwards.
+  pub fn update(&mut self, key: &str, current: usize) -> Option<f64> {
+    let now = Instant::now();
+    let rate = match &self.last {
+      Some((last_key, at, last_current)) if last_key == key && current >= *last_current => {
+        let secs = now.duration_since(*at).as_secs_f64();
+        (secs > 0.0).then(|| (current - last_current) as f64 / secs)
+      }
+      _ => None,
+    };
+    self.last = Some((key.to_string(), now, current));
+    rate
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::thread;
+
+  use super::*;
+
+  #[test]
+  fn rates_restart_with_each_phase() {
+    let mut meter = RateMeter::default();
+    assert_eq!(meter.update("distill/meta", 0), None);
+    thread::sleep(Duration::from_millis(10));
+    let rate = meter.update("distill/meta", 1000).unwrap();
+    assert!(rate > 0.0 && rate <= 100_000.0, "{rate}");
+    assert_eq!(meter.update("distill/select", 0), None);
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(meter.update("distill/select", 0), Some(0.0));
+    assert_eq!(meter.update("distill/meta", 2000), None);
+  }
+
+  #[test]
+  fn progress_going_backwards_has_no_rate() {
+    let mut meter = RateMeter::default();
+    meter.update("export/write", 500);
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(meter.update("export/write", 100), None);
+    thread::sleep(Duration::from_millis(10));
+    assert!(meter.update("export/write", 200).is_some());
+  }
+
+  #[test]
+  fn stages_accumulate_in_first_seen_order() {
+    let mut timer = StageTimer::new();
+    timer.add("scan", Duration::from_millis(30));
+    timer.add("dedupe", Duration::from_millis(5));
+    timer.add("scan", Duration::from_millis(20));
+    timer.count("scan", 100);
+    timer.count("scan", 400);
+    let timings = timer.finish();
+    let stages = timings
+      .iter()
+      .map(|timing| (timing.stage.as_str(), timing.millis, timing.records_per_sec))
+      .collect::<Vec<_>>();
+    assert_eq!(stages, vec![("scan", 50, Some(10_000.0)), ("dedupe", 5, None)]);
+    assert_eq!(format_timings(&timings), "scan 50ms (10000 rec/s), dedupe 5ms");
+  }
+
+  #[test]
+  fn counted_stages_without_elapsed_time_have_no_rate() {
+    let mut timer = StageTimer::new();
+    timer.count("read", 10);
+    timer.time("write", || thread::sleep(Duration::from_millis(2)));
+    let timings = timer.finish();
+    assert_eq!(timings[0].records_per_sec, None);
+    assert!(timings[1].millis >= 2, "{:?}", timings[1]);
+  }
+}
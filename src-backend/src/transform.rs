@@ -0,0 +1,573 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde_json::Value;
+
+use crate::io::{detect_format, for_each_source_record, store_lines, StoreLine, StoreWriter};
+use crate::models::{ChunkSizeBin, FieldMap, TruncateOptions};
+use crate::records::{
+  extract_text_value,
+  token_spans,
+  truncate_tokens,
+  value_to_string,
+  TruncateBoundary,
+};
+use crate::state::DatasetStore;
+
+/// Field added to every materialized record, pointing at its record id in the parent store.
+pub const PARENT_ID_FIELD: &str = "_parent_id";
+
+/// Fails on a record too long for a scan of `store` to read, so a derived
+/// store never silently loses it.
+fn check_not_oversized(store: &DatasetStore, line: &StoreLine) -> Result<(), String> {
+  match line.oversized_size() {
+    Some(size) => Err(format!(
+      "Record {} is {size} bytes, over the {}-byte record limit",
+      line.id, store.max_record_bytes
+    )),
+    None => Ok(()),
+  }
+}
+
+/// Streams `parent` through `transform`, writing whatever it returns for each
+/// record into a new derived store. The partial store is removed on error.
+pub fn materialize(
+  parent: &DatasetStore,
+  store_dir: &Path,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+  mut transform: impl FnMut(usize, Value) -> Result<Vec<Value>, String>,
+) -> Result<DatasetStore, String> {
+  let mut writer = StoreWriter::create(store_dir)?;
+  let result = (|| -> Result<(), String> {
+    for line in store_lines(parent)? {
+      if cancel.load(Ordering::SeqCst) {
+        return Err("Materialization canceled".to_string());
+      }
+      let line = line?;
+      let idx = line.id;
+      let Some(record) = line.record()? else {
+        check_not_oversized(parent, &line)?;
+        continue;
+      };
+      for mut derived in transform(idx, record)? {
+        if let Some(map) = derived.as_object_mut() {
+          map.insert(PARENT_ID_FIELD.to_string(), Value::from(idx));
+        }
+        writer.write_value(&derived)?;
+      }
+      if idx.is_multiple_of(1000) {
+        on_progress(idx, parent.record_count);
+      }
+    }
+    Ok(())
+  })();
+  if let Err(err) = result {
+    writer.discard();
+    return Err(err);
+  }
+  let size_bytes = writer.bytes_written();
+  let mut store = writer.finish(
+    &parent.source_path,
+    size_bytes,
+    &parent.format,
+    Some(parent.id.clone()),
+  )?;
+  store.delimiter = parent.delimiter;
+  store.max_record_bytes = parent.max_record_bytes;
+  Ok(store)
+}
+
+/// A new store holding the records of `base` followed by those of
+/// `incoming`, so base records keep their ids. The partial store is removed
+/// on error.
+pub fn append_store(
+  base: &DatasetStore,
+  incoming: &DatasetStore,
+  store_dir: &Path,
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<DatasetStore, String> {
+  let total = base.record_count + incoming.record_count;
+  let mut writer = StoreWriter::create(store_dir)?;
+  let result = (|| -> Result<(), String> {
+    for store in [base, incoming] {
+      for line in store_lines(store)? {
+        if cancel.load(Ordering::SeqCst) {
+          return Err("Append canceled".to_string());
+        }
+        let line = line?;
+        let (Some(record), Some(bytes)) = (line.record()?, line.bytes()) else {
+          check_not_oversized(store, &line)?;
+          continue;
+        };
+        writer.write_serialized(&record, bytes)?;
+        if writer.len().is_multiple_of(1000) {
+          on_progress(writer.len(), total);
+        }
+      }
+    }
+    Ok(())
+  })();
+  if let Err(err) = result {
+    writer.discard();
+    return Err(err);
+  }
+  let size_bytes = writer.bytes_written();
+  let mut store = writer.finish(
+    &base.source_path,
+    size_bytes,
+    &base.format,
+    Some(base.id.clone()),
+  )?;
+  store.delimiter = base.delimiter;
+  store.max_record_bytes = base.max_record_bytes.max(incoming.max_record_bytes);
+  Ok(store)
+}
+
+/// One record per element of the array in `field`. Object elements are merged
+/// over the record unless `nest_key` is given, in which case each element is
+/// stored under that key. Returns the derived store and how many records were exploded.
+pub fn explode_field(
+  parent: &DatasetStore,
+  store_dir: &Path,
+  field: &str,
+  nest_key: Option<&str>,
+  cancel: &AtomicBool,
+  on_progress: impl FnMut(usize, usize),
+) -> Result<(DatasetStore, usize), String> {
+  let mut exploded = 0usize;
+  let store = materialize(parent, store_dir, cancel, on_progress, |_, record| {
+    let Value::Object(mut map) = record else {
+      return Ok(vec![record]);
+    };
+    let items = match map.get(field) {
+      Some(Value::Array(items)) if !items.is_empty() => items.clone(),
+      _ => return Ok(vec![Value::Object(map)]),
+    };
+    map.remove(field);
+    exploded += 1;
+    let derived = items
+      .into_iter()
+      .map(|item| {
+        let mut out = map.clone();
+        match (nest_key, item) {
+          (Some(key), item) => {
+            out.insert(key.to_string(), item);
+          }
+          (None, Value::Object(fields)) => {
+            out.extend(fields);
+          }
+          (None, item) => {
+            out.insert(field.to_string(), item);
+          }
+        }
+        Value::Object(out)
+      })
+      .collect();
+    Ok(derived)
+  })?;
+  Ok((store, exploded))
+}
+
+#[derive(Debug, Clone)]
+pub struct TruncateSpec {
+  pub field: String,
+  pub max_tokens: usize,
+  pub boundary: TruncateBoundary,
+  pub marker: String,
+}
+
+impl TruncateSpec {
+  pub fn from_options(options: &TruncateOptions) -> Result<Self, String> {
+    if options.field.trim().is_empty() {
+      return Err("Choose a field to truncate".to_string());
+    }
+    if options.max_tokens == 0 {
+      return Err("Token budget must be positive".to_string());
+    }
+    Ok(TruncateSpec {
+      field: options.field.clone(),
+      max_tokens: options.max_tokens,
+      boundary: TruncateBoundary::parse(&options.boundary)?,
+      marker: options.marker.clone(),
+    })
+  }
+
+  /// Cuts the text in the field of `record` to the token budget. Returns the
+  /// tokens dropped, or `None` when the record was left as it was.
+  pub fn apply(&self, record: &mut Value) -> Option<usize> {
+    let Some(Value::String(text)) = record.get_mut(&self.field) else {
+      return None;
+    };
+    let (truncated, saved) = truncate_tokens(text, self.max_tokens, self.boundary, &self.marker)?;
+    *text = truncated;
+    Some(saved)
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct TruncateCounts {
+  pub truncated: usize,
+  pub tokens_saved: usize,
+}
+
+/// Copies `parent` with the text in `spec.field` cut to `spec.max_tokens`.
+/// Records within the budget or without text in the field are copied as they are.
+pub fn truncate_field(
+  parent: &DatasetStore,
+  store_dir: &Path,
+  spec: &TruncateSpec,
+  cancel: &AtomicBool,
+  on_progress: impl FnMut(usize, usize),
+) -> Result<(DatasetStore, TruncateCounts), String> {
+  let mut counts = TruncateCounts::default();
+  let store = materialize(parent, store_dir, cancel, on_progress, |_, mut record| {
+    if let Some(saved) = spec.apply(&mut record) {
+      counts.truncated += 1;
+      counts.tokens_saved += saved;
+    }
+    Ok(vec![record])
+  })?;
+  Ok((store, counts))
+}
+
+/// Fields added to chunked records: the chunk's position and how many chunks
+/// its parent text was split into.
+pub const CHUNK_INDEX_FIELD: &str = "_chunk_index";
+pub const CHUNK_TOTAL_FIELD: &str = "_chunk_total";
+const CHUNK_HISTOGRAM_BINS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkUnit {
+  Chars,
+  Words,
+  /// The estimate of `count_tokens`.
+  Tokens,
+}
+
+impl ChunkUnit {
+  pub fn parse(unit: &str) -> Result<Self, String> {
+    match unit {
+      "chars" => Ok(ChunkUnit::Chars),
+      "words" => Ok(ChunkUnit::Words),
+      "tokens" => Ok(ChunkUnit::Tokens),
+      _ => Err(format!("Unknown chunk unit: {unit}")),
+    }
+  }
+
+  /// Byte ranges of the units in `text`.
+  fn spans(self, text: &str) -> Vec<Range<usize>> {
+    match self {
+      ChunkUnit::Chars => text
+        .char_indices()
+        .map(|(offset, c)| offset..offset + c.len_utf8())
+        .collect(),
+      ChunkUnit::Words => word_spans(text),
+      ChunkUnit::Tokens => token_spans(text),
+    }
+  }
+}
+
+fn word_spans(text: &str) -> Vec<Range<usize>> {
+  let mut spans = Vec::new();
+  let mut start = None;
+  for (offset, c) in text.char_indices() {
+    match (c.is_whitespace(), start) {
+      (true, Some(word_start)) => {
+        spans.push(word_start..offset);
+        start = None;
+      }
+      (false, None) => start = Some(offset),
+      _ => {}
+    }
+  }
+  if let Some(word_start) = start {
+    spans.push(word_start..text.len());
+  }
+  spans
+}
+
+#[derive(Debug, Clone)]
+pub struct ChunkSpec {
+  pub field: String,
+  pub max_len: usize,
+  pub overlap: usize,
+  pub unit: ChunkUnit,
+}
+
+impl ChunkSpec {
+  pub fn validate(&self) -> Result<(), String> {
+    if self.max_len == 0 {
+      return Err("Chunk length must be positive".to_string());
+    }
+    if self.overlap >= self.max_len {
+      return Err("Chunk overlap must be smaller than the chunk length".to_string());
+    }
+    Ok(())
+  }
+
+  /// Empty histogram of chunk sizes, in up to ten equal bins up to `max_len`.
+  fn histogram(&self) -> Vec<ChunkSizeBin> {
+    let width = self.max_len.div_ceil(CHUNK_HISTOGRAM_BINS);
+    (0..self.max_len.div_ceil(width))
+      .map(|bin| ChunkSizeBin {
+        min: bin * width + 1,
+        max: (bin + 1).saturating_mul(width).min(self.max_len),
+        count: 0,
+      })
+      .collect()
+  }
+}
+
+/// Splits `text` into windows of at most `max_len` units, each starting
+/// `overlap` units before the previous one ended. Windows are cut back to the
+/// last whitespace inside them rather than split a word, unless the word alone
+/// is longer than a window. Returns each chunk with its size in units, or an
+/// error when the spec is invalid.
+pub fn chunk_text<'a>(text: &'a str, spec: &ChunkSpec) -> Result<Vec<(&'a str, usize)>, String> {
+  spec.validate()?;
+  let spans = spec.unit.spans(text);
+  // Whether a chunk may start at this unit without splitting a word.
+  let word_start = |index: usize| {
+    let start = spans[index].start;
+    text[..start].chars().next_back().is_none_or(char::is_whitespace)
+      && !text[start..].starts_with(char::is_whitespace)
+  };
+
+  let mut chunks = Vec::new();
+  let mut start = 0usize;
+  let mut previous_end = 0usize;
+  while start < spans.len() {
+    let mut end = start.saturating_add(spec.max_len).min(spans.len());
+    if end < spans.len() && !word_start(end) {
+      // A chunk cut back into the previous one would add nothing new.
+      let earliest = (start + 1).max(previous_end + 1);
+      if let Some(cut) = (earliest..end).rev().find(|index| word_start(*index)) {
+        end = cut;
+      }
+    }
+    previous_end = end;
+    let chunk = text[spans[start].start..spans[end - 1].end].trim();
+    if !chunk.is_empty() {
+      let size = match spec.unit {
+        ChunkUnit::Chars => chunk.chars().count(),
+        _ => end - start,
+      };
+      chunks.push((chunk, size));
+    }
+    if end == spans.len() {
+      break;
+    }
+    start = end.saturating_sub(spec.overlap).max(start + 1);
+    if let Some(aligned) = (start..=end).find(|index| word_start(*index)) {
+      start = aligned;
+    }
+  }
+  Ok(chunks)
+}
+
+#[derive(Debug, Default)]
+pub struct ChunkCounts {
+  pub chunked: usize,
+  pub skipped: usize,
+  pub histogram: Vec<ChunkSizeBin>,
+}
+
+/// One record per chunk of the text in `spec.field`, with the other fields
+/// copied and `_chunk_index`/`_chunk_total` added. Records without text in the
+/// field are copied unchanged.
+pub fn chunk_field(
+  parent: &DatasetStore,
+  store_dir: &Path,
+  spec: &ChunkSpec,
+  cancel: &AtomicBool,
+  on_progress: impl FnMut(usize, usize),
+) -> Result<(DatasetStore, ChunkCounts), String> {
+  spec.validate()?;
+  let mut counts = ChunkCounts {
+    histogram: spec.histogram(),
+    ..ChunkCounts::default()
+  };
+  let width = spec.max_len.div_ceil(CHUNK_HISTOGRAM_BINS);
+  let store = materialize(parent, store_dir, cancel, on_progress, |_, record| {
+    let chunks = match record.get(&spec.field) {
+      Some(Value::String(text)) => chunk_text(text, spec)?
+        .into_iter()
+        .map(|(chunk, size)| (chunk.to_string(), size))
+        .collect::<Vec<_>>(),
+      _ => Vec::new(),
+    };
+    let Value::Object(map) = record else {
+      counts.skipped += 1;
+      return Ok(vec![record]);
+    };
+    if chunks.is_empty() {
+      counts.skipped += 1;
+      return Ok(vec![Value::Object(map)]);
+    }
+    if chunks.len() > 1 {
+      counts.chunked += 1;
+    }
+    let total = chunks.len();
+    let mut derived = Vec::with_capacity(total);
+    for (index, (chunk, size)) in chunks.into_iter().enumerate() {
+      let bin = (size.saturating_sub(1) / width).min(counts.histogram.len() - 1);
+      counts.histogram[bin].count += 1;
+      let mut out = map.clone();
+      out.insert(spec.field.clone(), Value::String(chunk));
+      out.insert(CHUNK_INDEX_FIELD.to_string(), Value::from(index));
+      out.insert(CHUNK_TOTAL_FIELD.to_string(), Value::from(total));
+      derived.push(Value::Object(out));
+    }
+    Ok(derived)
+  })?;
+  Ok((store, counts))
+}
+
+/// Mapped fields that pruning down to `keep` would drop.
+pub fn pruned_mapped_fields(field_map: &FieldMap, keep: &[String]) -> Vec<String> {
+  field_map
+    .mapped_fields()
+    .into_iter()
+    .filter(|name| !keep.iter().any(|kept| kept == name))
+    .map(str::to_string)
+    .collect()
+}
+
+/// Keeps only the `keep` fields of every record, plus the parent id provenance.
+pub fn prune_fields(
+  parent: &DatasetStore,
+  store_dir: &Path,
+  keep: &[String],
+  cancel: &AtomicBool,
+  on_progress: impl FnMut(usize, usize),
+) -> Result<DatasetStore, String> {
+  let keep = keep.iter().map(String::as_str).collect::<HashSet<_>>();
+  materialize(parent, store_dir, cancel, on_progress, |_, record| {
+    let Value::Object(mut map) = record else {
+      return Ok(vec![record]);
+    };
+    map.retain(|name, _| keep.contains(name.as_str()));
+    Ok(vec![Value::Object(map)])
+  })
+}
+
+#[derive(Debug, Clone)]
+pub struct JoinSpec {
+  pub aux_path: PathBuf,
+  pub left_key: String,
+  pub right_key: String,
+  /// Auxiliary columns to copy; empty means all except the key.
+  pub fields: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct JoinCounts {
+  pub right_rows: usize,
+  pub duplicate_right_keys: usize,
+  pub matched: usize,
+  pub missed: usize,
+}
+
+/// Adds the selected columns of an auxiliary csv/json/jsonl file to every record
+/// whose left key equals the row's right key. Later rows win on duplicate keys.
+pub fn join_metadata(
+  parent: &DatasetStore,
+  store_dir: &Path,
+  spec: &JoinSpec,
+  cancel: &AtomicBool,
+  on_progress: impl FnMut(usize, usize),
+) -> Result<(DatasetStore, JoinCounts), String> {
+  let mut counts = JoinCounts::default();
+  let mut rows: HashMap<String, serde_json::Map<String, Value>> = HashMap::new();
+  let format = detect_format(&spec.aux_path)?;
+  for_each_source_record(
+    &spec.aux_path,
+    &format,
+    usize::MAX,
+    |row| {
+      if cancel.load(Ordering::SeqCst) {
+        return Err("Join canceled".to_string());
+      }
+      let Value::Object(mut map) = row else {
+        return Ok(());
+      };
+      let Some(key) = map
+        .remove(&spec.right_key)
+        .map(|value| value_to_string(&value))
+      else {
+        return Ok(());
+      };
+      counts.right_rows += 1;
+      if !spec.fields.is_empty() {
+        map.retain(|name, _| spec.fields.contains(name));
+      }
+      if rows.insert(key, map).is_some() {
+        counts.duplicate_right_keys += 1;
+      }
+      Ok(())
+    },
+    |_| {},
+  )?;
+
+  let left_field = Some(spec.left_key.clone());
+  let store = materialize(parent, store_dir, cancel, on_progress, |_, record| {
+    let key = extract_text_value(&record, &left_field);
+    let Value::Object(mut map) = record else {
+      counts.missed += 1;
+      return Ok(vec![record]);
+    };
+    match key.and_then(|key| rows.get(&key)) {
+      Some(extra) => {
+        counts.matched += 1;
+        for (name, value) in extra {
+          map.insert(name.clone(), value.clone());
+        }
+      }
+      None => counts.missed += 1,
+    }
+    Ok(vec![Value::Object(map)])
+  })?;
+  Ok((store, counts))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn pruned_mapped_fields_matches_names_exactly_like_prune_fields() {
+    let field_map = FieldMap {
+      instruction: Some("Prompt".to_string()),
+      output: Some("response".to_string()),
+      ..FieldMap::default()
+    };
+    let keep = vec!["prompt".to_string(), "response".to_string()];
+    assert_eq!(pruned_mapped_fields(&field_map, &keep), vec!["Prompt".to_string()]);
+  }
+
+  fn word_chunks(max_len: usize, overlap: usize) -> ChunkSpec {
+    ChunkSpec {
+      field: "text".to_string(),
+      max_len,
+      overlap,
+      unit: ChunkUnit::Words,
+    }
+  }
+
+  #[test]
+  fn chunking_rejects_a_zero_length() {
+    assert!(chunk_text("some words here", &word_chunks(0, 0)).is_err());
+  }
+
+  #[test]
+  fn chunking_with_the_largest_length_keeps_the_text_whole() {
+    let spec = word_chunks(usize::MAX, 2);
+    let chunks = chunk_text("one two three four", &spec).unwrap();
+    assert_eq!(chunks, vec![("one two three four", 4)]);
+    assert_eq!(spec.histogram().last().map(|bin| bin.max), Some(usize::MAX));
+  }
+}
@@ -0,0 +1,399 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::io::store_lines;
+use crate::models::{RuleViolations, ValidationReport, ValidationRule};
+use crate::records::{extract_text_value, value_to_string};
+use crate::state::{DatasetStore, IdSet};
+
+/// Violating ids kept per rule in a validation report.
+const VIOLATION_SAMPLE_LIMIT: usize = 20;
+
+enum RuleCheck {
+  StartsWithField {
+    field: Option<String>,
+    prefix_field: Option<String>,
+  },
+  ContainsLiteral {
+    fields: Vec<String>,
+    literal: String,
+    case_sensitive: bool,
+  },
+  Regex {
+    field: Option<String>,
+    regex: Regex,
+    must_match: bool,
+  },
+  FieldEquals {
+    field: Option<String>,
+    other: Option<String>,
+  },
+}
+
+fn non_empty_text(record: &Value, field: &Option<String>) -> Option<String> {
+  extract_text_value(record, field)
+    .map(|text| text.trim().to_string())
+    .filter(|text| !text.is_empty())
+}
+
+impl RuleCheck {
+  fn new(rule: &ValidationRule) -> Result<Self, String> {
+    let compile = |pattern: &str| {
+      Regex::new(pattern).map_err(|e| format!("Invalid pattern in rule {}: {e}", rule.label()))
+    };
+    Ok(match rule {
+      ValidationRule::StartsWithField {
+        field,
+        prefix_field,
+      } => RuleCheck::StartsWithField {
+        field: Some(field.clone()),
+        prefix_field: Some(prefix_field.clone()),
+      },
+      ValidationRule::ContainsLiteral {
+        fields,
+        literal,
+        case_sensitive,
+      } => {
+        if literal.is_empty() {
+          return Err("contains_literal rule needs a literal".to_string());
+        }
+        RuleCheck::ContainsLiteral {
+          fields: fields.clone(),
+          literal: if *case_sensitive {
+            literal.clone()
+          } else {
+            literal.to_lowercase()
+          },
+          case_sensitive: *case_sensitive,
+        }
+      }
+      ValidationRule::RegexMust { field, pattern } => RuleCheck::Regex {
+        field: Some(field.clone()),
+        regex: compile(pattern)?,
+        must_match: true,
+      },
+      ValidationRule::RegexMustNot { field, pattern } => RuleCheck::Regex {
+        field: Some(field.clone()),
+        regex: compile(pattern)?,
+        must_match: false,
+      },
+      ValidationRule::FieldEquals { field, other } => RuleCheck::FieldEquals {
+        field: Some(field.clone()),
+        other: Some(other.clone()),
+      },
+    })
+  }
+
+  fn violated_by(&self, record: &Value) -> bool {
+    match self {
+      RuleCheck::StartsWithField {
+        field,
+        prefix_field,
+      } => match (non_empty_text(record, field), non_empty_text(record, prefix_field)) {
+        (Some(text), Some(prefix)) => text.starts_with(&prefix),
+        _ => false,
+      },
+      RuleCheck::ContainsLiteral {
+        fields,
+        literal,
+        case_sensitive,
+      } => {
+        let contains = |text: String| {
+          if *case_sensitive {
+            text.contains(literal.as_str())
+          } else {
+            text.to_lowercase().contains(literal.as_str())
+          }
+        };
+        if fields.is_empty() {
+          record
+            .as_object()
+            .is_some_and(|map| map.values().any(|value| contains(value_to_string(value))))
+        } else {
+          fields
+            .iter()
+            .filter_map(|name| extract_text_value(record, &Some(name.clone())))
+            .any(contains)
+        }
+      }
+      RuleCheck::Regex {
+        field,
+        regex,
+        must_match,
+      } => {
+        let text = extract_text_value(record, field).unwrap_or_default();
+        regex.is_match(&text) != *must_match
+      }
+      RuleCheck::FieldEquals { field, other } => {
+        match (non_empty_text(record, field), non_empty_text(record, other)) {
+          (Some(text), Some(other)) => text == other,
+          _ => false,
+        }
+      }
+    }
+  }
+}
+
+/// Compiled validation rules.
+pub struct RuleSet {
+  checks: Vec<RuleCheck>,
+}
+
+impl RuleSet {
+  pub fn new(rules: &[ValidationRule]) -> Result<Self, String> {
+    let checks = rules.iter().map(RuleCheck::new).collect::<Result<_, _>>()?;
+    Ok(Self { checks })
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.checks.is_empty()
+  }
+
+  /// Whether the record breaks any rule.
+  pub fn violated_by(&self, record: &Value) -> bool {
+    self.checks.iter().any(|check| check.violated_by(record))
+  }
+
+  /// Indexes of the rules the record breaks.
+  pub fn violations(&self, record: &Value) -> Vec<usize> {
+    self
+      .checks
+      .iter()
+      .enumerate()
+      .filter(|(_, check)| check.violated_by(record))
+      .map(|(index, _)| index)
+      .collect()
+  }
+}
+
+/// A suggested fix for records breaking `rule`, besides excluding them.
+fn suggestion(rule: &ValidationRule) -> String {
+  match rule {
+    ValidationRule::StartsWithField {
+      field,
+      prefix_field,
+    } => format!("Strip the repeated {prefix_field} text from the start of {field}"),
+    ValidationRule::ContainsLiteral { literal, .. } => {
+      format!("Remove or replace \"{literal}\" in the affected fields")
+    }
+    ValidationRule::RegexMust { field, .. } => format!("Rewrite {field} to match the pattern"),
+    ValidationRule::RegexMustNot { field, .. } => {
+      format!("Remove the matching text from {field}")
+    }
+    ValidationRule::FieldEquals { field, other } => {
+      format!("Rewrite {field} or {other} so they differ")
+    }
+  }
+}
+
+/// Checks every record of `ids` against the rules in one pass.
+pub fn validate_rules(
+  store: &DatasetStore,
+  ids: &IdSet,
+  rules: &[ValidationRule],
+  cancel: &AtomicBool,
+  mut on_progress: impl FnMut(usize, usize),
+) -> Result<ValidationReport, String> {
+  let rule_set = RuleSet::new(rules)?;
+  let mut results = rules
+    .iter()
+    .map(|rule| RuleViolations {
+      rule: rule.clone(),
+      label: rule.label(),
+      suggestion: suggestion(rule),
+      count: 0,
+      sample_ids: Vec::new(),
+    })
+    .collect::<Vec<_>>();
+  let mut scanned_count = 0usize;
+  let mut violating_count = 0usize;
+
+  for line in store_lines(store)? {
+    if cancel.load(Ordering::SeqCst) {
+      return Err("Validation canceled".to_string());
+    }
+    let line = line?;
+    let idx = line.id;
+    if !ids.contains(idx) {
+      continue;
+    }
+    let Some(record) = line.record()? else {
+      continue;
+    };
+    scanned_count += 1;
+    if scanned_count.is_multiple_of(1000) {
+      on_progress(scanned_count, ids.len());
+    }
+    let violations = rule_set.violations(&record);
+    if !violations.is_empty() {
+      violating_count += 1;
+    }
+    for index in violations {
+      let result = &mut results[index];
+      result.count += 1;
+      if result.sample_ids.len() < VIOLATION_SAMPLE_LIMIT {
+        result.sample_ids.push(idx);
+      }
+    }
+  }
+
+  Ok(ValidationReport {
+    scanned_count,
+    violating_count,
+    rules: results,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+  use crate::filters::apply_filters_inner;
+  use crate::models::{CategoryRules, FilterConfig};
+  use crate::test_support::{jsonl_store, text_field_map, TempDir};
+
+  fn rules() -> Vec<ValidationRule> {
+    vec![
+      ValidationRule::StartsWithField {
+        field: "output".to_string(),
+        prefix_field: "instruction".to_string(),
+      },
+      ValidationRule::ContainsLiteral {
+        fields: Vec::new(),
+        literal: "TODO".to_string(),
+        case_sensitive: false,
+      },
+      ValidationRule::RegexMust {
+        field: "lang".to_string(),
+        pattern: "^[a-z]{2}$".to_string(),
+      },
+      ValidationRule::RegexMustNot {
+        field: "output".to_string(),
+        pattern: "https?://".to_string(),
+      },
+      ValidationRule::FieldEquals {
+        field: "instruction".to_string(),
+        other: "output".to_string(),
+      },
+    ]
+  }
+
+  fn records() -> Vec<Value> {
+    vec![
+      json!({ "instruction": "hello", "output": "hello world", "lang": "en" }),
+      json!({ "instruction": "a", "output": "see the todo list", "lang": "en" }),
+      json!({ "instruction": "a", "output": "b", "lang": "english" }),
+      json!({ "instruction": "link", "output": "at http://example.com", "lang": "en" }),
+      json!({ "instruction": "same", "output": "same", "lang": "en" }),
+      json!({ "instruction": "clean", "output": "fine", "lang": "vi" }),
+      json!({ "instruction": "", "output": "", "lang": "en", "note": "TODO" }),
+      json!({ "instruction": "x", "output": "y" }),
+    ]
+  }
+
+  fn validate(store: &DatasetStore, ids: &IdSet, rules: &[ValidationRule]) -> ValidationReport {
+    validate_rules(store, ids, rules, &AtomicBool::new(false), |_, _| {}).unwrap()
+  }
+
+  #[test]
+  fn each_rule_kind_reports_its_violations() {
+    let dir = TempDir::new();
+    let store = jsonl_store(&dir, &records());
+    let report = validate(&store, &IdSet::full(store.record_count), &rules());
+    assert_eq!((report.scanned_count, report.violating_count), (8, 7));
+    let found = report
+      .rules
+      .iter()
+      .map(|rule| (rule.count, rule.sample_ids.clone()))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      found,
+      vec![
+        (2, vec![0, 4]),
+        (2, vec![1, 6]),
+        (2, vec![2, 7]),
+        (1, vec![3]),
+        (1, vec![4]),
+      ]
+    );
+
+    let without_four = IdSet::from_iter([0, 1, 2, 3, 5, 6, 7]);
+    let report = validate(&store, &without_four, &rules());
+    assert_eq!((report.scanned_count, report.violating_count), (7, 6));
+    assert_eq!(report.rules[4].count, 0);
+  }
+
+  #[test]
+  fn literal_case_follows_the_rule() {
+    let rule = |case_sensitive| {
+      let rule = ValidationRule::ContainsLiteral {
+        fields: vec!["output".to_string()],
+        literal: "TODO".to_string(),
+        case_sensitive,
+      };
+      RuleSet::new(&[rule]).unwrap()
+    };
+    let record = json!({ "output": "see the todo list", "note": "TODO" });
+    assert!(rule(false).violated_by(&record));
+    assert!(!rule(true).violated_by(&record));
+  }
+
+  #[test]
+  fn only_the_first_violating_ids_are_kept_as_samples() {
+    let dir = TempDir::new();
+    let records = (0..25)
+      .map(|id| json!({ "instruction": format!("q{id}"), "output": "TODO" }))
+      .collect::<Vec<_>>();
+    let store = jsonl_store(&dir, &records);
+    let report = validate(&store, &IdSet::full(25), &rules()[1..2]);
+    assert_eq!(report.rules[0].count, 25);
+    assert_eq!(report.rules[0].sample_ids, (0..VIOLATION_SAMPLE_LIMIT).collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn invalid_rules_are_refused() {
+    let invalid = ValidationRule::RegexMustNot {
+      field: "output".to_string(),
+      pattern: "(unclosed".to_string(),
+    };
+    let err = RuleSet::new(&[rules()[0].clone(), invalid]).err().unwrap();
+    assert!(err.starts_with("Invalid pattern in rule output must not match /(unclosed/"));
+    let empty = ValidationRule::ContainsLiteral {
+      fields: Vec::new(),
+      literal: String::new(),
+      case_sensitive: false,
+    };
+    assert!(RuleSet::new(&[empty]).is_err());
+  }
+
+  #[test]
+  fn rules_in_the_filters_exclude_the_records_breaking_them() {
+    let dir = TempDir::new();
+    let store = jsonl_store(&dir, &records());
+    let filter = |validation_rules| {
+      let filters = FilterConfig {
+        dedupe_exact: false,
+        validation_rules,
+        ..FilterConfig::default()
+      };
+      apply_filters_inner(
+        &store,
+        None,
+        &filters,
+        &text_field_map(),
+        &CategoryRules::default(),
+        &AtomicBool::new(false),
+        |_, _| {},
+      )
+      .unwrap()
+    };
+    let (all, _) = filter(Vec::new());
+    let (kept, summary) = filter(rules()[3..].to_vec());
+    let expected = all.iter().filter(|id| ![3, 4].contains(id)).collect::<Vec<_>>();
+    assert_eq!(kept.to_vec(), expected);
+    assert_eq!(summary.rejected.get("validation"), Some(&2));
+  }
+}
@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+
+use crate::models::{CategoryRules, FieldMap, FilterConfig, ViewDefinition, ViewRefinement};
+use crate::state::{IdSet, InnerState};
+
+pub const NAMED_VIEW_PREFIX: &str = "view:";
+/// Deepest chain of saved views referring to each other.
+const MAX_VIEW_DEPTH: usize = 16;
+
+/// A saved view with the results of its filter refinements.
+#[derive(Debug, Clone)]
+pub struct SavedView {
+  pub definition: ViewDefinition,
+  /// Output of each filter refinement by step, kept while its inputs are unchanged.
+  filter_results: BTreeMap<usize, FilterResult>,
+}
+
+impl SavedView {
+  pub fn new(definition: ViewDefinition) -> Self {
+    Self {
+      definition,
+      filter_results: BTreeMap::new(),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+struct FilterResult {
+  input: IdSet,
+  field_map: FieldMap,
+  category_rules: CategoryRules,
+  output: IdSet,
+}
+
+/// A filter refinement that has to run before a view can be resolved.
+#[derive(Debug, Clone)]
+pub struct PendingFilter {
+  pub view: String,
+  pub step: usize,
+  pub input: IdSet,
+  pub config: FilterConfig,
+}
+
+pub enum ViewResolution {
+  Ready(IdSet),
+  Pending(Box<PendingFilter>),
+}
+
+fn resolve(inner: &InnerState, view: &str, depth: usize) -> Result<ViewResolution, String> {
+  let Some(name) = view.strip_prefix(NAMED_VIEW_PREFIX) else {
+    return Ok(ViewResolution::Ready(inner.view_ids(view).to_set()));
+  };
+  if depth > MAX_VIEW_DEPTH {
+    return Err(format!("View {name} nests too deeply"));
+  }
+  let saved = inner
+    .views
+    .get(name)
+    .ok_or_else(|| format!("Unknown view: {name}"))?;
+  let mut ids = match resolve(inner, &saved.definition.base, depth + 1)? {
+    ViewResolution::Ready(ids) => ids,
+    pending => return Ok(pending),
+  };
+  for (step, refinement) in saved.definition.refinements.iter().enumerate() {
+    ids = match refinement {
+      ViewRefinement::Filter { config } => match saved.filter_results.get(&step) {
+        Some(result)
+          if result.input == ids
+            && result.field_map == inner.field_map
+            && result.category_rules == inner.category_rules =>
+        {
+          result.output.clone()
+        }
+        _ => {
+          return Ok(ViewResolution::Pending(Box::new(PendingFilter {
+            view: name.to_string(),
+            step,
+            input: ids,
+            config: (**config).clone(),
+          })))
+        }
+      },
+      ViewRefinement::Tag { tag } => inner
+        .tags
+        .get(tag)
+        .map_or_else(IdSet::new, |tagged| ids.intersection(tagged)),
+      ViewRefinement::Combine { op, view } => {
+        let other = match resolve(inner, view, depth + 1)? {
+          ViewResolution::Ready(other) => other,
+          pending => return Ok(pending),
+        };
+        match op.as_str() {
+          "intersect" => ids.intersection(&other),
+          "union" => ids.union(&other),
+          "subtract" => ids.difference(&other),
+          other => return Err(format!("Unknown view operation: {other}")),
+        }
+      }
+    };
+  }
+  Ok(ViewResolution::Ready(ids))
+}
+
+/// The ids of any view, or the first filter refinement that still has to run.
+/// Filter results are reused while their input ids, field map and category
+/// rules are unchanged; every other step is recomputed from current state.
+pub fn resolve_view(inner: &InnerState, view: &str) -> Result<ViewResolution, String> {
+  resolve(inner, view, 0)
+}
+
+/// Stores the output of a pending filter, unless the view changed meanwhile.
+pub fn record_filter_result(
+  inner: &mut InnerState,
+  pending: PendingFilter,
+  field_map: FieldMap,
+  category_rules: CategoryRules,
+  output: IdSet,
+) {
+  let Some(saved) = inner.views.get_mut(&pending.view) else {
+    return;
+  };
+  let unchanged = matches!(
+    saved.definition.refinements.get(pending.step),
+    Some(ViewRefinement::Filter { config }) if **config == pending.config
+  );
+  if unchanged {
+    saved.filter_results.insert(
+      pending.step,
+      FilterResult {
+        input: pending.input,
+        field_map,
+        category_rules,
+        output,
+      },
+    );
+  }
+}
+
+/// The sample view a saved view's base chain starts from, if any.
+pub fn named_view_sample_origin(inner: &InnerState, view: &str) -> Option<String> {
+  let mut view = view;
+  for _ in 0..=MAX_VIEW_DEPTH {
+    let Some(name) = view.strip_prefix(NAMED_VIEW_PREFIX) else {
+      return inner.sample_origin(view);
+    };
+    view = &inner.views.get(name)?.definition.base;
+  }
+  None
+}
+
+fn referenced_views(definition: &ViewDefinition) -> impl Iterator<Item = &str> {
+  let combined = definition
+    .refinements
+    .iter()
+    .filter_map(|refinement| match refinement {
+      ViewRefinement::Combine { view, .. } => Some(view.as_str()),
+      _ => None,
+    });
+  std::iter::once(definition.base.as_str())
+    .chain(combined)
+    .filter_map(|view| view.strip_prefix(NAMED_VIEW_PREFIX))
+}
+
+/// Checks a definition before it is saved as `name`: known operations, known
+/// saved views and no cycle back to `name`.
+pub fn check_definition(
+  views: &BTreeMap<String, SavedView>,
+  name: &str,
+  definition: &ViewDefinition,
+) -> Result<(), String> {
+  if name.trim().is_empty() {
+    return Err("View name must not be empty".to_string());
+  }
+  for refinement in &definition.refinements {
+    if let ViewRefinement::Combine { op, .. } = refinement {
+      if !matches!(op.as_str(), "intersect" | "union" | "subtract") {
+        return Err(format!("Unknown view operation: {op}"));
+      }
+    }
+  }
+  let mut stack = referenced_views(definition).collect::<Vec<_>>();
+  let mut seen = Vec::new();
+  while let Some(view) = stack.pop() {
+    if view == name {
+      return Err(format!("View {name} would refer to itself"));
+    }
+    if seen.contains(&view) {
+      continue;
+    }
+    seen.push(view);
+    let saved = views
+      .get(view)
+      .ok_or_else(|| format!("Unknown view: {view}"))?;
+    stack.extend(referenced_views(&saved.definition));
+  }
+  Ok(())
+}
+
+/// Saved views whose definitions refer to `name`.
+pub fn dependents(views: &BTreeMap<String, SavedView>, name: &str) -> Vec<String> {
+  views
+    .iter()
+    .filter(|(_, saved)| referenced_views(&saved.definition).any(|view| view == name))
+    .map(|(other, _)| other.clone())
+    .collect()
+}
@@ -0,0 +1,219 @@
+use crate::models::{DistillConfig, DistillSummary, FilterSummary};
+
+/// Retained share below which a result is reported as nearly empty.
+pub const MIN_RETAINED_SHARE: f64 = 0.001;
+/// Share of deduplicated records dropped as duplicates above which the
+/// dedupe pass is reported.
+pub const MAX_DUPLICATE_SHARE: f64 = 0.9;
+
+/// Tracks whether every value seen so far is the same, without keeping them.
+#[derive(Debug, Default)]
+pub struct Uniformity<T> {
+  first: Option<T>,
+  mixed: bool,
+}
+
+impl<T: PartialEq> Uniformity<T> {
+  pub fn add(&mut self, value: T) {
+    match &self.first {
+      None => self.first = Some(value),
+      Some(first) => self.mixed |= *first != value,
+    }
+  }
+
+  pub fn is_mixed(&self) -> bool {
+    self.mixed
+  }
+
+  /// The value, when values were seen and all of them were equal.
+  pub fn single(&self) -> Option<&T> {
+    self.first.as_ref().filter(|_| !self.mixed)
+  }
+}
+
+fn percent(count: usize, total: usize) -> String {
+  format!("{:.3}%", count as f64 / total as f64 * 100.0)
+}
+
+/// Warns when nothing, or less than `MIN_RETAINED_SHARE`, of `total` is kept.
+fn retained_warning(kept: usize, total: usize, outcome: &str) -> Option<String> {
+  if total == 0 {
+    None
+  } else if kept == 0 {
+    Some(format!("No records {outcome}"))
+  } else if (kept as f64) < total as f64 * MIN_RETAINED_SHARE {
+    Some(format!(
+      "Only {kept} of {total} records ({}) {outcome}",
+      percent(kept, total)
+    ))
+  } else {
+    None
+  }
+}
+
+/// Warnings on a filter result that is likely a mistake: almost nothing
+/// retained, one reason rejecting everything, a dedupe pass dropping most
+/// records, or the retained records collapsing into `single_category`.
+pub fn filter_warnings(summary: &FilterSummary, single_category: Option<&str>) -> Vec<String> {
+  let mut warnings = Vec::new();
+  warnings.extend(retained_warning(
+    summary.filtered_count,
+    summary.total_count,
+    "passed the filters",
+  ));
+  if summary.filtered_count == 0 && summary.total_count > 0 && summary.rejected.len() == 1 {
+    if let Some(reason) = summary.rejected.keys().next() {
+      warnings.push(format!("Every record was rejected by the {reason} check"));
+    }
+  }
+  let deduped = summary.filtered_count + summary.duplicates_removed;
+  if deduped > 0 && summary.duplicates_removed as f64 > deduped as f64 * MAX_DUPLICATE_SHARE {
+    warnings.push(format!(
+      "Deduplication removed {} of {deduped} records ({})",
+      summary.duplicates_removed,
+      percent(summary.duplicates_removed, deduped)
+    ));
+  }
+  if let Some(category) = single_category {
+    warnings.push(format!("Every retained record is in category {category}"));
+  }
+  warnings
+}
+
+/// Warnings on a distillation that is likely a mistake: nothing selected, a
+/// target beyond the records available, almost nothing selected without a
+/// fixed target, or the selection collapsing into `single_category`.
+pub fn distill_warnings(
+  summary: &DistillSummary,
+  config: &DistillConfig,
+  single_category: Option<&str>,
+) -> Vec<String> {
+  let mut warnings = Vec::new();
+  match config.target_count {
+    Some(target) if target as usize > summary.total_count => warnings.push(format!(
+      "Target of {target} records exceeds the {} available",
+      summary.total_count
+    )),
+    Some(_) => {}
+    None => warnings.extend(retained_warning(
+      summary.selected_count,
+      summary.total_count,
+      "were selected",
+    )),
+  }
+  if summary.selected_count == 0 && config.target_count.is_some() && summary.total_count > 0 {
+    warnings.push("No records were selected".to_string());
+  }
+  if let Some(category) = single_category {
+    warnings.push(format!("Every selected record is in category {category}"));
+  }
+  warnings
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeMap;
+
+  use serde_json::json;
+
+  use super::*;
+
+  fn filtered(total: usize, kept: usize, duplicates: usize, rejected: &[&str]) -> FilterSummary {
+    let rejected = rejected
+      .iter()
+      .map(|reason| (reason.to_string(), 1))
+      .collect::<BTreeMap<_, _>>();
+    serde_json::from_value(json!({
+      "totalCount": total,
+      "filteredCount": kept,
+      "duplicatesRemoved": duplicates,
+      "rejected": rejected,
+    }))
+    .unwrap()
+  }
+
+  fn distilled(total: usize, selected: usize) -> DistillSummary {
+    serde_json::from_value(json!({
+      "totalCount": total,
+      "selectedCount": selected,
+      "removedCount": total - selected,
+    }))
+    .unwrap()
+  }
+
+  #[test]
+  fn retained_share_warns_below_a_tenth_of_a_percent() {
+    assert!(filter_warnings(&filtered(0, 0, 0, &[]), None).is_empty());
+    assert!(filter_warnings(&filtered(10_000, 10, 0, &["length"]), None).is_empty());
+    assert_eq!(
+      filter_warnings(&filtered(10_000, 9, 0, &["length"]), None),
+      vec!["Only 9 of 10000 records (0.090%) passed the filters"]
+    );
+  }
+
+  #[test]
+  fn a_single_reason_rejecting_everything_is_named() {
+    assert_eq!(
+      filter_warnings(&filtered(50, 0, 0, &["include_keywords"]), None),
+      vec![
+        "No records passed the filters",
+        "Every record was rejected by the include_keywords check",
+      ]
+    );
+    assert_eq!(
+      filter_warnings(&filtered(50, 0, 0, &["length", "score"]), None),
+      vec!["No records passed the filters"]
+    );
+  }
+
+  #[test]
+  fn dedupe_warns_above_nine_tenths_removed() {
+    assert!(filter_warnings(&filtered(100, 10, 90, &[]), None).is_empty());
+    assert_eq!(
+      filter_warnings(&filtered(100, 9, 91, &[]), None),
+      vec!["Deduplication removed 91 of 100 records (91.000%)"]
+    );
+  }
+
+  #[test]
+  fn a_single_category_is_reported() {
+    assert_eq!(
+      filter_warnings(&filtered(100, 40, 0, &[]), Some("math")),
+      vec!["Every retained record is in category math"]
+    );
+    assert_eq!(
+      distill_warnings(&distilled(100, 10), &DistillConfig::default(), Some("math")),
+      vec!["Every selected record is in category math"]
+    );
+  }
+
+  #[test]
+  fn distillation_warns_on_targets_and_empty_selections() {
+    let target = |count| DistillConfig {
+      target_count: Some(count),
+      target_percent: None,
+      ..DistillConfig::default()
+    };
+    assert_eq!(
+      distill_warnings(&distilled(100, 100), &target(500), None),
+      vec!["Target of 500 records exceeds the 100 available"]
+    );
+    assert!(distill_warnings(&distilled(100, 100), &target(100), None).is_empty());
+    assert_eq!(
+      distill_warnings(&distilled(100, 0), &target(10), None),
+      vec!["No records were selected"]
+    );
+    let percent = DistillConfig {
+      target_count: None,
+      ..DistillConfig::default()
+    };
+    assert_eq!(
+      distill_warnings(&distilled(10_000, 1), &percent, None),
+      vec!["Only 1 of 10000 records (0.010%) were selected"]
+    );
+    assert_eq!(
+      distill_warnings(&distilled(10_000, 0), &percent, None),
+      vec!["No records were selected"]
+    );
+  }
+}
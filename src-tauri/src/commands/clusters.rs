@@ -0,0 +1,185 @@
+use std::sync::atomic::Ordering;
+
+use tauri::{AppHandle, State};
+
+use datalab_backend::clusters::{
+  find_duplicate_clusters as find_duplicate_clusters_inner,
+  ClusterReview,
+};
+use datalab_backend::io::{read_content_hashes, read_value_at, record_offsets};
+use datalab_backend::models::{ClusterMember, ClusterProgress, ClusterSummary, ClusterView};
+use datalab_backend::records::{build_preview_fields, oversized_preview_fields};
+use datalab_backend::state::AppState;
+
+use crate::tauri_support::{emit_progress, log_event, schedule_autosave};
+
+/// Computes duplicate clusters under the active filter config. Decisions from an
+/// earlier review carry over to clusters whose records were all decided before.
+#[tauri::command]
+pub async fn find_duplicate_clusters(
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<ClusterSummary, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let (store, filters, field_map, category_rules, previous) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    (
+      store,
+      inner.filters.clone(),
+      inner.field_map.clone(),
+      inner.category_rules.clone(),
+      inner.cluster_review.clone(),
+    )
+  };
+
+  let (review, restored_count) = tauri::async_runtime::spawn_blocking(move || {
+    let clusters = find_duplicate_clusters_inner(
+      &store,
+      &filters,
+      &field_map,
+      &category_rules,
+      cancel.as_ref(),
+      |current, total| {
+        emit_progress(
+          &handle,
+          "clusters",
+          current,
+          total,
+          &format!("Scanned {current} records"),
+        );
+      },
+    )?;
+    // Without hashes the clusters still work, but no decisions carry over.
+    let hashes = read_content_hashes(&store).unwrap_or_default();
+    let mut review = ClusterReview::new(clusters, &filters, &hashes);
+    let restored_count = previous.map_or(0, |previous| review.restore_from(&previous));
+    Ok::<_, String>((review, restored_count))
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  let summary = ClusterSummary {
+    duplicate_count: review
+      .clusters
+      .iter()
+      .map(|cluster| cluster.record_ids.len() - 1)
+      .sum(),
+    restored_count,
+    progress: review.progress(),
+  };
+  log_event(
+    &app,
+    &format!(
+      "Found {} duplicate clusters ({} duplicates), restored {} decisions",
+      summary.progress.cluster_count, summary.duplicate_count, restored_count
+    ),
+  );
+
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.cluster_review = Some(review);
+  inner.apply_cluster_decisions();
+  drop(inner);
+  schedule_autosave(&app);
+
+  Ok(summary)
+}
+
+/// The first cluster without a decision, or `None` when the review is done.
+#[tauri::command]
+pub async fn get_next_cluster(state: State<'_, AppState>) -> Result<Option<ClusterView>, String> {
+  // Only the members' offsets are taken under the lock; their records are read after.
+  let (store_path, record_bytes, offsets, field_map, cluster_id, progress) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .as_ref()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    let review = inner
+      .cluster_review
+      .as_ref()
+      .ok_or_else(|| "No duplicate clusters computed".to_string())?;
+    if review.stale {
+      return Err("Filters changed since clusters were computed; find clusters again".to_string());
+    }
+    let Some(cluster_id) = review.next_unreviewed() else {
+      return Ok(None);
+    };
+    let offsets = record_offsets(store, &review.clusters[cluster_id].record_ids)?
+      .into_iter()
+      .map(|(id, offset)| {
+        let kept = inner
+          .filtered_ids
+          .as_ref()
+          .is_none_or(|filtered_ids| filtered_ids.contains(id));
+        (id, offset, kept)
+      })
+      .collect::<Vec<_>>();
+    (
+      store.store_path.clone(),
+      store.preview_record_bytes(),
+      offsets,
+      inner.field_map.clone(),
+      cluster_id,
+      review.progress(),
+    )
+  };
+
+  let members = tauri::async_runtime::spawn_blocking(move || {
+    let mut members = Vec::with_capacity(offsets.len());
+    for (id, offset, kept) in offsets {
+      let (record, fields) = match read_value_at(&store_path, offset, record_bytes)? {
+        Ok(record) => {
+          let fields = build_preview_fields(&record, &field_map);
+          (record, fields)
+        }
+        Err(size) => (serde_json::Value::Null, oversized_preview_fields(size)),
+      };
+      members.push(ClusterMember {
+        id,
+        record,
+        fields,
+        kept,
+      });
+    }
+    Ok::<_, String>(members)
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+  Ok(Some(ClusterView {
+    cluster_id,
+    members,
+    progress,
+  }))
+}
+
+/// Keeps `keep_ids` of the cluster and drops its other records.
+#[tauri::command]
+pub fn resolve_cluster(
+  cluster_id: usize,
+  keep_ids: Vec<usize>,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<ClusterProgress, String> {
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.resolve_cluster(cluster_id, &keep_ids)?;
+  let progress = inner
+    .cluster_review
+    .as_ref()
+    .map(ClusterReview::progress)
+    .ok_or_else(|| "No duplicate clusters computed".to_string())?;
+  drop(inner);
+  schedule_autosave(&app);
+  Ok(progress)
+}
+
+#[tauri::command]
+pub fn get_cluster_progress(state: State<'_, AppState>) -> Result<Option<ClusterProgress>, String> {
+  let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+  Ok(inner.cluster_review.as_ref().map(ClusterReview::progress))
+}
@@ -0,0 +1,107 @@
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+use tauri::{AppHandle, State};
+
+use datalab_backend::compare::{
+  compare_datasets as compare_datasets_inner,
+  comparison_markdown,
+  CompareSpec,
+  DEFAULT_OVERLAP_SAMPLE,
+};
+use datalab_backend::models::DatasetComparison;
+use datalab_backend::paths::{check_output_path, create_output_file, io_error, OutputError};
+use datalab_backend::state::AppState;
+
+use crate::tauri_support::{emit_phase_progress, log_event, output_guard, Phases};
+
+const COMPARE_PHASES: Phases = Phases {
+  stage: "compare",
+  names: &["dataset_a", "dataset_b"],
+};
+
+/// Compares two open datasets and optionally writes the report as markdown.
+#[tauri::command]
+pub async fn compare_datasets(
+  id_a: String,
+  id_b: String,
+  sample_size: Option<usize>,
+  markdown_path: Option<String>,
+  overwrite: Option<bool>,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<DatasetComparison, OutputError> {
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let (store_a, store_b, spec, field_map, category_rules, markdown_target) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let open = |id: &str| {
+      inner
+        .datasets
+        .get(id)
+        .cloned()
+        .ok_or_else(|| format!("Dataset {id} is not open"))
+    };
+    let markdown_target = match &markdown_path {
+      Some(path) => {
+        let guard = output_guard(&app, &inner, false, overwrite.unwrap_or(false))?;
+        Some(check_output_path(Path::new(path), &guard)?)
+      }
+      None => None,
+    };
+    let spec = CompareSpec {
+      sample_size: sample_size.unwrap_or(DEFAULT_OVERLAP_SAMPLE),
+      length_scope: inner.filters.length_scope.clone(),
+      length_unit: inner.filters.length_unit.clone(),
+    };
+    (
+      open(&id_a)?,
+      open(&id_b)?,
+      spec,
+      inner.field_map.clone(),
+      inner.category_rules.clone(),
+      markdown_target,
+    )
+  };
+
+  let mut report = tauri::async_runtime::spawn_blocking(move || {
+    compare_datasets_inner(
+      &store_a,
+      &store_b,
+      &spec,
+      &field_map,
+      &category_rules,
+      cancel.as_ref(),
+      |phase, current, total| {
+        let message = format!("Compared {current} of {total} records");
+        emit_phase_progress(&handle, &COMPARE_PHASES, phase, current, total, &message);
+      },
+    )
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  if let Some(target) = markdown_target {
+    create_output_file(&target)?
+      .write_all(comparison_markdown(&report).as_bytes())
+      .map_err(|e| io_error(&target, &e))?;
+    report.markdown_path = Some(target.to_string_lossy().to_string());
+  }
+
+  log_event(
+    &app,
+    &format!(
+      "Compared {id_a} ({} records) with {id_b} ({} records): {:.1}% of {} sampled \
+       instructions found in B, {:.1}% including near duplicates",
+      report.dataset_a.record_count,
+      report.dataset_b.record_count,
+      report.overlap.exact_rate * 100.0,
+      report.overlap.sample_size,
+      report.overlap.fuzzy_rate * 100.0
+    ),
+  );
+
+  Ok(report)
+}
@@ -1,107 +1,254 @@
-use std::sync::atomic::Ordering;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use tauri::{AppHandle, State};
+use tauri::{AppHandle, Manager, State};
+use tauri_plugin_clipboard_manager::ClipboardExt;
 
+use datalab_backend::convert::convert_file as convert_file_inner;
+use datalab_backend::delta::{
+  export_manifest_path,
+  record_export,
+  write_manifest,
+  ManifestContext,
+};
+use datalab_backend::field_matrix::{
+  field_matrix,
+  field_matrix_csv,
+  field_matrix_markdown,
+};
 use datalab_backend::io::{
   export_dataset as export_dataset_file,
+  export_duplicate_report,
   ingest_dataset,
+  read_content_hashes,
+  read_content_hashes_for,
   read_record_value,
+  read_record_value_bounded,
+  record_offsets,
+  ExportCompression,
+  ExportSpec,
+  PreviewPageSource,
+};
+use datalab_backend::metrics::{collect_extremes, ExtremesSpec, RecordMetric};
+use datalab_backend::models::{
+  ConvertSummary,
+  DatasetConfig,
+  DatasetStats,
+  DatasetSummary,
+  ExportOptions,
+  ExportSummary,
+  ExtremeItem,
+  ExtremesResult,
+  FieldMap,
+  FieldMatrix,
+  ImportOptions,
+  OrderKey,
+  OrderPreview,
+  OrderedItem,
+  PreviewPage,
+  ReviewExportSummary,
+  StoredDataset,
+};
+use datalab_backend::ordering::{
+  collect_sort_keys,
+  export_order_keys,
+  shuffle_ids,
+  sort_rows,
+  validate_order,
+  OrderCache,
+  SortRow,
+};
+use datalab_backend::paths::{
+  check_output_path,
+  create_output_file,
+  io_error,
+  normalize_path,
+  OutputError,
+};
+use datalab_backend::records::{build_preview_fields, oversized_preview_fields};
+use datalab_backend::render::{read_render_records, render_records};
+use datalab_backend::session::LastSession;
+use datalab_backend::report::{review_html, ReviewSpec, DEFAULT_REVIEW_LIMIT};
+use datalab_backend::sidecar::load_derived_state;
+use datalab_backend::stable::{
+  load_annotations,
+  remap_annotations,
+  resolve_stable_ids as resolve_stable_ids_inner,
+};
+use datalab_backend::state::{AppState, DatasetStore, IdSet};
+use datalab_backend::stats::dataset_stats;
+use datalab_backend::store_index::{
+  delete_stored_dataset,
+  list_stored_datasets,
+  reopen_indexed_store,
+};
+use datalab_backend::timing::format_timings;
+
+use crate::tauri_support::{
+  annotation_dir,
+  dataset_dir,
+  default_dataset_config,
+  emit_import_progress,
+  emit_phase_progress,
+  emit_progress,
+  log_event,
+  materialize_view,
+  output_guard,
+  prepare_dataset_switch,
+  remember_session,
+  Phases,
 };
-use datalab_backend::models::{DatasetSummary, PreviewItem, PreviewPage};
-use datalab_backend::records::build_preview_fields;
-use datalab_backend::state::{AppState, DatasetStore, InnerState};
 
-use crate::tauri_support::{dataset_dir, emit_progress, log_event};
+const CSV_CONVERT_PHASES: Phases = Phases {
+  stage: "convert",
+  names: &["columns", "write"],
+};
 
-fn resolve_view_ids(
-  inner: &InnerState,
+/// Rows of `ids` sorted by `order_by`, reusing the keys of the last ordering
+/// scan when it was made for the same store, ordering and field map.
+fn ordered_rows(
+  handle: &AppHandle,
   store: &DatasetStore,
-  view: &str,
-  page: usize,
-  page_size: usize,
-) -> (Vec<usize>, usize) {
-  let offset = page.saturating_sub(1) * page_size;
-  match view {
-    "filtered" => {
-      if let Some(filtered) = &inner.filtered_ids {
-        let total = filtered.len();
-        let slice = filtered
-          .iter()
-          .skip(offset)
-          .take(page_size)
-          .cloned()
-          .collect();
-        (slice, total)
-      } else {
-        let total = store.record_count;
-        let slice = (offset..(offset + page_size).min(total)).collect();
-        (slice, total)
-      }
-    }
-    "selected" => {
-      if let Some(selected) = &inner.selected_ids {
-        let total = selected.len();
-        let slice = selected
-          .iter()
-          .skip(offset)
-          .take(page_size)
-          .cloned()
-          .collect();
-        (slice, total)
-      } else {
-        (Vec::new(), 0)
-      }
-    }
-    "removed" => {
-      if let Some(removed) = &inner.removed_ids {
-        let total = removed.len();
-        let slice = removed
-          .iter()
-          .skip(offset)
-          .take(page_size)
-          .cloned()
-          .collect();
-        (slice, total)
-      } else {
-        (Vec::new(), 0)
-      }
-    }
-    _ => {
-      let total = store.record_count;
-      let slice = (offset..(offset + page_size).min(total)).collect();
-      (slice, total)
+  ids: &IdSet,
+  order_by: &[OrderKey],
+  field_map: &FieldMap,
+  cancel: &AtomicBool,
+  on_progress: impl FnMut(usize, usize),
+) -> Result<Vec<SortRow>, String> {
+  let state = handle.state::<AppState>();
+  let cached = state
+    .order_cache
+    .lock()
+    .map_err(|_| "State lock error".to_string())?
+    .as_ref()
+    .and_then(|cache| cache.rows_for(store, ids, order_by, field_map));
+  let mut rows = match cached {
+    Some(rows) => rows,
+    None => {
+      let rows = collect_sort_keys(store, ids, order_by, field_map, cancel, on_progress)?;
+      *state.order_cache.lock().map_err(|_| "State lock error".to_string())? = Some(OrderCache {
+        store_path: store.store_path.clone(),
+        order_by: order_by.to_vec(),
+        field_map: field_map.clone(),
+        rows: rows.clone(),
+      });
+      rows
     }
-  }
+  };
+  sort_rows(&mut rows, order_by);
+  Ok(rows)
+}
+
+/// Phases of an export: reading sort keys when ordered, finding the columns
+/// of a flattened CSV, writing, and reading back when verified. `None` when
+/// it only writes.
+fn export_phases(ordered: bool, flattened: bool, verified: bool) -> Option<Phases> {
+  let names: &'static [&'static str] = match (ordered, flattened, verified) {
+    (false, false, false) => return None,
+    (false, false, true) => &["write", "verify"],
+    (false, true, false) => &["columns", "write"],
+    (false, true, true) => &["columns", "write", "verify"],
+    (true, false, false) => &["keys", "write"],
+    (true, false, true) => &["keys", "write", "verify"],
+    (true, true, false) => &["keys", "columns", "write"],
+    (true, true, true) => &["keys", "columns", "write", "verify"],
+  };
+  Some(Phases {
+    stage: "export",
+    names,
+  })
+}
+
+fn describe_order(order_by: &[OrderKey]) -> String {
+  order_by
+    .iter()
+    .map(|key| format!("{} {}", key.field_or_metric, key.direction))
+    .collect::<Vec<_>>()
+    .join(", ")
 }
 
 #[tauri::command]
 pub async fn import_dataset(
   path: String,
+  options: Option<ImportOptions>,
   app: AppHandle,
   state: State<'_, AppState>,
 ) -> Result<DatasetSummary, String> {
   state.cancel.store(false, Ordering::SeqCst);
   let cancel = state.cancel.clone();
   let handle = app.clone();
-  let path_buf = std::path::PathBuf::from(&path);
+  let path_buf = normalize_path(Path::new(&path));
   let store_dir = dataset_dir(&app)?;
+  let annotations_dir = annotation_dir(&app)?;
+  let options = options.unwrap_or_default();
 
-  let dataset = tauri::async_runtime::spawn_blocking(move || {
-    ingest_dataset(&path_buf, &store_dir, cancel.as_ref(), |count, _| {
-      emit_progress(
-        &handle,
-        "import",
-        count,
-        0,
-        &format!("Imported {count} records"),
-      );
-    })
+  let (dataset, import_report, remapped) = tauri::async_runtime::spawn_blocking(move || {
+    let (dataset, import_report) =
+      ingest_dataset(&path_buf, &store_dir, &options, cancel.as_ref(), |count, source| {
+        emit_import_progress(&handle, None, count, source, &format!("Imported {count} records"));
+      })?;
+    let remapped = match load_annotations(&annotations_dir, &path_buf) {
+      Ok(Some(saved)) if !saved.is_empty() => Some(remap_annotations(&dataset, &saved)),
+      Ok(_) => None,
+      Err(err) => Some(Err(err)),
+    };
+    Ok::<_, String>((dataset, import_report, remapped))
   })
   .await
   .map_err(|e| e.to_string())??;
 
-  log_event(&app, &format!("Imported dataset from {}", path));
+  match &dataset.encoding {
+    Some(encoding) => log_event(&app, &format!("Imported dataset from {path} ({encoding})")),
+    None => log_event(&app, &format!("Imported dataset from {path}")),
+  }
+  if dataset.truncated {
+    log_event(
+      &app,
+      &format!(
+        "Import of {path} was canceled; kept the first {} records",
+        dataset.record_count
+      ),
+    );
+  }
+  if import_report.oversized_skipped > 0 || import_report.truncated_records > 0 {
+    log_event(
+      &app,
+      &format!(
+        "Skipped {} oversized records, truncated {}",
+        import_report.oversized_skipped, import_report.truncated_records
+      ),
+    );
+  }
+  if import_report.skipped_count > 0 {
+    let first = &import_report.malformed_lines[0];
+    log_event(
+      &app,
+      &format!(
+        "Skipped {} malformed lines, the first at line {}: {}",
+        import_report.skipped_count, first.line_number, first.error
+      ),
+    );
+  }
+  if import_report.short_sections_skipped > 0 {
+    log_event(
+      &app,
+      &format!(
+        "Skipped {} Markdown sections with a short body",
+        import_report.short_sections_skipped
+      ),
+    );
+  }
+  if import_report.generated_headers {
+    log_event(
+      &app,
+      &format!(
+        "{path} has no header row; named its columns column_1 to column_{}",
+        dataset.fields.len()
+      ),
+    );
+  }
   emit_progress(
     &app,
     "import",
@@ -110,50 +257,265 @@ pub async fn import_dataset(
     "Import complete",
   );
 
+  let (remapped, remap_report) = match remapped {
+    Some(Ok((remapped, report))) => {
+      log_event(
+        &app,
+        &format!(
+          "Restored annotations: {} records matched, {} missing, {} collisions",
+          report.matched_count, report.missing_count, report.collision_count
+        ),
+      );
+      (Some(remapped), Some(report))
+    }
+    Some(Err(err)) => {
+      log_event(&app, &format!("Could not restore annotations: {err}"));
+      (None, None)
+    }
+    None => (None, None),
+  };
   let summary = DatasetSummary {
-    id: dataset.id.clone(),
-    source_path: dataset.source_path.to_string_lossy().to_string(),
-    format: dataset.format.clone(),
-    record_count: dataset.record_count,
-    fields: dataset.fields.clone(),
-    size_bytes: dataset.size_bytes,
+    import_report,
+    remap_report,
+    ..dataset.summary()
+  };
+
+  let config = prepare_dataset_switch(&app, &dataset, default_dataset_config(&app));
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.activate_dataset(dataset);
+  inner.apply_config(config);
+  if let Some(remapped) = remapped {
+    remapped.apply_to(&mut inner);
+  }
+
+  Ok(summary)
+}
+
+/// Positional ids of the active dataset's records with the given content hashes (hex).
+#[tauri::command]
+pub async fn resolve_stable_ids(
+  hashes: Vec<String>,
+  state: State<'_, AppState>,
+) -> Result<Vec<usize>, String> {
+  let hashes = hashes
+    .iter()
+    .map(|hash| u64::from_str_radix(hash, 16).map_err(|_| format!("Invalid content hash: {hash}")))
+    .collect::<Result<Vec<_>, _>>()?;
+  let store = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?
   };
+  tauri::async_runtime::spawn_blocking(move || {
+    let store_hashes = read_content_hashes(&store)?;
+    let (ids, _) = resolve_stable_ids_inner(&store_hashes, &hashes);
+    Ok(ids.to_vec())
+  })
+  .await
+  .map_err(|e| e.to_string())?
+}
 
+/// Content hashes (hex) of the given records of the active dataset.
+#[tauri::command]
+pub async fn get_stable_ids(
+  ids: Vec<usize>,
+  state: State<'_, AppState>,
+) -> Result<Vec<String>, String> {
+  let store = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?
+  };
+  if let Some(id) = ids.iter().find(|id| **id >= store.record_count) {
+    return Err(format!("Record id {id} out of range"));
+  }
+  tauri::async_runtime::spawn_blocking(move || {
+    let wanted = ids.iter().copied().collect::<IdSet>();
+    let hashes = wanted
+      .iter()
+      .zip(read_content_hashes_for(&store, &wanted)?)
+      .collect::<HashMap<_, _>>();
+    Ok(ids.iter().map(|id| format!("{:016x}", hashes[id])).collect())
+  })
+  .await
+  .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+pub fn list_open_datasets(state: State<'_, AppState>) -> Result<Vec<DatasetSummary>, String> {
+  let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+  Ok(inner.datasets.values().map(|store| store.summary()).collect())
+}
+
+/// Switches to an open dataset, restoring the field map and configs it was
+/// last used with.
+#[tauri::command]
+pub fn activate_dataset(
+  id: String,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<DatasetSummary, String> {
+  let store = state
+    .inner
+    .read()
+    .map_err(|_| "State lock error".to_string())?
+    .datasets
+    .get(&id)
+    .cloned()
+    .ok_or_else(|| format!("Dataset {id} is not open"))?;
+  let config = prepare_dataset_switch(&app, &store, default_dataset_config(&app));
+  let summary = store.summary();
   let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
-  inner.dataset = Some(dataset);
-  inner.filtered_ids = None;
-  inner.selected_ids = None;
-  inner.removed_ids = None;
-  inner.manual_include.clear();
-  inner.manual_exclude.clear();
+  inner.activate_dataset(store);
+  inner.apply_config(config);
+  Ok(summary)
+}
+
+/// Opens a dataset imported in an earlier run from the index saved with its
+/// store, restoring the configs and derived state it was last used with.
+#[tauri::command]
+pub async fn reopen_dataset(
+  dataset_id: String,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<DatasetSummary, String> {
+  let store_dir = dataset_dir(&app)?;
+  let (store, saved) = tauri::async_runtime::spawn_blocking(move || {
+    let store = reopen_indexed_store(&store_dir, &dataset_id)?;
+    let saved = load_derived_state(&store)?;
+    Ok::<_, String>((store, saved))
+  })
+  .await
+  .map_err(|e| e.to_string())??;
 
+  log_event(
+    &app,
+    &format!(
+      "Reopened dataset {} from {} ({} records)",
+      store.id,
+      store.source_path.display(),
+      store.record_count
+    ),
+  );
+  let config = prepare_dataset_switch(&app, &store, default_dataset_config(&app));
+  let summary = store.summary();
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.activate_dataset(store);
+  inner.apply_config(config);
+  if let Some(saved) = saved {
+    saved.apply_to(&mut inner);
+  }
   Ok(summary)
 }
 
+/// Datasets imported in this or earlier runs that can be opened again.
+#[tauri::command]
+pub fn list_datasets(
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<Vec<StoredDataset>, String> {
+  let mut datasets = list_stored_datasets(&dataset_dir(&app)?)?;
+  let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+  let active = inner.dataset.as_ref().map(|store| store.id.as_str());
+  for dataset in &mut datasets {
+    dataset.open = inner.datasets.contains_key(&dataset.id);
+    dataset.active = active == Some(dataset.id.as_str());
+  }
+  Ok(datasets)
+}
+
+/// Switches to a dataset listed by `list_datasets`: one open this session
+/// as `activate_dataset` does, any other by reopening it from disk.
+#[tauri::command]
+pub async fn open_dataset(
+  id: String,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<DatasetSummary, String> {
+  let open = state
+    .inner
+    .read()
+    .map_err(|_| "State lock error".to_string())?
+    .datasets
+    .contains_key(&id);
+  if open {
+    activate_dataset(id, app, state)
+  } else {
+    reopen_dataset(id, app, state).await
+  }
+}
+
+/// Deletes a stored dataset and its sidecars from disk. The active dataset
+/// cannot be deleted.
 #[tauri::command]
-pub fn get_preview(
+pub fn delete_dataset(
+  id: String,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<(), String> {
+  let store_dir = dataset_dir(&app)?;
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  if inner.dataset.as_ref().is_some_and(|store| store.id == id) {
+    return Err("The open dataset cannot be deleted; switch to another one first".to_string());
+  }
+  delete_stored_dataset(&store_dir, &id)?;
+  inner.datasets.remove(&id);
+  drop(inner);
+  log_event(&app, &format!("Deleted dataset {id}"));
+  Ok(())
+}
+
+/// Field map and configs of the active dataset.
+#[tauri::command]
+pub fn get_dataset_config(state: State<'_, AppState>) -> Result<DatasetConfig, String> {
+  let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+  if inner.dataset.is_none() {
+    return Err("No dataset loaded".to_string());
+  }
+  Ok(inner.config())
+}
+
+#[tauri::command]
+pub async fn get_preview(
   view: String,
   page: usize,
   page_size: usize,
+  app: AppHandle,
   state: State<'_, AppState>,
 ) -> Result<PreviewPage, String> {
-  let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
-  let store = inner
-    .dataset
-    .as_ref()
-    .ok_or_else(|| "No dataset loaded".to_string())?;
-  let (ids, total) = resolve_view_ids(&inner, store, &view, page, page_size);
-  let mut items = Vec::new();
-  for id in ids {
-    let record = read_record_value(store, id)?;
-    let fields = build_preview_fields(&record, &inner.field_map);
-    items.push(PreviewItem { id, fields });
+  materialize_view(&app, &view).await?;
+  let limits = state.preview_limits();
+  let (page_size, page_size_clamped) = limits.clamp_page_size(page_size);
+  // Only the page's offsets are taken under the lock; the records are read after.
+  let (source, session) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let source = PreviewPageSource::locate(&inner, &view, page, page_size)?;
+    (source, inner.dataset.as_ref().map(LastSession::new))
+  };
+  if let Some(session) = session {
+    remember_session(&app, session, |session| {
+      session.view = view.clone();
+      session.page = page;
+      session.page_size = page_size;
+    });
   }
+
+  let total = source.total;
+  let (items, truncated_page) =
+    tauri::async_runtime::spawn_blocking(move || source.read(limits.max_page_bytes))
+      .await
+      .map_err(|e| e.to_string())??;
   Ok(PreviewPage {
     items,
     total_count: total,
     page,
     page_size,
+    page_size_clamped,
+    truncated_page,
   })
 }
 
@@ -167,50 +529,600 @@ pub fn get_record(id: usize, state: State<'_, AppState>) -> Result<serde_json::V
   read_record_value(store, id)
 }
 
+/// Copies the given records to the system clipboard as `json`, `jsonl` or a
+/// `markdown` table. Returns the number of records copied.
+#[tauri::command]
+pub async fn export_to_clipboard(
+  ids: Vec<usize>,
+  format: String,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<usize, String> {
+  if ids.is_empty() {
+    return Err("Select at least one record to copy".to_string());
+  }
+  // Only the offsets are taken under the lock; the records are read after.
+  let (store_path, offsets, field_map) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .as_ref()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    (store.store_path.clone(), record_offsets(store, &ids)?, inner.field_map.clone())
+  };
+  let render_format = format.clone();
+  let text = tauri::async_runtime::spawn_blocking(move || {
+    let records = read_render_records(&store_path, &offsets)?;
+    render_records(&records, &render_format, &field_map)
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+  app.clipboard().write_text(text).map_err(|e| e.to_string())?;
+  log_event(&app, &format!("Copied {} records to the clipboard as {format}", ids.len()));
+  Ok(ids.len())
+}
+
 #[tauri::command]
 pub async fn export_dataset(
   view: String,
   path: String,
   format: String,
+  compression: Option<String>,
+  options: Option<ExportOptions>,
   app: AppHandle,
   state: State<'_, AppState>,
-) -> Result<(), String> {
+) -> Result<ExportSummary, OutputError> {
   state.cancel.store(false, Ordering::SeqCst);
   let cancel = state.cancel.clone();
   let handle = app.clone();
-  let path_clone = PathBuf::from(path.clone());
-  let format_clone = format.clone();
-  let store = {
+  let options = options.unwrap_or_default();
+  if format == "duplicate_report" {
+    return export_duplicates(path, options, app, state).await;
+  }
+  let compression = ExportCompression::parse(compression.as_deref().unwrap_or("none"))?;
+  materialize_view(&app, &view).await?;
+  let (store, ids, spec, order_by, sample_view, manifest) = {
     let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
-    inner
+    let store = inner
       .dataset
       .clone()
-      .ok_or_else(|| "No dataset loaded".to_string())?
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    let order_by = export_order_keys(&options, &inner.field_map)?;
+    let guard = output_guard(&app, &inner, options.allow_internal, options.overwrite)?;
+    let target = check_output_path(Path::new(&path), &guard)?;
+    let ids = inner.view_ids(&view).to_set();
+    let manifest = if options.write_manifest {
+      let manifest_path = export_manifest_path(&target);
+      check_output_path(&manifest_path, &guard)?;
+      Some(ManifestContext {
+        path: manifest_path,
+        view: view.clone(),
+        view_record_count: ids.len(),
+        filters: inner.filters.clone(),
+        distill_config: inner.distill_config.clone(),
+        app_version: app.package_info().version.to_string(),
+      })
+    } else {
+      None
+    };
+    let spec = ExportSpec {
+      path: target,
+      format,
+      compression,
+      options,
+      field_map: inner.field_map.clone(),
+    };
+    (store, ids, spec, order_by, inner.sample_origin(&view), manifest)
   };
-  let ids = {
+
+  let record_view = view.clone();
+  let (mut summary, recorded) = tauri::async_runtime::spawn_blocking(move || {
+    let ordered = !order_by.is_empty();
+    let phases = export_phases(ordered, spec.options.flatten_nested, spec.options.verify);
+    let mut order = if let Some(phases) = phases.as_ref().filter(|_| ordered) {
+      ordered_rows(
+        &handle,
+        &store,
+        &ids,
+        &order_by,
+        &spec.field_map,
+        cancel.as_ref(),
+        |current, total| {
+          let message = format!("Read sort keys of {current} records");
+          emit_phase_progress(&handle, phases, "keys", current, total, &message);
+        },
+      )?
+      .into_iter()
+      .map(|(id, _)| id)
+      .collect()
+    } else {
+      ids.iter().collect::<Vec<_>>()
+    };
+    let shuffle_seed = (spec.options.order == "shuffled")
+      .then(|| shuffle_ids(&mut order, spec.options.shuffle_seed));
+    let (mut summary, written) =
+      export_dataset_file(&store, &order, &spec, cancel.as_ref(), |phase, current, total| {
+        let message = match phase {
+          "columns" => format!("Found the columns of {current} records"),
+          "verify" => format!("Verified {current} records"),
+          _ => format!("Exported {current} records"),
+        };
+        match &phases {
+          Some(phases) => emit_phase_progress(&handle, phases, phase, current, total, &message),
+          None => emit_progress(&handle, "export", current, total, &message),
+        }
+      })?;
+    summary.order_by = order_by;
+    summary.shuffle_seed = shuffle_seed;
+    // Later delta exports of this view start from what was just written,
+    // leaving out the records the options skipped.
+    let written = written.into_iter().collect::<IdSet>();
+    let recorded = read_content_hashes_for(&store, &written).and_then(|hashes| {
+      record_export(&store, &record_view, &spec.path, &spec.format, &hashes)
+    });
+    if let Some(context) = manifest {
+      let manifest_path = write_manifest(&store, &spec, &summary, recorded.as_ref().ok(), context)
+        .map_err(|err| {
+          format!("Exported to {} but could not write its manifest: {err}", spec.path.display())
+        })?;
+      summary.manifest_path = Some(manifest_path.display().to_string());
+    }
+    Ok::<_, String>((summary, recorded))
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+  if let Err(err) = recorded {
+    log_event(&app, &format!("Could not record the export to {path}: {err}"));
+  }
+  summary.sample_view = sample_view;
+
+  log_event(
+    &app,
+    &format!(
+      "Exported dataset to {path} ({})",
+      format_timings(&summary.timings)
+    ),
+  );
+  if let Some(manifest_path) = &summary.manifest_path {
+    log_event(&app, &format!("Wrote the manifest of {path} to {manifest_path}"));
+  }
+  if !summary.order_by.is_empty() {
+    log_event(
+      &app,
+      &format!("Export to {path} ordered by {}", describe_order(&summary.order_by)),
+    );
+  }
+  if let Some(seed) = summary.shuffle_seed {
+    log_event(&app, &format!("Export to {path} shuffled with seed {seed}"));
+  }
+  if let Some(sample_view) = &summary.sample_view {
+    log_event(
+      &app,
+      &format!("Warning: export to {path} only covers sample view {sample_view}"),
+    );
+  }
+  if !summary.injected_fields.is_empty() || summary.system_prompt_count > 0 {
+    log_event(
+      &app,
+      &format!(
+        "Export to {path} injected fields [{}] and a system prompt into {} records; \
+         kept {} existing values",
+        summary.injected_fields.join(", "),
+        summary.system_prompt_count,
+        summary.kept_existing_count
+      ),
+    );
+  }
+  if summary.verified {
+    log_event(
+      &app,
+      &format!(
+        "Verified export to {path}: {} records read back, {} compared with the store",
+        summary.exported_count, summary.spot_checked_count
+      ),
+    );
+  }
+  if summary.truncated_count > 0 {
+    log_event(
+      &app,
+      &format!(
+        "Export to {path} truncated {} records, saving {} tokens",
+        summary.truncated_count, summary.tokens_saved
+      ),
+    );
+  }
+  if summary.invalid_weight_count > 0 {
+    log_event(
+      &app,
+      &format!(
+        "Found {} invalid weights, skipped {} records",
+        summary.invalid_weight_count, summary.skipped_count
+      ),
+    );
+  }
+  if summary.template_skipped_count > 0 {
+    log_event(
+      &app,
+      &format!(
+        "Export to {path} skipped {} records missing the template's fields",
+        summary.template_skipped_count
+      ),
+    );
+  }
+  Ok(summary)
+}
+
+/// Writes the duplicate clusters of the last filter run, which must have kept
+/// its duplicate map. The view and record options do not apply.
+async fn export_duplicates(
+  path: String,
+  options: ExportOptions,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<ExportSummary, OutputError> {
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let (store, clusters, duplicate_count, target) = {
     let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
-    match view.as_str() {
-      "removed" => inner.removed_ids.clone().unwrap_or_default(),
-      "selected" => inner.selected_ids.clone().unwrap_or_default(),
-      "filtered" => inner.filtered_ids.clone().unwrap_or_default(),
-      _ => (0..store.record_count).collect(),
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    let duplicates = inner
+      .filter_run
+      .as_ref()
+      .and_then(|run| run.duplicates.as_ref())
+      .ok_or_else(|| {
+        "The last filter run did not keep its duplicate map; keep it and filter again".to_string()
+      })?;
+    let guard = output_guard(&app, &inner, options.allow_internal, options.overwrite)?;
+    let target = check_output_path(Path::new(&path), &guard)?;
+    (store, duplicates.clusters(), duplicates.len(), target)
+  };
+
+  let summary = tauri::async_runtime::spawn_blocking(move || {
+    export_duplicate_report(&store, &clusters, &target, cancel.as_ref(), |current, total| {
+      emit_progress(
+        &handle,
+        "export",
+        current,
+        total,
+        &format!("Exported {current} duplicate clusters"),
+      );
+    })
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  log_event(
+    &app,
+    &format!(
+      "Exported {} duplicate clusters holding {duplicate_count} duplicates to {path} ({})",
+      summary.exported_count,
+      format_timings(&summary.timings)
+    ),
+  );
+  Ok(summary)
+}
+
+/// Converts the file at `input` to `format` at `output` without loading it:
+/// the open datasets and the datasets directory are left alone. The output
+/// is compressed when its name ends in `.gz` or `.zst`.
+#[tauri::command]
+pub async fn convert_file(
+  input: String,
+  output: String,
+  format: String,
+  input_options: Option<ImportOptions>,
+  output_options: Option<ExportOptions>,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<ConvertSummary, OutputError> {
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let input_path = normalize_path(Path::new(&input));
+  let input_options = input_options.unwrap_or_default();
+  let options = output_options.unwrap_or_default();
+  let spec = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let mut guard = output_guard(&app, &inner, options.allow_internal, options.overwrite)?;
+    guard.protected_files.push(input_path.clone());
+    let target = check_output_path(Path::new(&output), &guard)?;
+    ExportSpec {
+      compression: ExportCompression::for_path(&target),
+      path: target,
+      format: format.clone(),
+      options,
+      field_map: inner.field_map.clone(),
     }
   };
 
-  tauri::async_runtime::spawn_blocking(move || {
-    export_dataset_file(
+  let summary = tauri::async_runtime::spawn_blocking(move || {
+    let csv = spec.format == "csv";
+    convert_file_inner(&input_path, &input_options, &spec, cancel.as_ref(), |phase, count| {
+      let message = match phase {
+        "columns" => format!("Read the columns of {count} records"),
+        _ => format!("Converted {count} records"),
+      };
+      if csv {
+        emit_phase_progress(&handle, &CSV_CONVERT_PHASES, phase, count, 0, &message);
+      } else {
+        emit_progress(&handle, "convert", count, 0, &message);
+      }
+    })
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  log_event(
+    &app,
+    &format!(
+      "Converted {input} ({}) to {output} ({format}): {} records ({})",
+      summary.source_format,
+      summary.export.exported_count,
+      format_timings(&summary.export.timings)
+    ),
+  );
+  if summary.import_report.oversized_skipped > 0 {
+    log_event(
+      &app,
+      &format!(
+        "Conversion of {input} skipped {} oversized records",
+        summary.import_report.oversized_skipped
+      ),
+    );
+  }
+  Ok(summary)
+}
+
+/// The first `limit` records of a view under an ordering. The sort keys are
+/// kept so an export with the same ordering skips the scan.
+#[tauri::command]
+pub async fn preview_order(
+  view: String,
+  order_by: Vec<OrderKey>,
+  limit: usize,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<OrderPreview, String> {
+  validate_order(&order_by)?;
+  state.cancel.store(false, Ordering::SeqCst);
+  materialize_view(&app, &view).await?;
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let (store, ids, field_map) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    (store, inner.view_ids(&view).to_set(), inner.field_map.clone())
+  };
+  remember_session(&app, LastSession::new(&store), |session| {
+    if session.view != view {
+      session.view = view.clone();
+      session.page = 1;
+    }
+    session.order_by = order_by.clone();
+  });
+  let keys = order_by.clone();
+
+  let (items, scanned_count) = tauri::async_runtime::spawn_blocking(move || {
+    let rows = ordered_rows(
+      &handle,
       &store,
       &ids,
-      &path_clone,
-      &format_clone,
+      &keys,
+      &field_map,
       cancel.as_ref(),
       |current, total| {
         emit_progress(
           &handle,
-          "export",
+          "order",
           current,
           total,
-          &format!("Exported {current} records"),
+          &format!("Read sort keys of {current} records"),
+        );
+      },
+    )?;
+    let mut items = Vec::with_capacity(limit.min(rows.len()));
+    for (id, values) in rows.iter().take(limit) {
+      let fields = match read_record_value_bounded(&store, *id, store.preview_record_bytes())? {
+        Ok(record) => build_preview_fields(&record, &field_map),
+        Err(size) => oversized_preview_fields(size),
+      };
+      let values = values
+        .iter()
+        .map(|value| value.as_ref().map_or(serde_json::Value::Null, |value| value.to_json()))
+        .collect();
+      items.push(OrderedItem {
+        id: *id,
+        values,
+        fields,
+      });
+    }
+    Ok::<_, String>((items, rows.len()))
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  log_event(
+    &app,
+    &format!("Ordered {scanned_count} records by {}", describe_order(&order_by)),
+  );
+  Ok(OrderPreview {
+    order_by,
+    scanned_count,
+    items,
+  })
+}
+
+#[tauri::command]
+pub async fn get_extremes(
+  metric: String,
+  direction: String,
+  k: usize,
+  view: String,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<ExtremesResult, String> {
+  let metric_kind = RecordMetric::parse(&metric)?;
+  let descending = match direction.as_str() {
+    "desc" => true,
+    "asc" => false,
+    other => return Err(format!("Unknown direction: {other}")),
+  };
+  let spec = ExtremesSpec {
+    metric: metric_kind,
+    descending,
+    k,
+  };
+  state.cancel.store(false, Ordering::SeqCst);
+  materialize_view(&app, &view).await?;
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let (store, ids, field_map) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    (store, inner.view_ids(&view).to_set(), inner.field_map.clone())
+  };
+
+  let (items, scanned_count) = tauri::async_runtime::spawn_blocking(move || {
+    let (ranked, scanned) = collect_extremes(
+      &store,
+      &ids,
+      &spec,
+      &field_map,
+      cancel.as_ref(),
+      |current, total| {
+        emit_progress(
+          &handle,
+          "extremes",
+          current,
+          total,
+          &format!("Ranked {current} records"),
+        );
+      },
+    )?;
+    let mut items = Vec::with_capacity(ranked.len());
+    for (id, value) in ranked {
+      let fields = match read_record_value_bounded(&store, id, store.preview_record_bytes())? {
+        Ok(record) => build_preview_fields(&record, &field_map),
+        Err(size) => oversized_preview_fields(size),
+      };
+      items.push(ExtremeItem { id, value, fields });
+    }
+    Ok::<_, String>((items, scanned))
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  log_event(
+    &app,
+    &format!("Ranked {scanned_count} records by {metric} ({direction})"),
+  );
+  Ok(ExtremesResult {
+    metric,
+    direction,
+    scanned_count,
+    items,
+  })
+}
+
+/// Type, fill rate, distinct count, examples and mean length of every field
+/// in `view`, profiled from up to `sample_size` records.
+#[tauri::command]
+pub async fn get_field_matrix(
+  view: String,
+  sample_size: Option<usize>,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<FieldMatrix, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  materialize_view(&app, &view).await?;
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let (store, ids) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    (store, inner.view_ids(&view).to_set())
+  };
+
+  let matrix = tauri::async_runtime::spawn_blocking(move || {
+    field_matrix(&store, &ids, sample_size, cancel.as_ref(), |current, total| {
+      emit_progress(
+        &handle,
+        "fields",
+        current,
+        total,
+        &format!("Profiled {current} records"),
+      );
+    })
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  log_event(
+    &app,
+    &format!(
+      "Profiled {} fields over {} of {} records in {view}",
+      matrix.fields.len(),
+      matrix.scanned_count,
+      matrix.view_count
+    ),
+  );
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.field_matrix = Some(matrix.clone());
+  Ok(matrix)
+}
+
+/// Field coverage, mapped text lengths, category count, duplicate rate and
+/// estimated tokens of every record in `view`.
+#[tauri::command]
+pub async fn get_dataset_stats(
+  view: String,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<DatasetStats, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  materialize_view(&app, &view).await?;
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let (store, ids, field_map, category_rules) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    (
+      store,
+      inner.view_ids(&view).to_set(),
+      inner.field_map.clone(),
+      inner.category_rules.clone(),
+    )
+  };
+
+  let stats = tauri::async_runtime::spawn_blocking(move || {
+    dataset_stats(
+      &store,
+      &ids,
+      &field_map,
+      &category_rules,
+      cancel.as_ref(),
+      |current, total| {
+        emit_progress(
+          &handle,
+          "stats",
+          current,
+          total,
+          &format!("Scanned {current} records"),
         );
       },
     )
@@ -218,6 +1130,133 @@ pub async fn export_dataset(
   .await
   .map_err(|e| e.to_string())??;
 
-  log_event(&app, &format!("Exported dataset to {path}"));
-  Ok(())
+  log_event(
+    &app,
+    &format!(
+      "Computed stats of {} records in {view}: about {} tokens, {:.1}% duplicates",
+      stats.scanned_count,
+      stats.estimated_tokens,
+      stats.duplicate_rate * 100.0
+    ),
+  );
+  Ok(stats)
+}
+
+/// Writes the last field matrix as `markdown` or `csv`; returns the path written.
+#[tauri::command]
+pub fn export_field_matrix(
+  path: String,
+  format: String,
+  overwrite: Option<bool>,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<String, OutputError> {
+  let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+  let matrix = inner
+    .field_matrix
+    .as_ref()
+    .ok_or_else(|| "No field matrix computed".to_string())?;
+  let content = match format.as_str() {
+    "markdown" => field_matrix_markdown(matrix),
+    "csv" => field_matrix_csv(matrix)?,
+    other => return Err(format!("Unknown field matrix format: {other}").into()),
+  };
+  let guard = output_guard(&app, &inner, false, overwrite.unwrap_or(false))?;
+  let target = check_output_path(Path::new(&path), &guard)?;
+  drop(inner);
+  create_output_file(&target)?
+    .write_all(content.as_bytes())
+    .map_err(|e| io_error(&target, &e))?;
+
+  let written = target.to_string_lossy().to_string();
+  log_event(&app, &format!("Exported field matrix to {written}"));
+  Ok(written)
+}
+
+/// Writes up to `limit` records of a view as a self-contained HTML page for
+/// reviewers without the app.
+#[tauri::command]
+pub async fn export_review_html(
+  path: String,
+  view: String,
+  limit: Option<usize>,
+  overwrite: Option<bool>,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<ReviewExportSummary, OutputError> {
+  state.cancel.store(false, Ordering::SeqCst);
+  materialize_view(&app, &view).await?;
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let (store, ids, target, spec) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    let guard = output_guard(&app, &inner, false, overwrite.unwrap_or(false))?;
+    let target = check_output_path(Path::new(&path), &guard)?;
+    let ids = inner.view_ids(&view).to_set();
+    let mut manifest = vec![
+      ("Dataset".to_string(), store.source_path.to_string_lossy().to_string()),
+      ("Format".to_string(), store.format.clone()),
+      ("Records".to_string(), store.record_count.to_string()),
+      ("View".to_string(), view.clone()),
+      ("View records".to_string(), ids.len().to_string()),
+    ];
+    let counts = [
+      ("Filtered", &inner.filtered_ids),
+      ("Selected", &inner.selected_ids),
+      ("Removed", &inner.removed_ids),
+    ];
+    for (label, set) in counts {
+      if let Some(set) = set {
+        manifest.push((label.to_string(), set.len().to_string()));
+      }
+    }
+    if let Some(sample_view) = inner.sample_origin(&view) {
+      manifest.push(("Sample".to_string(), sample_view));
+    }
+    let spec = ReviewSpec {
+      view: view.clone(),
+      limit: limit.unwrap_or(DEFAULT_REVIEW_LIMIT),
+      manifest,
+      field_map: inner.field_map.clone(),
+      category_rules: inner.category_rules.clone(),
+    };
+    (store, ids, target, spec)
+  };
+
+  let view_count = ids.len();
+  let (page, exported_count) = tauri::async_runtime::spawn_blocking(move || {
+    review_html(&store, &ids, &spec, cancel.as_ref(), |current, total| {
+      emit_progress(
+        &handle,
+        "export",
+        current,
+        total,
+        &format!("Read {current} records"),
+      );
+    })
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+  create_output_file(&target)?
+    .write_all(page.as_bytes())
+    .map_err(|e| io_error(&target, &e))?;
+
+  let summary = ReviewExportSummary {
+    path: target.to_string_lossy().to_string(),
+    view_count,
+    exported_count,
+    truncated: exported_count < view_count,
+  };
+  log_event(
+    &app,
+    &format!(
+      "Exported {exported_count} of {view_count} records in {view} for review to {}",
+      summary.path
+    ),
+  );
+  Ok(summary)
 }
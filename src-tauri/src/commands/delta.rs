@@ -0,0 +1,174 @@
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+use tauri::{AppHandle, State};
+
+use datalab_backend::delta::{
+  delta_sibling_paths,
+  file_sha256,
+  load_last_export,
+  plan_delta,
+  record_export,
+  write_delta_manifest,
+  write_removals,
+};
+use datalab_backend::io::{
+  export_dataset as export_dataset_file,
+  read_content_hashes_for,
+  ExportCompression,
+  ExportSpec,
+};
+use datalab_backend::models::{DeltaExportSummary, DeltaManifest, ExportOptions};
+use datalab_backend::paths::{check_output_path, OutputError};
+use datalab_backend::state::{AppState, IdSet};
+
+use crate::tauri_support::{emit_progress, log_event, materialize_view, output_guard};
+
+/// The view delta exports are taken of.
+const DELTA_VIEW: &str = "selected";
+
+/// Exports the selected records whose content was not in the last export of
+/// the selection, with a removals list of those no longer selected and a
+/// manifest naming the export it follows. Without an earlier export, every
+/// selected record is written.
+#[tauri::command]
+pub async fn export_delta(
+  path: String,
+  format: String,
+  compression: Option<String>,
+  options: Option<ExportOptions>,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<DeltaExportSummary, OutputError> {
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let options = options.unwrap_or_default();
+  if !options.order_by.is_empty() || options.order != "original" {
+    return Err("Delta exports are written in record order".to_string().into());
+  }
+  if format == "duplicate_report" {
+    return Err("Duplicate reports cannot be exported as a delta".to_string().into());
+  }
+  let compression = ExportCompression::parse(compression.as_deref().unwrap_or("none"))?;
+  materialize_view(&app, DELTA_VIEW).await?;
+  let (store, ids, spec, removals_path, manifest_path) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    if inner.selected_ids.is_none() {
+      return Err("No records are selected; run distillation first".to_string().into());
+    }
+    let guard = output_guard(&app, &inner, options.allow_internal, options.overwrite)?;
+    let target = check_output_path(Path::new(&path), &guard)?;
+    let (removals_path, manifest_path) = delta_sibling_paths(&target);
+    check_output_path(&removals_path, &guard)?;
+    check_output_path(&manifest_path, &guard)?;
+    let spec = ExportSpec {
+      path: target,
+      format,
+      compression,
+      options,
+      field_map: inner.field_map.clone(),
+    };
+    (store, inner.view_ids(DELTA_VIEW).to_set(), spec, removals_path, manifest_path)
+  };
+
+  let summary = tauri::async_runtime::spawn_blocking(move || {
+    let previous = load_last_export(&store, DELTA_VIEW)?;
+    let selected = ids.iter().collect::<Vec<_>>();
+    // Both in ascending id order, so positions line up.
+    let hashes = read_content_hashes_for(&store, &ids)?;
+    let (added, removed) = match &previous {
+      Some(previous) => {
+        let (positions, removed) = plan_delta(&hashes, &previous.hashes);
+        let added = positions.into_iter().map(|position| selected[position]).collect();
+        (added, removed)
+      }
+      None => (selected, Vec::new()),
+    };
+
+    let (export, written) =
+      export_dataset_file(&store, &added, &spec, cancel.as_ref(), |_, current, total| {
+        emit_progress(
+          &handle,
+          "export",
+          current,
+          total,
+          &format!("Exported {current} new records"),
+        );
+      })?;
+    // Records the options skipped never went downstream, so the next delta
+    // offers them again.
+    let written = written.into_iter().collect::<IdSet>();
+    let skipped = added
+      .iter()
+      .copied()
+      .filter(|id| !written.contains(*id))
+      .collect::<IdSet>();
+    let downstream = ids
+      .iter()
+      .zip(&hashes)
+      .filter(|(id, _)| !skipped.contains(*id))
+      .map(|(_, hash)| *hash)
+      .collect::<Vec<_>>();
+    let removals_sha256 = if previous.is_some() {
+      write_removals(&removals_path, &removed)?;
+      Some(file_sha256(&removals_path)?)
+    } else {
+      None
+    };
+    let recorded = record_export(&store, DELTA_VIEW, &spec.path, &spec.format, &downstream)?;
+    let manifest = DeltaManifest {
+      dataset_id: store.id.clone(),
+      view: DELTA_VIEW.to_string(),
+      format: spec.format.clone(),
+      created_at: recorded.exported_at,
+      file: recorded.path.clone(),
+      sha256: recorded.sha256.clone(),
+      added_count: written.len(),
+      removals_file: removals_sha256
+        .as_ref()
+        .map(|_| removals_path.display().to_string()),
+      removals_sha256,
+      removed_count: removed.len(),
+      previous_file: previous.as_ref().map(|previous| previous.path.clone()),
+      previous_sha256: previous.as_ref().map(|previous| previous.sha256.clone()),
+      previous_exported_at: previous.as_ref().map(|previous| previous.exported_at),
+      total_count: recorded.hashes.len(),
+    };
+    write_delta_manifest(&manifest_path, &manifest)?;
+    Ok::<_, String>(DeltaExportSummary {
+      export,
+      full_export: previous.is_none(),
+      added_count: written.len(),
+      removed_count: removed.len(),
+      removals_path: manifest.removals_file.clone(),
+      manifest_path: manifest_path.display().to_string(),
+      manifest,
+    })
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  if summary.full_export {
+    log_event(
+      &app,
+      &format!(
+        "No earlier export of the selection was recorded; exported all {} selected records to {path}",
+        summary.added_count
+      ),
+    );
+  } else {
+    log_event(
+      &app,
+      &format!(
+        "Exported {} new selected records to {path}, {} removed since the last export",
+        summary.added_count, summary.removed_count
+      ),
+    );
+  }
+  Ok(summary)
+}
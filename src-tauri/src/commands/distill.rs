@@ -1,22 +1,72 @@
 use std::collections::HashSet;
 use std::sync::atomic::Ordering;
+use std::time::Duration;
 
 use tauri::{AppHandle, State};
 
-use datalab_backend::distill::preview_distillation as preview_distillation_inner;
-use datalab_backend::models::{DistillConfig, DistillSummary, FieldMap, ManualChange};
-use datalab_backend::state::AppState;
+use datalab_backend::distill::{
+  apply_manual_changes,
+  distill_ranking,
+  explain_record_score as explain_record_score_inner,
+  preview_distillation as preview_distillation_inner,
+};
+use datalab_backend::io::{read_value_at, record_offsets};
+use datalab_backend::models::{
+  CommandScoreSummary,
+  DatasetSummary,
+  DistillConfig,
+  DistillSummary,
+  FieldMap,
+  ManualChange,
+  ReviewQueue,
+  ScoreBreakdown,
+};
+use datalab_backend::refusals::{find_refusals, RefusalDetector};
+use datalab_backend::review_queue::{review_queue, ReviewSignals};
+use datalab_backend::score_command::{
+  attach_scores,
+  score_with_command as score_with_command_inner,
+  ScoreCommandSpec,
+  DEFAULT_SCORE_BATCH_SIZE,
+  DEFAULT_SCORE_TIMEOUT_SECS,
+};
+use datalab_backend::state::{AppState, CommandScores};
+use datalab_backend::timing::format_timings;
 
-use crate::tauri_support::{emit_progress, log_event};
+use crate::tauri_support::{
+  dataset_dir,
+  emit_phase_progress,
+  emit_progress,
+  log_event,
+  materialize_view,
+  prepare_dataset_switch,
+  read_settings,
+  schedule_autosave,
+  Phases,
+};
+
+const DISTILL_PHASES: Phases = Phases {
+  stage: "distill",
+  names: &["meta", "select"],
+};
+
+const REVIEW_PHASES: Phases = Phases {
+  stage: "review",
+  names: &["rank", "refusals"],
+};
 
 #[tauri::command]
 pub async fn preview_distillation(
   config: DistillConfig,
   field_map: FieldMap,
+  base_view: Option<String>,
   app: AppHandle,
   state: State<'_, AppState>,
 ) -> Result<DistillSummary, String> {
   state.cancel.store(false, Ordering::SeqCst);
+  if let Some(view) = &base_view {
+    materialize_view(&app, view).await?;
+  }
   let cancel = state.cancel.clone();
   let handle = app.clone();
   let config_clone = config.clone();
@@ -28,44 +78,74 @@ pub async fn preview_distillation(
       .clone()
       .ok_or_else(|| "No dataset loaded".to_string())?
   };
-  let filtered_ids = {
+  // A base view narrows the filtered records further, e.g. to a sample.
+  let (base_ids, sample_view, category_rules) = {
     let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
-    inner.filtered_ids.clone()
+    let (base_ids, sample_view) = match base_view.as_deref() {
+      Some(view) => {
+        let view_ids = inner.view_ids(view).to_set();
+        let base_ids = match &inner.filtered_ids {
+          Some(filtered_ids) => filtered_ids.intersection(&view_ids),
+          None => view_ids,
+        };
+        let sample_view = inner
+          .sample_origin(view)
+          .or_else(|| inner.filtered_sample.clone());
+        (Some(base_ids), sample_view)
+      }
+      None => (inner.filtered_ids.clone(), inner.filtered_sample.clone()),
+    };
+    (base_ids, sample_view, inner.category_rules.clone())
   };
 
-  let (selected_ids, removed_ids, summary) = tauri::async_runtime::spawn_blocking(move || {
+  let (selected_ids, removed_ids, mut summary) = tauri::async_runtime::spawn_blocking(move || {
     preview_distillation_inner(
       &store,
-      filtered_ids.as_deref(),
+      base_ids.as_ref(),
       &config_clone,
       &field_map_clone,
+      &category_rules,
       cancel.as_ref(),
-      |current, total| {
-        emit_progress(
-          &handle,
-          "distill",
-          current,
-          total,
-          &format!("Prepared {current} records"),
-        );
+      |phase, current, total| {
+        let message = match phase {
+          "select" => format!("Selecting from {total} records"),
+          _ => format!("Prepared {current} records"),
+        };
+        emit_phase_progress(&handle, &DISTILL_PHASES, phase, current, total, &message);
       },
     )
   })
   .await
   .map_err(|e| e.to_string())??;
+  summary.sample_view = sample_view.clone();
 
   log_event(
     &app,
-    &format!("Previewed distillation, {} selected", summary.selected_count),
+    &format!(
+      "Previewed distillation{}, {} selected ({})",
+      sample_view
+        .as_deref()
+        .map(|view| format!(" on {view}"))
+        .unwrap_or_default(),
+      summary.selected_count,
+      format_timings(&summary.timings)
+    ),
   );
+  for warning in &summary.constraint_warnings {
+    log_event(&app, &format!("Distillation constraint not met: {warning}"));
+  }
 
   let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
   inner.distill_config = config;
   inner.field_map = field_map;
   inner.selected_ids = Some(selected_ids);
   inner.removed_ids = Some(removed_ids);
+  inner.distill_score_ranges = summary.score_ranges.clone();
+  inner.selected_sample = sample_view;
   inner.manual_include.clear();
   inner.manual_exclude.clear();
+  drop(inner);
+  schedule_autosave(&app);
 
   Ok(summary)
 }
@@ -73,45 +153,360 @@ pub async fn preview_distillation(
 #[tauri::command]
 pub fn update_manual_selection(
   changes: Vec<ManualChange>,
+  app: AppHandle,
   state: State<'_, AppState>,
 ) -> Result<DistillSummary, String> {
-  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
-  let selected_ids = inner
-    .selected_ids
-    .take()
-    .ok_or_else(|| "No distillation preview available".to_string())?;
-  let removed_ids = inner
-    .removed_ids
-    .take()
-    .ok_or_else(|| "No distillation preview available".to_string())?;
-
-  let mut selected_set: HashSet<usize> = selected_ids.into_iter().collect();
-  let mut removed_set: HashSet<usize> = removed_ids.into_iter().collect();
-
-  for change in changes {
+  let mut guard = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  let inner = &mut *guard;
+  let record_count = inner
+    .dataset
+    .as_ref()
+    .map(|store| store.record_count)
+    .unwrap_or_default();
+  let (Some(selected_ids), Some(removed_ids)) =
+    (inner.selected_ids.as_mut(), inner.removed_ids.as_mut())
+  else {
+    return Err("No distillation preview available".to_string());
+  };
+  let added = apply_manual_changes(selected_ids, removed_ids, &changes, record_count)?;
+  for change in &changes {
     if change.include {
-      selected_set.insert(change.id);
-      removed_set.remove(&change.id);
+      inner.manual_exclude.remove(&change.id);
+      inner.manual_include.insert(change.id);
     } else {
-      selected_set.remove(&change.id);
-      removed_set.insert(change.id);
+      inner.manual_include.remove(&change.id);
+      inner.manual_exclude.insert(change.id);
     }
   }
 
-  let mut selected_vec = selected_set.into_iter().collect::<Vec<_>>();
-  let mut removed_vec = removed_set.into_iter().collect::<Vec<_>>();
-  selected_vec.sort_unstable();
-  removed_vec.sort_unstable();
-
-  let total_count = selected_vec.len() + removed_vec.len();
+  let total_count = selected_ids.len() + removed_ids.len();
   let summary = DistillSummary {
     total_count,
-    selected_count: selected_vec.len(),
-    removed_count: removed_vec.len(),
+    selected_count: selected_ids.len(),
+    removed_count: removed_ids.len(),
+    timings: Vec::new(),
+    sample_view: inner.selected_sample.clone(),
+    constraint_warnings: Vec::new(),
+    top_prefixes: Vec::new(),
+    score_ranges: Vec::new(),
+    external_selection: false,
+    warnings: Vec::new(),
+  };
+  drop(guard);
+
+  if added > 0 {
+    log_event(
+      &app,
+      &format!("Manually included {added} records from outside the distilled set"),
+    );
+  }
+  schedule_autosave(&app);
+
+  Ok(summary)
+}
+
+/// The `limit` undecided records of the distillation preview most worth a
+/// manual look: those nearest the cut, those kept over the largest duplicate
+/// clusters and refusals, weighted as set in Settings. Decisions go through
+/// `update_manual_selection`, which takes them out of the queue.
+#[tauri::command]
+pub async fn get_review_queue(
+  limit: usize,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<ReviewQueue, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let weights = read_settings(&app)?
+    .and_then(|settings| settings.review_weights)
+    .unwrap_or_default();
+  let (
+    store,
+    selected,
+    removed,
+    config,
+    field_map,
+    category_rules,
+    filters,
+    duplicates,
+    decided,
+  ) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    let (Some(selected), Some(removed)) = (inner.selected_ids.clone(), inner.removed_ids.clone())
+    else {
+      return Err("No distillation preview available".to_string());
+    };
+    let duplicates = inner
+      .filter_run
+      .as_ref()
+      .and_then(|run| run.duplicates.clone());
+    let decided = inner
+      .manual_include
+      .union(&inner.manual_exclude)
+      .copied()
+      .collect::<HashSet<_>>();
+    (
+      store,
+      selected,
+      removed,
+      inner.distill_config.clone(),
+      inner.field_map.clone(),
+      inner.category_rules.clone(),
+      inner.filters.clone(),
+      duplicates,
+      decided,
+    )
+  };
+
+  let queue = tauri::async_runtime::spawn_blocking(move || {
+    let base = selected.union(&removed);
+    let ranking = distill_ranking(
+      &store,
+      &base,
+      &config,
+      &field_map,
+      &category_rules,
+      cancel.as_ref(),
+      |_, current, total| {
+        let message = format!("Ranked {current} records");
+        emit_phase_progress(&handle, &REVIEW_PHASES, "rank", current, total, &message);
+      },
+    )?;
+    let detector = RefusalDetector::from_filters(&filters);
+    let refusals = if detector.is_empty() {
+      None
+    } else {
+      let (refusals, _) = find_refusals(
+        &store,
+        Some(&base),
+        &detector,
+        &field_map,
+        cancel.as_ref(),
+        |current, total| {
+          let message = format!("Checked {current} records for refusals");
+          emit_phase_progress(&handle, &REVIEW_PHASES, "refusals", current, total, &message);
+        },
+      )?;
+      Some(refusals)
+    };
+    let signals = ReviewSignals {
+      selected: &selected,
+      removed: &removed,
+      ranking: ranking.as_deref(),
+      balanced: config.preserve_category_balance,
+      by_prefix: config.strategy == "prefix_diversity",
+      duplicates: duplicates.as_ref(),
+      refusals: refusals.as_ref(),
+      decided: &decided,
+    };
+    Ok::<_, String>(ReviewQueue {
+      items: review_queue(&signals, &weights, limit),
+      boundary_ranked: ranking.is_some(),
+      duplicates_known: duplicates.is_some(),
+      refusals_checked: refusals.is_some(),
+    })
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  log_event(&app, &format!("Queued {} records for review", queue.items.len()));
+  Ok(queue)
+}
+
+/// How a record scores under the active distillation config, component by component.
+#[tauri::command]
+pub async fn explain_record_score(
+  id: usize,
+  state: State<'_, AppState>,
+) -> Result<ScoreBreakdown, String> {
+  // Only the record's offset is taken under the lock; the record is read after.
+  let (store_path, record_bytes, offset, field_map, config, ranges) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .as_ref()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    let (_, offset) = record_offsets(store, &[id])?[0];
+    (
+      store.store_path.clone(),
+      store.max_record_bytes,
+      offset,
+      inner.field_map.clone(),
+      inner.distill_config.clone(),
+      inner.distill_score_ranges.clone(),
+    )
+  };
+  tauri::async_runtime::spawn_blocking(move || {
+    // A record too large to score gets a neutral meta, so it is explained as empty.
+    let record = read_value_at(&store_path, offset, record_bytes)?
+      .unwrap_or(serde_json::Value::Null);
+    Ok(explain_record_score_inner(&record, id, &field_map, &config, &ranges))
+  })
+  .await
+  .map_err(|e| e.to_string())?
+}
+
+/// Scores the records of `view` (all records by default) with a user-provided
+/// command and keeps the scores by id. With `field`, they are also written to
+/// that field of a derived store, which becomes active with the field mapped
+/// as its score.
+#[tauri::command]
+pub async fn score_with_command(
+  command: String,
+  batch_size: Option<usize>,
+  field: Option<String>,
+  view: Option<String>,
+  timeout_secs: Option<u64>,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<CommandScoreSummary, String> {
+  if command.trim().is_empty() {
+    return Err("Scoring command is empty".to_string());
+  }
+  if field.as_deref().is_some_and(|field| field.trim().is_empty()) {
+    return Err("Score field name is empty".to_string());
+  }
+  state.cancel.store(false, Ordering::SeqCst);
+  let view = view.unwrap_or_else(|| "all".to_string());
+  materialize_view(&app, &view).await?;
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let spec = ScoreCommandSpec {
+    command: command.clone(),
+    batch_size: batch_size.unwrap_or(DEFAULT_SCORE_BATCH_SIZE).max(1),
+    timeout: Duration::from_secs(timeout_secs.unwrap_or(DEFAULT_SCORE_TIMEOUT_SECS).max(1)),
+  };
+  let batch_size = spec.batch_size;
+  let (store, ids) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    (store, inner.view_ids(&view).to_set())
   };
 
-  inner.selected_ids = Some(selected_vec);
-  inner.removed_ids = Some(removed_vec);
+  let scores = tauri::async_runtime::spawn_blocking(move || {
+    score_with_command_inner(&store, &ids, &spec, cancel.as_ref(), |current, total| {
+      emit_progress(
+        &handle,
+        "score",
+        current,
+        total,
+        &format!("Scored {current} records"),
+      );
+    })
+  })
+  .await
+  .map_err(|e| e.to_string())?
+  .map_err(|e| e.to_string())?;
 
+  let values = scores.values().copied().collect::<Vec<_>>();
+  let mut summary = CommandScoreSummary {
+    scored_count: values.len(),
+    batch_count: values.len().div_ceil(batch_size),
+    min_score: values.iter().copied().reduce(f64::min),
+    max_score: values.iter().copied().reduce(f64::max),
+    mean_score: (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64),
+    field: None,
+    dataset: None,
+  };
+  log_event(
+    &app,
+    &format!("Scored {} records of {view} with `{command}`", summary.scored_count),
+  );
+  let cache = CommandScores { command, scores };
+  match field {
+    Some(field) => {
+      summary.dataset = Some(attach_command_scores(&app, &state, cache, &field).await?);
+      summary.field = Some(field);
+    }
+    None => {
+      state
+        .inner
+        .write()
+        .map_err(|_| "State lock error".to_string())?
+        .command_scores = Some(cache);
+    }
+  }
   Ok(summary)
 }
+
+/// Writes the scores kept by the last `score_with_command` run to `field` of a
+/// derived store, without running the command again.
+#[tauri::command]
+pub async fn materialize_command_scores(
+  field: String,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<DatasetSummary, String> {
+  if field.trim().is_empty() {
+    return Err("Score field name is empty".to_string());
+  }
+  state.cancel.store(false, Ordering::SeqCst);
+  let cache = state
+    .inner
+    .read()
+    .map_err(|_| "State lock error".to_string())?
+    .command_scores
+    .clone()
+    .ok_or_else(|| "No command scores to materialize; score the dataset first".to_string())?;
+  attach_command_scores(&app, &state, cache, &field).await
+}
+
+async fn attach_command_scores(
+  app: &AppHandle,
+  state: &State<'_, AppState>,
+  cache: CommandScores,
+  field: &str,
+) -> Result<DatasetSummary, String> {
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let store_dir = dataset_dir(app)?;
+  let parent = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?
+  };
+  let scores = cache.scores.clone();
+  let field_clone = field.to_string();
+  let (derived, attached) = tauri::async_runtime::spawn_blocking(move || {
+    attach_scores(&parent, &store_dir, &scores, &field_clone, cancel.as_ref(), |current, total| {
+      emit_progress(
+        &handle,
+        "score",
+        current,
+        total,
+        &format!("Wrote scores of {current} records"),
+      );
+    })
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  log_event(
+    app,
+    &format!("Wrote {attached} command scores to field {field} of a derived dataset"),
+  );
+  let dataset = derived.summary();
+  let parent_config = state
+    .inner
+    .read()
+    .map_err(|_| "State lock error".to_string())?
+    .config();
+  let mut config = prepare_dataset_switch(app, &derived, parent_config);
+  config.field_map.score = Some(field.to_string());
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.activate_dataset(derived);
+  inner.apply_config(config);
+  // The derived store keeps every record in place, so the ids still match.
+  inner.command_scores = Some(cache);
+  Ok(dataset)
+}
@@ -1,35 +1,227 @@
 use std::sync::atomic::Ordering;
 
-use tauri::{AppHandle, State};
+use tauri::{AppHandle, Manager, State};
 
-use datalab_backend::filters::{apply_filters_inner, collect_categories};
-use datalab_backend::models::{CategoryCount, FieldMap, FilterConfig, FilterSummary};
-use datalab_backend::state::AppState;
+use datalab_backend::benchmark::run_benchmark as run_benchmark_inner;
+use datalab_backend::categories::{code_language_stats, CategoryMatcher, CategorySource};
+use datalab_backend::filters::{
+  apply_filters_tracked,
+  compose_drill_down,
+  list_active_categories,
+  narrow_filtered,
+  DedupeKey,
+  DEFAULT_MAX_DISTINCT_CATEGORIES,
+};
+use datalab_backend::language::{
+  find_language_mismatches,
+  language_sample,
+  language_stats,
+  DEFAULT_LANGUAGE_SAMPLE,
+};
+use datalab_backend::models::{
+  BenchmarkReport,
+  CategoryList,
+  CategoryRules,
+  CodeLanguageReport,
+  DrillDown,
+  DrillDownResult,
+  FieldMap,
+  FilterConfig,
+  FilterSummary,
+  LanguageStats,
+  PatternTest,
+  PiiFilter,
+  PiiScan,
+  TagSummary,
+  TemplateCapSummary,
+  TemplateReport,
+  ValidationReport,
+  ValidationRule,
+};
+use datalab_backend::pattern::{
+  test_pattern as test_pattern_inner,
+  PatternSpec,
+  DEFAULT_PATTERN_SAMPLE,
+};
+use datalab_backend::pii::{scan_pii as scan_pii_inner, DEFAULT_PII_SAMPLE};
+use datalab_backend::refusals::{find_refusals, RefusalDetector};
+use datalab_backend::state::{AppState, FilterRun, IdSet, LanguageStatsCache};
+use datalab_backend::templates::{
+  cap_per_template as cap_per_template_inner,
+  detect_templates as detect_templates_inner,
+};
+use datalab_backend::timing::format_timings;
+use datalab_backend::validation::validate_rules as validate_rules_inner;
 
-use crate::tauri_support::{emit_progress, log_event};
+use crate::tauri_support::{
+  emit_phase_progress,
+  emit_progress,
+  log_event,
+  materialize_view,
+  schedule_autosave,
+  Phases,
+};
+
+const BENCHMARK_PHASES: Phases = Phases {
+  stage: "benchmark",
+  names: &["read", "sample"],
+};
 
 #[tauri::command]
 pub async fn apply_filters(
   filters: FilterConfig,
   field_map: FieldMap,
+  base_view: Option<String>,
   app: AppHandle,
   state: State<'_, AppState>,
 ) -> Result<FilterSummary, String> {
   state.cancel.store(false, Ordering::SeqCst);
+  if let Some(view) = &base_view {
+    materialize_view(&app, view).await?;
+  }
   let cancel = state.cancel.clone();
   let handle = app.clone();
   let filters_clone = filters.clone();
   let field_map_clone = field_map.clone();
-  let store = {
+  let dedupe = DedupeKey::new(&filters, &field_map);
+  let (store, base_ids, sample_view, category_rules) = {
     let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
-    inner
+    let store = inner
       .dataset
       .clone()
-      .ok_or_else(|| "No dataset loaded".to_string())?
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    let base_ids = base_view.as_deref().map(|view| inner.view_ids(view).to_set());
+    let sample_view = base_view.as_deref().and_then(|view| inner.sample_origin(view));
+    (store, base_ids, sample_view, inner.category_rules.clone())
   };
 
-  let (filtered_ids, summary) = tauri::async_runtime::spawn_blocking(move || {
-    apply_filters_inner(&store, &filters_clone, &field_map_clone, cancel.as_ref(), |current, total| {
+  let (filtered_ids, mut summary, duplicates) = tauri::async_runtime::spawn_blocking(move || {
+    apply_filters_tracked(
+      &store,
+      base_ids.as_ref(),
+      &filters_clone,
+      &field_map_clone,
+      &category_rules,
+      cancel.as_ref(),
+      |current, total| {
+        emit_progress(
+          &handle,
+          "filter",
+          current,
+          total,
+          &format!("Filtered {current} records"),
+        );
+      },
+    )
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+  summary.sample_view = sample_view.clone();
+
+  log_event(
+    &app,
+    &format!(
+      "Applied filters{}, {} records retained ({})",
+      sample_view
+        .as_deref()
+        .map(|view| format!(" on {view}"))
+        .unwrap_or_default(),
+      summary.filtered_count,
+      format_timings(&summary.timings)
+    ),
+  );
+  if let Some(tokens) = summary.kept_tokens {
+    log_event(&app, &format!("Retained records hold about {tokens} tokens in the length scope"));
+  }
+
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.check_cluster_filters(&filters);
+  inner.filters = filters;
+  inner.field_map = field_map;
+  inner.filtered_ids = Some(filtered_ids);
+  inner.filtered_sample = sample_view;
+  inner.filter_run = Some(FilterRun {
+    base_view,
+    dedupe,
+    duplicates,
+  });
+  inner.selected_ids = None;
+  inner.removed_ids = None;
+  inner.selected_sample = None;
+  inner.manual_include.clear();
+  inner.manual_exclude.clear();
+  inner.apply_cluster_decisions();
+  drop(inner);
+  schedule_autosave(&app);
+
+  Ok(summary)
+}
+
+/// Adds `drill` to the applied filters and filters again. When the filtered
+/// ids came from a run with the same dedupe settings they are narrowed in
+/// place; otherwise the run's base view is filtered from scratch.
+#[tauri::command]
+pub async fn drill_down(
+  drill: DrillDown,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<DrillDownResult, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  let base_view = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    match &inner.filter_run {
+      Some(run) => run.base_view.clone(),
+      None => inner.filtered_sample.clone(),
+    }
+  };
+  if let Some(view) = &base_view {
+    materialize_view(&app, view).await?;
+  }
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let (store, filters, field_map, category_rules, reused_ids, base_ids, sample_view) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    let filters = compose_drill_down(&inner.filters, &drill)?;
+    if matches!(drill, DrillDown::Category { .. }) {
+      let category_field = filters
+        .category_field
+        .as_deref()
+        .or(inner.field_map.category.as_deref());
+      if CategorySource::new(category_field, &inner.category_rules)?.is_none() {
+        return Err("Map a category field or set category rules first".to_string());
+      }
+    }
+    let dedupe = DedupeKey::new(&filters, &inner.field_map);
+    let reused_ids = inner
+      .filter_run
+      .as_ref()
+      .filter(|run| run.dedupe == dedupe)
+      .and(inner.filtered_ids.clone());
+    let base_ids = match &reused_ids {
+      Some(_) => None,
+      None => base_view.as_deref().map(|view| inner.view_ids(view).to_set()),
+    };
+    let sample_view = base_view.as_deref().and_then(|view| inner.sample_origin(view));
+    (
+      store,
+      filters,
+      inner.field_map.clone(),
+      inner.category_rules.clone(),
+      reused_ids,
+      base_ids,
+      sample_view,
+    )
+  };
+
+  let dedupe_reused = reused_ids.is_some();
+  let filters_clone = filters.clone();
+  let field_map_clone = field_map.clone();
+  let (filtered_ids, mut summary, duplicates) = tauri::async_runtime::spawn_blocking(move || {
+    let on_progress = |current: usize, total: usize| {
       emit_progress(
         &handle,
         "filter",
@@ -37,41 +229,747 @@ pub async fn apply_filters(
         total,
         &format!("Filtered {current} records"),
       );
-    })
+    };
+    match &reused_ids {
+      Some(ids) => narrow_filtered(
+        &store,
+        ids,
+        &filters_clone,
+        &field_map_clone,
+        &category_rules,
+        cancel.as_ref(),
+        on_progress,
+      )
+      .map(|(ids, summary)| (ids, summary, None)),
+      None => apply_filters_tracked(
+        &store,
+        base_ids.as_ref(),
+        &filters_clone,
+        &field_map_clone,
+        &category_rules,
+        cancel.as_ref(),
+        on_progress,
+      ),
+    }
   })
   .await
   .map_err(|e| e.to_string())??;
+  summary.sample_view = sample_view.clone();
 
   log_event(
     &app,
-    &format!("Applied filters, {} records retained", summary.filtered_count),
+    &format!(
+      "Drilled down{}, {} records retained ({})",
+      if dedupe_reused {
+        " on the filtered records"
+      } else {
+        ""
+      },
+      summary.filtered_count,
+      format_timings(&summary.timings)
+    ),
   );
 
   let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
-  inner.filters = filters;
-  inner.field_map = field_map;
+  // Narrowing drops no duplicates, so the earlier run's map still applies.
+  let duplicates = if dedupe_reused {
+    inner.filter_run.take().and_then(|run| run.duplicates)
+  } else {
+    duplicates
+  };
+  summary.duplicate_map_kept = duplicates.is_some();
+  inner.check_cluster_filters(&filters);
+  inner.filters = filters.clone();
   inner.filtered_ids = Some(filtered_ids);
+  inner.filtered_sample = sample_view;
+  inner.filter_run = Some(FilterRun {
+    base_view,
+    dedupe: DedupeKey::new(&filters, &field_map),
+    duplicates,
+  });
   inner.selected_ids = None;
   inner.removed_ids = None;
+  inner.selected_sample = None;
   inner.manual_include.clear();
   inner.manual_exclude.clear();
+  inner.apply_cluster_decisions();
+  drop(inner);
+  schedule_autosave(&app);
+
+  Ok(DrillDownResult {
+    filters,
+    summary,
+    dedupe_reused,
+  })
+}
+
+#[tauri::command]
+pub async fn tag_refusals(app: AppHandle, state: State<'_, AppState>) -> Result<TagSummary, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let (store, filtered_ids, filters, field_map) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    (
+      store,
+      inner.filtered_ids.clone(),
+      inner.filters.clone(),
+      inner.field_map.clone(),
+    )
+  };
+
+  let (tagged_ids, scanned_count) = tauri::async_runtime::spawn_blocking(move || {
+    let detector = RefusalDetector::from_filters(&filters);
+    find_refusals(
+      &store,
+      filtered_ids.as_ref(),
+      &detector,
+      &field_map,
+      cancel.as_ref(),
+      |current, total| {
+        emit_progress(
+          &handle,
+          "tag",
+          current,
+          total,
+          &format!("Scanned {current} records"),
+        );
+      },
+    )
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  log_event(
+    &app,
+    &format!("Tagged {} records as refusals", tagged_ids.len()),
+  );
+
+  let summary = TagSummary {
+    tag: "refusal".to_string(),
+    scanned_count,
+    tagged_count: tagged_ids.len(),
+  };
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.tags.insert(summary.tag.clone(), tagged_ids);
+  drop(inner);
+  schedule_autosave(&app);
+
+  Ok(summary)
+}
+
+#[tauri::command]
+pub async fn tag_language_mismatches(
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<TagSummary, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let (store, filtered_ids, filters, field_map) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    (
+      store,
+      inner.filtered_ids.clone(),
+      inner.filters.clone(),
+      inner.field_map.clone(),
+    )
+  };
+
+  let (tagged_ids, scanned_count) = tauri::async_runtime::spawn_blocking(move || {
+    find_language_mismatches(
+      &store,
+      filtered_ids.as_ref(),
+      &filters,
+      &field_map,
+      cancel.as_ref(),
+      |current, total| {
+        emit_progress(
+          &handle,
+          "tag",
+          current,
+          total,
+          &format!("Scanned {current} records"),
+        );
+      },
+    )
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  log_event(
+    &app,
+    &format!("Tagged {} records as language mismatches", tagged_ids.len()),
+  );
+
+  let summary = TagSummary {
+    tag: "language_mismatch".to_string(),
+    scanned_count,
+    tagged_count: tagged_ids.len(),
+  };
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.tags.insert(summary.tag.clone(), tagged_ids);
+  drop(inner);
+  schedule_autosave(&app);
+
+  Ok(summary)
+}
+
+#[tauri::command]
+pub async fn detect_templates(
+  min_cluster: usize,
+  view: String,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<TemplateReport, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  materialize_view(&app, &view).await?;
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let (store, ids, field_map) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    (store, inner.view_ids(&view).to_set(), inner.field_map.clone())
+  };
+
+  let report = tauri::async_runtime::spawn_blocking(move || {
+    detect_templates_inner(
+      &store,
+      &ids,
+      &field_map,
+      min_cluster,
+      cancel.as_ref(),
+      |current, total| {
+        emit_progress(
+          &handle,
+          "templates",
+          current,
+          total,
+          &format!("Masked {current} instructions"),
+        );
+      },
+    )
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  log_event(
+    &app,
+    &format!(
+      "Found {} templates shared by {min_cluster}+ records in {view} ({} records)",
+      report.clusters.len(),
+      report.clustered_count
+    ),
+  );
+  Ok(report)
+}
+
+#[tauri::command]
+pub async fn cap_per_template(
+  n: usize,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<TemplateCapSummary, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let (store, filtered_ids, field_map) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    (store, inner.filtered_ids.clone(), inner.field_map.clone())
+  };
+
+  let record_count = store.record_count;
+  let (removed, summary) = tauri::async_runtime::spawn_blocking(move || {
+    cap_per_template_inner(
+      &store,
+      filtered_ids.as_ref(),
+      &field_map,
+      n,
+      cancel.as_ref(),
+      |current, total| {
+        emit_progress(
+          &handle,
+          "templates",
+          current,
+          total,
+          &format!("Masked {current} instructions"),
+        );
+      },
+    )
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  log_event(
+    &app,
+    &format!(
+      "Capped {} templates at {n} records each, {} records removed",
+      summary.capped_templates, summary.removed_count
+    ),
+  );
+
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  let filtered_ids = inner
+    .filtered_ids
+    .get_or_insert_with(|| IdSet::full(record_count));
+  *filtered_ids = filtered_ids.difference(&removed);
+  if let Some(selected_ids) = inner.selected_ids.as_mut() {
+    *selected_ids = selected_ids.difference(&removed);
+  }
+  if let Some(removed_ids) = inner.removed_ids.as_mut() {
+    *removed_ids = removed_ids.union(&removed);
+  }
+  drop(inner);
+  schedule_autosave(&app);
 
   Ok(summary)
 }
 
+/// Times store reads, parsing, simhash and each configured filter on a sample,
+/// for diagnosing slow filtering.
 #[tauri::command]
-pub fn list_categories(field: String, state: State<'_, AppState>) -> Result<Vec<CategoryCount>, String> {
+pub async fn run_benchmark(
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<BenchmarkReport, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let (store, filters, field_map, category_rules) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    (
+      store,
+      inner.filters.clone(),
+      inner.field_map.clone(),
+      inner.category_rules.clone(),
+    )
+  };
+
+  let report = tauri::async_runtime::spawn_blocking(move || {
+    run_benchmark_inner(
+      &store,
+      &filters,
+      &field_map,
+      &category_rules,
+      cancel.as_ref(),
+      |phase, current, total| {
+        let message = match phase {
+          "read" => "Measured read throughput".to_string(),
+          _ => format!("Benchmarked {current} records"),
+        };
+        emit_phase_progress(&handle, &BENCHMARK_PHASES, phase, current, total, &message);
+      },
+    )
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  let predicates = report
+    .predicates
+    .iter()
+    .map(|cost| {
+      format!(
+        "{} {}ms ({} rejected)",
+        cost.predicate, cost.millis, cost.rejected_count
+      )
+    })
+    .collect::<Vec<_>>();
+  log_event(
+    &app,
+    &format!(
+      "Benchmark on {} of {} records ({} oversized skipped): read {} MB at {:.0} MB/s; {}; \
+       predicates: {}",
+      report.sample_count,
+      report.record_count,
+      report.oversized_count,
+      report.read_bytes / (1024 * 1024),
+      report.read_mb_per_sec.unwrap_or(0.0),
+      format_timings(&report.timings),
+      if predicates.is_empty() {
+        "none".to_string()
+      } else {
+        predicates.join(", ")
+      }
+    ),
+  );
+
+  Ok(report)
+}
+
+/// Programming-language mix of a view, as the code-language categories see it.
+#[tauri::command]
+pub async fn detect_code_languages(
+  view: String,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<CodeLanguageReport, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  materialize_view(&app, &view).await?;
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let (store, ids, field_map) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    (store, inner.view_ids(&view).to_set(), inner.field_map.clone())
+  };
+
+  let report = tauri::async_runtime::spawn_blocking(move || {
+    code_language_stats(&store, &ids, &field_map, cancel.as_ref(), |current, total| {
+      emit_progress(
+        &handle,
+        "categories",
+        current,
+        total,
+        &format!("Scanned {current} records"),
+      );
+    })
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  log_event(
+    &app,
+    &format!(
+      "Detected {} code languages over {} records in {view}",
+      report.languages.len(),
+      report.scanned_count
+    ),
+  );
+  Ok(report)
+}
+
+/// Instruction languages and script mix of up to `sample_size` records of
+/// `view`. The same sample of the active dataset is only scanned once.
+#[tauri::command]
+pub async fn get_language_stats(
+  view: String,
+  sample_size: Option<usize>,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<LanguageStats, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  materialize_view(&app, &view).await?;
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let (store, sample, view_count, field_map) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    let ids = inner.view_ids(&view).to_set();
+    let sample = language_sample(&ids, sample_size.unwrap_or(DEFAULT_LANGUAGE_SAMPLE));
+    let cached = inner.language_stats.as_ref().filter(|cache| {
+      cache.instruction_field == inner.field_map.instruction && cache.sample == sample
+    });
+    if let Some(cache) = cached {
+      return Ok(LanguageStats {
+        view_count: ids.len(),
+        cached: true,
+        ..cache.stats.clone()
+      });
+    }
+    (store, sample, ids.len(), inner.field_map.clone())
+  };
+
+  let sample_clone = sample.clone();
+  let instruction_field = field_map.instruction.clone();
+  let stats = tauri::async_runtime::spawn_blocking(move || {
+    language_stats(
+      &store,
+      &sample_clone,
+      view_count,
+      &field_map,
+      cancel.as_ref(),
+      |current, total| {
+        emit_progress(
+          &handle,
+          "languages",
+          current,
+          total,
+          &format!("Scanned {current} records"),
+        );
+      },
+    )
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  log_event(
+    &app,
+    &format!(
+      "Detected {} languages over {} of {} records in {view}",
+      stats.languages.len(),
+      stats.scanned_count,
+      stats.view_count
+    ),
+  );
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.language_stats = Some(LanguageStatsCache {
+    instruction_field,
+    sample,
+    stats: stats.clone(),
+  });
+  Ok(stats)
+}
+
+/// Predicts what a keyword or regex filter would match from a sample of the
+/// view the filters last ran on, or of all records. Scope and case
+/// sensitivity default to the applied filters' length scope and keyword setting.
+#[tauri::command]
+pub async fn test_pattern(
+  pattern: String,
+  scope: Option<String>,
+  sample_size: Option<usize>,
+  case_sensitive: Option<bool>,
+  regex: Option<bool>,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<PatternTest, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  let view = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    inner
+      .filter_run
+      .as_ref()
+      .and_then(|run| run.base_view.clone())
+      .unwrap_or_else(|| "all".to_string())
+  };
+  materialize_view(&app, &view).await?;
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let (store, ids, spec, field_map) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    let spec = PatternSpec {
+      pattern,
+      scope: scope.unwrap_or_else(|| inner.filters.length_scope.clone()),
+      sample_size: sample_size.unwrap_or(DEFAULT_PATTERN_SAMPLE),
+      case_sensitive: case_sensitive.unwrap_or(inner.filters.keyword_case_sensitive),
+      regex: regex.unwrap_or(false),
+    };
+    (store, inner.view_ids(&view).to_set(), spec, inner.field_map.clone())
+  };
+
+  tauri::async_runtime::spawn_blocking(move || {
+    test_pattern_inner(&store, &ids, &spec, &field_map, cancel.as_ref(), |current, total| {
+      emit_progress(
+        &handle,
+        "pattern",
+        current,
+        total,
+        &format!("Tested {current} records"),
+      );
+    })
+  })
+  .await
+  .map_err(|e| e.to_string())?
+}
+
+/// Runs the PII detectors on a sample of a view and returns masked matches,
+/// so false positives can be reviewed before filtering. The detectors default
+/// to those enabled in the applied filters; with none enabled, all run.
+#[tauri::command]
+pub async fn scan_pii(
+  view: String,
+  pii: Option<PiiFilter>,
+  sample_size: Option<usize>,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<PiiScan, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  materialize_view(&app, &view).await?;
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let (store, ids, pii, field_map) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    let pii = pii.unwrap_or_else(|| inner.filters.pii.clone());
+    (store, inner.view_ids(&view).to_set(), pii, inner.field_map.clone())
+  };
+  let sample_size = sample_size.unwrap_or(DEFAULT_PII_SAMPLE);
+
+  let scan = tauri::async_runtime::spawn_blocking(move || {
+    scan_pii_inner(
+      &store,
+      &ids,
+      &pii,
+      &field_map,
+      sample_size,
+      cancel.as_ref(),
+      |current, total| {
+        emit_progress(&handle, "pii", current, total, &format!("Scanned {current} records"));
+      },
+    )
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  log_event(
+    &app,
+    &format!(
+      "Scanned {} records for personal data, {} with matches",
+      scan.scanned_count, scan.matched_count
+    ),
+  );
+  Ok(scan)
+}
+
+/// Category counts of `field`, or of the inferred categories when no field is
+/// given. The scan stops at `max_distinct` values and returns what it has.
+#[tauri::command]
+pub async fn list_categories(
+  field: Option<String>,
+  max_distinct: Option<usize>,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<CategoryList, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let max_distinct = max_distinct.unwrap_or(DEFAULT_MAX_DISTINCT_CATEGORIES);
+
+  let list = tauri::async_runtime::spawn_blocking(move || {
+    let state = handle.state::<AppState>();
+    list_active_categories(
+      &state.inner,
+      field.as_deref(),
+      max_distinct,
+      cancel.as_ref(),
+      |current, total| {
+        emit_progress(
+          &handle,
+          "categories",
+          current,
+          total,
+          &format!("Scanned {current} records"),
+        );
+      },
+    )
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  if list.truncated {
+    log_event(
+      &app,
+      &format!(
+        "Category scan stopped after {} records with {} distinct values",
+        list.scanned_count, list.distinct_count
+      ),
+    );
+  }
+  Ok(list)
+}
+
+#[tauri::command]
+pub fn get_category_rules(state: State<'_, AppState>) -> Result<CategoryRules, String> {
   let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
-  let store = inner
-    .dataset
-    .as_ref()
-    .ok_or_else(|| "No dataset loaded".to_string())?;
-  collect_categories(store, &field)
+  Ok(inner.category_rules.clone())
 }
 
+/// Replaces the category rules after checking that they compile.
 #[tauri::command]
-pub fn set_field_map(field_map: FieldMap, state: State<'_, AppState>) -> Result<(), String> {
+pub fn set_category_rules(
+  rules: CategoryRules,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<(), String> {
+  CategoryMatcher::new(&rules)?;
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.category_rules = rules;
+  drop(inner);
+  schedule_autosave(&app);
+  Ok(())
+}
+
+/// Sets the active dataset's field map, which is saved with the dataset.
+#[tauri::command]
+pub fn set_field_map(
+  field_map: FieldMap,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<(), String> {
   let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
   inner.field_map = field_map;
+  drop(inner);
+  schedule_autosave(&app);
   Ok(())
 }
+
+/// Checks the records of a view against validation rules without changing any state.
+#[tauri::command]
+pub async fn validate_rules(
+  rules: Vec<ValidationRule>,
+  view: String,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<ValidationReport, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  materialize_view(&app, &view).await?;
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let (store, ids) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    (store, inner.view_ids(&view).to_set())
+  };
+
+  let report = tauri::async_runtime::spawn_blocking(move || {
+    validate_rules_inner(&store, &ids, &rules, cancel.as_ref(), |current, total| {
+      emit_progress(
+        &handle,
+        "validate",
+        current,
+        total,
+        &format!("Validated {current} records"),
+      );
+    })
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  let broken = report.rules.iter().filter(|result| result.count > 0).count();
+  log_event(
+    &app,
+    &format!(
+      "Validated {} records against {} rules, {} broken, {} records in violation",
+      report.scanned_count,
+      report.rules.len(),
+      broken,
+      report.violating_count
+    ),
+  );
+  Ok(report)
+}
@@ -0,0 +1,195 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+
+use tauri::{AppHandle, State};
+
+use datalab_backend::hub::{
+  download_hub_file,
+  list_repo_files,
+  pick_hub_file,
+  push_to_hub as push_to_hub_inner,
+  HubError,
+  PushSpec,
+  HUB_ENDPOINT,
+};
+use datalab_backend::io::ingest_dataset;
+use datalab_backend::models::{DatasetSummary, ImportOptions, PushSummary};
+use datalab_backend::state::AppState;
+
+use crate::tauri_support::{
+  dataset_dir,
+  default_dataset_config,
+  download_dir,
+  emit_import_progress,
+  emit_phase_progress,
+  emit_progress,
+  log_event,
+  prepare_dataset_switch,
+  read_settings,
+  Phases,
+};
+
+const HUB_IMPORT_PHASES: Phases = Phases {
+  stage: "import",
+  names: &["download", "import"],
+};
+
+/// The explicit token if given, otherwise the one saved in settings.
+fn resolve_hub_token(app: &AppHandle, token: Option<String>) -> Result<Option<String>, String> {
+  if let Some(token) = token.filter(|token| !token.trim().is_empty()) {
+    return Ok(Some(token));
+  }
+  Ok(read_settings(app)?.and_then(|settings| settings.hub_token))
+}
+
+#[tauri::command]
+pub async fn import_from_hub(
+  repo_id: String,
+  filename_or_split: Option<String>,
+  token: Option<String>,
+  options: Option<ImportOptions>,
+  keep_download: Option<bool>,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<DatasetSummary, HubError> {
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let store_dir = dataset_dir(&app)?;
+  let downloads = download_dir(&app)?;
+  let token = resolve_hub_token(&app, token)?;
+  let options = options.unwrap_or_default();
+  let keep_download = keep_download.unwrap_or(false);
+  let repo = repo_id.trim().to_string();
+
+  let (mut dataset, import_report, file_path, download_path) =
+    tauri::async_runtime::spawn_blocking(move || {
+      let files = list_repo_files(&repo, token.as_deref())?;
+      let file = pick_hub_file(&files, filename_or_split.as_deref())?;
+      let download_path = download_hub_file(
+        &repo,
+        &file,
+        token.as_deref(),
+        &downloads,
+        cancel.as_ref(),
+        |bytes, total| {
+          emit_phase_progress(
+            &handle,
+            &HUB_IMPORT_PHASES,
+            "download",
+            bytes as usize,
+            total as usize,
+            &format!("Downloaded {} MB", bytes / (1024 * 1024)),
+          );
+        },
+      )?;
+      let ingested =
+        ingest_dataset(&download_path, &store_dir, &options, cancel.as_ref(), |count, source| {
+          let message = format!("Imported {count} records");
+          emit_import_progress(&handle, Some(&HUB_IMPORT_PHASES), count, source, &message);
+        });
+      if !keep_download {
+        let _ = fs::remove_file(&download_path);
+      }
+      let (dataset, report) = ingested?;
+      Ok::<_, HubError>((dataset, report, file.path, download_path))
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+  log_event(
+    &app,
+    &format!("Imported dataset from Hub repository {repo_id} ({file_path})"),
+  );
+  emit_phase_progress(
+    &app,
+    &HUB_IMPORT_PHASES,
+    "import",
+    dataset.record_count,
+    dataset.record_count,
+    "Import complete",
+  );
+
+  if keep_download {
+    log_event(
+      &app,
+      &format!("Kept Hub download at {}", download_path.display()),
+    );
+  } else {
+    // The download is gone, so point the dataset at where it came from; a
+    // canceled import can no longer continue from it.
+    dataset.resume_offset = None;
+    dataset.source_path = PathBuf::from(format!(
+      "{HUB_ENDPOINT}/datasets/{}/blob/main/{file_path}",
+      repo_id.trim()
+    ));
+  }
+  let summary = DatasetSummary {
+    import_report,
+    ..dataset.summary()
+  };
+
+  let config = prepare_dataset_switch(&app, &dataset, default_dataset_config(&app));
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.activate_dataset(dataset);
+  inner.apply_config(config);
+
+  Ok(summary)
+}
+
+#[tauri::command]
+pub async fn push_to_hub(
+  repo_id: String,
+  folder: String,
+  private: Option<bool>,
+  token: Option<String>,
+  commit_message: Option<String>,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<PushSummary, HubError> {
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  // Without a token the push is refused as unauthorized.
+  let token = resolve_hub_token(&app, token)?.unwrap_or_default();
+  let spec = PushSpec {
+    repo_id: repo_id.trim().to_string(),
+    private: private.unwrap_or(true),
+    commit_message: commit_message
+      .filter(|message| !message.trim().is_empty())
+      .unwrap_or_else(|| "Upload dataset from DataLab".to_string()),
+    folder: PathBuf::from(&folder),
+  };
+
+  let result = tauri::async_runtime::spawn_blocking(move || {
+    push_to_hub_inner(&spec, &token, cancel.as_ref(), |progress| {
+      emit_progress(
+        &handle,
+        "upload",
+        progress.file_index + 1,
+        progress.file_count,
+        &format!(
+          "Uploading {} ({} / {} MB)",
+          progress.file,
+          progress.bytes_sent / (1024 * 1024),
+          progress.bytes_total / (1024 * 1024)
+        ),
+      );
+    })
+  })
+  .await
+  .map_err(|e| e.to_string())?;
+
+  match &result {
+    Ok(summary) => log_event(
+      &app,
+      &format!(
+        "Pushed {} files from {folder} to Hub repository {repo_id}",
+        summary.uploaded_files.len()
+      ),
+    ),
+    Err(err) => log_event(&app, &format!("Push to Hub repository {repo_id} failed: {err}")),
+  }
+  result
+}
@@ -1,4 +1,13 @@
+pub mod clusters;
+pub mod compare;
 pub mod dataset;
+pub mod delta;
 pub mod distill;
 pub mod filters;
+pub mod hub;
+pub mod remote;
+pub mod sample;
+pub mod session;
 pub mod settings;
+pub mod transform;
+pub mod views;
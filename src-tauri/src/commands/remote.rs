@@ -0,0 +1,103 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+
+use tauri::{AppHandle, State};
+
+use datalab_backend::io::ingest_dataset;
+use datalab_backend::models::{DatasetSummary, ImportOptions};
+use datalab_backend::remote::{download_url, validate_url};
+use datalab_backend::state::AppState;
+
+use crate::tauri_support::{
+  dataset_dir,
+  default_dataset_config,
+  download_dir,
+  emit_import_progress,
+  emit_phase_progress,
+  log_event,
+  prepare_dataset_switch,
+  Phases,
+};
+
+const URL_IMPORT_PHASES: Phases = Phases {
+  stage: "import",
+  names: &["download", "import"],
+};
+
+/// Downloads a remote JSONL, JSON or CSV file into the app data dir and
+/// imports it. The dataset's source path is the URL.
+#[tauri::command]
+pub async fn import_dataset_url(
+  url: String,
+  options: Option<ImportOptions>,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<DatasetSummary, String> {
+  let url = url.trim().to_string();
+  validate_url(&url)?;
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let store_dir = dataset_dir(&app)?;
+  let downloads = download_dir(&app)?;
+  let options = options.unwrap_or_default();
+  let source = url.clone();
+
+  let (mut dataset, import_report) = tauri::async_runtime::spawn_blocking(move || {
+    let download_path = download_url(&source, &downloads, cancel.as_ref(), |bytes, total| {
+      let message = if total > 0 {
+        format!(
+          "Downloaded {} / {} MB",
+          bytes / (1024 * 1024),
+          total / (1024 * 1024)
+        )
+      } else {
+        format!("Downloaded {} MB", bytes / (1024 * 1024))
+      };
+      emit_phase_progress(
+        &handle,
+        &URL_IMPORT_PHASES,
+        "download",
+        bytes as usize,
+        total as usize,
+        &message,
+      );
+    })?;
+    let ingested =
+      ingest_dataset(&download_path, &store_dir, &options, cancel.as_ref(), |count, source| {
+        let message = format!("Imported {count} records");
+        emit_import_progress(&handle, Some(&URL_IMPORT_PHASES), count, source, &message);
+      });
+    let _ = fs::remove_file(&download_path);
+    ingested
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  log_event(&app, &format!("Imported dataset from {url}"));
+  emit_phase_progress(
+    &app,
+    &URL_IMPORT_PHASES,
+    "import",
+    dataset.record_count,
+    dataset.record_count,
+    "Import complete",
+  );
+
+  // The download is gone, so point the dataset at where it came from; a
+  // canceled import can no longer continue from it.
+  dataset.source_path = PathBuf::from(&url);
+  dataset.resume_offset = None;
+  let summary = DatasetSummary {
+    import_report,
+    ..dataset.summary()
+  };
+
+  let config = prepare_dataset_switch(&app, &dataset, default_dataset_config(&app));
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.activate_dataset(dataset);
+  inner.apply_config(config);
+
+  Ok(summary)
+}
@@ -0,0 +1,270 @@
+use std::sync::atomic::Ordering;
+
+use tauri::{AppHandle, State};
+
+use datalab_backend::distill::preview_distillation as preview_distillation_inner;
+use datalab_backend::estimate::{
+  estimate_pipeline as estimate_pipeline_inner,
+  DEFAULT_ESTIMATE_SAMPLE,
+};
+use datalab_backend::filters::{apply_filters_tracked, DedupeKey};
+use datalab_backend::models::{
+  PipelineEstimate,
+  PipelineSpec,
+  PromoteSummary,
+  SampleSpec,
+  SampleSummary,
+};
+use datalab_backend::sample::{sample_ids, sample_view_name, validate_sample};
+use datalab_backend::state::{AppState, FilterRun, SampleView};
+use datalab_backend::timing::format_timings;
+
+use crate::tauri_support::{emit_phase_progress, log_event, schedule_autosave, Phases};
+
+const PROMOTE_FILTER_PHASES: Phases = Phases {
+  stage: "promote",
+  names: &["filter"],
+};
+const PROMOTE_DISTILL_PHASES: Phases = Phases {
+  stage: "promote",
+  names: &["filter", "meta", "select"],
+};
+const ESTIMATE_PHASES: Phases = Phases {
+  stage: "estimate",
+  names: &["filter", "meta", "select", "profile"],
+};
+
+#[tauri::command]
+pub fn create_sample_view(
+  percent: f32,
+  seed: u64,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<SampleSummary, String> {
+  let spec = SampleSpec { percent, seed };
+  validate_sample(&spec)?;
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  let total_count = inner
+    .dataset
+    .as_ref()
+    .map(|store| store.record_count)
+    .ok_or_else(|| "No dataset loaded".to_string())?;
+  let ids = sample_ids(total_count, &spec);
+  let view = sample_view_name(&spec);
+  let summary = SampleSummary {
+    view: view.clone(),
+    percent,
+    seed,
+    sample_count: ids.len(),
+    total_count,
+  };
+  inner.samples.insert(view, SampleView { spec, ids });
+  drop(inner);
+  schedule_autosave(&app);
+
+  log_event(
+    &app,
+    &format!(
+      "Created {} with {} of {} records",
+      summary.view, summary.sample_count, summary.total_count
+    ),
+  );
+  Ok(summary)
+}
+
+/// Re-runs the active filter config, then the active distillation config if a
+/// preview exists, on the full dataset.
+#[tauri::command]
+pub async fn promote_to_full(
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<PromoteSummary, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let (store, filters, distill_config, field_map, category_rules, promoted_from) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    let distill_config = inner
+      .selected_ids
+      .is_some()
+      .then(|| inner.distill_config.clone());
+    let promoted_from = inner
+      .selected_sample
+      .clone()
+      .or_else(|| inner.filtered_sample.clone());
+    (
+      store,
+      inner.filters.clone(),
+      distill_config,
+      inner.field_map.clone(),
+      inner.category_rules.clone(),
+      promoted_from,
+    )
+  };
+
+  let phases = if distill_config.is_some() {
+    &PROMOTE_DISTILL_PHASES
+  } else {
+    &PROMOTE_FILTER_PHASES
+  };
+  let dedupe = DedupeKey::new(&filters, &field_map);
+  let promoted = tauri::async_runtime::spawn_blocking(move || {
+    let (filtered_ids, filter_summary, duplicates) = apply_filters_tracked(
+      &store,
+      None,
+      &filters,
+      &field_map,
+      &category_rules,
+      cancel.as_ref(),
+      |current, total| {
+        let message = format!("Filtered {current} records");
+        emit_phase_progress(&handle, phases, "filter", current, total, &message);
+      },
+    )?;
+    let distilled = match &distill_config {
+      Some(config) => Some(preview_distillation_inner(
+        &store,
+        Some(&filtered_ids),
+        config,
+        &field_map,
+        &category_rules,
+        cancel.as_ref(),
+        |phase, current, total| {
+          let message = match phase {
+            "select" => format!("Selecting from {total} records"),
+            _ => format!("Prepared {current} records"),
+          };
+          emit_phase_progress(&handle, phases, phase, current, total, &message);
+        },
+      )?),
+      None => None,
+    };
+    Ok::<_, String>((filtered_ids, filter_summary, duplicates, distilled))
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+  let (filtered_ids, filter_summary, duplicates, distilled) = promoted;
+
+  log_event(
+    &app,
+    &format!(
+      "Promoted configs{} to the full dataset, {} records retained ({})",
+      promoted_from
+        .as_deref()
+        .map(|view| format!(" from {view}"))
+        .unwrap_or_default(),
+      filter_summary.filtered_count,
+      format_timings(&filter_summary.timings)
+    ),
+  );
+
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.filtered_ids = Some(filtered_ids);
+  inner.filtered_sample = None;
+  inner.filter_run = Some(FilterRun {
+    base_view: None,
+    dedupe,
+    duplicates,
+  });
+  inner.selected_sample = None;
+  inner.manual_include.clear();
+  inner.manual_exclude.clear();
+  let distill_summary = match distilled {
+    Some((selected_ids, removed_ids, summary)) => {
+      log_event(
+        &app,
+        &format!(
+          "Promoted distillation, {} selected ({})",
+          summary.selected_count,
+          format_timings(&summary.timings)
+        ),
+      );
+      inner.selected_ids = Some(selected_ids);
+      inner.removed_ids = Some(removed_ids);
+      inner.distill_score_ranges = summary.score_ranges.clone();
+      Some(summary)
+    }
+    None => {
+      inner.selected_ids = None;
+      inner.removed_ids = None;
+      None
+    }
+  };
+  inner.apply_cluster_decisions();
+  drop(inner);
+  schedule_autosave(&app);
+
+  Ok(PromoteSummary {
+    promoted_from,
+    filter: filter_summary,
+    distill: distill_summary,
+  })
+}
+
+/// Dry run of `spec`, or of the configs `promote_to_full` would apply, on a
+/// seeded sample, extrapolated to the full dataset. Nothing in the state changes.
+#[tauri::command]
+pub async fn estimate_pipeline(
+  spec: Option<PipelineSpec>,
+  sample_size: Option<usize>,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<PipelineEstimate, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let (store, spec, field_map, category_rules) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let store = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    let spec = spec.unwrap_or_else(|| PipelineSpec {
+      filters: inner.filters.clone(),
+      distill: inner
+        .selected_ids
+        .is_some()
+        .then(|| inner.distill_config.clone()),
+    });
+    (store, spec, inner.field_map.clone(), inner.category_rules.clone())
+  };
+  let sample_size = sample_size.unwrap_or(DEFAULT_ESTIMATE_SAMPLE);
+
+  let estimate = tauri::async_runtime::spawn_blocking(move || {
+    estimate_pipeline_inner(
+      &store,
+      &spec,
+      &field_map,
+      &category_rules,
+      sample_size,
+      cancel.as_ref(),
+      |phase, current, total| {
+        let message = match phase {
+          "select" => format!("Selecting from {total} records"),
+          _ => format!("Estimated from {current} records"),
+        };
+        emit_phase_progress(&handle, &ESTIMATE_PHASES, phase, current, total, &message);
+      },
+    )
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  let retained = estimate
+    .stages
+    .last()
+    .map(|stage| stage.estimated_output)
+    .unwrap_or_default();
+  log_event(
+    &app,
+    &format!(
+      "Estimated pipeline from {} of {} records: about {retained} records retained",
+      estimate.sample_count, estimate.total_count
+    ),
+  );
+  Ok(estimate)
+}
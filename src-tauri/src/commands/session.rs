@@ -0,0 +1,38 @@
+use tauri::State;
+
+use datalab_backend::models::DerivedStateInfo;
+use datalab_backend::sidecar::load_derived_state;
+use datalab_backend::state::AppState;
+
+#[tauri::command]
+pub fn get_autosave_info(state: State<'_, AppState>) -> Result<Option<DerivedStateInfo>, String> {
+  let store = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?
+  };
+  Ok(load_derived_state(&store)?.map(|saved| saved.info()))
+}
+
+#[tauri::command]
+pub fn restore_autosave(state: State<'_, AppState>) -> Result<DerivedStateInfo, String> {
+  let store = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?
+  };
+  let saved = load_derived_state(&store)?
+    .ok_or_else(|| "No saved state for this dataset".to_string())?;
+  let info = saved.info();
+
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  if inner.dataset.as_ref().map(|active| active.id.as_str()) != Some(store.id.as_str()) {
+    return Err("Active dataset changed during restore".to_string());
+  }
+  saved.apply_to(&mut inner);
+  Ok(info)
+}
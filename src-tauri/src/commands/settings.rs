@@ -4,36 +4,108 @@ use std::sync::atomic::Ordering;
 
 use tauri::{AppHandle, State};
 
-use datalab_backend::models::Settings;
+use datalab_backend::appdata::write_settings_file;
+use datalab_backend::models::{RestoredSession, Settings, StartupReport};
 use datalab_backend::state::AppState;
 
-use crate::tauri_support::{log_file_path, settings_path};
+use crate::tauri_support::{log_file_path, read_settings, settings_path};
+use crate::window_status::{clear_task_progress, refresh_window_title, set_badge};
 
 #[tauri::command]
-pub fn cancel_task(state: State<'_, AppState>) -> Result<(), String> {
+pub fn cancel_task(app: AppHandle, state: State<'_, AppState>) -> Result<(), String> {
   state.cancel.store(true, Ordering::SeqCst);
+  clear_task_progress(&app);
   Ok(())
 }
 
+/// Called by the UI after every long operation: clears the taskbar progress
+/// and retitles the window for the dataset and selection it left.
+#[tauri::command]
+pub fn finish_task(app: AppHandle) {
+  clear_task_progress(&app);
+  refresh_window_title(&app);
+}
+
+/// Number on the dock icon where the platform has one; `None` clears it.
+#[tauri::command]
+pub fn set_window_badge(count: Option<i64>, app: AppHandle) {
+  set_badge(&app, count);
+}
+
 #[tauri::command]
 pub fn load_settings(app: AppHandle) -> Result<Option<Settings>, String> {
-  let settings_path = settings_path(&app)?;
-  if !settings_path.exists() {
-    return Ok(None);
-  }
-  let content = fs::read_to_string(settings_path).map_err(|e| e.to_string())?;
-  let settings = serde_json::from_str(&content).map_err(|e| e.to_string())?;
-  Ok(Some(settings))
+  read_settings(&app)
 }
 
 #[tauri::command]
-pub fn save_settings(app: AppHandle, settings: Settings) -> Result<(), String> {
-  let settings_path = settings_path(&app)?;
-  let content = serde_json::to_string_pretty(&settings).map_err(|e| e.to_string())?;
-  fs::write(settings_path, content).map_err(|e| e.to_string())?;
+pub fn save_settings(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  mut settings: Settings,
+) -> Result<(), String> {
+  // The UI round-trips neither the token nor the preview limits, review
+  // weights, refusal phrases and startup options, so keep the saved ones
+  // unless they are replaced.
+  let saved = if settings.hub_token.is_none()
+    || settings.preview_limits.is_none()
+    || settings.review_weights.is_none()
+    || settings.refusal_phrases.is_none()
+    || settings.restore_last_session.is_none()
+    || settings.default_view.is_none()
+    || settings.default_page_size.is_none()
+  {
+    read_settings(&app)?
+  } else {
+    None
+  };
+  match settings.hub_token.as_deref() {
+    None => settings.hub_token = saved.as_ref().and_then(|saved| saved.hub_token.clone()),
+    Some("") => settings.hub_token = None,
+    Some(_) => {}
+  }
+  if settings.preview_limits.is_none() {
+    settings.preview_limits = saved.as_ref().and_then(|saved| saved.preview_limits.clone());
+  }
+  if settings.review_weights.is_none() {
+    settings.review_weights = saved.as_ref().and_then(|saved| saved.review_weights.clone());
+  }
+  if settings.refusal_phrases.is_none() {
+    settings.refusal_phrases = saved.as_ref().and_then(|saved| saved.refusal_phrases.clone());
+  }
+  if settings.restore_last_session.is_none() {
+    settings.restore_last_session = saved.as_ref().and_then(|saved| saved.restore_last_session);
+  }
+  if settings.default_view.is_none() {
+    settings.default_view = saved.as_ref().and_then(|saved| saved.default_view.clone());
+  }
+  if settings.default_page_size.is_none() {
+    settings.default_page_size = saved.and_then(|saved| saved.default_page_size);
+  }
+  write_settings_file(&settings_path(&app)?, &settings)?;
+  state.set_preview_limits(settings.preview_limits);
   Ok(())
 }
 
+/// The dataset and preview reopened at startup, if the last session was restored.
+#[tauri::command]
+pub fn get_restored_session(state: State<'_, AppState>) -> Result<Option<RestoredSession>, String> {
+  let restored = state
+    .restored_session
+    .lock()
+    .map_err(|_| "State lock error".to_string())?;
+  Ok(restored.clone())
+}
+
+/// What the startup check of the app data directory repaired.
+#[tauri::command]
+pub fn get_startup_report(state: State<'_, AppState>) -> Result<StartupReport, String> {
+  let report = state
+    .startup_report
+    .lock()
+    .map_err(|_| "State lock error".to_string())?;
+  Ok(report.clone())
+}
+
 #[tauri::command]
 pub fn get_logs(app: AppHandle, limit: usize) -> Result<Vec<String>, String> {
   let log_path = log_file_path(&app)?;
@@ -0,0 +1,654 @@
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+use tauri::{AppHandle, State};
+
+use datalab_backend::field_matrix::{
+  describe_schema_diff,
+  schema_diff,
+  DEFAULT_SCHEMA_SAMPLE,
+  SCHEMA_FILL_RATE_THRESHOLD,
+};
+use datalab_backend::io::{discard_store, ingest_dataset};
+use datalab_backend::models::{
+  AppendSummary,
+  ChunkSummary,
+  DatasetSummary,
+  ImportOptions,
+  JoinSummary,
+  MaterializeSummary,
+  PruneSummary,
+  TruncateOptions,
+  TruncateSummary,
+};
+use datalab_backend::paths::normalize_path;
+use datalab_backend::state::AppState;
+use datalab_backend::store_index::save_store_index;
+use datalab_backend::transform::{
+  append_store,
+  chunk_field as chunk_field_inner,
+  explode_field as explode_field_inner,
+  join_metadata as join_metadata_inner,
+  prune_fields as prune_fields_inner,
+  pruned_mapped_fields,
+  truncate_field as truncate_field_inner,
+  ChunkSpec,
+  ChunkUnit,
+  JoinSpec,
+  TruncateSpec,
+  PARENT_ID_FIELD,
+};
+
+use crate::tauri_support::{
+  dataset_dir,
+  emit_import_progress,
+  emit_phase_progress,
+  emit_progress,
+  log_event,
+  prepare_dataset_switch,
+  Phases,
+};
+
+const APPEND_PHASES: Phases = Phases {
+  stage: "append",
+  names: &["import", "base", "incoming", "write"],
+};
+
+const CONTINUE_PHASES: Phases = Phases {
+  stage: "import",
+  names: &["import", "write"],
+};
+
+/// Cuts the text in a field to a token budget, at a sentence end where the
+/// boundary allows, into a derived dataset.
+#[tauri::command]
+pub async fn truncate_field(
+  options: TruncateOptions,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<TruncateSummary, String> {
+  let spec = TruncateSpec::from_options(&options)?;
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let store_dir = dataset_dir(&app)?;
+  let parent = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?
+  };
+  let input_count = parent.record_count;
+
+  let (derived, counts) = tauri::async_runtime::spawn_blocking(move || {
+    truncate_field_inner(&parent, &store_dir, &spec, cancel.as_ref(), |current, total| {
+      emit_progress(
+        &handle,
+        "truncate",
+        current,
+        total,
+        &format!("Truncated {current} records"),
+      );
+    })
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  log_event(
+    &app,
+    &format!(
+      "Truncated field {} to {} tokens at {} boundaries: {} of {input_count} records, \
+       {} tokens saved",
+      options.field, options.max_tokens, options.boundary, counts.truncated, counts.tokens_saved
+    ),
+  );
+
+  let summary = TruncateSummary {
+    dataset: derived.summary(),
+    input_count,
+    truncated_count: counts.truncated,
+    tokens_saved: counts.tokens_saved,
+  };
+  let parent_config = state
+    .inner
+    .read()
+    .map_err(|_| "State lock error".to_string())?
+    .config();
+  let config = prepare_dataset_switch(&app, &derived, parent_config);
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.activate_dataset(derived);
+  inner.apply_config(config);
+
+  Ok(summary)
+}
+
+/// Appends the records of an open dataset, or of a file imported for the
+/// purpose, to the active dataset as a new derived store. When their fields
+/// differ from the active dataset's, only the schema diff is returned unless
+/// `accept_schema_changes` is set; an imported file stays open so the append
+/// can be retried by its id.
+#[tauri::command]
+pub async fn append_dataset(
+  dataset_id: Option<String>,
+  path: Option<String>,
+  options: Option<ImportOptions>,
+  accept_schema_changes: bool,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<AppendSummary, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let store_dir = dataset_dir(&app)?;
+  let (base, open_incoming) = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let base = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    let open_incoming = match (&dataset_id, &path) {
+      (Some(id), None) if *id == base.id => {
+        return Err("Cannot append a dataset to itself".to_string())
+      }
+      (Some(id), None) => Some(
+        inner
+          .datasets
+          .get(id)
+          .cloned()
+          .ok_or_else(|| format!("Dataset {id} is not open"))?,
+      ),
+      (None, Some(_)) => None,
+      _ => return Err("Give either an open dataset or a file to append".to_string()),
+    };
+    (base, open_incoming)
+  };
+  let imported = open_incoming.is_none();
+  let source = path
+    .clone()
+    .unwrap_or_else(|| format!("dataset {}", dataset_id.unwrap_or_default()));
+  let options = options.unwrap_or_default();
+
+  let (incoming, diff, combined) = tauri::async_runtime::spawn_blocking(move || {
+    let progress = |phase: &'static str, current: usize, total: usize| {
+      let message = match phase {
+        "write" => format!("Appended {current} records"),
+        _ => format!("Profiled {current} records"),
+      };
+      emit_phase_progress(&handle, &APPEND_PHASES, phase, current, total, &message);
+    };
+    let incoming = match open_incoming {
+      Some(store) => store,
+      None => {
+        let path = normalize_path(Path::new(path.as_deref().unwrap_or_default()));
+        let (store, _) =
+          ingest_dataset(&path, &store_dir, &options, cancel.as_ref(), |count, source| {
+            let message = format!("Imported {count} records");
+            emit_import_progress(&handle, Some(&APPEND_PHASES), count, source, &message);
+          })?;
+        // Appending part of a file is not what was asked for.
+        if store.truncated {
+          discard_store(&store);
+          return Err("Import canceled".to_string());
+        }
+        store
+      }
+    };
+    let diff = schema_diff(
+      &base,
+      &incoming,
+      DEFAULT_SCHEMA_SAMPLE,
+      SCHEMA_FILL_RATE_THRESHOLD,
+      cancel.as_ref(),
+      progress,
+    )?;
+    if !diff.is_empty() && !accept_schema_changes {
+      return Ok::<_, String>((incoming, diff, None));
+    }
+    let combined = append_store(&base, &incoming, &store_dir, cancel.as_ref(), |current, total| {
+      progress("write", current, total)
+    })?;
+    Ok((incoming, diff, Some(combined)))
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  if imported {
+    let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+    inner.datasets.insert(incoming.id.clone(), incoming.clone());
+  }
+  let Some(combined) = combined else {
+    log_event(
+      &app,
+      &format!(
+        "Held back appending {source}: schema changes need accepting ({})",
+        describe_schema_diff(&diff)
+      ),
+    );
+    return Ok(AppendSummary {
+      schema_diff: diff,
+      appended: false,
+      incoming: incoming.summary(),
+      dataset: None,
+    });
+  };
+
+  log_event(
+    &app,
+    &format!(
+      "Appended {} records from {source}, accepted {}",
+      incoming.record_count,
+      describe_schema_diff(&diff)
+    ),
+  );
+  let summary = AppendSummary {
+    schema_diff: diff,
+    appended: true,
+    incoming: incoming.summary(),
+    dataset: Some(combined.summary()),
+  };
+  let parent_config = state
+    .inner
+    .read()
+    .map_err(|_| "State lock error".to_string())?
+    .config();
+  let config = prepare_dataset_switch(&app, &combined, parent_config);
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.activate_dataset(combined);
+  inner.apply_config(config);
+
+  Ok(summary)
+}
+
+/// Continues a canceled import of the active dataset from where it stopped,
+/// appending the rest of its source file into a new derived store. Options
+/// other than the format apply to the rest of the file.
+#[tauri::command]
+pub async fn continue_import(
+  options: Option<ImportOptions>,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<DatasetSummary, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let store_dir = dataset_dir(&app)?;
+  let base = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?
+  };
+  if !base.truncated {
+    return Err("The active dataset was imported completely".to_string());
+  }
+  let Some(resume_offset) = base.resume_offset else {
+    return Err("This import cannot be continued; import the file again".to_string());
+  };
+  if !base.source_path.is_file() {
+    return Err(format!("{} is no longer available", base.source_path.display()));
+  }
+  let options = ImportOptions {
+    format: Some(base.format.clone()),
+    resume_offset: Some(resume_offset),
+    ..options.unwrap_or_default()
+  };
+  let source = base.source_path.display().to_string();
+
+  let combined = tauri::async_runtime::spawn_blocking(move || {
+    let (incoming, _) =
+      ingest_dataset(&base.source_path, &store_dir, &options, cancel.as_ref(), |count, source| {
+        let message = format!("Imported {count} more records");
+        emit_import_progress(&handle, Some(&CONTINUE_PHASES), count, source, &message);
+      })?;
+    let combined = append_store(&base, &incoming, &store_dir, cancel.as_ref(), |current, total| {
+      let message = format!("Appended {current} records");
+      emit_phase_progress(&handle, &CONTINUE_PHASES, "write", current, total, &message);
+    });
+    // The combined store holds the continued records; the intermediate one is never opened.
+    discard_store(&incoming);
+    let mut combined = combined?;
+    combined.truncated = incoming.truncated;
+    combined.resume_offset = incoming.resume_offset;
+    save_store_index(&combined)?;
+    Ok::<_, String>(combined)
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  if combined.truncated {
+    log_event(
+      &app,
+      &format!(
+        "Continued import of {source} was canceled again; {} records so far",
+        combined.record_count
+      ),
+    );
+  } else {
+    log_event(
+      &app,
+      &format!(
+        "Finished the import of {source}: {} records",
+        combined.record_count
+      ),
+    );
+  }
+  let summary = combined.summary();
+  let parent_config = state
+    .inner
+    .read()
+    .map_err(|_| "State lock error".to_string())?
+    .config();
+  let config = prepare_dataset_switch(&app, &combined, parent_config);
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.activate_dataset(combined);
+  inner.apply_config(config);
+
+  Ok(summary)
+}
+
+#[tauri::command]
+pub async fn explode_field(
+  field: String,
+  nest_key: Option<String>,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<MaterializeSummary, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let store_dir = dataset_dir(&app)?;
+  let parent = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?
+  };
+  let input_count = parent.record_count;
+  let field_clone = field.clone();
+
+  let (derived, exploded) = tauri::async_runtime::spawn_blocking(move || {
+    explode_field_inner(
+      &parent,
+      &store_dir,
+      &field_clone,
+      nest_key.as_deref(),
+      cancel.as_ref(),
+      |current, total| {
+        emit_progress(
+          &handle,
+          "explode",
+          current,
+          total,
+          &format!("Exploded {current} records"),
+        );
+      },
+    )
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  log_event(
+    &app,
+    &format!(
+      "Exploded field {field}: {exploded} records into {} rows",
+      derived.record_count
+    ),
+  );
+
+  let summary = MaterializeSummary {
+    dataset: derived.summary(),
+    input_count,
+    output_count: derived.record_count,
+    affected_count: exploded,
+  };
+  let parent_config = state
+    .inner
+    .read()
+    .map_err(|_| "State lock error".to_string())?
+    .config();
+  let config = prepare_dataset_switch(&app, &derived, parent_config);
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.activate_dataset(derived);
+  inner.apply_config(config);
+
+  Ok(summary)
+}
+
+#[tauri::command]
+pub async fn join_metadata(
+  path: String,
+  left_key: String,
+  right_key: String,
+  fields: Vec<String>,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<JoinSummary, String> {
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let store_dir = dataset_dir(&app)?;
+  let parent = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?
+  };
+  let input_count = parent.record_count;
+  let spec = JoinSpec {
+    aux_path: normalize_path(Path::new(&path)),
+    left_key,
+    right_key,
+    fields,
+  };
+
+  let (derived, counts) = tauri::async_runtime::spawn_blocking(move || {
+    join_metadata_inner(&parent, &store_dir, &spec, cancel.as_ref(), |current, total| {
+      emit_progress(
+        &handle,
+        "join",
+        current,
+        total,
+        &format!("Joined {current} records"),
+      );
+    })
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  log_event(
+    &app,
+    &format!(
+      "Joined metadata from {path}: {} matched, {} missed, {} duplicate keys",
+      counts.matched, counts.missed, counts.duplicate_right_keys
+    ),
+  );
+
+  let summary = JoinSummary {
+    dataset: derived.summary(),
+    input_count,
+    right_row_count: counts.right_rows,
+    matched_count: counts.matched,
+    missed_count: counts.missed,
+    duplicate_right_keys: counts.duplicate_right_keys,
+  };
+  let parent_config = state
+    .inner
+    .read()
+    .map_err(|_| "State lock error".to_string())?
+    .config();
+  let config = prepare_dataset_switch(&app, &derived, parent_config);
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.activate_dataset(derived);
+  inner.apply_config(config);
+
+  Ok(summary)
+}
+
+/// Materializes a store with only the `keep` fields. Refuses to drop fields the
+/// field map uses unless `force` is set; the parent store stays open.
+#[tauri::command]
+pub async fn prune_fields(
+  keep: Vec<String>,
+  force: Option<bool>,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<PruneSummary, String> {
+  if keep.is_empty() {
+    return Err("Select at least one field to keep".to_string());
+  }
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let store_dir = dataset_dir(&app)?;
+  let parent = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    let parent = inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?;
+    let mapped = pruned_mapped_fields(&inner.field_map, &keep);
+    if !mapped.is_empty() && !force.unwrap_or(false) {
+      return Err(format!(
+        "Pruning would drop mapped fields: {}",
+        mapped.join(", ")
+      ));
+    }
+    parent
+  };
+  let input_count = parent.record_count;
+  let dropped_fields = parent
+    .fields
+    .iter()
+    .filter(|field| !keep.contains(field) && field.as_str() != PARENT_ID_FIELD)
+    .cloned()
+    .collect::<Vec<_>>();
+  let size_before = fs::metadata(&parent.store_path)
+    .map(|meta| meta.len())
+    .map_err(|e| e.to_string())?;
+
+  let derived = tauri::async_runtime::spawn_blocking(move || {
+    prune_fields_inner(&parent, &store_dir, &keep, cancel.as_ref(), |current, total| {
+      emit_progress(
+        &handle,
+        "prune",
+        current,
+        total,
+        &format!("Pruned {current} records"),
+      );
+    })
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+  let size_after = fs::metadata(&derived.store_path)
+    .map(|meta| meta.len())
+    .map_err(|e| e.to_string())?;
+
+  log_event(
+    &app,
+    &format!(
+      "Pruned {} fields, store shrank from {} to {} MB",
+      dropped_fields.len(),
+      size_before / (1024 * 1024),
+      size_after / (1024 * 1024)
+    ),
+  );
+
+  let summary = PruneSummary {
+    dataset: derived.summary(),
+    input_count,
+    dropped_fields,
+    size_before,
+    size_after,
+  };
+  let parent_config = state
+    .inner
+    .read()
+    .map_err(|_| "State lock error".to_string())?
+    .config();
+  let config = prepare_dataset_switch(&app, &derived, parent_config);
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.activate_dataset(derived);
+  inner.apply_config(config);
+
+  Ok(summary)
+}
+
+/// Splits the text in `field` into overlapping windows of `max_len` chars,
+/// words or tokens, one derived record per window.
+#[tauri::command]
+pub async fn chunk_field(
+  field: String,
+  max_len: usize,
+  overlap: usize,
+  unit: String,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<ChunkSummary, String> {
+  let spec = ChunkSpec {
+    field,
+    max_len,
+    overlap,
+    unit: ChunkUnit::parse(&unit)?,
+  };
+  spec.validate()?;
+  state.cancel.store(false, Ordering::SeqCst);
+  let cancel = state.cancel.clone();
+  let handle = app.clone();
+  let store_dir = dataset_dir(&app)?;
+  let parent = {
+    let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+    inner
+      .dataset
+      .clone()
+      .ok_or_else(|| "No dataset loaded".to_string())?
+  };
+  let input_count = parent.record_count;
+  let field = spec.field.clone();
+
+  let (derived, counts) = tauri::async_runtime::spawn_blocking(move || {
+    chunk_field_inner(&parent, &store_dir, &spec, cancel.as_ref(), |current, total| {
+      emit_progress(
+        &handle,
+        "chunk",
+        current,
+        total,
+        &format!("Chunked {current} records"),
+      );
+    })
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+
+  log_event(
+    &app,
+    &format!(
+      "Chunked field {field} by {max_len} {unit} with overlap {overlap}: {} records into {} chunks",
+      input_count, derived.record_count
+    ),
+  );
+
+  let summary = ChunkSummary {
+    dataset: derived.summary(),
+    input_count,
+    output_count: derived.record_count,
+    chunked_count: counts.chunked,
+    skipped_count: counts.skipped,
+    histogram: counts.histogram,
+  };
+  let parent_config = state
+    .inner
+    .read()
+    .map_err(|_| "State lock error".to_string())?
+    .config();
+  let config = prepare_dataset_switch(&app, &derived, parent_config);
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.activate_dataset(derived);
+  inner.apply_config(config);
+
+  Ok(summary)
+}
@@ -0,0 +1,85 @@
+use tauri::{AppHandle, State};
+
+use datalab_backend::models::{NamedViewInfo, ViewDefinition};
+use datalab_backend::state::{AppState, InnerState};
+use datalab_backend::views::{
+  check_definition,
+  dependents,
+  resolve_view,
+  SavedView,
+  ViewResolution,
+  NAMED_VIEW_PREFIX,
+};
+
+use crate::tauri_support::{log_event, schedule_autosave};
+
+fn view_info(inner: &InnerState, name: &str, definition: &ViewDefinition) -> NamedViewInfo {
+  let count = match resolve_view(inner, &format!("{NAMED_VIEW_PREFIX}{name}")) {
+    Ok(ViewResolution::Ready(ids)) => Some(ids.len()),
+    _ => None,
+  };
+  NamedViewInfo {
+    name: name.to_string(),
+    definition: definition.clone(),
+    count,
+  }
+}
+
+/// Saves a view under `name`, replacing any previous definition. Its ids are
+/// computed when the view is first used as `view:<name>`.
+#[tauri::command]
+pub fn create_view(
+  name: String,
+  definition: ViewDefinition,
+  app: AppHandle,
+  state: State<'_, AppState>,
+) -> Result<NamedViewInfo, String> {
+  let name = name.trim().to_string();
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  if inner.dataset.is_none() {
+    return Err("No dataset loaded".to_string());
+  }
+  check_definition(&inner.views, &name, &definition)?;
+  let replaced = inner
+    .views
+    .insert(name.clone(), SavedView::new(definition.clone()))
+    .is_some();
+  let info = view_info(&inner, &name, &definition);
+  drop(inner);
+  schedule_autosave(&app);
+
+  let action = if replaced { "Updated" } else { "Created" };
+  log_event(&app, &format!("{action} view {name} on {}", definition.base));
+  Ok(info)
+}
+
+#[tauri::command]
+pub fn list_views(state: State<'_, AppState>) -> Result<Vec<NamedViewInfo>, String> {
+  let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+  Ok(
+    inner
+      .views
+      .iter()
+      .map(|(name, saved)| view_info(&inner, name, &saved.definition))
+      .collect(),
+  )
+}
+
+/// Deletes a saved view that no other view refers to.
+#[tauri::command]
+pub fn delete_view(name: String, app: AppHandle, state: State<'_, AppState>) -> Result<(), String> {
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  let users = dependents(&inner.views, &name);
+  if !users.is_empty() {
+    return Err(format!("View {name} is used by {}", users.join(", ")));
+  }
+  inner
+    .views
+    .remove(&name)
+    .ok_or_else(|| format!("Unknown view: {name}"))?;
+  drop(inner);
+  schedule_autosave(&app);
+
+  log_event(&app, &format!("Deleted view {name}"));
+  Ok(())
+}
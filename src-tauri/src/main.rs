@@ -1,11 +1,13 @@
 mod commands;
 mod menu;
 mod tauri_support;
+mod window_status;
 
 use datalab_backend::state::AppState;
 
 fn main() {
   tauri::Builder::default()
+    .plugin(tauri_plugin_clipboard_manager::init())
     .plugin(tauri_plugin_dialog::init())
     .plugin(tauri_plugin_process::init())
     .setup(|app| {
@@ -13,6 +15,9 @@ fn main() {
       app.handle().plugin(tauri_plugin_updater::Builder::new().build())?;
       #[cfg(desktop)]
       menu::datalab_menu_setup(app)?;
+      tauri_support::run_startup_check(app.handle())?;
+      tauri_support::load_preview_limits(app.handle());
+      tauri_support::restore_last_session(app.handle());
       Ok(())
     })
     .manage(AppState::default())
@@ -21,15 +26,77 @@ fn main() {
       commands::dataset::get_preview,
       commands::dataset::get_record,
       commands::dataset::export_dataset,
+      commands::dataset::convert_file,
+      commands::delta::export_delta,
+      commands::dataset::list_open_datasets,
+      commands::dataset::activate_dataset,
+      commands::dataset::reopen_dataset,
+      commands::dataset::list_datasets,
+      commands::dataset::open_dataset,
+      commands::dataset::delete_dataset,
+      commands::dataset::get_dataset_config,
+      commands::dataset::get_extremes,
+      commands::dataset::get_field_matrix,
+      commands::dataset::get_dataset_stats,
+      commands::dataset::export_field_matrix,
+      commands::dataset::export_review_html,
+      commands::dataset::preview_order,
+      commands::dataset::resolve_stable_ids,
+      commands::dataset::get_stable_ids,
+      commands::dataset::export_to_clipboard,
+      commands::hub::import_from_hub,
+      commands::hub::push_to_hub,
+      commands::remote::import_dataset_url,
       commands::filters::apply_filters,
+      commands::filters::drill_down,
       commands::filters::list_categories,
+      commands::filters::detect_code_languages,
+      commands::filters::get_language_stats,
+      commands::filters::test_pattern,
+      commands::filters::scan_pii,
+      commands::filters::get_category_rules,
+      commands::filters::set_category_rules,
       commands::filters::set_field_map,
+      commands::filters::tag_refusals,
+      commands::filters::tag_language_mismatches,
+      commands::filters::detect_templates,
+      commands::filters::cap_per_template,
+      commands::filters::run_benchmark,
+      commands::filters::validate_rules,
+      commands::clusters::find_duplicate_clusters,
+      commands::clusters::get_next_cluster,
+      commands::clusters::resolve_cluster,
+      commands::clusters::get_cluster_progress,
+      commands::compare::compare_datasets,
+      commands::sample::create_sample_view,
+      commands::sample::promote_to_full,
+      commands::sample::estimate_pipeline,
       commands::distill::preview_distillation,
       commands::distill::update_manual_selection,
+      commands::distill::explain_record_score,
+      commands::distill::get_review_queue,
+      commands::distill::score_with_command,
+      commands::distill::materialize_command_scores,
+      commands::session::get_autosave_info,
+      commands::session::restore_autosave,
+      commands::transform::explode_field,
+      commands::transform::join_metadata,
+      commands::transform::prune_fields,
+      commands::transform::chunk_field,
+      commands::transform::append_dataset,
+      commands::transform::continue_import,
+      commands::transform::truncate_field,
+      commands::views::create_view,
+      commands::views::list_views,
+      commands::views::delete_view,
       commands::settings::cancel_task,
+      commands::settings::finish_task,
+      commands::settings::set_window_badge,
       commands::settings::load_settings,
       commands::settings::save_settings,
-      commands::settings::get_logs
+      commands::settings::get_logs,
+      commands::settings::get_startup_report,
+      commands::settings::get_restored_session
     ])
     .run(tauri::generate_context!())
     .expect("error while running tauri application");
@@ -1,15 +1,60 @@
 use std::fs::{self, OpenOptions};
 use std::io::Write;
 use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 
 use chrono::Utc;
 use tauri::{AppHandle, Emitter, Manager};
 
-use datalab_backend::models::ProgressPayload;
+use datalab_backend::appdata::{check_app_data, read_settings_file, AppDataDirs};
+use datalab_backend::delta::exports_path;
+use datalab_backend::filters::apply_filters_inner;
+use datalab_backend::io::{content_hashes_path, SourceProgress};
+use datalab_backend::models::{
+  DatasetConfig,
+  ProgressPayload,
+  RestoredSession,
+  Settings,
+  StartupReport,
+};
+use datalab_backend::paths::OutputGuard;
+use datalab_backend::session::{
+  read_last_session,
+  reopen_store,
+  restorable_view,
+  write_last_session,
+  LastSession,
+};
+use datalab_backend::sidecar::{
+  derived_state_path,
+  load_dataset_config,
+  load_derived_state,
+  save_derived_state,
+  DerivedState,
+};
+use datalab_backend::stable::{capture_annotations, save_annotations};
+use datalab_backend::state::{AppState, DatasetStore, InnerState};
+use datalab_backend::views::{
+  record_filter_result,
+  resolve_view,
+  ViewResolution,
+  NAMED_VIEW_PREFIX,
+};
+
+use crate::window_status::{refresh_window_title, set_task_progress};
+
+/// Quiet period before derived state is written, so bursts of edits save once.
+const AUTOSAVE_DEBOUNCE: Duration = Duration::from_millis(1500);
 
 pub struct AppPaths {
+  pub root: PathBuf,
   pub datasets: PathBuf,
+  pub downloads: PathBuf,
+  pub annotations: PathBuf,
   pub settings: PathBuf,
+  /// Where the app was when last used, see `remember_session`.
+  pub session: PathBuf,
   pub log_file: PathBuf,
 }
 
@@ -19,14 +64,23 @@ fn app_paths(handle: &AppHandle) -> Result<AppPaths, String> {
     .app_data_dir()
     .map_err(|e| format!("Unable to resolve app data dir: {e}"))?;
   let datasets = root.join("datasets");
+  let downloads = root.join("downloads");
+  let annotations = root.join("annotations");
   let logs = root.join("logs");
   fs::create_dir_all(&datasets).map_err(|e| e.to_string())?;
+  fs::create_dir_all(&downloads).map_err(|e| e.to_string())?;
+  fs::create_dir_all(&annotations).map_err(|e| e.to_string())?;
   fs::create_dir_all(&logs).map_err(|e| e.to_string())?;
   let settings = root.join("settings.json");
+  let session = root.join("session.json");
   let log_file = logs.join("datalab.log");
   Ok(AppPaths {
+    root,
     datasets,
+    downloads,
+    annotations,
     settings,
+    session,
     log_file,
   })
 }
@@ -35,10 +89,220 @@ pub fn dataset_dir(handle: &AppHandle) -> Result<PathBuf, String> {
   Ok(app_paths(handle)?.datasets)
 }
 
+pub fn download_dir(handle: &AppHandle) -> Result<PathBuf, String> {
+  Ok(app_paths(handle)?.downloads)
+}
+
+pub fn annotation_dir(handle: &AppHandle) -> Result<PathBuf, String> {
+  Ok(app_paths(handle)?.annotations)
+}
+
 pub fn settings_path(handle: &AppHandle) -> Result<PathBuf, String> {
   Ok(app_paths(handle)?.settings)
 }
 
+/// Guard for user-chosen output paths: open stores and their sidecars, the
+/// settings and the log are never written; the datasets dir only on request.
+pub fn output_guard(
+  handle: &AppHandle,
+  inner: &InnerState,
+  allow_internal: bool,
+  overwrite: bool,
+) -> Result<OutputGuard, String> {
+  let paths = app_paths(handle)?;
+  let mut protected_files = vec![paths.settings, paths.session, paths.log_file];
+  for store in inner.datasets.values().chain(inner.dataset.as_ref()) {
+    protected_files.push(store.store_path.clone());
+    protected_files.push(derived_state_path(store));
+    protected_files.push(exports_path(store));
+    protected_files.push(content_hashes_path(&store.store_path));
+  }
+  Ok(OutputGuard {
+    protected_files,
+    internal_dirs: vec![paths.datasets],
+    allow_internal,
+    overwrite,
+  })
+}
+
+pub fn read_settings(handle: &AppHandle) -> Result<Option<Settings>, String> {
+  read_settings_file(&settings_path(handle)?)
+}
+
+/// Checks the app data directory before the UI loads and keeps the report for
+/// `get_startup_report`.
+pub fn run_startup_check(handle: &AppHandle) -> Result<StartupReport, String> {
+  let paths = app_paths(handle)?;
+  let temp_dirs = [
+    paths.root.as_path(),
+    paths.datasets.as_path(),
+    paths.downloads.as_path(),
+    paths.annotations.as_path(),
+  ];
+  let report = check_app_data(&AppDataDirs {
+    settings: &paths.settings,
+    datasets: &paths.datasets,
+    temp_dirs: &temp_dirs,
+  });
+
+  if let Some(backup) = &report.settings_backup {
+    log_event(
+      handle,
+      &format!(
+        "Settings were unreadable ({}); moved to {backup}",
+        report.settings_error.as_deref().unwrap_or_default()
+      ),
+    );
+  }
+  for path in &report.removed_temp_files {
+    log_event(handle, &format!("Removed stale temp file {path}"));
+  }
+  for path in &report.removed_sidecars {
+    log_event(handle, &format!("Removed sidecar without a store: {path}"));
+  }
+  for error in &report.errors {
+    log_event(handle, &format!("Startup check: {error}"));
+  }
+  if report.is_clean() {
+    log_event(handle, "Startup check found no problems");
+  }
+
+  let state = handle.state::<AppState>();
+  let mut stored = state
+    .startup_report
+    .lock()
+    .map_err(|_| "State lock error".to_string())?;
+  *stored = report.clone();
+  Ok(report)
+}
+
+/// Caches the preview limits of the saved settings in the state, once the
+/// startup check has set aside an unreadable settings file.
+pub fn load_preview_limits(handle: &AppHandle) {
+  let limits = read_settings(handle)
+    .ok()
+    .flatten()
+    .and_then(|settings| settings.preview_limits);
+  handle.state::<AppState>().set_preview_limits(limits);
+}
+
+/// Notes the dataset the preview shows, `session` from `LastSession::new`,
+/// and where it is, updated by `change`, so the next startup can return to
+/// it. Only kept while the settings ask for it.
+pub fn remember_session(
+  handle: &AppHandle,
+  mut session: LastSession,
+  change: impl FnOnce(&mut LastSession),
+) {
+  let Some(settings) = read_settings(handle).ok().flatten() else {
+    return;
+  };
+  if settings.restore_last_session != Some(true) {
+    return;
+  }
+  let Ok(path) = app_paths(handle).map(|paths| paths.session) else {
+    return;
+  };
+  // Where the preview was stays unless it was on another dataset.
+  match read_last_session(&path) {
+    Ok(Some(previous)) if previous.store_path == session.store_path => {
+      session.view = previous.view;
+      session.page = previous.page;
+      session.page_size = previous.page_size;
+      session.order_by = previous.order_by;
+    }
+    _ => {
+      if let Some(page_size) = settings.default_page_size {
+        session.page_size = page_size;
+      }
+    }
+  }
+  change(&mut session);
+  if let Err(err) = write_last_session(&path, &session) {
+    log_event(handle, &format!("Could not save the session: {err}"));
+  }
+}
+
+/// Reopens the dataset of the last session before the UI loads, when the
+/// settings ask for it, and keeps what was restored for
+/// `get_restored_session`. Anything missing or unreadable leaves the app
+/// without a dataset and a note in the log.
+pub fn restore_last_session(handle: &AppHandle) {
+  let settings = match read_settings(handle) {
+    Ok(Some(settings)) => settings,
+    Ok(None) => return,
+    Err(err) => {
+      log_event(handle, &format!("Could not restore the last session: {err}"));
+      return;
+    }
+  };
+  if settings.restore_last_session != Some(true) {
+    return;
+  }
+  match reopen_last_session(handle, &settings) {
+    Ok(Some(restored)) => {
+      log_event(
+        handle,
+        &format!(
+          "Restored the last session: {} at view {}, page {}",
+          restored.dataset.source_path, restored.view, restored.page
+        ),
+      );
+      refresh_window_title(handle);
+      let _ = handle.emit("session-restored", &restored);
+      if let Ok(mut stored) = handle.state::<AppState>().restored_session.lock() {
+        *stored = Some(restored);
+      }
+    }
+    Ok(None) => {}
+    Err(err) => log_event(handle, &format!("Could not restore the last session: {err}")),
+  }
+}
+
+/// Activates the last session's store with the configs and derived state
+/// saved with it. Nothing is activated unless every file could be read.
+fn reopen_last_session(
+  handle: &AppHandle,
+  settings: &Settings,
+) -> Result<Option<RestoredSession>, String> {
+  let Some(session) = read_last_session(&app_paths(handle)?.session)? else {
+    return Ok(None);
+  };
+  if !session.store_path.is_file() {
+    return Err(format!("{} no longer exists", session.store_path.display()));
+  }
+  let store = reopen_store(&session)?;
+  let config =
+    load_dataset_config(&store)?.unwrap_or_else(|| DatasetConfig::from_settings(settings));
+  let saved = load_derived_state(&store)?;
+  let dataset = store.summary();
+
+  let state = handle.state::<AppState>();
+  let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+  inner.activate_dataset(store);
+  inner.apply_config(config);
+  if let Some(saved) = saved {
+    saved.apply_to(&mut inner);
+  }
+  let (view, page) = if restorable_view(&inner, &session.view) {
+    (session.view, session.page.max(1))
+  } else {
+    let view = settings
+      .default_view
+      .clone()
+      .filter(|view| restorable_view(&inner, view))
+      .unwrap_or_else(|| "all".to_string());
+    (view, 1)
+  };
+  Ok(Some(RestoredSession {
+    dataset,
+    view,
+    page,
+    page_size: session.page_size,
+    order_by: session.order_by,
+  }))
+}
+
 pub fn log_file_path(handle: &AppHandle) -> Result<PathBuf, String> {
   Ok(app_paths(handle)?.log_file)
 }
@@ -56,12 +320,240 @@ pub fn log_event(handle: &AppHandle, message: &str) {
   }
 }
 
-pub fn emit_progress(handle: &AppHandle, stage: &str, current: usize, total: usize, message: &str) {
-  let payload = ProgressPayload {
-    stage: stage.to_string(),
-    current,
-    total,
-    message: Some(message.to_string()),
+/// The ordered phases of a multi-phase operation, reported under one stage.
+pub struct Phases {
+  pub stage: &'static str,
+  pub names: &'static [&'static str],
+}
+
+fn emit_payload(handle: &AppHandle, mut payload: ProgressPayload) {
+  let key = format!("{}/{}", payload.stage, payload.phase.as_deref().unwrap_or_default());
+  payload.rate = handle
+    .state::<AppState>()
+    .progress_rate
+    .lock()
+    .ok()
+    .and_then(|mut meter| meter.update(&key, payload.current));
+  // Phases share the taskbar bar evenly; an unknown total shows it busy
+  // unless the bytes read of the source tell how far along it is.
+  let within = match (payload.total, payload.bytes_current, payload.bytes_total) {
+    (0, Some(current), Some(total)) if total > 0 => Some(current.min(total) as f64 / total as f64),
+    (0, _, _) => None,
+    (total, _, _) => Some(payload.current as f64 / total as f64),
   };
+  let fraction = within.map(|within| match (payload.phase_index, payload.phase_count) {
+    (Some(index), Some(count)) if count > 0 => (index as f64 + within) / count as f64,
+    _ => within,
+  });
+  set_task_progress(handle, fraction);
   let _ = handle.emit("progress", payload);
 }
+
+pub fn emit_progress(handle: &AppHandle, stage: &str, current: usize, total: usize, message: &str) {
+  emit_payload(
+    handle,
+    ProgressPayload {
+      stage: stage.to_string(),
+      current,
+      total,
+      message: Some(message.to_string()),
+      phase: None,
+      phase_index: None,
+      phase_count: None,
+      rate: None,
+      bytes_current: None,
+      bytes_total: None,
+    },
+  );
+}
+
+/// Progress within `phase`, one of `phases.names`.
+pub fn emit_phase_progress(
+  handle: &AppHandle,
+  phases: &Phases,
+  phase: &str,
+  current: usize,
+  total: usize,
+  message: &str,
+) {
+  emit_payload(
+    handle,
+    ProgressPayload {
+      stage: phases.stage.to_string(),
+      current,
+      total,
+      message: Some(message.to_string()),
+      phase: Some(phase.to_string()),
+      phase_index: phases.names.iter().position(|name| *name == phase),
+      phase_count: Some(phases.names.len()),
+      rate: None,
+      bytes_current: None,
+      bytes_total: None,
+    },
+  );
+}
+
+/// Progress of an import that has read `count` records, on its own or as the
+/// `import` phase of `phases`. The record total is unknown, so how far
+/// through the source file it is goes along when known.
+pub fn emit_import_progress(
+  handle: &AppHandle,
+  phases: Option<&Phases>,
+  count: usize,
+  source: SourceProgress,
+  message: &str,
+) {
+  let known = source.bytes_total > 0;
+  emit_payload(
+    handle,
+    ProgressPayload {
+      stage: phases.map_or("import", |phases| phases.stage).to_string(),
+      current: count,
+      total: 0,
+      message: Some(message.to_string()),
+      phase: phases.map(|_| "import".to_string()),
+      phase_index: phases.and_then(|phases| {
+        phases.names.iter().position(|name| *name == "import")
+      }),
+      phase_count: phases.map(|phases| phases.names.len()),
+      rate: None,
+      bytes_current: known.then_some(source.bytes_read),
+      bytes_total: known.then_some(source.bytes_total),
+    },
+  );
+}
+
+/// Writes the active dataset's derived state and annotations. The state lock
+/// is only held while cloning the snapshot, never during IO.
+fn save_active_state(handle: &AppHandle) {
+  let state = handle.state::<AppState>();
+  let Ok(_guard) = state.autosave_lock.lock() else {
+    return;
+  };
+  let snapshot = match state.inner.read() {
+    Ok(inner) => inner
+      .dataset
+      .clone()
+      .zip(DerivedState::capture(&inner)),
+    Err(_) => None,
+  };
+  let Some((store, snapshot)) = snapshot else {
+    return;
+  };
+  if let Err(err) = save_derived_state(&store, &snapshot) {
+    log_event(handle, &format!("Autosave failed: {err}"));
+  }
+  let saved = annotation_dir(handle).and_then(|dir| {
+    let annotations = capture_annotations(&store, &snapshot)?;
+    save_annotations(&dir, &annotations)
+  });
+  if let Err(err) = saved {
+    log_event(handle, &format!("Saving annotations failed: {err}"));
+  }
+}
+
+/// Persists the active dataset's derived ids to its sidecar in the background.
+pub fn schedule_autosave(handle: &AppHandle) {
+  let generation = handle
+    .state::<AppState>()
+    .autosave_generation
+    .fetch_add(1, Ordering::SeqCst)
+    + 1;
+  let handle = handle.clone();
+  tauri::async_runtime::spawn_blocking(move || {
+    std::thread::sleep(AUTOSAVE_DEBOUNCE);
+    let state = handle.state::<AppState>();
+    if state.autosave_generation.load(Ordering::SeqCst) != generation {
+      return;
+    }
+    save_active_state(&handle);
+  });
+}
+
+/// Field map and configs from the settings, the defaults for new datasets.
+pub fn default_dataset_config(handle: &AppHandle) -> DatasetConfig {
+  read_settings(handle)
+    .ok()
+    .flatten()
+    .map(|settings| DatasetConfig::from_settings(&settings))
+    .unwrap_or_default()
+}
+
+/// Saves the active dataset's state before `store` replaces it, so a pending
+/// autosave cannot lose it or write it to the wrong sidecar, and returns the
+/// field map and configs `store` starts with: those in its sidecar, or
+/// `fallback` when it has none yet.
+pub fn prepare_dataset_switch(
+  handle: &AppHandle,
+  store: &DatasetStore,
+  fallback: DatasetConfig,
+) -> DatasetConfig {
+  handle
+    .state::<AppState>()
+    .autosave_generation
+    .fetch_add(1, Ordering::SeqCst);
+  save_active_state(handle);
+  match load_dataset_config(store) {
+    Ok(Some(config)) => config,
+    Ok(None) => fallback,
+    Err(err) => {
+      log_event(
+        handle,
+        &format!("Could not read the saved configs of dataset {}: {err}", store.id),
+      );
+      fallback
+    }
+  }
+}
+
+/// Runs the filter refinements of a saved view that are missing or stale, so
+/// `InnerState::view_ids` can resolve it. Other views need nothing.
+pub async fn materialize_view(handle: &AppHandle, view: &str) -> Result<(), String> {
+  if !view.starts_with(NAMED_VIEW_PREFIX) {
+    return Ok(());
+  }
+  let state = handle.state::<AppState>();
+  loop {
+    let (pending, store, field_map, category_rules) = {
+      let inner = state.inner.read().map_err(|_| "State lock error".to_string())?;
+      let pending = match resolve_view(&inner, view)? {
+        ViewResolution::Ready(_) => return Ok(()),
+        ViewResolution::Pending(pending) => *pending,
+      };
+      let store = inner
+        .dataset
+        .clone()
+        .ok_or_else(|| "No dataset loaded".to_string())?;
+      (pending, store, inner.field_map.clone(), inner.category_rules.clone())
+    };
+
+    let cancel = state.cancel.clone();
+    let progress = handle.clone();
+    let job = pending.clone();
+    let (job_field_map, job_rules) = (field_map.clone(), category_rules.clone());
+    let (output, _) = tauri::async_runtime::spawn_blocking(move || {
+      apply_filters_inner(
+        &store,
+        Some(&job.input),
+        &job.config,
+        &job_field_map,
+        &job_rules,
+        cancel.as_ref(),
+        |current, total| {
+          emit_progress(
+            &progress,
+            "view",
+            current,
+            total,
+            &format!("Filtering view {} ({current} records)", job.view),
+          );
+        },
+      )
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    let mut inner = state.inner.write().map_err(|_| "State lock error".to_string())?;
+    record_filter_result(&mut inner, pending, field_map, category_rules, output);
+  }
+}
@@ -0,0 +1,90 @@
+use tauri::{AppHandle, Manager};
+
+use datalab_backend::state::AppState;
+
+const MAIN_WINDOW: &str = "main";
+/// Title of the main window with no dataset open, as in tauri.conf.json.
+const DEFAULT_TITLE: &str = "DataLab (by Vietrix)";
+
+/// Titles the main window with the active dataset's file name and how many
+/// of its records are selected: those the distillation keeps, or those that
+/// pass the filters before it has run.
+pub fn refresh_window_title(handle: &AppHandle) {
+  let Some(window) = handle.get_webview_window(MAIN_WINDOW) else {
+    return;
+  };
+  let title = {
+    let state = handle.state::<AppState>();
+    let Ok(inner) = state.inner.read() else {
+      return;
+    };
+    match &inner.dataset {
+      Some(store) => {
+        let name = store
+          .source_path
+          .file_name()
+          .map(|name| name.to_string_lossy().to_string())
+          .unwrap_or_else(|| store.source_path.display().to_string());
+        let selected = match &inner.selected_ids {
+          Some(ids) => ids.len(),
+          None => inner.view_ids("filtered").len(),
+        };
+        format!("DataLab — {name} ({selected}/{})", store.record_count)
+      }
+      None => DEFAULT_TITLE.to_string(),
+    }
+  };
+  let _ = window.set_title(&title);
+}
+
+/// Shows task progress on the Windows taskbar button or the macOS dock icon,
+/// `fraction` in [0, 1] or `None` while the total is unknown. Elsewhere it
+/// does nothing.
+pub fn set_task_progress(handle: &AppHandle, fraction: Option<f64>) {
+  #[cfg(any(windows, target_os = "macos"))]
+  {
+    use tauri::window::{ProgressBarState, ProgressBarStatus};
+    let state = match fraction {
+      Some(fraction) => ProgressBarState {
+        status: Some(ProgressBarStatus::Normal),
+        progress: Some((fraction.clamp(0.0, 1.0) * 100.0).round() as u64),
+      },
+      None => ProgressBarState {
+        status: Some(ProgressBarStatus::Indeterminate),
+        progress: None,
+      },
+    };
+    if let Some(window) = handle.get_webview_window(MAIN_WINDOW) {
+      let _ = window.set_progress_bar(state);
+    }
+  }
+  #[cfg(not(any(windows, target_os = "macos")))]
+  let _ = (handle, fraction);
+}
+
+/// Removes the taskbar or dock progress once a task has finished or stopped.
+pub fn clear_task_progress(handle: &AppHandle) {
+  #[cfg(any(windows, target_os = "macos"))]
+  {
+    use tauri::window::{ProgressBarState, ProgressBarStatus};
+    if let Some(window) = handle.get_webview_window(MAIN_WINDOW) {
+      let _ = window.set_progress_bar(ProgressBarState {
+        status: Some(ProgressBarStatus::None),
+        progress: None,
+      });
+    }
+  }
+  #[cfg(not(any(windows, target_os = "macos")))]
+  let _ = handle;
+}
+
+/// Shows `count` on the macOS dock icon, or removes it for `None`. Other
+/// platforms have no badge and ignore it.
+pub fn set_badge(handle: &AppHandle, count: Option<i64>) {
+  #[cfg(target_os = "macos")]
+  if let Some(window) = handle.get_webview_window(MAIN_WINDOW) {
+    let _ = window.set_badge_count(count);
+  }
+  #[cfg(not(target_os = "macos"))]
+  let _ = (handle, count);
+}
@@ -16,18 +16,25 @@ import { check } from "@tauri-apps/plugin-updater";
 import {
   applyFilters,
   cancelTask,
+  deleteDataset,
   exportDataset,
+  finishTask,
+  getDatasetConfig,
   getLogs,
   getPreview,
   getRecord,
+  getRestoredSession,
   importDataset,
   listenMenuAction,
   listenProgress,
   listCategories,
+  listDatasets,
   loadSettings,
+  openDataset,
   previewDistillation,
   saveSettings,
   selectDatasetFile,
+  selectDatasetFolder,
   selectExportPath,
   setFieldMap,
   updateManualSelection
@@ -41,8 +48,11 @@ import type {
   FilterConfig,
   FilterSummary,
   MenuAction,
+  OutputError,
   PreviewPage,
   ProgressEvent,
+  RestoredSession,
+  StoredDataset,
   ViewMode
 } from "./lib/types";
 import { resolveLanguage, translate, type Language } from "./i18n";
@@ -62,6 +72,17 @@ type RecordPayload = {
   language?: string;
 };
 
+/** Text of a rejected command: a string, an Error, an `OutputError` or a `HubError`. */
+function errorText(error: unknown): string {
+  if (error instanceof Error) {
+    return error.message;
+  }
+  if (typeof error === "object" && error !== null && "message" in error) {
+    return (error as OutputError).message;
+  }
+  return String(error);
+}
+
 const defaultFilters: FilterConfig = {
   requireFields: [],
   includeKeywords: [],
@@ -107,6 +128,7 @@ export class AppRoot extends LitElement {
   @state() private showLogs = false;
   @state() private logEntries: string[] = [];
   @state() private categorySuggestions: CategoryCount[] = [];
+  @state() private storedDatasets: StoredDataset[] = [];
   @state() private showUpdateDialog = false;
   @state() private updateStatus:
     | "idle"
@@ -186,8 +208,21 @@ export class AppRoot extends LitElement {
         this.fieldMap = settings.fieldMap ?? {};
         this.filters = { ...defaultFilters, ...settings.filters };
         this.distillConfig = { ...defaultDistill, ...settings.distill };
+        this.pageSize = settings.defaultPageSize ?? this.pageSize;
+      }
+      if (settings?.restoreLastSession) {
+        const restored = await this.runBootstrapStep(
+          "splash.step.session",
+          () => getRestoredSession(),
+          null
+        );
+        if (restored) {
+          await this.openRestoredSession(restored);
+        }
       }
 
+      await this.refreshStoredDatasets();
+
       this.bootLogs = await this.runBootstrapStep(
         "splash.step.logs",
         () => getLogs(12),
@@ -261,6 +296,21 @@ export class AppRoot extends LitElement {
     return Math.min(1, this.bootSteps.length / totalSteps);
   }
 
+  /** How far the running operation is, or null while that is unknown. */
+  private get progressFraction(): number | null {
+    const progress = this.progress;
+    if (!progress) {
+      return null;
+    }
+    if (progress.total) {
+      return progress.current / progress.total;
+    }
+    if (progress.bytesTotal && progress.bytesCurrent != null) {
+      return Math.min(1, progress.bytesCurrent / progress.bytesTotal);
+    }
+    return null;
+  }
+
   private get stepLabels() {
     return [
       this.t("step.import"),
@@ -446,23 +496,28 @@ export class AppRoot extends LitElement {
       const result = await task();
       return result;
     } catch (error) {
-      this.errorMessage = error instanceof Error ? error.message : String(error);
+      this.errorMessage = errorText(error);
     } finally {
       this.busy = false;
       this.progress = null;
+      finishTask().catch(() => undefined);
     }
     return null;
   }
 
-  private async handleImport() {
-    const selection = await selectDatasetFile();
+  private async handleImport(folder = false) {
+    const selection = folder ? await selectDatasetFolder() : await selectDatasetFile();
     if (!selection || typeof selection !== "string") {
       return;
     }
 
     await this.runTask(async () => {
       const summary = await importDataset(selection);
+      const config = await getDatasetConfig();
       this.dataset = summary;
+      this.fieldMap = config.fieldMap;
+      this.filters = { ...defaultFilters, ...config.filters };
+      this.distillConfig = { ...defaultDistill, ...config.distillConfig };
       this.filterSummary = null;
       this.distillSummary = null;
       this.previewView = "all";
@@ -472,6 +527,51 @@ export class AppRoot extends LitElement {
       await this.refreshPreview();
       await this.saveUserSettings();
     });
+    await this.refreshStoredDatasets();
+  }
+
+  private async refreshStoredDatasets() {
+    try {
+      this.storedDatasets = await listDatasets();
+    } catch (error) {
+      console.error(error);
+    }
+  }
+
+  private async handleOpenStored(id: string) {
+    await this.runTask(async () => {
+      const summary = await openDataset(id);
+      const config = await getDatasetConfig();
+      this.dataset = summary;
+      this.fieldMap = config.fieldMap;
+      this.filters = { ...defaultFilters, ...config.filters };
+      this.distillConfig = { ...defaultDistill, ...config.distillConfig };
+      this.filterSummary = null;
+      this.distillSummary = null;
+      this.page = 1;
+      await this.refreshPreview("all");
+    });
+    await this.refreshStoredDatasets();
+  }
+
+  private async handleDeleteStored(id: string) {
+    await this.runTask(() => deleteDataset(id));
+    await this.refreshStoredDatasets();
+  }
+
+  private async openRestoredSession(restored: RestoredSession) {
+    try {
+      const config = await getDatasetConfig();
+      this.dataset = restored.dataset;
+      this.fieldMap = config.fieldMap;
+      this.filters = { ...defaultFilters, ...config.filters };
+      this.distillConfig = { ...defaultDistill, ...config.distillConfig };
+      this.page = restored.page;
+      this.pageSize = restored.pageSize;
+      await this.refreshPreview(restored.view);
+    } catch (error) {
+      this.errorMessage = error instanceof Error ? error.message : String(error);
+    }
   }
 
   private autoMapFields(fields: string[]) {
@@ -525,7 +625,7 @@ export class AppRoot extends LitElement {
       return;
     }
     const list = await listCategories(this.fieldMap.category);
-    this.categorySuggestions = list;
+    this.categorySuggestions = list.categories;
   }
 
   private async runDistillationPreview() {
@@ -554,16 +654,47 @@ export class AppRoot extends LitElement {
     if (!this.dataset || !this.distillSummary) {
       return;
     }
+    // Exports default to the format the dataset was imported from.
+    const sourceFormat = this.dataset.format;
+    const extension = sourceFormat === "csv" || sourceFormat === "jsonl" ? sourceFormat : "json";
     const defaultName =
-      view === "removed" ? "distilled_removed.json" : "distilled_dataset.json";
+      view === "removed"
+        ? `distilled_removed.${extension}`
+        : `distilled_dataset.${extension}`;
     const exportPath = await selectExportPath(defaultName);
     if (!exportPath || typeof exportPath !== "string") {
       return;
     }
 
-    const format = exportPath.toLowerCase().endsWith(".csv") ? "csv" : "json";
+    const lowerPath = exportPath.toLowerCase();
+    const compression = lowerPath.endsWith(".gz")
+      ? "gzip"
+      : lowerPath.endsWith(".zst")
+        ? "zstd"
+        : "none";
+    const basePath = lowerPath.replace(/\.(gz|zst)$/, "");
+    const format = basePath.endsWith(".csv")
+      ? "csv"
+      : basePath.endsWith(".jsonl")
+        ? "jsonl"
+        : /\.(sqlite|db)$/.test(basePath)
+          ? "sqlite"
+          : "json";
     await this.runTask(async () => {
-      await exportDataset(view, exportPath, format);
+      // The save dialog has already confirmed replacing an existing file.
+      await exportDataset(view, exportPath, format, { overwrite: true }, compression);
+    });
+  }
+
+  private async handleDuplicateReport() {
+    const exportPath = await selectExportPath("duplicate_report.jsonl");
+    if (!exportPath || typeof exportPath !== "string") {
+      return;
+    }
+    await this.runTask(async () => {
+      await exportDataset("filtered", exportPath, "duplicate_report", {
+        overwrite: true
+      });
     });
   }
 
@@ -743,7 +874,7 @@ export class AppRoot extends LitElement {
   }
 
   private updateFilterText(
-    key: "includeKeywords" | "excludeKeywords" | "categories",
+    key: "includeKeywords" | "excludeKeywords" | "categories" | "excludeCategories",
     value: string
   ) {
     const list = value
@@ -1144,6 +1275,11 @@ export class AppRoot extends LitElement {
           @click=${() => this.handleImport()}
           >${this.t("action.import")}</md-filled-button
         >
+        <md-outlined-button
+          ?disabled=${this.busy}
+          @click=${() => this.handleImport(true)}
+          >${this.t("action.importFolder")}</md-outlined-button
+        >
         ${this.busy
           ? html`<md-outlined-button @click=${() => cancelTask()}
               >${this.t("action.cancel")}</md-outlined-button
@@ -1169,6 +1305,16 @@ export class AppRoot extends LitElement {
                     ${this.formatBytes(this.dataset.sizeBytes)}
                   </div>
                 </div>
+                ${this.dataset.delimiter
+                  ? html`<div class="summary-card">
+                      <div class="summary-label">${this.t("summary.delimiter")}</div>
+                      <div class="summary-value">
+                        ${this.dataset.delimiter === "\t"
+                          ? this.t("summary.delimiterTab")
+                          : this.dataset.delimiter}
+                      </div>
+                    </div>`
+                  : nothing}
               </div>
               <div class="hint">
                 ${this.t("hint.fieldsDetected", {
@@ -1178,6 +1324,7 @@ export class AppRoot extends LitElement {
             </div>
           `
         : html`<div class="empty-state">${this.t("hint.importEmpty")}</div>`}
+      ${this.renderStoredDatasets()}
       ${this.dataset ? this.renderFieldMapping() : nothing}
     `;
     return html`
@@ -1198,6 +1345,50 @@ export class AppRoot extends LitElement {
     `;
   }
 
+  private renderStoredDatasets() {
+    if (!this.storedDatasets.length) {
+      return nothing;
+    }
+    return html`
+      <div>
+        <div class="panel-title">${this.t("panel.stored.title")}</div>
+        <div class="list">
+          ${this.storedDatasets.map(
+            (stored) => html`
+              <div class="record">
+                <div class="record-header">
+                  <div class="record-value">${stored.sourcePath}</div>
+                  ${stored.active
+                    ? html`<span class="pill">${this.t("stored.active")}</span>`
+                    : nothing}
+                </div>
+                <div class="hint">
+                  ${this.t("stored.details", {
+                    records: stored.recordCount,
+                    size: this.formatBytes(stored.storeBytes),
+                    date: new Date(stored.importedAt * 1000).toLocaleString(this.language)
+                  })}
+                </div>
+                <div class="actions">
+                  <md-outlined-button
+                    ?disabled=${this.busy || stored.active}
+                    @click=${() => this.handleOpenStored(stored.id)}
+                    >${this.t("action.open")}</md-outlined-button
+                  >
+                  <md-outlined-button
+                    ?disabled=${this.busy || stored.active}
+                    @click=${() => this.handleDeleteStored(stored.id)}
+                    >${this.t("action.delete")}</md-outlined-button
+                  >
+                </div>
+              </div>
+            `
+          )}
+        </div>
+      </div>
+    `;
+  }
+
   private renderFieldMapping() {
     const options = this.dataset?.fields ?? [];
     const renderSelect = (
@@ -1292,6 +1483,22 @@ export class AppRoot extends LitElement {
             <div slot="headline">${this.t("field.combined")}</div>
           </md-select-option>
         </md-outlined-select>
+        <md-outlined-select
+          label=${this.t("field.lengthUnit")}
+          value=${this.filters.lengthUnit ?? "chars"}
+          @change=${(event: Event) =>
+            this.updateFilterValue(
+              "lengthUnit",
+              (event.target as HTMLInputElement).value as FilterConfig["lengthUnit"]
+            )}
+        >
+          <md-select-option value="chars">
+            <div slot="headline">${this.t("field.lengthUnitChars")}</div>
+          </md-select-option>
+          <md-select-option value="tokens">
+            <div slot="headline">${this.t("field.lengthUnitTokens")}</div>
+          </md-select-option>
+        </md-outlined-select>
       </div>
       <div class="field-grid">
         <md-outlined-text-field
@@ -1351,6 +1558,17 @@ export class AppRoot extends LitElement {
           ></md-checkbox>
           ${this.t("filter.dedupeFuzzy")}
         </label>
+        <label class="inline-row">
+          <md-checkbox
+            ?checked=${this.filters.keepDuplicateMap ?? false}
+            @change=${(event: Event) =>
+              this.updateFilterValue(
+                "keepDuplicateMap",
+                (event.target as HTMLInputElement).checked
+              )}
+          ></md-checkbox>
+          ${this.t("filter.keepDuplicateMap")}
+        </label>
         <label class="inline-row">
           <md-checkbox
             ?checked=${this.filters.keywordCaseSensitive}
@@ -1381,6 +1599,15 @@ export class AppRoot extends LitElement {
                     (event.target as HTMLInputElement).value
                   )}
               ></md-outlined-text-field>
+              <md-outlined-text-field
+                label=${this.t("field.excludeCategories")}
+                value=${(this.filters.excludeCategories ?? []).join(", ")}
+                @input=${(event: Event) =>
+                  this.updateFilterText(
+                    "excludeCategories",
+                    (event.target as HTMLInputElement).value
+                  )}
+              ></md-outlined-text-field>
               ${this.categorySuggestions.length
                 ? html`<div class="hint">
                     ${this.t("filter.topCategories", {
@@ -1416,6 +1643,24 @@ export class AppRoot extends LitElement {
                   count: this.filterSummary.duplicatesRemoved
                 })}
               </div>
+              ${this.filterSummary.keptTokens != null
+                ? html`<div class="hint">
+                    ${this.t("summary.keptTokens", {
+                      count: this.filterSummary.keptTokens
+                    })}
+                  </div>`
+                : nothing}
+              ${(this.filterSummary.warnings ?? []).map(
+                (warning) => html`<div class="hint warning">${warning}</div>`
+              )}
+              ${this.filterSummary.duplicateMapKept &&
+              this.filterSummary.duplicatesRemoved > 0
+                ? html`<md-outlined-button
+                    ?disabled=${this.busy}
+                    @click=${() => this.handleDuplicateReport()}
+                    >${this.t("action.exportDuplicates")}</md-outlined-button
+                  >`
+                : nothing}
             </div>
           `
         : nothing}
@@ -1493,6 +1738,9 @@ export class AppRoot extends LitElement {
           <md-select-option value="importance">
             <div slot="headline">${this.t("distill.strategy.importance")}</div>
           </md-select-option>
+          <md-select-option value="prefix_diversity">
+            <div slot="headline">${this.t("distill.strategy.prefixDiversity")}</div>
+          </md-select-option>
         </md-outlined-select>
         <md-outlined-text-field
           label=${this.t("field.randomSeed")}
@@ -1538,6 +1786,9 @@ export class AppRoot extends LitElement {
                   count: this.distillSummary.removedCount
                 })}
               </div>
+              ${(this.distillSummary.warnings ?? []).map(
+                (warning) => html`<div class="hint warning">${warning}</div>`
+              )}
             </div>
           `
         : html`<div class="hint">${this.t("hint.distillEmpty")}</div>`}
@@ -1658,6 +1909,11 @@ export class AppRoot extends LitElement {
           <span class="pill">${this.t("status.view", {
             view: this.viewLabel(this.previewView)
           })}</span>
+          ${this.preview.truncatedPage
+            ? html`<span class="hint warning">${this.t("hint.pageTruncated", {
+                count: this.preview.items.length
+              })}</span>`
+            : nothing}
         </div>
         <div class="pagination">
           <md-outlined-button
@@ -1742,10 +1998,8 @@ export class AppRoot extends LitElement {
           <div>${this.progress?.message ?? this.t("dialog.working.body")}</div>
           ${this.progress
             ? html`<md-linear-progress
-                ?indeterminate=${this.progress.total === 0}
-                .value=${this.progress.total
-                  ? this.progress.current / this.progress.total
-                  : 0}
+                ?indeterminate=${this.progressFraction === null}
+                .value=${this.progressFraction ?? 0}
               ></md-linear-progress>`
             : nothing}
         </div>
@@ -3,18 +3,73 @@ import { listen } from "@tauri-apps/api/event";
 import { open, save } from "@tauri-apps/plugin-dialog";
 
 import type {
-  CategoryCount,
+  AppendSummary,
+  BenchmarkReport,
+  CategoryList,
+  CategoryRules,
+  ChunkSummary,
+  ChunkUnit,
+  ClusterProgress,
+  ClusterSummary,
+  ClusterView,
+  CodeLanguageReport,
+  CommandScoreSummary,
+  ConvertSummary,
+  DatasetComparison,
+  DatasetConfig,
+  DatasetStats,
+  DeltaExportSummary,
+  DerivedStateInfo,
   DistillConfig,
   DistillSummary,
+  DrillDown,
+  DrillDownResult,
+  ExportCompression,
+  ExportOptions,
+  ExportSummary,
+  ExtremeDirection,
+  ExtremesResult,
   FieldMap,
+  FieldMatrix,
+  FieldMatrixFormat,
   FilterConfig,
   FilterSummary,
   ManualChange,
+  MaterializeSummary,
   MenuAction,
+  NamedViewInfo,
+  OrderKey,
+  OrderPreview,
+  PatternTest,
+  PiiFilter,
+  PiiScan,
+  PipelineEstimate,
+  PipelineSpec,
   PreviewPage,
+  PushSummary,
   ProgressEvent,
+  PromoteSummary,
+  PruneSummary,
+  RestoredSession,
+  ReviewExportSummary,
+  ReviewQueue,
+  SampleSummary,
+  ScoreBreakdown,
   Settings,
+  StartupReport,
+  StoredDataset,
   DatasetSummary,
+  ImportOptions,
+  JoinSummary,
+  LanguageStats,
+  TagSummary,
+  TemplateCapSummary,
+  TemplateReport,
+  TruncateOptions,
+  TruncateSummary,
+  ValidationReport,
+  ValidationRule,
+  ViewDefinition,
   ViewMode
 } from "./types";
 
@@ -22,25 +77,158 @@ export async function selectDatasetFile() {
   return open({
     multiple: false,
     filters: [
-      { name: "Datasets", extensions: ["json", "jsonl", "csv"] },
-      { name: "JSON", extensions: ["json", "jsonl"] },
-      { name: "CSV", extensions: ["csv"] }
+      {
+        name: "Datasets",
+        extensions: ["json", "jsonl", "csv", "tsv", "gz", "md", "markdown"]
+      },
+      { name: "JSON", extensions: ["json", "jsonl", "gz"] },
+      { name: "CSV", extensions: ["csv", "tsv", "gz"] },
+      { name: "Markdown", extensions: ["md", "markdown"] }
     ]
   });
 }
 
+/** Picks a directory of Markdown or text files to import. */
+export async function selectDatasetFolder() {
+  return open({ directory: true, multiple: false });
+}
+
 export async function selectExportPath(defaultName: string) {
-  return save({
-    defaultPath: defaultName,
-    filters: [
-      { name: "JSON", extensions: ["json"] },
-      { name: "CSV", extensions: ["csv"] }
-    ]
+  const filters = [
+    { name: "JSON", extensions: ["json", "gz", "zst"] },
+    { name: "JSON Lines", extensions: ["jsonl", "gz", "zst"] },
+    { name: "CSV", extensions: ["csv", "gz", "zst"] },
+    { name: "SQLite", extensions: ["sqlite", "db"] }
+  ];
+  // The filter matching the default name comes first, so the dialog starts on it.
+  const extension = defaultName.split(".").pop() ?? "";
+  filters.sort(
+    (a, b) => Number(b.extensions[0] === extension) - Number(a.extensions[0] === extension)
+  );
+  return save({ defaultPath: defaultName, filters });
+}
+
+export async function importDataset(
+  path: string,
+  options?: ImportOptions
+): Promise<DatasetSummary> {
+  return invoke("import_dataset", { path, options });
+}
+
+export async function importDatasetUrl(
+  url: string,
+  options?: ImportOptions
+): Promise<DatasetSummary> {
+  return invoke("import_dataset_url", { url, options });
+}
+
+export async function importFromHub(
+  repoId: string,
+  filenameOrSplit?: string,
+  token?: string,
+  options?: ImportOptions,
+  keepDownload = false
+): Promise<DatasetSummary> {
+  return invoke("import_from_hub", {
+    repoId,
+    filenameOrSplit,
+    token,
+    options,
+    keepDownload
+  });
+}
+
+export async function pushToHub(
+  repoId: string,
+  folder: string,
+  isPrivate = true,
+  token?: string,
+  commitMessage?: string
+): Promise<PushSummary> {
+  return invoke("push_to_hub", {
+    repoId,
+    folder,
+    private: isPrivate,
+    token,
+    commitMessage
   });
 }
 
-export async function importDataset(path: string): Promise<DatasetSummary> {
-  return invoke("import_dataset", { path });
+export async function listOpenDatasets(): Promise<DatasetSummary[]> {
+  return invoke("list_open_datasets");
+}
+
+export async function activateDataset(id: string): Promise<DatasetSummary> {
+  return invoke("activate_dataset", { id });
+}
+
+export async function reopenDataset(datasetId: string): Promise<DatasetSummary> {
+  return invoke("reopen_dataset", { datasetId });
+}
+
+export async function listDatasets(): Promise<StoredDataset[]> {
+  return invoke("list_datasets");
+}
+
+export async function openDataset(id: string): Promise<DatasetSummary> {
+  return invoke("open_dataset", { id });
+}
+
+export async function deleteDataset(id: string): Promise<void> {
+  return invoke("delete_dataset", { id });
+}
+
+export async function getDatasetConfig(): Promise<DatasetConfig> {
+  return invoke("get_dataset_config");
+}
+
+export async function explodeField(
+  field: string,
+  nestKey?: string
+): Promise<MaterializeSummary> {
+  return invoke("explode_field", { field, nestKey });
+}
+
+export async function joinMetadata(
+  path: string,
+  leftKey: string,
+  rightKey: string,
+  fields: string[] = []
+): Promise<JoinSummary> {
+  return invoke("join_metadata", { path, leftKey, rightKey, fields });
+}
+
+export async function truncateField(options: TruncateOptions): Promise<TruncateSummary> {
+  return invoke("truncate_field", { options });
+}
+
+export async function appendDataset(
+  datasetId: string | null,
+  path: string | null,
+  acceptSchemaChanges = false,
+  options?: ImportOptions
+): Promise<AppendSummary> {
+  return invoke("append_dataset", { datasetId, path, options, acceptSchemaChanges });
+}
+
+export async function continueImport(options?: ImportOptions): Promise<DatasetSummary> {
+  return invoke("continue_import", { options });
+}
+
+export async function pruneFields(
+  keep: string[],
+  force = false
+): Promise<PruneSummary> {
+  return invoke("prune_fields", { keep, force });
+}
+
+export async function chunkField(
+  field: string,
+  maxLen: number,
+  overlap: number,
+  unit: ChunkUnit
+): Promise<ChunkSummary> {
+  return invoke("chunk_field", { field, maxLen, overlap, unit });
 }
 
 export async function getPreview(
@@ -51,30 +239,241 @@ export async function getPreview(
   return invoke("get_preview", { view, page, pageSize });
 }
 
+/**
+ * `metric` is `score`, `length:<scope>`, `tokens:<scope>`, `ratio:<unit>` (output over
+ * instruction length in `chars` or `tokens`) or `field:<name>`.
+ */
+export async function getExtremes(
+  metric: string,
+  direction: ExtremeDirection,
+  k: number,
+  view: ViewMode
+): Promise<ExtremesResult> {
+  return invoke("get_extremes", { metric, direction, k, view });
+}
+
+export async function getFieldMatrix(
+  view: ViewMode,
+  sampleSize?: number
+): Promise<FieldMatrix> {
+  return invoke("get_field_matrix", { view, sampleSize });
+}
+
+export async function getDatasetStats(view: ViewMode): Promise<DatasetStats> {
+  return invoke("get_dataset_stats", { view });
+}
+
+export async function exportFieldMatrix(
+  path: string,
+  format: FieldMatrixFormat,
+  overwrite?: boolean
+): Promise<string> {
+  return invoke("export_field_matrix", { path, format, overwrite });
+}
+
+export async function exportReviewHtml(
+  path: string,
+  view: ViewMode,
+  limit?: number,
+  overwrite?: boolean
+): Promise<ReviewExportSummary> {
+  return invoke("export_review_html", { path, view, limit, overwrite });
+}
+
+export async function previewOrder(
+  view: ViewMode,
+  orderBy: OrderKey[],
+  limit: number
+): Promise<OrderPreview> {
+  return invoke("preview_order", { view, orderBy, limit });
+}
+
+export async function resolveStableIds(hashes: string[]): Promise<number[]> {
+  return invoke("resolve_stable_ids", { hashes });
+}
+
+export async function getStableIds(ids: number[]): Promise<string[]> {
+  return invoke("get_stable_ids", { ids });
+}
+
 export async function getRecord(id: number) {
   return invoke("get_record", { id });
 }
 
 export async function applyFilters(
   filters: FilterConfig,
-  fieldMap: FieldMap
+  fieldMap: FieldMap,
+  baseView?: ViewMode
+): Promise<FilterSummary> {
+  return invoke("apply_filters", { filters, fieldMap, baseView });
+}
+
+/** Adds a stats-bucket constraint to the applied filters and filters again. */
+export async function drillDown(drill: DrillDown): Promise<DrillDownResult> {
+  return invoke("drill_down", { drill });
+}
+
+export async function validateRules(
+  rules: ValidationRule[],
+  view: ViewMode
+): Promise<ValidationReport> {
+  return invoke("validate_rules", { rules, view });
+}
+
+/** Re-applies the filters with `rule` added, excluding the records that break it. */
+export async function excludeRuleViolations(
+  filters: FilterConfig,
+  rule: ValidationRule,
+  fieldMap: FieldMap,
+  baseView?: ViewMode
 ): Promise<FilterSummary> {
-  return invoke("apply_filters", { filters, fieldMap });
+  const validationRules = [...(filters.validationRules ?? []), rule];
+  return applyFilters({ ...filters, validationRules }, fieldMap, baseView);
 }
 
 export async function setFieldMap(fieldMap: FieldMap): Promise<void> {
   return invoke("set_field_map", { fieldMap });
 }
 
-export async function listCategories(field: string): Promise<CategoryCount[]> {
-  return invoke("list_categories", { field });
+export async function tagRefusals(): Promise<TagSummary> {
+  return invoke("tag_refusals");
+}
+
+export async function tagLanguageMismatches(): Promise<TagSummary> {
+  return invoke("tag_language_mismatches");
+}
+
+export async function detectTemplates(
+  minCluster: number,
+  view: ViewMode
+): Promise<TemplateReport> {
+  return invoke("detect_templates", { minCluster, view });
+}
+
+export async function capPerTemplate(n: number): Promise<TemplateCapSummary> {
+  return invoke("cap_per_template", { n });
+}
+
+export async function runBenchmark(): Promise<BenchmarkReport> {
+  return invoke("run_benchmark");
+}
+
+export async function compareDatasets(
+  idA: string,
+  idB: string,
+  sampleSize?: number,
+  markdownPath?: string,
+  overwrite = false
+): Promise<DatasetComparison> {
+  return invoke("compare_datasets", { idA, idB, sampleSize, markdownPath, overwrite });
+}
+
+export async function findDuplicateClusters(): Promise<ClusterSummary> {
+  return invoke("find_duplicate_clusters");
+}
+
+export async function getNextCluster(): Promise<ClusterView | null> {
+  return invoke("get_next_cluster");
+}
+
+export async function resolveCluster(
+  clusterId: number,
+  keepIds: number[]
+): Promise<ClusterProgress> {
+  return invoke("resolve_cluster", { clusterId, keepIds });
+}
+
+export async function getClusterProgress(): Promise<ClusterProgress | null> {
+  return invoke("get_cluster_progress");
+}
+
+/** Stops at `maxDistinct` values (10,000 by default) and returns a partial list. */
+export async function listCategories(
+  field?: string,
+  maxDistinct?: number
+): Promise<CategoryList> {
+  return invoke("list_categories", { field, maxDistinct });
+}
+
+export async function detectCodeLanguages(view: ViewMode): Promise<CodeLanguageReport> {
+  return invoke("detect_code_languages", { view });
+}
+
+/** Instruction languages and script mix of a seeded sample of `view`. */
+export async function getLanguageStats(
+  view: ViewMode,
+  sampleSize?: number
+): Promise<LanguageStats> {
+  return invoke("get_language_stats", { view, sampleSize });
+}
+
+export async function testPattern(
+  pattern: string,
+  scope?: "instruction" | "output" | "combined",
+  sampleSize?: number,
+  caseSensitive?: boolean,
+  regex = false
+): Promise<PatternTest> {
+  return invoke("test_pattern", { pattern, scope, sampleSize, caseSensitive, regex });
+}
+
+/** Masked personal data matches in a sample of a view; `pii` defaults to the applied filters. */
+export async function scanPii(
+  view: ViewMode,
+  pii?: PiiFilter,
+  sampleSize?: number
+): Promise<PiiScan> {
+  return invoke("scan_pii", { view, pii, sampleSize });
+}
+
+export async function getCategoryRules(): Promise<CategoryRules> {
+  return invoke("get_category_rules");
+}
+
+export async function setCategoryRules(rules: CategoryRules): Promise<void> {
+  return invoke("set_category_rules", { rules });
 }
 
 export async function previewDistillation(
   config: DistillConfig,
-  fieldMap: FieldMap
+  fieldMap: FieldMap,
+  baseView?: ViewMode
 ): Promise<DistillSummary> {
-  return invoke("preview_distillation", { config, fieldMap });
+  return invoke("preview_distillation", { config, fieldMap, baseView });
+}
+
+export async function createSampleView(
+  percent: number,
+  seed: number
+): Promise<SampleSummary> {
+  return invoke("create_sample_view", { percent, seed });
+}
+
+/** Saves a view usable anywhere a view is taken as `view:<name>`. */
+export async function createView(
+  name: string,
+  definition: ViewDefinition
+): Promise<NamedViewInfo> {
+  return invoke("create_view", { name, definition });
+}
+
+export async function listViews(): Promise<NamedViewInfo[]> {
+  return invoke("list_views");
+}
+
+export async function deleteView(name: string): Promise<void> {
+  return invoke("delete_view", { name });
+}
+
+export async function promoteToFull(): Promise<PromoteSummary> {
+  return invoke("promote_to_full");
+}
+
+export async function estimatePipeline(
+  spec?: PipelineSpec,
+  sampleSize?: number
+): Promise<PipelineEstimate> {
+  return invoke("estimate_pipeline", { spec, sampleSize });
 }
 
 export async function updateManualSelection(
@@ -83,18 +482,100 @@ export async function updateManualSelection(
   return invoke("update_manual_selection", { changes });
 }
 
+/** Records most worth a manual look; decide them with `updateManualSelection`. */
+export async function getReviewQueue(limit = 50): Promise<ReviewQueue> {
+  return invoke("get_review_queue", { limit });
+}
+
+export async function explainRecordScore(id: number): Promise<ScoreBreakdown> {
+  return invoke("explain_record_score", { id });
+}
+
+/**
+ * Scores records with a shell command that reads `{id, record}` JSONL on stdin
+ * and writes `{id, score}` JSONL to stdout. With `field`, the scores are
+ * written to a derived dataset that becomes active.
+ */
+export async function scoreWithCommand(
+  command: string,
+  batchSize?: number,
+  field?: string,
+  view?: ViewMode,
+  timeoutSecs?: number
+): Promise<CommandScoreSummary> {
+  return invoke("score_with_command", { command, batchSize, field, view, timeoutSecs });
+}
+
+export async function materializeCommandScores(field: string): Promise<DatasetSummary> {
+  return invoke("materialize_command_scores", { field });
+}
+
+export async function exportToClipboard(
+  ids: number[],
+  format: "json" | "jsonl" | "markdown"
+): Promise<number> {
+  return invoke("export_to_clipboard", { ids, format });
+}
+
 export async function exportDataset(
   view: ViewMode,
   path: string,
-  format: "json" | "csv"
-) {
-  return invoke("export_dataset", { view, path, format });
+  format: "json" | "jsonl" | "csv" | "sqlite" | "duplicate_report",
+  options?: ExportOptions,
+  compression: ExportCompression = "none"
+): Promise<ExportSummary> {
+  return invoke("export_dataset", { view, path, format, compression, options });
+}
+
+/**
+ * Exports the selected records that were not in the last export of the
+ * selection, plus a removals list and a manifest next to `path`.
+ */
+export async function exportDelta(
+  path: string,
+  format: "json" | "csv",
+  options?: ExportOptions,
+  compression: ExportCompression = "none"
+): Promise<DeltaExportSummary> {
+  return invoke("export_delta", { path, format, compression, options });
+}
+
+/**
+ * Converts a file to `format` without loading it as a dataset. The output is
+ * compressed when its name ends in `.gz` or `.zst`.
+ */
+export async function convertFile(
+  input: string,
+  output: string,
+  format: "json" | "jsonl" | "csv",
+  inputOptions?: ImportOptions,
+  outputOptions?: ExportOptions
+): Promise<ConvertSummary> {
+  return invoke("convert_file", { input, output, format, inputOptions, outputOptions });
+}
+
+export async function getAutosaveInfo(): Promise<DerivedStateInfo | null> {
+  return invoke("get_autosave_info");
+}
+
+export async function restoreAutosave(): Promise<DerivedStateInfo> {
+  return invoke("restore_autosave");
 }
 
 export async function cancelTask() {
   return invoke("cancel_task");
 }
 
+/** Clears the taskbar progress and retitles the window after a long operation. */
+export async function finishTask(): Promise<void> {
+  return invoke("finish_task");
+}
+
+/** Sets the dock badge where the platform has one; `null` clears it. */
+export async function setWindowBadge(count: number | null): Promise<void> {
+  return invoke("set_window_badge", { count });
+}
+
 export async function loadSettings(): Promise<Settings | null> {
   return invoke("load_settings");
 }
@@ -107,6 +588,14 @@ export async function getLogs(limit = 200): Promise<string[]> {
   return invoke("get_logs", { limit });
 }
 
+export async function getStartupReport(): Promise<StartupReport> {
+  return invoke("get_startup_report");
+}
+
+export async function getRestoredSession(): Promise<RestoredSession | null> {
+  return invoke("get_restored_session");
+}
+
 export async function listenProgress(
   handler: (event: ProgressEvent) => void
 ) {
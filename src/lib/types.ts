@@ -1,12 +1,182 @@
-export type ViewMode = "all" | "filtered" | "selected" | "removed";
+export type ViewMode =
+  | "all"
+  | "filtered"
+  | "selected"
+  | "removed"
+  | `tag:${string}`
+  | `sample:${string}`
+  | `view:${string}`;
 
 export interface DatasetSummary {
   id: string;
   sourcePath: string;
   format: string;
+  delimiter?: string | null;
+  /** Text encoding the source was read in, such as `UTF-8` or `windows-1252`. */
+  encoding?: string | null;
   recordCount: number;
   fields: string[];
   sizeBytes: number;
+  importReport: ImportReport;
+  remapReport?: RemapReport | null;
+  parentId?: string;
+  /** The import was canceled; only the records read until then are here. */
+  truncated?: boolean;
+  /** Set when `continueImport` can read the rest of the source. */
+  resumeOffset?: number | null;
+}
+
+export interface RemapReport {
+  restoredTags: string[];
+  matchedCount: number;
+  missingCount: number;
+  collisionCount: number;
+}
+
+export interface PruneSummary {
+  dataset: DatasetSummary;
+  inputCount: number;
+  droppedFields: string[];
+  sizeBefore: number;
+  sizeAfter: number;
+}
+
+export interface ChunkSizeBin {
+  min: number;
+  max: number;
+  count: number;
+}
+
+export interface TruncateSummary {
+  dataset: DatasetSummary;
+  inputCount: number;
+  truncatedCount: number;
+  tokensSaved: number;
+}
+
+export interface ChunkSummary {
+  dataset: DatasetSummary;
+  inputCount: number;
+  outputCount: number;
+  chunkedCount: number;
+  skippedCount: number;
+  histogram: ChunkSizeBin[];
+}
+
+export type ChunkUnit = "chars" | "words" | "tokens";
+
+export interface JoinSummary {
+  dataset: DatasetSummary;
+  inputCount: number;
+  rightRowCount: number;
+  matchedCount: number;
+  missedCount: number;
+  duplicateRightKeys: number;
+}
+
+export interface FillRateChange {
+  field: string;
+  baseFillRate: number;
+  incomingFillRate: number;
+}
+
+export interface SchemaDiff {
+  onlyInBase: string[];
+  onlyInIncoming: string[];
+  fillRateChanges: FillRateChange[];
+  baseScannedCount: number;
+  incomingScannedCount: number;
+}
+
+export interface AppendSummary {
+  schemaDiff: SchemaDiff;
+  appended: boolean;
+  incoming: DatasetSummary;
+  dataset: DatasetSummary | null;
+}
+
+export interface PushSummary {
+  repoId: string;
+  commitUrl?: string | null;
+  uploadedFiles: string[];
+  lfsFileCount: number;
+}
+
+export interface MaterializeSummary {
+  dataset: DatasetSummary;
+  inputCount: number;
+  outputCount: number;
+  affectedCount: number;
+}
+
+export interface CommandScoreSummary {
+  scoredCount: number;
+  batchCount: number;
+  minScore?: number | null;
+  maxScore?: number | null;
+  meanScore?: number | null;
+  /** Field the scores were written to, when they were materialized. */
+  field?: string | null;
+  /** The derived dataset holding the scores, now active. */
+  dataset?: DatasetSummary | null;
+}
+
+/** A store in the datasets directory that can be opened again. */
+export interface StoredDataset {
+  id: string;
+  sourcePath: string;
+  format: string;
+  recordCount: number;
+  /** Size of the source; the compressed size for gzip sources. */
+  sizeBytes: number;
+  storeBytes: number;
+  /** Unix seconds. */
+  importedAt: number;
+  truncated: boolean;
+  open: boolean;
+  active: boolean;
+}
+
+export interface ImportOptions {
+  maxRecordBytes?: number;
+  truncateLargeFields?: boolean;
+  /** Chunks read ahead of parsing, at most 64; raise for slow sources like network drives. */
+  readAheadChunks?: number;
+  /** Detected from the path when unset; directories are always read as Markdown. */
+  format?: "csv" | "json" | "jsonl" | "markdown";
+  /** Markdown headings of this level or higher start a new record. */
+  headingLevel?: number;
+  /** Markdown sections with a shorter body, in characters, are skipped. */
+  minBodyLength?: number;
+  /** Whether a CSV file's first row names its columns; guessed when unset. */
+  hasHeader?: boolean | null;
+  /** Import CSV cells as numbers, booleans and nulls where their column allows. */
+  inferTypes?: boolean;
+  /** Byte offset to start reading an uncompressed CSV or JSON Lines file at. */
+  resumeOffset?: number | null;
+  /** Skip JSON Lines lines that do not parse instead of failing the import. */
+  lenient?: boolean;
+  /** Dot path of the records array in a JSON document, e.g. `data.rows`. */
+  recordsPath?: string | null;
+}
+
+export interface ImportReport {
+  oversizedSkipped: number;
+  oversizedSizes: number[];
+  truncatedRecords: number;
+  shortSectionsSkipped?: number;
+  /** The CSV had no header row; columns are named column_1 onwards. */
+  generatedHeaders?: boolean;
+  /** JSON Lines lines a lenient import skipped. */
+  skippedCount?: number;
+  /** The first 100 skipped lines. */
+  malformedLines?: MalformedLine[];
+}
+
+export interface MalformedLine {
+  lineNumber: number;
+  error: string;
+  snippet: string;
 }
 
 export interface PreviewField {
@@ -20,21 +190,359 @@ export interface PreviewItem {
   fields: PreviewField[];
 }
 
+export interface ClusterProgress {
+  clusterCount: number;
+  reviewedCount: number;
+  remainingCount: number;
+  stale: boolean;
+}
+
+export interface ClusterSummary {
+  duplicateCount: number;
+  restoredCount: number;
+  progress: ClusterProgress;
+}
+
+export interface ClusterMember {
+  id: number;
+  record: Record<string, unknown> | null;
+  fields: PreviewField[];
+  kept: boolean;
+}
+
+export interface ClusterView {
+  clusterId: number;
+  members: ClusterMember[];
+  progress: ClusterProgress;
+}
+
 export interface PreviewPage {
   items: PreviewItem[];
   totalCount: number;
   page: number;
+  /** Page size actually used; smaller than requested when clamped. */
   pageSize: number;
+  pageSizeClamped: boolean;
+  /** The page hit the byte cap, so it holds fewer than pageSize records. */
+  truncatedPage: boolean;
+}
+
+export type ExtremeDirection = "asc" | "desc";
+
+export interface ExtremeItem {
+  id: number;
+  value: number;
+  fields: PreviewField[];
+}
+
+export interface ExtremesResult {
+  metric: string;
+  direction: ExtremeDirection;
+  scannedCount: number;
+  items: ExtremeItem[];
+}
+
+export interface FieldStats {
+  name: string;
+  presentCount: number;
+  nonEmptyCount: number;
+  fillRate: number;
+  types: Record<string, number>;
+  distinctEstimate: number;
+  distinctExact: boolean;
+  examples: string[];
+  meanLength?: number | null;
+}
+
+export interface FieldMatrix {
+  viewCount: number;
+  scannedCount: number;
+  fields: FieldStats[];
 }
 
+export interface FieldCoverage {
+  name: string;
+  nonNullCount: number;
+  /** Non-null values over scanned records, in percent. */
+  coveragePercent: number;
+}
+
+export interface LengthStats {
+  mean: number;
+  median: number;
+  p95: number;
+}
+
+/** Lengths of a mapped text field over the records where it is not empty. */
+export interface TextStats {
+  field: string;
+  count: number;
+  chars: LengthStats;
+  words: LengthStats;
+}
+
+export interface DatasetStats {
+  viewCount: number;
+  scannedCount: number;
+  fields: FieldCoverage[];
+  instruction?: TextStats | null;
+  output?: TextStats | null;
+  /** Distinct categories; null when no category field or rules are configured. */
+  categoryCount?: number | null;
+  /** Share of records repeating an earlier record's instruction and output. */
+  duplicateRate: number;
+  estimatedTokens: number;
+}
+
+export type FieldMatrixFormat = "markdown" | "csv";
+
+export interface ReviewExportSummary {
+  path: string;
+  viewCount: number;
+  exportedCount: number;
+  truncated: boolean;
+}
+
+/** Field of each role; dotted paths such as `meta.category` reach nested values. */
 export interface FieldMap {
   instruction?: string;
   output?: string;
   code?: string;
   category?: string;
   score?: string;
+  weight?: string;
+  /** System prompt field, written as the system turn of chat templates. */
+  system?: string;
+}
+
+export type ExportCompression = "none" | "gzip" | "zstd";
+
+export type OutputErrorKind = "protectedFile" | "internalDir" | "exists" | "invalidPath" | "failed";
+
+/** Rejection of a command that writes output files. */
+export interface OutputError {
+  kind: OutputErrorKind;
+  message: string;
+  path: string | null;
+  dir: string | null;
+}
+
+export type HubErrorKind = "unauthorized" | "gated" | "notFound" | "network" | "status" | "failed";
+
+/** Rejection of a Hub import or push. */
+export interface HubError {
+  kind: HubErrorKind;
+  message: string;
+  /** HTTP status of the failed request. */
+  status: number | null;
+}
+
+export interface ExportOptions {
+  includeWeight?: boolean;
+  invalidWeight?: "default" | "skip";
+  overwrite?: boolean;
+  allowInternal?: boolean;
+  /** Up to three sort keys, most significant first. */
+  orderBy?: OrderKey[];
+  /** Write in id order (or by orderBy), shuffled, or by the mapped score. */
+  order?: ExportOrder;
+  /** Seed of a shuffled order; the default seed when unset. */
+  shuffleSeed?: number | null;
+  /** Static fields added to every exported record. */
+  injectFields?: InjectedField[];
+  /** System turn for records without one; a `system` field when there is no chat layout. */
+  systemPrompt?: string | null;
+  /** Let injected values replace existing ones. */
+  overwriteExisting?: boolean;
+  /** Cut an over-long text field to a token budget. */
+  truncate?: TruncateOptions | null;
+  /** Read the file back before moving it into place; a mismatch fails the export. */
+  verify?: boolean;
+  /** Keep only these fields, in this order for CSV; missing ones are written empty. */
+  fields?: string[] | null;
+  /** Write mapped fields as instruction, output, category and score; mapped values win. */
+  normalizeFields?: boolean;
+  /** With normalizeFields, leave out fields that are not mapped. */
+  dropUnmappedFields?: boolean;
+  /** Rewrite records as alpaca or ShareGPT; records missing instruction or output are skipped. */
+  template?: "alpaca" | "sharegpt" | null;
+  /** Delimiter, quoting and header row of CSV exports. */
+  csv?: CsvOptions;
+  /** Write `<path>.manifest.json` with the dataset, configuration and file hash. */
+  writeManifest?: boolean;
+  /** Spread nested objects of CSV exports over dotted columns such as `meta.lang`. */
+  flattenNested?: boolean;
+  /** With flattenNested, give array items columns too (`tags.0`) instead of writing JSON. */
+  flattenArrays?: boolean;
+  /** Indent JSON array exports by two spaces per level. */
+  pretty?: boolean;
+}
+
+export type ExportOrder = "original" | "shuffled" | "score_desc" | "score_asc";
+
+export type CsvQuoteStyle = "necessary" | "always" | "non_numeric";
+
+export interface CsvOptions {
+  /** Character code of the field delimiter; 44 (`,`) by default. */
+  delimiter?: number;
+  writeHeader?: boolean;
+  quoteStyle?: CsvQuoteStyle;
+}
+
+export type TruncateBoundary = "token" | "sentence";
+
+export interface TruncateOptions {
+  field: string;
+  maxTokens: number;
+  boundary?: TruncateBoundary;
+  /** Appended to truncated texts; defaults to "...[truncated]". */
+  marker?: string;
+}
+
+export interface InjectedField {
+  name: string;
+  value: unknown;
+}
+
+export interface OrderKey {
+  /** `score`, `length:<scope>`, `tokens:<scope>`, `field:<name>`, `category` or a field name. */
+  fieldOrMetric: string;
+  direction: ExtremeDirection;
+}
+
+export interface OrderedItem {
+  id: number;
+  values: (number | string | null)[];
+  fields: PreviewField[];
+}
+
+export interface OrderPreview {
+  orderBy: OrderKey[];
+  scannedCount: number;
+  items: OrderedItem[];
+}
+
+export interface StageTiming {
+  stage: string;
+  millis: number;
+  recordsPerSec?: number | null;
+}
+
+export interface PredicateCost {
+  predicate: string;
+  millis: number;
+  recordsPerSec?: number | null;
+  rejectedCount: number;
+}
+
+export interface BenchmarkReport {
+  recordCount: number;
+  sampleCount: number;
+  oversizedCount: number;
+  readBytes: number;
+  readMbPerSec?: number | null;
+  timings: StageTiming[];
+  predicates: PredicateCost[];
+}
+
+export interface NumericSummary {
+  count: number;
+  min: number;
+  max: number;
+  mean: number;
+  p10: number;
+  p50: number;
+  p90: number;
+}
+
+export interface CategoryShare {
+  name: string;
+  countA: number;
+  countB: number;
+  shareA: number;
+  shareB: number;
+}
+
+export interface OverlapEstimate {
+  sampleSize: number;
+  exactCount: number;
+  fuzzyCount: number;
+  exactRate: number;
+  fuzzyRate: number;
+}
+
+export interface DatasetComparison {
+  datasetA: DatasetSummary;
+  datasetB: DatasetSummary;
+  sharedFields: string[];
+  onlyAFields: string[];
+  onlyBFields: string[];
+  lengthA: NumericSummary;
+  lengthB: NumericSummary;
+  scoreA?: NumericSummary | null;
+  scoreB?: NumericSummary | null;
+  categories: CategoryShare[];
+  overlap: OverlapEstimate;
+  markdownPath?: string | null;
+}
+
+export interface ExportSummary {
+  exportedCount: number;
+  invalidWeightCount: number;
+  skippedCount: number;
+  timings: StageTiming[];
+  sampleView?: string | null;
+  orderBy?: OrderKey[];
+  shuffleSeed?: number | null;
+  injectedFields?: string[];
+  systemPromptCount?: number;
+  keptExistingCount?: number;
+  truncatedCount?: number;
+  tokensSaved?: number;
+  verified?: boolean;
+  spotCheckedCount?: number;
+  templateSkippedCount?: number;
+  manifestPath?: string | null;
 }
 
+/** Written next to a delta export, naming the export it follows. */
+export interface DeltaManifest {
+  datasetId: string;
+  view: string;
+  format: string;
+  createdAt: number;
+  file: string;
+  sha256: string;
+  addedCount: number;
+  removalsFile?: string | null;
+  removalsSha256?: string | null;
+  removedCount: number;
+  previousFile?: string | null;
+  previousSha256?: string | null;
+  previousExportedAt?: number | null;
+  totalCount: number;
+}
+
+export interface DeltaExportSummary {
+  export: ExportSummary;
+  /** No earlier export was recorded, so every selected record was written. */
+  fullExport: boolean;
+  addedCount: number;
+  removedCount: number;
+  removalsPath?: string | null;
+  manifestPath: string;
+  manifest: DeltaManifest;
+}
+
+/** A file converted to another format without being loaded. */
+export interface ConvertSummary {
+  sourceFormat: string;
+  importReport: ImportReport;
+  export: ExportSummary;
+}
+
+export type DedupeMode = "instruction" | "output" | "both" | "either" | "combined";
+
 export interface FilterConfig {
   requireFields: string[];
   minLength?: number;
@@ -43,19 +551,156 @@ export interface FilterConfig {
   excludeKeywords: string[];
   categoryField?: string;
   categories: string[];
+  includeUncategorized?: boolean;
+  /** Categories to drop, applied after `categories`; records without a category are kept. */
+  excludeCategories?: string[];
   dedupeExact: boolean;
   dedupeFuzzy: boolean;
+  dedupeMode?: DedupeMode;
   lengthScope: "instruction" | "output" | "combined";
+  /** Unit of minLength and maxLength; tokens are estimated. */
+  lengthUnit?: "chars" | "tokens";
   keywordCaseSensitive: boolean;
+  dropRefusals?: boolean;
+  useBuiltinRefusalPhrases?: boolean;
+  refusalPhrases?: string[];
+  /** Drop records whose instruction and output are confidently in different languages. */
+  requireSameLanguage?: boolean;
+  /** Share of a text's letters that must agree on its language before it is judged. */
+  languageMinConfidence?: number;
+  /** Records breaking any of these rules are excluded. */
+  validationRules?: ValidationRule[];
+  /** Bounds on output length divided by instruction length. */
+  minOutputInstructionRatio?: number | null;
+  maxOutputInstructionRatio?: number | null;
+  ratioUnit?: "chars" | "tokens";
+  /** Whether records with an empty instruction or output pass (`skip`) or fail the ratio check. */
+  ratioMissing?: "skip" | "reject";
+  /** Bounds on the mapped score field; records without a numeric score fail when either is set. */
+  minScore?: number | null;
+  maxScore?: number | null;
+  /** Bounds on arbitrary numeric fields; records must satisfy all of them. */
+  numericFilters?: NumericFilter[];
+  /** Kinds of personal data that exclude a record when found in its instruction or output. */
+  pii?: PiiFilter;
+  /** Remember which kept record each dropped duplicate matched, for a duplicate report. */
+  keepDuplicateMap?: boolean;
+  /** Words per near-duplicate shingle; defaults to 1, 3 keeps reworded texts apart. */
+  shingleSize?: number;
+  /** Which exact duplicate is kept; ties and near-duplicates keep the first. */
+  dedupeKeep?: "first" | "highest_score" | "longest_output";
+  /** Largest simhash Hamming distance counted as a near-duplicate; defaults to 3, at most 16. */
+  fuzzyThreshold?: number | null;
+  /** How near-duplicates are found; `minhash` suits long texts. */
+  dedupeMethod?: "simhash" | "minhash";
+  /** Estimated Jaccard similarity at which MinHash counts a near-duplicate; defaults to 0.8. */
+  minhashThreshold?: number;
+}
+
+/** Inclusive bounds on a numeric field, which may be a dotted path; numeric strings count. */
+export interface NumericFilter {
+  field: string;
+  min?: number | null;
+  max?: number | null;
+  /** Whether records where the field is missing or not a number are kept; defaults to `drop`. */
+  missing?: "keep" | "drop";
+}
+
+/** Which personal data detectors exclude records. */
+export interface PiiFilter {
+  dropEmails?: boolean;
+  dropPhoneNumbers?: boolean;
+  dropUrls?: boolean;
+  dropIpAddresses?: boolean;
+}
+
+export type PiiKind = "email" | "phone" | "url" | "ip_address";
+
+/** A record-level invariant; field names refer to raw record fields. */
+export type ValidationRule =
+  | { kind: "starts_with_field"; field: string; prefixField: string }
+  | { kind: "contains_literal"; fields?: string[]; literal: string; caseSensitive?: boolean }
+  | { kind: "regex_must"; field: string; pattern: string }
+  | { kind: "regex_must_not"; field: string; pattern: string }
+  | { kind: "field_equals"; field: string; other: string };
+
+export interface RuleViolations {
+  rule: ValidationRule;
+  label: string;
+  suggestion: string;
+  count: number;
+  sampleIds: number[];
+}
+
+export interface ValidationReport {
+  scannedCount: number;
+  violatingCount: number;
+  rules: RuleViolations[];
 }
 
 export interface FilterSummary {
   totalCount: number;
   filteredCount: number;
   duplicatesRemoved: number;
+  rejected: Record<string, number>;
+  timings: StageTiming[];
+  sampleView?: string | null;
+  /** Signs the result is probably a mistake, such as nothing retained. */
+  warnings?: string[];
+  /** Whether a duplicate report can be exported from this run. */
+  duplicateMapKept?: boolean;
+  /** Estimated tokens in the length scope of the kept records, with lengthUnit `tokens`. */
+  keptTokens?: number | null;
+  /** Duplicates removed by each method: `exact`, `simhash` or `minhash`. */
+  duplicatesByMethod?: Record<string, number>;
+  /** Records rejected by each numeric filter, in order, counted against the first rule failed. */
+  numericRejected?: number[];
+  /** Records rejected by each PII detector, counted against the first one that matched. */
+  piiRejected?: Partial<Record<PiiKind, number>>;
+}
+
+/** A constraint added to the applied filters from a stats bucket. */
+export type DrillDown =
+  | { dimension: "category"; value: string }
+  | { dimension: "length"; min?: number | null; max?: number | null }
+  | { dimension: "score"; min?: number | null; max?: number | null };
+
+export interface DrillDownResult {
+  filters: FilterConfig;
+  summary: FilterSummary;
+  /** The filtered records were narrowed rather than filtered and deduplicated again. */
+  dedupeReused: boolean;
+}
+
+export interface TagSummary {
+  tag: string;
+  scannedCount: number;
+  taggedCount: number;
+}
+
+export interface TemplateCluster {
+  masked: string;
+  count: number;
+  sampleIds: number[];
+}
+
+export interface TemplateReport {
+  clusters: TemplateCluster[];
+  truncated: boolean;
+  scannedCount: number;
+  templateCount: number;
+  clusteredCount: number;
+}
+
+export interface TemplateCapSummary {
+  scannedCount: number;
+  templateCount: number;
+  cappedTemplates: number;
+  removedCount: number;
+  filteredCount: number;
 }
 
-export type DistillStrategy = "random" | "diversity" | "importance";
+export type DistillStrategy = "random" | "diversity" | "importance" | "prefix_diversity";
 
 export interface DistillConfig {
   targetCount?: number;
@@ -63,12 +708,137 @@ export interface DistillConfig {
   strategy: DistillStrategy;
   randomSeed?: number;
   preserveCategoryBalance: boolean;
+  uncategorizedWeight?: number;
+  minPerCategory?: number | null;
+  maxPerCategoryFraction?: number | null;
+  /** Leading instruction words that form a `prefix_diversity` bucket; defaults to 2. */
+  prefixTokens?: number;
+  /** Scores by the weighted sum of these fields instead of the mapped score. */
+  scoreComponents?: ScoreComponent[];
+  /** Used in place of a component a record does not have; defaults to 0. */
+  missingComponentValue?: number;
+  /** Rescales each component to [0, 1] over the records being distilled. */
+  normalizeComponents?: boolean;
+  /**
+   * Balanced importance or random selection over more records than this keeps
+   * the metas in temp files; defaults to 20,000,000.
+   */
+  externalMetaThreshold?: number;
+  /** Words per shingle of the `diversity` instruction signature; defaults to 1. */
+  shingleSize?: number;
+}
+
+export interface ScoreComponent {
+  field: string;
+  weight: number;
 }
 
 export interface DistillSummary {
   totalCount: number;
   selectedCount: number;
   removedCount: number;
+  timings: StageTiming[];
+  sampleView?: string | null;
+  constraintWarnings?: string[];
+  topPrefixes?: PrefixCount[];
+  scoreRanges?: ComponentRange[];
+  /** Whether the metas were kept in temp files rather than memory. */
+  externalSelection?: boolean;
+  /** Signs the selection is probably a mistake, such as nothing selected. */
+  warnings?: string[];
+}
+
+export interface ComponentRange {
+  field: string;
+  min: number | null;
+  max: number | null;
+}
+
+export interface ComponentScore {
+  field: string;
+  weight: number;
+  raw: number | null;
+  value: number;
+  contribution: number;
+}
+
+export interface ScoreBreakdown {
+  id: number;
+  score: number;
+  /** Whether the ranges of the last preview were applied. */
+  normalized: boolean;
+  components: ComponentScore[];
+}
+
+export interface PrefixCount {
+  prefix: string;
+  count: number;
+  selectedCount: number;
+}
+
+export interface SampleSummary {
+  view: ViewMode;
+  percent: number;
+  seed: number;
+  sampleCount: number;
+  totalCount: number;
+}
+
+export type ViewRefinement =
+  | { kind: "filter"; config: FilterConfig }
+  | { kind: "tag"; tag: string }
+  | { kind: "combine"; op: "intersect" | "union" | "subtract"; view: ViewMode };
+
+export interface ViewDefinition {
+  base: ViewMode;
+  refinements?: ViewRefinement[];
+}
+
+export interface NamedViewInfo {
+  name: string;
+  definition: ViewDefinition;
+  /** Null until the view's filters have run for the current state. */
+  count?: number | null;
+}
+
+export interface PromoteSummary {
+  promotedFrom?: string | null;
+  filter: FilterSummary;
+  distill?: DistillSummary | null;
+}
+
+export interface PipelineSpec {
+  filters: FilterConfig;
+  distill?: DistillConfig | null;
+}
+
+export interface StageEstimate {
+  stage: "filter" | "dedupe" | "distill";
+  sampleInput: number;
+  sampleOutput: number;
+  estimatedInput: number;
+  estimatedOutput: number;
+  low: number;
+  high: number;
+}
+
+export interface CategoryEstimate {
+  name: string;
+  sampleCount: number;
+  share: number;
+  estimatedCount: number;
+}
+
+export interface PipelineEstimate {
+  totalCount: number;
+  sampleCount: number;
+  stages: StageEstimate[];
+  estimatedRejected: Record<string, number>;
+  observedDuplicateRate: number;
+  correctedDuplicateRate: number;
+  categories: CategoryEstimate[];
+  estimatedTokens: number;
+  caveats: string[];
 }
 
 export interface ManualChange {
@@ -81,6 +851,30 @@ export interface ProgressEvent {
   current: number;
   total: number;
   message?: string;
+  /** Step of a multi-phase operation; current and total restart with each phase. */
+  phase?: string | null;
+  phaseIndex?: number | null;
+  phaseCount?: number | null;
+  /** Records (bytes while downloading) per second since the previous event. */
+  rate?: number | null;
+  /** Source bytes an import has read, as its record total is unknown. */
+  bytesCurrent?: number | null;
+  bytesTotal?: number | null;
+}
+
+export interface CategoryRule {
+  name: string;
+  keywords?: string[];
+  patterns?: string[];
+}
+
+export interface CategoryRules {
+  rules: CategoryRule[];
+  fallback?: string | null;
+  scope?: "instruction" | "output" | "combined";
+  caseSensitive?: boolean;
+  /** Categorize records by the programming language of their code instead of by the rules. */
+  codeLanguage?: boolean;
 }
 
 export interface CategoryCount {
@@ -88,12 +882,174 @@ export interface CategoryCount {
   count: number;
 }
 
+export interface CategoryList {
+  categories: CategoryCount[];
+  /** The scan stopped early, at the distinct-value cap or when canceled. */
+  truncated: boolean;
+  scannedCount: number;
+  /** A lower bound when `truncated`. */
+  distinctCount: number;
+}
+
+export interface CodeLanguageReport {
+  scannedCount: number;
+  /** Records per programming language, most common first. */
+  languages: CategoryCount[];
+  /** Records per way the language was recognized. */
+  sources: Record<string, number>;
+}
+
+export interface ScriptShare {
+  script: string;
+  letterCount: number;
+  /** Share of all letters in the sample. */
+  share: number;
+}
+
+export interface LanguageStats {
+  viewCount: number;
+  /** Records examined; fewer than `viewCount` when sampled. */
+  scannedCount: number;
+  /** Records per detected instruction language; `unknown` when too short to tell. */
+  languages: CategoryCount[];
+  /** Letters of the sampled instructions by script, most common first. */
+  scripts: ScriptShare[];
+  /** Whether the stats were reused from an earlier request. */
+  cached?: boolean;
+}
+
+export interface PatternExample {
+  id: number;
+  before: string;
+  /** Empty for records the pattern did not match. */
+  matched: string;
+  after: string;
+}
+
+export interface PatternTest {
+  viewCount: number;
+  scannedCount: number;
+  matchedCount: number;
+  matchRate: number;
+  matches: PatternExample[];
+  nonMatches: PatternExample[];
+}
+
+/** A PII match split like a PatternExample, with personal data masked. */
+export interface PiiMatch {
+  id: number;
+  kind: PiiKind;
+  before: string;
+  matched: string;
+  after: string;
+}
+
+export interface PiiScan {
+  viewCount: number;
+  scannedCount: number;
+  /** Records with at least one match. */
+  matchedCount: number;
+  /** Records with a match, by detector. */
+  counts: Partial<Record<PiiKind, number>>;
+  matches: PiiMatch[];
+}
+
+export interface DerivedStateInfo {
+  datasetId: string;
+  savedAt: number;
+  filteredCount?: number;
+  selectedCount?: number;
+  removedCount?: number;
+  tags: string[];
+}
+
+/** Field map and configs a dataset was last used with. */
+export interface DatasetConfig {
+  fieldMap: FieldMap;
+  categoryRules: CategoryRules;
+  filters: FilterConfig;
+  distillConfig: DistillConfig;
+}
+
 export interface Settings {
   lastPath?: string;
   language?: string;
   fieldMap: FieldMap;
   filters: FilterConfig;
   distill: DistillConfig;
+  /** Omitted keeps the saved token; an empty string clears it. */
+  hubToken?: string;
+  /** Omitted keeps the saved limits. */
+  previewLimits?: PreviewLimits;
+  /** Omitted keeps the saved weights. */
+  reviewWeights?: ReviewWeights;
+  /** Refusal phrases new datasets start with; omitted keeps the saved ones. */
+  refusalPhrases?: RefusalPhrases;
+  /** Reopen the last dataset at its view, page and sort on startup; omitted keeps the saved choice. */
+  restoreLastSession?: boolean;
+  /** View shown when a restored view no longer exists; omitted keeps the saved one. */
+  defaultView?: ViewMode;
+  /** Omitted keeps the saved page size. */
+  defaultPageSize?: number;
+}
+
+/** The dataset and preview reopened at startup. */
+export interface RestoredSession {
+  dataset: DatasetSummary;
+  view: ViewMode;
+  page: number;
+  pageSize: number;
+  orderBy: OrderKey[];
+}
+
+/** Phrases that mark an output as a refusal. */
+export interface RefusalPhrases {
+  /** Use the English and Vietnamese phrases shipped with DataLab. */
+  useBuiltin: boolean;
+  phrases: string[];
+}
+
+/** How much each signal counts toward a record's place in the review queue. */
+export interface ReviewWeights {
+  boundary: number;
+  duplicates: number;
+  refusal: number;
+}
+
+export interface ReviewReason {
+  kind: "boundary" | "duplicates" | "refusal";
+  /** Signal strength in [0, 1], before weighting. */
+  signal: number;
+  detail: string;
+}
+
+export interface ReviewQueueItem {
+  id: number;
+  priority: number;
+  selected: boolean;
+  reasons: ReviewReason[];
+}
+
+export interface ReviewQueue {
+  items: ReviewQueueItem[];
+  boundaryRanked: boolean;
+  duplicatesKnown: boolean;
+  refusalsChecked: boolean;
+}
+
+export interface PreviewLimits {
+  maxPageSize: number;
+  maxPageBytes: number;
+}
+
+export interface StartupReport {
+  /** Why the settings file could not be read, when it was moved aside. */
+  settingsError?: string | null;
+  settingsBackup?: string | null;
+  removedTempFiles: string[];
+  removedSidecars: string[];
+  /** Problems the startup check could not repair. */
+  errors: string[];
 }
 
 export type MenuAction =